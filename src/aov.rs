@@ -0,0 +1,355 @@
+//! Arbitrary output variables: what the camera saw first besides light,
+//! for compositing and denoising elsewhere. They're gathered from the
+//! renderer's guides into one texture array only when they're shown or
+//! exported. See aov.wgsl.
+
+use std::{cell::RefCell, rc::Rc};
+
+use wgpu::util::DeviceExt;
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    camera::{Camera, CameraUniform},
+    lut,
+    readback::Readbacks,
+    shader,
+    texture::encode_srgb,
+    tonemap::Tonemap,
+    tracer::Renderer,
+};
+
+const WORKGROUP_SIZE: u32 = 8;
+/// Brightness change per pixel of motion when shown, matched in
+/// render.wgsl.
+const MOTION_SCALE: f32 = 0.05;
+
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct AovParams {
+    camera: CameraUniform,
+    prev_camera: CameraUniform,
+}
+
+/// What the viewport shows, and the layers of an EXR export. Matched by
+/// value in render.wgsl.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Aov {
+    /// The rendered image itself.
+    #[default]
+    Beauty = 0,
+    /// Base color of the first hit, or the light seen where there's none.
+    Albedo = 1,
+    /// Shading normal of the first hit in world space.
+    Normal = 2,
+    /// Distance to the first hit, zero where there's none.
+    Depth = 3,
+    /// Index of the instance hit first plus one, zero where there's none.
+    ObjectId = 4,
+    /// Semantic class of the instance hit first, given by the `class` of
+    /// glTF nodes' extras, zero where there's none.
+    ClassId = 5,
+    /// How far what each pixel sees moved on screen since the previous
+    /// frame, in pixels to the right and down. Only the camera's motion is
+    /// followed, and it's zero through a distorted lens or a projection
+    /// other than perspective.
+    Motion = 6,
+}
+
+impl Aov {
+    pub const ALL: [Self; 7] = [
+        Self::Beauty,
+        Self::Albedo,
+        Self::Normal,
+        Self::Depth,
+        Self::ObjectId,
+        Self::ClassId,
+        Self::Motion,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|aov| aov.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Beauty => "beauty",
+            Self::Albedo => "albedo",
+            Self::Normal => "normal",
+            Self::Depth => "depth",
+            Self::ObjectId => "object-id",
+            Self::ClassId => "class-id",
+            Self::Motion => "motion",
+        }
+    }
+
+    /// Layer of the AOV texture array, none for the image.
+    fn layer(self) -> Option<u32> {
+        (self as u32).checked_sub(1)
+    }
+
+    /// EXR channel names of the first components of each pixel, by the
+    /// usual conventions of compositors. The others aren't written.
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Self::Beauty => &["R", "G", "B", "A"],
+            Self::Albedo => &["albedo.R", "albedo.G", "albedo.B"],
+            Self::Normal => &["N.X", "N.Y", "N.Z"],
+            Self::Depth => &["Z"],
+            Self::ObjectId => &["id"],
+            Self::ClassId => &["class"],
+            Self::Motion => &["motion.X", "motion.Y"],
+        }
+    }
+
+    /// 8-bit colors of the AOV as the viewport shows it, see render.wgsl.
+    /// IDs get a distinct color each, the same in every image.
+    pub fn display_image(self, image: &image::Rgba32FImage) -> image::RgbaImage {
+        if self == Self::Beauty {
+            return lut::display_image(image, 1.0, Tonemap::None, None);
+        }
+        let unit = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        image::RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+            let [r, g, b, _] = image.get_pixel(x, y).0;
+            let [r, g, b] = match self {
+                Self::Beauty | Self::Albedo => [r, g, b].map(encode_srgb),
+                Self::Normal => [r, g, b].map(|c| unit(c * 0.5 + 0.5)),
+                Self::Depth => [unit(if r > 0.0 { 1.0 / (1.0 + 0.2 * r) } else { 0.0 }); 3],
+                Self::ObjectId | Self::ClassId => id_color(r.round() as u32),
+                Self::Motion => [
+                    unit(0.5 + MOTION_SCALE * r),
+                    unit(0.5 + MOTION_SCALE * g),
+                    128,
+                ],
+            };
+            image::Rgba([r, g, b, 255])
+        })
+    }
+}
+
+/// Distinct color of an ID, black for none. A PCG hash, matching
+/// render.wgsl.
+pub fn id_color(id: u32) -> [u8; 3] {
+    if id == 0 {
+        return [0; 3];
+    }
+    let state = id.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    let hash = (word >> 22) ^ word;
+    [hash as u8, (hash >> 8) as u8, (hash >> 16) as u8]
+}
+
+pub struct Aovs {
+    pipeline: wgpu::ComputePipeline,
+    params_buffer: wgpu::Buffer,
+    texture: wgpu::Texture,
+    /// One view per layer, for display.
+    views: Vec<wgpu::TextureView>,
+    array_view: wgpu::TextureView,
+}
+
+impl Aovs {
+    pub fn new(device: &wgpu::Device, size: PhysicalSize<u32>) -> Self {
+        let shader = shader::create_module(
+            device,
+            "AOV Shader",
+            "aov.wgsl",
+            &[("WORKGROUP_SIZE", WORKGROUP_SIZE.to_string())],
+        );
+        // Float textures aren't filterable, so the layout can't be derived
+        // from the shader
+        let read_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("AOV Bind Group Layout"),
+            entries: &[
+                read_entry(0),
+                read_entry(1),
+                read_entry(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("AOV Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("AOV Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("AOV Params Buffer"),
+            contents: bytemuck::bytes_of(&AovParams::default()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // GL takes square arrays of a multiple of six layers for cube maps,
+        // which it can't copy back, so those get a spare one
+        let mut layers = Aov::ALL.len() as u32 - 1;
+        if layers.is_multiple_of(6) && size.width == size.height {
+            layers += 1;
+        }
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("AOV Texture"),
+            size: wgpu::Extent3d {
+                width: size.width.max(1),
+                height: size.height.max(1),
+                depth_or_array_layers: layers,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let views = (0..layers)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let array_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        Self {
+            pipeline,
+            params_buffer,
+            texture,
+            views,
+            array_view,
+        }
+    }
+
+    /// Gathers the AOVs of the latest result of `tracer`, which has to have
+    /// the size they were created with, seen from `camera`. Motion is
+    /// measured from where `prev_camera` saw the same points.
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        tracer: &dyn Renderer,
+        camera: &Camera,
+        prev_camera: &Camera,
+    ) {
+        let params = AovParams {
+            camera: camera.uniform(),
+            prev_camera: prev_camera.uniform(),
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+
+        let [albedo, normal] = tracer.guide_views();
+        let texture = wgpu::BindingResource::TextureView;
+        let views = [albedo, normal, tracer.object_view(), &self.array_view];
+        let entries: Vec<_> = (0..)
+            .zip(views)
+            .map(|(binding, view)| wgpu::BindGroupEntry {
+                binding,
+                resource: texture(view),
+            })
+            .chain([wgpu::BindGroupEntry {
+                binding: 4,
+                resource: self.params_buffer.as_entire_binding(),
+            }])
+            .collect();
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("AOV Bind Group"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &entries,
+        });
+
+        let size = self.texture.size();
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("AOV Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.dispatch_workgroups(
+            size.width.div_ceil(WORKGROUP_SIZE),
+            size.height.div_ceil(WORKGROUP_SIZE),
+            1,
+        );
+    }
+
+    /// The layer holding `aov`, none for the image.
+    pub fn view(&self, aov: Aov) -> Option<&wgpu::TextureView> {
+        aov.layer().map(|layer| &self.views[layer as usize])
+    }
+
+    /// Copies back every AOV besides the image, handing them to `done`
+    /// once they all arrive.
+    pub fn read(
+        &self,
+        device: &wgpu::Device,
+        readbacks: &mut Readbacks,
+        done: impl FnOnce(Vec<(Aov, image::Rgba32FImage)>) + 'static,
+    ) {
+        let layers = Rc::new(RefCell::new(Vec::new()));
+        for aov in Aov::ALL {
+            let layers = layers.clone();
+            self.read_one(device, readbacks, aov, move |layer| {
+                layers.borrow_mut().push((aov, layer));
+            });
+        }
+        readbacks.then(move || done(layers.take()));
+    }
+
+    /// Copies back `aov`, handing it to `done` once it arrives. The image
+    /// has no layer, so `done` is never called for it.
+    pub fn read_one(
+        &self,
+        device: &wgpu::Device,
+        readbacks: &mut Readbacks,
+        aov: Aov,
+        done: impl FnOnce(image::Rgba32FImage) + 'static,
+    ) {
+        if let Some(layer) = aov.layer() {
+            readbacks.read_texture(device, &self.texture, layer, done);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Audio reactive modulation of scene parameters for live visuals.
+//!
+//! Audio is read as raw signed 16-bit little endian mono PCM from a file or
+//! standard input, so a microphone or line in can be piped in with e.g.
+//! `arecord -t raw -f S16_LE -c 1 -r 44100 | spectrum scene.gltf --audio -`.
+//! The latest samples are split into frequency bands, and bindings in the
+//! scene file map each band onto a light, material or instance.
+
+use std::{
+    collections::VecDeque,
+    f32::consts::PI,
+    fs::File,
+    io::Read,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use glam::{Mat4, Vec2, Vec3};
+use web_time::Instant;
+
+use crate::scene::Scene;
+
+/// Number of frequency bands, from bass to treble.
+pub const BAND_COUNT: usize = 8;
+/// Samples in one analysis window, about 46 ms at 44.1 kHz.
+const WINDOW_SIZE: usize = 2048;
+/// Band edges in Hz, spaced evenly in octaves.
+const MIN_FREQUENCY: f32 = 40.0;
+const MAX_FREQUENCY: f32 = 16000.0;
+/// Levels map from this many decibels below full scale up to 0 dB.
+const DYNAMIC_RANGE: f32 = 60.0;
+/// Time constants in seconds of rising and falling levels, so beats hit
+/// immediately but fade out smoothly.
+const ATTACK: f32 = 0.01;
+const RELEASE: f32 = 0.25;
+
+/// Level of every band between 0 and 1.
+pub type Bands = [f32; BAND_COUNT];
+
+/// Analyzes audio streamed in on a background thread.
+pub struct AudioInput {
+    samples: Arc<Mutex<VecDeque<f32>>>,
+    sample_rate: u32,
+    levels: Bands,
+}
+
+impl AudioInput {
+    /// Streams PCM from `path` at `sample_rate`, or from standard input if
+    /// the path is `-`. Files are played back in real time.
+    pub fn open(path: &Path, sample_rate: u32) -> Result<Self> {
+        let source: Box<dyn Read + Send> = if path == Path::new("-") {
+            Box::new(std::io::stdin())
+        } else {
+            Box::new(
+                File::open(path)
+                    .with_context(|| format!("Failed to open audio {}", path.display()))?,
+            )
+        };
+        let samples = Arc::new(Mutex::new(VecDeque::with_capacity(WINDOW_SIZE)));
+        let shared = samples.clone();
+        std::thread::Builder::new()
+            .name(String::from("audio input"))
+            .spawn(move || stream(source, sample_rate, &shared))?;
+        Ok(Self {
+            samples,
+            sample_rate,
+            levels: [0.0; BAND_COUNT],
+        })
+    }
+
+    /// Analyzes the latest window and smooths the levels over `dt` seconds.
+    pub fn update(&mut self, dt: f32) -> Bands {
+        let mut window = vec![Vec2::ZERO; WINDOW_SIZE];
+        {
+            let samples = self.samples.lock().unwrap();
+            let offset = WINDOW_SIZE - samples.len();
+            for (i, &sample) in samples.iter().enumerate() {
+                // Hann window against leakage between bands
+                let hann = 0.5 - 0.5 * (2.0 * PI * (offset + i) as f32 / WINDOW_SIZE as f32).cos();
+                window[offset + i].x = sample * hann;
+            }
+        }
+        fft(&mut window);
+
+        let bin_width = self.sample_rate as f32 / WINDOW_SIZE as f32;
+        let max_frequency = MAX_FREQUENCY.min(0.5 * self.sample_rate as f32);
+        let octaves = (max_frequency / MIN_FREQUENCY).log2();
+        let mut power = [0.0; BAND_COUNT];
+        for (bin, value) in window.iter().enumerate().take(WINDOW_SIZE / 2).skip(1) {
+            let frequency = bin as f32 * bin_width;
+            if !(MIN_FREQUENCY..max_frequency).contains(&frequency) {
+                continue;
+            }
+            let band = ((frequency / MIN_FREQUENCY).log2() / octaves * BAND_COUNT as f32) as usize;
+            power[band.min(BAND_COUNT - 1)] += value.length_squared();
+        }
+
+        for (level, power) in self.levels.iter_mut().zip(power) {
+            // A full scale sine has amplitude 1 after the window's gain of
+            // 1/2 and the half of its energy in negative frequencies
+            let amplitude = 4.0 * power.sqrt() / WINDOW_SIZE as f32;
+            let decibels = 20.0 * amplitude.max(1e-6).log10();
+            let target = (1.0 + decibels / DYNAMIC_RANGE).clamp(0.0, 1.0);
+            let time_constant = if target > *level { ATTACK } else { RELEASE };
+            *level += (target - *level) * (1.0 - (-dt / time_constant).exp());
+        }
+        self.levels
+    }
+}
+
+/// Keeps the latest window of samples, paced to the sample rate.
+fn stream(mut source: Box<dyn Read + Send>, sample_rate: u32, samples: &Mutex<VecDeque<f32>>) {
+    let start = Instant::now();
+    let mut read = 0u64;
+    let mut bytes = [0u8; 1024];
+    while source.read_exact(&mut bytes).is_ok() {
+        {
+            let mut samples = samples.lock().unwrap();
+            for pair in bytes.chunks_exact(2) {
+                if samples.len() == WINDOW_SIZE {
+                    samples.pop_front();
+                }
+                samples.push_back(i16::from_le_bytes([pair[0], pair[1]]) as f32 / 32768.0);
+            }
+        }
+        // Live input arrives in real time anyway, files would be read at once
+        read += bytes.len() as u64 / 2;
+        let due = Duration::from_secs_f64(read as f64 / sample_rate as f64);
+        if let Some(ahead) = due.checked_sub(start.elapsed()) {
+            std::thread::sleep(ahead);
+        }
+    }
+    tracing::info!("Audio input ended");
+}
+
+/// In place radix 2 FFT of a power of two number of complex values.
+fn fft(values: &mut [Vec2]) {
+    let n = values.len();
+    let bits = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if i < j {
+            values.swap(i, j);
+        }
+    }
+    let mut span = 1;
+    while span < n {
+        let angle = -PI / span as f32;
+        for start in (0..n).step_by(2 * span) {
+            for k in 0..span {
+                let twiddle = Vec2::from_angle(angle * k as f32);
+                let a = values[start + k];
+                let b = twiddle.rotate(values[start + k + span]);
+                values[start + k] = a + b;
+                values[start + k + span] = a - b;
+            }
+        }
+        span *= 2;
+    }
+}
+
+/// A scene parameter driven by the level of one band.
+#[derive(Clone, Debug)]
+pub struct AudioBinding {
+    pub band: usize,
+    pub gain: f32,
+    pub target: AudioTarget,
+}
+
+#[derive(Clone, Debug)]
+pub enum AudioTarget {
+    /// Scales the intensity of a light by `1 + gain * level`.
+    LightIntensity { light: usize, base: f32 },
+    /// Adds `color * gain * level` to the emission of a material.
+    Emission {
+        material: usize,
+        base: Vec3,
+        color: Vec3,
+    },
+    /// Scales an instance about its origin by `1 + gain * level`.
+    Scale { instance: usize, base: Mat4 },
+}
+
+/// What `modulate` changed, and so has to be uploaded again.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Modulated {
+    pub lights: bool,
+    pub materials: bool,
+    pub geometry: bool,
+}
+
+/// Applies the scene's audio bindings for the given band levels.
+pub fn modulate(scene: &mut Scene, bands: &Bands) -> Modulated {
+    let mut modulated = Modulated::default();
+    for index in 0..scene.audio_bindings.len() {
+        let binding = &scene.audio_bindings[index];
+        let amount = binding.gain * bands[binding.band];
+        match binding.target {
+            AudioTarget::LightIntensity { light, base } => {
+                scene.lights[light].intensity = base * (1.0 + amount);
+                modulated.lights = true;
+            }
+            AudioTarget::Emission {
+                material,
+                base,
+                color,
+            } => {
+                scene.materials[material].emission = base + color * amount;
+                modulated.materials = true;
+            }
+            AudioTarget::Scale { instance, base } => {
+                let world = base * Mat4::from_scale(Vec3::splat(1.0 + amount));
+                scene.set_instance_transform(instance, world);
+                modulated.geometry = true;
+            }
+        }
+    }
+    modulated
+}
@@ -0,0 +1,131 @@
+//! Autofocus of the physical camera, which keeps the focus distance on
+//! the center of the image or on the selected instance while the camera
+//! and scene move, as along an animated path. The distance is read back
+//! from the normal guide of the tracer, which has the distance to the
+//! first hit in alpha, so it follows a frame or so behind.
+
+use std::{cell::Cell, rc::Rc};
+
+use glam::{UVec2, Vec3};
+use winit::dpi::PhysicalSize;
+
+use crate::{camera::Camera, readback::Readbacks, tracer::Renderer};
+
+/// Smallest relative change of the focus distance that's followed, so
+/// noise in the guide doesn't keep restarting the image.
+const MIN_CHANGE: f32 = 0.02;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AutofocusMode {
+    /// The focus distance is left alone.
+    #[default]
+    Off,
+    /// Focuses on what's at the center of the image.
+    Center,
+    /// Focuses on the selected instance, on what's seen at its center.
+    Selected,
+}
+
+impl AutofocusMode {
+    pub const ALL: [Self; 3] = [Self::Off, Self::Center, Self::Selected];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Center => "center",
+            Self::Selected => "selected",
+        }
+    }
+
+    /// The mode after this one, wrapping around.
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&mode| mode == self);
+        Self::ALL[index.map_or(0, |index| (index + 1) % Self::ALL.len())]
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Autofocus {
+    pub mode: AutofocusMode,
+    /// Distance of the last readback, once it arrives.
+    distance: Rc<Cell<Option<f32>>>,
+    /// Set while a readback is on its way.
+    reading: Rc<Cell<bool>>,
+}
+
+impl Autofocus {
+    pub fn new(mode: AutofocusMode) -> Self {
+        Self {
+            mode,
+            ..Default::default()
+        }
+    }
+
+    /// Starts reading the distance to focus at, and returns the one read
+    /// before if the camera should change to it. `target` is the center
+    /// of the selected instance, if there is one, and `size` that of the
+    /// traced image.
+    pub fn update(
+        &self,
+        device: &wgpu::Device,
+        readbacks: &mut Readbacks,
+        tracer: &dyn Renderer,
+        camera: &Camera,
+        target: Option<Vec3>,
+        size: PhysicalSize<u32>,
+    ) -> Option<f32> {
+        let pixel = match (self.mode, target) {
+            (AutofocusMode::Off, _) | (AutofocusMode::Selected, None) => return None,
+            (AutofocusMode::Center, _) => Some(UVec2::new(size.width / 2, size.height / 2)),
+            (AutofocusMode::Selected, Some(target)) => project(camera, target, size),
+        };
+        if let Some(pixel) = pixel {
+            if !self.reading.replace(true) {
+                let (distance, reading) = (self.distance.clone(), self.reading.clone());
+                readbacks.read_texel(device, tracer.normal_texture(), pixel, move |texel| {
+                    distance.set(Some(texel[3]));
+                    reading.set(false);
+                });
+            }
+        }
+        let read = self.distance.take();
+        // Nothing there, or guides the backend doesn't fill in, leave the
+        // instance to focus on by its distance
+        let distance = match (read.filter(|&distance| distance > 0.0), target) {
+            (Some(distance), _) => distance,
+            (None, Some(target)) if self.mode == AutofocusMode::Selected => {
+                target.distance(camera.position)
+            }
+            (None, _) => return None,
+        };
+        let change = (distance - camera.focus_distance).abs() / camera.focus_distance;
+        (change > MIN_CHANGE).then_some(distance)
+    }
+}
+
+/// Pixel of an image of `size` that sees `point`, as a pinhole camera
+/// would, none if it's out of view.
+fn project(camera: &Camera, point: Vec3, size: PhysicalSize<u32>) -> Option<UVec2> {
+    let dir = point - camera.position;
+    let z = dir.dot(camera.forward());
+    if z <= 0.0 {
+        return None;
+    }
+    let tan_half_fov = (0.5 * camera.fov_y).tan();
+    let aspect = size.width as f32 / size.height as f32;
+    let x = dir.dot(camera.right()) / (z * tan_half_fov * aspect);
+    let y = -dir.dot(camera.up()) / (z * tan_half_fov);
+    if x.abs() >= 1.0 || y.abs() >= 1.0 {
+        return None;
+    }
+    Some(UVec2::new(
+        ((x + 1.0) * 0.5 * size.width as f32) as u32,
+        ((y + 1.0) * 0.5 * size.height as f32) as u32,
+    ))
+}
@@ -0,0 +1,518 @@
+//! HDR stills as AVIF, which phones and browsers show without EXR tooling.
+//! Light goes out as BT.2100 PQ or HLG, or as an sRGB image with an
+//! ISO 21496-1 gain map, which HDR displays apply to bring back what the
+//! sRGB image clipped and other viewers ignore.
+
+use std::path::Path;
+
+use anyhow::{Context as _, Result};
+use glam::{Mat3, Vec3};
+use rav1e::prelude::*;
+
+/// Nits that a linear value of one is shown at, BT.2408's reference white.
+pub const REFERENCE_WHITE: f32 = 203.0;
+/// Scene light that HLG encodes as 75%, the same reference white.
+const HLG_REFERENCE_WHITE: f32 = 0.264_96;
+/// Added to both images before taking their ratio for the gain map, so
+/// black doesn't divide by zero.
+const GAIN_MAP_OFFSET: f32 = 1.0 / 64.0;
+/// rav1e's quantizer, out of 255.
+const QUANTIZER: usize = 60;
+const SPEED: u8 = 6;
+
+const REC709_TO_REC2020: Mat3 = Mat3::from_cols_array(&[
+    0.627_404, 0.069_097, 0.016_391, //
+    0.329_283, 0.919_540, 0.088_013, //
+    0.043_313, 0.011_362, 0.895_595,
+]);
+
+/// How the light above SDR white is stored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HdrEncoding {
+    /// Perceptual quantizer, absolute levels of up to 10000 nits.
+    #[default]
+    Pq,
+    /// Hybrid log-gamma, relative to the display's peak.
+    Hlg,
+    /// An sRGB image and how much brighter each pixel is in HDR.
+    GainMap,
+}
+
+impl HdrEncoding {
+    pub const ALL: [Self; 3] = [Self::Pq, Self::Hlg, Self::GainMap];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|encoding| encoding.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Pq => "pq",
+            Self::Hlg => "hlg",
+            Self::GainMap => "gain-map",
+        }
+    }
+}
+
+pub fn save(image: &image::Rgba32FImage, path: &Path, encoding: HdrEncoding) -> Result<()> {
+    let data = encode(image, encoding)?;
+    std::fs::write(path, data).with_context(|| format!("Failed to save {}", path.display()))
+}
+
+/// Encodes premultiplied linear Rec. 709 colors, like the tracer's output.
+pub fn encode(image: &image::Rgba32FImage, encoding: HdrEncoding) -> Result<Vec<u8>> {
+    let (width, height) = image.dimensions();
+    // AVIF alpha is straight unless the file says otherwise
+    let colors: Vec<Vec3> = image
+        .pixels()
+        .map(|pixel| {
+            let [r, g, b, a] = pixel.0;
+            let unpremultiply = if a > 0.0 { a.recip() } else { 0.0 };
+            (Vec3::new(r, g, b) * unpremultiply).max(Vec3::ZERO)
+        })
+        .collect();
+    let mut items = Vec::new();
+    let mut brands = vec![*b"mif1", *b"miaf", *b"avif"];
+    let mut alternatives = None;
+    match encoding {
+        HdrEncoding::Pq | HdrEncoding::Hlg => {
+            let hdr_color = |transfer_characteristics| ColorDescription {
+                color_primaries: ColorPrimaries::BT2020,
+                transfer_characteristics,
+                matrix_coefficients: MatrixCoefficients::BT2020NCL,
+            };
+            let colors: Vec<Vec3> = colors.iter().map(|&c| REC709_TO_REC2020 * c).collect();
+            if encoding == HdrEncoding::Pq {
+                let signal: Vec<Vec3> = colors
+                    .iter()
+                    .map(|&c| (c * (REFERENCE_WHITE / 10000.0)).map(pq))
+                    .collect();
+                let mut item = color_item(
+                    &signal,
+                    width,
+                    height,
+                    10,
+                    hdr_color(TransferCharacteristics::SMPTE2084),
+                )?;
+                item.properties.push((clli(&colors), false));
+                items.push(item);
+            } else {
+                let signal: Vec<Vec3> = colors
+                    .iter()
+                    .map(|&c| (c * HLG_REFERENCE_WHITE).map(hlg))
+                    .collect();
+                items.push(color_item(
+                    &signal,
+                    width,
+                    height,
+                    10,
+                    hdr_color(TransferCharacteristics::HLG),
+                )?);
+            }
+        }
+        HdrEncoding::GainMap => {
+            let sdr: Vec<Vec3> = colors
+                .iter()
+                .map(|c| c.clamp(Vec3::ZERO, Vec3::ONE))
+                .collect();
+            let signal: Vec<Vec3> = sdr.iter().map(|c| c.map(srgb)).collect();
+            items.push(color_item(
+                &signal,
+                width,
+                height,
+                8,
+                ColorDescription {
+                    color_primaries: ColorPrimaries::BT709,
+                    transfer_characteristics: TransferCharacteristics::SRGB,
+                    matrix_coefficients: MatrixCoefficients::BT709,
+                },
+            )?);
+
+            // Stops of luminance above the sRGB image, applied to all
+            // channels alike
+            let luminance = |c: Vec3| c.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+            let stops: Vec<f32> = colors
+                .iter()
+                .zip(&sdr)
+                .map(|(&hdr, &sdr)| {
+                    ((luminance(hdr) + GAIN_MAP_OFFSET) / (luminance(sdr) + GAIN_MAP_OFFSET)).log2()
+                })
+                .collect();
+            let min = stops.iter().copied().fold(f32::INFINITY, f32::min);
+            // A little headroom even without highlights, since viewers
+            // divide by it
+            let max = stops
+                .iter()
+                .copied()
+                .fold(f32::NEG_INFINITY, f32::max)
+                .max(min + 1.0 / 64.0);
+            let gain: Vec<u16> = stops
+                .iter()
+                .map(|s| ((s - min) / (max - min) * 255.0).round() as u16)
+                .collect();
+            items.push(Item {
+                kind: *b"av01",
+                data: encode_av1(&[gain], width, height, 8, None)?,
+                properties: vec![
+                    (ispe(width, height), false),
+                    (pixi(&[8]), false),
+                    (av1c(8, true), true),
+                ],
+                hidden: true,
+                reference: None,
+            });
+
+            items.push(Item {
+                kind: *b"tmap",
+                data: gain_map_metadata(min, max),
+                properties: vec![
+                    (ispe(width, height), false),
+                    (pixi(&[10, 10, 10]), false),
+                    (
+                        colr(ColorDescription {
+                            color_primaries: ColorPrimaries::BT2020,
+                            transfer_characteristics: TransferCharacteristics::SMPTE2084,
+                            matrix_coefficients: MatrixCoefficients::BT2020NCL,
+                        }),
+                        false,
+                    ),
+                ],
+                hidden: false,
+                reference: Some((*b"dimg", vec![1, 2])),
+            });
+            brands.push(*b"tmap");
+            // Readers that know gain maps pick the tone mapped image
+            alternatives = Some(vec![3, 1]);
+        }
+    }
+
+    if image.pixels().any(|pixel| pixel.0[3] < 1.0) {
+        let depth = if encoding == HdrEncoding::GainMap {
+            8
+        } else {
+            10
+        };
+        let max = ((1 << depth) - 1) as f32;
+        let alpha = image
+            .pixels()
+            .map(|pixel| (pixel.0[3].clamp(0.0, 1.0) * max).round() as u16)
+            .collect();
+        let auxiliary = b"urn:mpeg:mpegB:cicp:systems:auxiliary:alpha\0";
+        items.push(Item {
+            kind: *b"av01",
+            data: encode_av1(&[alpha], width, height, depth, None)?,
+            properties: vec![
+                (ispe(width, height), false),
+                (pixi(&[depth as u8]), false),
+                (av1c(depth, true), true),
+                (full_box(b"auxC", 0, 0, auxiliary), true),
+            ],
+            hidden: false,
+            reference: Some((*b"auxl", vec![1])),
+        });
+    }
+    Ok(container(&items, &brands, alternatives))
+}
+
+/// An image item, whose ID is one more than its index.
+struct Item {
+    kind: [u8; 4],
+    data: Vec<u8>,
+    /// Property boxes, and whether readers have to understand them.
+    properties: Vec<(Vec<u8>, bool)>,
+    hidden: bool,
+    /// Type of reference and the IDs of the items referred to.
+    reference: Option<([u8; 4], Vec<u16>)>,
+}
+
+/// Full range 4:4:4 YCbCr of colors already in `color`'s transfer.
+fn color_item(
+    signal: &[Vec3],
+    width: u32,
+    height: u32,
+    depth: usize,
+    color: ColorDescription,
+) -> Result<Item> {
+    let (kr, kb) = match color.matrix_coefficients {
+        MatrixCoefficients::BT2020NCL => (0.2627, 0.0593),
+        _ => (0.2126, 0.0722),
+    };
+    let max = ((1 << depth) - 1) as f32;
+    let middle = (1 << (depth - 1)) as f32;
+    let quantize = |value: f32, offset: f32| (value * max + offset).round().clamp(0.0, max) as u16;
+    let mut planes = [(); 3].map(|_| Vec::with_capacity(signal.len()));
+    for c in signal {
+        let y = kr * c.x + (1.0 - kr - kb) * c.y + kb * c.z;
+        planes[0].push(quantize(y, 0.0));
+        planes[1].push(quantize((c.z - y) / (2.0 * (1.0 - kb)), middle));
+        planes[2].push(quantize((c.x - y) / (2.0 * (1.0 - kr)), middle));
+    }
+    Ok(Item {
+        kind: *b"av01",
+        data: encode_av1(&planes, width, height, depth, Some(color))?,
+        properties: vec![
+            (ispe(width, height), false),
+            (pixi(&[depth as u8; 3]), false),
+            (av1c(depth, false), true),
+            (colr(color), false),
+        ],
+        hidden: false,
+        reference: None,
+    })
+}
+
+/// Compresses one plane as monochrome or three as 4:4:4.
+fn encode_av1(
+    planes: &[Vec<u16>],
+    width: u32,
+    height: u32,
+    depth: usize,
+    color: Option<ColorDescription>,
+) -> Result<Vec<u8>> {
+    let (width, height) = (width as usize, height as usize);
+    let config = Config::new().with_encoder_config(EncoderConfig {
+        width,
+        height,
+        bit_depth: depth,
+        chroma_sampling: if planes.len() == 1 {
+            ChromaSampling::Cs400
+        } else {
+            ChromaSampling::Cs444
+        },
+        pixel_range: PixelRange::Full,
+        color_description: color,
+        still_picture: true,
+        quantizer: QUANTIZER,
+        min_quantizer: QUANTIZER as u8,
+        // Tiles are what rav1e encodes in parallel
+        tiles: rayon::current_num_threads().min(width * height / (128 * 128)),
+        speed_settings: SpeedSettings::from_preset(SPEED),
+        ..Default::default()
+    });
+    let mut context: Context<u16> = config.new_context().context("Invalid AV1 settings")?;
+    let mut frame = context.new_frame();
+    for (plane, values) in frame.planes.iter_mut().zip(planes) {
+        plane.copy_from_raw_u8(bytemuck::cast_slice(values), width * 2, 2);
+    }
+    context.send_frame(frame).context("Failed to encode AV1")?;
+    context.flush();
+    let mut data = Vec::new();
+    loop {
+        match context.receive_packet() {
+            Ok(mut packet) => data.append(&mut packet.data),
+            Err(EncoderStatus::Encoded) => {}
+            Err(EncoderStatus::LimitReached) => break,
+            Err(err) => return Err(err).context("Failed to encode AV1"),
+        }
+    }
+    Ok(data)
+}
+
+/// ISO 21496-1 metadata of a single channel gain map between `min` and
+/// `max` stops, as AVIF stores it.
+fn gain_map_metadata(min: f32, max: f32) -> Vec<u8> {
+    const DENOMINATOR: u32 = 1 << 16;
+    let mut data = vec![0];
+    // Minimum and writer version
+    data.extend(0u16.to_be_bytes());
+    data.extend(0u16.to_be_bytes());
+    // One channel, applied to linear light in the sRGB image's primaries
+    data.push(1 << 6);
+    let mut fraction = |value: f32| {
+        data.extend(((value * DENOMINATOR as f32).round() as i32).to_be_bytes());
+        data.extend(DENOMINATOR.to_be_bytes());
+    };
+    // Headroom of the sRGB image and the HDR one, in stops
+    fraction(0.0);
+    fraction(max);
+    fraction(min);
+    fraction(max);
+    // Gamma
+    fraction(1.0);
+    fraction(GAIN_MAP_OFFSET);
+    fraction(GAIN_MAP_OFFSET);
+    data
+}
+
+/// Writes the items and their data into one file, the first item being
+/// the primary one.
+fn container(items: &[Item], brands: &[[u8; 4]], alternatives: Option<Vec<u16>>) -> Vec<u8> {
+    let mut ftyp = b"avif".to_vec();
+    ftyp.extend(0u32.to_be_bytes());
+    ftyp.extend(brands.iter().flatten());
+    let ftyp = basic_box(b"ftyp", &ftyp);
+
+    let meta = |data_start: u32| {
+        let mut hdlr = 0u32.to_be_bytes().to_vec();
+        hdlr.extend(b"pict");
+        hdlr.extend([0; 13]);
+        let mut meta = full_box(b"hdlr", 0, 0, &hdlr);
+        meta.extend(full_box(b"pitm", 0, 0, &1u16.to_be_bytes()));
+
+        // Four byte offsets and lengths
+        let mut iloc = vec![0x44, 0];
+        iloc.extend((items.len() as u16).to_be_bytes());
+        let mut offset = data_start;
+        for (id, item) in (1u16..).zip(items) {
+            iloc.extend(id.to_be_bytes());
+            iloc.extend(0u16.to_be_bytes());
+            iloc.extend(1u16.to_be_bytes());
+            iloc.extend(offset.to_be_bytes());
+            iloc.extend((item.data.len() as u32).to_be_bytes());
+            offset += item.data.len() as u32;
+        }
+        meta.extend(full_box(b"iloc", 0, 0, &iloc));
+
+        let mut iinf = (items.len() as u16).to_be_bytes().to_vec();
+        for (id, item) in (1u16..).zip(items) {
+            let mut infe = id.to_be_bytes().to_vec();
+            infe.extend(0u16.to_be_bytes());
+            infe.extend(item.kind);
+            infe.push(0);
+            iinf.extend(full_box(b"infe", 2, item.hidden as u32, &infe));
+        }
+        meta.extend(full_box(b"iinf", 0, 0, &iinf));
+
+        let mut iref = Vec::new();
+        for (id, item) in (1u16..).zip(items) {
+            if let Some((kind, to)) = &item.reference {
+                let mut reference = id.to_be_bytes().to_vec();
+                reference.extend((to.len() as u16).to_be_bytes());
+                reference.extend(to.iter().flat_map(|id| id.to_be_bytes()));
+                iref.extend(basic_box(kind, &reference));
+            }
+        }
+        if !iref.is_empty() {
+            meta.extend(full_box(b"iref", 0, 0, &iref));
+        }
+
+        let mut ipco = Vec::new();
+        let mut ipma = (items.len() as u32).to_be_bytes().to_vec();
+        let mut index = 1;
+        for (id, item) in (1u16..).zip(items) {
+            ipma.extend(id.to_be_bytes());
+            ipma.push(item.properties.len() as u8);
+            for (property, essential) in &item.properties {
+                ipco.extend(property);
+                ipma.push((*essential as u8) << 7 | index);
+                index += 1;
+            }
+        }
+        let mut iprp = basic_box(b"ipco", &ipco);
+        iprp.extend(full_box(b"ipma", 0, 0, &ipma));
+        meta.extend(basic_box(b"iprp", &iprp));
+
+        if let Some(alternatives) = &alternatives {
+            // Group IDs share the space of item IDs
+            let mut altr = (items.len() as u32 + 1).to_be_bytes().to_vec();
+            altr.extend((alternatives.len() as u32).to_be_bytes());
+            altr.extend(
+                alternatives
+                    .iter()
+                    .flat_map(|&id| u32::from(id).to_be_bytes()),
+            );
+            meta.extend(basic_box(b"grpl", &full_box(b"altr", 0, 0, &altr)));
+        }
+        full_box(b"meta", 0, 0, &meta)
+    };
+    // Offsets have a fixed size, so the box is as long with any of them
+    let data_start = (ftyp.len() + meta(0).len() + 8) as u32;
+    let mut file = ftyp;
+    file.extend(meta(data_start));
+    let data: Vec<u8> = items.iter().flat_map(|item| item.data.clone()).collect();
+    file.extend(basic_box(b"mdat", &data));
+    file
+}
+
+fn basic_box(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut data = (content.len() as u32 + 8).to_be_bytes().to_vec();
+    data.extend(kind);
+    data.extend(content);
+    data
+}
+
+fn full_box(kind: &[u8; 4], version: u8, flags: u32, content: &[u8]) -> Vec<u8> {
+    let mut data = ((version as u32) << 24 | flags).to_be_bytes().to_vec();
+    data.extend(content);
+    basic_box(kind, &data)
+}
+
+fn ispe(width: u32, height: u32) -> Vec<u8> {
+    let mut size = width.to_be_bytes().to_vec();
+    size.extend(height.to_be_bytes());
+    full_box(b"ispe", 0, 0, &size)
+}
+
+fn pixi(depths: &[u8]) -> Vec<u8> {
+    let mut pixi = vec![depths.len() as u8];
+    pixi.extend(depths);
+    full_box(b"pixi", 0, 0, &pixi)
+}
+
+/// Has to match the sequence header rav1e writes, which uses the high
+/// profile for 4:4:4.
+fn av1c(depth: usize, monochrome: bool) -> Vec<u8> {
+    let profile = if monochrome { 0 } else { 1 };
+    // Monochrome also counts as subsampled both ways
+    let monochrome = monochrome as u8;
+    let flags = ((depth >= 10) as u8) << 6 | monochrome << 4 | monochrome << 3 | monochrome << 2;
+    // Level 31 is the one without limits
+    basic_box(b"av1C", &[0x81, profile << 5 | 31, flags, 0])
+}
+
+fn colr(color: ColorDescription) -> Vec<u8> {
+    let mut colr = b"nclx".to_vec();
+    colr.extend((color.color_primaries as u16).to_be_bytes());
+    colr.extend((color.transfer_characteristics as u16).to_be_bytes());
+    colr.extend((color.matrix_coefficients as u16).to_be_bytes());
+    // Full range
+    colr.push(0x80);
+    basic_box(b"colr", &colr)
+}
+
+/// Brightest pixel and average brightness in nits.
+fn clli(colors: &[Vec3]) -> Vec<u8> {
+    let nits: Vec<f32> = colors
+        .iter()
+        .map(|c| c.max_element() * REFERENCE_WHITE)
+        .collect();
+    let max = nits.iter().copied().fold(0.0, f32::max);
+    let average = nits.iter().sum::<f32>() / nits.len().max(1) as f32;
+    let mut clli = (max.min(10000.0) as u16).to_be_bytes().to_vec();
+    clli.extend((average.min(10000.0) as u16).to_be_bytes());
+    basic_box(b"clli", &clli)
+}
+
+/// SMPTE ST 2084 of light relative to 10000 nits.
+fn pq(light: f32) -> f32 {
+    const M1: f32 = 0.159_301_76;
+    const M2: f32 = 78.843_75;
+    const C1: f32 = 0.835_937_5;
+    const C2: f32 = 18.851_563;
+    const C3: f32 = 18.6875;
+    let y = light.clamp(0.0, 1.0).powf(M1);
+    ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+}
+
+/// BT.2100 HLG of scene light relative to the peak.
+fn hlg(light: f32) -> f32 {
+    const A: f32 = 0.178_832_77;
+    const B: f32 = 0.284_668_92;
+    const C: f32 = 0.559_910_7;
+    let light = light.clamp(0.0, 1.0);
+    if light <= 1.0 / 12.0 {
+        (3.0 * light).sqrt()
+    } else {
+        A * (12.0 * light - B).ln() + C
+    }
+}
+
+fn srgb(linear: f32) -> f32 {
+    if linear <= 0.003_130_8 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
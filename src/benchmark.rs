@@ -0,0 +1,196 @@
+//! Times the GPU tracer on scenes heavy on triangles, for judging changes
+//! to the kernels and to how the scene buffers are laid out.
+
+use std::{f32::consts::PI, fmt};
+
+use anyhow::Result;
+use glam::{Mat4, Vec2, Vec3};
+use web_time::Instant;
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    bvh::InstancedBvh,
+    camera::Camera,
+    ocean::Ocean,
+    scene::{Instance, Material, Mesh, Scene},
+    spectral::Conductor,
+    thumbnail,
+    tracer::{Backend, Renderer, TraceSettings},
+};
+
+/// Frames traced before timing, which compile the kernels and warm up.
+const WARMUP_FRAMES: u32 = 4;
+const FRAMES: u32 = 32;
+
+pub struct Benchmark {
+    size: PhysicalSize<u32>,
+    adapter: String,
+    results: Vec<BenchmarkResult>,
+}
+
+struct BenchmarkResult {
+    scene: String,
+    triangles: usize,
+    /// Seconds per frame of one sample per pixel.
+    frame_time: f64,
+}
+
+/// Built in scenes the benchmark runs without one given: a grid of finely
+/// tessellated spheres in diffuse, metal and glass, and a calm ocean
+/// seen from above.
+pub fn scenes() -> Vec<(String, Scene)> {
+    let mut ocean = Scene::default();
+    ocean.add_ocean(Ocean {
+        resolution: 256,
+        amplitude: 0.0,
+        ..Default::default()
+    });
+    vec![
+        (String::from("spheres"), spheres()),
+        (String::from("ocean"), ocean),
+    ]
+}
+
+/// Traces `scenes` at `size` on the GPU and times each frame.
+pub fn run(scenes: Vec<(String, Scene)>, size: PhysicalSize<u32>) -> Result<Benchmark> {
+    let (adapter, device, queue) = thumbnail::request_device()?;
+    let settings = TraceSettings::default();
+    let mut results = Vec::with_capacity(scenes.len());
+    for (name, scene) in scenes {
+        let bvh = InstancedBvh::new(&scene);
+        let mut tracer = Backend::Gpu.create(&device, &queue, &scene, &bvh, size);
+        let mut camera = Camera::default();
+        camera.frame(&scene.bounds(), size.width as f32 / size.height as f32);
+        render(
+            &device,
+            &queue,
+            tracer.as_mut(),
+            &camera,
+            &settings,
+            WARMUP_FRAMES,
+        );
+        let start = Instant::now();
+        render(&device, &queue, tracer.as_mut(), &camera, &settings, FRAMES);
+        results.push(BenchmarkResult {
+            scene: name,
+            triangles: scene.triangle_count(),
+            frame_time: start.elapsed().as_secs_f64() / FRAMES as f64,
+        });
+    }
+    Ok(Benchmark {
+        size,
+        adapter: adapter.get_info().name,
+        results,
+    })
+}
+
+fn render(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    tracer: &mut dyn Renderer,
+    camera: &Camera,
+    settings: &TraceSettings,
+    frames: u32,
+) {
+    for _ in 0..frames {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Benchmark Encoder"),
+        });
+        tracer.render_frame(device, queue, &mut encoder, camera, settings);
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+    device.poll(wgpu::Maintain::Wait);
+}
+
+impl fmt::Display for Benchmark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pixels = (self.size.width * self.size.height) as f64;
+        write!(
+            f,
+            "{} at {}x{}\n{:<12}{:>12}{:>12}{:>14}",
+            self.adapter,
+            self.size.width,
+            self.size.height,
+            "Scene",
+            "Triangles",
+            "ms/frame",
+            "Msamples/s"
+        )?;
+        for result in &self.results {
+            write!(
+                f,
+                "\n{:<12}{:>12}{:>12.2}{:>14.2}",
+                result.scene,
+                result.triangles,
+                result.frame_time * 1000.0,
+                pixels / result.frame_time / 1e6
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Eight by eight spheres of 8192 triangles each.
+fn spheres() -> Scene {
+    const COUNT: i32 = 8;
+    let mut scene = Scene::default();
+    scene.meshes.push(uv_sphere(64, 64));
+    let materials = [
+        Material::default(),
+        Material::metal(Conductor::Copper),
+        Material {
+            transmission: 1.0,
+            roughness: 0.0,
+            ..Default::default()
+        },
+    ];
+    for material in materials {
+        scene.add_material(material);
+    }
+    for z in 0..COUNT {
+        for x in 0..COUNT {
+            let center = Vec3::new(x as f32, 0.0, z as f32) * 2.5;
+            scene.instances.push(Instance {
+                mesh: 0,
+                material: (x + z) as usize % scene.materials.len(),
+                transform: Mat4::from_translation(center),
+                lod: 0,
+                body: None,
+                holdout: false,
+                class: 0,
+            });
+        }
+    }
+    scene
+}
+
+/// Unit sphere of `segments` around and `rings` from pole to pole.
+fn uv_sphere(segments: u32, rings: u32) -> Mesh {
+    let mut mesh = Mesh {
+        name: String::from("sphere"),
+        ..Default::default()
+    };
+    for ring in 0..=rings {
+        for segment in 0..=segments {
+            let uv = Vec2::new(segment as f32 / segments as f32, ring as f32 / rings as f32);
+            let (theta, phi) = (uv.y * PI, uv.x * 2.0 * PI);
+            let n = Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            );
+            mesh.positions.push(n);
+            mesh.normals.push(n);
+            mesh.uvs.push(uv);
+        }
+    }
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let v = ring * (segments + 1) + segment;
+            let below = v + segments + 1;
+            mesh.indices
+                .extend_from_slice(&[v, v + 1, below, v + 1, below + 1, below]);
+        }
+    }
+    mesh
+}
@@ -0,0 +1,81 @@
+//! Tileable blue noise mask made with Ulichney's void-and-cluster method.
+//! Neighboring pixels get very different values, so per-pixel error driven
+//! by it looks like fine grain instead of the clumps of white noise.
+
+/// Width of the energy filter in pixels.
+const SIGMA: f32 = 1.5;
+
+/// Returns `size` squared values in [0, 1), every one of them once.
+pub fn mask(size: usize) -> Vec<f32> {
+    let n = size * size;
+    // The filter is negligible past three sigma
+    let radius = ((3.0 * SIGMA).ceil() as isize).min(size as isize / 2);
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0.0; n];
+    let toggle = |pattern: &mut [bool], energy: &mut [f32], i: usize| {
+        pattern[i] = !pattern[i];
+        let sign = if pattern[i] { 1.0 } else { -1.0 };
+        let (x, y) = ((i % size) as isize, (i / size) as isize);
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let j = (x + dx).rem_euclid(size as isize) as usize
+                    + (y + dy).rem_euclid(size as isize) as usize * size;
+                let r2 = (dx * dx + dy * dy) as f32;
+                energy[j] += sign * (-r2 / (2.0 * SIGMA * SIGMA)).exp();
+            }
+        }
+    };
+    // Tightest cluster among the set pixels, or largest void among the rest
+    let extreme = |pattern: &[bool], energy: &[f32], set: bool| {
+        let candidates = (0..n).filter(|&i| pattern[i] == set);
+        if set {
+            candidates.max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+        } else {
+            candidates.min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+        }
+        .expect("pattern has pixels of both kinds")
+    };
+
+    // Start from a tenth of the pixels at random and even them out
+    let ones = (n / 10).max(1);
+    let mut state = 0x9e37_79b9_u32;
+    let mut placed = 0;
+    while placed < ones {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let i = state as usize % n;
+        if !pattern[i] {
+            toggle(&mut pattern, &mut energy, i);
+            placed += 1;
+        }
+    }
+    loop {
+        let cluster = extreme(&pattern, &energy, true);
+        toggle(&mut pattern, &mut energy, cluster);
+        let void = extreme(&pattern, &energy, false);
+        toggle(&mut pattern, &mut energy, void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    // Rank the initial pixels by taking clusters away, then the others by
+    // filling voids
+    let mut rank = vec![0; n];
+    let (initial_pattern, initial_energy) = (pattern.clone(), energy.clone());
+    for r in (0..ones).rev() {
+        let cluster = extreme(&pattern, &energy, true);
+        toggle(&mut pattern, &mut energy, cluster);
+        rank[cluster] = r;
+    }
+    let (mut pattern, mut energy) = (initial_pattern, initial_energy);
+    for r in ones..n {
+        let void = extreme(&pattern, &energy, false);
+        toggle(&mut pattern, &mut energy, void);
+        rank[void] = r;
+    }
+    rank.into_iter()
+        .map(|r| (r as f32 + 0.5) / n as f32)
+        .collect()
+}
@@ -0,0 +1,259 @@
+use glam::Vec3;
+
+mod instanced;
+
+pub use instanced::InstancedBvh;
+
+const BIN_COUNT: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub const EMPTY: Self = Self {
+        min: Vec3::INFINITY,
+        max: Vec3::NEG_INFINITY,
+    };
+
+    pub fn grow(&mut self, p: Vec3) {
+        self.min = self.min.min(p);
+        self.max = self.max.max(p);
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    pub fn center(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let e = self.extent();
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+}
+
+/// Flattened node layout shared with the GPU.
+/// Leaves have `count > 0` and `left_first` points into `Bvh::indices`,
+/// otherwise the children are `left_first` and `left_first + 1`.
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct BvhNode {
+    pub min: [f32; 3],
+    pub left_first: u32,
+    pub max: [f32; 3],
+    pub count: u32,
+}
+
+impl BvhNode {
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        Aabb {
+            min: self.min.into(),
+            max: self.max.into(),
+        }
+    }
+
+    fn set_aabb(&mut self, aabb: &Aabb) {
+        self.min = aabb.min.into();
+        self.max = aabb.max.into();
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    pub indices: Vec<u32>,
+}
+
+impl Bvh {
+    /// Builds a binned SAH BVH over the given primitive bounds.
+    pub fn build(bounds: &[Aabb]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(2 * bounds.len().max(1)),
+            indices: (0..bounds.len() as u32).collect(),
+        };
+        let centers: Vec<Vec3> = bounds.iter().map(Aabb::center).collect();
+
+        bvh.nodes.push(BvhNode {
+            count: bounds.len() as u32,
+            ..Default::default()
+        });
+        if bounds.is_empty() {
+            // Slab tests still hit an inverted box, so the root is a leaf of
+            // the degenerate triangle the tracers upload in place of an
+            // empty buffer, which no ray hits
+            bvh.nodes[0].count = 1;
+            bvh.nodes[0].set_aabb(&Aabb::EMPTY);
+            return bvh;
+        }
+
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = bvh.nodes[node_index];
+            let (first, count) = (node.left_first as usize, node.count as usize);
+            let prims = &mut bvh.indices[first..first + count];
+
+            let aabb = prims
+                .iter()
+                .fold(Aabb::EMPTY, |acc, &i| acc.union(&bounds[i as usize]));
+            bvh.nodes[node_index].set_aabb(&aabb);
+
+            if count <= MAX_LEAF_SIZE {
+                continue;
+            }
+            let Some((axis, split)) = find_split(prims, bounds, &centers, &aabb) else {
+                continue;
+            };
+
+            // Partition the primitives in place around the split plane
+            let mut i = 0;
+            let mut j = count;
+            while i < j {
+                if centers[prims[i] as usize][axis] < split {
+                    i += 1;
+                } else {
+                    j -= 1;
+                    prims.swap(i, j);
+                }
+            }
+            if i == 0 || i == count {
+                continue;
+            }
+
+            let left = bvh.nodes.len();
+            bvh.nodes.push(BvhNode {
+                left_first: first as u32,
+                count: i as u32,
+                ..Default::default()
+            });
+            bvh.nodes.push(BvhNode {
+                left_first: (first + i) as u32,
+                count: (count - i) as u32,
+                ..Default::default()
+            });
+            bvh.nodes[node_index].left_first = left as u32;
+            bvh.nodes[node_index].count = 0;
+            stack.push(left);
+            stack.push(left + 1);
+        }
+
+        bvh
+    }
+
+    /// Updates the bounds of every node after primitives moved, keeping the
+    /// tree. Much cheaper than a rebuild, but traversal slows down the
+    /// further primitives move from where the tree was built.
+    pub fn refit(&mut self, bounds: &[Aabb]) {
+        if self.indices.is_empty() {
+            return;
+        }
+        // Children are always stored after their parent
+        for i in (0..self.nodes.len()).rev() {
+            let node = self.nodes[i];
+            let first = node.left_first as usize;
+            let aabb = if node.is_leaf() {
+                self.indices[first..first + node.count as usize]
+                    .iter()
+                    .fold(Aabb::EMPTY, |acc, &p| acc.union(&bounds[p as usize]))
+            } else {
+                self.nodes[first]
+                    .aabb()
+                    .union(&self.nodes[first + 1].aabb())
+            };
+            self.nodes[i].set_aabb(&aabb);
+        }
+    }
+
+    /// Number of nodes on the longest root to leaf path.
+    pub fn depth(&self) -> u32 {
+        if self.indices.is_empty() {
+            return 0;
+        }
+        let mut max_depth = 0;
+        let mut stack = vec![(0, 1)];
+        while let Some((node_index, depth)) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if node.is_leaf() {
+                max_depth = max_depth.max(depth);
+            } else {
+                let left = node.left_first as usize;
+                stack.push((left, depth + 1));
+                stack.push((left + 1, depth + 1));
+            }
+        }
+        max_depth
+    }
+}
+
+/// Returns the (axis, position) of the cheapest SAH split, or `None`
+/// when keeping the primitives in a single leaf is cheaper.
+fn find_split(
+    prims: &[u32],
+    bounds: &[Aabb],
+    centers: &[Vec3],
+    aabb: &Aabb,
+) -> Option<(usize, f32)> {
+    let centroid_bounds = prims.iter().fold(Aabb::EMPTY, |mut acc, &i| {
+        acc.grow(centers[i as usize]);
+        acc
+    });
+
+    let mut best: Option<(usize, f32)> = None;
+    let mut best_cost = prims.len() as f32 * aabb.surface_area();
+    for axis in [0, 1, 2] {
+        let lo = centroid_bounds.min[axis];
+        let hi = centroid_bounds.max[axis];
+        if hi <= lo {
+            continue;
+        }
+
+        let mut bins = [(Aabb::EMPTY, 0usize); BIN_COUNT];
+        let scale = BIN_COUNT as f32 / (hi - lo);
+        for &i in prims {
+            let bin = (((centers[i as usize][axis] - lo) * scale) as usize).min(BIN_COUNT - 1);
+            bins[bin].0 = bins[bin].0.union(&bounds[i as usize]);
+            bins[bin].1 += 1;
+        }
+
+        // Sweep from both sides to get the cost of every bin boundary
+        let mut right_cost = [0.0; BIN_COUNT - 1];
+        let mut acc = (Aabb::EMPTY, 0);
+        for b in (1..BIN_COUNT).rev() {
+            acc = (acc.0.union(&bins[b].0), acc.1 + bins[b].1);
+            right_cost[b - 1] = acc.1 as f32 * acc.0.surface_area();
+        }
+        let mut acc = (Aabb::EMPTY, 0);
+        for b in 0..BIN_COUNT - 1 {
+            acc = (acc.0.union(&bins[b].0), acc.1 + bins[b].1);
+            let cost = acc.1 as f32 * acc.0.surface_area() + right_cost[b];
+            if cost < best_cost {
+                best_cost = cost;
+                best = Some((axis, lo + (b + 1) as f32 / scale));
+            }
+        }
+    }
+    best
+}
@@ -0,0 +1,214 @@
+//! Two-level BVH: a bottom-level tree per unique mesh and detail level,
+//! built once in the mesh's own space, and a top-level tree over the
+//! instances. Moving instances only rebuilds the small top-level tree.
+//!
+//! The tracers walk both levels from the nodes laid out by `nodes`, moving
+//! rays into the space of a mesh at a top-level leaf, so the triangles of a
+//! mesh are stored once however often it's instanced.
+
+use std::collections::HashMap;
+
+use glam::{BVec3, Mat4, Vec3};
+
+use super::{Aabb, Bvh, BvhNode};
+use crate::scene::Scene;
+
+#[derive(Clone, Debug, Default)]
+pub struct InstancedBvh {
+    /// Bottom-level trees in the order they were first needed, of the mesh
+    /// levels with any triangles.
+    blas: Vec<Blas>,
+    /// Index into `blas` by mesh and detail level.
+    blas_index: HashMap<(usize, usize), usize>,
+    /// Top-level tree, indexing `instances`.
+    tlas: Bvh,
+    /// Instances with any triangles, as a tree leaf can't be empty.
+    instances: Vec<usize>,
+}
+
+#[derive(Clone, Debug)]
+struct Blas {
+    mesh: usize,
+    lod: usize,
+    /// Indexes the triangles of that level.
+    bvh: Bvh,
+    /// Nodes of the trees before it in `nodes`, after the top-level ones.
+    first_node: u32,
+    /// Triangles of the trees before it, in the order of `triangles`.
+    first_triangle: u32,
+}
+
+impl InstancedBvh {
+    pub fn new(scene: &Scene) -> Self {
+        let mut bvh = Self::default();
+        bvh.update(scene);
+        bvh
+    }
+
+    /// Rebuilds the top-level tree after instances moved, switched detail
+    /// level or were added. Bottom-level trees are only built for meshes
+    /// and levels not seen before, so the meshes themselves mustn't change.
+    pub fn update(&mut self, scene: &Scene) {
+        let ocean = scene
+            .ocean
+            .as_ref()
+            .map(|(instance, ocean)| (scene.instances[*instance].mesh, ocean.max_displacement()));
+        for instance in &scene.instances {
+            let key = (instance.mesh, instance.lod);
+            let mesh = &scene.meshes[instance.mesh];
+            let indices = mesh.lod_indices(instance.lod);
+            if indices.is_empty() || self.blas_index.contains_key(&key) {
+                continue;
+            }
+            // Water triangles move anywhere within the wave amplitude
+            let padding = match ocean {
+                Some((ocean_mesh, padding)) if ocean_mesh == instance.mesh => padding,
+                _ => 0.0,
+            };
+            let bounds: Vec<Aabb> = indices
+                .chunks_exact(3)
+                .map(|triangle| {
+                    let mut aabb = triangle.iter().fold(Aabb::EMPTY, |mut aabb, &v| {
+                        aabb.grow(mesh.positions[v as usize]);
+                        aabb
+                    });
+                    aabb.min -= Vec3::splat(padding);
+                    aabb.max += Vec3::splat(padding);
+                    aabb
+                })
+                .collect();
+            let (first_node, first_triangle) = self.blas.last().map_or((0, 0), |last| {
+                (
+                    last.first_node + last.bvh.nodes.len() as u32,
+                    last.first_triangle + last.bvh.indices.len() as u32,
+                )
+            });
+            self.blas_index.insert(key, self.blas.len());
+            self.blas.push(Blas {
+                mesh: instance.mesh,
+                lod: instance.lod,
+                bvh: Bvh::build(&bounds),
+                first_node,
+                first_triangle,
+            });
+        }
+        self.instances = (0..scene.instances.len())
+            .filter(|&index| {
+                let instance = &scene.instances[index];
+                self.blas_index.contains_key(&(instance.mesh, instance.lod))
+            })
+            .collect();
+        self.tlas = Bvh::build(&self.instance_bounds(scene));
+    }
+
+    /// Refits the top-level tree around instances that moved, keeping its
+    /// structure. Quicker than `update`, but the tree loosens as the
+    /// instances move away from where it was built.
+    pub fn refit(&mut self, scene: &Scene) {
+        let bounds = self.instance_bounds(scene);
+        self.tlas.refit(&bounds);
+    }
+
+    /// Number of nodes the top-level tree takes at the start of `nodes`,
+    /// enough for any tree over the same instances so it can be rebuilt in
+    /// place.
+    pub fn tlas_capacity(&self) -> usize {
+        2 * self.instances.len().max(1)
+    }
+
+    /// The top-level nodes, padded to `tlas_capacity`. Their leaves index
+    /// `instances`.
+    pub fn tlas_nodes(&self) -> Vec<BvhNode> {
+        let mut nodes = self.tlas.nodes.clone();
+        nodes.resize(self.tlas_capacity(), BvhNode::default());
+        nodes
+    }
+
+    /// The top-level nodes followed by every bottom-level tree, whose leaves
+    /// index `triangles` and whose children are offset to where they ended
+    /// up. An empty scene only has the top-level root, a leaf of a single
+    /// placeholder instance and triangle.
+    pub fn nodes(&self) -> Vec<BvhNode> {
+        let mut nodes = self.tlas_nodes();
+        let tlas_capacity = nodes.len() as u32;
+        for blas in &self.blas {
+            nodes.extend(blas.bvh.nodes.iter().map(|&node| {
+                let offset = match node.is_leaf() {
+                    true => blas.first_triangle,
+                    false => tlas_capacity + blas.first_node,
+                };
+                BvhNode {
+                    left_first: node.left_first + offset,
+                    ..node
+                }
+            }));
+        }
+        nodes
+    }
+
+    /// The instances in the order the top-level leaves index them, with the
+    /// root of their bottom-level tree in `nodes`.
+    pub fn instances(&self, scene: &Scene) -> Vec<(usize, u32)> {
+        let tlas_capacity = self.tlas_capacity() as u32;
+        self.tlas
+            .indices
+            .iter()
+            .map(|&leaf| {
+                let index = self.instances[leaf as usize];
+                (index, tlas_capacity + self.blas_of(scene, index).first_node)
+            })
+            .collect()
+    }
+
+    /// The mesh, detail level and index within that level of every triangle
+    /// the bottom-level leaves index, in order.
+    pub fn triangles(&self) -> impl Iterator<Item = (usize, usize, u32)> + '_ {
+        self.blas.iter().flat_map(|blas| {
+            blas.bvh
+                .indices
+                .iter()
+                .map(|&triangle| (blas.mesh, blas.lod, triangle))
+        })
+    }
+
+    /// Number of nodes on the longest path through both levels.
+    pub fn depth(&self) -> u32 {
+        let blas_depth = self.blas.iter().map(|blas| blas.bvh.depth()).max();
+        self.tlas.depth() + blas_depth.unwrap_or(0)
+    }
+
+    /// Entries a traversal stack needs at most: the far child of every
+    /// level above a leaf of either tree, and the instances of the largest
+    /// top-level leaf.
+    pub fn stack_size(&self) -> u32 {
+        let leaf_size = self.tlas.nodes.iter().filter(|node| node.is_leaf());
+        let leaf_size = leaf_size.map(|node| node.count).max();
+        self.depth() + leaf_size.unwrap_or(0)
+    }
+
+    /// World space bounds of every instance in `instances`, from the
+    /// corners of the root of its bottom-level tree.
+    fn instance_bounds(&self, scene: &Scene) -> Vec<Aabb> {
+        self.instances
+            .iter()
+            .map(|&index| {
+                let root = self.blas_of(scene, index).bvh.nodes[0].aabb();
+                transform_aabb(scene.instances[index].transform, &root)
+            })
+            .collect()
+    }
+
+    fn blas_of(&self, scene: &Scene, instance: usize) -> &Blas {
+        let instance = &scene.instances[instance];
+        &self.blas[self.blas_index[&(instance.mesh, instance.lod)]]
+    }
+}
+
+fn transform_aabb(transform: Mat4, aabb: &Aabb) -> Aabb {
+    let mut bounds = Aabb::EMPTY;
+    for corner in 0..8 {
+        let select = BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0);
+        bounds.grow(transform.transform_point3(Vec3::select(select, aabb.max, aabb.min)));
+    }
+    bounds
+}
@@ -0,0 +1,384 @@
+//! Guided calibration of the display transform against a monitor, in two
+//! steps:
+//!
+//! 1. `spectrum calibrate --illuminant a -o chart.png` renders a chart of
+//!    the 24 ColorChecker patches lit by the illuminant, through the same
+//!    spectral integration as the renderer, and writes `chart.csv` with the
+//!    linear color each patch should show.
+//! 2. With the chart full screen, each patch is measured with a colorimeter
+//!    and its X, Y and Z filled into the CSV. `spectrum calibrate
+//!    --measurements chart.csv -o display.cube` fits how the monitor is off
+//!    and writes a display LUT undoing it, for `--display-lut`.
+//!
+//! The chart rendered again with `--display-lut display.cube` shows through
+//! the fitted transform, to measure again and check.
+
+use std::{fmt, fmt::Write, path::Path};
+
+use anyhow::{bail, Context, Result};
+use glam::{Mat3, UVec3, Vec3};
+
+use crate::{
+    lut::{DisplayLut, Lut1d, Lut3d},
+    spectral::{self, MIN_WAVELENGTH},
+    texture::{decode_srgb, encode_srgb_f32},
+};
+
+/// The ColorChecker patches row by row, as X-Rite publishes them in sRGB.
+const PATCHES: [(&str, [u8; 3]); 24] = [
+    ("dark skin", [115, 82, 68]),
+    ("light skin", [194, 150, 130]),
+    ("blue sky", [98, 122, 157]),
+    ("foliage", [87, 108, 67]),
+    ("blue flower", [133, 128, 177]),
+    ("bluish green", [103, 189, 170]),
+    ("orange", [214, 126, 44]),
+    ("purplish blue", [80, 91, 166]),
+    ("moderate red", [193, 90, 99]),
+    ("purple", [94, 60, 108]),
+    ("yellow green", [157, 188, 64]),
+    ("orange yellow", [224, 163, 46]),
+    ("blue", [56, 61, 150]),
+    ("green", [70, 148, 73]),
+    ("red", [175, 54, 60]),
+    ("yellow", [231, 199, 31]),
+    ("magenta", [187, 86, 149]),
+    ("cyan", [8, 133, 161]),
+    ("white", [243, 243, 242]),
+    ("neutral 8", [200, 200, 200]),
+    ("neutral 6.5", [160, 160, 160]),
+    ("neutral 5", [122, 122, 121]),
+    ("neutral 3.5", [85, 85, 85]),
+    ("black", [52, 52, 52]),
+];
+const COLUMNS: u32 = 6;
+/// Side of a patch and the gap around it in pixels.
+const PATCH_SIZE: u32 = 100;
+const GAP: u32 = 20;
+
+/// Smits' spectra for turning RGB reflectances into spectral ones, over
+/// even bins from 380 to 720 nm.
+const SMITS_BIN: f32 = 0.034;
+const SMITS_WHITE: [f32; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f32; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f32; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f32; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f32; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f32; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f32; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// CIE XYZ to linear sRGB.
+const XYZ_TO_SRGB: Mat3 = Mat3::from_cols(
+    Vec3::new(3.2406, -0.9689, 0.0557),
+    Vec3::new(-1.5372, 1.8758, -0.2040),
+    Vec3::new(-0.4986, 0.0415, 1.0570),
+);
+const SHAPER_SIZE: usize = 1024;
+const CUBE_SIZE: u32 = 33;
+
+/// Light the chart is rendered under.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Illuminant {
+    /// Equal energy, which shows the chart as sRGB means it.
+    #[default]
+    Equal,
+    /// CIE A, incandescent light.
+    A,
+    /// Blackbody at a temperature in kelvin.
+    Blackbody(f32),
+}
+
+impl Illuminant {
+    /// Parses `e`, `a` or a temperature such as `5000` or `5000K`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "e" => Some(Self::Equal),
+            "a" => Some(Self::A),
+            name => {
+                let kelvin: f32 = name.strip_suffix('k').unwrap_or(name).parse().ok()?;
+                (spectral::MIN_TEMPERATURE..=spectral::MAX_TEMPERATURE)
+                    .contains(&kelvin)
+                    .then_some(Self::Blackbody(kelvin))
+            }
+        }
+    }
+
+    fn power(self, wavelength: f32) -> f32 {
+        match self {
+            Self::Equal => 1.0,
+            Self::A => spectral::planck(wavelength, 2856.0),
+            Self::Blackbody(kelvin) => spectral::planck(wavelength, kelvin),
+        }
+    }
+}
+
+impl fmt::Display for Illuminant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Equal => write!(f, "E"),
+            Self::A => write!(f, "A"),
+            Self::Blackbody(kelvin) => write!(f, "{kelvin}K"),
+        }
+    }
+}
+
+/// Linear color of each patch under `illuminant`, which lights white with
+/// a luminance of 1.
+pub fn chart(illuminant: Illuminant) -> Vec<(&'static str, Vec3)> {
+    let white = spectral::luminance(spectral::integrate(|wavelength| {
+        illuminant.power(wavelength)
+    }));
+    PATCHES
+        .iter()
+        .map(|&(name, srgb)| {
+            let reflectance = upsample(Vec3::from_array(
+                srgb.map(|c| decode_srgb(c as f32 / 255.0)),
+            ));
+            let bin = |wavelength: f32| {
+                let bin = ((wavelength - MIN_WAVELENGTH) / SMITS_BIN) as usize;
+                reflectance[bin.min(reflectance.len() - 1)]
+            };
+            let color =
+                spectral::integrate(|wavelength| bin(wavelength) * illuminant.power(wavelength));
+            (name, color / white)
+        })
+        .collect()
+}
+
+/// The patches on black, row by row.
+pub fn chart_image(colors: &[(&str, Vec3)]) -> image::Rgba32FImage {
+    let rows = (colors.len() as u32).div_ceil(COLUMNS);
+    let pitch = PATCH_SIZE + GAP;
+    image::Rgba32FImage::from_fn(COLUMNS * pitch + GAP, rows * pitch + GAP, |x, y| {
+        let (column, row) = (x.saturating_sub(GAP) / pitch, y.saturating_sub(GAP) / pitch);
+        let inside = x >= GAP
+            && y >= GAP
+            && (x - GAP) % pitch < PATCH_SIZE
+            && (y - GAP) % pitch < PATCH_SIZE;
+        match colors.get((row * COLUMNS + column) as usize) {
+            Some((_, color)) if inside => image::Rgba([color.x, color.y, color.z, 1.0]),
+            _ => image::Rgba([0.0, 0.0, 0.0, 1.0]),
+        }
+    })
+}
+
+/// Writes the sheet measurements are filled into, with the X, Y and Z
+/// columns left empty.
+pub fn write_sheet(path: &Path, illuminant: Illuminant, colors: &[(&str, Vec3)]) -> Result<()> {
+    let mut text = format!(
+        "# Chart under illuminant {illuminant}. Fill in X, Y and Z measured off each patch.\n\
+         patch,name,r,g,b,X,Y,Z\n"
+    );
+    for (i, (name, color)) in colors.iter().enumerate() {
+        let _ = writeln!(
+            text,
+            "{},{name},{:.6},{:.6},{:.6},,,",
+            i + 1,
+            color.x,
+            color.y,
+            color.z
+        );
+    }
+    std::fs::write(path, text).with_context(|| format!("Failed to save {}", path.display()))
+}
+
+/// Expected linear colors and measured XYZ of the filled in rows of a
+/// sheet.
+pub fn read_sheet(path: &Path) -> Result<Vec<(Vec3, Vec3)>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut pairs = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("patch,") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let location = || format!("{}:{}", path.display(), number + 1);
+        let [_, _, r, g, b, x, y, z] = fields[..] else {
+            bail!("{}: Expected 8 fields", location());
+        };
+        if [x, y, z].iter().all(|field| field.is_empty()) {
+            continue;
+        }
+        let parse = |fields: [&str; 3]| -> Result<Vec3> {
+            let mut numbers = [0.0; 3];
+            for (number, field) in numbers.iter_mut().zip(fields) {
+                *number = field
+                    .parse()
+                    .with_context(|| format!("{}: Invalid number {field}", location()))?;
+            }
+            Ok(Vec3::from_array(numbers))
+        };
+        pairs.push((parse([r, g, b])?, parse([x, y, z])?));
+    }
+    Ok(pairs)
+}
+
+/// How the monitor shows linear sRGB, as a matrix fitted to measurements.
+#[derive(Clone, Copy, Debug)]
+pub struct Fit {
+    /// Takes the color meant to the color shown.
+    pub monitor: Mat3,
+    /// Its inverse, which the display transform applies.
+    pub correction: Mat3,
+    /// Mean distance between the colors meant and shown, before correcting.
+    pub error: f32,
+    /// Mean distance left between the matrix and the measurements.
+    pub residual: f32,
+}
+
+impl Fit {
+    /// Least squares fit of `pairs` of expected linear sRGB and measured
+    /// XYZ. The measurements are scaled so the brightest patch has the
+    /// luminance it should, whatever the units.
+    pub fn new(pairs: &[(Vec3, Vec3)]) -> Result<Self> {
+        if pairs.len() < 6 {
+            bail!(
+                "Only {} patches are measured, at least 6 are needed",
+                pairs.len()
+            );
+        }
+        let measured: Vec<Vec3> = pairs.iter().map(|&(_, xyz)| XYZ_TO_SRGB * xyz).collect();
+        let brightest = (0..pairs.len())
+            .max_by(|&a, &b| {
+                spectral::luminance(pairs[a].0).total_cmp(&spectral::luminance(pairs[b].0))
+            })
+            .unwrap_or_default();
+        let measured_white = spectral::luminance(measured[brightest]);
+        if measured_white <= 0.0 {
+            bail!("The brightest patch measured black");
+        }
+        let scale = spectral::luminance(pairs[brightest].0) / measured_white;
+        let measured: Vec<Vec3> = measured.iter().map(|&color| color * scale).collect();
+
+        // Normal equations of measured = monitor * expected
+        let outer = |a: Vec3, b: Vec3| Mat3::from_cols(a * b.x, a * b.y, a * b.z);
+        let (mut cross, mut gram) = (Mat3::ZERO, Mat3::ZERO);
+        for (&(expected, _), &shown) in pairs.iter().zip(&measured) {
+            cross += outer(shown, expected);
+            gram += outer(expected, expected);
+        }
+        if gram.determinant().abs() < 1e-9 {
+            bail!("The measured patches are too alike to fit");
+        }
+        let monitor = cross * gram.inverse();
+        if monitor.determinant().abs() < 1e-6 {
+            bail!("The measurements don't fit a monitor that can be corrected");
+        }
+        let count = pairs.len() as f32;
+        let error = pairs
+            .iter()
+            .zip(&measured)
+            .map(|(&(expected, _), &shown)| expected.distance(shown))
+            .sum::<f32>()
+            / count;
+        let residual = pairs
+            .iter()
+            .zip(&measured)
+            .map(|(&(expected, _), &shown)| (monitor * expected).distance(shown))
+            .sum::<f32>()
+            / count;
+        Ok(Self {
+            monitor,
+            correction: monitor.inverse(),
+            error,
+            residual,
+        })
+    }
+
+    /// Encodes linear light for display after correcting it, sRGB encoded
+    /// like the window does without a LUT.
+    pub fn display_lut(&self) -> DisplayLut {
+        let shaper = Lut1d {
+            domain_min: Vec3::ZERO,
+            domain_max: Vec3::ONE,
+            values: (0..SHAPER_SIZE)
+                .map(|i| Vec3::splat(encode_srgb_f32(i as f32 / (SHAPER_SIZE - 1) as f32)))
+                .collect(),
+        };
+        let last = (CUBE_SIZE - 1) as f32;
+        let cube = Lut3d {
+            size: CUBE_SIZE,
+            domain_min: Vec3::ZERO,
+            domain_max: Vec3::ONE,
+            values: (0..CUBE_SIZE.pow(3))
+                .map(|i| {
+                    let index = UVec3::new(
+                        i % CUBE_SIZE,
+                        (i / CUBE_SIZE) % CUBE_SIZE,
+                        i / CUBE_SIZE.pow(2),
+                    );
+                    let linear = (index.as_vec3() / last).to_array().map(decode_srgb);
+                    let corrected = self.correction * Vec3::from_array(linear);
+                    Vec3::from_array(corrected.to_array().map(encode_srgb_f32))
+                })
+                .collect(),
+        };
+        DisplayLut { shaper, cube }
+    }
+}
+
+/// Spectral reflectance of a linear RGB one by Smits' method, one value per
+/// bin.
+fn upsample(rgb: Vec3) -> [f32; 10] {
+    let mix = |parts: [(f32, &[f32; 10]); 3]| {
+        std::array::from_fn(|i| parts.iter().map(|(weight, basis)| weight * basis[i]).sum())
+    };
+    let Vec3 { x: r, y: g, z: b } = rgb;
+    if r <= g && r <= b {
+        if g <= b {
+            mix([
+                (r, &SMITS_WHITE),
+                (g - r, &SMITS_CYAN),
+                (b - g, &SMITS_BLUE),
+            ])
+        } else {
+            mix([
+                (r, &SMITS_WHITE),
+                (b - r, &SMITS_CYAN),
+                (g - b, &SMITS_GREEN),
+            ])
+        }
+    } else if g <= r && g <= b {
+        if r <= b {
+            mix([
+                (g, &SMITS_WHITE),
+                (r - g, &SMITS_MAGENTA),
+                (b - r, &SMITS_BLUE),
+            ])
+        } else {
+            mix([
+                (g, &SMITS_WHITE),
+                (b - g, &SMITS_MAGENTA),
+                (r - b, &SMITS_RED),
+            ])
+        }
+    } else if r <= g {
+        mix([
+            (b, &SMITS_WHITE),
+            (r - b, &SMITS_YELLOW),
+            (g - r, &SMITS_GREEN),
+        ])
+    } else {
+        mix([
+            (b, &SMITS_WHITE),
+            (g - b, &SMITS_YELLOW),
+            (r - g, &SMITS_RED),
+        ])
+    }
+}
@@ -0,0 +1,558 @@
+use glam::{Vec2, Vec3};
+use winit::{
+    event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
+    keyboard::PhysicalKey,
+};
+
+use crate::{
+    bvh::Aabb,
+    keys::{Action, Keybindings},
+};
+
+const MAX_PITCH: f32 = 1.55;
+/// Full frame sensor height in meters, used to derive the focal length.
+const SENSOR_HEIGHT: f32 = 0.024;
+const MIN_F_STOP: f32 = 1.0;
+/// Stopping down past this turns the lens into a pinhole.
+const MAX_F_STOP: f32 = 32.0;
+/// Pixel scroll deltas are converted to lines with this many pixels per line.
+const PIXELS_PER_LINE: f32 = 100.0;
+
+/// How primary rays are generated, matched by value in the trace shader.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum CameraProjection {
+    #[default]
+    Perspective = 0,
+    /// Parallel rays, framing the same height as perspective at the focus distance.
+    Orthographic = 1,
+    /// Equidistant circular fisheye with a 180 degree field across the image height.
+    Fisheye = 2,
+    /// Full 360 by 180 degree latitude-longitude panorama.
+    Equirectangular = 3,
+}
+
+impl CameraProjection {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Perspective => Self::Orthographic,
+            Self::Orthographic => Self::Fisheye,
+            Self::Fisheye => Self::Equirectangular,
+            Self::Equirectangular => Self::Perspective,
+        }
+    }
+}
+
+/// What lens distortion does to the image, matched by value in the trace
+/// shader.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum DistortionMode {
+    #[default]
+    Off = 0,
+    /// Renders through the distorted lens, lining up with the raw plate.
+    Apply = 1,
+    /// Renders without distortion and undistorts the backdrop to match.
+    Remove = 2,
+}
+
+impl DistortionMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "off" => Some(Self::Off),
+            "apply" => Some(Self::Apply),
+            "remove" => Some(Self::Remove),
+            _ => None,
+        }
+    }
+}
+
+/// Brown-Conrady distortion of a tracked camera, as solved by match-move
+/// software. Points are on the image plane one focal length in front of
+/// the lens with y down, like OpenCV. Only perspective cameras distort.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LensDistortion {
+    pub mode: DistortionMode,
+    /// Radial coefficients, negative k1 for barrel distortion and positive
+    /// for pincushion.
+    pub k1: f32,
+    pub k2: f32,
+    pub k3: f32,
+    /// Tangential coefficients of a lens not quite parallel to the sensor.
+    pub p1: f32,
+    pub p2: f32,
+}
+
+impl LensDistortion {
+    /// Reads the coefficients `k1,k2,k3,p1,p2`, trailing zeros optional.
+    /// The mode is left off.
+    pub fn parse(coefficients: &str) -> Option<Self> {
+        let values = coefficients
+            .split(',')
+            .map(|value| value.trim().parse().ok())
+            .collect::<Option<Vec<f32>>>()?;
+        if values.len() > 5 {
+            return None;
+        }
+        let get = |i: usize| values.get(i).copied().unwrap_or(0.0);
+        Some(Self {
+            mode: DistortionMode::Off,
+            k1: get(0),
+            k2: get(1),
+            k3: get(2),
+            p1: get(3),
+            p2: get(4),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub position: Vec3,
+    /// Rotation around +Y in radians, zero looks down -Z.
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Vertical field of view in radians.
+    pub fov_y: f32,
+    /// Aperture as an f-number, infinite for a pinhole camera.
+    pub f_stop: f32,
+    /// Distance to the plane in perfect focus.
+    pub focus_distance: f32,
+    pub projection: CameraProjection,
+    pub distortion: LensDistortion,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            position: Vec3::new(0.0, 0.0, 5.0),
+            yaw: 0.0,
+            pitch: 0.0,
+            fov_y: 45f32.to_radians(),
+            f_stop: f32::INFINITY,
+            focus_distance: 5.0,
+            projection: CameraProjection::Perspective,
+            distortion: LensDistortion::default(),
+        }
+    }
+}
+
+impl Camera {
+    /// Focal length in meters of a full frame camera with this field of view.
+    pub fn focal_length(&self) -> f32 {
+        0.5 * SENSOR_HEIGHT / (0.5 * self.fov_y).tan()
+    }
+
+    pub fn aperture_radius(&self) -> f32 {
+        if self.f_stop.is_finite() {
+            0.5 * self.focal_length() / self.f_stop
+        } else {
+            0.0
+        }
+    }
+
+    /// Opens the aperture by one stop per step, or closes it for negative steps.
+    pub fn adjust_f_stop(&mut self, steps: i32) {
+        let f_stop = if self.f_stop.is_finite() {
+            self.f_stop
+        } else {
+            MAX_F_STOP * std::f32::consts::SQRT_2
+        };
+        let f_stop = f_stop * std::f32::consts::SQRT_2.powi(-steps);
+        self.f_stop = if f_stop > MAX_F_STOP * 1.01 {
+            f32::INFINITY
+        } else {
+            f_stop.max(MIN_F_STOP)
+        };
+    }
+
+    /// Backs the camera up along its view direction until the bounding
+    /// sphere of `bounds` fits an image of the given aspect ratio, and
+    /// focuses on its center.
+    pub fn frame(&mut self, bounds: &Aabb, aspect: f32) {
+        if bounds.is_empty() {
+            return;
+        }
+        let radius = (0.5 * bounds.extent().length()).max(1e-3);
+        let tan_half_fov = (0.5 * self.fov_y).tan() * aspect.min(1.0);
+        let distance = radius / tan_half_fov.atan().sin();
+        self.position = bounds.center() - self.forward() * distance;
+        self.focus_distance = distance;
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        Vec3::new(cos_pitch * sin_yaw, sin_pitch, -cos_pitch * cos_yaw)
+    }
+
+    pub fn right(&self) -> Vec3 {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        Vec3::new(cos_yaw, 0.0, sin_yaw)
+    }
+
+    pub fn up(&self) -> Vec3 {
+        self.right().cross(self.forward())
+    }
+
+    pub fn uniform(&self) -> CameraUniform {
+        CameraUniform {
+            position: self.position.into(),
+            tan_half_fov: (0.5 * self.fov_y).tan(),
+            forward: self.forward().into(),
+            aperture_radius: self.aperture_radius(),
+            right: self.right().into(),
+            focus_distance: self.focus_distance,
+            up: self.up().into(),
+            projection: self.projection as u32,
+            distortion_k: [self.distortion.k1, self.distortion.k2, self.distortion.k3],
+            distortion: self.distortion.mode as u32,
+            distortion_p: [self.distortion.p1, self.distortion.p2],
+            _pad: [0.0; 2],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct CameraUniform {
+    pub position: [f32; 3],
+    pub tan_half_fov: f32,
+    pub forward: [f32; 3],
+    pub aperture_radius: f32,
+    pub right: [f32; 3],
+    pub focus_distance: f32,
+    pub up: [f32; 3],
+    pub projection: u32,
+    pub distortion_k: [f32; 3],
+    pub distortion: u32,
+    pub distortion_p: [f32; 2],
+    pub _pad: [f32; 2],
+}
+
+/// First person controller: WASD to move, Q/E for down/up, shift to
+/// go faster and the mouse to look around while captured, unless rebound.
+#[derive(Clone, Debug)]
+pub struct FlyController {
+    /// Movement speed in units per second.
+    pub speed: f32,
+    pub boost: f32,
+    /// Radians per pixel of mouse motion.
+    pub sensitivity: f32,
+    pub captured: bool,
+    forward: bool,
+    backward: bool,
+    left: bool,
+    right: bool,
+    up: bool,
+    down: bool,
+    boosting: bool,
+    look: Vec2,
+}
+
+impl Default for FlyController {
+    fn default() -> Self {
+        Self {
+            speed: 2.0,
+            boost: 5.0,
+            sensitivity: 0.002,
+            captured: false,
+            forward: false,
+            backward: false,
+            left: false,
+            right: false,
+            up: false,
+            down: false,
+            boosting: false,
+            look: Vec2::ZERO,
+        }
+    }
+}
+
+impl FlyController {
+    /// Returns true if the event was consumed.
+    pub fn process_event(&mut self, event: &WindowEvent, keys: &Keybindings) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => {
+                let pressed = *state == ElementState::Pressed;
+                match keys.action(*key) {
+                    Some(Action::Forward) => self.forward = pressed,
+                    Some(Action::Backward) => self.backward = pressed,
+                    Some(Action::Left) => self.left = pressed,
+                    Some(Action::Right) => self.right = pressed,
+                    Some(Action::Up) => self.up = pressed,
+                    Some(Action::Down) => self.down = pressed,
+                    Some(Action::Boost) => self.boosting = pressed,
+                    _ => return false,
+                }
+                true
+            }
+            WindowEvent::Focused(false) => {
+                self.release_keys();
+                false
+            }
+            _ => false,
+        }
+    }
+
+    pub fn process_mouse_motion(&mut self, delta: (f64, f64)) {
+        if self.captured {
+            self.look += Vec2::new(delta.0 as f32, delta.1 as f32);
+        }
+    }
+
+    pub fn release_keys(&mut self) {
+        self.forward = false;
+        self.backward = false;
+        self.left = false;
+        self.right = false;
+        self.up = false;
+        self.down = false;
+        self.boosting = false;
+    }
+
+    /// Applies pending input to the camera, returning true if it moved.
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) -> bool {
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        let movement = camera.forward() * axis(self.forward, self.backward)
+            + camera.right() * axis(self.right, self.left)
+            + Vec3::Y * axis(self.up, self.down);
+
+        let look = std::mem::take(&mut self.look);
+        let mut moved = false;
+        if look != Vec2::ZERO {
+            camera.yaw += look.x * self.sensitivity;
+            camera.pitch = (camera.pitch - look.y * self.sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+            moved = true;
+        }
+        if movement != Vec3::ZERO {
+            let speed = if self.boosting {
+                self.speed * self.boost
+            } else {
+                self.speed
+            };
+            camera.position += movement.normalize() * speed * dt;
+            moved = true;
+        }
+        moved
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CameraMode {
+    #[default]
+    Fly,
+    Orbit,
+}
+
+/// Routes input to the fly or orbit controller depending on the mode.
+/// Lens controls work in both modes: comma and period open and close the
+/// aperture by a stop, semicolon and quote move the focus nearer or further
+/// and P cycles through the projections, unless rebound.
+#[derive(Clone, Debug, Default)]
+pub struct CameraController {
+    pub mode: CameraMode,
+    pub fly: FlyController,
+    pub orbit: OrbitController,
+    f_stop_steps: i32,
+    focus_steps: i32,
+    cycle_projection: bool,
+}
+
+impl CameraController {
+    /// Switches between fly and orbit mode, keeping the current view.
+    pub fn toggle_mode(&mut self, camera: &Camera) {
+        self.mode = match self.mode {
+            CameraMode::Fly => {
+                self.fly.release_keys();
+                self.orbit.attach(camera);
+                CameraMode::Orbit
+            }
+            CameraMode::Orbit => {
+                self.orbit.release_buttons();
+                CameraMode::Fly
+            }
+        };
+        tracing::info!("Camera mode: {:?}", self.mode);
+    }
+
+    /// Frames `bounds` with the camera, see `Camera::frame`, and makes its
+    /// center the point orbited around.
+    pub fn frame(&mut self, camera: &mut Camera, bounds: &Aabb, aspect: f32) {
+        if bounds.is_empty() {
+            return;
+        }
+        camera.frame(bounds, aspect);
+        self.orbit.focus = bounds.center();
+        self.orbit.distance = camera.focus_distance;
+    }
+
+    pub fn process_event(&mut self, event: &WindowEvent, keys: &Keybindings) -> bool {
+        if let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    physical_key: PhysicalKey::Code(key),
+                    state: ElementState::Pressed,
+                    ..
+                },
+            ..
+        } = event
+        {
+            match keys.action(*key) {
+                Some(Action::StopDown) => self.f_stop_steps += 1,
+                Some(Action::OpenUp) => self.f_stop_steps -= 1,
+                Some(Action::FocusNearer) => self.focus_steps -= 1,
+                Some(Action::FocusFarther) => self.focus_steps += 1,
+                Some(Action::Projection) => self.cycle_projection = true,
+                _ => return self.process_mode_event(event, keys),
+            }
+            return true;
+        }
+        self.process_mode_event(event, keys)
+    }
+
+    fn process_mode_event(&mut self, event: &WindowEvent, keys: &Keybindings) -> bool {
+        match self.mode {
+            CameraMode::Fly => self.fly.process_event(event, keys),
+            CameraMode::Orbit => self.orbit.process_event(event),
+        }
+    }
+
+    pub fn process_mouse_motion(&mut self, delta: (f64, f64)) {
+        match self.mode {
+            CameraMode::Fly => self.fly.process_mouse_motion(delta),
+            CameraMode::Orbit => self.orbit.process_mouse_motion(delta),
+        }
+    }
+
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) -> bool {
+        let lens_changed = self.f_stop_steps != 0 || self.focus_steps != 0;
+        if lens_changed {
+            camera.adjust_f_stop(std::mem::take(&mut self.f_stop_steps));
+            // Ten steps per doubling of the focus distance
+            let focus_steps = std::mem::take(&mut self.focus_steps);
+            camera.focus_distance *= 2f32.powf(focus_steps as f32 / 10.0);
+            tracing::info!(
+                "f/{:.1}, focus distance {:.2}",
+                camera.f_stop,
+                camera.focus_distance
+            );
+        }
+
+        let projection_changed = std::mem::take(&mut self.cycle_projection);
+        if projection_changed {
+            camera.projection = camera.projection.next();
+            tracing::info!("Camera projection: {:?}", camera.projection);
+        }
+
+        let moved = match self.mode {
+            CameraMode::Fly => self.fly.update(camera, dt),
+            CameraMode::Orbit => self.orbit.update(camera),
+        };
+        moved || lens_changed || projection_changed
+    }
+}
+
+/// Turntable controller: left drag rotates around the focus point, middle
+/// drag pans it and the scroll wheel zooms.
+#[derive(Clone, Debug)]
+pub struct OrbitController {
+    pub focus: Vec3,
+    pub distance: f32,
+    /// Radians per pixel of mouse motion.
+    pub sensitivity: f32,
+    /// Fraction of the distance covered by one scroll line.
+    pub zoom_speed: f32,
+    rotating: bool,
+    panning: bool,
+    drag: Vec2,
+    scroll: f32,
+}
+
+impl Default for OrbitController {
+    fn default() -> Self {
+        Self {
+            focus: Vec3::ZERO,
+            distance: 5.0,
+            sensitivity: 0.005,
+            zoom_speed: 0.1,
+            rotating: false,
+            panning: false,
+            drag: Vec2::ZERO,
+            scroll: 0.0,
+        }
+    }
+}
+
+impl OrbitController {
+    /// Puts the focus point `distance` units in front of the camera.
+    pub fn attach(&mut self, camera: &Camera) {
+        self.focus = camera.position + camera.forward() * self.distance;
+    }
+
+    pub fn release_buttons(&mut self) {
+        self.rotating = false;
+        self.panning = false;
+    }
+
+    pub fn process_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::MouseInput { state, button, .. } => {
+                let pressed = *state == ElementState::Pressed;
+                match button {
+                    MouseButton::Left => self.rotating = pressed,
+                    MouseButton::Middle => self.panning = pressed,
+                    _ => return false,
+                }
+                true
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+                };
+                true
+            }
+            WindowEvent::Focused(false) => {
+                self.release_buttons();
+                false
+            }
+            _ => false,
+        }
+    }
+
+    pub fn process_mouse_motion(&mut self, delta: (f64, f64)) {
+        if self.rotating || self.panning {
+            self.drag += Vec2::new(delta.0 as f32, delta.1 as f32);
+        }
+    }
+
+    /// Applies pending input to the camera, returning true if it moved.
+    pub fn update(&mut self, camera: &mut Camera) -> bool {
+        let drag = std::mem::take(&mut self.drag);
+        let scroll = std::mem::take(&mut self.scroll);
+        if drag == Vec2::ZERO && scroll == 0.0 {
+            return false;
+        }
+
+        if self.rotating {
+            camera.yaw += drag.x * self.sensitivity;
+            camera.pitch = (camera.pitch - drag.y * self.sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+        } else if self.panning {
+            // Keep the point under the cursor roughly fixed
+            let scale = self.distance * self.sensitivity * (0.5 * camera.fov_y).tan();
+            self.focus += (camera.up() * drag.y - camera.right() * drag.x) * scale;
+        }
+        self.distance = (self.distance * (1.0 - self.zoom_speed).powf(scroll)).max(1e-3);
+        camera.position = self.focus - camera.forward() * self.distance;
+        true
+    }
+}
@@ -0,0 +1,107 @@
+//! Recording of the interactive camera's motion as a camera path, for demos
+//! or as the camera of an animation. A path is a timeline with only camera
+//! tracks, saved in the same format, so it plays back with `--timeline` or
+//! from the timeline panel.
+
+use std::path::PathBuf;
+
+use crate::{
+    camera::Camera,
+    control::ControlTarget,
+    timeline::{Easing, Timeline},
+};
+
+/// Seconds between samples of the camera.
+const SAMPLE_INTERVAL: f32 = 0.1;
+
+const TARGETS: [ControlTarget; 7] = [
+    ControlTarget::CameraPosition(0),
+    ControlTarget::CameraPosition(1),
+    ControlTarget::CameraPosition(2),
+    ControlTarget::CameraYaw,
+    ControlTarget::CameraPitch,
+    ControlTarget::CameraFov,
+    ControlTarget::CameraFocusDistance,
+];
+
+#[derive(Clone, Debug, Default)]
+pub struct PathRecorder {
+    /// Seconds of motion each key is averaged over, to steady a path flown
+    /// by hand.
+    pub smoothing: f32,
+    /// Where recorded paths are saved, a timestamped file in the working
+    /// directory if none.
+    pub path: Option<PathBuf>,
+    /// Camera values in the order of `TARGETS`, while recording.
+    samples: Option<Vec<[f32; 7]>>,
+    /// Seconds since the last sample.
+    elapsed: f32,
+}
+
+impl PathRecorder {
+    pub fn new(smoothing: f32, path: Option<PathBuf>) -> Self {
+        Self {
+            smoothing,
+            path,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.samples.is_some()
+    }
+
+    pub fn start(&mut self, camera: &Camera) {
+        self.samples = Some(vec![values(camera)]);
+        self.elapsed = 0.0;
+    }
+
+    /// Samples the camera as `dt` seconds pass.
+    pub fn update(&mut self, camera: &Camera, dt: f32) {
+        let Some(samples) = &mut self.samples else {
+            return;
+        };
+        self.elapsed += dt;
+        while self.elapsed >= SAMPLE_INTERVAL {
+            self.elapsed -= SAMPLE_INTERVAL;
+            let mut sample = values(camera);
+            // Keep turning the same way instead of back across the seam
+            let yaw = samples.last().map_or(sample[3], |last| last[3]);
+            sample[3] = yaw + (sample[3] - yaw + 180.0).rem_euclid(360.0) - 180.0;
+            samples.push(sample);
+        }
+    }
+
+    /// Stops recording and returns the path, none if it wasn't recording.
+    pub fn stop(&mut self) -> Option<Timeline> {
+        let samples = self.samples.take()?;
+        let radius = (self.smoothing / SAMPLE_INTERVAL).round() as usize;
+        let mut timeline = Timeline::default();
+        timeline.duration = 0.0;
+        timeline.looping = false;
+        for (i, target) in TARGETS.into_iter().enumerate() {
+            for j in 0..samples.len() {
+                // Centered moving average, narrowing towards the ends so
+                // the path still starts and stops where the camera did
+                let radius = radius.min(j).min(samples.len() - 1 - j);
+                let window = &samples[j - radius..=j + radius];
+                let value =
+                    window.iter().map(|sample| sample[i]).sum::<f32>() / window.len() as f32;
+                timeline.insert(target, j as f32 * SAMPLE_INTERVAL, value, Easing::Linear);
+            }
+        }
+        Some(timeline)
+    }
+}
+
+fn values(camera: &Camera) -> [f32; 7] {
+    [
+        camera.position.x,
+        camera.position.y,
+        camera.position.z,
+        camera.yaw.to_degrees(),
+        camera.pitch.to_degrees(),
+        camera.fov_y.to_degrees(),
+        camera.focus_distance,
+    ]
+}
@@ -0,0 +1,736 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use glam::Vec3;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::avif::HdrEncoding;
+use crate::{
+    aov::Aov,
+    autofocus::AutofocusMode,
+    calibration::Illuminant,
+    camera::{DistortionMode, LensDistortion},
+    import::{self, ImportOptions, UpAxis},
+    output::{bracket, exr::Precision},
+    preset::Preset,
+    sampler::SamplerKind,
+    tile::{self, Tile},
+    tonemap::Tonemap,
+    tracer::Backend,
+    turntable::Turntable,
+    FullscreenMode, PresentMode,
+};
+
+/// Size of a render or the window when only one side, or neither, is given.
+pub const DEFAULT_RESOLUTION: (u32, u32) = (1280, 720);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Command {
+    /// Opens the scene in a window. With `--output`, saves the image once
+    /// it has `--spp` samples and exits.
+    #[default]
+    View,
+    /// `spectrum thumbnail scene.gltf --size 256 -o thumb.png` renders a
+    /// quick preview without a window.
+    Thumbnail,
+    /// `spectrum render scene.gltf --resolution 1920x1080 -o frame.exr`
+    /// renders the scene as the window first shows it, without a window.
+    Render,
+    /// `spectrum contact-sheet scene.gltf --views 8 -o sheet.png` renders
+    /// turntable stops around the scene into one image.
+    ContactSheet,
+    /// `spectrum dataset scene.gltf --count 100 -o dataset` renders random
+    /// views with depth, normals and segmentation for training models.
+    Dataset,
+    /// `spectrum export-job scene.gltf --resolution 1920x1080 -o frame.exr`
+    /// describes the render for a farm manager instead of rendering it.
+    ExportJob,
+    /// `spectrum calibrate --illuminant a -o chart.png` renders a chart to
+    /// measure a monitor with, and `spectrum calibrate --measurements
+    /// chart.csv -o display.cube` fits a display transform to the
+    /// measurements, see `calibration`.
+    Calibrate,
+    /// `spectrum samplers` prints how fast each sampler converges.
+    Samplers,
+    /// `spectrum colorspaces --ocio config.ocio` prints the displays,
+    /// views, looks and color spaces of an OCIO config.
+    Colorspaces,
+    /// `spectrum benchmark` prints how fast the GPU traces a few built in
+    /// scenes, or the scene given, see `benchmark`.
+    Benchmark,
+}
+
+#[derive(Clone, Debug)]
+pub struct Args {
+    pub command: Command,
+    pub scene: Option<PathBuf>,
+    /// Read in place of `spectrum.toml`, see `config`.
+    pub config: Option<PathBuf>,
+    /// Coordinate system of the scene file when it isn't the format's usual
+    /// one.
+    pub import: ImportOptions,
+    /// More files merged into the scene, read with the same options.
+    pub add: Vec<PathBuf>,
+    /// Lat-long HDR environment map used for lighting and background.
+    pub environment: Option<PathBuf>,
+    /// Photographic plate shown behind the scene, without lighting it.
+    pub backdrop: Option<PathBuf>,
+    /// .cube display transform, such as one baked from an OCIO config,
+    /// replacing plain sRGB in the window and thumbnails.
+    pub display_lut: Option<PathBuf>,
+    /// OCIO config the display transform is baked from instead, see
+    /// `ocio`.
+    pub ocio: Option<PathBuf>,
+    /// Display and view of the config shown, its defaults without them.
+    pub display: Option<String>,
+    pub view: Option<String>,
+    /// Color space of the config the renderer's linear Rec.709 output is
+    /// in, `scene_linear` by default.
+    pub working_colorspace: Option<String>,
+    /// Color space of the config saved .exr images and frames are converted
+    /// into.
+    pub output_colorspace: Option<String>,
+    /// Raw 16-bit mono PCM driving the scene's audio bindings, `-` for
+    /// standard input.
+    pub audio: Option<PathBuf>,
+    /// Sample rate of the audio input in Hz.
+    pub audio_rate: u32,
+    /// UDP port to receive OSC control messages on.
+    pub osc_port: Option<u16>,
+    /// Raw MIDI device to receive control changes from.
+    pub midi: Option<PathBuf>,
+    /// Maps control surface messages onto parameters.
+    pub control_map: Option<PathBuf>,
+    /// Keyframed parameters, see `timeline`.
+    pub timeline: Option<PathBuf>,
+    /// Where camera paths recorded in the window are saved, see
+    /// `camera_path`.
+    pub camera_path: Option<PathBuf>,
+    /// Seconds recorded camera paths are smoothed over.
+    pub path_smoothing: f32,
+    /// Renders the timeline into numbered EXR frames in this directory and
+    /// exits.
+    pub animation: Option<PathBuf>,
+    /// Frames per second of a rendered animation.
+    pub fps: f32,
+    /// Samples per pixel of every rendered frame or thumbnail, and where
+    /// the window stops accumulating.
+    pub samples: Option<u32>,
+    /// Splits GPU frames into submissions of at most this many rays, see
+    /// `TraceSettings::rays_per_submit`.
+    pub rays_per_submit: Option<u32>,
+    /// Width and height of a thumbnail, or of each view of a contact sheet.
+    pub size: u32,
+    /// Width of a render or the window.
+    pub width: Option<u32>,
+    /// Height of a render or the window.
+    pub height: Option<u32>,
+    /// Turntable stops on a contact sheet.
+    pub views: u32,
+    /// Samples of a dataset.
+    pub count: u32,
+    /// Seed of the first sample of a dataset.
+    pub seed: u64,
+    /// What a dataset randomizes, see `dataset`.
+    pub randomization: Option<PathBuf>,
+    /// Image file of a thumbnail or render, next to the scene by default.
+    /// An .avif or .exr one keeps the light above white, see `hdr` and
+    /// `exr`.
+    pub output: Option<PathBuf>,
+    /// Stops from the exposure also saved tonemapped with the output or
+    /// recorded frames, see `output::bracket`.
+    pub bracket: Vec<f32>,
+    /// JPEG rewritten every `preview_interval` during a render, see
+    /// `output::preview`.
+    pub preview: Option<PathBuf>,
+    pub preview_interval: Duration,
+    /// `http://` URL render previews are POSTed to.
+    pub webhook: Option<String>,
+    /// Light the calibration chart is rendered under.
+    pub illuminant: Illuminant,
+    /// Sheet of calibration measurements to fit a display transform to.
+    pub measurements: Option<PathBuf>,
+    /// Where `export-job` writes the job, standard output by default.
+    pub job: Option<PathBuf>,
+    /// The only part of a render traced, see `tile`.
+    pub tile: Option<Tile>,
+    /// Splits an exported job into tiles of this size.
+    pub tile_size: Option<u32>,
+    /// Fraction of a job's tiles rendered twice to check the workers.
+    pub verify_rate: f32,
+    /// Shell command run when a render or dataset is done, see
+    /// `notification`.
+    pub on_done: Option<String>,
+    /// `http://` URL the stats of a finished render are POSTed to.
+    pub done_webhook: Option<String>,
+    /// Shows a desktop notification when a render or dataset is done.
+    pub notify: bool,
+    /// How an AVIF thumbnail stores HDR.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub hdr: HdrEncoding,
+    /// Bits per channel of EXR thumbnails and animation frames.
+    pub exr: Precision,
+    /// Add an animated ocean surface to the scene.
+    pub ocean: bool,
+    /// Print scene statistics and exit without opening a window.
+    pub stats: bool,
+    /// Render settings to start with.
+    pub preset: Option<Preset>,
+    /// Sequence paths draw their random numbers from.
+    pub sampler: Option<SamplerKind>,
+    /// Replaces the default tone mapping of the window and saved images.
+    pub tonemap: Option<Tonemap>,
+    /// Starts the window metering its exposure, see `exposure`.
+    pub auto_exposure: bool,
+    /// Keeps the window's camera focused, see `autofocus`.
+    pub autofocus: AutofocusMode,
+    /// Starts the turntable at this many degrees per second, see
+    /// `turntable`.
+    pub turntable: Option<f32>,
+    /// Point the turntable turns around.
+    pub turntable_pivot: Option<Vec3>,
+    /// Opens the window fullscreen, and how F11 makes it so.
+    pub fullscreen: Option<FullscreenMode>,
+    /// How the window presents frames, vsync if the surface can't.
+    pub present_mode: Option<PresentMode>,
+    /// How long the GPU may take over a frame of the window before it's
+    /// traced at lighter settings, zero never, see `watchdog`.
+    pub watchdog: Option<Duration>,
+    /// Lens distortion of the tracked camera, applied unless told to remove
+    /// it from the backdrop instead.
+    pub distortion: Option<LensDistortion>,
+    /// Leaves the background out with zero alpha, showing what's behind a
+    /// transparent window or thumbnail.
+    pub transparent: bool,
+    /// Shown in the viewport, or saved by a render, in place of the image.
+    pub aov: Option<Aov>,
+    /// Adds the AOVs to rendered animation frames.
+    pub aovs: bool,
+    /// Where paths are traced, on the CPU by default when the only adapter
+    /// is a software one and on the GPU otherwise.
+    pub backend: Option<Backend>,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            command: Command::View,
+            scene: None,
+            config: None,
+            import: ImportOptions::default(),
+            add: Vec::new(),
+            environment: None,
+            backdrop: None,
+            display_lut: None,
+            ocio: None,
+            display: None,
+            view: None,
+            working_colorspace: None,
+            output_colorspace: None,
+            audio: None,
+            audio_rate: 44100,
+            osc_port: None,
+            midi: None,
+            control_map: None,
+            timeline: None,
+            camera_path: None,
+            path_smoothing: 0.0,
+            animation: None,
+            fps: 24.0,
+            samples: None,
+            rays_per_submit: None,
+            size: 256,
+            width: None,
+            height: None,
+            views: 8,
+            count: 100,
+            seed: 0,
+            randomization: None,
+            output: None,
+            bracket: Vec::new(),
+            preview: None,
+            preview_interval: Duration::from_secs(10),
+            webhook: None,
+            illuminant: Illuminant::default(),
+            measurements: None,
+            job: None,
+            tile: None,
+            tile_size: None,
+            verify_rate: tile::DEFAULT_VERIFY_RATE,
+            on_done: None,
+            done_webhook: None,
+            notify: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            hdr: HdrEncoding::default(),
+            exr: Precision::default(),
+            ocean: false,
+            stats: false,
+            preset: None,
+            sampler: None,
+            tonemap: None,
+            auto_exposure: false,
+            autofocus: AutofocusMode::Off,
+            turntable: None,
+            turntable_pivot: None,
+            fullscreen: None,
+            present_mode: None,
+            watchdog: None,
+            distortion: None,
+            transparent: false,
+            aov: None,
+            aovs: false,
+            backend: None,
+        }
+    }
+}
+
+impl Args {
+    pub fn from_env() -> Result<Self> {
+        let mut args = Self::default();
+        let mut iter = std::env::args().skip(1).peekable();
+        let mut distortion_mode = None;
+        if iter.next_if(|arg| arg == "thumbnail").is_some() {
+            args.command = Command::Thumbnail;
+        } else if iter.next_if(|arg| arg == "render").is_some() {
+            args.command = Command::Render;
+        } else if iter.next_if(|arg| arg == "contact-sheet").is_some() {
+            args.command = Command::ContactSheet;
+        } else if iter.next_if(|arg| arg == "dataset").is_some() {
+            args.command = Command::Dataset;
+        } else if iter.next_if(|arg| arg == "export-job").is_some() {
+            args.command = Command::ExportJob;
+        } else if iter.next_if(|arg| arg == "calibrate").is_some() {
+            args.command = Command::Calibrate;
+        } else if iter.next_if(|arg| arg == "samplers").is_some() {
+            args.command = Command::Samplers;
+        } else if iter.next_if(|arg| arg == "colorspaces").is_some() {
+            args.command = Command::Colorspaces;
+        } else if iter.next_if(|arg| arg == "benchmark").is_some() {
+            args.command = Command::Benchmark;
+        }
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--stats" => args.stats = true,
+                "--ocean" => args.ocean = true,
+                "--transparent" => args.transparent = true,
+                "--cpu" => args.backend = Some(Backend::Cpu),
+                "--aovs" => args.aovs = true,
+                "--notify" => args.notify = true,
+                "--up-axis" => {
+                    let axis = iter.next().context("--up-axis requires y or z")?;
+                    args.import.up_axis = Some(match axis.to_ascii_lowercase().as_str() {
+                        "y" => UpAxis::Y,
+                        "z" => UpAxis::Z,
+                        _ => bail!("Invalid up axis: {axis}"),
+                    });
+                }
+                "--units" => {
+                    let unit = iter.next().context("--units requires a unit such as mm")?;
+                    args.import.meters_per_unit = Some(
+                        import::parse_unit(&unit)
+                            .with_context(|| format!("Invalid unit: {unit}"))?,
+                    );
+                }
+                "--tolerance" => {
+                    let length = iter
+                        .next()
+                        .context("--tolerance requires a length such as 0.1mm")?;
+                    args.import.tolerance = Some(
+                        import::parse_length(&length)
+                            .with_context(|| format!("Invalid tolerance: {length}"))?,
+                    );
+                }
+                "--left-handed" => args.import.left_handed = Some(true),
+                "--right-handed" => args.import.left_handed = Some(false),
+                "--add" => {
+                    let path = iter.next().context("--add requires a path")?;
+                    args.add.push(PathBuf::from(path));
+                }
+                "--environment" => {
+                    let path = iter.next().context("--environment requires a path")?;
+                    args.environment = Some(PathBuf::from(path));
+                }
+                "--backdrop" => {
+                    let path = iter.next().context("--backdrop requires a path")?;
+                    args.backdrop = Some(PathBuf::from(path));
+                }
+                "--display-lut" => {
+                    let path = iter.next().context("--display-lut requires a path")?;
+                    args.display_lut = Some(PathBuf::from(path));
+                }
+                "--ocio" => {
+                    let path = iter.next().context("--ocio requires a path")?;
+                    args.ocio = Some(PathBuf::from(path));
+                }
+                "--display" => {
+                    args.display = Some(iter.next().context("--display requires a name")?);
+                }
+                "--view" => args.view = Some(iter.next().context("--view requires a name")?),
+                "--working-colorspace" => {
+                    let name = iter
+                        .next()
+                        .context("--working-colorspace requires a name")?;
+                    args.working_colorspace = Some(name);
+                }
+                "--output-colorspace" => {
+                    let name = iter.next().context("--output-colorspace requires a name")?;
+                    args.output_colorspace = Some(name);
+                }
+                "--audio" => {
+                    let path = iter.next().context("--audio requires a path or -")?;
+                    args.audio = Some(PathBuf::from(path));
+                }
+                "--audio-rate" => {
+                    let rate = iter.next().context("--audio-rate requires a value")?;
+                    args.audio_rate = rate
+                        .parse()
+                        .with_context(|| format!("Invalid sample rate: {rate}"))?;
+                }
+                "--osc" => {
+                    let port = iter.next().context("--osc requires a port")?;
+                    args.osc_port = Some(
+                        port.parse()
+                            .with_context(|| format!("Invalid port: {port}"))?,
+                    );
+                }
+                "--midi" => {
+                    let path = iter.next().context("--midi requires a device path")?;
+                    args.midi = Some(PathBuf::from(path));
+                }
+                "--control-map" => {
+                    let path = iter.next().context("--control-map requires a path")?;
+                    args.control_map = Some(PathBuf::from(path));
+                }
+                "--timeline" => {
+                    let path = iter.next().context("--timeline requires a path")?;
+                    args.timeline = Some(PathBuf::from(path));
+                }
+                "--camera-path" => {
+                    let path = iter.next().context("--camera-path requires a path")?;
+                    args.camera_path = Some(PathBuf::from(path));
+                }
+                "--path-smoothing" => {
+                    let seconds = iter.next().context("--path-smoothing requires seconds")?;
+                    args.path_smoothing = seconds
+                        .parse()
+                        .ok()
+                        .filter(|&seconds: &f32| seconds >= 0.0)
+                        .with_context(|| format!("Invalid path smoothing: {seconds}"))?;
+                }
+                "--animation" => {
+                    let path = iter.next().context("--animation requires a directory")?;
+                    args.animation = Some(PathBuf::from(path));
+                }
+                "--fps" => {
+                    let fps = iter.next().context("--fps requires a value")?;
+                    args.fps = fps
+                        .parse()
+                        .ok()
+                        .filter(|&fps: &f32| fps > 0.0)
+                        .with_context(|| format!("Invalid frame rate: {fps}"))?;
+                }
+                "--rays-per-submit" => {
+                    let rays = iter.next().context("--rays-per-submit requires a count")?;
+                    args.rays_per_submit = Some(
+                        rays.parse()
+                            .with_context(|| format!("Invalid ray count: {rays}"))?,
+                    );
+                }
+                "--samples" | "--spp" => {
+                    let samples = iter.next().context("--samples requires a value")?;
+                    args.samples = Some(
+                        samples
+                            .parse()
+                            .ok()
+                            .filter(|&samples| samples > 0)
+                            .with_context(|| format!("Invalid sample count: {samples}"))?,
+                    );
+                }
+                "--size" => {
+                    let size = iter.next().context("--size requires a value")?;
+                    args.size = size
+                        .parse()
+                        .ok()
+                        .filter(|&size| size > 0)
+                        .with_context(|| format!("Invalid size: {size}"))?;
+                }
+                "--resolution" => {
+                    let resolution = iter.next().context("--resolution requires WIDTHxHEIGHT")?;
+                    let (width, height) = resolution
+                        .split_once('x')
+                        .and_then(|(width, height)| {
+                            Some((width.parse().ok()?, height.parse().ok()?))
+                        })
+                        .filter(|&(width, height)| width > 0 && height > 0)
+                        .with_context(|| format!("Invalid resolution: {resolution}"))?;
+                    args.width = Some(width);
+                    args.height = Some(height);
+                }
+                "--width" => {
+                    let width = iter.next().context("--width requires a value")?;
+                    args.width = Some(
+                        width
+                            .parse()
+                            .ok()
+                            .filter(|&width| width > 0)
+                            .with_context(|| format!("Invalid width: {width}"))?,
+                    );
+                }
+                "--height" => {
+                    let height = iter.next().context("--height requires a value")?;
+                    args.height = Some(
+                        height
+                            .parse()
+                            .ok()
+                            .filter(|&height| height > 0)
+                            .with_context(|| format!("Invalid height: {height}"))?,
+                    );
+                }
+                "--views" => {
+                    let views = iter.next().context("--views requires a value")?;
+                    args.views = views
+                        .parse()
+                        .ok()
+                        .filter(|&views| views > 0)
+                        .with_context(|| format!("Invalid view count: {views}"))?;
+                }
+                "--aov" => {
+                    let name = iter.next().context("--aov requires an AOV name")?;
+                    args.aov =
+                        Some(Aov::parse(&name).with_context(|| format!("Unknown AOV: {name}"))?);
+                }
+                "--count" => {
+                    let count = iter.next().context("--count requires a value")?;
+                    args.count = count
+                        .parse()
+                        .ok()
+                        .filter(|&count| count > 0)
+                        .with_context(|| format!("Invalid sample count: {count}"))?;
+                }
+                "--seed" => {
+                    let seed = iter.next().context("--seed requires a value")?;
+                    args.seed = seed
+                        .parse()
+                        .with_context(|| format!("Invalid seed: {seed}"))?;
+                }
+                "--randomize" => {
+                    let path = iter.next().context("--randomize requires a spec file")?;
+                    args.randomization = Some(PathBuf::from(path));
+                }
+                "--backend" => {
+                    let name = iter.next().context("--backend requires gpu or cpu")?;
+                    args.backend = Some(
+                        Backend::parse(&name)
+                            .with_context(|| format!("Unknown backend: {name}"))?,
+                    );
+                }
+                "--preset" => {
+                    let name = iter
+                        .next()
+                        .context("--preset requires draft, medium or final")?;
+                    args.preset = Some(
+                        Preset::parse(&name).with_context(|| format!("Unknown preset: {name}"))?,
+                    );
+                }
+                "--sampler" => {
+                    let name = iter.next().context("--sampler requires a sampler name")?;
+                    args.sampler = Some(
+                        SamplerKind::parse(&name)
+                            .with_context(|| format!("Unknown sampler: {name}"))?,
+                    );
+                }
+                "--tonemap" => {
+                    let name = iter.next().context("--tonemap requires an operator")?;
+                    args.tonemap = Some(
+                        Tonemap::parse(&name)
+                            .with_context(|| format!("Unknown tone mapping: {name}"))?,
+                    );
+                }
+                "--auto-exposure" => args.auto_exposure = true,
+                "--autofocus" => {
+                    let name = iter.next().context("--autofocus requires a mode")?;
+                    args.autofocus = AutofocusMode::parse(&name)
+                        .with_context(|| format!("Unknown autofocus mode: {name}"))?;
+                }
+                "--turntable" => {
+                    let speed = iter
+                        .next()
+                        .context("--turntable requires degrees per second")?;
+                    args.turntable = Some(
+                        speed
+                            .parse()
+                            .ok()
+                            .filter(|speed: &f32| speed.is_finite())
+                            .with_context(|| format!("Invalid turntable speed: {speed}"))?,
+                    );
+                }
+                "--turntable-pivot" => {
+                    let pivot = iter.next().context("--turntable-pivot requires x,y,z")?;
+                    args.turntable_pivot = Some(
+                        Turntable::parse_pivot(&pivot)
+                            .with_context(|| format!("Invalid turntable pivot: {pivot}"))?,
+                    );
+                }
+                "--fullscreen" => {
+                    let mode = iter
+                        .next()
+                        .context("--fullscreen requires borderless or exclusive")?;
+                    args.fullscreen = Some(
+                        FullscreenMode::parse(&mode)
+                            .with_context(|| format!("Unknown fullscreen mode: {mode}"))?,
+                    );
+                }
+                "--present-mode" => {
+                    let mode = iter
+                        .next()
+                        .context("--present-mode requires fifo, mailbox or immediate")?;
+                    args.present_mode = Some(
+                        PresentMode::parse(&mode)
+                            .with_context(|| format!("Unknown present mode: {mode}"))?,
+                    );
+                }
+                "--watchdog" => {
+                    let seconds = iter.next().context("--watchdog requires seconds")?;
+                    args.watchdog = Some(
+                        seconds
+                            .parse()
+                            .ok()
+                            .filter(|&seconds: &f32| seconds >= 0.0)
+                            .map(Duration::from_secs_f32)
+                            .with_context(|| format!("Invalid watchdog timeout: {seconds}"))?,
+                    );
+                }
+                "--distortion" => {
+                    let coefficients = iter
+                        .next()
+                        .context("--distortion requires k1,k2,k3,p1,p2")?;
+                    args.distortion =
+                        Some(LensDistortion::parse(&coefficients).with_context(|| {
+                            format!("Invalid distortion coefficients: {coefficients}")
+                        })?);
+                }
+                "--distortion-mode" => {
+                    let mode = iter
+                        .next()
+                        .context("--distortion-mode requires off, apply or remove")?;
+                    distortion_mode = Some(
+                        DistortionMode::parse(&mode)
+                            .with_context(|| format!("Invalid distortion mode: {mode}"))?,
+                    );
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                "--hdr" => {
+                    let name = iter.next().context("--hdr requires pq, hlg or gain-map")?;
+                    args.hdr = HdrEncoding::parse(&name)
+                        .with_context(|| format!("Unknown HDR encoding: {name}"))?;
+                }
+                "--exr" => {
+                    let name = iter.next().context("--exr requires half or float")?;
+                    args.exr = Precision::parse(&name)
+                        .with_context(|| format!("Unknown EXR precision: {name}"))?;
+                }
+                "--config" => {
+                    let path = iter.next().context("--config requires a path")?;
+                    args.config = Some(PathBuf::from(path));
+                }
+                "--scene" => {
+                    let path = iter.next().context("--scene requires a path")?;
+                    args.scene = Some(PathBuf::from(path));
+                }
+                "--preview" => {
+                    let path = iter.next().context("--preview requires a path")?;
+                    args.preview = Some(PathBuf::from(path));
+                }
+                "--preview-interval" => {
+                    let seconds = iter.next().context("--preview-interval requires seconds")?;
+                    args.preview_interval = seconds
+                        .parse()
+                        .ok()
+                        .filter(|&seconds: &f32| seconds > 0.0)
+                        .map(Duration::from_secs_f32)
+                        .with_context(|| format!("Invalid preview interval: {seconds}"))?;
+                }
+                "--webhook" => {
+                    let url = iter.next().context("--webhook requires a URL")?;
+                    if !url.starts_with("http://") {
+                        bail!("Invalid webhook URL: {url}");
+                    }
+                    args.webhook = Some(url);
+                }
+                "--illuminant" => {
+                    let name = iter
+                        .next()
+                        .context("--illuminant requires e, a or a temperature")?;
+                    args.illuminant = Illuminant::parse(&name)
+                        .with_context(|| format!("Unknown illuminant: {name}"))?;
+                }
+                "--measurements" => {
+                    let path = iter.next().context("--measurements requires a path")?;
+                    args.measurements = Some(PathBuf::from(path));
+                }
+                "--job" => {
+                    let path = iter.next().context("--job requires a path")?;
+                    args.job = Some(PathBuf::from(path));
+                }
+                "--bracket" => {
+                    let stops = iter.next().context("--bracket requires stops")?;
+                    args.bracket = bracket::parse(&stops)
+                        .with_context(|| format!("Invalid bracket: {stops}"))?;
+                }
+                "--tile" => {
+                    let tile = iter.next().context("--tile requires x,y,width,height")?;
+                    args.tile =
+                        Some(Tile::parse(&tile).with_context(|| format!("Invalid tile: {tile}"))?);
+                }
+                "--tile-size" => {
+                    let size = iter.next().context("--tile-size requires a size")?;
+                    args.tile_size = Some(
+                        size.parse()
+                            .ok()
+                            .filter(|&size| size > 0)
+                            .with_context(|| format!("Invalid tile size: {size}"))?,
+                    );
+                }
+                "--verify-rate" => {
+                    let rate = iter.next().context("--verify-rate requires a fraction")?;
+                    args.verify_rate = rate
+                        .parse()
+                        .ok()
+                        .filter(|rate| (0.0..=1.0).contains(rate))
+                        .with_context(|| format!("Invalid verify rate: {rate}"))?;
+                }
+                "--on-done" => {
+                    let command = iter.next().context("--on-done requires a command")?;
+                    args.on_done = Some(command);
+                }
+                "--done-webhook" => {
+                    let url = iter.next().context("--done-webhook requires a URL")?;
+                    if !url.starts_with("http://") {
+                        bail!("Invalid webhook URL: {url}");
+                    }
+                    args.done_webhook = Some(url);
+                }
+                "-o" | "--output" => {
+                    let path = iter.next().context("--output requires a path")?;
+                    args.output = Some(PathBuf::from(path));
+                }
+                flag if flag.starts_with('-') => bail!("Unknown argument: {flag}"),
+                path => args.scene = Some(PathBuf::from(path)),
+            }
+        }
+        if let Some(distortion) = &mut args.distortion {
+            distortion.mode = distortion_mode.unwrap_or(DistortionMode::Apply);
+        }
+        Ok(args)
+    }
+
+    /// Width and height given on the command line, filling in a missing
+    /// one from `DEFAULT_RESOLUTION`.
+    pub fn resolution(&self) -> Option<(u32, u32)> {
+        if self.width.is_none() && self.height.is_none() {
+            return None;
+        }
+        let (width, height) = DEFAULT_RESOLUTION;
+        Some((self.width.unwrap_or(width), self.height.unwrap_or(height)))
+    }
+}
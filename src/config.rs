@@ -0,0 +1,176 @@
+//! Defaults read at startup from `spectrum.toml` in the working directory,
+//! or the file given with `--config`, so they can change without a
+//! rebuild. The command line overrides the file and the scene's own render
+//! settings come in between:
+//!
+//! ```toml
+//! backend = "gpu"
+//! display_lut = "aces.cube"
+//! # or a display and view of an OCIO config
+//! ocio = "config.ocio"
+//! display = "sRGB"
+//! view = "AgX"
+//! width = 1920
+//! height = 1080
+//! max_depth = 12
+//! denoise = "svgf"
+//! rays_per_submit = 2000000
+//!
+//! [keys]
+//! camera-mode = "C"
+//! boost = ["ShiftLeft", "ControlLeft"]
+//! ```
+//!
+//! `preset`, `samples`, `sampler`, `spectral` and `resolution_scale` work
+//! as they do in the scene, and `[keys]` rebinds the actions of `keys` to
+//! one or more keys. Paths are relative to the file.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use winit::keyboard::KeyCode;
+
+use crate::{
+    cli::Args,
+    keys::{self, Action, Keybindings},
+    preset::{Preset, RenderOverrides},
+    sampler::SamplerKind,
+    tracer::Backend,
+    DenoiseMode,
+};
+
+pub const FILE_NAME: &str = "spectrum.toml";
+
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// The file read, if there was one.
+    pub path: Option<PathBuf>,
+    pub backend: Option<Backend>,
+    /// The display transform, see `lut`.
+    pub display_lut: Option<PathBuf>,
+    /// OCIO config, display and view the display transform is baked from
+    /// instead, see `ocio`.
+    pub ocio: Option<PathBuf>,
+    pub display: Option<String>,
+    pub view: Option<String>,
+    /// Inner size of the window and size of renders.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// See `TraceSettings::rays_per_submit`.
+    pub rays_per_submit: Option<u32>,
+    pub render: RenderOverrides,
+    pub keys: Keybindings,
+}
+
+impl Config {
+    /// Reads `path`, or `spectrum.toml` if there is one without it.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(FILE_NAME).is_file() => Path::new(FILE_NAME),
+            None => return Ok(Self::default()),
+        };
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        let table: toml::Table = text
+            .parse()
+            .with_context(|| format!("Failed to parse config {}", path.display()))?;
+        let mut config = Self {
+            path: Some(path.to_owned()),
+            ..Self::default()
+        };
+        for (key, value) in &table {
+            let invalid = || format!("{}: Invalid {key}", path.display());
+            let name = || value.as_str().with_context(invalid);
+            let count = || {
+                value
+                    .as_integer()
+                    .and_then(|n| u32::try_from(n).ok())
+                    .filter(|&n| n > 0)
+                    .with_context(invalid)
+            };
+            match key.as_str() {
+                "backend" => config.backend = Some(Backend::parse(name()?).with_context(invalid)?),
+                "display_lut" => {
+                    let parent = path.parent().unwrap_or(Path::new(""));
+                    config.display_lut = Some(parent.join(name()?));
+                }
+                "ocio" => {
+                    let parent = path.parent().unwrap_or(Path::new(""));
+                    config.ocio = Some(parent.join(name()?));
+                }
+                "display" => config.display = Some(name()?.to_owned()),
+                "view" => config.view = Some(name()?.to_owned()),
+                "width" => config.width = Some(count()?),
+                "height" => config.height = Some(count()?),
+                "rays_per_submit" => config.rays_per_submit = Some(count()?),
+                "preset" => {
+                    config.render.preset = Some(Preset::parse(name()?).with_context(invalid)?)
+                }
+                "resolution_scale" => {
+                    let scale = match value {
+                        toml::Value::Float(scale) => *scale,
+                        toml::Value::Integer(scale) => *scale as f64,
+                        _ => bail!(invalid()),
+                    };
+                    config.render.resolution_scale = Some((scale as f32).clamp(0.05, 1.0));
+                }
+                "samples" => config.render.samples = Some(count()?),
+                "max_depth" => config.render.max_depth = Some(count()?),
+                "spectral" => config.render.spectral = Some(value.as_bool().with_context(invalid)?),
+                "denoise" => {
+                    config.render.denoise = Some(DenoiseMode::parse(name()?).with_context(invalid)?)
+                }
+                "sampler" => {
+                    config.render.sampler = Some(SamplerKind::parse(name()?).with_context(invalid)?)
+                }
+                "keys" => {
+                    let keys = value.as_table().with_context(invalid)?;
+                    for (action, keys) in keys {
+                        config.keys.bind(
+                            Action::parse(action).with_context(|| {
+                                format!("{}: Unknown action {action}", path.display())
+                            })?,
+                            &parse_keys(keys).with_context(|| {
+                                format!("{}: Invalid keys for {action}", path.display())
+                            })?,
+                        );
+                    }
+                }
+                _ => bail!("{}: Unknown setting {key}", path.display()),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Fills in what the command line leaves out.
+    pub fn apply(&self, args: &mut Args) {
+        args.backend = args.backend.or(self.backend);
+        args.rays_per_submit = args.rays_per_submit.or(self.rays_per_submit);
+        // Either display transform on the command line replaces both
+        if args.display_lut.is_none() && args.ocio.is_none() {
+            args.display_lut.clone_from(&self.display_lut);
+            args.ocio.clone_from(&self.ocio);
+            if args.display.is_none() && args.view.is_none() {
+                args.display.clone_from(&self.display);
+                args.view.clone_from(&self.view);
+            }
+        }
+        if args.width.is_none() && args.height.is_none() {
+            args.width = self.width;
+            args.height = self.height;
+        }
+    }
+}
+
+/// A key name or an array of them.
+fn parse_keys(value: &toml::Value) -> Option<Vec<KeyCode>> {
+    match value {
+        toml::Value::String(name) => Some(vec![keys::parse_key(name)?]),
+        toml::Value::Array(names) => names
+            .iter()
+            .map(|name| keys::parse_key(name.as_str()?))
+            .collect(),
+        _ => None,
+    }
+}
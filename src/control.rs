@@ -0,0 +1,337 @@
+//! Remote control of render and scene parameters from OSC and MIDI
+//! control surfaces.
+//!
+//! OSC messages arrive over UDP and MIDI as a raw byte stream from a device
+//! such as `/dev/snd/midiC1D0`. MIDI is turned into the addresses
+//! `/midi/cc/<controller>` and `/midi/note/<note>` with values from 0 to 1.
+//! Messages sent straight to a target address, like `/exposure 1.5`, set
+//! the parameter directly. Others go through a control map, a text file of
+//! lines `<source> <target> [<min> <max>]` that scale values from 0 to 1
+//! onto the range of the target.
+
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    net::UdpSocket,
+    path::Path,
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+use anyhow::{bail, Context, Result};
+
+const AXES: [&str; 3] = ["x", "y", "z"];
+
+/// A parameter that can be controlled remotely, in the units of its
+/// address.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControlTarget {
+    /// `/exposure` in stops.
+    Exposure,
+    /// `/environment/intensity`
+    EnvironmentIntensity,
+    /// `/environment/rotation` in degrees.
+    EnvironmentRotation,
+    /// `/sun/elevation` in degrees.
+    SunElevation,
+    /// `/sun/azimuth` in degrees.
+    SunAzimuth,
+    /// `/sky/turbidity`
+    Turbidity,
+    /// `/light/<index>/intensity`
+    LightIntensity(usize),
+    /// `/material/<index>/emission`, the brightest channel of the emission.
+    MaterialEmission(usize),
+    /// `/camera/position/<x|y|z>`
+    CameraPosition(usize),
+    /// `/camera/yaw` in degrees.
+    CameraYaw,
+    /// `/camera/pitch` in degrees.
+    CameraPitch,
+    /// `/camera/fov`, the vertical field of view in degrees.
+    CameraFov,
+    /// `/camera/focus_distance`
+    CameraFocusDistance,
+}
+
+impl ControlTarget {
+    pub fn parse(address: &str) -> Option<Self> {
+        let parts: Vec<&str> = address.trim_start_matches('/').split('/').collect();
+        match parts[..] {
+            ["exposure"] => Some(Self::Exposure),
+            ["environment", "intensity"] => Some(Self::EnvironmentIntensity),
+            ["environment", "rotation"] => Some(Self::EnvironmentRotation),
+            ["sun", "elevation"] => Some(Self::SunElevation),
+            ["sun", "azimuth"] => Some(Self::SunAzimuth),
+            ["sky", "turbidity"] => Some(Self::Turbidity),
+            ["light", index, "intensity"] => Some(Self::LightIntensity(index.parse().ok()?)),
+            ["material", index, "emission"] => Some(Self::MaterialEmission(index.parse().ok()?)),
+            ["camera", "position", axis] => {
+                Some(Self::CameraPosition(AXES.iter().position(|&a| a == axis)?))
+            }
+            ["camera", "yaw"] => Some(Self::CameraYaw),
+            ["camera", "pitch"] => Some(Self::CameraPitch),
+            ["camera", "fov"] => Some(Self::CameraFov),
+            ["camera", "focus_distance"] => Some(Self::CameraFocusDistance),
+            _ => None,
+        }
+    }
+
+    pub fn address(&self) -> String {
+        match self {
+            Self::Exposure => String::from("/exposure"),
+            Self::EnvironmentIntensity => String::from("/environment/intensity"),
+            Self::EnvironmentRotation => String::from("/environment/rotation"),
+            Self::SunElevation => String::from("/sun/elevation"),
+            Self::SunAzimuth => String::from("/sun/azimuth"),
+            Self::Turbidity => String::from("/sky/turbidity"),
+            Self::LightIntensity(index) => format!("/light/{index}/intensity"),
+            Self::MaterialEmission(index) => format!("/material/{index}/emission"),
+            Self::CameraPosition(axis) => format!("/camera/position/{}", AXES[*axis]),
+            Self::CameraYaw => String::from("/camera/yaw"),
+            Self::CameraPitch => String::from("/camera/pitch"),
+            Self::CameraFov => String::from("/camera/fov"),
+            Self::CameraFocusDistance => String::from("/camera/focus_distance"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct ControlMapping {
+    source: String,
+    target: ControlTarget,
+    min: f32,
+    max: f32,
+}
+
+/// Collects messages from every control surface, received on background
+/// threads.
+pub struct ControlInput {
+    sender: Sender<(String, f32)>,
+    receiver: Receiver<(String, f32)>,
+    mappings: Vec<ControlMapping>,
+}
+
+impl Default for ControlInput {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            sender,
+            receiver,
+            mappings: Vec::new(),
+        }
+    }
+}
+
+impl ControlInput {
+    /// Reads a control map, see the module documentation.
+    pub fn load_map(&mut self, path: &Path) -> Result<()> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read control map {}", path.display()))?;
+        for (number, line) in text.lines().enumerate() {
+            let location = || format!("{}:{}", path.display(), number + 1);
+            let line = line.split('#').next().unwrap_or_default();
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (source, target, min, max) = match fields[..] {
+                [] => continue,
+                [source, target] => (source, target, "0", "1"),
+                [source, target, min, max] => (source, target, min, max),
+                _ => bail!(
+                    "{}: Expected a source, a target and an optional range",
+                    location()
+                ),
+            };
+            let target = ControlTarget::parse(target)
+                .with_context(|| format!("{}: Unknown target {target}", location()))?;
+            let (min, max) = min
+                .parse()
+                .and_then(|min| Ok((min, max.parse()?)))
+                .with_context(|| format!("{}: Invalid range", location()))?;
+            self.mappings.push(ControlMapping {
+                source: source.to_owned(),
+                target,
+                min,
+                max,
+            });
+        }
+        Ok(())
+    }
+
+    /// Listens for OSC messages on a UDP port of every interface.
+    pub fn listen_osc(&self, port: u16) -> Result<()> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))
+            .with_context(|| format!("Failed to listen for OSC on port {port}"))?;
+        tracing::info!("Listening for OSC on port {port}");
+        let sender = self.sender.clone();
+        std::thread::Builder::new()
+            .name(String::from("osc input"))
+            .spawn(move || {
+                let mut packet = [0u8; 4096];
+                while let Ok(size) = socket.recv(&mut packet) {
+                    let mut messages = Vec::new();
+                    parse_osc_packet(&packet[..size], &mut messages);
+                    for message in messages {
+                        if sender.send(message).is_err() {
+                            return;
+                        }
+                    }
+                }
+            })?;
+        Ok(())
+    }
+
+    /// Reads raw MIDI from a device file.
+    pub fn open_midi(&self, path: &Path) -> Result<()> {
+        let device = File::open(path)
+            .with_context(|| format!("Failed to open MIDI device {}", path.display()))?;
+        let sender = self.sender.clone();
+        std::thread::Builder::new()
+            .name(String::from("midi input"))
+            .spawn(move || read_midi(device, &sender))?;
+        Ok(())
+    }
+
+    /// Drains the messages received since the last call, mapped onto their
+    /// targets. Unknown addresses are ignored.
+    pub fn poll(&self) -> Vec<(ControlTarget, f32)> {
+        let mut updates = Vec::new();
+        for (address, value) in self.receiver.try_iter() {
+            let mut mapped = false;
+            for mapping in self.mappings.iter().filter(|m| m.source == address) {
+                updates.push((
+                    mapping.target,
+                    mapping.min + value * (mapping.max - mapping.min),
+                ));
+                mapped = true;
+            }
+            if !mapped {
+                match ControlTarget::parse(&address) {
+                    Some(target) => updates.push((target, value)),
+                    None => tracing::debug!("Unmapped control {address} {value}"),
+                }
+            }
+        }
+        updates
+    }
+}
+
+/// Appends the first numeric argument of every message in an OSC packet,
+/// looking into bundles.
+fn parse_osc_packet(packet: &[u8], messages: &mut Vec<(String, f32)>) {
+    if let Some(mut elements) = packet.strip_prefix(b"#bundle\0") {
+        // Skip the time tag, bundles are applied immediately
+        elements = elements.get(8..).unwrap_or_default();
+        while let Some(&size) = elements.first_chunk::<4>() {
+            let size = u32::from_be_bytes(size) as usize;
+            let Some(element) = elements.get(4..4 + size) else {
+                return;
+            };
+            parse_osc_packet(element, messages);
+            elements = &elements[4 + size..];
+        }
+        return;
+    }
+    let Some((address, rest)) = osc_string(packet) else {
+        return;
+    };
+    let Some((tags, mut args)) = osc_string(rest) else {
+        return;
+    };
+    for tag in tags.strip_prefix(',').unwrap_or_default().chars() {
+        let value = match tag {
+            'f' | 'i' => {
+                let Some(&bytes) = args.first_chunk::<4>() else {
+                    return;
+                };
+                if tag == 'f' {
+                    f32::from_be_bytes(bytes)
+                } else {
+                    i32::from_be_bytes(bytes) as f32
+                }
+            }
+            'd' | 'h' => {
+                let Some(&bytes) = args.first_chunk::<8>() else {
+                    return;
+                };
+                if tag == 'd' {
+                    f64::from_be_bytes(bytes) as f32
+                } else {
+                    i64::from_be_bytes(bytes) as f32
+                }
+            }
+            'T' => 1.0,
+            'F' => 0.0,
+            // Skip over strings and blobs to reach later numbers
+            's' | 'S' => {
+                let Some((_, rest)) = osc_string(args) else {
+                    return;
+                };
+                args = rest;
+                continue;
+            }
+            'b' => {
+                let Some(&size) = args.first_chunk::<4>() else {
+                    return;
+                };
+                let size = u32::from_be_bytes(size) as usize;
+                args = args.get(4 + size.next_multiple_of(4)..).unwrap_or_default();
+                continue;
+            }
+            _ => continue,
+        };
+        messages.push((address.to_owned(), value));
+        return;
+    }
+}
+
+/// Splits off a null terminated string padded to four bytes.
+fn osc_string(bytes: &[u8]) -> Option<(&str, &[u8])> {
+    let end = bytes.iter().position(|&b| b == 0)?;
+    let string = std::str::from_utf8(&bytes[..end]).ok()?;
+    let rest = bytes.get((end + 1).next_multiple_of(4)..)?;
+    Some((string, rest))
+}
+
+/// Turns control changes and notes on any channel into messages, ignoring
+/// everything else.
+fn read_midi(device: File, sender: &Sender<(String, f32)>) {
+    let mut status = 0u8;
+    let mut data = Vec::with_capacity(2);
+    for byte in BufReader::new(device).bytes() {
+        let Ok(byte) = byte else {
+            break;
+        };
+        if byte >= 0xF8 {
+            // Real time messages can appear anywhere
+            continue;
+        }
+        if byte & 0x80 != 0 {
+            status = byte;
+            data.clear();
+            continue;
+        }
+        // Data bytes reuse the last status, called running status. System
+        // messages such as sysex are skipped over.
+        let length = match status & 0xF0 {
+            0x80..=0xB0 | 0xE0 => 2,
+            0xC0 | 0xD0 => 1,
+            _ => continue,
+        };
+        data.push(byte);
+        if data.len() < length {
+            continue;
+        }
+        let message = match (status & 0xF0, &data[..]) {
+            (0xB0, &[controller, value]) => Some((format!("/midi/cc/{controller}"), value)),
+            (0x90, &[note, velocity]) => Some((format!("/midi/note/{note}"), velocity)),
+            (0x80, &[note, _]) => Some((format!("/midi/note/{note}"), 0)),
+            _ => None,
+        };
+        data.clear();
+        if let Some((address, value)) = message {
+            if sender.send((address, value as f32 / 127.0)).is_err() {
+                break;
+            }
+        }
+    }
+    tracing::info!("MIDI input ended");
+}
@@ -0,0 +1,558 @@
+//! Synthetic training data: the scene from random viewpoints under random
+//! lighting, each with aligned depth, normals, and instance and semantic
+//! segmentation, listed in a JSON manifest. Depth is the distance along
+//! the ray, instance segmentation the instance index plus one and semantic
+//! segmentation the instance's class, all zero where nothing was hit. They
+//! come from the GPU tracer's guides, which the CPU backend leaves blank.
+//!
+//! What gets randomized is read from text files of lines `<name> <min>
+//! [<max>]`, a fixed value without a maximum, with angles in degrees.
+//! `environment <path>` adds an HDRI to pick from, relative to the file.
+//! See `Randomization` for the names.
+
+use std::{
+    cell::RefCell,
+    f32::consts::TAU,
+    fmt::Write,
+    ops::Range,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use glam::{Mat4, Vec3};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    aov::{Aov, Aovs},
+    bvh::InstancedBvh,
+    camera::{Camera, DistortionMode, LensDistortion},
+    lod,
+    lut::{self, DisplayLut},
+    output::exr::{self, Precision},
+    readback::Readbacks,
+    scene::{Environment, Material, Scene},
+    sky::Sky,
+    thumbnail,
+    tonemap::Tonemap,
+    tracer::{Backend, TraceSettings},
+};
+
+/// Ranges every configuration is drawn from uniformly, named as in spec
+/// files. Angles are in radians and an empty range always gives its start.
+/// What has no range is left as the scene has it.
+#[derive(Clone, Debug)]
+pub struct Randomization {
+    /// `yaw`, rotation of the camera around the scene.
+    pub yaw: Range<f32>,
+    /// `pitch`, angle of the camera above the horizon, negative looking
+    /// down.
+    pub pitch: Range<f32>,
+    /// `distance` of the camera relative to the one framing the whole
+    /// scene.
+    pub distance: Range<f32>,
+    /// `fov`, vertical field of view of the camera.
+    pub fov: Option<Range<f32>>,
+    /// `distortion`, first radial coefficient of the camera's lens.
+    pub distortion: Option<Range<f32>>,
+    /// `environment-rotation` around +Y.
+    pub environment_rotation: Range<f32>,
+    /// `environment-stops`, brightness of the environment or sky.
+    pub environment_stops: Range<f32>,
+    /// `light-stops`, brightness of the scene's lights.
+    pub light_stops: Range<f32>,
+    pub sun_elevation: Range<f32>,
+    pub sun_azimuth: Range<f32>,
+    /// HDRIs, one of which lights each sample in place of the scene's
+    /// environment.
+    pub environments: Vec<PathBuf>,
+    /// `base-color`, drawn for each channel of every material.
+    pub base_color: Option<Range<f32>>,
+    /// `roughness` of every material.
+    pub roughness: Option<Range<f32>>,
+    /// `metallic` of every material.
+    pub metallic: Option<Range<f32>>,
+    /// `offset` of every instance along each axis, in scene units.
+    pub offset: Option<Range<f32>>,
+    /// `rotation` of every instance around +Y through its center.
+    pub rotation: Option<Range<f32>>,
+    /// `scale` of every instance about its center.
+    pub scale: Option<Range<f32>>,
+}
+
+impl Default for Randomization {
+    fn default() -> Self {
+        Self {
+            yaw: 0.0..TAU,
+            pitch: -60f32.to_radians()..5f32.to_radians(),
+            distance: 0.8..1.5,
+            environment_rotation: 0.0..TAU,
+            environment_stops: -1.0..1.0,
+            light_stops: -1.0..1.0,
+            sun_elevation: 10f32.to_radians()..80f32.to_radians(),
+            sun_azimuth: 0.0..TAU,
+            fov: None,
+            distortion: None,
+            environments: Vec::new(),
+            base_color: None,
+            roughness: None,
+            metallic: None,
+            offset: None,
+            rotation: None,
+            scale: None,
+        }
+    }
+}
+
+impl Randomization {
+    /// Reads a spec file, starting from the default ranges.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read randomization {}", path.display()))?;
+        let mut randomization = Self::default();
+        for (number, line) in text.lines().enumerate() {
+            let location = || format!("{}:{}", path.display(), number + 1);
+            let line = line.split('#').next().unwrap_or_default();
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (name, values) = match fields[..] {
+                [] => continue,
+                ["environment", environment] => {
+                    let directory = path.parent().unwrap_or(Path::new(""));
+                    randomization.environments.push(directory.join(environment));
+                    continue;
+                }
+                [name, min] => (name, [min, min]),
+                [name, min, max] => (name, [min, max]),
+                _ => bail!("{}: Expected a name, a minimum and a maximum", location()),
+            };
+            let [min, max] = values
+                .map(str::parse::<f32>)
+                .map(|value| value.with_context(|| format!("{}: Invalid value", location())));
+            let range = min?..max?;
+            let angle = range.start.to_radians()..range.end.to_radians();
+            match name {
+                "yaw" => randomization.yaw = angle,
+                "pitch" => randomization.pitch = angle,
+                "distance" => randomization.distance = range,
+                "fov" => randomization.fov = Some(angle),
+                "distortion" => randomization.distortion = Some(range),
+                "environment-rotation" => randomization.environment_rotation = angle,
+                "environment-stops" => randomization.environment_stops = range,
+                "light-stops" => randomization.light_stops = range,
+                "sun-elevation" => randomization.sun_elevation = angle,
+                "sun-azimuth" => randomization.sun_azimuth = angle,
+                "base-color" => randomization.base_color = Some(range),
+                "roughness" => randomization.roughness = Some(range),
+                "metallic" => randomization.metallic = Some(range),
+                "offset" => randomization.offset = Some(range),
+                "rotation" => randomization.rotation = Some(angle),
+                "scale" => randomization.scale = Some(range),
+                _ => bail!("{}: Unknown randomization {name}", location()),
+            }
+        }
+        Ok(randomization)
+    }
+
+    fn recolors(&self) -> bool {
+        self.base_color.is_some() || self.roughness.is_some() || self.metallic.is_some()
+    }
+
+    fn poses(&self) -> bool {
+        self.offset.is_some() || self.rotation.is_some() || self.scale.is_some()
+    }
+}
+
+/// What samples change about the scene, as it was loaded.
+struct Original {
+    materials: Vec<Material>,
+    transforms: Vec<Mat4>,
+    centers: Vec<Vec3>,
+    intensities: Vec<f32>,
+}
+
+fn draw(rng: &mut StdRng, range: &Range<f32>) -> f32 {
+    range.start + (range.end - range.start) * rng.gen::<f32>()
+}
+
+/// What to render, square images of `size` with `samples` per pixel.
+/// Sample `i` is drawn with seed `seed + i`, so any one of them can be
+/// rendered again on its own.
+#[derive(Clone, Debug)]
+pub struct Dataset {
+    pub count: u32,
+    pub size: u32,
+    pub samples: u32,
+    pub seed: u64,
+    pub randomization: Randomization,
+}
+
+impl Dataset {
+    /// Writes the images and `manifest.json` to `directory`. Colors are
+    /// encoded as sRGB, or by the display LUT if there is one.
+    pub fn render(
+        &self,
+        mut scene: Scene,
+        directory: &Path,
+        display_lut: Option<&DisplayLut>,
+        backend: Option<Backend>,
+    ) -> Result<()> {
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Failed to create {}", directory.display()))?;
+        let (adapter, device, queue) = thumbnail::request_device()?;
+        let bounds = scene.bounds();
+        let mut framing = Camera::default();
+        framing.frame(&bounds, 1.0);
+        lod::select(&mut scene, framing.position, framing.fov_y, self.size);
+        let mut bvh = InstancedBvh::new(&scene);
+        let backend = thumbnail::choose_backend(&adapter, backend);
+        if backend == Backend::Cpu {
+            tracing::warn!("The CPU backend leaves depth, normals and segmentations blank");
+        }
+        let size = PhysicalSize::new(self.size, self.size);
+        let mut tracer = backend.create(&device, &queue, &scene, &bvh, size);
+        let aovs = Aovs::new(&device, size);
+        let ranges = &self.randomization;
+        let environments = ranges
+            .environments
+            .iter()
+            .map(Environment::load)
+            .collect::<Result<Vec<_>>>()?;
+        let original = Original {
+            materials: scene.materials.clone(),
+            transforms: scene
+                .instances
+                .iter()
+                .map(|instance| instance.transform)
+                .collect(),
+            centers: (0..scene.instances.len())
+                .map(|index| scene.instance_bounds(index).center())
+                .collect(),
+            intensities: scene.lights.iter().map(|light| light.intensity).collect(),
+        };
+        let mut current_environment = None;
+
+        let mut readbacks = Readbacks::default();
+        // The first error saving a sample, which ends the render
+        let failed = Rc::new(RefCell::new(None));
+        let mut frames = Vec::new();
+        for index in 0..self.count {
+            let mut rng = StdRng::seed_from_u64(self.seed.wrapping_add(index as u64));
+            let mut camera = Camera {
+                yaw: draw(&mut rng, &ranges.yaw),
+                pitch: draw(&mut rng, &ranges.pitch),
+                ..Default::default()
+            };
+            if let Some(fov) = &ranges.fov {
+                camera.fov_y = draw(&mut rng, fov);
+            }
+            if let Some(distortion) = &ranges.distortion {
+                camera.distortion = LensDistortion {
+                    mode: DistortionMode::Apply,
+                    k1: draw(&mut rng, distortion),
+                    ..Default::default()
+                };
+            }
+            camera.frame(&bounds, 1.0);
+            camera.focus_distance *= draw(&mut rng, &ranges.distance);
+            camera.position = bounds.center() - camera.forward() * camera.focus_distance;
+
+            let light_scale = draw(&mut rng, &ranges.light_stops).exp2();
+            for (light, intensity) in scene.lights.iter_mut().zip(&original.intensities) {
+                light.intensity = intensity * light_scale;
+            }
+            if ranges.recolors() {
+                for (material, original) in scene.materials.iter_mut().zip(&original.materials) {
+                    *material = original.clone();
+                    if let Some(range) = &ranges.base_color {
+                        let [r, g, b] = [(); 3].map(|_| draw(&mut rng, range));
+                        material.base_color = Vec3::new(r, g, b).extend(original.base_color.w);
+                    }
+                    if let Some(range) = &ranges.roughness {
+                        material.roughness = draw(&mut rng, range);
+                    }
+                    if let Some(range) = &ranges.metallic {
+                        material.metallic = draw(&mut rng, range);
+                    }
+                }
+            }
+            if ranges.poses() {
+                let ocean = scene.ocean.as_ref().map(|(instance, _)| *instance);
+                // The primitives of a node take one pose between them
+                let mut posed = Vec::new();
+                for instance_index in 0..scene.instances.len() {
+                    if ocean == Some(instance_index) {
+                        continue;
+                    }
+                    if let Some(node) = scene.node_of_instance(instance_index) {
+                        if posed.contains(&node) {
+                            continue;
+                        }
+                        posed.push(node);
+                    }
+                    let mut draw_or = |range: &Option<Range<f32>>, default| {
+                        range
+                            .as_ref()
+                            .map_or(default, |range| draw(&mut rng, range))
+                    };
+                    let offset = Vec3::new(
+                        draw_or(&ranges.offset, 0.0),
+                        draw_or(&ranges.offset, 0.0),
+                        draw_or(&ranges.offset, 0.0),
+                    );
+                    let angle = draw_or(&ranges.rotation, 0.0);
+                    let scale = draw_or(&ranges.scale, 1.0);
+                    let center = original.centers[instance_index];
+                    let world = Mat4::from_translation(center + offset)
+                        * Mat4::from_rotation_y(angle)
+                        * Mat4::from_scale(Vec3::splat(scale))
+                        * Mat4::from_translation(-center)
+                        * original.transforms[instance_index];
+                    scene.set_instance_transform(instance_index, world);
+                }
+                // Poses jump anywhere in their ranges, too far for a refit
+                bvh.update(&scene);
+            }
+            let environment =
+                (!environments.is_empty()).then(|| rng.gen_range(0..environments.len()));
+            if environment != current_environment {
+                // A different map has to be uploaded with everything else
+                scene.environment =
+                    environment.map(|environment| environments[environment].clone());
+                tracer.init(&device, &queue, &scene, &bvh);
+                current_environment = environment;
+            } else {
+                if ranges.poses() {
+                    tracer.update_geometry(&queue, &scene, &bvh);
+                }
+                if ranges.recolors() {
+                    tracer.update_materials(&queue, &scene);
+                }
+                tracer.update_lights(&queue, &scene);
+            }
+            let settings = TraceSettings {
+                environment_rotation: draw(&mut rng, &ranges.environment_rotation),
+                environment_intensity: draw(&mut rng, &ranges.environment_stops).exp2(),
+                sky: Sky {
+                    sun_elevation: draw(&mut rng, &ranges.sun_elevation),
+                    sun_azimuth: draw(&mut rng, &ranges.sun_azimuth),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+
+            tracer.reset();
+            for _ in 0..self.samples {
+                let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Dataset Encoder"),
+                });
+                tracer.render_frame(&device, &queue, &mut encoder, &camera, &settings);
+                queue.submit(std::iter::once(encoder.finish()));
+            }
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Dataset Encoder"),
+            });
+            aovs.render(&device, &queue, &mut encoder, &*tracer, &camera, &camera);
+            queue.submit(std::iter::once(encoder.finish()));
+
+            let files = Files::new(directory, index);
+            let environment =
+                environment.map(|environment| ranges.environments[environment].as_path());
+            frames.push(frame_json(
+                &files,
+                &camera,
+                self.size,
+                &settings,
+                light_scale,
+                environment,
+            ));
+            // The next sample is traced while this one comes back and saves
+            let image = Rc::new(RefCell::new(None));
+            let read = image.clone();
+            readbacks.read_texture(&device, tracer.output_texture(), 0, move |rgb| {
+                *read.borrow_mut() = Some(rgb);
+            });
+            let (lut, error) = (display_lut.cloned(), failed.clone());
+            aovs.read(&device, &mut readbacks, move |layers| {
+                let result = match image.take() {
+                    Some(image) => save_sample(&files, &image, &layers, lut.as_ref()),
+                    None => Err(anyhow!("Failed to read sample {index} back from the GPU")),
+                };
+                if let Err(err) = result {
+                    error.borrow_mut().get_or_insert(err);
+                }
+            });
+            readbacks.submit(&queue);
+            readbacks.poll(&device);
+            if let Some(err) = failed.take() {
+                return Err(err);
+            }
+            tracing::info!("Rendered sample {} of {}", index + 1, self.count);
+        }
+
+        thumbnail::finish_readbacks(&device, &queue, &mut readbacks);
+        if let Some(err) = failed.take() {
+            return Err(err);
+        }
+
+        let manifest = format!(
+            "{{\n  \"width\": {size},\n  \"height\": {size},\n  \"samples\": {},\n  \
+             \"seed\": {},\n  \"instances\": {},\n  \"frames\": [\n{}\n  ]\n}}\n",
+            self.samples,
+            self.seed,
+            scene.instances.len(),
+            frames.join(",\n"),
+            size = self.size,
+        );
+        let path = directory.join("manifest.json");
+        std::fs::write(&path, manifest)
+            .with_context(|| format!("Failed to save {}", path.display()))
+    }
+}
+
+/// Images of one sample.
+struct Files {
+    rgb: PathBuf,
+    depth: PathBuf,
+    normal: PathBuf,
+    segmentation: PathBuf,
+    classes: PathBuf,
+}
+
+impl Files {
+    fn new(directory: &Path, index: u32) -> Self {
+        let path = |kind, extension| directory.join(format!("{index:05}_{kind}.{extension}"));
+        Self {
+            rgb: path("rgb", "png"),
+            depth: path("depth", "exr"),
+            normal: path("normal", "exr"),
+            segmentation: path("segmentation", "png"),
+            classes: path("classes", "png"),
+        }
+    }
+}
+
+/// Writes the image and AOVs of one sample.
+fn save_sample(
+    files: &Files,
+    image: &image::Rgba32FImage,
+    layers: &[(Aov, image::Rgba32FImage)],
+    display_lut: Option<&DisplayLut>,
+) -> Result<()> {
+    save_png(
+        &lut::display_image(image, 1.0, Tonemap::None, display_lut),
+        &files.rgb,
+    )?;
+    for &(aov, ref layer) in layers {
+        match aov {
+            Aov::Depth => {
+                let depth = layer.pixels().map(|pixel| pixel.0[0]).collect();
+                exr::save_channels(
+                    layer.dimensions(),
+                    &[("Z", depth)],
+                    &files.depth,
+                    Precision::Float,
+                )?;
+            }
+            Aov::Normal => {
+                let channels = aov.channels().iter().enumerate().map(|(axis, &name)| {
+                    (name, layer.pixels().map(|pixel| pixel.0[axis]).collect())
+                });
+                exr::save_channels(
+                    layer.dimensions(),
+                    &channels.collect::<Vec<_>>(),
+                    &files.normal,
+                    Precision::Float,
+                )?;
+            }
+            Aov::ObjectId | Aov::ClassId => {
+                let segmentation =
+                    image::ImageBuffer::from_fn(layer.width(), layer.height(), |x, y| {
+                        image::Luma([layer.get_pixel(x, y).0[0].round() as u16])
+                    });
+                let path = if aov == Aov::ObjectId {
+                    &files.segmentation
+                } else {
+                    &files.classes
+                };
+                save_png(&segmentation, path)?;
+            }
+            Aov::Beauty | Aov::Albedo | Aov::Motion => {}
+        }
+    }
+    Ok(())
+}
+
+fn save_png<P: image::PixelWithColorType>(
+    image: &image::ImageBuffer<P, Vec<P::Subpixel>>,
+    path: &Path,
+) -> Result<()>
+where
+    [P::Subpixel]: image::EncodableLayout,
+{
+    image
+        .save(path)
+        .with_context(|| format!("Failed to save {}", path.display()))
+}
+
+/// Manifest entry of a sample, with the pinhole intrinsics of its camera
+/// in pixels.
+fn frame_json(
+    files: &Files,
+    camera: &Camera,
+    size: u32,
+    settings: &TraceSettings,
+    light_scale: f32,
+    environment: Option<&Path>,
+) -> String {
+    let name = |path: &Path| {
+        path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    };
+    let vector = |v: Vec3| format!("[{}, {}, {}]", v.x, v.y, v.z);
+    let distortion = camera.distortion;
+    let focal = 0.5 * size as f32 / (0.5 * camera.fov_y).tan();
+    let center = 0.5 * size as f32;
+    let mut json = String::from("    {\n");
+    for (key, path) in [
+        ("rgb", &files.rgb),
+        ("depth", &files.depth),
+        ("normal", &files.normal),
+        ("segmentation", &files.segmentation),
+        ("classes", &files.classes),
+    ] {
+        let _ = writeln!(json, "      \"{key}\": \"{}\",", name(path));
+    }
+    let _ = writeln!(
+        json,
+        "      \"camera\": {{\n        \"position\": {},\n        \"forward\": {},\n        \
+         \"up\": {},\n        \"fov_y\": {},\n        \
+         \"intrinsics\": [[{focal}, 0, {center}], [0, {focal}, {center}], [0, 0, 1]],\n        \
+         \"distortion\": [{}, {}, {}, {}, {}]\n      }},",
+        vector(camera.position),
+        vector(camera.forward()),
+        vector(camera.up()),
+        camera.fov_y,
+        distortion.k1,
+        distortion.k2,
+        distortion.k3,
+        distortion.p1,
+        distortion.p2,
+    );
+    if let Some(environment) = environment {
+        let _ = writeln!(json, "      \"environment\": \"{}\",", name(environment));
+    }
+    let _ = write!(
+        json,
+        "      \"environment_rotation\": {},\n      \"environment_intensity\": {},\n      \
+         \"light_scale\": {light_scale},\n      \"sun_elevation\": {},\n      \
+         \"sun_azimuth\": {}\n    }}",
+        settings.environment_rotation,
+        settings.environment_intensity,
+        settings.sky.sun_elevation,
+        settings.sky.sun_azimuth,
+    );
+    json
+}
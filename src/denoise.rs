@@ -0,0 +1,131 @@
+//! Denoising of the accumulated image with Intel Open Image Denoise, guided
+//! by the albedo and normal the tracer writes next to it. Links against the
+//! OpenImageDenoise 2 library installed on the system.
+
+use std::ffi::{c_char, c_void, CStr};
+
+use anyhow::{bail, Result};
+
+type OIDNDevice = *mut c_void;
+type OIDNFilter = *mut c_void;
+
+const OIDN_DEVICE_TYPE_DEFAULT: i32 = 0;
+const OIDN_ERROR_NONE: i32 = 0;
+const OIDN_FORMAT_FLOAT3: i32 = 3;
+
+#[link(name = "OpenImageDenoise")]
+extern "C" {
+    fn oidnNewDevice(device_type: i32) -> OIDNDevice;
+    fn oidnCommitDevice(device: OIDNDevice);
+    fn oidnGetDeviceError(device: OIDNDevice, message: *mut *const c_char) -> i32;
+    fn oidnReleaseDevice(device: OIDNDevice);
+    fn oidnNewFilter(device: OIDNDevice, filter_type: *const c_char) -> OIDNFilter;
+    fn oidnSetSharedFilterImage(
+        filter: OIDNFilter,
+        name: *const c_char,
+        data: *mut c_void,
+        format: i32,
+        width: usize,
+        height: usize,
+        byte_offset: usize,
+        pixel_byte_stride: usize,
+        row_byte_stride: usize,
+    );
+    fn oidnSetFilterBool(filter: OIDNFilter, name: *const c_char, value: bool);
+    fn oidnCommitFilter(filter: OIDNFilter);
+    fn oidnExecuteFilter(filter: OIDNFilter);
+    fn oidnReleaseFilter(filter: OIDNFilter);
+}
+
+/// A device and ray tracing filter, kept around between images since
+/// creating them is slow.
+pub struct Denoiser {
+    device: OIDNDevice,
+    filter: OIDNFilter,
+}
+
+impl Denoiser {
+    pub fn new() -> Result<Self> {
+        // SAFETY: the handles are released in drop, and only used while
+        // they're alive
+        unsafe {
+            let device = oidnNewDevice(OIDN_DEVICE_TYPE_DEFAULT);
+            if device.is_null() {
+                bail!("Failed to create a denoising device");
+            }
+            oidnCommitDevice(device);
+            let filter = oidnNewFilter(device, c"RT".as_ptr());
+            let denoiser = Self { device, filter };
+            denoiser.check()?;
+            Ok(denoiser)
+        }
+    }
+
+    /// Denoises an HDR `color` image. `albedo` and `normal` have the same
+    /// size, the alpha of all three is ignored.
+    pub fn denoise(
+        &mut self,
+        color: &image::Rgba32FImage,
+        albedo: &image::Rgba32FImage,
+        normal: &image::Rgba32FImage,
+    ) -> Result<image::Rgba32FImage> {
+        let (width, height) = color.dimensions();
+        debug_assert_eq!(albedo.dimensions(), (width, height));
+        debug_assert_eq!(normal.dimensions(), (width, height));
+        let mut output = color.clone();
+        let set_image = |name: &CStr, image: *const f32| {
+            // SAFETY: the images outlive the execution of the filter below,
+            // and OIDN only writes to the output
+            unsafe {
+                oidnSetSharedFilterImage(
+                    self.filter,
+                    name.as_ptr(),
+                    image as *mut c_void,
+                    OIDN_FORMAT_FLOAT3,
+                    width as usize,
+                    height as usize,
+                    0,
+                    16,
+                    16 * width as usize,
+                );
+            }
+        };
+        set_image(c"color", color.as_ptr());
+        set_image(c"albedo", albedo.as_ptr());
+        set_image(c"normal", normal.as_ptr());
+        set_image(c"output", output.as_mut_ptr());
+        // SAFETY: see `new`
+        unsafe {
+            oidnSetFilterBool(self.filter, c"hdr".as_ptr(), true);
+            oidnCommitFilter(self.filter);
+            oidnExecuteFilter(self.filter);
+        }
+        self.check()?;
+        Ok(output)
+    }
+
+    fn check(&self) -> Result<()> {
+        let mut message = std::ptr::null();
+        // SAFETY: the message is owned by the device and copied right away
+        let error = unsafe { oidnGetDeviceError(self.device, &mut message) };
+        if error != OIDN_ERROR_NONE {
+            let message = if message.is_null() {
+                "unknown error".into()
+            } else {
+                unsafe { CStr::from_ptr(message) }.to_string_lossy()
+            };
+            bail!("Denoising failed: {message}");
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Denoiser {
+    fn drop(&mut self) {
+        // SAFETY: see `new`
+        unsafe {
+            oidnReleaseFilter(self.filter);
+            oidnReleaseDevice(self.device);
+        }
+    }
+}
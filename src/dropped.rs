@@ -0,0 +1,78 @@
+//! Loading of files dropped onto the window. Float images become the
+//! environment map and anything else replaces the scene. Files are read,
+//! and scenes get their BVH, on a background thread, so only the upload
+//! to the tracer happens between frames.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+use anyhow::Result;
+use glam::Vec3;
+
+use crate::{
+    bvh::InstancedBvh,
+    lod,
+    scene::{Environment, Scene},
+};
+
+/// Extensions of the files loaded as environment maps.
+const ENVIRONMENTS: [&str; 2] = ["exr", "hdr"];
+
+pub enum Dropped {
+    Scene(Box<Scene>, InstancedBvh),
+    Environment(Environment),
+}
+
+/// Where the detail of a scene's meshes is chosen from, as it's loaded.
+#[derive(Clone, Copy, Debug)]
+pub struct View {
+    pub eye: Vec3,
+    pub fov_y: f32,
+    pub viewport_height: u32,
+}
+
+type Loaded = (PathBuf, Result<Dropped>);
+
+pub struct DropLoader {
+    sender: Sender<Loaded>,
+    receiver: Receiver<Loaded>,
+}
+
+impl Default for DropLoader {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self { sender, receiver }
+    }
+}
+
+impl DropLoader {
+    /// Starts loading `path`, see `finished` for the result.
+    pub fn load(&self, path: PathBuf, view: View) {
+        let sender = self.sender.clone();
+        std::thread::spawn(move || {
+            let loaded = load(&path, view);
+            let _ = sender.send((path, loaded));
+        });
+    }
+
+    /// Files loaded since the last call, in the order they finished.
+    pub fn finished(&self) -> Vec<Loaded> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+fn load(path: &Path, view: View) -> Result<Dropped> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+    if extension.is_some_and(|extension| ENVIRONMENTS.contains(&extension.as_str())) {
+        return Ok(Dropped::Environment(Environment::load(path)?));
+    }
+    let mut scene = Scene::load(path)?;
+    lod::select(&mut scene, view.eye, view.fov_y, view.viewport_height);
+    let instanced = InstancedBvh::new(&scene);
+    Ok(Dropped::Scene(Box::new(scene), instanced))
+}
@@ -0,0 +1,200 @@
+//! Auto-exposure for the viewport, metered from a histogram of the shown
+//! image's luminance in compute passes, see exposure.wgsl. The metered
+//! exposure stays in a buffer the render pass reads, so nothing is read
+//! back, and eases toward the image's brightness over a second or so as
+//! the camera moves between dark and bright parts of the scene.
+
+use web_time::Instant;
+use winit::dpi::PhysicalSize;
+
+use crate::shader;
+
+/// Workgroups of the histogram pass cover this many pixels across and
+/// down, matched in exposure.wgsl.
+const WORKGROUP_SIZE: u32 = 16;
+/// Bins of the histogram, matched in exposure.wgsl.
+const BINS: u64 = 256;
+/// Range of log2 luminance the histogram covers, darker pixels aren't
+/// metered and brighter ones count as the brightest.
+const MIN_LOG_LUMINANCE: f32 = -12.0;
+const MAX_LOG_LUMINANCE: f32 = 8.0;
+/// Rate at which the exposure closes the gap to the metered one, per
+/// second.
+const ADAPTATION_RATE: f32 = 2.0;
+
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct ExposureParams {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    _pad: u32,
+}
+
+pub struct AutoExposure {
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    params_buffer: wgpu::Buffer,
+    histogram_buffer: wgpu::Buffer,
+    metered_buffer: wgpu::Buffer,
+    /// When the exposure was last metered, none to jump straight to it
+    /// next time.
+    metered_at: Option<Instant>,
+}
+
+impl AutoExposure {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let module = shader::create_module(device, "Exposure Shader", "exposure.wgsl", &[]);
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Exposure Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                storage_entry(2),
+                storage_entry(3),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Exposure Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+        let buffer = |label, size, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        Self {
+            histogram_pipeline: pipeline("Exposure Histogram Pipeline", "build_histogram"),
+            average_pipeline: pipeline("Exposure Average Pipeline", "average_histogram"),
+            params_buffer: buffer(
+                "Exposure Params",
+                std::mem::size_of::<ExposureParams>() as u64,
+                wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            ),
+            histogram_buffer: buffer("Exposure Histogram", BINS * 4, wgpu::BufferUsages::STORAGE),
+            metered_buffer: buffer(
+                "Metered Exposure",
+                4,
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            ),
+            metered_at: None,
+        }
+    }
+
+    /// Meters the exposure of `image`, which is `size` pixels, easing
+    /// toward it from the last one unless this is the first time since a
+    /// `reset`.
+    pub fn meter(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        image: &wgpu::TextureView,
+        size: PhysicalSize<u32>,
+    ) {
+        let now = Instant::now();
+        let adaptation = self.metered_at.map_or(1.0, |metered_at| {
+            1.0 - (-ADAPTATION_RATE * (now - metered_at).as_secs_f32()).exp()
+        });
+        self.metered_at = Some(now);
+        let params = ExposureParams {
+            min_log_luminance: MIN_LOG_LUMINANCE,
+            log_luminance_range: MAX_LOG_LUMINANCE - MIN_LOG_LUMINANCE,
+            adaptation,
+            _pad: 0,
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Exposure Bind Group"),
+            layout: &self.histogram_pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(image),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.histogram_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.metered_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Exposure Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.histogram_pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.dispatch_workgroups(
+            size.width.div_ceil(WORKGROUP_SIZE),
+            size.height.div_ceil(WORKGROUP_SIZE),
+            1,
+        );
+        pass.set_pipeline(&self.average_pipeline);
+        pass.dispatch_workgroups(1, 1, 1);
+    }
+
+    /// Jumps to the metered exposure the next time, as after the image
+    /// wasn't metered for a while.
+    pub fn reset(&mut self) {
+        self.metered_at = None;
+    }
+
+    /// Holds the metered exposure in stops as an `f32`, for the render
+    /// pass to add to the exposure compensation.
+    pub fn metered_buffer(&self) -> &wgpu::Buffer {
+        &self.metered_buffer
+    }
+}
+
+fn storage_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
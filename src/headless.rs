@@ -0,0 +1,141 @@
+//! Final renders without a window or surface, for servers and batch jobs.
+
+use std::{cell::RefCell, rc::Rc, time::Instant};
+
+use anyhow::{bail, Context, Result};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    aov::{Aov, Aovs},
+    bvh::InstancedBvh,
+    camera::Camera,
+    lod,
+    output::preview::Preview,
+    readback::Readbacks,
+    scene::Scene,
+    thumbnail,
+    tile::Tile,
+    tracer::{Backend, CpuRenderer, Renderer, TraceSettings},
+    tuning,
+};
+
+/// A render of the scene from `camera`, by default the view the window
+/// opens with, or one of its AOVs. Nothing is denoised.
+#[derive(Clone, Debug)]
+pub struct Headless {
+    pub size: PhysicalSize<u32>,
+    /// Samples per pixel.
+    pub samples: u32,
+    pub camera: Camera,
+    pub settings: TraceSettings,
+    pub aov: Aov,
+    /// Written every so often while the samples accumulate, and once
+    /// more at the end.
+    pub preview: Option<Preview>,
+    /// The only part of the image traced, see `tile`.
+    pub tile: Option<Tile>,
+}
+
+impl Headless {
+    /// Linear light with premultiplied alpha, as the tracer leaves it, or
+    /// the values of the AOV. Traces with `backend`, by default on the CPU when the only adapter
+    /// is a software one.
+    pub fn render(
+        &self,
+        mut scene: Scene,
+        backend: Option<Backend>,
+    ) -> Result<image::Rgba32FImage> {
+        let (adapter, device, queue) = thumbnail::request_device()?;
+        lod::select(
+            &mut scene,
+            self.camera.position,
+            self.camera.fov_y,
+            self.size.height,
+        );
+        let bvh = InstancedBvh::new(&scene);
+        let mut tracer: Box<dyn Renderer> = match self.tile {
+            // Only the CPU traces every pixel the same on every machine
+            Some(tile) => {
+                if backend == Some(Backend::Gpu) {
+                    bail!("Tiles are only traced on the CPU");
+                }
+                if self.aov != Aov::Beauty {
+                    bail!("Tiles of AOVs can't be rendered");
+                }
+                Box::new(CpuRenderer::tile(&device, &scene, &bvh, self.size, tile))
+            }
+            None => {
+                let backend = thumbnail::choose_backend(&adapter, backend);
+                let mut tracer = backend.create(&device, &queue, &scene, &bvh, self.size);
+                if backend == Backend::Gpu {
+                    tuning::tune(
+                        &device,
+                        &queue,
+                        &adapter.get_info(),
+                        &mut *tracer,
+                        &self.camera,
+                        &self.settings,
+                    );
+                }
+                tracer
+            }
+        };
+        let mut readbacks = Readbacks::default();
+        let mut last_preview = Instant::now();
+        while tracer.sample_count() < self.samples {
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Headless Encoder"),
+            });
+            tracer.render_frame(&device, &queue, &mut encoder, &self.camera, &self.settings);
+            queue.submit(std::iter::once(encoder.finish()));
+            // Frames traced in bands count once they're whole
+            let Some(sample) = tracer.sample_count().checked_sub(1) else {
+                continue;
+            };
+            if (sample + 1) % 64 == 0 {
+                tracing::info!("Rendered {} of {} samples", sample + 1, self.samples);
+            }
+            if let Some(preview) = &self.preview {
+                let done = sample + 1 == self.samples;
+                if done || last_preview.elapsed() >= preview.interval {
+                    // Dashboards are no reason to stop the render, or to
+                    // wait for the copy
+                    let preview = preview.clone();
+                    readbacks.read_texture(&device, tracer.output_texture(), 0, move |image| {
+                        if let Err(err) = preview.write(&image, sample + 1) {
+                            tracing::warn!("{err:#}");
+                        }
+                    });
+                    readbacks.submit(&queue);
+                    last_preview = Instant::now();
+                }
+            }
+            readbacks.poll(&device);
+        }
+        let image = Rc::new(RefCell::new(None));
+        let read = image.clone();
+        let done = move |layer| *read.borrow_mut() = Some(layer);
+        if self.aov == Aov::Beauty {
+            readbacks.read_texture(&device, tracer.output_texture(), 0, done);
+        } else {
+            let aovs = Aovs::new(&device, self.size);
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Headless Encoder"),
+            });
+            aovs.render(
+                &device,
+                &queue,
+                &mut encoder,
+                &*tracer,
+                &self.camera,
+                &self.camera,
+            );
+            queue.submit(std::iter::once(encoder.finish()));
+            aovs.read_one(&device, &mut readbacks, self.aov, done);
+        }
+        thumbnail::finish_readbacks(&device, &queue, &mut readbacks);
+        image
+            .take()
+            .context("Failed to read the image back from the GPU")
+    }
+}
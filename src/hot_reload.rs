@@ -0,0 +1,114 @@
+//! Rebuilds pipelines when their shaders in `src/wgsl` change, so shaders
+//! can be worked on without restarting and setting the scene up again. A
+//! shader that doesn't compile leaves the last good pipeline running.
+//!
+//! The trace, reproject and display shaders are reloaded, along with any
+//! file they include, from the checkout the binary was built from.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+};
+
+use anyhow::{Context, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::shader;
+
+pub const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/wgsl");
+
+pub struct ShaderWatcher {
+    /// Stops watching when dropped.
+    _watcher: RecommendedWatcher,
+    changes: Receiver<PathBuf>,
+}
+
+impl ShaderWatcher {
+    pub fn new() -> Result<Self> {
+        let (sender, changes) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                if event.kind.is_create() || event.kind.is_modify() {
+                    for path in event.paths {
+                        let _ = sender.send(path);
+                    }
+                }
+            })?;
+        watcher
+            .watch(Path::new(SHADER_DIR), RecursiveMode::NonRecursive)
+            .with_context(|| format!("Failed to watch {SHADER_DIR}"))?;
+        tracing::info!("Watching {SHADER_DIR} for shader changes");
+        Ok(Self {
+            _watcher: watcher,
+            changes,
+        })
+    }
+
+    /// Names of the shader files, such as `bsdf.wgsl`, changed since the
+    /// last call.
+    pub fn changed(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .changes
+            .try_iter()
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "wgsl")
+            })
+            .filter_map(|path| Some(path.file_name()?.to_string_lossy().into_owned()))
+            .collect();
+        // Editors tend to write a file in several steps
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+/// The shader file `name` put together from the files on disk, if it uses
+/// one of the `changed` ones.
+pub fn compose(name: &str, defines: &[(&str, String)], changed: &[String]) -> Option<String> {
+    let composed = shader::compose(name, defines, |file| {
+        let path = Path::new(SHADER_DIR).join(file);
+        std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))
+    });
+    match composed {
+        Ok(composed) => composed
+            .files
+            .iter()
+            .any(|file| changed.contains(file))
+            .then_some(composed.source),
+        Err(err) => {
+            // It put together before, so one of its files is what changed
+            tracing::error!("Keeping the last good {name}: {err:#}");
+            None
+        }
+    }
+}
+
+/// Builds something from a module of `source`, or logs why it couldn't
+/// instead of panicking like a built in shader would.
+pub fn compile<T>(
+    device: &wgpu::Device,
+    label: &str,
+    source: &str,
+    build: impl FnOnce(&wgpu::ShaderModule) -> T,
+) -> Option<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+    let built = build(&module);
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(err) => {
+            tracing::error!("Keeping the last good {label}: {err}");
+            None
+        }
+        None => {
+            tracing::info!("Reloaded the {label}");
+            Some(built)
+        }
+    }
+}
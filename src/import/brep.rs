@@ -0,0 +1,1475 @@
+//! Boundary representations, the way CAD files describe solids: faces cut
+//! out of surfaces by loops of edges. Each face is triangulated in the
+//! parameter space of its surface, with enough points inside that every
+//! triangle stays within the tolerance of the surface, while the edges
+//! between faces are sampled once so both sides share their points and
+//! the mesh closes up.
+
+use std::{
+    collections::HashMap,
+    f64::consts::{FRAC_PI_2, TAU},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use glam::{DMat4, DQuat, DVec2, DVec3, DVec4, Vec2, Vec3};
+use spade::{ConstrainedDelaunayTriangulation, Point2, Triangulation};
+
+use crate::{
+    scene::{Material, Mesh},
+    texture::decode_srgb,
+};
+
+/// Largest angle in radians a segment turns through, so small radii stay
+/// round however loose the tolerance is.
+const MAX_ANGLE: f64 = 0.25;
+/// Points inside a face at most, which gets coarser than the tolerance
+/// asks for beyond that.
+const MAX_INTERIOR_POINTS: f64 = 100_000.0;
+/// Highest B-spline degree evaluated, far above what CAD packages write.
+const MAX_DEGREE: usize = 31;
+
+/// Right handed coordinate frame of a placed curve or surface.
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    pub origin: DVec3,
+    pub x: DVec3,
+    pub y: DVec3,
+    pub z: DVec3,
+}
+
+impl Frame {
+    /// Frame with the given Z axis, and X as close to `x` as it can be
+    /// while perpendicular to it.
+    pub fn new(origin: DVec3, z: DVec3, x: Option<DVec3>) -> Self {
+        let z = z.normalize_or(DVec3::Z);
+        let x = x
+            .map(|x| x - z * x.dot(z))
+            .filter(|x| x.length_squared() > 1e-20)
+            .map_or_else(|| z.any_orthonormal_vector(), DVec3::normalize);
+        Self {
+            origin,
+            x,
+            y: z.cross(x),
+            z,
+        }
+    }
+
+    pub fn matrix(&self) -> DMat4 {
+        DMat4::from_cols(
+            self.x.extend(0.0),
+            self.y.extend(0.0),
+            self.z.extend(0.0),
+            self.origin.extend(1.0),
+        )
+    }
+
+    fn local(&self, p: DVec3) -> DVec3 {
+        let d = p - self.origin;
+        DVec3::new(d.dot(self.x), d.dot(self.y), d.dot(self.z))
+    }
+
+    fn world(&self, l: DVec3) -> DVec3 {
+        self.origin + self.x * l.x + self.y * l.y + self.z * l.z
+    }
+
+    /// The frame moved by a rigid transform, and how much it scales.
+    fn transformed(&self, m: &DMat4) -> (Self, f64) {
+        let scale = m.transform_vector3(self.x).length();
+        let frame = Self::new(
+            m.transform_point3(self.origin),
+            m.transform_vector3(self.z),
+            Some(m.transform_vector3(self.x)),
+        );
+        (frame, scale)
+    }
+}
+
+/// Non-uniform rational B-spline curve.
+#[derive(Clone, Debug)]
+pub struct NurbsCurve {
+    degree: usize,
+    knots: Vec<f64>,
+    /// Control points multiplied by their weights, with the weight in w.
+    points: Vec<DVec4>,
+}
+
+impl NurbsCurve {
+    pub fn new(
+        degree: usize,
+        knots: Vec<f64>,
+        points: &[DVec3],
+        weights: Option<&[f64]>,
+    ) -> Result<Self> {
+        Ok(Self {
+            degree,
+            points: weighted(degree, &knots, points, weights)?,
+            knots,
+        })
+    }
+
+    pub fn domain(&self) -> (f64, f64) {
+        (self.knots[self.degree], self.knots[self.points.len()])
+    }
+
+    fn point(&self, t: f64) -> DVec3 {
+        let h = de_boor(self.degree, &self.knots, |i| self.points[i], t);
+        h.truncate() / h.w
+    }
+}
+
+/// Non-uniform rational B-spline surface.
+#[derive(Clone, Debug)]
+pub struct NurbsSurface {
+    degrees: (usize, usize),
+    knots: (Vec<f64>, Vec<f64>),
+    /// Control points multiplied by their weights, with the weight in w,
+    /// in rows of constant u.
+    points: Vec<DVec4>,
+    count_v: usize,
+}
+
+impl NurbsSurface {
+    /// Surface through `points` in rows of constant u.
+    pub fn new(
+        degrees: (usize, usize),
+        knots: (Vec<f64>, Vec<f64>),
+        points: &[Vec<DVec3>],
+        weights: Option<&[Vec<f64>]>,
+    ) -> Result<Self> {
+        let count_v = points.first().map_or(0, Vec::len);
+        ensure!(
+            points.iter().all(|row| row.len() == count_v),
+            "B-spline surface with ragged control points"
+        );
+        if let Some(weights) = weights {
+            ensure!(
+                weights.len() == points.len() && weights.iter().all(|row| row.len() == count_v),
+                "B-spline surface with {} by {count_v} points but different weights",
+                points.len()
+            );
+        }
+        // Checks the knots against the points along both directions
+        let column = vec![DVec3::ZERO; points.len()];
+        weighted(degrees.0, &knots.0, &column, None)?;
+        weighted(degrees.1, &knots.1, &points[0], None)?;
+        let flat: Vec<DVec3> = points.concat();
+        let flat_weights = weights.map(<[Vec<f64>]>::concat);
+        let points = flat
+            .iter()
+            .zip(
+                flat_weights
+                    .iter()
+                    .flatten()
+                    .copied()
+                    .chain(std::iter::repeat(1.0)),
+            )
+            .map(|(p, w)| (*p * w).extend(w))
+            .collect();
+        Ok(Self {
+            degrees,
+            knots,
+            points,
+            count_v,
+        })
+    }
+
+    fn domain(&self) -> (DVec2, DVec2) {
+        let count_u = self.points.len() / self.count_v;
+        (
+            DVec2::new(self.knots.0[self.degrees.0], self.knots.1[self.degrees.1]),
+            DVec2::new(self.knots.0[count_u], self.knots.1[self.count_v]),
+        )
+    }
+
+    fn point(&self, uv: DVec2) -> DVec3 {
+        let h = de_boor(
+            self.degrees.0,
+            &self.knots.0,
+            |i| {
+                de_boor(
+                    self.degrees.1,
+                    &self.knots.1,
+                    |j| self.points[i * self.count_v + j],
+                    uv.y,
+                )
+            },
+            uv.x,
+        );
+        h.truncate() / h.w
+    }
+
+    fn transformed(&self, m: &DMat4) -> Self {
+        let points = self
+            .points
+            .iter()
+            .map(|h| (m.transform_point3(h.truncate() / h.w) * h.w).extend(h.w))
+            .collect();
+        Self {
+            points,
+            ..self.clone()
+        }
+    }
+}
+
+/// Checks a B-spline's knots against its points, and weighs the points.
+fn weighted(
+    degree: usize,
+    knots: &[f64],
+    points: &[DVec3],
+    weights: Option<&[f64]>,
+) -> Result<Vec<DVec4>> {
+    ensure!(
+        (1..=MAX_DEGREE).contains(&degree) && points.len() > degree,
+        "B-spline of degree {degree} with {} points",
+        points.len()
+    );
+    ensure!(
+        knots.len() == points.len() + degree + 1,
+        "B-spline of degree {degree} with {} points but {} knots",
+        points.len(),
+        knots.len()
+    );
+    ensure!(
+        knots.windows(2).all(|pair| pair[0] <= pair[1]) && knots[degree] < knots[points.len()],
+        "B-spline knots are out of order"
+    );
+    if let Some(weights) = weights {
+        ensure!(
+            weights.len() == points.len() && weights.iter().all(|&w| w > 0.0),
+            "B-spline weights don't match its points"
+        );
+    }
+    Ok(points
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let w = weights.map_or(1.0, |weights| weights[i]);
+            (*p * w).extend(w)
+        })
+        .collect())
+}
+
+/// Evaluates a B-spline with de Boor's algorithm at `t`, clamped to its
+/// domain, fetching only the control points it needs.
+fn de_boor(degree: usize, knots: &[f64], point: impl Fn(usize) -> DVec4, t: f64) -> DVec4 {
+    let count = knots.len() - degree - 1;
+    let t = t.clamp(knots[degree], knots[count]);
+    // The span holding t, the last non-empty one at the end of the domain
+    let span =
+        (degree + knots[degree + 1..count].partition_point(|&knot| knot <= t)).min(count - 1);
+    let mut d = [DVec4::ZERO; MAX_DEGREE + 1];
+    for (j, d) in d.iter_mut().enumerate().take(degree + 1) {
+        *d = point(j + span - degree);
+    }
+    for r in 1..=degree {
+        for j in (r..=degree).rev() {
+            let i = j + span - degree;
+            let denominator = knots[i + degree + 1 - r] - knots[i];
+            let alpha = if denominator > 0.0 {
+                (t - knots[i]) / denominator
+            } else {
+                0.0
+            };
+            d[j] = d[j - 1] * (1.0 - alpha) + d[j] * alpha;
+        }
+    }
+    d[degree]
+}
+
+/// Knot vector of knots repeated by their multiplicities.
+pub fn expand_knots(knots: &[f64], multiplicities: &[usize]) -> Result<Vec<f64>> {
+    ensure!(
+        knots.len() == multiplicities.len(),
+        "{} knots with {} multiplicities",
+        knots.len(),
+        multiplicities.len()
+    );
+    Ok(knots
+        .iter()
+        .zip(multiplicities)
+        .flat_map(|(&knot, &count)| std::iter::repeat_n(knot, count))
+        .collect())
+}
+
+#[derive(Clone, Debug)]
+pub enum Curve {
+    Line {
+        origin: DVec3,
+        direction: DVec3,
+    },
+    /// Ellipse around the frame's origin in its XY plane, starting on its X
+    /// axis, and a circle when both radii are the same.
+    Ellipse {
+        frame: Frame,
+        radii: DVec2,
+    },
+    Nurbs(NurbsCurve),
+    /// Straight segments through the points, parametrized by their index.
+    Polyline(Vec<DVec3>),
+}
+
+impl Curve {
+    pub fn point(&self, t: f64) -> DVec3 {
+        match self {
+            Self::Line { origin, direction } => *origin + *direction * t,
+            Self::Ellipse { frame, radii } => {
+                frame.world(DVec3::new(radii.x * t.cos(), radii.y * t.sin(), 0.0))
+            }
+            Self::Nurbs(nurbs) => nurbs.point(t),
+            Self::Polyline(points) => {
+                let last = points.len() - 1;
+                let t = t.clamp(0.0, last as f64);
+                let i = (t as usize).min(last.saturating_sub(1));
+                points[i].lerp(points[(i + 1).min(last)], t - i as f64)
+            }
+        }
+    }
+
+    /// Parameter range of the whole curve, or of a stretch of a line long
+    /// enough for any model.
+    pub fn domain(&self) -> (f64, f64) {
+        match self {
+            Self::Line { .. } => (-1e6, 1e6),
+            Self::Ellipse { .. } => (0.0, TAU),
+            Self::Nurbs(nurbs) => nurbs.domain(),
+            Self::Polyline(points) => (0.0, (points.len() - 1) as f64),
+        }
+    }
+
+    pub fn period(&self) -> Option<f64> {
+        matches!(self, Self::Ellipse { .. }).then_some(TAU)
+    }
+
+    /// Parameter of the point on the curve closest to `p`.
+    pub fn closest(&self, p: DVec3) -> f64 {
+        match self {
+            Self::Line { origin, direction } => {
+                (p - *origin).dot(*direction) / direction.length_squared().max(f64::MIN_POSITIVE)
+            }
+            Self::Ellipse { frame, radii } => {
+                let l = frame.local(p);
+                (l.y / radii.y).atan2(l.x / radii.x).rem_euclid(TAU)
+            }
+            Self::Nurbs(nurbs) => {
+                let (t0, t1) = nurbs.domain();
+                let samples = (nurbs.points.len() * 8).clamp(32, 4096);
+                minimize(|t| nurbs.point(t).distance_squared(p), t0, t1, samples)
+            }
+            Self::Polyline(points) => {
+                let samples = (points.len() - 1) * 16;
+                minimize(
+                    |t| self.point(t).distance_squared(p),
+                    0.0,
+                    (points.len() - 1) as f64,
+                    samples,
+                )
+            }
+        }
+    }
+
+    /// Parameters from `t0` to `t1`, either way, close enough that straight
+    /// segments between the points stay within `tolerance` of the curve.
+    pub fn sample(&self, t0: f64, t1: f64, tolerance: f64) -> Vec<f64> {
+        let segments = match self {
+            Self::Line { .. } => return vec![t0, t1],
+            Self::Polyline(_) => {
+                let mut ts = vec![t0];
+                let (lo, hi) = (t0.min(t1).floor() as i64 + 1, t0.max(t1).ceil() as i64);
+                let corners = (lo..hi).map(|i| i as f64);
+                if t1 > t0 {
+                    ts.extend(corners);
+                } else {
+                    ts.extend(corners.rev());
+                }
+                ts.push(t1);
+                return ts;
+            }
+            Self::Ellipse { .. } => ((t1 - t0).abs() / MAX_ANGLE).ceil() as usize,
+            Self::Nurbs(nurbs) => {
+                let (lo, hi) = nurbs.domain();
+                let fraction = (t1 - t0).abs() / (hi - lo);
+                (fraction * (nurbs.points.len() * 2) as f64).ceil() as usize
+            }
+        };
+        adaptive(&|t| self.point(t), t0, t1, segments.max(1), tolerance)
+    }
+
+    pub fn transformed(&self, m: &DMat4) -> Self {
+        match self {
+            Self::Line { origin, direction } => Self::Line {
+                origin: m.transform_point3(*origin),
+                direction: m.transform_vector3(*direction),
+            },
+            Self::Ellipse { frame, radii } => {
+                let (frame, scale) = frame.transformed(m);
+                Self::Ellipse {
+                    frame,
+                    radii: *radii * scale,
+                }
+            }
+            Self::Nurbs(nurbs) => Self::Nurbs(NurbsCurve {
+                points: nurbs
+                    .points
+                    .iter()
+                    .map(|h| (m.transform_point3(h.truncate() / h.w) * h.w).extend(h.w))
+                    .collect(),
+                ..nurbs.clone()
+            }),
+            Self::Polyline(points) => {
+                Self::Polyline(points.iter().map(|p| m.transform_point3(*p)).collect())
+            }
+        }
+    }
+}
+
+/// The parameter in [t0, t1] where `f` is smallest, from the best of
+/// evenly spaced samples refined by golden section search.
+fn minimize(f: impl Fn(f64) -> f64, t0: f64, t1: f64, samples: usize) -> f64 {
+    let step = (t1 - t0) / samples as f64;
+    let best = (0..=samples)
+        .map(|i| t0 + step * i as f64)
+        .min_by(|a, b| f(*a).total_cmp(&f(*b)))
+        .unwrap_or(t0);
+    let (mut a, mut b) = ((best - step).max(t0), (best + step).min(t1));
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    for _ in 0..64 {
+        let c = b - ratio * (b - a);
+        let d = a + ratio * (b - a);
+        if f(c) < f(d) {
+            b = d;
+        } else {
+            a = c;
+        }
+    }
+    (a + b) / 2.0
+}
+
+/// Splits [t0, t1] into `segments` and halves them until the curve turns
+/// less than `MAX_ANGLE` and strays no further than `tolerance` from each
+/// chord.
+fn adaptive(
+    f: &impl Fn(f64) -> DVec3,
+    t0: f64,
+    t1: f64,
+    segments: usize,
+    tolerance: f64,
+) -> Vec<f64> {
+    fn split(
+        f: &impl Fn(f64) -> DVec3,
+        (a, pa): (f64, DVec3),
+        (b, pb): (f64, DVec3),
+        tolerance: f64,
+        depth: u32,
+        ts: &mut Vec<f64>,
+    ) {
+        let middle = (a + b) / 2.0;
+        let pm = f(middle);
+        let chord = pb - pa;
+        let deviation = |p: DVec3| {
+            let d = p - pa;
+            let along = (d.dot(chord) / chord.length_squared()).clamp(0.0, 1.0);
+            if along.is_finite() {
+                d.distance(chord * along)
+            } else {
+                d.length()
+            }
+        };
+        let turn = (pm - pa).angle_between(pb - pm);
+        let stray = [0.25, 0.5, 0.75]
+            .iter()
+            .any(|&s| deviation(if s == 0.5 { pm } else { f(a + (b - a) * s) }) > tolerance);
+        if depth < 16 && (stray || turn > MAX_ANGLE) {
+            split(f, (a, pa), (middle, pm), tolerance, depth + 1, ts);
+            split(f, (middle, pm), (b, pb), tolerance, depth + 1, ts);
+        } else {
+            ts.push(b);
+        }
+    }
+    let mut ts = vec![t0];
+    let mut previous = (t0, f(t0));
+    for i in 1..=segments {
+        let t = t0 + (t1 - t0) * i as f64 / segments as f64;
+        let next = (t, f(t));
+        split(f, previous, next, tolerance, 0, &mut ts);
+        previous = next;
+    }
+    ts
+}
+
+#[derive(Clone, Debug)]
+pub enum Surface {
+    Plane(Frame),
+    Cylinder {
+        frame: Frame,
+        radius: f64,
+    },
+    /// Cone widening along the frame's Z axis from `radius` at its origin.
+    Cone {
+        frame: Frame,
+        radius: f64,
+        semi_angle: f64,
+    },
+    Sphere {
+        frame: Frame,
+        radius: f64,
+    },
+    Torus {
+        frame: Frame,
+        major: f64,
+        minor: f64,
+    },
+    Nurbs(NurbsSurface),
+    /// Curve swept around an axis, by the angle u through the curve's
+    /// parameter v.
+    Revolution {
+        curve: Box<Curve>,
+        origin: DVec3,
+        axis: DVec3,
+    },
+    /// Curve swept along a direction, by the curve's parameter u through
+    /// multiples v of the direction.
+    Extrusion {
+        curve: Box<Curve>,
+        direction: DVec3,
+    },
+}
+
+impl Surface {
+    pub fn point(&self, uv: DVec2) -> DVec3 {
+        let DVec2 { x: u, y: v } = uv;
+        match self {
+            Self::Plane(frame) => frame.world(DVec3::new(u, v, 0.0)),
+            Self::Cylinder { frame, radius } => {
+                frame.world(DVec3::new(radius * u.cos(), radius * u.sin(), v))
+            }
+            Self::Cone {
+                frame,
+                radius,
+                semi_angle,
+            } => {
+                let r = radius + v * semi_angle.tan();
+                frame.world(DVec3::new(r * u.cos(), r * u.sin(), v))
+            }
+            Self::Sphere { frame, radius } => {
+                let r = radius * v.cos();
+                frame.world(DVec3::new(r * u.cos(), r * u.sin(), radius * v.sin()))
+            }
+            Self::Torus {
+                frame,
+                major,
+                minor,
+            } => {
+                let r = major + minor * v.cos();
+                frame.world(DVec3::new(r * u.cos(), r * u.sin(), minor * v.sin()))
+            }
+            Self::Nurbs(nurbs) => nurbs.point(uv),
+            Self::Revolution {
+                curve,
+                origin,
+                axis,
+            } => *origin + DQuat::from_axis_angle(*axis, u) * (curve.point(v) - *origin),
+            Self::Extrusion { curve, direction } => curve.point(u) + *direction * v,
+        }
+    }
+
+    fn derivatives(&self, uv: DVec2) -> (DVec3, DVec3) {
+        let h = 1e-6 * (1.0 + uv.abs().max_element());
+        let du = (self.point(uv + DVec2::X * h) - self.point(uv - DVec2::X * h)) / (2.0 * h);
+        let dv = (self.point(uv + DVec2::Y * h) - self.point(uv - DVec2::Y * h)) / (2.0 * h);
+        (du, dv)
+    }
+
+    /// Unit normal along the cross product of the u and v directions, zero
+    /// where they're parallel, like at the poles of a sphere.
+    fn normal(&self, uv: DVec2) -> DVec3 {
+        let (du, dv) = self.derivatives(uv);
+        du.cross(dv).normalize_or_zero()
+    }
+
+    /// Periods of u and v, for the surfaces that close on themselves.
+    fn periods(&self, tolerance: f64) -> [Option<f64>; 2] {
+        match self {
+            Self::Plane(_) => [None, None],
+            Self::Cylinder { .. } | Self::Cone { .. } | Self::Sphere { .. } => [Some(TAU), None],
+            Self::Revolution { .. } => [Some(TAU), None],
+            Self::Torus { .. } => [Some(TAU), Some(TAU)],
+            Self::Extrusion { curve, .. } => {
+                let (t0, t1) = curve.domain();
+                let closed = curve.point(t0).distance(curve.point(t1)) < tolerance;
+                [closed.then_some(t1 - t0), None]
+            }
+            Self::Nurbs(nurbs) => {
+                let (lo, hi) = nurbs.domain();
+                // A B-spline closes if its opposite edges meet everywhere
+                let closed = |axis: usize| {
+                    (0..=8).all(|i| {
+                        let mut a = lo.lerp(hi, i as f64 / 8.0);
+                        let mut b = a;
+                        a[axis] = lo[axis];
+                        b[axis] = hi[axis];
+                        nurbs.point(a).distance(nurbs.point(b)) < tolerance
+                    })
+                };
+                [0, 1].map(|axis| closed(axis).then_some(hi[axis] - lo[axis]))
+            }
+        }
+    }
+
+    /// Parameters of the point on the surface closest to `p`, starting the
+    /// search from `hint` where the surface has no closed form.
+    fn project(&self, p: DVec3, hint: Option<DVec2>, tolerance: f64) -> DVec2 {
+        let angle = |l: DVec3| l.y.atan2(l.x);
+        match self {
+            Self::Plane(frame) => frame.local(p).truncate(),
+            Self::Cylinder { frame, .. } | Self::Cone { frame, .. } => {
+                let l = frame.local(p);
+                DVec2::new(angle(l), l.z)
+            }
+            Self::Sphere { frame, .. } => {
+                let l = frame.local(p);
+                DVec2::new(angle(l), l.z.atan2(l.truncate().length()))
+            }
+            Self::Torus { frame, major, .. } => {
+                let l = frame.local(p);
+                DVec2::new(angle(l), l.z.atan2(l.truncate().length() - major))
+            }
+            Self::Revolution {
+                curve,
+                origin,
+                axis,
+            } => {
+                // Matches the distance from and height along the axis to
+                // find v, then the angle around it to find u
+                let axis = axis.normalize();
+                let split = |q: DVec3| {
+                    let d = q - *origin;
+                    let height = d.dot(axis);
+                    (d - axis * height, height)
+                };
+                let (radial, height) = split(p);
+                let (t0, t1) = curve.domain();
+                let v = minimize(
+                    |t| {
+                        let (r, h) = split(curve.point(t));
+                        DVec2::new(r.length() - radial.length(), h - height).length_squared()
+                    },
+                    t0,
+                    t1,
+                    1024,
+                );
+                let (r, _) = split(curve.point(v));
+                let u = r.cross(radial).dot(axis).atan2(r.dot(radial));
+                DVec2::new(u, v)
+            }
+            Self::Extrusion { curve, direction } => {
+                let off_line = |t: f64| {
+                    let d = p - curve.point(t);
+                    (d - *direction * d.dot(*direction) / direction.length_squared())
+                        .length_squared()
+                };
+                let (t0, t1) = curve.domain();
+                let u = minimize(off_line, t0, t1, 1024);
+                let v = (p - curve.point(u)).dot(*direction) / direction.length_squared();
+                DVec2::new(u, v)
+            }
+            Self::Nurbs(nurbs) => {
+                let (lo, hi) = nurbs.domain();
+                let refine = |start: DVec2| self.newton(p, start, lo, hi);
+                if let Some(uv) = hint.map(refine) {
+                    if self.point(uv).distance(p) < tolerance * 0.1 {
+                        return uv;
+                    }
+                }
+                let steps = 24;
+                let start = (0..=steps)
+                    .flat_map(|i| {
+                        (0..=steps).map(move |j| DVec2::new(i as f64, j as f64) / steps as f64)
+                    })
+                    .map(|f| lo + (hi - lo) * f)
+                    .min_by(|a, b| {
+                        let da = self.point(*a).distance_squared(p);
+                        let db = self.point(*b).distance_squared(p);
+                        da.total_cmp(&db)
+                    })
+                    .unwrap();
+                refine(start)
+            }
+        }
+    }
+
+    /// Gauss-Newton iterations towards the parameters closest to `p`.
+    fn newton(&self, p: DVec3, mut uv: DVec2, lo: DVec2, hi: DVec2) -> DVec2 {
+        for _ in 0..24 {
+            let (du, dv) = self.derivatives(uv);
+            let r = self.point(uv) - p;
+            let (a, b, c) = (du.dot(du), du.dot(dv), dv.dot(dv));
+            let g = DVec2::new(du.dot(r), dv.dot(r));
+            let determinant = a * c - b * b;
+            if determinant.abs() < 1e-30 {
+                break;
+            }
+            let step = DVec2::new(c * g.x - b * g.y, a * g.y - b * g.x) / determinant;
+            uv = (uv - step).clamp(lo, hi);
+            if step.abs().max_element() < 1e-12 * (hi - lo).max_element() {
+                break;
+            }
+        }
+        uv
+    }
+
+    /// Distance of `p` from the axis of surfaces that pinch to a point on
+    /// it, where u means nothing.
+    fn axis_distance(&self, p: DVec3) -> Option<f64> {
+        match self {
+            Self::Cone { frame, .. } | Self::Sphere { frame, .. } => {
+                Some(frame.local(p).truncate().length())
+            }
+            Self::Revolution { origin, axis, .. } => {
+                let d = p - *origin;
+                Some(d.reject_from(*axis).length())
+            }
+            _ => None,
+        }
+    }
+
+    /// v of the point a surface pinches to on the `side` of `v`, for faces
+    /// bounded by a single loop around it.
+    fn pole(&self, v: f64, side: f64) -> Option<f64> {
+        let pole = match self {
+            Self::Sphere { .. } => side * FRAC_PI_2,
+            Self::Cone {
+                radius, semi_angle, ..
+            } => -radius / semi_angle.tan(),
+            _ => return None,
+        };
+        (pole.is_finite() && (pole - v) * side > 0.0).then_some(pole)
+    }
+
+    pub fn transformed(&self, m: &DMat4) -> Self {
+        let frame = |frame: &Frame| frame.transformed(m);
+        match self {
+            Self::Plane(f) => Self::Plane(frame(f).0),
+            Self::Cylinder { frame: f, radius } => {
+                let (frame, scale) = frame(f);
+                Self::Cylinder {
+                    frame,
+                    radius: radius * scale,
+                }
+            }
+            Self::Cone {
+                frame: f,
+                radius,
+                semi_angle,
+            } => {
+                let (frame, scale) = frame(f);
+                Self::Cone {
+                    frame,
+                    radius: radius * scale,
+                    semi_angle: *semi_angle,
+                }
+            }
+            Self::Sphere { frame: f, radius } => {
+                let (frame, scale) = frame(f);
+                Self::Sphere {
+                    frame,
+                    radius: radius * scale,
+                }
+            }
+            Self::Torus {
+                frame: f,
+                major,
+                minor,
+            } => {
+                let (frame, scale) = frame(f);
+                Self::Torus {
+                    frame,
+                    major: major * scale,
+                    minor: minor * scale,
+                }
+            }
+            Self::Nurbs(nurbs) => Self::Nurbs(nurbs.transformed(m)),
+            Self::Revolution {
+                curve,
+                origin,
+                axis,
+            } => Self::Revolution {
+                curve: Box::new(curve.transformed(m)),
+                origin: m.transform_point3(*origin),
+                axis: m.transform_vector3(*axis).normalize(),
+            },
+            Self::Extrusion { curve, direction } => Self::Extrusion {
+                curve: Box::new(curve.transformed(m)),
+                direction: m.transform_vector3(*direction),
+            },
+        }
+    }
+}
+
+/// Point on the boundary of a face, with its surface parameters when the
+/// file gives them.
+#[derive(Clone, Copy, Debug)]
+pub struct BoundaryPoint {
+    pub position: DVec3,
+    pub uv: Option<DVec2>,
+}
+
+/// Region of a surface inside its outer loop and outside the others.
+#[derive(Clone, Debug)]
+pub struct Face {
+    pub surface: Surface,
+    /// Closed loops, the last point of each joining back to its first.
+    /// Faces lie on their left seen from outside.
+    pub loops: Vec<Vec<BoundaryPoint>>,
+    /// Whether the outside is along the surface normal.
+    pub same_sense: bool,
+}
+
+/// Points of an edge from `t0` to `t1`, starting and ending exactly on the
+/// vertices so the edges meeting there share them.
+pub fn sample_edge(
+    curve: &Curve,
+    t0: f64,
+    t1: f64,
+    ends: [DVec3; 2],
+    tolerance: f64,
+) -> Vec<DVec3> {
+    let ts = curve.sample(t0, t1, tolerance);
+    let mut points: Vec<DVec3> = ts.iter().map(|&t| curve.point(t)).collect();
+    points[0] = ends[0];
+    *points.last_mut().unwrap() = ends[1];
+    points
+}
+
+/// Points of a curve in a surface's parameter space from `t0` to `t1`.
+pub fn sample_uv_curve(
+    surface: &Surface,
+    curve: &Curve,
+    t0: f64,
+    t1: f64,
+    tolerance: f64,
+) -> Vec<BoundaryPoint> {
+    let on_surface = |t: f64| surface.point(curve.point(t).truncate());
+    let segments = match curve {
+        Curve::Line { .. } => 8,
+        Curve::Polyline(points) => points.len() - 1,
+        _ => ((t1 - t0).abs() / MAX_ANGLE).ceil() as usize,
+    };
+    adaptive(&on_surface, t0, t1, segments.max(1), tolerance)
+        .into_iter()
+        .map(|t| {
+            let uv = curve.point(t).truncate();
+            BoundaryPoint {
+                position: surface.point(uv),
+                uv: Some(uv),
+            }
+        })
+        .collect()
+}
+
+/// Loop of points in parameter space with their positions.
+type Polygon = Vec<(DVec2, DVec3)>;
+
+/// Appends the triangles of `face` to `mesh`, in the face's outward
+/// winding, with normals from its surface.
+pub fn tessellate(face: &Face, tolerance: f64, mesh: &mut Mesh) -> Result<()> {
+    let surface = &face.surface;
+    let periods = surface.periods(tolerance);
+    let mut wrapping = Vec::new();
+    let mut closed = Vec::new();
+    for points in face.loops.iter().filter(|points| points.len() > 1) {
+        let (polygon, winding) = unwrap(surface, points, periods, tolerance)?;
+        if winding == 0 {
+            closed.push(polygon);
+        } else {
+            wrapping.push((polygon, winding));
+        }
+    }
+    ensure!(
+        !closed.is_empty() || !wrapping.is_empty(),
+        "Face has no boundary"
+    );
+
+    // Loops that double back along a seam, like a full cylinder with a
+    // single edge up its side, stand for the whole period around
+    if let Some(period) = periods[0] {
+        for polygon in &mut closed {
+            if is_seam(polygon, tolerance) {
+                *polygon = seam_rectangle(surface, polygon, period, tolerance);
+            }
+        }
+    }
+
+    let mut polygons = Vec::new();
+    if let Some(period) = periods[0].filter(|_| !wrapping.is_empty()) {
+        let (band, start) = around(surface, wrapping, period, face.same_sense, tolerance)?;
+        polygons.push(band);
+        // Holes go where they fall within the band
+        for mut polygon in closed {
+            let mean = polygon.iter().map(|(uv, _)| uv.x).sum::<f64>() / polygon.len() as f64;
+            let shift = ((mean - start) / period).floor() * period;
+            polygon.iter_mut().for_each(|(uv, _)| uv.x -= shift);
+            polygons.push(polygon);
+        }
+    } else {
+        ensure!(
+            wrapping.is_empty(),
+            "Face wraps around a surface that doesn't close"
+        );
+        // Holes go where they're nearest the outer loop
+        let center = |polygon: &Polygon| {
+            polygon.iter().map(|(uv, _)| *uv).sum::<DVec2>() / polygon.len() as f64
+        };
+        let outer = center(&closed[0]);
+        for mut polygon in closed {
+            let offset = center(&polygon) - outer;
+            for (axis, period) in periods.iter().enumerate() {
+                if let Some(period) = period {
+                    let shift = (offset[axis] / period).round() * period;
+                    polygon.iter_mut().for_each(|(uv, _)| uv[axis] -= shift);
+                }
+            }
+            polygons.push(polygon);
+        }
+    }
+    triangulate(surface, &polygons, face.same_sense, tolerance, mesh)
+}
+
+/// A loop in parameter space made continuous across the seams of a closed
+/// surface, and the number of times it winds around u.
+fn unwrap(
+    surface: &Surface,
+    points: &[BoundaryPoint],
+    periods: [Option<f64>; 2],
+    tolerance: f64,
+) -> Result<(Polygon, i64)> {
+    // Points where u means nothing get the u of their neighbours later
+    let mut hint = None;
+    let mut uvs: Vec<Option<DVec2>> = points
+        .iter()
+        .map(|point| {
+            if let Some(uv) = point.uv {
+                return Some(uv);
+            }
+            let singular = surface
+                .axis_distance(point.position)
+                .is_some_and(|distance| distance < tolerance * 0.01);
+            let uv = surface.project(point.position, hint, tolerance);
+            hint = Some(uv);
+            (!singular).then_some(uv)
+        })
+        .collect();
+    ensure!(
+        uvs.iter().any(Option::is_some),
+        "Face boundary is a single point"
+    );
+
+    let mut windings = [0; 2];
+    if points.iter().all(|point| point.uv.is_none()) {
+        for (axis, period) in periods.iter().enumerate() {
+            let Some(period) = *period else {
+                continue;
+            };
+            let mut previous: Option<f64> = None;
+            for uv in uvs.iter_mut().flatten() {
+                if let Some(previous) = previous {
+                    uv[axis] += ((previous - uv[axis]) / period).round() * period;
+                }
+                previous = Some(uv[axis]);
+            }
+            let mut known = uvs.iter().flatten();
+            let first = known.next().unwrap()[axis];
+            let last = known.last().map_or(first, |uv| uv[axis]);
+            windings[axis] = ((last - first) / period).round() as i64;
+        }
+    }
+    ensure!(
+        windings[1] == 0,
+        "Face wraps around the v direction of its surface"
+    );
+
+    // A loop through a pole closes by cutting it there
+    let mut winding = windings[0];
+    if let (Some(period), Some(pole)) = (periods[0], uvs.iter().position(Option::is_none)) {
+        if winding != 0 {
+            for uv in uvs[pole..].iter_mut().flatten() {
+                uv.x -= winding as f64 * period;
+            }
+            winding = 0;
+        }
+    }
+
+    let count = points.len();
+    let known_u = |start: usize, step: usize| {
+        (1..count)
+            .map(|i| (start + i * step) % count)
+            .find_map(|i| uvs[i])
+            .map(|uv| uv.x)
+    };
+    let mut polygon = Vec::with_capacity(count + 2);
+    for (i, point) in points.iter().enumerate() {
+        if let Some(uv) = uvs[i] {
+            polygon.push((uv, point.position));
+            continue;
+        }
+        // Both the u it arrives from and the one it leaves along
+        let v = surface.project(point.position, None, tolerance).y;
+        let before = known_u(i, count - 1).unwrap_or(0.0);
+        let after = known_u(i, 1).unwrap_or(before);
+        polygon.push((DVec2::new(before, v), point.position));
+        if (after - before).abs() > 1e-9 {
+            polygon.push((DVec2::new(after, v), point.position));
+        }
+    }
+    Ok((polygon, winding))
+}
+
+/// Whether a loop encloses nothing, just running up a seam and back.
+fn is_seam(polygon: &Polygon, tolerance: f64) -> bool {
+    let area = signed_area(polygon).abs();
+    let (lo, hi) = bounds(std::slice::from_ref(polygon));
+    let extent = hi - lo;
+    area <= 1e-6 * extent.x.max(tolerance) * extent.y.max(tolerance)
+}
+
+fn signed_area(polygon: &Polygon) -> f64 {
+    let mut area = 0.0;
+    for (i, (a, _)) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()].0;
+        area += a.perp_dot(b);
+    }
+    area / 2.0
+}
+
+fn bounds(polygons: &[Polygon]) -> (DVec2, DVec2) {
+    polygons
+        .iter()
+        .flatten()
+        .fold((DVec2::MAX, DVec2::MIN), |(lo, hi), (uv, _)| {
+            (lo.min(*uv), hi.max(*uv))
+        })
+}
+
+/// The whole period of u beside a seam, with the seam's points on both of
+/// its sides.
+fn seam_rectangle(surface: &Surface, seam: &Polygon, period: f64, tolerance: f64) -> Polygon {
+    let u = seam.iter().map(|(uv, _)| uv.x).sum::<f64>() / seam.len() as f64;
+    let mut side: Polygon = seam
+        .iter()
+        .map(|(uv, p)| (DVec2::new(u, uv.y), *p))
+        .collect();
+    side.sort_by(|a, b| a.0.y.total_cmp(&b.0.y));
+    side.dedup_by(|a, b| (a.0.y - b.0.y).abs() < 1e-12);
+    let (bottom, top) = (side[0].0.y, side[side.len() - 1].0.y);
+    let mut polygon = Vec::new();
+    polygon.extend(along_u(surface, u, u + period, bottom, tolerance));
+    polygon.extend(side.iter().map(|(uv, p)| (*uv + DVec2::X * period, *p)));
+    polygon.extend(along_u(surface, u + period, u, top, tolerance));
+    polygon.extend(side.iter().rev().copied());
+    polygon.dedup_by(|a, b| a.0.distance(b.0) < 1e-12);
+    polygon
+}
+
+/// Points along constant v from `u0` to `u1`, without the last.
+fn along_u(surface: &Surface, u0: f64, u1: f64, v: f64, tolerance: f64) -> Polygon {
+    let segments = ((u1 - u0).abs() / MAX_ANGLE).ceil() as usize;
+    let mut ts = adaptive(
+        &|u| surface.point(DVec2::new(u, v)),
+        u0,
+        u1,
+        segments.max(1),
+        tolerance,
+    );
+    ts.pop();
+    ts.into_iter()
+        .map(|u| {
+            let uv = DVec2::new(u, v);
+            (uv, surface.point(uv))
+        })
+        .collect()
+}
+
+/// Points along constant u from `v0` to `v1`, without either end.
+fn along_v(surface: &Surface, u: f64, v0: f64, v1: f64, tolerance: f64) -> Polygon {
+    let segments = ((v1 - v0).abs() / MAX_ANGLE).ceil() as usize;
+    let mut ts = adaptive(
+        &|v| surface.point(DVec2::new(u, v)),
+        v0,
+        v1,
+        segments.max(1),
+        tolerance,
+    );
+    ts.pop();
+    ts.into_iter()
+        .skip(1)
+        .map(|v| {
+            let uv = DVec2::new(u, v);
+            (uv, surface.point(uv))
+        })
+        .collect()
+}
+
+/// The part of a closed surface between two loops around it, or between
+/// one loop and the pole on the face's side of it, cut open along u =
+/// `start` so it lies flat.
+fn around(
+    surface: &Surface,
+    mut wrapping: Vec<(Polygon, i64)>,
+    period: f64,
+    same_sense: bool,
+    tolerance: f64,
+) -> Result<(Polygon, f64)> {
+    ensure!(
+        wrapping.len() <= 2 && wrapping.iter().all(|(_, winding)| winding.abs() == 1),
+        "Face wraps around its surface more than once"
+    );
+    let (first, winding) = wrapping.remove(0);
+    let start = first[0].0.x;
+    let cut = |polygon, winding| {
+        open(polygon, winding, start, period).context("Face boundary doesn't wrap around")
+    };
+    let mut lower = cut(first, winding)?;
+    if winding < 0 {
+        lower.reverse();
+    }
+    let polygon = match wrapping.pop() {
+        Some((second, winding)) => {
+            let mut upper = cut(second, winding)?;
+            if winding > 0 {
+                upper.reverse();
+            }
+            let end = |polygon: &Polygon| polygon[polygon.len() - 1].0.y;
+            let mut polygon = lower.clone();
+            polygon.extend(along_v(
+                surface,
+                start + period,
+                end(&lower),
+                upper[0].0.y,
+                tolerance,
+            ));
+            polygon.extend(upper.iter().copied());
+            polygon.extend(along_v(
+                surface,
+                start,
+                end(&upper),
+                lower[0].0.y,
+                tolerance,
+            ));
+            polygon
+        }
+        None => {
+            // The face lies on its left, which is towards +v seen from the
+            // side the surface normal points to
+            let v = lower[0].0.y;
+            let side = if (winding > 0) == same_sense {
+                1.0
+            } else {
+                -1.0
+            };
+            let Some(pole) = surface.pole(v, side) else {
+                bail!("Face wraps around a surface without closing it off");
+            };
+            let end = lower[lower.len() - 1].0.y;
+            let mut polygon = lower.clone();
+            polygon.extend(along_v(surface, start + period, end, pole, tolerance));
+            polygon.extend(along_u(surface, start + period, start, pole, tolerance));
+            polygon.push((
+                DVec2::new(start, pole),
+                surface.point(DVec2::new(start, pole)),
+            ));
+            polygon.extend(along_v(surface, start, pole, v, tolerance));
+            polygon
+        }
+    };
+    Ok((polygon, start))
+}
+
+/// A loop winding once around u, cut where it crosses u = `start` into
+/// a path running a whole period from there, upwards if `winding` is.
+fn open(polygon: Polygon, winding: i64, start: f64, period: f64) -> Option<Polygon> {
+    let count = polygon.len();
+    let direction = winding.signum() as f64;
+    // The closing segment continues from the last point to the first one
+    // a period along
+    let point = |i: usize| {
+        let (uv, p) = polygon[i % count];
+        let shift = (i / count) as f64 * period * direction;
+        (uv + DVec2::X * shift, p)
+    };
+    let turn = |x: f64| ((x - start) / period).floor();
+    let offset = (polygon[0].0.x - start).rem_euclid(period);
+    let mut path: Polygon = if offset.min(period - offset) < 1e-9 * period {
+        (0..=count).map(point).collect()
+    } else {
+        let i = (0..count).find(|&i| turn(point(i).0.x) != turn(point(i + 1).0.x))?;
+        let ((a, pa), (b, pb)) = (point(i), point(i + 1));
+        let line = start + turn(a.x.max(b.x)) * period;
+        let s = (line - a.x) / (b.x - a.x);
+        let cut = (a.lerp(b, s), pa.lerp(pb, s));
+        let mut path = vec![cut];
+        path.extend((i + 1..=i + count).map(point));
+        path.push((cut.0 + DVec2::X * period * direction, cut.1));
+        path
+    };
+    let shift = start - path[0].0.x + if direction < 0.0 { period } else { 0.0 };
+    path.iter_mut().for_each(|(uv, _)| uv.x += shift);
+    Some(path)
+}
+
+/// Constrained Delaunay triangulation of the polygons in parameter space,
+/// keeping the triangles inside an odd number of them.
+fn triangulate(
+    surface: &Surface,
+    polygons: &[Polygon],
+    same_sense: bool,
+    tolerance: f64,
+    mesh: &mut Mesh,
+) -> Result<()> {
+    let (lo, hi) = bounds(polygons);
+    let extent = hi - lo;
+    ensure!(extent.is_finite(), "Face boundary is not finite");
+
+    // Parameters are scaled to roughly match lengths on the surface, so
+    // the triangles come out well shaped there too
+    let samples = 6;
+    let mut speed = DVec2::ZERO;
+    let mut bending = DVec2::ZERO;
+    // Stencils stay inside the domain, where B-spline evaluation would clamp
+    for i in 0..samples {
+        for j in 0..samples {
+            let uv = lo + extent * (DVec2::new(i as f64, j as f64) + 0.5) / samples as f64;
+            let p = surface.point(uv);
+            for axis in 0..2 {
+                let h = extent[axis] / (4 * samples) as f64;
+                if h <= 0.0 {
+                    continue;
+                }
+                let mut offset = DVec2::ZERO;
+                offset[axis] = h;
+                let (a, b) = (surface.point(uv - offset), surface.point(uv + offset));
+                speed[axis] += (b - a).length() / (2.0 * h);
+                bending[axis] = bending[axis].max((a + b - 2.0 * p).length() / (h * h));
+            }
+        }
+    }
+    let scale = (speed / (samples * samples) as f64).max(DVec2::splat(1e-12));
+
+    // Spacing of the points inside, from the chord error along each
+    // direction and the turn between neighbouring points
+    let mut spacing = DVec2::INFINITY;
+    for axis in 0..2 {
+        let curved = bending[axis] * extent[axis] * extent[axis] / 8.0 > tolerance;
+        if curved {
+            let step = (8.0 * tolerance / bending[axis])
+                .sqrt()
+                .min(MAX_ANGLE * scale[axis] / bending[axis]);
+            spacing[axis] = step * scale[axis];
+        }
+    }
+    let interior = if spacing.min_element().is_finite() {
+        let spacing = DVec2::splat(spacing.min_element());
+        let size = extent * scale;
+        let mut counts = (size / spacing).ceil().max(DVec2::ONE);
+        let total = counts.x * counts.y;
+        if total > MAX_INTERIOR_POINTS {
+            counts = (counts / (total / MAX_INTERIOR_POINTS).sqrt()).ceil();
+        }
+        grid(polygons, lo, hi, scale, counts)
+    } else {
+        Vec::new()
+    };
+
+    let mut cdt = ConstrainedDelaunayTriangulation::<Point2<f64>>::new();
+    let mut positions = HashMap::new();
+    let to_point = |uv: DVec2| {
+        let scaled = (uv - lo) * scale;
+        Point2::new(scaled.x, scaled.y)
+    };
+    for polygon in polygons {
+        let mut handles = Vec::with_capacity(polygon.len());
+        for (uv, position) in polygon {
+            let handle = cdt.insert(to_point(*uv))?;
+            positions.insert(handle.index(), *position);
+            handles.push(handle);
+        }
+        for (i, &from) in handles.iter().enumerate() {
+            let to = handles[(i + 1) % handles.len()];
+            if from != to {
+                cdt.add_constraint_and_split(from, to, |p| p);
+            }
+        }
+    }
+    for uv in interior {
+        cdt.insert(to_point(uv))?;
+    }
+
+    // Walks out from the outside, flipping between inside and out across
+    // each constraint edge
+    let mut inside = vec![None; cdt.num_all_faces()];
+    let outer = cdt.outer_face();
+    inside[outer.fix().index()] = Some(false);
+    let mut stack = vec![outer];
+    while let Some(face) = stack.pop() {
+        let parity = inside[face.fix().index()].unwrap();
+        let Some(start) = face.adjacent_edge() else {
+            continue;
+        };
+        let mut edge = start;
+        loop {
+            let neighbour = edge.rev().face();
+            let slot = &mut inside[neighbour.fix().index()];
+            if slot.is_none() {
+                *slot = Some(parity ^ edge.as_undirected().is_constraint_edge());
+                stack.push(neighbour);
+            }
+            edge = edge.next();
+            if edge == start {
+                break;
+            }
+        }
+    }
+
+    let base = mesh.positions.len() as u32;
+    let sign = if same_sense { 1.0 } else { -1.0 };
+    for vertex in cdt.vertices() {
+        let p = vertex.position();
+        let uv = DVec2::new(p.x, p.y) / scale + lo;
+        let position = positions
+            .get(&vertex.fix().index())
+            .copied()
+            .unwrap_or_else(|| surface.point(uv));
+        mesh.positions.push(position.as_vec3());
+        mesh.normals.push((surface.normal(uv) * sign).as_vec3());
+        mesh.uvs.push(Vec2::ZERO);
+    }
+    for face in cdt.inner_faces() {
+        if inside[face.fix().index()] != Some(true) {
+            continue;
+        }
+        let [a, b, c] = face.vertices().map(|v| base + v.fix().index() as u32);
+        if same_sense {
+            mesh.indices.extend([a, b, c]);
+        } else {
+            mesh.indices.extend([a, c, b]);
+        }
+    }
+    Ok(())
+}
+
+/// Grid points inside the polygons by the even-odd rule, away from their
+/// edges so the triangles there don't turn into slivers.
+fn grid(polygons: &[Polygon], lo: DVec2, hi: DVec2, scale: DVec2, counts: DVec2) -> Vec<DVec2> {
+    let step = (hi - lo) / counts;
+    let clearance = (step * scale).min_element() * 0.4;
+    let segments: Vec<(DVec2, DVec2)> = polygons
+        .iter()
+        .flat_map(|polygon| {
+            (0..polygon.len()).map(|i| {
+                let a = polygon[i].0;
+                let b = polygon[(i + 1) % polygon.len()].0;
+                ((a - lo) * scale, (b - lo) * scale)
+            })
+        })
+        .collect();
+    // Buckets of nearby segments, to keep clear of them
+    let cell = clearance.max(1e-12);
+    let key = |p: DVec2| ((p.x / cell).floor() as i64, (p.y / cell).floor() as i64);
+    let mut buckets: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (index, (a, b)) in segments.iter().enumerate() {
+        let (from, to) = (key(a.min(*b)), key(a.max(*b)));
+        if (to.0 - from.0 + 1) * (to.1 - from.1 + 1) > 4096 {
+            continue;
+        }
+        for x in from.0..=to.0 {
+            for y in from.1..=to.1 {
+                buckets.entry((x, y)).or_default().push(index);
+            }
+        }
+    }
+    let near_edge = |p: DVec2| {
+        let (x, y) = key(p);
+        (x - 1..=x + 1).any(|x| {
+            (y - 1..=y + 1).any(|y| {
+                buckets.get(&(x, y)).is_some_and(|indices| {
+                    indices.iter().any(|&i| {
+                        let (a, b) = segments[i];
+                        let ab = b - a;
+                        let t = ((p - a).dot(ab) / ab.length_squared()).clamp(0.0, 1.0);
+                        let t = if t.is_finite() { t } else { 0.0 };
+                        p.distance(a + ab * t) < clearance
+                    })
+                })
+            })
+        })
+    };
+
+    let mut points = Vec::new();
+    for j in 1..counts.y as usize {
+        let v = lo.y + step.y * j as f64;
+        // Where the row crosses the boundary, sorted along it
+        let mut crossings: Vec<f64> = polygons
+            .iter()
+            .flat_map(|polygon| {
+                (0..polygon.len()).filter_map(move |i| {
+                    let a = polygon[i].0;
+                    let b = polygon[(i + 1) % polygon.len()].0;
+                    ((a.y <= v) != (b.y <= v)).then(|| a.x + (v - a.y) / (b.y - a.y) * (b.x - a.x))
+                })
+            })
+            .collect();
+        crossings.sort_by(f64::total_cmp);
+        for span in crossings.chunks_exact(2) {
+            let first = ((span[0] - lo.x) / step.x).floor() as usize + 1;
+            for i in first..counts.x as usize {
+                let u = lo.x + step.x * i as f64;
+                if u >= span[1] {
+                    break;
+                }
+                let uv = DVec2::new(u, v);
+                if !near_edge((uv - lo) * scale) {
+                    points.push(uv);
+                }
+            }
+        }
+    }
+    points
+}
+
+/// Gives the vertices no surface normal was found for, like those at a
+/// pole, the average normal of their triangles.
+pub fn fill_normals(mesh: &mut Mesh) {
+    let mut missing: HashMap<u32, Vec3> = mesh
+        .normals
+        .iter()
+        .enumerate()
+        .filter(|(_, n)| !n.is_finite() || n.length_squared() < 0.5)
+        .map(|(i, _)| (i as u32, Vec3::ZERO))
+        .collect();
+    if missing.is_empty() {
+        return;
+    }
+    for triangle in mesh.indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| mesh.positions[triangle[i] as usize]);
+        let normal = (b - a).cross(c - a);
+        for index in triangle {
+            if let Some(sum) = missing.get_mut(index) {
+                *sum += normal;
+            }
+        }
+    }
+    for (index, sum) in missing {
+        mesh.normals[index as usize] = sum.normalize_or(Vec3::Y);
+    }
+}
+
+/// Material of a colour picked in a CAD package, which shows them as
+/// display sRGB.
+pub fn material(color: Vec3) -> Material {
+    let [r, g, b] = color
+        .to_array()
+        .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+    Material {
+        name: format!("#{r:02x}{g:02x}{b:02x}"),
+        base_color: color.map(decode_srgb).extend(1.0),
+        ..Default::default()
+    }
+}
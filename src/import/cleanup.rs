@@ -0,0 +1,168 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    ops::AddAssign,
+};
+
+use glam::{IVec3, Vec3};
+
+use crate::scene::Mesh;
+
+#[derive(Clone, Copy, Debug)]
+pub struct CleanupOptions {
+    /// Weld distance as a fraction of the mesh bounding box diagonal.
+    pub position_epsilon: f32,
+    /// Minimum cosine between two normals for them to be welded.
+    pub normal_cos: f32,
+    pub uv_epsilon: f32,
+}
+
+impl Default for CleanupOptions {
+    fn default() -> Self {
+        Self {
+            position_epsilon: 1e-5,
+            normal_cos: 0.999,
+            uv_epsilon: 1e-4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CleanupReport {
+    pub vertices_before: usize,
+    pub vertices_after: usize,
+    /// Vertices merged into a nearby vertex with matching attributes.
+    pub welded_vertices: usize,
+    /// Vertices moved onto a nearby vertex that differs only in attributes,
+    /// closing cracks along normal and UV seams.
+    pub snapped_vertices: usize,
+    pub degenerate_triangles: usize,
+    pub duplicate_triangles: usize,
+    /// Edges still shared by more than two triangles after cleanup.
+    pub non_manifold_edges: usize,
+}
+
+impl AddAssign for CleanupReport {
+    fn add_assign(&mut self, other: Self) {
+        self.vertices_before += other.vertices_before;
+        self.vertices_after += other.vertices_after;
+        self.welded_vertices += other.welded_vertices;
+        self.snapped_vertices += other.snapped_vertices;
+        self.degenerate_triangles += other.degenerate_triangles;
+        self.duplicate_triangles += other.duplicate_triangles;
+        self.non_manifold_edges += other.non_manifold_edges;
+    }
+}
+
+impl fmt::Display for CleanupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {} vertices ({} welded, {} snapped), removed {} degenerate and {} duplicate triangles, {} non-manifold edges",
+            self.vertices_before,
+            self.vertices_after,
+            self.welded_vertices,
+            self.snapped_vertices,
+            self.degenerate_triangles,
+            self.duplicate_triangles,
+            self.non_manifold_edges,
+        )
+    }
+}
+
+pub fn cleanup(mesh: &mut Mesh, options: &CleanupOptions) -> CleanupReport {
+    let mut report = CleanupReport {
+        vertices_before: mesh.positions.len(),
+        ..Default::default()
+    };
+    let epsilon = (options.position_epsilon * mesh.bounds().extent().length()).max(1e-12);
+    let cell = |p: Vec3| (p / epsilon).floor().as_ivec3();
+
+    let mut welded = Mesh {
+        name: std::mem::take(&mut mesh.name),
+        ..Default::default()
+    };
+    let mut grid: HashMap<IVec3, Vec<u32>> = HashMap::new();
+    let mut remap = Vec::with_capacity(mesh.positions.len());
+    for v in 0..mesh.positions.len() {
+        let (p, n, uv) = (mesh.positions[v], mesh.normals[v], mesh.uvs[v]);
+        let center = cell(p);
+
+        let mut snap = None;
+        let mut found = None;
+        'search: for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let neighbours = grid.get(&(center + IVec3::new(dx, dy, dz)));
+                    for &r in neighbours.into_iter().flatten() {
+                        let q = welded.positions[r as usize];
+                        if q.distance_squared(p) > epsilon * epsilon {
+                            continue;
+                        }
+                        snap.get_or_insert(q);
+                        if welded.normals[r as usize].dot(n) >= options.normal_cos
+                            && welded.uvs[r as usize].distance(uv) <= options.uv_epsilon
+                        {
+                            found = Some(r);
+                            break 'search;
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(r) = found {
+            report.welded_vertices += 1;
+            remap.push(r);
+            continue;
+        }
+        let position = snap.unwrap_or(p);
+        if position != p {
+            report.snapped_vertices += 1;
+        }
+        let index = welded.positions.len() as u32;
+        welded.positions.push(position);
+        welded.normals.push(n);
+        welded.uvs.push(uv);
+        if let Some(&tangent) = mesh.tangents.get(v) {
+            welded.tangents.push(tangent);
+        }
+        grid.entry(cell(position)).or_default().push(index);
+        remap.push(index);
+    }
+
+    // Drop collapsed and zero area triangles, and faces repeated with the
+    // same winding which would otherwise make their edges non-manifold.
+    let mut seen = HashSet::new();
+    for triangle in mesh.indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|v| remap[v as usize]);
+        let [p0, p1, p2] = [a, b, c].map(|v| welded.positions[v as usize]);
+        if a == b || b == c || c == a || (p1 - p0).cross(p2 - p0).length() < epsilon * epsilon {
+            report.degenerate_triangles += 1;
+            continue;
+        }
+        let key = match a.min(b).min(c) {
+            m if m == a => [a, b, c],
+            m if m == b => [b, c, a],
+            _ => [c, a, b],
+        };
+        if !seen.insert(key) {
+            report.duplicate_triangles += 1;
+            continue;
+        }
+        welded.indices.extend_from_slice(&[a, b, c]);
+    }
+
+    let mut edges: HashMap<(u32, u32), u32> = HashMap::new();
+    for triangle in welded.indices.chunks_exact(3) {
+        for k in 0..3 {
+            let (a, b) = (triangle[k], triangle[(k + 1) % 3]);
+            *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+        }
+    }
+    report.non_manifold_edges = edges.values().filter(|&&count| count > 2).count();
+    report.vertices_after = welded.positions.len();
+
+    *mesh = welded;
+    report
+}
@@ -0,0 +1,46 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::{scene::Environment, texture::decode_srgb};
+
+/// Loads a lat-long environment map from any float format the image crate
+/// reads, such as OpenEXR or Radiance HDR.
+pub fn load(path: &Path) -> Result<Environment> {
+    let image = image::open(path)
+        .with_context(|| format!("Failed to load environment {}", path.display()))?
+        .into_rgba32f();
+    let (width, height) = image.dimensions();
+    tracing::info!("Loaded environment {}: {width}x{height}", path.display());
+    Ok(Environment {
+        width,
+        height,
+        pixels: image.pixels().map(|pixel| pixel.0).collect(),
+    })
+}
+
+/// Loads a backdrop plate. Float formats are taken as linear, anything else
+/// as sRGB encoded like most photographs.
+pub fn load_backdrop(path: &Path) -> Result<Environment> {
+    let image =
+        image::open(path).with_context(|| format!("Failed to load backdrop {}", path.display()))?;
+    let linear = matches!(
+        image.color(),
+        image::ColorType::Rgb32F | image::ColorType::Rgba32F
+    );
+    let image = image.into_rgba32f();
+    let (width, height) = image.dimensions();
+    tracing::info!("Loaded backdrop {}: {width}x{height}", path.display());
+    let decode = |value: f32| if linear { value } else { decode_srgb(value) };
+    Ok(Environment {
+        width,
+        height,
+        pixels: image
+            .pixels()
+            .map(|pixel| {
+                let [r, g, b, a] = pixel.0;
+                [decode(r), decode(g), decode(b), a]
+            })
+            .collect(),
+    })
+}
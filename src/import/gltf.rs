@@ -0,0 +1,598 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{Context, Result};
+use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
+use gltf::{animation::util::ReadOutputs, image::Format, khr_lights_punctual::Kind, mesh::Mode};
+
+use crate::{
+    audio::{AudioBinding, AudioTarget, BAND_COUNT},
+    preset::{Preset, RenderOverrides},
+    sampler::SamplerKind,
+    scene::{
+        Animation, BodyKind, Channel, Dispersion, Instance, Interpolation, Keys, Light, LightKind,
+        Material, Mesh, Scene,
+    },
+    spectral::{Conductor, Subsurface, ThinFilm},
+    texture::Texture,
+    DenoiseMode,
+};
+
+pub fn load(path: &Path) -> Result<Scene> {
+    let (document, buffers, images) =
+        gltf::import(path).with_context(|| format!("Failed to import {}", path.display()))?;
+
+    let mut scene = Scene {
+        textures: images.iter().enumerate().map(convert_image).collect(),
+        materials: document
+            .materials()
+            .map(|material| convert_material(&document, material))
+            .collect(),
+        ..Default::default()
+    };
+    // Color textures are sRGB, the rest hold linear data
+    for material in &scene.materials {
+        for index in [material.base_color_texture, material.emission_texture]
+            .into_iter()
+            .flatten()
+        {
+            if let Some(texture) = scene.textures.get_mut(index) {
+                texture.srgb = true;
+            }
+        }
+    }
+    for (index, material) in document.materials().enumerate() {
+        let Some(extras) = parse_extras(material.extras()) else {
+            continue;
+        };
+        let color = extras
+            .get("audio")
+            .and_then(|audio| audio.get("color"))
+            .and_then(parse_vec3)
+            .unwrap_or(Vec3::ONE);
+        let target = AudioTarget::Emission {
+            material: index,
+            base: scene.materials[index].emission,
+            color,
+        };
+        scene.audio_bindings.extend(audio_binding(&extras, target));
+    }
+
+    // glTF meshes are a list of primitives, each with its own material,
+    // so every primitive becomes one of our meshes.
+    let mut primitives: HashMap<usize, Vec<(usize, Option<usize>)>> = HashMap::new();
+    for mesh in document.meshes() {
+        for primitive in mesh.primitives() {
+            if primitive.mode() != Mode::Triangles {
+                tracing::warn!(
+                    "Skipping {:?} primitive in mesh {:?}",
+                    primitive.mode(),
+                    mesh.name()
+                );
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+
+            let mut out = Mesh {
+                name: mesh.name().unwrap_or_default().to_owned(),
+                positions: positions.map(Vec3::from).collect(),
+                ..Default::default()
+            };
+            out.indices = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..out.positions.len() as u32).collect(),
+            };
+            match reader.read_normals() {
+                Some(normals) => out.normals = normals.map(Vec3::from).collect(),
+                None => out.compute_normals(),
+            }
+            out.uvs = match reader.read_tex_coords(0) {
+                Some(uvs) => uvs.into_f32().map(Vec2::from).collect(),
+                None => vec![Vec2::ZERO; out.positions.len()],
+            };
+            if let Some(tangents) = reader.read_tangents() {
+                out.tangents = tangents.map(Vec4::from).collect();
+            }
+
+            primitives
+                .entry(mesh.index())
+                .or_default()
+                .push((scene.meshes.len(), primitive.material().index()));
+            scene.meshes.push(out);
+        }
+    }
+
+    let gltf_scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .context("glTF file contains no scenes")?;
+    if let Some(extras) = parse_extras(gltf_scene.extras()) {
+        scene.render = render_overrides(&extras);
+        // A plate to match the render against, next to the file
+        if let Some(backdrop) = extras.get("backdrop").and_then(gltf::json::Value::as_str) {
+            let backdrop = path.parent().unwrap_or(Path::new("")).join(backdrop);
+            scene.backdrop = Some(super::load_backdrop(&backdrop)?);
+        }
+    }
+    let mut default_material = None;
+    // Our node of every glTF node in the scene, for the animations
+    let mut nodes = HashMap::new();
+    let mut stack: Vec<_> = gltf_scene
+        .nodes()
+        .map(|node| (node, None, Mat4::IDENTITY))
+        .collect();
+    while let Some((node, parent, parent_transform)) = stack.pop() {
+        let local = Mat4::from_cols_array_2d(&node.transform().matrix());
+        let transform = parent_transform * local;
+        let name = node
+            .name()
+            .map_or_else(|| format!("Node {}", node.index()), str::to_owned);
+        let index = scene.add_node(name, local, parent);
+        nodes.insert(node.index(), index);
+
+        let extras = parse_extras(node.extras());
+        if let Some(mesh) = node.mesh() {
+            let body = extras.as_ref().and_then(body_kind);
+            let holdout = extras
+                .as_ref()
+                .and_then(|extras| extras.get("holdout"))
+                .and_then(gltf::json::Value::as_bool)
+                .unwrap_or(false);
+            let class = extras
+                .as_ref()
+                .and_then(|extras| extras.get("class"))
+                .and_then(gltf::json::Value::as_u64)
+                .map_or(0, |class| class as u32);
+            for &(mesh, material) in primitives.get(&mesh.index()).into_iter().flatten() {
+                let material = material.unwrap_or_else(|| {
+                    *default_material.get_or_insert_with(|| {
+                        scene.materials.push(Material::default());
+                        scene.materials.len() - 1
+                    })
+                });
+                if let Some(extras) = &extras {
+                    let target = AudioTarget::Scale {
+                        instance: scene.instances.len(),
+                        base: transform,
+                    };
+                    scene.audio_bindings.extend(audio_binding(extras, target));
+                }
+                scene.nodes[index].instances.push(scene.instances.len());
+                scene.instances.push(Instance {
+                    mesh,
+                    material,
+                    transform,
+                    lod: 0,
+                    body,
+                    holdout,
+                    class,
+                });
+            }
+        }
+        if let Some(light) = node.light() {
+            let extras = parse_extras(light.extras());
+            let light = convert_light(&light, transform);
+            if let Some(extras) = &extras {
+                let target = AudioTarget::LightIntensity {
+                    light: scene.lights.len(),
+                    base: light.intensity,
+                };
+                scene.audio_bindings.extend(audio_binding(extras, target));
+            }
+            scene.nodes[index].lights.push(scene.lights.len());
+            scene.lights.push(light);
+        }
+
+        stack.extend(node.children().map(|child| (child, Some(index), transform)));
+    }
+
+    for animation in document.animations() {
+        let channels: Vec<Channel> = animation
+            .channels()
+            .filter_map(|channel| convert_channel(&channel, &buffers, &nodes))
+            .collect();
+        if channels.is_empty() {
+            continue;
+        }
+        scene.animations.push(Animation {
+            name: animation
+                .name()
+                .map_or_else(|| format!("Animation {}", animation.index()), str::to_owned),
+            channels,
+        });
+    }
+    // The nodes start out posed as at the start of the timeline
+    scene.animate(0.0);
+
+    Ok(scene)
+}
+
+/// Keys of an animation channel, none if it moves something we don't, such
+/// as morph target weights or a node of another scene.
+fn convert_channel(
+    channel: &gltf::animation::Channel,
+    buffers: &[gltf::buffer::Data],
+    nodes: &HashMap<usize, usize>,
+) -> Option<Channel> {
+    let node = *nodes.get(&channel.target().node().index())?;
+    let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+    let keys = match reader.read_outputs()? {
+        ReadOutputs::Translations(values) => Keys::Translation(values.map(Vec3::from).collect()),
+        ReadOutputs::Rotations(values) => {
+            Keys::Rotation(values.into_f32().map(Quat::from_array).collect())
+        }
+        ReadOutputs::Scales(values) => Keys::Scale(values.map(Vec3::from).collect()),
+        ReadOutputs::MorphTargetWeights(_) => {
+            tracing::warn!("Skipping morph target animation");
+            return None;
+        }
+    };
+    let interpolation = match channel.sampler().interpolation() {
+        gltf::animation::Interpolation::Step => Interpolation::Step,
+        gltf::animation::Interpolation::Linear => Interpolation::Linear,
+        gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+    };
+    let channel = Channel {
+        node,
+        times: reader.read_inputs()?.collect(),
+        keys,
+        interpolation,
+    };
+    if !channel.is_valid() {
+        tracing::warn!("Skipping animation channel with mismatched keys");
+        return None;
+    }
+    Some(channel)
+}
+
+fn convert_material(document: &gltf::Document, material: gltf::Material) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    // Extensions not supported by the gltf crate yet are read from the JSON
+    let extension = |name, key| material.extension_value(name)?.get(key);
+    let factor = |name, key, default| {
+        extension(name, key)
+            .and_then(gltf::json::Value::as_f64)
+            .map_or(default, |value| value as f32)
+    };
+    // Texture infos read from JSON, to the index of their image
+    let texture_source = |info: &gltf::json::Value| {
+        let index = info.get("index")?.as_u64()?;
+        Some(document.textures().nth(index as usize)?.source().index())
+    };
+    let texture_scale = |info: Option<&gltf::json::Value>, key, default| {
+        info.and_then(|info| info.get(key)?.as_f64())
+            .map_or(default, |scale| scale as f32)
+    };
+    let clearcoat_normal = extension("KHR_materials_clearcoat", "clearcoatNormalTexture");
+    let extras = parse_extras(material.extras());
+    // Height maps have no extension, they're given in the extras as
+    // `{"bump": {"index": 0, "scale": 0.01}}` with the scale in world units
+    let bump = extras.as_ref().and_then(|extras| extras.get("bump"));
+    let sheen_color = extension("KHR_materials_sheen", "sheenColorFactor")
+        .and_then(gltf::json::Value::as_array)
+        .map(|color| {
+            Vec3::from_array(
+                [0, 1, 2].map(|i| color.get(i).and_then(|c| c.as_f64()).unwrap_or(0.0) as f32),
+            )
+        });
+    let ior = material.ior().unwrap_or(1.5);
+    // KHR_materials_dispersion stores 20 over the Abbe number
+    let dispersion = factor("KHR_materials_dispersion", "dispersion", 0.0);
+    // Iridescence may vary the thickness with a texture, the thickest is used
+    let iridescence = factor("KHR_materials_iridescence", "iridescenceFactor", 0.0);
+    let mut out = Material {
+        name: material.name().unwrap_or_default().to_owned(),
+        base_color: Vec4::from(pbr.base_color_factor()),
+        base_color_texture: pbr
+            .base_color_texture()
+            .map(|info| info.texture().source().index()),
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        normal_texture: material
+            .normal_texture()
+            .map(|info| info.texture().source().index()),
+        normal_scale: material.normal_texture().map_or(1.0, |info| info.scale()),
+        bump_texture: bump.and_then(texture_source),
+        bump_scale: texture_scale(bump, "scale", 0.01),
+        metallic_roughness_texture: pbr
+            .metallic_roughness_texture()
+            .map(|info| info.texture().source().index()),
+        emission: Vec3::from(material.emissive_factor()),
+        emission_texture: material
+            .emissive_texture()
+            .map(|info| info.texture().source().index()),
+        transmission: material
+            .transmission()
+            .map_or(0.0, |transmission| transmission.transmission_factor()),
+        ior,
+        // Without a volume glTF defines the material as thin walled
+        thin_walled: material
+            .volume()
+            .is_none_or(|volume| volume.thickness_factor() <= 0.0),
+        anisotropy: factor("KHR_materials_anisotropy", "anisotropyStrength", 0.0),
+        anisotropy_rotation: factor("KHR_materials_anisotropy", "anisotropyRotation", 0.0),
+        clearcoat: factor("KHR_materials_clearcoat", "clearcoatFactor", 0.0),
+        clearcoat_roughness: factor("KHR_materials_clearcoat", "clearcoatRoughnessFactor", 0.0),
+        clearcoat_normal_texture: clearcoat_normal.and_then(texture_source),
+        clearcoat_normal_scale: texture_scale(clearcoat_normal, "scale", 1.0),
+        sheen_color: sheen_color.unwrap_or(Vec3::ZERO),
+        sheen_roughness: factor("KHR_materials_sheen", "sheenRoughnessFactor", 0.0),
+        dispersion: (dispersion > 0.0).then(|| Dispersion::from_abbe(ior, 20.0 / dispersion)),
+        thin_film: (iridescence > 0.0).then(|| ThinFilm {
+            thickness: factor(
+                "KHR_materials_iridescence",
+                "iridescenceThicknessMaximum",
+                400.0,
+            ),
+            ior: factor("KHR_materials_iridescence", "iridescenceIor", 1.3),
+        }),
+        ..Default::default()
+    };
+    if let Some(extras) = &extras {
+        apply_material_extras(&mut out, extras);
+    }
+    out
+}
+
+/// Settings without a glTF extension come from the material extras.
+/// `{"preset": "car_paint"}` turns the material into car paint of the same
+/// base color, and `flake_coverage`, `flake_size` and `flake_roughness`
+/// adjust its flakes. Exact dispersion is given by `"cauchy": [a, b]` or
+/// `"sellmeier": {"b": [b1, b2, b3], "c": [c1, c2, c3]}` with wavelengths
+/// in micrometers. `"conductor"` makes the material one of the measured
+/// metals `gold`, `silver`, `copper` or `aluminum`, and
+/// `"thin_film": {"thickness": 400, "ior": 1.33}` coats it with a film of
+/// the given thickness in nanometers. `"subsurface"` scatters the light of
+/// the base through one of the media `skin`, `wax` or `marble` beneath it,
+/// scaled by `"units_per_meter"` for scenes not in meters.
+fn apply_material_extras(material: &mut Material, extras: &gltf::json::Value) {
+    if extras.get("preset").and_then(gltf::json::Value::as_str) == Some("car_paint") {
+        *material = Material {
+            name: std::mem::take(&mut material.name),
+            ..Material::car_paint(material.base_color.truncate())
+        };
+    }
+    let number = |key| {
+        extras
+            .get(key)
+            .and_then(gltf::json::Value::as_f64)
+            .map(|value| value as f32)
+    };
+    if let Some(coverage) = number("flake_coverage") {
+        material.flake_coverage = coverage;
+    }
+    if let Some(size) = number("flake_size") {
+        material.flake_size = size;
+    }
+    if let Some(roughness) = number("flake_roughness") {
+        material.flake_roughness = roughness;
+    }
+
+    let cauchy = extras.get("cauchy").and_then(|cauchy| {
+        let a = cauchy.get(0)?.as_f64()? as f32;
+        let b = cauchy.get(1)?.as_f64()? as f32;
+        Some(Dispersion::Cauchy { a, b })
+    });
+    let sellmeier = extras.get("sellmeier").and_then(|sellmeier| {
+        let b = parse_vec3(sellmeier.get("b")?)?;
+        let c = parse_vec3(sellmeier.get("c")?)?;
+        Some(Dispersion::Sellmeier {
+            b: b.to_array(),
+            c: c.to_array(),
+        })
+    });
+    if let Some(dispersion) = cauchy.or(sellmeier) {
+        material.ior = dispersion.ior(Dispersion::D_LINE);
+        material.dispersion = Some(dispersion);
+    }
+
+    match extras.get("conductor").and_then(gltf::json::Value::as_str) {
+        Some("gold") => material.conductor = Some(Conductor::Gold),
+        Some("silver") => material.conductor = Some(Conductor::Silver),
+        Some("copper") => material.conductor = Some(Conductor::Copper),
+        Some("aluminum") => material.conductor = Some(Conductor::Aluminum),
+        Some(name) => tracing::warn!("Unknown conductor {name:?}"),
+        None => {}
+    }
+    if let Some(temperature) = temperature(extras) {
+        material.temperature = Some(temperature);
+    }
+    let subsurface = match extras.get("subsurface").and_then(gltf::json::Value::as_str) {
+        Some("skin") => Some(Subsurface::skin()),
+        Some("wax") => Some(Subsurface::wax()),
+        Some("marble") => Some(Subsurface::marble()),
+        Some(name) => {
+            tracing::warn!("Unknown subsurface medium {name:?}");
+            None
+        }
+        None => None,
+    };
+    if let Some(subsurface) = subsurface {
+        material.subsurface = Some(subsurface.scaled(number("units_per_meter").unwrap_or(1.0)));
+    }
+    if let Some(film) = extras.get("thin_film") {
+        let number = |key, default| {
+            film.get(key)
+                .and_then(gltf::json::Value::as_f64)
+                .map_or(default, |value| value as f32)
+        };
+        material.thin_film = Some(ThinFilm {
+            thickness: number("thickness", 400.0),
+            ior: number("ior", 1.33),
+        });
+    }
+}
+
+fn convert_light(light: &gltf::khr_lights_punctual::Light, transform: Mat4) -> Light {
+    let kind = match light.kind() {
+        Kind::Directional => LightKind::Directional,
+        Kind::Point => LightKind::Point,
+        Kind::Spot {
+            inner_cone_angle,
+            outer_cone_angle,
+        } => LightKind::Spot {
+            inner_cone_angle,
+            outer_cone_angle,
+        },
+    };
+    Light {
+        kind: area_light(light.extras()).unwrap_or(kind),
+        color: Vec3::from(light.color()),
+        intensity: light.intensity(),
+        temperature: parse_extras(light.extras()).and_then(|extras| temperature(&extras)),
+        transform,
+    }
+}
+
+/// KHR_lights_punctual has no area lights, so they're marked in the light
+/// extras as `{"shape": "quad", "width": w, "height": h}` or
+/// `{"shape": "disk", "radius": r}`.
+fn area_light(extras: &gltf::json::Extras) -> Option<LightKind> {
+    let extras: gltf::json::Value =
+        gltf::json::deserialize::from_str(extras.as_ref()?.get()).ok()?;
+    let number = |key, default| {
+        extras
+            .get(key)
+            .and_then(gltf::json::Value::as_f64)
+            .map_or(default, |value| value as f32)
+    };
+    match extras.get("shape")?.as_str()? {
+        "quad" => Some(LightKind::Quad {
+            width: number("width", 1.0),
+            height: number("height", 1.0),
+        }),
+        "disk" => Some(LightKind::Disk {
+            radius: number("radius", 0.5),
+        }),
+        _ => None,
+    }
+}
+
+/// Blackbody emitters give their color temperature in kelvin in the extras
+/// of lights and materials as `{"temperature": 3200}`.
+fn temperature(extras: &gltf::json::Value) -> Option<f32> {
+    Some(extras.get("temperature")?.as_f64()? as f32)
+}
+
+/// Physics bodies are marked in the node extras as `{"physics": "dynamic"}`
+/// or `{"physics": "fixed"}`.
+fn body_kind(extras: &gltf::json::Value) -> Option<BodyKind> {
+    match extras.get("physics")?.as_str()? {
+        "dynamic" => Some(BodyKind::Dynamic),
+        "fixed" => Some(BodyKind::Fixed),
+        _ => None,
+    }
+}
+
+/// Recommended render settings are given in the extras of the scene as
+/// `{"render": {"preset": "final", "samples": 1024, "max_depth": 12}}`,
+/// along with `resolution_scale`, `spectral` and `denoise`.
+fn render_overrides(extras: &gltf::json::Value) -> RenderOverrides {
+    let Some(render) = extras.get("render") else {
+        return RenderOverrides::default();
+    };
+    let get = |key| render.get(key);
+    let name = |key| get(key).and_then(gltf::json::Value::as_str);
+    let count = |key| {
+        get(key)
+            .and_then(gltf::json::Value::as_u64)
+            .map(|n| n.min(u32::MAX as u64) as u32)
+    };
+    let overrides = RenderOverrides {
+        preset: name("preset").and_then(Preset::parse),
+        resolution_scale: get("resolution_scale")
+            .and_then(gltf::json::Value::as_f64)
+            .map(|scale| (scale as f32).clamp(0.05, 1.0)),
+        samples: count("samples"),
+        max_depth: count("max_depth").map(|depth| depth.max(1)),
+        spectral: get("spectral").and_then(gltf::json::Value::as_bool),
+        denoise: name("denoise").and_then(DenoiseMode::parse),
+        sampler: name("sampler").and_then(SamplerKind::parse),
+    };
+    for (key, parsed) in [
+        ("preset", overrides.preset.is_some()),
+        ("denoise", overrides.denoise.is_some()),
+        ("sampler", overrides.sampler.is_some()),
+    ] {
+        if let (Some(value), false) = (name(key), parsed) {
+            tracing::warn!("Ignoring unknown render {key} {value:?} in the scene");
+        }
+    }
+    overrides
+}
+
+/// Audio reactive parameters are bound in the extras of lights, materials
+/// and nodes as `{"audio": {"band": 0, "gain": 1.0}}`, band 0 being the
+/// lowest. Materials also take the emission `color` added at full level.
+fn audio_binding(extras: &gltf::json::Value, target: AudioTarget) -> Option<AudioBinding> {
+    let audio = extras.get("audio")?;
+    Some(AudioBinding {
+        band: audio
+            .get("band")
+            .and_then(gltf::json::Value::as_u64)
+            .map_or(0, |band| (band as usize).min(BAND_COUNT - 1)),
+        gain: audio
+            .get("gain")
+            .and_then(gltf::json::Value::as_f64)
+            .map_or(1.0, |gain| gain as f32),
+        target,
+    })
+}
+
+fn parse_vec3(value: &gltf::json::Value) -> Option<Vec3> {
+    let array = value.as_array()?;
+    let component = |i: usize| Some(array.get(i)?.as_f64()? as f32);
+    Some(Vec3::new(component(0)?, component(1)?, component(2)?))
+}
+
+fn parse_extras(extras: &gltf::json::Extras) -> Option<gltf::json::Value> {
+    gltf::json::deserialize::from_str(extras.as_ref()?.get()).ok()
+}
+
+/// Expands any glTF image format to 8-bit RGBA.
+fn convert_image((index, image): (usize, &gltf::image::Data)) -> Texture {
+    let to_u8 = |bytes: &[u8]| -> Vec<u8> {
+        match image.format {
+            Format::R8 | Format::R8G8 | Format::R8G8B8 | Format::R8G8B8A8 => bytes.to_vec(),
+            // Little endian, keep the high byte
+            Format::R16 | Format::R16G16 | Format::R16G16B16 | Format::R16G16B16A16 => {
+                bytes.chunks_exact(2).map(|c| c[1]).collect()
+            }
+            Format::R32G32B32FLOAT | Format::R32G32B32A32FLOAT => bytes
+                .chunks_exact(4)
+                .map(|c| {
+                    let v = f32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+                    (v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8
+                })
+                .collect(),
+        }
+    };
+    let channels = match image.format {
+        Format::R8 | Format::R16 => 1,
+        Format::R8G8 | Format::R16G16 => 2,
+        Format::R8G8B8 | Format::R16G16B16 | Format::R32G32B32FLOAT => 3,
+        Format::R8G8B8A8 | Format::R16G16B16A16 | Format::R32G32B32A32FLOAT => 4,
+    };
+
+    let values = to_u8(&image.pixels);
+    let mut pixels = Vec::with_capacity(image.width as usize * image.height as usize * 4);
+    for texel in values.chunks_exact(channels) {
+        pixels.extend_from_slice(&match *texel {
+            [r] => [r, r, r, 255],
+            [r, a] => [r, r, r, a],
+            [r, g, b] => [r, g, b, 255],
+            [r, g, b, a] => [r, g, b, a],
+            _ => unreachable!(),
+        });
+    }
+
+    Texture {
+        name: format!("image {index}"),
+        width: image.width,
+        height: image.height,
+        pixels,
+        srgb: false,
+    }
+}
@@ -0,0 +1,670 @@
+//! IGES files (ANSI Y14.26M) of trimmed NURBS and analytic surfaces, the
+//! way CAD packages have long exchanged surface models.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use glam::{DMat4, DVec2, DVec3, DVec4, Mat4, Vec3};
+use rayon::prelude::*;
+
+use super::{
+    brep::{self, BoundaryPoint, Curve, Face, Frame, NurbsCurve, NurbsSurface, Surface},
+    file_stem, parse_unit, ImportOptions,
+};
+use crate::scene::{Instance, Material, Mesh, Scene};
+
+/// Loads the surfaces of an IGES file, one mesh per colour, along with the
+/// length of its unit in meters.
+pub fn load(path: &Path, options: &ImportOptions) -> Result<(Scene, f32)> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let iges = Iges::parse(&String::from_utf8_lossy(&bytes))
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    let tolerance = options.tolerance_in(iges.unit as f32);
+
+    // Trimmed surfaces, and B-spline surfaces drawn on their own rather
+    // than through something trimming them
+    let mut entries: Vec<usize> = iges.entries.keys().copied().collect();
+    entries.sort_unstable();
+    let trimmed: HashSet<usize> = entries
+        .iter()
+        .filter(|&&de| iges.entries[&de].kind == 144)
+        .filter_map(|de| iges.int(*de, 1).ok())
+        .map(|surface| surface as usize)
+        .collect();
+    let faces: Vec<usize> = entries
+        .iter()
+        .copied()
+        .filter(|de| {
+            let entry = &iges.entries[de];
+            entry.kind == 144 || (entry.kind == 128 && entry.independent && !trimmed.contains(de))
+        })
+        .collect();
+    let solids = entries
+        .iter()
+        .filter(|de| matches!(iges.entries[de].kind, 186 | 143))
+        .count();
+    if solids > 0 {
+        tracing::warn!("Skipped {solids} solids and bounded surfaces of {}, only trimmed surfaces are supported", path.display());
+    }
+    ensure!(!faces.is_empty(), "{} has no surfaces", path.display());
+
+    let tessellated: Vec<_> = faces
+        .par_iter()
+        .map(|&de| {
+            let mut mesh = Mesh::default();
+            let result = iges
+                .face(de, tolerance)
+                .and_then(|face| brep::tessellate(&face, tolerance, &mut mesh));
+            (iges.face_color(de), mesh, result)
+        })
+        .collect();
+
+    let mut meshes: Vec<(Option<Vec3>, Mesh)> = Vec::new();
+    let mut skipped = 0;
+    for (de, (color, face, result)) in faces.iter().zip(tessellated) {
+        if let Err(error) = result {
+            tracing::debug!("Skipped surface {de}: {error:#}");
+            skipped += 1;
+            continue;
+        }
+        let mesh = match meshes.iter_mut().find(|(c, _)| *c == color) {
+            Some((_, mesh)) => mesh,
+            None => {
+                meshes.push((color, Mesh::default()));
+                &mut meshes.last_mut().unwrap().1
+            }
+        };
+        let base = mesh.positions.len() as u32;
+        mesh.positions.extend(face.positions);
+        mesh.normals.extend(face.normals);
+        mesh.uvs.extend(face.uvs);
+        mesh.indices
+            .extend(face.indices.iter().map(|index| base + index));
+    }
+    if skipped > 0 {
+        tracing::warn!(
+            "Skipped {skipped} surfaces of {} that couldn't be tessellated",
+            path.display()
+        );
+    }
+    meshes.retain(|(_, mesh)| !mesh.indices.is_empty());
+    ensure!(
+        !meshes.is_empty(),
+        "{} has no surfaces that could be tessellated",
+        path.display()
+    );
+
+    let mut scene = Scene {
+        materials: vec![Material::default()],
+        ..Default::default()
+    };
+    for (color, mut mesh) in meshes {
+        brep::fill_normals(&mut mesh);
+        mesh.name = file_stem(path);
+        let material = match color {
+            Some(color) => {
+                scene.materials.push(brep::material(color));
+                scene.materials.len() - 1
+            }
+            None => 0,
+        };
+        scene.instances.push(Instance {
+            mesh: scene.meshes.len(),
+            material,
+            transform: Mat4::IDENTITY,
+            lod: 0,
+            body: None,
+            holdout: false,
+            class: 0,
+        });
+        scene.meshes.push(mesh);
+    }
+    Ok((scene, iges.unit as f32))
+}
+
+/// Directory entry of an entity, with its parameters.
+struct Entry {
+    kind: u32,
+    /// Directory entry of the transformation matrix placing it, or zero.
+    transform: usize,
+    /// Whether it stands on its own rather than being part of another.
+    independent: bool,
+    /// Colour number, or the negated directory entry of a colour.
+    color: i64,
+    form: i64,
+    params: Vec<String>,
+}
+
+struct Iges {
+    /// Entities by the sequence number of their directory entry.
+    entries: HashMap<usize, Entry>,
+    /// Meters per model unit.
+    unit: f64,
+}
+
+impl Iges {
+    fn parse(text: &str) -> Result<Self> {
+        let mut sections: HashMap<u8, Vec<&str>> = HashMap::new();
+        for line in text.lines() {
+            let line = line.trim_end_matches('\r');
+            if line.len() < 73 || !line.is_char_boundary(72) {
+                continue;
+            }
+            sections.entry(line.as_bytes()[72]).or_default().push(line);
+        }
+
+        let global: String = sections
+            .get(&b'G')
+            .into_iter()
+            .flatten()
+            .map(|line| &line[..72])
+            .collect();
+        let (delimiter, terminator) = delimiters(&global);
+        let global = split(&global, delimiter, terminator);
+        let unit = match global
+            .get(13)
+            .and_then(|flag| flag.trim().parse::<u32>().ok())
+        {
+            Some(1) => 0.0254,
+            Some(2) | None => 0.001,
+            Some(3) => global
+                .get(14)
+                .and_then(|name| parse_unit(name.trim()))
+                .context("Unsupported unit name")? as f64,
+            Some(4) => 0.3048,
+            Some(5) => 1609.344,
+            Some(6) => 1.0,
+            Some(7) => 1000.0,
+            Some(8) => 2.54e-5,
+            Some(9) => 1e-6,
+            Some(10) => 0.01,
+            Some(11) => 2.54e-8,
+            Some(flag) => bail!("Unsupported unit flag {flag}"),
+        };
+
+        // Parameter data is tagged with the directory entry it belongs to
+        let mut data: HashMap<usize, String> = HashMap::new();
+        for line in sections.get(&b'P').into_iter().flatten() {
+            let Some(de) = line.get(64..72).and_then(|de| de.trim().parse().ok()) else {
+                continue;
+            };
+            data.entry(de)
+                .or_default()
+                .push_str(line.get(..64).unwrap_or(line));
+        }
+
+        let directory = sections.get(&b'D').map(Vec::as_slice).unwrap_or_default();
+        let mut entries = HashMap::new();
+        for (index, lines) in directory.chunks_exact(2).enumerate() {
+            let field = |line: &str, i: usize| {
+                let text = line.get(8 * i..8 * i + 8).unwrap_or_default();
+                text.trim().parse::<i64>().unwrap_or(0)
+            };
+            let de = 2 * index + 1;
+            let status = field(lines[0], 8);
+            let params = data
+                .get(&de)
+                .map(|text| split(text, delimiter, terminator))
+                .unwrap_or_default();
+            entries.insert(
+                de,
+                Entry {
+                    kind: field(lines[0], 0) as u32,
+                    transform: field(lines[0], 6).max(0) as usize,
+                    independent: status / 10000 % 100 == 0,
+                    color: field(lines[1], 2),
+                    form: field(lines[1], 4),
+                    params,
+                },
+            );
+        }
+        ensure!(!entries.is_empty(), "No directory entries");
+        Ok(Self { entries, unit })
+    }
+
+    fn entry(&self, de: usize) -> Result<&Entry> {
+        self.entries
+            .get(&de)
+            .with_context(|| format!("Missing directory entry {de}"))
+    }
+
+    /// Parameter `i`, counting the entity type as the zeroth.
+    fn float(&self, de: usize, i: usize) -> Result<f64> {
+        let param = self
+            .entry(de)?
+            .params
+            .get(i)
+            .with_context(|| format!("Entity {de} has no parameter {i}"))?
+            .trim();
+        if param.is_empty() {
+            return Ok(0.0);
+        }
+        param
+            .replace(['D', 'd'], "E")
+            .parse()
+            .with_context(|| format!("Invalid number {param:?} in entity {de}"))
+    }
+
+    fn int(&self, de: usize, i: usize) -> Result<i64> {
+        Ok(self.float(de, i)?.round() as i64)
+    }
+
+    fn pointer(&self, de: usize, i: usize) -> Result<usize> {
+        Ok(self.int(de, i)?.unsigned_abs() as usize)
+    }
+
+    fn point(&self, de: usize, i: usize) -> Result<DVec3> {
+        Ok(DVec3::new(
+            self.float(de, i)?,
+            self.float(de, i + 1)?,
+            self.float(de, i + 2)?,
+        ))
+    }
+
+    /// Transform from the entity's own space into the model, through any
+    /// chain of transformation matrices.
+    fn transform(&self, de: usize) -> Result<DMat4> {
+        let mut transform = DMat4::IDENTITY;
+        let mut matrix = self.entry(de)?.transform;
+        for _ in 0..32 {
+            if matrix == 0 {
+                return Ok(transform);
+            }
+            ensure!(
+                self.entry(matrix)?.kind == 124,
+                "Entity {matrix} is not a transformation matrix"
+            );
+            let row = |r: usize| -> Result<DVec4> {
+                Ok(DVec4::new(
+                    self.float(matrix, 4 * r + 1)?,
+                    self.float(matrix, 4 * r + 2)?,
+                    self.float(matrix, 4 * r + 3)?,
+                    self.float(matrix, 4 * r + 4)?,
+                ))
+            };
+            let m = DMat4::from_cols(row(0)?, row(1)?, row(2)?, DVec4::W).transpose();
+            transform = m * transform;
+            matrix = self.entry(matrix)?.transform;
+        }
+        bail!("Transformation matrices of entity {de} form a cycle")
+    }
+
+    /// Pieces of a curve in model space, each with the range of its
+    /// parameter.
+    fn curve(&self, de: usize) -> Result<Vec<(Curve, f64, f64)>> {
+        let entry = self.entry(de)?;
+        let (curve, t0, t1) = match entry.kind {
+            102 => {
+                let count = self.int(de, 1)? as usize;
+                let transform = self.transform(de)?;
+                let mut pieces = Vec::new();
+                for i in 0..count {
+                    for (curve, t0, t1) in self.curve(self.pointer(de, 2 + i)?)? {
+                        pieces.push((curve.transformed(&transform), t0, t1));
+                    }
+                }
+                return Ok(pieces);
+            }
+            100 => {
+                let z = self.float(de, 1)?;
+                let center = DVec3::new(self.float(de, 2)?, self.float(de, 3)?, z);
+                let start = DVec3::new(self.float(de, 4)?, self.float(de, 5)?, z) - center;
+                let end = DVec3::new(self.float(de, 6)?, self.float(de, 7)?, z) - center;
+                let t0 = start.y.atan2(start.x);
+                let mut t1 = end.y.atan2(end.x);
+                if t1 <= t0 + 1e-9 {
+                    t1 += std::f64::consts::TAU;
+                }
+                let frame = Frame::new(center, DVec3::Z, Some(DVec3::X));
+                let radii = DVec2::splat(start.length());
+                (Curve::Ellipse { frame, radii }, t0, t1)
+            }
+            104 => {
+                ensure!(entry.form == 1, "Unsupported conic of form {}", entry.form);
+                let [a, _, c, _, _, f] =
+                    [1, 2, 3, 4, 5, 6].map(|i| self.float(de, i).unwrap_or(0.0));
+                let z = self.float(de, 7)?;
+                let radii = DVec2::new((-f / a).sqrt(), (-f / c).sqrt());
+                ensure!(radii.is_finite(), "Ellipse {de} isn't in standard position");
+                let angle = |x: f64, y: f64| (y / radii.y).atan2(x / radii.x);
+                let t0 = angle(self.float(de, 8)?, self.float(de, 9)?);
+                let mut t1 = angle(self.float(de, 10)?, self.float(de, 11)?);
+                if t1 <= t0 + 1e-9 {
+                    t1 += std::f64::consts::TAU;
+                }
+                let frame = Frame::new(DVec3::Z * z, DVec3::Z, Some(DVec3::X));
+                (Curve::Ellipse { frame, radii }, t0, t1)
+            }
+            106 => {
+                let (layout, count) = (self.int(de, 1)?, self.int(de, 2)? as usize);
+                let points: Vec<DVec3> = (0..count)
+                    .map(|i| match layout {
+                        1 => Ok(DVec3::new(
+                            self.float(de, 4 + 2 * i)?,
+                            self.float(de, 5 + 2 * i)?,
+                            self.float(de, 3)?,
+                        )),
+                        2 => self.point(de, 3 + 3 * i),
+                        3 => self.point(de, 3 + 6 * i),
+                        _ => bail!("Unsupported copious data layout {layout}"),
+                    })
+                    .collect::<Result<_>>()?;
+                ensure!(
+                    points.len() > 1,
+                    "Copious data {de} has fewer than two points"
+                );
+                let last = (points.len() - 1) as f64;
+                (Curve::Polyline(points), 0.0, last)
+            }
+            110 => {
+                let origin = self.point(de, 1)?;
+                let direction = self.point(de, 4)? - origin;
+                (Curve::Line { origin, direction }, 0.0, 1.0)
+            }
+            126 => {
+                let (upper, degree) = (self.int(de, 1)? as usize, self.int(de, 2)? as usize);
+                let count = upper + 1;
+                let knots = (0..count + degree + 1)
+                    .map(|i| self.float(de, 7 + i))
+                    .collect::<Result<_>>()?;
+                let weights_at = 7 + count + degree + 1;
+                let weights: Vec<f64> = (0..count)
+                    .map(|i| self.float(de, weights_at + i))
+                    .collect::<Result<_>>()?;
+                let points: Vec<DVec3> = (0..count)
+                    .map(|i| self.point(de, weights_at + count + 3 * i))
+                    .collect::<Result<_>>()?;
+                let range = weights_at + 4 * count;
+                let nurbs = NurbsCurve::new(degree, knots, &points, Some(&weights))
+                    .with_context(|| format!("Invalid B-spline curve {de}"))?;
+                (
+                    Curve::Nurbs(nurbs),
+                    self.float(de, range)?,
+                    self.float(de, range + 1)?,
+                )
+            }
+            kind => bail!("Unsupported curve type {kind}"),
+        };
+        Ok(vec![(curve.transformed(&self.transform(de)?), t0, t1)])
+    }
+
+    /// A curve that comes in one piece.
+    fn single_curve(&self, de: usize) -> Result<Curve> {
+        let mut pieces = self.curve(de)?;
+        ensure!(
+            pieces.len() == 1,
+            "Surfaces of composite curves aren't supported"
+        );
+        Ok(pieces.remove(0).0)
+    }
+
+    fn surface(&self, de: usize) -> Result<Surface> {
+        let surface = match self.entry(de)?.kind {
+            108 => {
+                let normal = self.point(de, 1)?;
+                let distance = self.float(de, 4)?;
+                let origin = normal * distance / normal.length_squared();
+                Surface::Plane(Frame::new(origin, normal, None))
+            }
+            120 => {
+                let axis = self.pointer(de, 1)?;
+                ensure!(
+                    self.entry(axis)?.kind == 110,
+                    "Axis of revolution {de} isn't a line"
+                );
+                let Curve::Line { origin, direction } = self.single_curve(axis)? else {
+                    unreachable!("lines load as lines");
+                };
+                Surface::Revolution {
+                    curve: Box::new(self.single_curve(self.pointer(de, 2)?)?),
+                    origin,
+                    axis: direction.normalize(),
+                }
+            }
+            122 => {
+                let directrix = self.pointer(de, 1)?;
+                let mut pieces = self.curve(directrix)?;
+                ensure!(
+                    pieces.len() == 1,
+                    "Surfaces of composite curves aren't supported"
+                );
+                let (curve, t0, _) = pieces.remove(0);
+                let end = self.point(de, 2)?;
+                Surface::Extrusion {
+                    direction: end - curve.point(t0),
+                    curve: Box::new(curve),
+                }
+            }
+            128 => Surface::Nurbs(self.b_spline_surface(de)?),
+            kind => bail!("Unsupported surface type {kind}"),
+        };
+        Ok(surface.transformed(&self.transform(de)?))
+    }
+
+    fn b_spline_surface(&self, de: usize) -> Result<NurbsSurface> {
+        let [upper_u, upper_v, degree_u, degree_v] =
+            [1, 2, 3, 4].map(|i| self.int(de, i).unwrap_or(0).max(0) as usize);
+        let counts = (upper_u + 1, upper_v + 1);
+        let mut at = 10;
+        let mut floats = |count: usize| -> Result<Vec<f64>> {
+            let values = (at..at + count).map(|i| self.float(de, i)).collect();
+            at += count;
+            values
+        };
+        let knots_u = floats(counts.0 + degree_u + 1)?;
+        let knots_v = floats(counts.1 + degree_v + 1)?;
+        // Weights and points run along u first
+        let weights = floats(counts.0 * counts.1)?;
+        let coordinates = floats(3 * counts.0 * counts.1)?;
+        let at = |i: usize, j: usize| i + j * counts.0;
+        let points: Vec<Vec<DVec3>> = (0..counts.0)
+            .map(|i| {
+                (0..counts.1)
+                    .map(|j| DVec3::from_slice(&coordinates[3 * at(i, j)..]))
+                    .collect()
+            })
+            .collect();
+        let weights: Vec<Vec<f64>> = (0..counts.0)
+            .map(|i| (0..counts.1).map(|j| weights[at(i, j)]).collect())
+            .collect();
+        NurbsSurface::new(
+            (degree_u, degree_v),
+            (knots_u, knots_v),
+            &points,
+            Some(&weights),
+        )
+        .with_context(|| format!("Invalid B-spline surface {de}"))
+    }
+
+    /// Parameter range a B-spline surface is drawn over.
+    fn domain(&self, de: usize) -> Result<(DVec2, DVec2)> {
+        let [upper_u, upper_v, degree_u, degree_v] =
+            [1, 2, 3, 4].map(|i| self.int(de, i).unwrap_or(0).max(0) as usize);
+        let count = (upper_u + 1) * (upper_v + 1);
+        let at = 10 + (upper_u + degree_u + 2) + (upper_v + degree_v + 2) + 4 * count;
+        Ok((
+            DVec2::new(self.float(de, at)?, self.float(de, at + 2)?),
+            DVec2::new(self.float(de, at + 1)?, self.float(de, at + 3)?),
+        ))
+    }
+
+    /// Loop of a curve on a surface, in the surface's parameters when it
+    /// gives them and in model space otherwise.
+    fn boundary(
+        &self,
+        de: usize,
+        surface: &Surface,
+        parametric: bool,
+        tolerance: f64,
+    ) -> Result<Vec<BoundaryPoint>> {
+        ensure!(
+            self.entry(de)?.kind == 142,
+            "Trimming curve {de} isn't a curve on a surface"
+        );
+        let (uv_curve, model_curve) = (self.pointer(de, 3)?, self.pointer(de, 4)?);
+        let mut points: Vec<BoundaryPoint> = Vec::new();
+        let mut extend = |piece: Vec<BoundaryPoint>| {
+            let joined = match (points.last(), piece.first()) {
+                (Some(a), Some(b)) => a.position.distance(b.position) <= tolerance * 1e-3,
+                _ => false,
+            };
+            points.extend(piece.into_iter().skip(usize::from(joined)));
+        };
+        if parametric && uv_curve != 0 {
+            for (curve, t0, t1) in self.curve(uv_curve)? {
+                extend(brep::sample_uv_curve(surface, &curve, t0, t1, tolerance));
+            }
+        } else if model_curve != 0 {
+            for (curve, t0, t1) in self.curve(model_curve)? {
+                let piece = curve
+                    .sample(t0, t1, tolerance)
+                    .into_iter()
+                    .map(|t| BoundaryPoint {
+                        position: curve.point(t),
+                        uv: None,
+                    })
+                    .collect();
+                extend(piece);
+            }
+        } else {
+            bail!("Trimming curve {de} has no curve the surface can use");
+        }
+        if points.len() > 1
+            && points[0]
+                .position
+                .distance(points[points.len() - 1].position)
+                <= tolerance * 1e-3
+        {
+            points.pop();
+        }
+        Ok(points)
+    }
+
+    /// Trimmed surface, or the whole of an untrimmed B-spline surface.
+    fn face(&self, de: usize, tolerance: f64) -> Result<Face> {
+        let base = match self.entry(de)?.kind {
+            144 => self.pointer(de, 1)?,
+            _ => de,
+        };
+        let surface = self.surface(base)?;
+        let parametric = self.entry(base)?.kind == 128;
+        let mut loops = Vec::new();
+        let whole = self.entry(de)?.kind == 128 || self.int(de, 2)? == 0;
+        if whole {
+            ensure!(parametric, "Untrimmed surface {base} has no bounds");
+            let (lo, hi) = self.domain(base)?;
+            let corners = vec![
+                lo.extend(0.0),
+                DVec3::new(hi.x, lo.y, 0.0),
+                hi.extend(0.0),
+                DVec3::new(lo.x, hi.y, 0.0),
+                lo.extend(0.0),
+            ];
+            let mut points =
+                brep::sample_uv_curve(&surface, &Curve::Polyline(corners), 0.0, 4.0, tolerance);
+            points.pop();
+            loops.push(points);
+        } else {
+            loops.push(self.boundary(self.pointer(de, 4)?, &surface, parametric, tolerance)?);
+        }
+        if self.entry(de)?.kind == 144 {
+            let holes = self.int(de, 3)?.max(0) as usize;
+            for i in 0..holes {
+                loops.push(self.boundary(
+                    self.pointer(de, 5 + i)?,
+                    &surface,
+                    parametric,
+                    tolerance,
+                )?);
+            }
+        }
+        Ok(Face {
+            surface,
+            loops,
+            same_sense: true,
+        })
+    }
+
+    /// Colour of a surface, or of the surface it trims.
+    fn face_color(&self, de: usize) -> Option<Vec3> {
+        self.color(de).or_else(|| {
+            let entry = self.entries.get(&de)?;
+            (entry.kind == 144)
+                .then(|| self.color(self.pointer(de, 1).ok()?))
+                .flatten()
+        })
+    }
+
+    fn color(&self, de: usize) -> Option<Vec3> {
+        let color = self.entries.get(&de)?.color;
+        Some(match color {
+            1 => Vec3::ZERO,
+            2 => Vec3::X,
+            3 => Vec3::Y,
+            4 => Vec3::Z,
+            5 => Vec3::new(1.0, 1.0, 0.0),
+            6 => Vec3::new(1.0, 0.0, 1.0),
+            7 => Vec3::new(0.0, 1.0, 1.0),
+            8 => Vec3::ONE,
+            // Percentages of red, green and blue
+            color if color < 0 => {
+                let de = color.unsigned_abs() as usize;
+                let channel = |i| self.float(de, i).ok().map(|value| value as f32 / 100.0);
+                Vec3::new(channel(1)?, channel(2)?, channel(3)?)
+            }
+            _ => return None,
+        })
+    }
+}
+
+/// Parameter and record delimiters the global section starts by
+/// declaring, or the default comma and semicolon.
+fn delimiters(global: &str) -> (char, char) {
+    fn declared(text: &str, default: char) -> (char, &str) {
+        match text.strip_prefix("1H").and_then(|rest| rest.chars().next()) {
+            Some(c) => (c, &text[2 + c.len_utf8()..]),
+            None => (default, text),
+        }
+    }
+    let (delimiter, rest) = declared(global, ',');
+    let rest = rest.strip_prefix(delimiter).unwrap_or(rest);
+    let (terminator, _) = declared(rest, ';');
+    (delimiter, terminator)
+}
+
+/// Fields of a record up to its terminator, with Hollerith strings like
+/// `5Hhello` unwrapped.
+fn split(text: &str, delimiter: char, terminator: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut rest = text;
+    loop {
+        let trimmed = rest.trim_start();
+        let digits = trimmed.bytes().take_while(u8::is_ascii_digit).count();
+        let field = if digits > 0 && trimmed[digits..].starts_with(['H', 'h']) {
+            let length: usize = trimmed[..digits].parse().unwrap_or(0);
+            let start = digits + 1;
+            let end = trimmed[start..]
+                .char_indices()
+                .nth(length)
+                .map_or(trimmed.len(), |(i, _)| start + i);
+            rest = &trimmed[end..];
+            trimmed[start..end].to_owned()
+        } else {
+            let end = rest.find([delimiter, terminator]).unwrap_or(rest.len());
+            let field = rest[..end].trim().to_owned();
+            rest = &rest[end..];
+            field
+        };
+        fields.push(field);
+        // Whatever follows up to the delimiter belongs to nothing
+        let end = rest.find([delimiter, terminator]);
+        match end.map(|end| (end, rest[end..].chars().next().unwrap())) {
+            Some((end, c)) if c == delimiter => rest = &rest[end + c.len_utf8()..],
+            _ => return fields,
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{bail, Result};
+use glam::{Mat4, Vec3};
+use rayon::prelude::*;
+
+use crate::{
+    lod, nanovdb,
+    scene::{Instance, Material, Mesh, Scene, Volume},
+};
+
+pub use cleanup::{CleanupOptions, CleanupReport};
+pub use environment::{load as load_environment, load_backdrop};
+
+#[cfg(feature = "cad")]
+mod brep;
+mod cleanup;
+mod environment;
+mod gltf;
+#[cfg(feature = "cad")]
+mod iges;
+mod optimize;
+mod ply;
+#[cfg(feature = "cad")]
+mod step;
+mod stl;
+
+/// Axis an asset treats as up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpAxis {
+    Y,
+    Z,
+}
+
+/// Coordinate system of a file format, unless told otherwise.
+#[derive(Clone, Copy, Debug)]
+struct Convention {
+    up_axis: UpAxis,
+    left_handed: bool,
+    meters_per_unit: f32,
+}
+
+const GLTF: Convention = Convention {
+    up_axis: UpAxis::Y,
+    left_handed: false,
+    meters_per_unit: 1.0,
+};
+/// Slicers and CAD packages write Z up millimeters.
+const STL: Convention = Convention {
+    up_axis: UpAxis::Z,
+    left_handed: false,
+    meters_per_unit: 0.001,
+};
+/// Scans and photogrammetry are mostly Z up, in meters.
+const PLY: Convention = Convention {
+    up_axis: UpAxis::Z,
+    left_handed: false,
+    meters_per_unit: 1.0,
+};
+/// VDBs mostly come out of Houdini, which is Y up in meters.
+const NANOVDB: Convention = GLTF;
+/// CAD packages model Z up, and STEP and IGES files declare their unit.
+#[cfg(feature = "cad")]
+const CAD: Convention = STL;
+
+/// Chord tolerance CAD files are tessellated to by default, in meters.
+pub const DEFAULT_TOLERANCE: f32 = 1e-4;
+
+/// Overrides for the coordinate system of an imported file, which is
+/// otherwise assumed to follow the conventions of its format.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ImportOptions {
+    pub up_axis: Option<UpAxis>,
+    pub left_handed: Option<bool>,
+    /// Length of one file unit, for formats without units.
+    pub meters_per_unit: Option<f32>,
+    /// Furthest in meters the triangles of tessellated CAD files may
+    /// stray from the true surfaces.
+    pub tolerance: Option<f32>,
+}
+
+impl ImportOptions {
+    /// Transform from the asset's space into the renderer's Y up, right
+    /// handed space in meters.
+    fn conversion(&self, convention: Convention) -> Mat4 {
+        let up_axis = self.up_axis.unwrap_or(convention.up_axis);
+        let left_handed = self.left_handed.unwrap_or(convention.left_handed);
+        let scale = self.meters_per_unit.unwrap_or(convention.meters_per_unit);
+        // Left handed assets are mirrored along their depth axis
+        let mirror = match (left_handed, up_axis) {
+            (false, _) => Mat4::IDENTITY,
+            (true, UpAxis::Y) => Mat4::from_scale(Vec3::new(1.0, 1.0, -1.0)),
+            (true, UpAxis::Z) => Mat4::from_scale(Vec3::new(1.0, -1.0, 1.0)),
+        };
+        let rotation = match up_axis {
+            UpAxis::Y => Mat4::IDENTITY,
+            UpAxis::Z => Mat4::from_rotation_x(-std::f32::consts::FRAC_PI_2),
+        };
+        Mat4::from_scale(Vec3::splat(scale)) * rotation * mirror
+    }
+
+    /// Chord tolerance in the units of a file declaring `meters_per_unit`.
+    #[cfg(feature = "cad")]
+    fn tolerance_in(&self, meters_per_unit: f32) -> f64 {
+        let meters = self.tolerance.unwrap_or(DEFAULT_TOLERANCE);
+        f64::from(meters / self.meters_per_unit.unwrap_or(meters_per_unit))
+    }
+}
+
+/// Parses lengths like `0.1mm` into meters, bare numbers being meters.
+pub fn parse_length(length: &str) -> Option<f32> {
+    let length = length.trim();
+    let number = length.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = match &length[number.len()..] {
+        "" => 1.0,
+        unit => parse_unit(unit)?,
+    };
+    let value: f32 = number.trim().parse().ok()?;
+    (value > 0.0 && value.is_finite()).then_some(value * unit)
+}
+
+/// Parses unit names like `mm` or `inches` into their length in meters.
+pub fn parse_unit(unit: &str) -> Option<f32> {
+    Some(match unit.to_ascii_lowercase().as_str() {
+        "mm" | "millimeter" | "millimeters" => 0.001,
+        "cm" | "centimeter" | "centimeters" => 0.01,
+        "m" | "meter" | "meters" => 1.0,
+        "in" | "inch" | "inches" => 0.0254,
+        "ft" | "foot" | "feet" => 0.3048,
+        _ => return None,
+    })
+}
+
+pub fn load(path: &Path, options: &ImportOptions) -> Result<Scene> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+
+    let mut scene = match extension.as_deref() {
+        Some("gltf" | "glb") => gltf::load(path)?.transformed(options.conversion(GLTF)),
+        Some("stl") => single_mesh(stl::load(path)?).transformed(options.conversion(STL)),
+        Some("ply") => single_mesh(ply::load(path)?).transformed(options.conversion(PLY)),
+        Some("nvdb") => volumes(path)?.transformed(options.conversion(NANOVDB)),
+        #[cfg(feature = "cad")]
+        Some("step" | "stp") => cad(step::load(path, options)?, options),
+        #[cfg(feature = "cad")]
+        Some("iges" | "igs") => cad(iges::load(path, options)?, options),
+        #[cfg(not(feature = "cad"))]
+        Some("step" | "stp" | "iges" | "igs") => bail!(
+            "Tessellating CAD files needs the cad feature, rebuild with --features cad to load {}",
+            path.display()
+        ),
+        _ => bail!("Unsupported scene format: {}", path.display()),
+    };
+    let options = CleanupOptions::default();
+    let report = scene
+        .meshes
+        .par_iter_mut()
+        .map(|mesh| {
+            let report = cleanup::cleanup(mesh, &options);
+            tracing::debug!("Cleaned up {:?}: {report}", mesh.name);
+            optimize::optimize(mesh);
+            if mesh.tangents.is_empty() {
+                mesh.compute_tangents();
+            }
+            lod::generate(mesh);
+            report
+        })
+        .reduce(CleanupReport::default, |mut total, report| {
+            total += report;
+            total
+        });
+    tracing::info!("Loaded {}: {report}", path.display());
+    Ok(scene)
+}
+
+/// A scene with one instance of `mesh` in the default material.
+fn single_mesh(mesh: Mesh) -> Scene {
+    Scene {
+        meshes: vec![mesh],
+        materials: vec![Material::default()],
+        instances: vec![Instance {
+            mesh: 0,
+            material: 0,
+            transform: Mat4::IDENTITY,
+            lod: 0,
+            body: None,
+            holdout: false,
+            class: 0,
+        }],
+        ..Default::default()
+    }
+}
+
+/// A tessellated CAD scene in the unit its file declares.
+#[cfg(feature = "cad")]
+fn cad((scene, meters_per_unit): (Scene, f32), options: &ImportOptions) -> Scene {
+    scene.transformed(options.conversion(Convention {
+        meters_per_unit,
+        ..CAD
+    }))
+}
+
+/// A scene of smoke from every grid of a NanoVDB file.
+fn volumes(path: &Path) -> Result<Scene> {
+    let volumes = nanovdb::load(path)?
+        .into_iter()
+        .map(|grid| Volume::new(Arc::new(grid)))
+        .collect();
+    Ok(Scene {
+        volumes,
+        ..Default::default()
+    })
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
@@ -0,0 +1,181 @@
+//! Index and vertex buffer optimizations in the spirit of meshoptimizer.
+//! There is no rasterization, so only the passes that shrink the buffers
+//! or improve the locality of vertex fetches while shading are applied.
+
+use std::collections::HashMap;
+
+use crate::scene::Mesh;
+
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+pub fn optimize(mesh: &mut Mesh) {
+    let vertex_count = mesh.positions.len();
+    reindex(mesh);
+    optimize_vertex_cache(&mut mesh.indices, mesh.positions.len());
+    optimize_vertex_fetch(mesh);
+    tracing::debug!(
+        "Optimized {:?}: {} -> {} vertices",
+        mesh.name,
+        vertex_count,
+        mesh.positions.len()
+    );
+}
+
+/// Merges vertices whose attributes are bitwise identical.
+pub fn reindex(mesh: &mut Mesh) {
+    let mut unique: HashMap<[u32; 8], u32> = HashMap::with_capacity(mesh.positions.len());
+    let mut remap = Vec::with_capacity(mesh.positions.len());
+    for v in 0..mesh.positions.len() {
+        let (p, n, uv) = (mesh.positions[v], mesh.normals[v], mesh.uvs[v]);
+        let key = [p.x, p.y, p.z, n.x, n.y, n.z, uv.x, uv.y].map(f32::to_bits);
+        let next = unique.len() as u32;
+        remap.push(*unique.entry(key).or_insert(next));
+    }
+    remap_vertices(mesh, &remap, unique.len());
+}
+
+/// Reorders vertices by first use in the index buffer, dropping any
+/// vertices that aren't referenced at all.
+pub fn optimize_vertex_fetch(mesh: &mut Mesh) {
+    let mut remap = vec![u32::MAX; mesh.positions.len()];
+    let mut next = 0;
+    for &i in &mesh.indices {
+        if remap[i as usize] == u32::MAX {
+            remap[i as usize] = next;
+            next += 1;
+        }
+    }
+    remap_vertices(mesh, &remap, next as usize);
+}
+
+/// Moves every vertex `v` to `remap[v]` and rewrites the indices.
+/// Vertices mapped to `u32::MAX` are dropped.
+fn remap_vertices(mesh: &mut Mesh, remap: &[u32], vertex_count: usize) {
+    fn apply<T: Copy + Default>(values: &[T], remap: &[u32], count: usize) -> Vec<T> {
+        let mut out = vec![T::default(); count];
+        for (value, &to) in values.iter().zip(remap) {
+            if to != u32::MAX {
+                out[to as usize] = *value;
+            }
+        }
+        out
+    }
+    mesh.positions = apply(&mesh.positions, remap, vertex_count);
+    mesh.normals = apply(&mesh.normals, remap, vertex_count);
+    mesh.uvs = apply(&mesh.uvs, remap, vertex_count);
+    if !mesh.tangents.is_empty() {
+        mesh.tangents = apply(&mesh.tangents, remap, vertex_count);
+    }
+    for i in &mut mesh.indices {
+        *i = remap[*i as usize];
+    }
+}
+
+/// Tom Forsyth's linear-speed vertex cache optimization.
+pub fn optimize_vertex_cache(indices: &mut [u32], vertex_count: usize) {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return;
+    }
+
+    let mut vertex_triangles = vec![Vec::new(); vertex_count];
+    for (t, triangle) in indices.chunks_exact(3).enumerate() {
+        for &v in triangle {
+            vertex_triangles[v as usize].push(t as u32);
+        }
+    }
+    let mut remaining: Vec<usize> = vertex_triangles.iter().map(Vec::len).collect();
+    let mut vertex_score: Vec<f32> = (0..vertex_count).map(|v| score(-1, remaining[v])).collect();
+    let triangle_score = |triangle: &[u32], vertex_score: &[f32]| -> f32 {
+        triangle.iter().map(|&v| vertex_score[v as usize]).sum()
+    };
+
+    let mut emitted = vec![false; triangle_count];
+    let mut output = Vec::with_capacity(indices.len());
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut scan_start = 0;
+    let mut best = (0..triangle_count)
+        .max_by(|&a, &b| {
+            let score_a = triangle_score(&indices[3 * a..3 * a + 3], &vertex_score);
+            let score_b = triangle_score(&indices[3 * b..3 * b + 3], &vertex_score);
+            score_a.total_cmp(&score_b)
+        })
+        .unwrap();
+
+    loop {
+        emitted[best] = true;
+        let triangle = [
+            indices[3 * best],
+            indices[3 * best + 1],
+            indices[3 * best + 2],
+        ];
+        output.extend_from_slice(&triangle);
+
+        // Move the triangle's vertices to the front of the LRU cache
+        for &v in triangle.iter().rev() {
+            remaining[v as usize] -= 1;
+            cache.retain(|&c| c != v);
+            cache.insert(0, v);
+        }
+        for &v in cache.iter().skip(CACHE_SIZE) {
+            vertex_score[v as usize] = score(-1, remaining[v as usize]);
+        }
+        cache.truncate(CACHE_SIZE);
+        for (position, &v) in cache.iter().enumerate() {
+            vertex_score[v as usize] = score(position as i32, remaining[v as usize]);
+        }
+
+        // The next triangle is the best one touching the cache, falling back
+        // to the first triangle not emitted yet.
+        let mut next = None;
+        let mut next_score = f32::NEG_INFINITY;
+        for &v in &cache {
+            for &t in &vertex_triangles[v as usize] {
+                let t = t as usize;
+                if emitted[t] {
+                    continue;
+                }
+                let s = triangle_score(&indices[3 * t..3 * t + 3], &vertex_score);
+                if s > next_score {
+                    next_score = s;
+                    next = Some(t);
+                }
+            }
+        }
+        best = match next {
+            Some(t) => t,
+            None => {
+                while scan_start < triangle_count && emitted[scan_start] {
+                    scan_start += 1;
+                }
+                if scan_start == triangle_count {
+                    break;
+                }
+                scan_start
+            }
+        };
+    }
+
+    indices.copy_from_slice(&output);
+}
+
+fn score(cache_position: i32, remaining_triangles: usize) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        p if p < 0 => 0.0,
+        p if p < 3 => LAST_TRIANGLE_SCORE,
+        p => {
+            let scale = 1.0 / (CACHE_SIZE - 3) as f32;
+            (1.0 - (p - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+    };
+    let valence_boost =
+        VALENCE_BOOST_SCALE * (remaining_triangles as f32).powf(-VALENCE_BOOST_POWER);
+    cache_score + valence_boost
+}
@@ -0,0 +1,266 @@
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+use glam::{Vec2, Vec3};
+
+use crate::{import::file_stem, scene::Mesh};
+
+#[derive(Clone, Copy, Debug)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => bail!("Unknown PLY type: {name}"),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar(String, Scalar),
+    List {
+        name: String,
+        count: Scalar,
+        item: Scalar,
+    },
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// Reads the values of the body one at a time, as text or binary.
+enum Body<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { bytes: &'a [u8], big_endian: bool },
+}
+
+impl Body<'_> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64> {
+        match self {
+            Self::Ascii(tokens) => {
+                let token = tokens.next().context("PLY file is truncated")?;
+                token
+                    .parse()
+                    .with_context(|| format!("Invalid PLY value: {token}"))
+            }
+            Self::Binary { bytes, big_endian } => {
+                let size = scalar.size();
+                ensure!(bytes.len() >= size, "PLY file is truncated");
+                let (value, rest) = bytes.split_at(size);
+                *bytes = rest;
+                let mut buffer = [0; 8];
+                buffer[..size].copy_from_slice(value);
+                if *big_endian {
+                    buffer[..size].reverse();
+                }
+                let [b0, b1, b2, b3, ..] = buffer;
+                let word = [b0, b1, b2, b3];
+                Ok(match scalar {
+                    Scalar::I8 => b0 as i8 as f64,
+                    Scalar::U8 => b0 as f64,
+                    Scalar::I16 => i16::from_le_bytes([b0, b1]) as f64,
+                    Scalar::U16 => u16::from_le_bytes([b0, b1]) as f64,
+                    Scalar::I32 => i32::from_le_bytes(word) as f64,
+                    Scalar::U32 => u32::from_le_bytes(word) as f64,
+                    Scalar::F32 => f32::from_le_bytes(word) as f64,
+                    Scalar::F64 => f64::from_le_bytes(buffer),
+                })
+            }
+        }
+    }
+}
+
+/// Loads the vertices and faces of an ASCII or binary PLY file, with
+/// normals and texture coordinates if it has them. Polygons are fanned into
+/// triangles, and smooth normals are generated when missing.
+pub fn load(path: &Path) -> Result<Mesh> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let (elements, format, body) = parse_header(&bytes)?;
+    let mut body = match format {
+        "ascii" => Body::Ascii(
+            std::str::from_utf8(body)
+                .context("PLY file is not valid text")?
+                .split_ascii_whitespace(),
+        ),
+        "binary_little_endian" => Body::Binary {
+            bytes: body,
+            big_endian: false,
+        },
+        "binary_big_endian" => Body::Binary {
+            bytes: body,
+            big_endian: true,
+        },
+        _ => bail!("Unknown PLY format: {format}"),
+    };
+
+    let mut mesh = Mesh {
+        name: file_stem(path),
+        ..Default::default()
+    };
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut values = Vec::new();
+    for element in &elements {
+        let column = |names: &[&str]| {
+            element.properties.iter().position(|property| {
+                matches!(property, Property::Scalar(name, _) if names.contains(&name.as_str()))
+            })
+        };
+        let position = [column(&["x"]), column(&["y"]), column(&["z"])];
+        let normal = [column(&["nx"]), column(&["ny"]), column(&["nz"])];
+        let uv = [
+            column(&["u", "s", "texture_u", "texture_s"]),
+            column(&["v", "t", "texture_v", "texture_t"]),
+        ];
+        let indices = element
+            .properties
+            .iter()
+            .position(|property| match property {
+                Property::List { name, .. } => name == "vertex_indices" || name == "vertex_index",
+                Property::Scalar(..) => false,
+            });
+
+        for _ in 0..element.count {
+            values.clear();
+            let mut polygon = Vec::new();
+            for (i, property) in element.properties.iter().enumerate() {
+                match property {
+                    Property::Scalar(_, scalar) => values.push(body.read(*scalar)?),
+                    Property::List { count, item, .. } => {
+                        values.push(0.0);
+                        let count = body.read(*count)? as usize;
+                        for _ in 0..count {
+                            let value = body.read(*item)?;
+                            if Some(i) == indices {
+                                polygon.push(value as u32);
+                            }
+                        }
+                    }
+                }
+            }
+            if element.name == "vertex" {
+                let get = |columns: &[Option<usize>]| -> Option<Vec<f32>> {
+                    columns
+                        .iter()
+                        .map(|c| c.map(|c| values[c] as f32))
+                        .collect()
+                };
+                let Some(p) = get(&position) else {
+                    bail!("PLY vertices have no position");
+                };
+                mesh.positions.push(Vec3::from_slice(&p));
+                if let Some(n) = get(&normal) {
+                    normals.push(Vec3::from_slice(&n));
+                }
+                if let Some(uv) = get(&uv) {
+                    uvs.push(Vec2::new(uv[0], 1.0 - uv[1]));
+                }
+            } else if element.name == "face" && polygon.len() >= 3 {
+                for i in 1..polygon.len() - 1 {
+                    mesh.indices
+                        .extend([polygon[0], polygon[i], polygon[i + 1]]);
+                }
+            }
+        }
+    }
+
+    let vertex_count = mesh.positions.len();
+    ensure!(!mesh.indices.is_empty(), "{} has no faces", path.display());
+    ensure!(
+        mesh.indices.iter().all(|&i| (i as usize) < vertex_count),
+        "{} has faces with invalid vertex indices",
+        path.display()
+    );
+    if normals.len() == vertex_count {
+        mesh.normals = normals
+            .into_iter()
+            .map(|n| n.try_normalize().unwrap_or(Vec3::Y))
+            .collect();
+    } else {
+        mesh.compute_normals();
+    }
+    mesh.uvs = if uvs.len() == vertex_count {
+        uvs
+    } else {
+        vec![Vec2::ZERO; vertex_count]
+    };
+    Ok(mesh)
+}
+
+/// Splits the file into its elements, its format and the body after the
+/// header.
+fn parse_header(bytes: &[u8]) -> Result<(Vec<Element>, &str, &[u8])> {
+    ensure!(bytes.starts_with(b"ply"), "Not a PLY file");
+    let mut elements: Vec<Element> = Vec::new();
+    let mut format = None;
+    let mut rest = bytes;
+    loop {
+        let end = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .context("PLY header is truncated")?;
+        let line = std::str::from_utf8(&rest[..end]).context("PLY header is not valid text")?;
+        rest = &rest[end + 1..];
+        let words: Vec<_> = line.split_ascii_whitespace().collect();
+        match words.as_slice() {
+            ["end_header"] => break,
+            ["format", name, _version] => format = Some(*name),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .with_context(|| format!("Invalid PLY element count: {count}"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => elements
+                .last_mut()
+                .context("PLY property outside of an element")?
+                .properties
+                .push(Property::List {
+                    name: name.to_string(),
+                    count: Scalar::parse(count)?,
+                    item: Scalar::parse(item)?,
+                }),
+            ["property", scalar, name] => elements
+                .last_mut()
+                .context("PLY property outside of an element")?
+                .properties
+                .push(Property::Scalar(name.to_string(), Scalar::parse(scalar)?)),
+            _ => {}
+        }
+    }
+    let format = format.context("PLY header has no format")?;
+    Ok((elements, format, rest))
+}
@@ -0,0 +1,1199 @@
+//! STEP files (ISO 10303-21) from CAD packages, tessellated straight from
+//! the boundary representations of their solids, with the assembly
+//! placements and colours of the AP203, AP214 and AP242 schemas.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use glam::{DMat4, DVec2, DVec3, Vec3};
+use rayon::prelude::*;
+
+use super::{
+    brep::{self, BoundaryPoint, Curve, Face, Frame, NurbsCurve, NurbsSurface, Surface},
+    file_stem, ImportOptions,
+};
+use crate::scene::{Instance, Material, Mesh, Scene};
+
+/// Loads the solids of a STEP file, along with the length of its unit in
+/// meters.
+pub fn load(path: &Path, options: &ImportOptions) -> Result<(Scene, f32)> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let text = String::from_utf8_lossy(&bytes);
+    let entities = parse(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+    let mut step = Step {
+        entities,
+        tolerance: 0.0,
+        angle: 1.0,
+    };
+    let (length, angle) = step.units();
+    step.tolerance = options.tolerance_in(length as f32);
+    step.angle = angle;
+
+    let placements = step.placements();
+    ensure!(!placements.is_empty(), "{} has no solids", path.display());
+    let colors = step.colors();
+
+    // Each body is tessellated once, however many times it's placed
+    let mut bodies: Vec<u64> = placements.iter().map(|(body, _)| *body).collect();
+    bodies.sort_unstable();
+    bodies.dedup();
+    let tessellated: Vec<_> = bodies
+        .par_iter()
+        .map(|&body| step.tessellate(body, &colors))
+        .collect();
+
+    let mut scene = Scene {
+        materials: vec![Material::default()],
+        ..Default::default()
+    };
+    let mut materials = HashMap::new();
+    let mut parts = HashMap::new();
+    let mut skipped = 0;
+    for (&body, result) in bodies.iter().zip(tessellated) {
+        let (meshes, failures) = result.unwrap_or_else(|error| {
+            tracing::warn!("Skipped body #{body} of {}: {error:#}", path.display());
+            (Vec::new(), 0)
+        });
+        skipped += failures;
+        let name = step.name(body).unwrap_or_else(|| file_stem(path));
+        let mut indices = Vec::new();
+        for (color, mut mesh) in meshes {
+            let material = match color {
+                None => 0,
+                Some(color) => *materials
+                    .entry(color.to_array().map(f32::to_bits))
+                    .or_insert_with(|| {
+                        scene.materials.push(brep::material(color));
+                        scene.materials.len() - 1
+                    }),
+            };
+            mesh.name = name.clone();
+            indices.push((scene.meshes.len(), material));
+            scene.meshes.push(mesh);
+        }
+        parts.insert(body, indices);
+    }
+    if skipped > 0 {
+        tracing::warn!(
+            "Skipped {skipped} faces of {} that couldn't be tessellated",
+            path.display()
+        );
+    }
+    for (body, transform) in placements {
+        for &(mesh, material) in &parts[&body] {
+            scene.instances.push(Instance {
+                mesh,
+                material,
+                transform: transform.as_mat4(),
+                lod: 0,
+                body: None,
+                holdout: false,
+                class: 0,
+            });
+        }
+    }
+    ensure!(
+        !scene.meshes.is_empty(),
+        "{} has no faces that could be tessellated",
+        path.display()
+    );
+    Ok((scene, length as f32))
+}
+
+#[derive(Clone, Debug)]
+enum Param {
+    Ref(u64),
+    Number(f64),
+    Str(String),
+    Enum(String),
+    List(Vec<Param>),
+    /// `$` for a missing value, or `*` for one derived from the others.
+    Unset,
+    /// Value wrapped in its type, like `LENGTH_MEASURE(2.5)`.
+    Typed(Vec<Param>),
+}
+
+impl Param {
+    fn id(&self) -> Result<u64> {
+        match self {
+            Self::Ref(id) => Ok(*id),
+            _ => bail!("Expected a reference, found {self:?}"),
+        }
+    }
+
+    fn number(&self) -> Result<f64> {
+        match self {
+            Self::Number(value) => Ok(*value),
+            Self::Typed(params) if params.len() == 1 => params[0].number(),
+            _ => bail!("Expected a number, found {self:?}"),
+        }
+    }
+
+    fn list(&self) -> Result<&[Param]> {
+        match self {
+            Self::List(params) => Ok(params),
+            _ => bail!("Expected a list, found {self:?}"),
+        }
+    }
+
+    fn boolean(&self) -> Result<bool> {
+        match self {
+            Self::Enum(value) if value == "T" => Ok(true),
+            Self::Enum(value) if value == "F" => Ok(false),
+            _ => bail!("Expected a boolean, found {self:?}"),
+        }
+    }
+
+    fn numbers(&self) -> Result<Vec<f64>> {
+        self.list()?.iter().map(Param::number).collect()
+    }
+
+    fn ids(&self) -> Result<Vec<u64>> {
+        self.list()?.iter().map(Param::id).collect()
+    }
+}
+
+/// Instance of one entity type, or of several at once for complex
+/// entities, each with their own attributes.
+#[derive(Debug)]
+struct Entity {
+    records: Vec<(String, Vec<Param>)>,
+}
+
+impl Entity {
+    fn is(&self, name: &str) -> bool {
+        self.records.iter().any(|(record, _)| record == name)
+    }
+
+    fn record(&self, name: &str) -> Option<&[Param]> {
+        self.records
+            .iter()
+            .find(|(record, _)| record == name)
+            .map(|(_, params)| params.as_slice())
+    }
+
+    /// Type and attributes of a simple entity.
+    fn simple(&self) -> (&str, &[Param]) {
+        let (name, params) = &self.records[0];
+        (name, params)
+    }
+}
+
+/// Parses the DATA section into entities by their instance number.
+fn parse(text: &str) -> Result<HashMap<u64, Entity>> {
+    let data = text.find("DATA;").context("No DATA section")? + "DATA;".len();
+    let mut parser = Parser {
+        bytes: &text.as_bytes()[data..],
+        position: 0,
+    };
+    let mut entities = HashMap::new();
+    loop {
+        parser.skip_space();
+        if parser.peek() != Some(b'#') {
+            break;
+        }
+        parser.position += 1;
+        let id = parser.integer()?;
+        parser.expect(b'=')?;
+        parser.skip_space();
+        let records = if parser.peek() == Some(b'(') {
+            parser.position += 1;
+            let mut records = Vec::new();
+            while parser.peek_past_space() != Some(b')') {
+                records.push(parser.record()?);
+            }
+            parser.expect(b')')?;
+            records
+        } else {
+            vec![parser.record()?]
+        };
+        parser.expect(b';')?;
+        entities.insert(id, Entity { records });
+    }
+    ensure!(!entities.is_empty(), "No entities");
+    Ok(entities)
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    fn peek_past_space(&mut self) -> Option<u8> {
+        self.skip_space();
+        self.peek()
+    }
+
+    /// Skips whitespace and comments.
+    fn skip_space(&mut self) {
+        loop {
+            match self.peek() {
+                Some(byte) if byte.is_ascii_whitespace() => self.position += 1,
+                Some(b'/') if self.bytes.get(self.position + 1) == Some(&b'*') => {
+                    let rest = &self.bytes[self.position + 2..];
+                    let end = rest.windows(2).position(|pair| pair == b"*/");
+                    self.position += end.map_or(rest.len() + 2, |end| end + 4);
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        self.skip_space();
+        ensure!(
+            self.peek() == Some(byte),
+            "Expected '{}' at byte {} of the data",
+            byte as char,
+            self.position
+        );
+        self.position += 1;
+        Ok(())
+    }
+
+    fn take_while(&mut self, f: impl Fn(u8) -> bool) -> &str {
+        let start = self.position;
+        while self.peek().is_some_and(&f) {
+            self.position += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.position]).unwrap_or_default()
+    }
+
+    fn integer(&mut self) -> Result<u64> {
+        let digits = self.take_while(|byte| byte.is_ascii_digit());
+        digits
+            .parse()
+            .with_context(|| format!("Expected an instance number at byte {}", self.position))
+    }
+
+    fn keyword(&mut self) -> String {
+        self.take_while(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'!')
+            .to_ascii_uppercase()
+    }
+
+    fn record(&mut self) -> Result<(String, Vec<Param>)> {
+        self.skip_space();
+        let name = self.keyword();
+        ensure!(
+            !name.is_empty(),
+            "Expected an entity name at byte {}",
+            self.position
+        );
+        let params = self.list()?;
+        Ok((name, params))
+    }
+
+    fn list(&mut self) -> Result<Vec<Param>> {
+        self.expect(b'(')?;
+        let mut params = Vec::new();
+        if self.peek_past_space() == Some(b')') {
+            self.position += 1;
+            return Ok(params);
+        }
+        loop {
+            params.push(self.param()?);
+            match self.peek_past_space() {
+                Some(b',') => self.position += 1,
+                Some(b')') => {
+                    self.position += 1;
+                    return Ok(params);
+                }
+                _ => bail!("Expected ',' or ')' at byte {} of the data", self.position),
+            }
+        }
+    }
+
+    fn param(&mut self) -> Result<Param> {
+        let Some(byte) = self.peek_past_space() else {
+            bail!("Data ends in the middle of an entity");
+        };
+        Ok(match byte {
+            b'#' => {
+                self.position += 1;
+                Param::Ref(self.integer()?)
+            }
+            b'$' | b'*' => {
+                self.position += 1;
+                Param::Unset
+            }
+            b'(' => Param::List(self.list()?),
+            b'.' if self
+                .bytes
+                .get(self.position + 1)
+                .is_some_and(u8::is_ascii_alphabetic) =>
+            {
+                self.position += 1;
+                let value = self.keyword();
+                self.expect(b'.')?;
+                Param::Enum(value)
+            }
+            b'\'' => {
+                self.position += 1;
+                let mut value = Vec::new();
+                loop {
+                    match self.peek() {
+                        None => bail!("String runs to the end of the data"),
+                        Some(b'\'') if self.bytes.get(self.position + 1) == Some(&b'\'') => {
+                            value.push(b'\'');
+                            self.position += 2;
+                        }
+                        Some(b'\'') => {
+                            self.position += 1;
+                            break;
+                        }
+                        Some(byte) => {
+                            value.push(byte);
+                            self.position += 1;
+                        }
+                    }
+                }
+                Param::Str(String::from_utf8_lossy(&value).into_owned())
+            }
+            b'"' => {
+                self.position += 1;
+                let value = self.take_while(|byte| byte != b'"').to_owned();
+                self.expect(b'"')?;
+                Param::Str(value)
+            }
+            byte if byte.is_ascii_alphabetic() => {
+                self.keyword();
+                Param::Typed(self.list()?)
+            }
+            _ => {
+                let number = self.take_while(|byte| {
+                    byte.is_ascii_digit() || matches!(byte, b'+' | b'-' | b'.' | b'E' | b'e')
+                });
+                Param::Number(
+                    number
+                        .parse()
+                        .with_context(|| format!("Invalid number {number:?}"))?,
+                )
+            }
+        })
+    }
+}
+
+/// A mesh with the colour of its faces, if they have one.
+type ColoredMesh = (Option<Vec3>, Mesh);
+
+struct Step {
+    entities: HashMap<u64, Entity>,
+    /// Chord tolerance in the file's length unit.
+    tolerance: f64,
+    /// Radians per plane angle unit.
+    angle: f64,
+}
+
+/// Faces of a body, whether each is turned inside out, and its colour.
+type BodyFaces = Vec<(u64, bool, Option<Vec3>)>;
+
+impl Step {
+    fn get(&self, id: u64) -> Result<&Entity> {
+        self.entities
+            .get(&id)
+            .with_context(|| format!("Missing entity #{id}"))
+    }
+
+    /// Attributes of a simple entity of one of the given types.
+    fn attributes(&self, id: u64, types: &[&str]) -> Result<&[Param]> {
+        let (name, params) = self.get(id)?.simple();
+        ensure!(
+            types.contains(&name),
+            "Expected #{id} to be {}, found {name}",
+            types.join(" or ")
+        );
+        Ok(params)
+    }
+
+    fn name(&self, id: u64) -> Option<String> {
+        match self.entities.get(&id)?.simple().1.first() {
+            Some(Param::Str(name)) if !name.is_empty() => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    /// Meters per length unit and radians per plane angle unit, from the
+    /// units of the first representation context that has them.
+    fn units(&self) -> (f64, f64) {
+        let mut ids: Vec<&u64> = self.entities.keys().collect();
+        ids.sort_unstable();
+        let (mut length, mut angle) = (None, None);
+        for id in ids {
+            let Some(units) = self.entities[id].record("GLOBAL_UNIT_ASSIGNED_CONTEXT") else {
+                continue;
+            };
+            for unit in units
+                .first()
+                .and_then(|units| units.ids().ok())
+                .unwrap_or_default()
+            {
+                let Ok(entity) = self.get(unit) else {
+                    continue;
+                };
+                let value = self.unit(unit).ok();
+                if entity.is("LENGTH_UNIT") && length.is_none() {
+                    length = value;
+                } else if entity.is("PLANE_ANGLE_UNIT") && angle.is_none() {
+                    angle = value;
+                }
+            }
+        }
+        if length.is_none() {
+            tracing::warn!("STEP file doesn't give its length unit, assuming millimeters");
+        }
+        (length.unwrap_or(0.001), angle.unwrap_or(1.0))
+    }
+
+    /// Size of a unit in meters or radians.
+    fn unit(&self, id: u64) -> Result<f64> {
+        let entity = self.get(id)?;
+        if let Some(params) = entity.record("SI_UNIT") {
+            let prefix = match params.first() {
+                Some(Param::Enum(prefix)) => match prefix.as_str() {
+                    "KILO" => 1e3,
+                    "HECTO" => 1e2,
+                    "DECA" => 1e1,
+                    "DECI" => 1e-1,
+                    "CENTI" => 1e-2,
+                    "MILLI" => 1e-3,
+                    "MICRO" => 1e-6,
+                    "NANO" => 1e-9,
+                    _ => bail!("Unsupported unit prefix {prefix}"),
+                },
+                _ => 1.0,
+            };
+            return Ok(prefix);
+        }
+        if let Some(params) = entity.record("CONVERSION_BASED_UNIT") {
+            // A measure of some other unit, like 25.4 millimeters
+            let measure = self.get(params[1].id()?)?;
+            let params = &measure.records[0].1;
+            return Ok(params[0].number()? * self.unit(params[1].id()?)?);
+        }
+        bail!("Unsupported unit #{id}")
+    }
+
+    fn point(&self, id: u64) -> Result<DVec3> {
+        let params = self.attributes(id, &["CARTESIAN_POINT"])?;
+        let coordinates = params[1].numbers()?;
+        ensure!(
+            (2..=3).contains(&coordinates.len()),
+            "Point #{id} has {} coordinates",
+            coordinates.len()
+        );
+        Ok(DVec3::new(
+            coordinates[0],
+            coordinates[1],
+            coordinates.get(2).copied().unwrap_or(0.0),
+        ))
+    }
+
+    fn direction(&self, id: u64) -> Result<DVec3> {
+        let params = self.attributes(id, &["DIRECTION"])?;
+        let ratios = params[1].numbers()?;
+        Ok(DVec3::new(
+            ratios[0],
+            ratios.get(1).copied().unwrap_or(0.0),
+            ratios.get(2).copied().unwrap_or(0.0),
+        ))
+    }
+
+    fn optional_direction(&self, param: &Param) -> Result<Option<DVec3>> {
+        match param {
+            Param::Unset => Ok(None),
+            param => Ok(Some(self.direction(param.id()?)?)),
+        }
+    }
+
+    fn frame(&self, id: u64) -> Result<Frame> {
+        let params = self.attributes(
+            id,
+            &[
+                "AXIS2_PLACEMENT_3D",
+                "AXIS2_PLACEMENT_2D",
+                "AXIS1_PLACEMENT",
+            ],
+        )?;
+        let origin = self.point(params[1].id()?)?;
+        match self.get(id)?.simple().0 {
+            "AXIS2_PLACEMENT_3D" => {
+                let z = self.optional_direction(&params[2])?.unwrap_or(DVec3::Z);
+                let x = self.optional_direction(&params[3])?;
+                Ok(Frame::new(origin, z, x))
+            }
+            "AXIS2_PLACEMENT_2D" => Ok(Frame::new(
+                origin,
+                DVec3::Z,
+                self.optional_direction(&params[2])?,
+            )),
+            _ => Ok(Frame::new(
+                origin,
+                self.optional_direction(&params[2])?.unwrap_or(DVec3::Z),
+                None,
+            )),
+        }
+    }
+
+    fn curve(&self, id: u64) -> Result<Curve> {
+        let entity = self.get(id)?;
+        if entity.is("B_SPLINE_CURVE") || entity.simple().0.contains("B_SPLINE_CURVE") {
+            return Ok(Curve::Nurbs(self.b_spline_curve(id)?));
+        }
+        let (name, params) = entity.simple();
+        Ok(match name {
+            "LINE" => {
+                let origin = self.point(params[1].id()?)?;
+                let vector = self.attributes(params[2].id()?, &["VECTOR"])?;
+                let direction =
+                    self.direction(vector[1].id()?)?.normalize() * vector[2].number()?;
+                Curve::Line { origin, direction }
+            }
+            "CIRCLE" => Curve::Ellipse {
+                frame: self.frame(params[1].id()?)?,
+                radii: DVec2::splat(params[2].number()?),
+            },
+            "ELLIPSE" => Curve::Ellipse {
+                frame: self.frame(params[1].id()?)?,
+                radii: DVec2::new(params[2].number()?, params[3].number()?),
+            },
+            "POLYLINE" => Curve::Polyline(
+                params[1]
+                    .ids()?
+                    .into_iter()
+                    .map(|point| self.point(point))
+                    .collect::<Result<_>>()?,
+            ),
+            // Edges give their own ends, so only the underlying curve matters
+            "SURFACE_CURVE" | "SEAM_CURVE" | "TRIMMED_CURVE" => self.curve(params[1].id()?)?,
+            _ => bail!("Unsupported curve {name} #{id}"),
+        })
+    }
+
+    /// B-spline curve in its simple form, or as a complex entity when it's
+    /// rational.
+    fn b_spline_curve(&self, id: u64) -> Result<NurbsCurve> {
+        let entity = self.get(id)?;
+        let (kind, curve, knots, weights) = match entity.record("B_SPLINE_CURVE") {
+            Some(curve) => {
+                let kind = ["BEZIER_CURVE", "UNIFORM_CURVE", "QUASI_UNIFORM_CURVE"]
+                    .into_iter()
+                    .find(|kind| entity.is(kind))
+                    .unwrap_or("B_SPLINE_CURVE_WITH_KNOTS");
+                let knots = entity.record("B_SPLINE_CURVE_WITH_KNOTS");
+                let weights = entity
+                    .record("RATIONAL_B_SPLINE_CURVE")
+                    .map(|params| &params[0]);
+                (kind, curve, knots, weights)
+            }
+            None => {
+                let (kind, params) = entity.simple();
+                ensure!(params.len() >= 6, "Truncated B-spline curve #{id}");
+                let knots = (kind == "B_SPLINE_CURVE_WITH_KNOTS").then(|| &params[6..]);
+                (kind, &params[1..], knots, None)
+            }
+        };
+        let degree = curve[0].number()? as usize;
+        let points: Vec<DVec3> = curve[1]
+            .ids()?
+            .into_iter()
+            .map(|point| self.point(point))
+            .collect::<Result<_>>()?;
+        let knots = match knots {
+            Some(knots) => brep::expand_knots(&knots[1].numbers()?, &multiplicities(&knots[0])?)?,
+            None => implicit_knots(kind, degree, points.len())?,
+        };
+        let weights = weights.map(Param::numbers).transpose()?;
+        NurbsCurve::new(degree, knots, &points, weights.as_deref())
+            .with_context(|| format!("Invalid B-spline curve #{id}"))
+    }
+
+    fn surface(&self, id: u64) -> Result<Surface> {
+        let entity = self.get(id)?;
+        if entity.is("B_SPLINE_SURFACE") || entity.simple().0.contains("B_SPLINE_SURFACE") {
+            return Ok(Surface::Nurbs(self.b_spline_surface(id)?));
+        }
+        let (name, params) = entity.simple();
+        Ok(match name {
+            "PLANE" => Surface::Plane(self.frame(params[1].id()?)?),
+            "CYLINDRICAL_SURFACE" => Surface::Cylinder {
+                frame: self.frame(params[1].id()?)?,
+                radius: params[2].number()?,
+            },
+            "CONICAL_SURFACE" => Surface::Cone {
+                frame: self.frame(params[1].id()?)?,
+                radius: params[2].number()?,
+                semi_angle: params[3].number()? * self.angle,
+            },
+            "SPHERICAL_SURFACE" => Surface::Sphere {
+                frame: self.frame(params[1].id()?)?,
+                radius: params[2].number()?,
+            },
+            "TOROIDAL_SURFACE" | "DEGENERATE_TOROIDAL_SURFACE" => Surface::Torus {
+                frame: self.frame(params[1].id()?)?,
+                major: params[2].number()?,
+                minor: params[3].number()?,
+            },
+            "SURFACE_OF_REVOLUTION" => {
+                let axis = self.frame(params[2].id()?)?;
+                Surface::Revolution {
+                    curve: Box::new(self.curve(params[1].id()?)?),
+                    origin: axis.origin,
+                    axis: axis.z,
+                }
+            }
+            "SURFACE_OF_LINEAR_EXTRUSION" => {
+                let vector = self.attributes(params[2].id()?, &["VECTOR"])?;
+                Surface::Extrusion {
+                    curve: Box::new(self.curve(params[1].id()?)?),
+                    direction: self.direction(vector[1].id()?)?.normalize() * vector[2].number()?,
+                }
+            }
+            // Faces give their own bounds, so only the underlying surface
+            // matters
+            "RECTANGULAR_TRIMMED_SURFACE" => self.surface(params[1].id()?)?,
+            _ => bail!("Unsupported surface {name} #{id}"),
+        })
+    }
+
+    fn b_spline_surface(&self, id: u64) -> Result<NurbsSurface> {
+        let entity = self.get(id)?;
+        let (kind, surface, knots, weights) = match entity.record("B_SPLINE_SURFACE") {
+            Some(surface) => {
+                let kind = ["BEZIER_SURFACE", "UNIFORM_SURFACE", "QUASI_UNIFORM_SURFACE"]
+                    .into_iter()
+                    .find(|kind| entity.is(kind))
+                    .unwrap_or("B_SPLINE_SURFACE_WITH_KNOTS");
+                let knots = entity.record("B_SPLINE_SURFACE_WITH_KNOTS");
+                let weights = entity
+                    .record("RATIONAL_B_SPLINE_SURFACE")
+                    .map(|params| &params[0]);
+                (kind, surface, knots, weights)
+            }
+            None => {
+                let (kind, params) = entity.simple();
+                ensure!(params.len() >= 8, "Truncated B-spline surface #{id}");
+                let knots = (kind == "B_SPLINE_SURFACE_WITH_KNOTS").then(|| &params[8..]);
+                (kind, &params[1..], knots, None)
+            }
+        };
+        let degrees = (surface[0].number()? as usize, surface[1].number()? as usize);
+        let points: Vec<Vec<DVec3>> = surface[2]
+            .list()?
+            .iter()
+            .map(|row| {
+                row.ids()?
+                    .into_iter()
+                    .map(|point| self.point(point))
+                    .collect()
+            })
+            .collect::<Result<_>>()?;
+        ensure!(!points.is_empty(), "B-spline surface #{id} has no points");
+        let counts = (points.len(), points[0].len());
+        let knots = match knots {
+            Some(knots) => (
+                brep::expand_knots(&knots[2].numbers()?, &multiplicities(&knots[0])?)?,
+                brep::expand_knots(&knots[3].numbers()?, &multiplicities(&knots[1])?)?,
+            ),
+            None => {
+                let kind = kind.replace("SURFACE", "CURVE");
+                (
+                    implicit_knots(&kind, degrees.0, counts.0)?,
+                    implicit_knots(&kind, degrees.1, counts.1)?,
+                )
+            }
+        };
+        let weights: Option<Vec<Vec<f64>>> = weights
+            .map(|weights| {
+                weights
+                    .list()?
+                    .iter()
+                    .map(Param::numbers)
+                    .collect::<Result<_>>()
+            })
+            .transpose()?;
+        NurbsSurface::new(degrees, knots, &points, weights.as_deref())
+            .with_context(|| format!("Invalid B-spline surface #{id}"))
+    }
+
+    fn vertex(&self, id: u64) -> Result<DVec3> {
+        let params = self.attributes(id, &["VERTEX_POINT"])?;
+        self.point(params[1].id()?)
+    }
+
+    /// Points along an edge from its start vertex to its end, sampled once
+    /// for both faces it bounds.
+    fn edge(&self, id: u64, edges: &mut HashMap<u64, Vec<DVec3>>) -> Result<Vec<DVec3>> {
+        if let Some(points) = edges.get(&id) {
+            return Ok(points.clone());
+        }
+        let params = self.attributes(id, &["EDGE_CURVE"])?;
+        let (start, end) = (params[1].id()?, params[2].id()?);
+        let ends = [self.vertex(start)?, self.vertex(end)?];
+        let curve = self.curve(params[3].id()?)?;
+        let same_sense = params[4].boolean()?;
+        let closed = start == end || ends[0] == ends[1];
+        let (mut t0, mut t1) = (curve.closest(ends[0]), curve.closest(ends[1]));
+        if let Some(period) = curve.period() {
+            if same_sense && t1 <= t0 + 1e-9 {
+                t1 += period;
+            } else if !same_sense && t1 >= t0 - 1e-9 {
+                t1 -= period;
+            }
+        } else if closed {
+            let (lo, hi) = curve.domain();
+            (t0, t1) = if same_sense { (lo, hi) } else { (hi, lo) };
+        }
+        let points = brep::sample_edge(&curve, t0, t1, ends, self.tolerance);
+        edges.insert(id, points.clone());
+        Ok(points)
+    }
+
+    /// Points around a loop, reversed when the bound runs against it.
+    fn boundary(&self, id: u64, edges: &mut HashMap<u64, Vec<DVec3>>) -> Result<Vec<DVec3>> {
+        let (name, params) = self.get(id)?.simple();
+        let mut points: Vec<DVec3> = Vec::new();
+        match name {
+            "EDGE_LOOP" => {
+                for oriented in params[1].ids()? {
+                    let oriented = self.attributes(oriented, &["ORIENTED_EDGE"])?;
+                    let mut edge = self.edge(oriented[3].id()?, edges)?;
+                    if !oriented[4].boolean()? {
+                        edge.reverse();
+                    }
+                    let skip = usize::from(points.last() == edge.first());
+                    points.extend(&edge[skip..]);
+                }
+                if points.len() > 1 && points.first() == points.last() {
+                    points.pop();
+                }
+            }
+            "POLY_LOOP" => {
+                for point in params[1].ids()? {
+                    points.push(self.point(point)?);
+                }
+            }
+            "VERTEX_LOOP" => points.push(self.vertex(params[1].id()?)?),
+            _ => bail!("Unsupported loop {name} #{id}"),
+        }
+        Ok(points)
+    }
+
+    fn face(&self, id: u64, flipped: bool, edges: &mut HashMap<u64, Vec<DVec3>>) -> Result<Face> {
+        let (name, params) = self.get(id)?.simple();
+        let mut loops = Vec::new();
+        let mut bounds = params[1].ids()?;
+        // The outer bound goes first, as the reference for the others
+        bounds.sort_by_key(|&bound| {
+            self.entities
+                .get(&bound)
+                .is_none_or(|entity| !entity.is("FACE_OUTER_BOUND"))
+        });
+        for bound in bounds {
+            let params = self.attributes(bound, &["FACE_BOUND", "FACE_OUTER_BOUND"])?;
+            let mut points = self.boundary(params[1].id()?, edges)?;
+            if !params[2].boolean()? {
+                points.reverse();
+            }
+            loops.push(points);
+        }
+        let (surface, same_sense) = match name {
+            "ADVANCED_FACE" | "FACE_SURFACE" => {
+                (self.surface(params[2].id()?)?, params[3].boolean()?)
+            }
+            "FACE" => (
+                plane_through(loops.first().context("Face without bounds")?),
+                true,
+            ),
+            _ => bail!("Unsupported face {name} #{id}"),
+        };
+        if flipped {
+            loops.iter_mut().for_each(|points| points.reverse());
+        }
+        Ok(Face {
+            surface,
+            loops: loops
+                .into_iter()
+                .map(|points| {
+                    points
+                        .into_iter()
+                        .map(|position| BoundaryPoint { position, uv: None })
+                        .collect()
+                })
+                .collect(),
+            same_sense: same_sense != flipped,
+        })
+    }
+
+    /// Faces of a solid, shell or surface model, with the colour each
+    /// inherits from whatever it's styled through.
+    fn body_faces(
+        &self,
+        id: u64,
+        flipped: bool,
+        color: Option<Vec3>,
+        colors: &HashMap<u64, Vec3>,
+        faces: &mut BodyFaces,
+    ) -> Result<()> {
+        let color = colors.get(&id).copied().or(color);
+        let (name, params) = self.get(id)?.simple();
+        match name {
+            "MANIFOLD_SOLID_BREP" | "FACETED_BREP" => {
+                self.body_faces(params[1].id()?, flipped, color, colors, faces)?
+            }
+            "BREP_WITH_VOIDS" => {
+                self.body_faces(params[1].id()?, flipped, color, colors, faces)?;
+                for void in params[2].ids()? {
+                    self.body_faces(void, flipped, color, colors, faces)?;
+                }
+            }
+            "SHELL_BASED_SURFACE_MODEL" => {
+                for shell in params[1].ids()? {
+                    self.body_faces(shell, flipped, color, colors, faces)?;
+                }
+            }
+            "CLOSED_SHELL" | "OPEN_SHELL" => {
+                for face in params[1].ids()? {
+                    self.body_faces(face, flipped, color, colors, faces)?;
+                }
+            }
+            "ORIENTED_CLOSED_SHELL" | "ORIENTED_OPEN_SHELL" | "ORIENTED_FACE" => {
+                let flipped = flipped == params[3].boolean()?;
+                self.body_faces(params[2].id()?, flipped, color, colors, faces)?;
+            }
+            "ADVANCED_FACE" | "FACE_SURFACE" | "FACE" => faces.push((id, flipped, color)),
+            _ => bail!("Unsupported body {name} #{id}"),
+        }
+        Ok(())
+    }
+
+    /// Meshes of a body, one per colour, and the number of its faces that
+    /// couldn't be tessellated.
+    fn tessellate(
+        &self,
+        body: u64,
+        colors: &HashMap<u64, Vec3>,
+    ) -> Result<(Vec<ColoredMesh>, usize)> {
+        let mut faces = Vec::new();
+        self.body_faces(body, false, None, colors, &mut faces)?;
+        let mut edges = HashMap::new();
+        let mut meshes: Vec<ColoredMesh> = Vec::new();
+        let mut failures = 0;
+        for (id, flipped, color) in faces {
+            let index = match meshes.iter().position(|(c, _)| *c == color) {
+                Some(index) => index,
+                None => {
+                    meshes.push((color, Mesh::default()));
+                    meshes.len() - 1
+                }
+            };
+            let result = self
+                .face(id, flipped, &mut edges)
+                .and_then(|face| brep::tessellate(&face, self.tolerance, &mut meshes[index].1));
+            if let Err(error) = result {
+                tracing::debug!("Skipped face #{id}: {error:#}");
+                failures += 1;
+            }
+        }
+        meshes.retain(|(_, mesh)| !mesh.indices.is_empty());
+        for (_, mesh) in &mut meshes {
+            brep::fill_normals(mesh);
+        }
+        Ok((meshes, failures))
+    }
+
+    /// Items of a representation, if `id` is one.
+    fn items(&self, id: u64) -> Option<Vec<u64>> {
+        self.entities
+            .get(&id)?
+            .records
+            .iter()
+            .find_map(|(name, params)| {
+                let items = params.get(1)?;
+                (name.ends_with("REPRESENTATION") && params.len() >= 3)
+                    .then(|| items.ids().ok())
+                    .flatten()
+            })
+    }
+
+    /// Every placement of every body, walking the assembly down from its
+    /// top level representations.
+    fn placements(&self) -> Vec<(u64, DMat4)> {
+        const BODIES: [&str; 5] = [
+            "MANIFOLD_SOLID_BREP",
+            "BREP_WITH_VOIDS",
+            "FACETED_BREP",
+            "SHELL_BASED_SURFACE_MODEL",
+            "CLOSED_SHELL",
+        ];
+        let is_body = |id: u64| {
+            self.entities
+                .get(&id)
+                .is_some_and(|entity| BODIES.contains(&entity.simple().0))
+        };
+        let mut ids: Vec<u64> = self.entities.keys().copied().collect();
+        ids.sort_unstable();
+        let representations: Vec<u64> = ids
+            .iter()
+            .copied()
+            .filter(|&id| self.items(id).is_some())
+            .collect();
+
+        // Representations related without a transformation share a space,
+        // like a part's shape and the solid model it's made of
+        let mut space: HashMap<u64, u64> = representations.iter().map(|&id| (id, id)).collect();
+        fn find(space: &HashMap<u64, u64>, mut id: u64) -> u64 {
+            while let Some(&parent) = space.get(&id).filter(|&&parent| parent != id) {
+                id = parent;
+            }
+            id
+        }
+        let mut children: HashMap<u64, Vec<(u64, DMat4)>> = HashMap::new();
+        let mut placed = HashSet::new();
+        for &id in &ids {
+            let entity = &self.entities[&id];
+            let Some(params) = entity.record("REPRESENTATION_RELATIONSHIP").or_else(|| {
+                entity
+                    .record("SHAPE_REPRESENTATION_RELATIONSHIP")
+                    .filter(|params| params.len() >= 4)
+            }) else {
+                continue;
+            };
+            let (Ok(first), Ok(second)) = (params[2].id(), params[3].id()) else {
+                continue;
+            };
+            let transform = entity
+                .record("REPRESENTATION_RELATIONSHIP_WITH_TRANSFORMATION")
+                .and_then(|params| params.first()?.id().ok());
+            match transform {
+                None => {
+                    let (a, b) = (find(&space, first), find(&space, second));
+                    if a != b {
+                        space.insert(a, b);
+                    }
+                }
+                Some(transform) => {
+                    let Ok((child, parent, matrix)) = self.relationship(first, second, transform)
+                    else {
+                        tracing::warn!("Skipped assembly placement #{id}");
+                        continue;
+                    };
+                    children.entry(parent).or_default().push((child, matrix));
+                    placed.insert(child);
+                }
+            }
+        }
+        // Mapped items place another representation inside this one
+        for &id in &representations {
+            for item in self.items(id).unwrap_or_default() {
+                let Some(params) = self
+                    .entities
+                    .get(&item)
+                    .and_then(|entity| entity.record("MAPPED_ITEM"))
+                else {
+                    continue;
+                };
+                let mapped = (|| {
+                    let map = self.attributes(params[1].id()?, &["REPRESENTATION_MAP"])?;
+                    let origin = self.frame(map[0].id()?)?.matrix();
+                    let target = self.frame(params[2].id()?)?.matrix();
+                    Ok::<_, anyhow::Error>((map[1].id()?, target * origin.inverse()))
+                })();
+                if let Ok((child, matrix)) = mapped {
+                    children.entry(id).or_default().push((child, matrix));
+                    placed.insert(child);
+                }
+            }
+        }
+
+        // Gathers everything by the space it's in
+        let mut members: HashMap<u64, Vec<u64>> = HashMap::new();
+        for &id in &representations {
+            members.entry(find(&space, id)).or_default().push(id);
+        }
+        let placed: HashSet<u64> = placed.iter().map(|&id| find(&space, id)).collect();
+        let mut roots: Vec<u64> = members
+            .keys()
+            .copied()
+            .filter(|root| !placed.contains(root))
+            .collect();
+        roots.sort_unstable();
+
+        let mut placements = Vec::new();
+        let mut stack: Vec<(u64, DMat4, usize)> = roots
+            .into_iter()
+            .map(|root| (root, DMat4::IDENTITY, 0))
+            .collect();
+        while let Some((root, transform, depth)) = stack.pop() {
+            let mut bodies = HashSet::new();
+            for &representation in &members[&root] {
+                for item in self.items(representation).unwrap_or_default() {
+                    if is_body(item) && bodies.insert(item) {
+                        placements.push((item, transform));
+                    }
+                }
+                if depth < 32 {
+                    for &(child, matrix) in children.get(&representation).into_iter().flatten() {
+                        stack.push((find(&space, child), transform * matrix, depth + 1));
+                    }
+                }
+            }
+        }
+        if placements.is_empty() {
+            // Without representations, every solid is where it's modeled
+            placements = ids
+                .into_iter()
+                .filter(|&id| is_body(id) && self.entities[&id].simple().0 != "CLOSED_SHELL")
+                .map(|id| (id, DMat4::IDENTITY))
+                .collect();
+        }
+        placements
+    }
+
+    /// Child and parent of an assembly relationship, and the transform
+    /// from the child's space into the parent's.
+    fn relationship(&self, first: u64, second: u64, transform: u64) -> Result<(u64, u64, DMat4)> {
+        let params = self.attributes(transform, &["ITEM_DEFINED_TRANSFORMATION"])?;
+        let (mut from, mut to) = (params[2].id()?, params[3].id()?);
+        // The first representation is the child with the first item in it,
+        // but some exporters write them the other way around
+        let contains = |representation: u64, item: u64| {
+            self.items(representation)
+                .is_some_and(|items| items.contains(&item))
+        };
+        if !contains(first, from) && contains(first, to) {
+            (from, to) = (to, from);
+        }
+        let matrix = self.frame(to)?.matrix() * self.frame(from)?.matrix().inverse();
+        Ok((first, second, matrix))
+    }
+
+    /// Surface colours of styled items, from the fill of their styles.
+    fn colors(&self) -> HashMap<u64, Vec3> {
+        let mut ids: Vec<u64> = self.entities.keys().copied().collect();
+        ids.sort_unstable();
+        let mut colors = HashMap::new();
+        for id in ids {
+            let entity = &self.entities[&id];
+            let Some(params) = entity
+                .record("STYLED_ITEM")
+                .or_else(|| entity.record("OVER_RIDING_STYLED_ITEM"))
+            else {
+                continue;
+            };
+            let (Some(styles), Some(Ok(item))) = (params.get(1), params.get(2).map(Param::id))
+            else {
+                continue;
+            };
+            if let Some(color) = self.fill_color(styles, 0) {
+                colors.insert(item, color);
+            }
+        }
+        colors
+    }
+
+    /// Colour of the first surface fill found under `param`.
+    fn fill_color(&self, param: &Param, depth: usize) -> Option<Vec3> {
+        match param {
+            Param::List(params) | Param::Typed(params) => params
+                .iter()
+                .find_map(|param| self.fill_color(param, depth)),
+            Param::Ref(id) if depth < 12 => {
+                let (name, params) = self.entities.get(id)?.simple();
+                match name {
+                    "FILL_AREA_STYLE_COLOUR" => self.color(params.get(1)?.id().ok()?),
+                    // Edges and outlines aren't surfaces
+                    "CURVE_STYLE" | "SURFACE_STYLE_BOUNDARY" | "SURFACE_STYLE_SILHOUETTE" => None,
+                    _ => params
+                        .iter()
+                        .find_map(|param| self.fill_color(param, depth + 1)),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn color(&self, id: u64) -> Option<Vec3> {
+        let (name, params) = self.entities.get(&id)?.simple();
+        match name {
+            "COLOUR_RGB" => Some(Vec3::new(
+                params.get(1)?.number().ok()? as f32,
+                params.get(2)?.number().ok()? as f32,
+                params.get(3)?.number().ok()? as f32,
+            )),
+            "DRAUGHTING_PRE_DEFINED_COLOUR" => {
+                let Param::Str(name) = params.first()? else {
+                    return None;
+                };
+                Some(match name.as_str() {
+                    "red" => Vec3::X,
+                    "green" => Vec3::Y,
+                    "blue" => Vec3::Z,
+                    "yellow" => Vec3::new(1.0, 1.0, 0.0),
+                    "magenta" => Vec3::new(1.0, 0.0, 1.0),
+                    "cyan" => Vec3::new(0.0, 1.0, 1.0),
+                    "black" => Vec3::ZERO,
+                    "white" => Vec3::ONE,
+                    _ => return None,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+fn multiplicities(param: &Param) -> Result<Vec<usize>> {
+    Ok(param
+        .numbers()?
+        .into_iter()
+        .map(|count| count as usize)
+        .collect())
+}
+
+/// Knots of the B-spline forms that don't list them.
+fn implicit_knots(kind: &str, degree: usize, count: usize) -> Result<Vec<f64>> {
+    let order = degree + 1;
+    Ok(match kind {
+        "BEZIER_CURVE" => {
+            ensure!(
+                count == order,
+                "Bezier curve of degree {degree} with {count} points"
+            );
+            [vec![0.0; order], vec![1.0; order]].concat()
+        }
+        "UNIFORM_CURVE" => (0..count + order).map(|i| i as f64).collect(),
+        "QUASI_UNIFORM_CURVE" => {
+            ensure!(
+                count >= order,
+                "B-spline of degree {degree} with {count} points"
+            );
+            let spans = count - degree;
+            (0..count + order)
+                .map(|i| i.saturating_sub(degree).min(spans) as f64)
+                .collect()
+        }
+        _ => bail!("Unsupported B-spline {kind}"),
+    })
+}
+
+/// Plane of a planar loop, from Newell's method.
+fn plane_through(points: &[DVec3]) -> Surface {
+    let mut normal = DVec3::ZERO;
+    for (i, a) in points.iter().enumerate() {
+        let b = points[(i + 1) % points.len()];
+        normal += DVec3::new(
+            (a.y - b.y) * (a.z + b.z),
+            (a.z - b.z) * (a.x + b.x),
+            (a.x - b.x) * (a.y + b.y),
+        );
+    }
+    Surface::Plane(Frame::new(points[0], normal, None))
+}
@@ -0,0 +1,91 @@
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+use glam::{Vec2, Vec3};
+
+use crate::{import::file_stem, scene::Mesh};
+
+/// Loads a binary or ASCII STL file. Stored facet normals are often wrong,
+/// so flat normals are derived from the winding instead.
+pub fn load(path: &Path) -> Result<Mesh> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    // ASCII files start with "solid", but so do some binary ones, which
+    // are recognized by their exact size
+    let binary_size = bytes
+        .get(80..84)
+        .map(|count| 84 + 50 * u32::from_le_bytes(count.try_into().unwrap()) as usize);
+    let positions = if binary_size == Some(bytes.len()) || !bytes.starts_with(b"solid") {
+        parse_binary(&bytes)?
+    } else {
+        parse_ascii(std::str::from_utf8(&bytes).context("STL file is not valid text")?)?
+    };
+    ensure!(!positions.is_empty(), "{} has no triangles", path.display());
+
+    let normals = positions
+        .chunks_exact(3)
+        .flat_map(|p| {
+            let n = (p[1] - p[0]).cross(p[2] - p[0]).normalize_or(Vec3::Y);
+            [n; 3]
+        })
+        .collect();
+    Ok(Mesh {
+        name: file_stem(path),
+        uvs: vec![Vec2::ZERO; positions.len()],
+        indices: (0..positions.len() as u32).collect(),
+        normals,
+        positions,
+        ..Default::default()
+    })
+}
+
+/// An 80 byte header, the triangle count and 50 bytes per triangle: a
+/// normal, three vertices and an attribute word.
+fn parse_binary(bytes: &[u8]) -> Result<Vec<Vec3>> {
+    ensure!(bytes.len() >= 84, "STL file is truncated");
+    let count = u32::from_le_bytes(bytes[80..84].try_into().unwrap()) as usize;
+    let triangles = &bytes[84..];
+    ensure!(triangles.len() >= 50 * count, "STL file is truncated");
+    let float = |bytes: &[u8]| f32::from_le_bytes(bytes.try_into().unwrap());
+    Ok(triangles
+        .chunks_exact(50)
+        .take(count)
+        .flat_map(|triangle| {
+            triangle[12..48]
+                .chunks_exact(12)
+                .map(|v| Vec3::new(float(&v[0..4]), float(&v[4..8]), float(&v[8..12])))
+        })
+        .collect())
+}
+
+/// `facet normal ... outer loop vertex x y z ... endloop endfacet`, with
+/// loops of more than three vertices fanned into triangles.
+fn parse_ascii(text: &str) -> Result<Vec<Vec3>> {
+    let mut positions = Vec::new();
+    let mut polygon = Vec::new();
+    let mut tokens = text.split_ascii_whitespace();
+    while let Some(token) = tokens.next() {
+        match token {
+            "vertex" => {
+                let mut coordinate = || -> Result<f32> {
+                    let value = tokens.next().context("STL vertex is truncated")?;
+                    value
+                        .parse()
+                        .with_context(|| format!("Invalid STL coordinate: {value}"))
+                };
+                polygon.push(Vec3::new(coordinate()?, coordinate()?, coordinate()?));
+            }
+            "endloop" => {
+                if polygon.len() < 3 {
+                    bail!("STL facet has {} vertices", polygon.len());
+                }
+                for i in 1..polygon.len() - 1 {
+                    positions.extend([polygon[0], polygon[i], polygon[i + 1]]);
+                }
+                polygon.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(positions)
+}
@@ -0,0 +1,214 @@
+//! Descriptions of renders for farm managers, written by
+//! `spectrum export-job` in place of rendering. The JSON has everything a
+//! wrapper for Deadline, OpenCue and the like needs to queue the render
+//! and check on it:
+//!
+//! - `command`, the arguments to `spectrum` that render it, run from
+//!   `working_directory`.
+//! - `inputs`, every file the render reads, with its size and SHA-256 so
+//!   stale copies on a node can be caught, and `inputs_sha256` of them all.
+//! - `settings`, what the render resolves to.
+//! - `outputs`, the files it writes.
+//! - `tiles`, with `--tile-size`, each rendered by adding
+//!   `--tile x,y,width,height` and an output of its own to `command`. The
+//!   ones marked `verify` are rendered on two machines and the hashes
+//!   printed compared, see `tile`.
+
+use std::{
+    fmt::Write as _,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    headless::Headless,
+    tile::{self, Tile},
+    tracer::Backend,
+    webhook::escape,
+};
+
+pub const VERSION: u32 = 1;
+
+#[derive(Clone, Debug)]
+pub struct Job {
+    /// Arguments to `spectrum`, starting with `render`.
+    pub command: Vec<String>,
+    pub working_directory: PathBuf,
+    pub inputs: Vec<PathBuf>,
+    /// `tile::inputs_hash` of the inputs, which tile hashes start from.
+    pub inputs_hash: String,
+    pub render: Headless,
+    /// Left to the node when not given.
+    pub backend: Option<Backend>,
+    pub outputs: Vec<PathBuf>,
+    /// Tiles to hand out, if split, and whether each is rendered twice.
+    pub tiles: Vec<(Tile, bool)>,
+}
+
+impl Job {
+    /// Hashes the inputs, so fails if one is missing.
+    pub fn to_json(&self) -> Result<String> {
+        let mut json = String::new();
+        let strings = |items: &[String]| {
+            let items: Vec<_> = items
+                .iter()
+                .map(|item| format!("\"{}\"", escape(item)))
+                .collect();
+            items.join(", ")
+        };
+        let path = |path: &Path| format!("\"{}\"", escape(&path.to_string_lossy()));
+
+        let _ = writeln!(json, "{{");
+        let _ = writeln!(json, "  \"version\": {VERSION},");
+        let _ = writeln!(json, "  \"command\": [{}],", strings(&self.command));
+        let _ = writeln!(
+            json,
+            "  \"working_directory\": {},",
+            path(&self.working_directory)
+        );
+        let _ = writeln!(json, "  \"inputs\": [");
+        for (i, input) in self.inputs.iter().enumerate() {
+            let (bytes, sha256) = hash(input)?;
+            let comma = if i + 1 < self.inputs.len() { "," } else { "" };
+            let _ = writeln!(
+                json,
+                "    {{\"path\": {}, \"bytes\": {bytes}, \"sha256\": \"{sha256}\"}}{comma}",
+                path(input)
+            );
+        }
+        let _ = writeln!(json, "  ],");
+        let _ = writeln!(json, "  \"inputs_sha256\": \"{}\",", self.inputs_hash);
+
+        let render = &self.render;
+        let backend = self.backend.map_or("null".to_owned(), |backend| {
+            format!("\"{}\"", backend.name())
+        });
+        let _ = writeln!(json, "  \"settings\": {{");
+        let _ = writeln!(json, "    \"width\": {},", render.size.width);
+        let _ = writeln!(json, "    \"height\": {},", render.size.height);
+        let _ = writeln!(json, "    \"samples\": {},", render.samples);
+        let _ = writeln!(json, "    \"max_depth\": {},", render.settings.max_depth);
+        let _ = writeln!(json, "    \"spectral\": {},", render.settings.spectral);
+        let _ = writeln!(
+            json,
+            "    \"sampler\": \"{}\",",
+            render.settings.sampler.name()
+        );
+        let _ = writeln!(
+            json,
+            "    \"transparent\": {},",
+            render.settings.transparent
+        );
+        let _ = writeln!(json, "    \"aov\": \"{}\",", render.aov.name());
+        let _ = writeln!(json, "    \"backend\": {backend}");
+        let _ = writeln!(json, "  }},");
+
+        let outputs: Vec<_> = self.outputs.iter().map(|output| path(output)).collect();
+        let _ = writeln!(json, "  \"outputs\": [{}],", outputs.join(", "));
+        let _ = writeln!(json, "  \"tiles\": [");
+        for (i, (tile, verify)) in self.tiles.iter().enumerate() {
+            let comma = if i + 1 < self.tiles.len() { "," } else { "" };
+            let _ = writeln!(
+                json,
+                "    {{\"x\": {}, \"y\": {}, \"width\": {}, \"height\": {}, \"verify\": {verify}}}{comma}",
+                tile.x, tile.y, tile.width, tile.height
+            );
+        }
+        let _ = writeln!(json, "  ]");
+        let _ = writeln!(json, "}}");
+        Ok(json)
+    }
+}
+
+/// The `render` command line a node runs, from the arguments given to
+/// `export-job` after the command.
+pub fn render_command(arguments: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut command = vec!["render".to_owned()];
+    let mut arguments = arguments.into_iter();
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--job" => {
+                arguments.next();
+            }
+            _ => command.push(argument),
+        }
+    }
+    command
+}
+
+/// `path` and the buffers and images outside it a glTF refers to.
+pub fn scene_files(path: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![path.to_owned()];
+    let is_gltf = path.extension().is_some_and(|extension| {
+        extension.eq_ignore_ascii_case("gltf") || extension.eq_ignore_ascii_case("glb")
+    });
+    if !is_gltf {
+        return Ok(files);
+    }
+    let gltf =
+        gltf::Gltf::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let uris = gltf
+        .buffers()
+        .filter_map(|buffer| match buffer.source() {
+            gltf::buffer::Source::Uri(uri) => Some(uri),
+            gltf::buffer::Source::Bin => None,
+        })
+        .chain(gltf.images().filter_map(|image| match image.source() {
+            gltf::image::Source::Uri { uri, .. } => Some(uri),
+            gltf::image::Source::View { .. } => None,
+        }));
+    let parent = path.parent().unwrap_or(Path::new(""));
+    for uri in uris {
+        if !uri.starts_with("data:") {
+            files.push(parent.join(percent_decode(uri)));
+        }
+    }
+    Ok(files)
+}
+
+/// Size in bytes and SHA-256 in hex of the file at `path`.
+fn hash(path: &Path) -> Result<(u64, String)> {
+    let mut file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to read input {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 16];
+    let mut bytes = 0;
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .with_context(|| format!("Failed to read input {}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        bytes += read as u64;
+    }
+    Ok((bytes, tile::hex(&hasher.finalize())))
+}
+
+/// Decodes the `%20` style escapes of a URI.
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
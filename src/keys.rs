@@ -0,0 +1,308 @@
+//! Keyboard shortcuts of the window, rebindable from the `[keys]` table of
+//! the config file. Escape always releases the mouse and closes the window.
+
+use winit::keyboard::KeyCode;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Switches between the fly and orbit camera.
+    CameraMode,
+    /// Frames the selected instance, or the whole scene.
+    Frame,
+    Draft,
+    Medium,
+    Final,
+    Screenshot,
+    Forward,
+    Backward,
+    Left,
+    Right,
+    Up,
+    Down,
+    /// Held to fly faster.
+    Boost,
+    /// Closes the aperture by a stop.
+    StopDown,
+    OpenUp,
+    FocusNearer,
+    FocusFarther,
+    /// Cycles through the projections.
+    Projection,
+    /// Shows frame rate, samples and throughput, see `stats`.
+    Stats,
+    /// Cycles through the tone mapping operators.
+    Tonemap,
+    /// Brightens the image by a third of a stop.
+    ExposureUp,
+    ExposureDown,
+    /// Marks the edges that are in focus.
+    FocusPeaking,
+    /// Meters the exposure from the image, see `exposure`.
+    AutoExposure,
+    /// Cycles through the autofocus modes.
+    Autofocus,
+    /// Starts or stops recording the camera path, see `camera_path`.
+    RecordPath,
+    /// Plays or pauses the timeline.
+    PlayTimeline,
+    /// Starts or stops the turntable, see `turntable`.
+    Turntable,
+    /// Stops or resumes accumulating samples.
+    Pause,
+    /// Traces one more frame while paused.
+    Step,
+    /// Enters or leaves fullscreen, also on Alt+Enter.
+    Fullscreen,
+    /// Cycles through the present modes the surface supports.
+    PresentMode,
+}
+
+impl Action {
+    pub const ALL: [Self; 32] = [
+        Self::CameraMode,
+        Self::Frame,
+        Self::Draft,
+        Self::Medium,
+        Self::Final,
+        Self::Screenshot,
+        Self::Forward,
+        Self::Backward,
+        Self::Left,
+        Self::Right,
+        Self::Up,
+        Self::Down,
+        Self::Boost,
+        Self::StopDown,
+        Self::OpenUp,
+        Self::FocusNearer,
+        Self::FocusFarther,
+        Self::Projection,
+        Self::Stats,
+        Self::Tonemap,
+        Self::ExposureUp,
+        Self::ExposureDown,
+        Self::FocusPeaking,
+        Self::AutoExposure,
+        Self::Autofocus,
+        Self::RecordPath,
+        Self::PlayTimeline,
+        Self::Turntable,
+        Self::Pause,
+        Self::Step,
+        Self::Fullscreen,
+        Self::PresentMode,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|action| action.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::CameraMode => "camera-mode",
+            Self::Frame => "frame",
+            Self::Draft => "draft",
+            Self::Medium => "medium",
+            Self::Final => "final",
+            Self::Screenshot => "screenshot",
+            Self::Forward => "forward",
+            Self::Backward => "backward",
+            Self::Left => "left",
+            Self::Right => "right",
+            Self::Up => "up",
+            Self::Down => "down",
+            Self::Boost => "boost",
+            Self::StopDown => "stop-down",
+            Self::OpenUp => "open-up",
+            Self::FocusNearer => "focus-nearer",
+            Self::FocusFarther => "focus-farther",
+            Self::Projection => "projection",
+            Self::Stats => "stats",
+            Self::Tonemap => "tonemap",
+            Self::ExposureUp => "exposure-up",
+            Self::ExposureDown => "exposure-down",
+            Self::FocusPeaking => "focus-peaking",
+            Self::AutoExposure => "auto-exposure",
+            Self::Autofocus => "autofocus",
+            Self::RecordPath => "record-path",
+            Self::PlayTimeline => "play-timeline",
+            Self::Turntable => "turntable",
+            Self::Pause => "pause",
+            Self::Step => "step",
+            Self::Fullscreen => "fullscreen",
+            Self::PresentMode => "present-mode",
+        }
+    }
+}
+
+/// Which key does what. A key has at most one action, an action any number
+/// of keys.
+#[derive(Clone, Debug)]
+pub struct Keybindings {
+    bindings: Vec<(KeyCode, Action)>,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        Self {
+            bindings: vec![
+                (KeyCode::Tab, Action::CameraMode),
+                (KeyCode::KeyF, Action::Frame),
+                (KeyCode::Digit1, Action::Draft),
+                (KeyCode::Digit2, Action::Medium),
+                (KeyCode::Digit3, Action::Final),
+                (KeyCode::F12, Action::Screenshot),
+                (KeyCode::KeyW, Action::Forward),
+                (KeyCode::KeyS, Action::Backward),
+                (KeyCode::KeyA, Action::Left),
+                (KeyCode::KeyD, Action::Right),
+                (KeyCode::KeyE, Action::Up),
+                (KeyCode::KeyQ, Action::Down),
+                (KeyCode::ShiftLeft, Action::Boost),
+                (KeyCode::ShiftRight, Action::Boost),
+                (KeyCode::Comma, Action::StopDown),
+                (KeyCode::Period, Action::OpenUp),
+                (KeyCode::Semicolon, Action::FocusNearer),
+                (KeyCode::Quote, Action::FocusFarther),
+                (KeyCode::KeyP, Action::Projection),
+                (KeyCode::F3, Action::Stats),
+                (KeyCode::KeyT, Action::Tonemap),
+                (KeyCode::BracketRight, Action::ExposureUp),
+                (KeyCode::BracketLeft, Action::ExposureDown),
+                (KeyCode::KeyK, Action::FocusPeaking),
+                (KeyCode::KeyX, Action::AutoExposure),
+                (KeyCode::KeyG, Action::Autofocus),
+                (KeyCode::KeyR, Action::RecordPath),
+                (KeyCode::Enter, Action::PlayTimeline),
+                (KeyCode::KeyO, Action::Turntable),
+                (KeyCode::Space, Action::Pause),
+                (KeyCode::KeyN, Action::Step),
+                (KeyCode::F11, Action::Fullscreen),
+                (KeyCode::KeyV, Action::PresentMode),
+            ],
+        }
+    }
+}
+
+impl Keybindings {
+    pub fn action(&self, key: KeyCode) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|&&(bound, _)| bound == key)
+            .map(|&(_, action)| action)
+    }
+
+    /// Replaces the keys of `action`, taking them from whatever they did
+    /// before.
+    pub fn bind(&mut self, action: Action, keys: &[KeyCode]) {
+        self.bindings
+            .retain(|(key, bound)| *bound != action && !keys.contains(key));
+        self.bindings.extend(keys.iter().map(|&key| (key, action)));
+    }
+}
+
+const KEYS: [KeyCode; 89] = [
+    KeyCode::KeyA,
+    KeyCode::KeyB,
+    KeyCode::KeyC,
+    KeyCode::KeyD,
+    KeyCode::KeyE,
+    KeyCode::KeyF,
+    KeyCode::KeyG,
+    KeyCode::KeyH,
+    KeyCode::KeyI,
+    KeyCode::KeyJ,
+    KeyCode::KeyK,
+    KeyCode::KeyL,
+    KeyCode::KeyM,
+    KeyCode::KeyN,
+    KeyCode::KeyO,
+    KeyCode::KeyP,
+    KeyCode::KeyQ,
+    KeyCode::KeyR,
+    KeyCode::KeyS,
+    KeyCode::KeyT,
+    KeyCode::KeyU,
+    KeyCode::KeyV,
+    KeyCode::KeyW,
+    KeyCode::KeyX,
+    KeyCode::KeyY,
+    KeyCode::KeyZ,
+    KeyCode::Digit0,
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F9,
+    KeyCode::F10,
+    KeyCode::F11,
+    KeyCode::F12,
+    KeyCode::ArrowUp,
+    KeyCode::ArrowDown,
+    KeyCode::ArrowLeft,
+    KeyCode::ArrowRight,
+    KeyCode::Space,
+    KeyCode::Tab,
+    KeyCode::Enter,
+    KeyCode::Backspace,
+    KeyCode::Insert,
+    KeyCode::Delete,
+    KeyCode::Home,
+    KeyCode::End,
+    KeyCode::PageUp,
+    KeyCode::PageDown,
+    KeyCode::ShiftLeft,
+    KeyCode::ShiftRight,
+    KeyCode::ControlLeft,
+    KeyCode::ControlRight,
+    KeyCode::AltLeft,
+    KeyCode::AltRight,
+    KeyCode::Comma,
+    KeyCode::Period,
+    KeyCode::Semicolon,
+    KeyCode::Quote,
+    KeyCode::Slash,
+    KeyCode::Backslash,
+    KeyCode::BracketLeft,
+    KeyCode::BracketRight,
+    KeyCode::Minus,
+    KeyCode::Equal,
+    KeyCode::Backquote,
+    KeyCode::Numpad0,
+    KeyCode::Numpad1,
+    KeyCode::Numpad2,
+    KeyCode::Numpad3,
+    KeyCode::Numpad4,
+    KeyCode::Numpad5,
+    KeyCode::Numpad6,
+    KeyCode::Numpad7,
+    KeyCode::Numpad8,
+    KeyCode::Numpad9,
+];
+
+/// Parses winit's name of a physical key, such as `KeyW`, `Digit1`, `F12`
+/// or `ShiftLeft`. Letters and digits can also be given on their own.
+pub fn parse_key(name: &str) -> Option<KeyCode> {
+    let name = match name.as_bytes() {
+        [c] if c.is_ascii_alphabetic() => format!("Key{name}"),
+        [c] if c.is_ascii_digit() => format!("Digit{name}"),
+        _ => name.to_owned(),
+    };
+    KEYS.into_iter()
+        .find(|key| format!("{key:?}").eq_ignore_ascii_case(&name))
+}
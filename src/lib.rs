@@ -1,16 +1,466 @@
-use std::sync::Arc;
+use std::{
+    cell::{Cell, RefCell},
+    path::PathBuf,
+    rc::Rc,
+    sync::Arc,
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use glam::{UVec2, Vec3};
 use tracing::Level;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use web_time::{Duration, Instant};
+#[cfg(feature = "oidn")]
+use wgpu::util::DeviceExt;
 use winit::{
     application::ApplicationHandler,
-    dpi::PhysicalSize,
-    event::{ElementState, KeyEvent, WindowEvent},
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{DeviceEvent, DeviceId, ElementState, KeyEvent, MouseButton, WindowEvent},
     event_loop::{ActiveEventLoop, EventLoop, EventLoopProxy},
-    keyboard::{KeyCode, PhysicalKey},
-    window::{Window, WindowId},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
+    window::{CursorGrabMode, Fullscreen, Window, WindowId},
+};
+
+use crate::{
+    aov::{Aov, Aovs},
+    audio::AudioInput,
+    autofocus::{Autofocus, AutofocusMode},
+    bvh::InstancedBvh,
+    camera::{Camera, CameraController, CameraMode, LensDistortion},
+    camera_path::PathRecorder,
+    cli::{Args, Command, DEFAULT_RESOLUTION},
+    config::Config,
+    control::{ControlInput, ControlTarget},
+    exposure::AutoExposure,
+    keys::{Action, Keybindings},
+    lut::DisplayLut,
+    ocean::Ocean,
+    output::{
+        bracket,
+        exr::{self, Precision},
+    },
+    preset::Preset,
+    readback::Readbacks,
+    sampler::SamplerKind,
+    scene::{Environment, Scene},
+    stats::{FrameStats, SceneStats},
+    svgf::Svgf,
+    timeline::Timeline,
+    tonemap::{FocusPeaking, Tonemap, TonemapPass},
+    tracer::{Backend, Renderer, TraceSettings},
+    turntable::Turntable,
+    watchdog::Watchdog,
 };
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{
+    avif::HdrEncoding,
+    dropped::{DropLoader, Dropped},
+    notification::{Notifier, Summary},
+    output::preview::{self, Preview},
+    tile::{Tile, Verifier},
+};
+
+pub mod aov;
+pub mod audio;
+pub mod autofocus;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod avif;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod benchmark;
+pub mod blue_noise;
+pub mod bvh;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod calibration;
+pub mod camera;
+pub mod camera_path;
+pub mod cli;
+pub mod config;
+pub mod control;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod dataset;
+#[cfg(feature = "oidn")]
+pub mod denoise;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod dropped;
+pub mod exposure;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod headless;
+#[cfg(feature = "hot-reload")]
+pub mod hot_reload;
+pub mod import;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod job;
+pub mod keys;
+pub mod lod;
+pub mod lut;
+pub mod nanovdb;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod notification;
+pub mod ocean;
+pub mod ocio;
+pub mod output;
+#[cfg(feature = "physics")]
+pub mod physics;
+pub mod preset;
+pub mod readback;
+pub mod sampler;
+pub mod scene;
+pub mod shader;
+pub mod sky;
+pub mod spectral;
+pub mod staging;
+pub mod stats;
+pub mod svgf;
+pub mod texture;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod thumbnail;
+pub mod tile;
+pub mod timeline;
+pub mod tonemap;
+pub mod tracer;
+pub mod tuning;
+pub mod turntable;
+#[cfg(feature = "ui")]
+mod ui;
+pub mod watchdog;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod webhook;
+
+/// Display and progressive rendering settings.
+pub struct Settings {
+    /// Exposure compensation in stops applied when displaying the image.
+    pub exposure: f32,
+    /// Meters the exposure of the viewport from the image, which the
+    /// compensation is added to, see `exposure`.
+    pub auto_exposure: bool,
+    /// Stops accumulating after this many samples per pixel, zero never stops.
+    pub max_samples: u32,
+    /// Restarts the image every frame when disabled.
+    pub accumulate: bool,
+    pub denoise: DenoiseMode,
+    /// Size of the traced image relative to the window.
+    pub resolution_scale: f32,
+    /// Shown in place of the image, see `aov`.
+    pub aov: Aov,
+    /// Applied to the image unless a display LUT replaces it.
+    pub tonemap: Tonemap,
+    /// Marks the edges that are in focus, see `tonemap`.
+    pub focus_peaking: bool,
+    /// Stretches the image over the window while it's being resized,
+    /// instead of showing it at its old size until it's traced again.
+    pub rescale_on_resize: bool,
+    /// Stops tracing, keeping the image as it is.
+    pub paused: bool,
+    /// How frames are handed to the display, used if the surface supports
+    /// it.
+    pub present_mode: PresentMode,
+}
+
+impl Settings {
+    /// Moves the exposure by `thirds` of a stop, onto the nearest third.
+    pub fn step_exposure(&mut self, thirds: f32) {
+        let thirds = (self.exposure * 3.0).round() + thirds;
+        self.exposure = (thirds / 3.0).clamp(-MAX_EXPOSURE, MAX_EXPOSURE);
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            auto_exposure: false,
+            max_samples: 0,
+            accumulate: true,
+            denoise: DenoiseMode::Off,
+            resolution_scale: 1.0,
+            aov: Aov::Beauty,
+            tonemap: Tonemap::None,
+            focus_peaking: false,
+            rescale_on_resize: true,
+            paused: false,
+            present_mode: PresentMode::Fifo,
+        }
+    }
+}
+
+/// How the image is cleaned up before it's shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DenoiseMode {
+    #[default]
+    Off,
+    /// Filtered on the GPU every frame, see `svgf`.
+    Svgf,
+    /// Open Image Denoise, refreshed whenever the sample count doubles.
+    #[cfg(feature = "oidn")]
+    Oidn,
+}
+
+impl DenoiseMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "off" => Some(Self::Off),
+            "svgf" => Some(Self::Svgf),
+            #[cfg(feature = "oidn")]
+            "oidn" => Some(Self::Oidn),
+            _ => None,
+        }
+    }
+}
+
+/// How the window covers the screen when it goes fullscreen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FullscreenMode {
+    /// A borderless window the size of the monitor, quick to switch.
+    #[default]
+    Borderless,
+    /// Takes over the monitor at its largest video mode.
+    Exclusive,
+}
+
+impl FullscreenMode {
+    pub const ALL: [Self; 2] = [Self::Borderless, Self::Exclusive];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Borderless => "borderless",
+            Self::Exclusive => "exclusive",
+        }
+    }
+}
+
+/// How finished frames reach the display, trading latency for tearing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PresentMode {
+    /// Waits for vertical sync, never tears. Every surface supports it.
+    #[default]
+    Fifo,
+    /// Waits for vertical sync but replaces a frame still waiting, so
+    /// it's less behind without tearing.
+    Mailbox,
+    /// Shows frames as soon as they're done, tearing.
+    Immediate,
+}
+
+impl PresentMode {
+    pub const ALL: [Self; 3] = [Self::Fifo, Self::Mailbox, Self::Immediate];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Fifo => "fifo",
+            Self::Mailbox => "mailbox",
+            Self::Immediate => "immediate",
+        }
+    }
+
+    fn wgpu(self) -> wgpu::PresentMode {
+        match self {
+            Self::Fifo => wgpu::PresentMode::Fifo,
+            Self::Mailbox => wgpu::PresentMode::Mailbox,
+            Self::Immediate => wgpu::PresentMode::Immediate,
+        }
+    }
+
+    /// Whether a surface that can present in `modes` can present this way.
+    pub fn supported(self, modes: &[wgpu::PresentMode]) -> bool {
+        modes.contains(&self.wgpu())
+    }
+}
+
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct DisplayParams {
+    exposure: f32,
+    /// Whether colors go through the display LUT instead of plain sRGB.
+    display_lut: u32,
+    /// Image pixels per window pixel, across and down.
+    resolution_scale: [f32; 2],
+    aov: u32,
+    /// Whether the metered exposure is added to `exposure`.
+    auto_exposure: u32,
+    _pad: [u32; 2],
+    shaper_min: [f32; 4],
+    shaper_max: [f32; 4],
+    cube_min: [f32; 4],
+    cube_max: [f32; 4],
+}
+
+/// Samples per pixel of a saved image or animation frame when nothing
+/// else says.
+const DEFAULT_OUTPUT_SAMPLES: u32 = 256;
+const TITLE: &str = "Spectrum";
+/// How long the window has to keep its size before the image follows it,
+/// so dragging the border doesn't restart it on every step.
+const RESIZE_DELAY: Duration = Duration::from_millis(250);
+/// Furthest the exposure goes from zero, in stops.
+const MAX_EXPOSURE: f32 = 10.0;
+/// Refits of the top-level BVH to animated instances before it's rebuilt.
+const MAX_TLAS_REFITS: u32 = 30;
+
+/// Renders the timeline frame by frame instead of in real time.
+struct Recording {
+    directory: PathBuf,
+    fps: f32,
+    samples: u32,
+    frame: u32,
+    frame_count: u32,
+    precision: Precision,
+    /// Adds the AOVs to every frame.
+    aovs: bool,
+    /// Stops saved tonemapped beside every frame.
+    bracket: Vec<f32>,
+    /// Converts frames out of linear Rec.709, see `ocio`.
+    colorspace: Option<ocio::Processor>,
+    /// Set when the next update moves on to a new frame.
+    advance: bool,
+}
+
+/// Files written once their readbacks arrive, which the app waits for
+/// before it exits.
+#[derive(Clone, Default)]
+struct Saves {
+    pending: Rc<Cell<u32>>,
+    /// Why the first one failed, which ends a recording or a final render.
+    error: Rc<RefCell<Option<String>>>,
+}
+
+impl Saves {
+    /// Counts a save as pending until `save` has run on what was read, or
+    /// was dropped with a readback that failed.
+    fn start<T>(&self, save: impl FnOnce(T) -> Result<()> + 'static) -> impl FnOnce(T) + 'static {
+        let pending = PendingSave::new(self);
+        move |read| {
+            if let Err(err) = save(read) {
+                tracing::error!("{err:#}");
+                pending
+                    .0
+                    .error
+                    .borrow_mut()
+                    .get_or_insert_with(|| format!("{err:#}"));
+            }
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.pending.get() == 0
+    }
+
+    fn failed(&self) -> bool {
+        self.error.borrow().is_some()
+    }
+}
+
+/// Held by a save until it's done.
+struct PendingSave(Saves);
+
+impl PendingSave {
+    fn new(saves: &Saves) -> Self {
+        saves.pending.set(saves.pending.get() + 1);
+        Self(saves.clone())
+    }
+}
+
+impl Drop for PendingSave {
+    fn drop(&mut self) {
+        self.0.pending.set(self.0.pending.get() - 1);
+    }
+}
+
+/// An image file named on the command line, stored by its extension.
+#[cfg(not(target_arch = "wasm32"))]
+struct Output {
+    path: PathBuf,
+    precision: Precision,
+    hdr: HdrEncoding,
+    bracket: Vec<f32>,
+    /// Converts .exr images out of linear Rec.709, see `ocio`.
+    colorspace: Option<ocio::Processor>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Output {
+    fn new(path: PathBuf, args: &Args, colorspace: Option<ocio::Processor>) -> Self {
+        Self {
+            path,
+            precision: args.exr,
+            hdr: args.hdr,
+            bracket: args.bracket.clone(),
+            colorspace,
+        }
+    }
+
+    /// Saves linear light scaled by `scale`, keeping it above white in .exr
+    /// and .avif files and tone mapping it for display otherwise, and the
+    /// brackets asked for beside it. .exr files are in the output color
+    /// space when there is one.
+    fn save(
+        &self,
+        image: &image::Rgba32FImage,
+        scale: f32,
+        tonemap: Tonemap,
+        display_lut: Option<&DisplayLut>,
+    ) -> Result<()> {
+        bracket::save(
+            image,
+            &self.path,
+            scale,
+            &self.bracket,
+            tonemap,
+            display_lut,
+        )?;
+        let extension = self
+            .path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        if !matches!(extension.as_deref(), Some("exr" | "avif")) {
+            return lut::display_image(image, scale, tonemap, display_lut)
+                .save(&self.path)
+                .with_context(|| format!("Failed to save {}", self.path.display()));
+        }
+        let mut image = image.clone();
+        for pixel in image.pixels_mut() {
+            for channel in &mut pixel.0[..3] {
+                *channel *= scale;
+            }
+        }
+        if extension.as_deref() == Some("exr") {
+            if let Some(colorspace) = &self.colorspace {
+                colorspace.apply_image(&mut image);
+            }
+            exr::save(&image, &self.path, self.precision)
+        } else {
+            avif::save(&image, &self.path, self.hdr)
+        }
+    }
+
+    /// Saves the values of `aov` to an .exr file, or colored as the
+    /// viewport shows them otherwise.
+    fn save_aov(&self, aov: Aov, image: &image::Rgba32FImage) -> Result<()> {
+        let extension = self
+            .path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("exr") => exr::save_aov(aov, image, &self.path, self.precision),
+            Some("avif") => anyhow::bail!("AOVs are saved as EXR or PNG"),
+            _ => aov
+                .display_image(image)
+                .save(&self.path)
+                .with_context(|| format!("Failed to save {}", self.path.display())),
+        }
+    }
+}
 
 struct State {
     surface: wgpu::Surface<'static>,
@@ -20,10 +470,121 @@ struct State {
     size: PhysicalSize<u32>,
     window: Arc<Window>,
     surface_configured: bool,
+    /// Supported ways of blending the window with what's behind it.
+    alpha_modes: Vec<wgpu::CompositeAlphaMode>,
+    /// Supported ways of presenting frames.
+    present_modes: Vec<wgpu::PresentMode>,
+    camera: Camera,
+    controller: CameraController,
+    keys: Keybindings,
+    tracer: Box<dyn Renderer>,
+    trace_settings: TraceSettings,
+    /// Threads along each side of a workgroup of the GPU tracer, see
+    /// `tuning`.
+    workgroup_size: u32,
+    /// Kept for updates to dynamic scenes.
+    scene: Scene,
+    /// Rebuilt or refit when instances move, see `MAX_TLAS_REFITS`.
+    instanced: InstancedBvh,
+    /// Top-level refits since it was last rebuilt for the animations.
+    tlas_refits: u32,
+    /// Timeline time the scene's animations were last posed at.
+    animated_at: Option<f32>,
+    audio: Option<AudioInput>,
+    control: Option<ControlInput>,
+    #[cfg(feature = "physics")]
+    physics: Option<physics::Physics>,
+    timeline: Timeline,
+    /// Records the camera's motion onto the timeline.
+    camera_path: PathRecorder,
+    turntable: Turntable,
+    recording: Option<Recording>,
+    /// Saved once the image has all its samples, which ends the session.
+    #[cfg(not(target_arch = "wasm32"))]
+    output: Option<Output>,
+    /// Recorded frames and the output being written.
+    saves: Saves,
+    /// Set once the output is on its way, to exit when it's saved.
+    #[cfg(not(target_arch = "wasm32"))]
+    saved_output: Option<PathBuf>,
+    /// Told once the recording or the output is done, then taken.
+    #[cfg(not(target_arch = "wasm32"))]
+    notifier: Option<Notifier>,
+    /// When tracing began, for the notification.
+    #[cfg(not(target_arch = "wasm32"))]
+    started: Instant,
+    /// Instance framed by the F key, the whole scene without one.
+    selected: Option<usize>,
+    settings: Settings,
+    blit_pipeline: wgpu::RenderPipeline,
+    blit_layout: wgpu::BindGroupLayout,
+    tonemap: TonemapPass,
+    auto_exposure: AutoExposure,
+    display_buffer: wgpu::Buffer,
+    /// Display transform replacing plain sRGB, see `lut`.
+    display_lut: Option<DisplayLut>,
+    /// Its shaper and cube, placeholders without one.
+    lut_views: [wgpu::TextureView; 2],
+    /// Created the first time it's used, and again after resizing.
+    svgf: Option<Svgf>,
+    /// Gathered when shown or exported, see `aov`.
+    aovs: Option<Aovs>,
+    /// Camera of the previous frame, or of the previous saved one while
+    /// recording, for the motion AOV.
+    prev_camera: Camera,
+    /// Created the first time denoising is turned on.
+    #[cfg(feature = "oidn")]
+    denoiser: Option<denoise::Denoiser>,
+    /// Latest denoised image, shown in place of the samples.
+    #[cfg(feature = "oidn")]
+    denoised: Option<wgpu::TextureView>,
+    /// Samples and guides read back for the denoiser, once they arrive.
+    #[cfg(feature = "oidn")]
+    denoise_read: Rc<RefCell<Option<Vec<image::Rgba32FImage>>>>,
+    /// Whether a readback for the denoiser is on its way.
+    #[cfg(feature = "oidn")]
+    denoising: bool,
+    #[cfg(feature = "ui")]
+    ui: ui::Ui,
+    #[cfg(feature = "hot-reload")]
+    shader_watcher: Option<hot_reload::ShaderWatcher>,
+    last_update: Instant,
+    frame_stats: FrameStats,
+    /// Counts of what the scene holds, for the stats overlay.
+    scene_stats: SceneStats,
+    /// Shows the frame stats in the title, and them along with the scene
+    /// stats over the image.
+    show_stats: bool,
+    /// Where the cursor last was in the window.
+    cursor: Option<PhysicalPosition<f64>>,
+    autofocus: Autofocus,
+    /// Distance under the cursor to focus at, once its readback arrives.
+    focus_read: Rc<Cell<Option<f32>>>,
+    /// Screenshots, picking and other copies back from the GPU.
+    readbacks: Readbacks,
+    /// When the window last changed size, until the image follows it.
+    resized_at: Option<Instant>,
+    /// Traces one frame on the next render while paused.
+    step: bool,
+    /// Used by F11 and Alt+Enter.
+    fullscreen_mode: FullscreenMode,
+    watchdog: Watchdog,
+    /// Files dropped onto the window, loading in the background.
+    #[cfg(not(target_arch = "wasm32"))]
+    dropped: DropLoader,
+    modifiers: ModifiersState,
 }
 
 impl State {
-    async fn new(window: Arc<Window>) -> State {
+    async fn new(
+        window: Arc<Window>,
+        mut scene: Scene,
+        audio: Option<AudioInput>,
+        control: Option<ControlInput>,
+        timeline: Timeline,
+        recording: Option<Recording>,
+        preset: Option<Preset>,
+    ) -> State {
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
@@ -45,14 +606,28 @@ impl State {
             println!("{:?}", adapter.get_info())
         }
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
+        let mut adapter_options = wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: Some(&surface),
+            force_fallback_adapter: false,
+        };
+        let adapter = match instance.request_adapter(&adapter_options).await {
+            Some(adapter) => adapter,
+            None => {
+                // A software adapter can still show what the CPU traces
+                adapter_options.force_fallback_adapter = true;
+                instance.request_adapter(&adapter_options).await.unwrap()
+            }
+        };
+
+        // wgpu 22 exposes the ray query features but not the API to build
+        // acceleration structures, so traversal stays on the software BVH
+        // until the upgrade that adds it
+        let ray_query =
+            wgpu::Features::RAY_QUERY | wgpu::Features::RAY_TRACING_ACCELERATION_STRUCTURE;
+        if adapter.features().contains(ray_query) {
+            tracing::info!("Adapter supports ray queries, tracing with the software BVH");
+        }
 
         let device_desc = wgpu::DeviceDescriptor {
             label: None,
@@ -66,12 +641,15 @@ impl State {
             },
             memory_hints: wgpu::MemoryHints::default(),
         };
+        // wgpu hands out a single queue per device, so tracing can't move to
+        // an async compute queue apart from the display and overlay. Long
+        // frames are kept from holding the window up by tracing in bands
+        // instead, see `TraceSettings::rays_per_submit`
         let (device, queue) = adapter.request_device(&device_desc, None).await.unwrap();
 
         let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an Srgb surface texture. Using a different
-        // one will result all the colors comming out darker. If you want to support non
-        // Srgb surfaces, you'll need to account for that when drawing to the frame.
+        // Preferably one that encodes sRGB itself, the tone mapping pass
+        // encodes for those that don't
         let surface_format = surface_caps
             .formats
             .iter()
@@ -83,12 +661,94 @@ impl State {
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
+            present_mode: PresentMode::default().wgpu(),
             alpha_mode: surface_caps.alpha_modes[0],
-            desired_maximum_frame_latency: 2,
+            desired_maximum_frame_latency: staging::FRAME_LATENCY,
             view_formats: vec![],
         };
 
+        let camera = Camera::default();
+        lod::select(&mut scene, camera.position, camera.fov_y, size.height);
+        let instanced = InstancedBvh::new(&scene);
+        let scene_stats = SceneStats::new(&scene, &instanced);
+        tracing::info!("Scene stats:\n{scene_stats}");
+        let backend = if adapter.get_info().device_type == wgpu::DeviceType::Cpu {
+            tracing::info!("No GPU adapter, tracing on the CPU");
+            Backend::Cpu
+        } else {
+            Backend::Gpu
+        };
+        let mut tracer = backend.create(&device, &queue, &scene, &instanced, size);
+        #[cfg(feature = "physics")]
+        let physics = physics::Physics::new(&scene);
+
+        let blit_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Blit Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let lut_views = lut::upload(&device, &queue, None);
+        let display_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Display Params"),
+            size: std::mem::size_of::<DisplayParams>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let shader = shader::create_module(&device, "Render Shader", "render.wgsl", &[]);
+        let blit_pipeline = blit_pipeline(&device, &blit_layout, tonemap::FORMAT, &shader);
+        let tonemap = TonemapPass::new(&device, config.format, size);
+        let auto_exposure = AutoExposure::new(&device);
+
         let surface_configured;
         #[cfg(not(target_arch = "wasm32"))]
         {
@@ -100,31 +760,890 @@ impl State {
             surface_configured = false;
         }
 
-        Self {
-            surface,
-            device,
-            queue,
-            config,
+        #[cfg(feature = "ui")]
+        let ui = ui::Ui::new(&device, config.format, &window);
+
+        // The command line wins over the scene file
+        let mut settings = Settings::default();
+        let mut trace_settings = TraceSettings::default();
+        scene.render.apply(&mut settings, &mut trace_settings);
+        if let Some(preset) = preset {
+            preset.apply(&mut settings, &mut trace_settings);
+        }
+        if let Some(recording) = &recording {
+            settings.max_samples = recording.samples;
+        }
+        let workgroup_size = if backend == Backend::Gpu {
+            tuning::tune(
+                &device,
+                &queue,
+                &adapter.get_info(),
+                &mut *tracer,
+                &camera,
+                &trace_settings,
+            )
+        } else {
+            tracer::WORKGROUP_SIZE
+        };
+
+        Self {
+            surface,
+            device,
+            queue,
+            config,
+            size,
+            window,
+            surface_configured,
+            alpha_modes: surface_caps.alpha_modes,
+            present_modes: surface_caps.present_modes,
+            camera,
+            controller: CameraController::default(),
+            keys: Keybindings::default(),
+            tracer,
+            trace_settings,
+            workgroup_size,
+            scene,
+            instanced,
+            tlas_refits: 0,
+            animated_at: None,
+            audio,
+            control,
+            #[cfg(feature = "physics")]
+            physics,
+            timeline,
+            camera_path: PathRecorder::default(),
+            turntable: Turntable::default(),
+            recording,
+            #[cfg(not(target_arch = "wasm32"))]
+            output: None,
+            saves: Saves::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            saved_output: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            notifier: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            started: Instant::now(),
+            selected: None,
+            settings,
+            blit_pipeline,
+            blit_layout,
+            tonemap,
+            auto_exposure,
+            display_buffer,
+            display_lut: None,
+            lut_views,
+            svgf: None,
+            aovs: None,
+            prev_camera: camera,
+            #[cfg(feature = "oidn")]
+            denoiser: None,
+            #[cfg(feature = "oidn")]
+            denoised: None,
+            #[cfg(feature = "oidn")]
+            denoise_read: Rc::default(),
+            #[cfg(feature = "oidn")]
+            denoising: false,
+            #[cfg(feature = "ui")]
+            ui,
+            #[cfg(feature = "hot-reload")]
+            shader_watcher: hot_reload::ShaderWatcher::new()
+                .inspect_err(|err| tracing::warn!("Shaders won't be reloaded: {err:#}"))
+                .ok(),
+            last_update: Instant::now(),
+            frame_stats: FrameStats::default(),
+            scene_stats,
+            show_stats: false,
+            cursor: None,
+            autofocus: Autofocus::default(),
+            focus_read: Rc::default(),
+            readbacks: Readbacks::default(),
+            resized_at: None,
+            step: false,
+            fullscreen_mode: FullscreenMode::default(),
+            watchdog: Watchdog::new(adapter.get_info(), watchdog::DEFAULT_TIMEOUT),
+            #[cfg(not(target_arch = "wasm32"))]
+            dropped: DropLoader::default(),
+            modifiers: ModifiersState::default(),
+        }
+    }
+
+    /// Follows the window to `new_size`. The image follows once the size
+    /// settles, see `RESIZE_DELAY`.
+    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        if new_size.width > 0 && new_size.height > 0 {
+            if new_size != self.size {
+                self.resized_at = Some(Instant::now());
+            }
+            self.size = new_size;
+            self.config.width = new_size.width;
+            self.config.height = new_size.height;
+            self.surface.configure(&self.device, &self.config);
+            self.tonemap.resize(&self.device, new_size);
+        }
+    }
+
+    /// Whether the window changed size too recently for the image to
+    /// follow.
+    fn resizing(&self) -> bool {
+        self.resized_at
+            .is_some_and(|resized_at| resized_at.elapsed() < RESIZE_DELAY)
+    }
+
+    /// Image pixels per window pixel, across and down, of the image as it
+    /// is, which lags behind the window while it's resized.
+    fn display_scale(&self) -> [f32; 2] {
+        let size = self.tracer.output_texture().size();
+        if self.settings.rescale_on_resize {
+            [
+                size.width as f32 / self.size.width as f32,
+                size.height as f32 / self.size.height as f32,
+            ]
+        } else {
+            [self.settings.resolution_scale; 2]
+        }
+    }
+
+    /// Size of the traced image, the window scaled by the resolution scale.
+    fn image_size(&self) -> PhysicalSize<u32> {
+        let scale =
+            |length: u32| ((length as f32 * self.settings.resolution_scale).round() as u32).max(1);
+        PhysicalSize::new(scale(self.size.width), scale(self.size.height))
+    }
+
+    fn resize_image(&mut self) {
+        self.tracer.resize(&self.device, self.image_size());
+        self.svgf = None;
+        self.aovs = None;
+        #[cfg(feature = "oidn")]
+        {
+            self.denoised = None;
+        }
+    }
+
+    fn input(&mut self, event: &WindowEvent) -> bool {
+        if let WindowEvent::ModifiersChanged(modifiers) = event {
+            self.modifiers = modifiers.state();
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let WindowEvent::DroppedFile(path) = event {
+            self.drop_file(path.clone());
+            return true;
+        }
+        // The overlay can't be used while the cursor is captured
+        #[cfg(feature = "ui")]
+        if !self.controller.fly.captured && self.ui.on_window_event(&self.window, event) {
+            return true;
+        }
+
+        if let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(key),
+                    repeat: false,
+                    ..
+                },
+            ..
+        } = event
+        {
+            // Alt+Enter goes fullscreen whatever Enter is bound to
+            if *key == KeyCode::Enter && self.modifiers.alt_key() {
+                self.toggle_fullscreen();
+                return true;
+            }
+            if let Some(action) = self.keys.action(*key) {
+                if self.shortcut(action) {
+                    return true;
+                }
+            }
+        }
+
+        if let WindowEvent::CursorMoved { position, .. } = event {
+            self.cursor = Some(*position);
+        }
+        match event {
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if self.controller.mode == CameraMode::Fly && !self.controller.fly.captured => {
+                self.set_captured(true);
+                true
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Right,
+                ..
+            } if !self.controller.fly.captured => {
+                self.focus_here();
+                true
+            }
+            // Escape releases the mouse before it closes the window
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(KeyCode::Escape),
+                        ..
+                    },
+                ..
+            } if self.controller.fly.captured => {
+                self.set_captured(false);
+                true
+            }
+            WindowEvent::Focused(false) => {
+                self.set_captured(false);
+                self.controller.process_event(event, &self.keys)
+            }
+            _ => self.controller.process_event(event, &self.keys),
+        }
+    }
+
+    /// Carries out the actions that aren't the camera's, returning false
+    /// for those.
+    fn shortcut(&mut self, action: Action) -> bool {
+        match action {
+            Action::CameraMode => {
+                if self.controller.fly.captured {
+                    self.set_captured(false);
+                }
+                self.controller.toggle_mode(&self.camera);
+            }
+            Action::Frame => self.frame_selection(),
+            Action::Draft | Action::Medium | Action::Final => {
+                let preset = match action {
+                    Action::Draft => Preset::Draft,
+                    Action::Medium => Preset::Medium,
+                    _ => Preset::Final,
+                };
+                preset.apply(&mut self.settings, &mut self.trace_settings);
+                self.tracer.reset();
+                tracing::info!("Switched to the {} preset", preset.name());
+            }
+            Action::Screenshot => self.screenshot(),
+            Action::Stats => {
+                self.show_stats = !self.show_stats;
+                if !self.show_stats {
+                    self.window.set_title(TITLE);
+                }
+            }
+            Action::Tonemap => {
+                self.settings.tonemap = self.settings.tonemap.next();
+                tracing::info!("Tone mapping: {}", self.settings.tonemap.name());
+            }
+            Action::Autofocus => {
+                self.autofocus.mode = self.autofocus.mode.next();
+                tracing::info!("Autofocus: {}", self.autofocus.mode.name());
+            }
+            Action::FocusPeaking => {
+                self.settings.focus_peaking = !self.settings.focus_peaking;
+            }
+            Action::AutoExposure => {
+                self.settings.auto_exposure = !self.settings.auto_exposure;
+                let state = if self.settings.auto_exposure {
+                    "on"
+                } else {
+                    "off"
+                };
+                tracing::info!("Auto-exposure: {state}");
+            }
+            Action::RecordPath => self.toggle_path_recording(),
+            Action::Fullscreen => self.toggle_fullscreen(),
+            Action::PresentMode => self.cycle_present_mode(),
+            Action::Pause => {
+                self.settings.paused = !self.settings.paused;
+                let state = if self.settings.paused {
+                    "Paused"
+                } else {
+                    "Resumed"
+                };
+                tracing::info!("{state} at {} samples", self.tracer.sample_count());
+            }
+            Action::Step => {
+                if self.settings.paused {
+                    self.step = true;
+                }
+            }
+            Action::Turntable => {
+                if self.turntable.is_on() {
+                    self.turntable.stop();
+                    tracing::info!("Turntable stopped");
+                } else {
+                    self.turntable.start(&self.scene, self.selected);
+                    tracing::info!("Turntable at {} degrees per second", self.turntable.speed);
+                }
+            }
+            Action::PlayTimeline => {
+                let timeline = &mut self.timeline;
+                if !timeline.playing && timeline.time >= timeline.duration {
+                    timeline.time = 0.0;
+                }
+                timeline.playing = !timeline.playing;
+            }
+            Action::ExposureUp | Action::ExposureDown => {
+                let step = if action == Action::ExposureUp {
+                    1.0
+                } else {
+                    -1.0
+                };
+                self.settings.step_exposure(step);
+                tracing::info!("Exposure: {:+.2} EV", self.settings.exposure);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Merges the file at `path` into the scene, read with the usual
+    /// conventions of its format.
+    #[cfg(feature = "ui")]
+    fn add_to_scene(&mut self, path: PathBuf) -> Result<()> {
+        self.scene.merge(Scene::load(&path)?);
+        lod::select(
+            &mut self.scene,
+            self.camera.position,
+            self.camera.fov_y,
+            self.size.height,
+        );
+        self.instanced = InstancedBvh::new(&self.scene);
+        self.upload_scene();
+        tracing::info!("Added {} to the scene", path.display());
+        Ok(())
+    }
+
+    /// Hands the whole scene to the tracer again after it's been replaced
+    /// or added to.
+    fn upload_scene(&mut self) {
+        self.tracer
+            .init(&self.device, &self.queue, &self.scene, &self.instanced);
+        self.scene_stats = SceneStats::new(&self.scene, &self.instanced);
+        #[cfg(feature = "physics")]
+        {
+            self.physics = physics::Physics::new(&self.scene);
+        }
+        self.svgf = None;
+        #[cfg(feature = "oidn")]
+        {
+            self.denoised = None;
+        }
+        self.animated_at = None;
+        self.timeline.duration = self.timeline.duration.max(self.scene.animation_duration());
+    }
+
+    /// Starts loading a file dropped onto the window.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn drop_file(&self, path: PathBuf) {
+        tracing::info!("Loading {}", path.display());
+        self.dropped.load(
+            path,
+            dropped::View {
+                eye: self.camera.position,
+                fov_y: self.camera.fov_y,
+                viewport_height: self.size.height,
+            },
+        );
+    }
+
+    /// Swaps in the dropped files that have finished loading. A scene keeps
+    /// the environment and backdrop unless it brings its own.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn apply_dropped(&mut self) {
+        for (path, loaded) in self.dropped.finished() {
+            match loaded {
+                Ok(Dropped::Scene(mut scene, instanced)) => {
+                    scene.environment = scene.environment.or(self.scene.environment.take());
+                    scene.backdrop = scene.backdrop.or(self.scene.backdrop.take());
+                    self.scene = *scene;
+                    self.instanced = instanced;
+                    self.selected = None;
+                    self.turntable.stop();
+                    self.upload_scene();
+                    self.frame_selection();
+                    tracing::info!("Opened {}", path.display());
+                }
+                Ok(Dropped::Environment(environment)) => {
+                    self.scene.environment = Some(environment);
+                    self.upload_scene();
+                    tracing::info!("Lighting with {}", path.display());
+                }
+                Err(err) => tracing::error!("{err:#}"),
+            }
+        }
+    }
+
+    /// Points the camera at the selected instance, or at everything.
+    fn frame_selection(&mut self) {
+        let bounds = self.selected.map_or_else(
+            || self.scene.bounds(),
+            |index| self.scene.instance_bounds(index),
+        );
+        let aspect = self.size.width as f32 / self.size.height.max(1) as f32;
+        self.controller.frame(&mut self.camera, &bounds, aspect);
+        self.tracer.reset();
+    }
+
+    /// Focuses at the depth under the cursor, as far as the guides of the
+    /// tracer have it.
+    fn focus_here(&mut self) {
+        let Some(cursor) = self.cursor else {
+            return;
+        };
+        let guide = self.tracer.normal_texture();
+        let scale = self.settings.resolution_scale;
+        let pixel = UVec2::new(
+            ((cursor.x as f32 * scale) as u32).min(guide.width() - 1),
+            ((cursor.y as f32 * scale) as u32).min(guide.height() - 1),
+        );
+        let focus_read = self.focus_read.clone();
+        self.readbacks
+            .read_texel(&self.device, guide, pixel, move |texel| {
+                if texel[3] <= 0.0 {
+                    tracing::info!("Nothing to focus on there");
+                    return;
+                }
+                focus_read.set(Some(texel[3]));
+            });
+    }
+
+    /// Moves the focus to the distance read by `focus_here`, once it's
+    /// arrived.
+    fn apply_focus_read(&mut self) {
+        let Some(distance) = self.focus_read.take() else {
+            return;
+        };
+        self.camera.focus_distance = distance;
+        self.tracer.reset();
+        tracing::info!("Focused at {distance:.2}");
+    }
+
+    /// Starts recording the camera path, or stops and saves it, replacing
+    /// the camera tracks of the timeline.
+    fn toggle_path_recording(&mut self) {
+        if !self.camera_path.is_recording() {
+            self.camera_path.start(&self.camera);
+            tracing::info!("Recording the camera path");
+            return;
+        }
+        let Some(path) = self.camera_path.stop() else {
+            return;
+        };
+        let file = self
+            .camera_path
+            .path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(format!("camera_path_{}.txt", timestamp())));
+        match path.save(&file) {
+            Ok(()) => tracing::info!("Saved the camera path to {}", file.display()),
+            Err(err) => tracing::error!("{err:#}"),
+        }
+        self.timeline.merge(path);
+        self.timeline.time = 0.0;
+    }
+
+    /// Saves the accumulated image as an sRGB PNG, as it is displayed but
+    /// without the overlay.
+    fn screenshot(&mut self) {
+        let exposure = Rc::new(Cell::new(self.settings.exposure));
+        if self.settings.auto_exposure {
+            let metered = exposure.clone();
+            self.readbacks.read_buffer(
+                &self.device,
+                self.auto_exposure.metered_buffer(),
+                0,
+                4,
+                move |bytes| {
+                    metered.set(metered.get() + bytemuck::pod_read_unaligned::<f32>(bytes));
+                },
+            );
+        }
+        let tonemap = self.settings.tonemap;
+        let display_lut = self.display_lut.clone();
+        let name = format!("spectrum_{}.png", timestamp());
+        self.readbacks.read_texture(
+            &self.device,
+            self.tracer.output_texture(),
+            0,
+            move |image| {
+                let scale = exposure.get().exp2();
+                let image = lut::display_image(&image, scale, tonemap, display_lut.as_ref());
+                if let Err(err) = save_screenshot(&image, &name) {
+                    tracing::error!("{err:#}");
+                }
+            },
+        );
+    }
+
+    /// Leaves fullscreen, or enters it in `fullscreen_mode`. The surface
+    /// follows with the resize event of the window.
+    fn toggle_fullscreen(&mut self) {
+        if self.window.fullscreen().is_some() {
+            self.window.set_fullscreen(None);
+            return;
+        }
+        let exclusive = match self.fullscreen_mode {
+            FullscreenMode::Borderless => None,
+            FullscreenMode::Exclusive => {
+                let mode = self.window.current_monitor().and_then(|monitor| {
+                    monitor.video_modes().max_by_key(|mode| {
+                        let size = mode.size();
+                        (size.width * size.height, mode.refresh_rate_millihertz())
+                    })
+                });
+                if mode.is_none() {
+                    tracing::warn!("No video mode to take over, going borderless");
+                }
+                mode
+            }
+        };
+        self.window.set_fullscreen(Some(match exclusive {
+            Some(mode) => Fullscreen::Exclusive(mode),
+            None => Fullscreen::Borderless(None),
+        }));
+    }
+
+    fn set_captured(&mut self, captured: bool) {
+        let result = if captured {
+            self.window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| self.window.set_cursor_grab(CursorGrabMode::Confined))
+        } else {
+            self.window.set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(err) = result {
+            tracing::warn!("Couldn't grab cursor: {err}");
+            return;
+        }
+        self.window.set_cursor_visible(!captured);
+        self.controller.fly.captured = captured;
+    }
+
+    /// Applies the messages of control surfaces since the last frame.
+    fn apply_controls(&mut self) {
+        if let Some(control) = &self.control {
+            let updates = control.poll();
+            self.apply_targets(&updates);
+        }
+    }
+
+    /// Sets parameters from control surfaces or the timeline.
+    fn apply_targets(&mut self, updates: &[(ControlTarget, f32)]) {
+        let (mut lights, mut materials, mut restart) = (false, false, false);
+        let mut camera = false;
+        for &(target, value) in updates {
+            match target {
+                ControlTarget::Exposure => self.settings.exposure = value,
+                ControlTarget::EnvironmentIntensity => {
+                    self.trace_settings.environment_intensity = value.max(0.0);
+                    restart = true;
+                }
+                ControlTarget::EnvironmentRotation => {
+                    self.trace_settings.environment_rotation = value.to_radians();
+                    restart = true;
+                }
+                ControlTarget::SunElevation => {
+                    self.trace_settings.sky.sun_elevation = value.clamp(-90.0, 90.0).to_radians();
+                    restart = true;
+                }
+                ControlTarget::SunAzimuth => {
+                    self.trace_settings.sky.sun_azimuth = value.to_radians();
+                    restart = true;
+                }
+                ControlTarget::Turbidity => {
+                    self.trace_settings.sky.turbidity = value.clamp(1.7, 10.0);
+                    restart = true;
+                }
+                ControlTarget::LightIntensity(index) => {
+                    if let Some(light) = self.scene.lights.get_mut(index) {
+                        light.intensity = value.max(0.0);
+                        lights = true;
+                    }
+                }
+                ControlTarget::MaterialEmission(index) => {
+                    if let Some(material) = self.scene.materials.get_mut(index) {
+                        // Keep the color, or glow in the base color
+                        let color = match material.emission.max_element() {
+                            max if max > 0.0 => material.emission / max,
+                            _ => material.base_color.truncate(),
+                        };
+                        material.emission = color * value.max(0.0);
+                        materials = true;
+                    }
+                }
+                ControlTarget::CameraPosition(axis) => {
+                    self.camera.position[axis] = value;
+                    camera = true;
+                }
+                ControlTarget::CameraYaw => {
+                    self.camera.yaw = value.to_radians();
+                    camera = true;
+                }
+                ControlTarget::CameraPitch => {
+                    self.camera.pitch = value.clamp(-89.0, 89.0).to_radians();
+                    camera = true;
+                }
+                ControlTarget::CameraFov => {
+                    self.camera.fov_y = value.clamp(1.0, 179.0).to_radians();
+                    camera = true;
+                }
+                ControlTarget::CameraFocusDistance => {
+                    self.camera.focus_distance = value.max(1e-3);
+                    camera = true;
+                }
+            }
+        }
+        if camera {
+            if self.controller.mode == CameraMode::Orbit {
+                self.controller.orbit.attach(&self.camera);
+            }
+            restart = true;
+        }
+        if lights {
+            self.tracer.update_lights(&self.queue, &self.scene);
+        }
+        if materials {
+            self.tracer.update_materials(&self.queue, &self.scene);
+        }
+        if restart {
+            self.tracer.reset();
+        }
+    }
+
+    /// Rebuilds the pipelines of shaders changed on disk.
+    #[cfg(feature = "hot-reload")]
+    fn reload_shaders(&mut self) {
+        let Some(watcher) = &self.shader_watcher else {
+            return;
+        };
+        let changed = watcher.changed();
+        if changed.is_empty() {
+            return;
+        }
+        if let Some(source) = hot_reload::compose("render.wgsl", &[], &changed) {
+            let (device, layout, format) = (&self.device, &self.blit_layout, tonemap::FORMAT);
+            if let Some(pipeline) =
+                hot_reload::compile(device, "Render Shader", &source, |module| {
+                    blit_pipeline(device, layout, format, module)
+                })
+            {
+                self.blit_pipeline = pipeline;
+            }
+        }
+        self.tonemap.reload_shaders(&self.device, &changed);
+        self.tracer.reload_shaders(&self.device, &changed);
+    }
+
+    /// Traces with `backend` from now on, starting the image over.
+    fn set_backend(&mut self, backend: Backend) {
+        if self.tracer.backend() == backend {
+            return;
+        }
+        self.tracer = backend.create(
+            &self.device,
+            &self.queue,
+            &self.scene,
+            &self.instanced,
+            self.image_size(),
+        );
+        self.tracer
+            .set_workgroup_size(&self.device, self.workgroup_size);
+        self.svgf = None;
+        tracing::info!("Tracing on the {}", backend.name().to_uppercase());
+    }
+
+    /// Shows the image through `lut`, or plain sRGB without one.
+    fn set_display_lut(&mut self, lut: Option<DisplayLut>) {
+        self.lut_views = lut::upload(&self.device, &self.queue, lut.as_ref());
+        self.display_lut = lut;
+    }
+
+    /// Blends the window with what's behind it while the background is
+    /// transparent, if the platform can.
+    fn alpha_mode(&self) -> wgpu::CompositeAlphaMode {
+        if self.trace_settings.transparent {
+            let blended = [
+                wgpu::CompositeAlphaMode::PreMultiplied,
+                wgpu::CompositeAlphaMode::Inherit,
+            ];
+            if let Some(mode) = blended
+                .into_iter()
+                .find(|mode| self.alpha_modes.contains(mode))
+            {
+                return mode;
+            }
+        }
+        self.alpha_modes[0]
+    }
+
+    /// Restarts the image at lighter settings if the GPU stalled on a
+    /// frame.
+    fn check_watchdog(&mut self) {
+        let Some(elapsed) = self.watchdog.stalled(&self.device) else {
+            return;
+        };
+        let size = self.image_size();
+        self.watchdog.report(
+            elapsed,
+            &self.trace_settings,
             size,
-            window,
-            surface_configured,
+            self.tracer.sample_count(),
+        );
+        if watchdog::lighten(&mut self.settings, &mut self.trace_settings, size) {
+            self.tracer.reset();
         }
     }
 
-    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 {
-            self.size = new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+    /// The present mode of the settings, or vsync if the surface can't.
+    fn present_mode(&self) -> wgpu::PresentMode {
+        let mode = self.settings.present_mode;
+        if mode.supported(&self.present_modes) {
+            mode.wgpu()
+        } else {
+            PresentMode::Fifo.wgpu()
+        }
+    }
+
+    /// Moves on to the next present mode the surface supports.
+    fn cycle_present_mode(&mut self) {
+        let modes = PresentMode::ALL;
+        let index = modes
+            .iter()
+            .position(|&mode| mode == self.settings.present_mode);
+        let start = index.map_or(0, |index| index + 1);
+        if let Some(mode) = (start..start + modes.len())
+            .map(|i| modes[i % modes.len()])
+            .find(|mode| mode.supported(&self.present_modes))
+        {
+            self.settings.present_mode = mode;
         }
+        tracing::info!("Present mode: {}", self.settings.present_mode.name());
     }
 
-    fn input(&mut self, _: &WindowEvent) -> bool {
-        false
+    fn update(&mut self) {
+        #[cfg(feature = "hot-reload")]
+        self.reload_shaders();
+        #[cfg(not(target_arch = "wasm32"))]
+        self.apply_dropped();
+        self.check_watchdog();
+        self.readbacks.poll(&self.device);
+        self.apply_focus_read();
+        #[cfg(feature = "oidn")]
+        self.apply_denoise_read();
+        let alpha_mode = self.alpha_mode();
+        let present_mode = self.present_mode();
+        if self.surface_configured
+            && (self.config.alpha_mode != alpha_mode || self.config.present_mode != present_mode)
+        {
+            self.config.alpha_mode = alpha_mode;
+            self.config.present_mode = present_mode;
+            self.surface.configure(&self.device, &self.config);
+        }
+        let now = Instant::now();
+        let mut dt = (now - self.last_update).as_secs_f32();
+        self.last_update = now;
+        if let Some(recording) = &mut self.recording {
+            // Time only passes between the frames of an animation
+            dt = if std::mem::take(&mut recording.advance) {
+                recording.fps.recip()
+            } else {
+                0.0
+            };
+        }
+
+        if self.tracer.is_animated() && dt > 0.0 {
+            self.trace_settings.time += dt;
+            self.tracer.reset();
+        }
+        self.apply_controls();
+        if let Some(updates) = self.timeline.update(dt) {
+            self.apply_targets(&updates);
+        }
+        let mut moved = self.animate();
+        if let Some(audio) = &mut self.audio {
+            let modulated = audio::modulate(&mut self.scene, &audio.update(dt));
+            if modulated.lights {
+                self.tracer.update_lights(&self.queue, &self.scene);
+            }
+            if modulated.materials {
+                self.tracer.update_materials(&self.queue, &self.scene);
+            }
+            if modulated.geometry {
+                self.instanced.refit(&self.scene);
+                moved = true;
+            }
+        }
+        #[cfg(feature = "physics")]
+        if let (Some(physics), true) = (&mut self.physics, dt > 0.0) {
+            moved |= physics.step(&mut self.scene, &mut self.instanced, dt);
+        }
+        let spin = self.turntable.step(dt);
+        let spun = self.selected.filter(|_| self.turntable.spin_selected);
+        if let (Some((center, angle)), Some(index)) = (spin, spun) {
+            // The node's other primitives and children turn along
+            let world = self.scene.instances[index].transform;
+            let world = turntable::turn_transform(world, center, angle);
+            self.scene.set_instance_transform(index, world);
+            // A refit would loosen the tree as the instance swings around
+            self.instanced.update(&self.scene);
+            moved = true;
+        }
+        if moved {
+            self.tracer
+                .update_geometry(&self.queue, &self.scene, &self.instanced);
+        }
+        let mut camera_moved = self.controller.update(&mut self.camera, dt);
+        if let (Some((center, angle)), None) = (spin, spun) {
+            turntable::turn_camera(&mut self.camera, center, angle);
+            if self.controller.mode == CameraMode::Orbit {
+                self.controller.orbit.attach(&self.camera);
+            }
+            camera_moved |= !self.turntable.motion_blur;
+        }
+        self.camera_path.update(&self.camera, dt);
+        let target = self
+            .selected
+            .map(|index| self.scene.instance_bounds(index).center());
+        let image_size = self.image_size();
+        if let Some(distance) = self.autofocus.update(
+            &self.device,
+            &mut self.readbacks,
+            &*self.tracer,
+            &self.camera,
+            target,
+            image_size,
+        ) {
+            self.camera.focus_distance = distance;
+            self.tracer.reset();
+        }
+        if !self.settings.accumulate {
+            self.tracer.reset();
+        } else if camera_moved {
+            self.tracer.camera_moved(self.trace_settings.reproject);
+        }
     }
 
-    fn update(&mut self) {}
+    /// Poses the scene's animations at the playhead of the timeline if it
+    /// moved, returning whether the geometry did.
+    fn animate(&mut self) -> bool {
+        let time = self.timeline.time;
+        if self.scene.animations.is_empty() || self.animated_at == Some(time) {
+            return false;
+        }
+        self.animated_at = Some(time);
+        if !self.scene.animate(time) {
+            return false;
+        }
+        self.tracer.update_lights(&self.queue, &self.scene);
+        // Refitting loosens the tree as the instances move apart, so it's
+        // rebuilt every so often
+        if self.tlas_refits < MAX_TLAS_REFITS {
+            self.instanced.refit(&self.scene);
+            self.tlas_refits += 1;
+        } else {
+            self.instanced.update(&self.scene);
+            self.tlas_refits = 0;
+        }
+        true
+    }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
         let output = self.surface.get_current_texture()?;
@@ -138,6 +1657,151 @@ impl State {
                 label: Some("Render Encoder"),
             });
 
+        let size = self.tracer.output_texture().size();
+        let mut image_size = PhysicalSize::new(size.width, size.height);
+        if image_size != self.image_size() && !self.resizing() {
+            self.resized_at = None;
+            self.resize_image();
+            image_size = self.image_size();
+        }
+        let display_scale = self.display_scale();
+        let max_samples = self.settings.max_samples;
+        let traced = (max_samples == 0 || self.tracer.sample_count() < max_samples)
+            && (!self.settings.paused || std::mem::take(&mut self.step));
+        if traced {
+            self.tracer.render_frame(
+                &self.device,
+                &self.queue,
+                &mut encoder,
+                &self.camera,
+                &self.trace_settings,
+            );
+        }
+        let rays = if traced {
+            image_size.width as u64 * image_size.height as u64
+        } else {
+            0
+        };
+        let measured = self
+            .frame_stats
+            .frame(&self.device, rays, self.tracer.sample_count());
+        if measured && self.show_stats {
+            self.window
+                .set_title(&format!("{TITLE} - {}", self.frame_stats));
+        }
+        #[cfg(feature = "oidn")]
+        if self.settings.denoise == DenoiseMode::Oidn {
+            let samples = self.tracer.sample_count();
+            let due = self.denoised.is_none()
+                || traced && (samples.is_power_of_two() || samples == max_samples);
+            // The readback is submitted after this frame, so it sees the
+            // samples just traced
+            if due && !self.denoising {
+                if let Err(err) = self.read_for_denoiser() {
+                    tracing::error!("{err:#}");
+                    self.settings.denoise = DenoiseMode::Off;
+                }
+            }
+        } else {
+            self.denoised = None;
+        }
+        let mut source = self.tracer.output_view();
+        #[cfg(feature = "oidn")]
+        if let Some(denoised) = &self.denoised {
+            source = denoised;
+        }
+        if self.settings.denoise == DenoiseMode::Svgf {
+            let svgf = self
+                .svgf
+                .get_or_insert_with(|| Svgf::new(&self.device, image_size));
+            svgf.render(
+                &self.device,
+                &self.queue,
+                &mut encoder,
+                &*self.tracer,
+                &self.camera,
+            );
+            source = svgf.output_view();
+        }
+        let export_aovs = self
+            .recording
+            .as_ref()
+            .is_some_and(|recording| recording.aovs);
+        if self.settings.aov != Aov::Beauty || export_aovs {
+            let aovs = self
+                .aovs
+                .get_or_insert_with(|| Aovs::new(&self.device, image_size));
+            aovs.render(
+                &self.device,
+                &self.queue,
+                &mut encoder,
+                &*self.tracer,
+                &self.camera,
+                &self.prev_camera,
+            );
+            if let Some(view) = aovs.view(self.settings.aov) {
+                source = view;
+            }
+        }
+        if self.recording.is_none() {
+            self.prev_camera = self.camera;
+        }
+        let auto_exposure = self.settings.auto_exposure && self.settings.aov == Aov::Beauty;
+        if auto_exposure {
+            self.auto_exposure
+                .meter(&self.device, &self.queue, &mut encoder, source, image_size);
+        } else {
+            self.auto_exposure.reset();
+        }
+
+        let (shaper, cube) = match &self.display_lut {
+            Some(lut) => (
+                [lut.shaper.domain_min, lut.shaper.domain_max],
+                [lut.cube.domain_min, lut.cube.domain_max],
+            ),
+            None => Default::default(),
+        };
+        let display = DisplayParams {
+            exposure: self.settings.exposure,
+            display_lut: self.display_lut.is_some() as u32,
+            resolution_scale: display_scale,
+            aov: self.settings.aov as u32,
+            auto_exposure: auto_exposure as u32,
+            _pad: [0; 2],
+            shaper_min: shaper[0].extend(0.0).to_array(),
+            shaper_max: shaper[1].extend(0.0).to_array(),
+            cube_min: cube[0].extend(0.0).to_array(),
+            cube_max: cube[1].extend(0.0).to_array(),
+        };
+        self.queue
+            .write_buffer(&self.display_buffer, 0, bytemuck::bytes_of(&display));
+        let blit_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Blit Bind Group"),
+            layout: &self.blit_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.display_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&self.lut_views[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.lut_views[1]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: self.auto_exposure.metered_buffer().as_entire_binding(),
+                },
+            ],
+        });
+
         {
             let clear_color = wgpu::Color {
                 r: 0.1,
@@ -146,7 +1810,7 @@ impl State {
                 a: 1.0,
             };
             let color_attachment = wgpu::RenderPassColorAttachment {
-                view: &view,
+                view: self.tonemap.view(),
                 resolve_target: None,
                 ops: wgpu::Operations {
                     load: wgpu::LoadOp::Clear(clear_color),
@@ -160,14 +1824,331 @@ impl State {
                 occlusion_query_set: None,
                 timestamp_writes: None,
             };
-            let _render_pass = encoder.begin_render_pass(&render_pass_desc);
+            let mut render_pass = encoder.begin_render_pass(&render_pass_desc);
+            render_pass.set_pipeline(&self.blit_pipeline);
+            render_pass.set_bind_group(0, &blit_bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        // AOVs are data and a display LUT is the whole transform
+        let tonemap = if self.settings.aov == Aov::Beauty && self.display_lut.is_none() {
+            self.settings.tonemap
+        } else {
+            Tonemap::None
+        };
+        let peaking =
+            (self.settings.focus_peaking && self.settings.aov == Aov::Beauty).then(|| {
+                let peaking = FocusPeaking::new(
+                    &self.camera,
+                    self.tracer.output_texture().height(),
+                    display_scale[1],
+                );
+                (peaking, self.tracer.guide_views()[1])
+            });
+        self.tonemap.draw(
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            &view,
+            tonemap,
+            peaking,
+        );
+
+        #[cfg(feature = "ui")]
+        {
+            let panels = ui::Panels {
+                camera: &mut self.camera,
+                controller: &mut self.controller,
+                tracer: &*self.tracer,
+                trace_settings: &mut self.trace_settings,
+                settings: &mut self.settings,
+                timeline: &mut self.timeline,
+                camera_path: &mut self.camera_path,
+                turntable: &mut self.turntable,
+                selected: &mut self.selected,
+                autofocus: &mut self.autofocus.mode,
+                present_modes: &self.present_modes,
+                scene: &self.scene,
+                stats: self
+                    .show_stats
+                    .then_some((&self.frame_stats, &self.scene_stats)),
+            };
+            let changed = self.ui.render(
+                &self.device,
+                &self.queue,
+                &mut encoder,
+                &view,
+                &self.window,
+                panels,
+            );
+            if changed {
+                self.tracer.reset();
+            }
         }
 
         self.queue.submit(std::iter::once(encoder.finish()));
+        self.readbacks.submit(&self.queue);
+        self.watchdog.submitted(&self.queue);
         output.present();
 
+        #[cfg(feature = "ui")]
+        if let Some(path) = self.ui.take_import() {
+            if let Err(err) = self.add_to_scene(path) {
+                tracing::error!("{err:#}");
+            }
+        }
+        #[cfg(feature = "ui")]
+        if self.ui.take_step() {
+            self.step = true;
+        }
+        #[cfg(feature = "ui")]
+        if self.ui.take_record_toggle() {
+            self.toggle_path_recording();
+        }
+        #[cfg(feature = "ui")]
+        if let Some(backend) = self.ui.take_backend() {
+            self.set_backend(backend);
+        }
+        #[cfg(feature = "ui")]
+        if let Some(index) = self.ui.take_holdout_toggle() {
+            let instance = &mut self.scene.instances[index];
+            instance.holdout = !instance.holdout;
+            self.tracer
+                .update_geometry(&self.queue, &self.scene, &self.instanced);
+        }
+
+        Ok(())
+    }
+
+    /// Reads back the latest samples and guides, which
+    /// `apply_denoise_read` denoises once they arrive.
+    #[cfg(feature = "oidn")]
+    fn read_for_denoiser(&mut self) -> Result<()> {
+        if self.denoiser.is_none() {
+            self.denoiser = Some(denoise::Denoiser::new()?);
+        }
+        let images = Rc::new(RefCell::new(Vec::new()));
+        let textures = [
+            self.tracer.output_texture(),
+            self.tracer.albedo_texture(),
+            self.tracer.normal_texture(),
+        ];
+        for texture in textures {
+            let images = images.clone();
+            self.readbacks
+                .read_texture(&self.device, texture, 0, move |image| {
+                    images.borrow_mut().push(image);
+                });
+        }
+        let read = self.denoise_read.clone();
+        self.readbacks
+            .then(move || *read.borrow_mut() = Some(images.take()));
+        self.denoising = true;
         Ok(())
     }
+
+    /// Replaces the denoised image with one of the samples read back, if
+    /// they arrived.
+    #[cfg(feature = "oidn")]
+    fn apply_denoise_read(&mut self) {
+        let Some(images) = self.denoise_read.take() else {
+            return;
+        };
+        self.denoising = false;
+        let size = self.tracer.output_texture().size();
+        let (Ok([color, albedo, normal]), Some(denoiser)) =
+            (<[_; 3]>::try_from(images), &mut self.denoiser)
+        else {
+            return;
+        };
+        // Samples from before a resize are left for the next readback
+        if color.dimensions() != (size.width, size.height) {
+            return;
+        }
+        let image = match denoiser.denoise(&color, &albedo, &normal) {
+            Ok(image) => image,
+            Err(err) => {
+                tracing::error!("{err:#}");
+                self.settings.denoise = DenoiseMode::Off;
+                return;
+            }
+        };
+        let texture = self.device.create_texture_with_data(
+            &self.queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Denoised Texture"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            bytemuck::cast_slice(image.as_raw()),
+        );
+        self.denoised = Some(texture.create_view(&wgpu::TextureViewDescriptor::default()));
+    }
+
+    /// Starts saving the current frame of an animation once it has all its
+    /// samples, and moves on to the next.
+    fn record_frame(&mut self) {
+        let resizing = self.resizing();
+        let Some(recording) = &mut self.recording else {
+            return;
+        };
+        if recording.frame >= recording.frame_count
+            || self.tracer.sample_count() < recording.samples
+            || resizing
+        {
+            return;
+        }
+        let image = Rc::new(RefCell::new(None));
+        let read = image.clone();
+        self.readbacks.read_texture(
+            &self.device,
+            self.tracer.output_texture(),
+            0,
+            move |color| {
+                *read.borrow_mut() = Some(color);
+            },
+        );
+        // Bake in the exposure so it can be animated too
+        let scale = self.settings.exposure.exp2();
+        let path = recording
+            .directory
+            .join(format!("frame_{:04}.exr", recording.frame));
+        let (frame, frame_count) = (recording.frame, recording.frame_count);
+        let (precision, bracket) = (recording.precision, recording.bracket.clone());
+        let colorspace = recording.colorspace.clone();
+        let (tonemap, display_lut) = (self.settings.tonemap, self.display_lut.clone());
+        let save = self
+            .saves
+            .start(move |aovs: Vec<(Aov, image::Rgba32FImage)>| {
+                let mut image = image
+                    .take()
+                    .with_context(|| format!("Failed to read frame {} back", frame + 1))?;
+                for pixel in image.pixels_mut() {
+                    for channel in &mut pixel.0[..3] {
+                        *channel *= scale;
+                    }
+                }
+                bracket::save(&image, &path, 1.0, &bracket, tonemap, display_lut.as_ref())?;
+                if let Some(colorspace) = &colorspace {
+                    colorspace.apply_image(&mut image);
+                }
+                exr::save_with_aovs(&image, &aovs, &path, precision)?;
+                tracing::info!("Saved frame {} of {frame_count}", frame + 1);
+                Ok(())
+            });
+        match &self.aovs {
+            Some(aovs) if recording.aovs => aovs.read(&self.device, &mut self.readbacks, save),
+            _ => self.readbacks.then(move || save(Vec::new())),
+        }
+        // Copied before the next frame replaces the samples
+        self.readbacks.submit(&self.queue);
+        self.prev_camera = self.camera;
+        recording.frame += 1;
+        recording.advance = true;
+        self.timeline.time = recording.frame as f32 / recording.fps;
+    }
+
+    /// Starts saving the image once it has all its samples, after which
+    /// the app exits.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_output(&mut self) {
+        let max_samples = self.settings.max_samples;
+        if max_samples == 0 || self.tracer.sample_count() < max_samples || self.resizing() {
+            return;
+        }
+        let Some(output) = self.output.take() else {
+            return;
+        };
+        let scale = self.settings.exposure.exp2();
+        let (tonemap, display_lut) = (self.settings.tonemap, self.display_lut.clone());
+        self.saved_output = Some(output.path.clone());
+        let save = self.saves.start(move |image: image::Rgba32FImage| {
+            output.save(&image, scale, tonemap, display_lut.as_ref())?;
+            tracing::info!("Saved {}", output.path.display());
+            Ok(())
+        });
+        self.readbacks
+            .read_texture(&self.device, self.tracer.output_texture(), 0, save);
+        self.readbacks.submit(&self.queue);
+    }
+
+    fn recording_finished(&self) -> bool {
+        self.recording
+            .as_ref()
+            .is_some_and(|recording| recording.frame >= recording.frame_count)
+    }
+
+    /// Sends word of the recording and the output saved, or of the save
+    /// that failed, the first time it's called.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn notify(&mut self) {
+        let Some(notifier) = self.notifier.take() else {
+            return;
+        };
+        let size = self.tracer.output_texture().size();
+        let error = self.saves.error.borrow().clone();
+        let recorded = self.recording.as_ref().map(|recording| {
+            let frames = recording.frame;
+            (recording.directory.clone(), recording.samples, frames)
+        });
+        let saved = self.saved_output.clone();
+        let saved = saved.map(|path| (path, self.settings.max_samples, 1));
+        for (output, samples, frames) in recorded.into_iter().chain(saved) {
+            notifier.send(&Summary {
+                output,
+                width: size.width,
+                height: size.height,
+                samples,
+                frames,
+                elapsed: self.started.elapsed(),
+                error: error.clone(),
+            });
+        }
+    }
+}
+
+/// Draws the image, or an AOV, to the window with the display transform.
+fn blit_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+    module: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Render Pipeline Layout"),
+        bind_group_layouts: &[layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "vert_main",
+            buffers: &[],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: "frag_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
 }
 
 enum UserEvent {
@@ -176,13 +2157,88 @@ enum UserEvent {
 
 struct App {
     state: Option<State>,
+    scene: Option<Scene>,
+    audio: Option<AudioInput>,
+    control: Option<ControlInput>,
+    timeline: Option<Timeline>,
+    camera_path: PathRecorder,
+    /// Degrees per second to start turning at, if given on the command line.
+    turntable: Option<f32>,
+    /// Starts fullscreen this way, if given on the command line.
+    fullscreen: Option<FullscreenMode>,
+    present_mode: Option<PresentMode>,
+    watchdog: Option<Duration>,
+    rays_per_submit: u32,
+    turntable_pivot: Option<Vec3>,
+    recording: Option<Recording>,
+    preset: Option<Preset>,
+    /// Replaces the sampler the scene asks for.
+    sampler: Option<SamplerKind>,
+    tonemap: Option<Tonemap>,
+    auto_exposure: bool,
+    autofocus: AutofocusMode,
+    /// Lens distortion of the camera, if given on the command line.
+    distortion: Option<LensDistortion>,
+    /// Opens a transparent window and leaves the background out.
+    transparent: bool,
+    backend: Option<Backend>,
+    aov: Option<Aov>,
+    /// Inner size of the window, if given on the command line.
+    size: Option<PhysicalSize<u32>>,
+    /// Where the window stops accumulating, if given on the command line.
+    samples: Option<u32>,
+    #[cfg(not(target_arch = "wasm32"))]
+    output: Option<Output>,
+    #[cfg(not(target_arch = "wasm32"))]
+    notifier: Notifier,
+    display_lut: Option<DisplayLut>,
+    keys: Option<Keybindings>,
     event_loop_proxy: EventLoopProxy<UserEvent>,
 }
 
 impl App {
-    fn new(event_loop: &EventLoop<UserEvent>) -> Self {
+    fn new(
+        event_loop: &EventLoop<UserEvent>,
+        scene: Scene,
+        audio: Option<AudioInput>,
+        control: Option<ControlInput>,
+        timeline: Timeline,
+        recording: Option<Recording>,
+        args: &Args,
+    ) -> Self {
         Self {
             state: None,
+            scene: Some(scene),
+            audio,
+            control,
+            timeline: Some(timeline),
+            camera_path: PathRecorder::new(args.path_smoothing, args.camera_path.clone()),
+            turntable: args.turntable,
+            fullscreen: args.fullscreen,
+            present_mode: args.present_mode,
+            watchdog: args.watchdog,
+            rays_per_submit: args.rays_per_submit.unwrap_or_default(),
+            turntable_pivot: args.turntable_pivot,
+            recording,
+            preset: args.preset,
+            sampler: args.sampler,
+            tonemap: args.tonemap,
+            auto_exposure: args.auto_exposure,
+            autofocus: args.autofocus,
+            distortion: args.distortion,
+            transparent: args.transparent,
+            backend: args.backend,
+            aov: args.aov,
+            size: args
+                .resolution()
+                .map(|(width, height)| PhysicalSize::new(width, height)),
+            samples: args.samples,
+            #[cfg(not(target_arch = "wasm32"))]
+            output: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            notifier: notifier(args),
+            display_lut: None,
+            keys: None,
             event_loop_proxy: event_loop.create_proxy(),
         }
     }
@@ -191,7 +2247,16 @@ impl App {
 impl ApplicationHandler<UserEvent> for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         tracing::info!("Resumed");
-        let window_attrs = Window::default_attributes();
+        // The scene is handed over to the first state
+        let Some(scene) = self.scene.take() else {
+            return;
+        };
+        let mut window_attrs = Window::default_attributes()
+            .with_title(TITLE)
+            .with_transparent(self.transparent);
+        if let Some(size) = self.size {
+            window_attrs = window_attrs.with_inner_size(size);
+        }
         let window = event_loop
             .create_window(window_attrs)
             .expect("Couldn't create window.");
@@ -213,9 +2278,17 @@ impl ApplicationHandler<UserEvent> for App {
 
             // Winit prevents sizing with CSS, so we have to set
             // the size manually when on web.
-            let _ = window.request_inner_size(PhysicalSize::new(450, 400));
-
-            let state_future = State::new(Arc::new(window));
+            let _ = window.request_inner_size(self.size.unwrap_or(PhysicalSize::new(450, 400)));
+
+            let state_future = State::new(
+                Arc::new(window),
+                scene,
+                self.audio.take(),
+                self.control.take(),
+                self.timeline.take().unwrap_or_default(),
+                self.recording.take(),
+                self.preset,
+            );
             let event_loop_proxy = self.event_loop_proxy.clone();
             let future = async move {
                 let state = state_future.await;
@@ -227,7 +2300,15 @@ impl ApplicationHandler<UserEvent> for App {
         }
         #[cfg(not(target_arch = "wasm32"))]
         {
-            let state = pollster::block_on(State::new(Arc::new(window)));
+            let state = pollster::block_on(State::new(
+                Arc::new(window),
+                scene,
+                self.audio.take(),
+                self.control.take(),
+                self.timeline.take().unwrap_or_default(),
+                self.recording.take(),
+                self.preset,
+            ));
             assert!(self
                 .event_loop_proxy
                 .send_event(UserEvent::StateReady(state))
@@ -236,7 +2317,67 @@ impl ApplicationHandler<UserEvent> for App {
     }
 
     fn user_event(&mut self, _: &ActiveEventLoop, event: UserEvent) {
-        let UserEvent::StateReady(state) = event;
+        let UserEvent::StateReady(mut state) = event;
+        state.trace_settings.transparent = self.transparent;
+        state.trace_settings.rays_per_submit = self.rays_per_submit;
+        if let Some(timeout) = self.watchdog {
+            state.watchdog.timeout = timeout;
+        }
+        if let Some(sampler) = self.sampler {
+            state.trace_settings.sampler = sampler;
+        }
+        if let Some(distortion) = self.distortion {
+            state.camera.distortion = distortion;
+        }
+        if let Some(aov) = self.aov {
+            state.settings.aov = aov;
+        }
+        if let Some(tonemap) = self.tonemap {
+            state.settings.tonemap = tonemap;
+        }
+        state.settings.auto_exposure |= self.auto_exposure;
+        if let Some(mode) = self.present_mode {
+            if !mode.supported(&state.present_modes) {
+                tracing::warn!("The surface can't present {}, using fifo", mode.name());
+            }
+            state.settings.present_mode = mode;
+        }
+        state.autofocus = Autofocus::new(self.autofocus);
+        state.camera_path = std::mem::take(&mut self.camera_path);
+        if let Some(mode) = self.fullscreen {
+            state.fullscreen_mode = mode;
+            state.toggle_fullscreen();
+        }
+        state.turntable.pivot = self.turntable_pivot;
+        if let Some(speed) = self.turntable {
+            state.turntable.speed = speed;
+            state.turntable.start(&state.scene, state.selected);
+        }
+        if let Some(samples) = self.samples {
+            state.settings.max_samples = samples;
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(output) = self.output.take() {
+            if state.settings.max_samples == 0 {
+                // Something has to say when the image is done
+                state.settings.max_samples = DEFAULT_OUTPUT_SAMPLES;
+            }
+            state.output = Some(output);
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            state.notifier = Some(self.notifier.clone());
+            state.started = Instant::now();
+        }
+        if let Some(backend) = self.backend {
+            state.set_backend(backend);
+        }
+        if let Some(lut) = self.display_lut.take() {
+            state.set_display_lut(Some(lut));
+        }
+        if let Some(keys) = self.keys.take() {
+            state.keys = keys;
+        }
         self.state = Some(state);
     }
 
@@ -299,11 +2440,41 @@ impl ApplicationHandler<UserEvent> for App {
                         tracing::warn!("Surface timeout");
                     }
                 }
+                state.record_frame();
+                #[cfg(not(target_arch = "wasm32"))]
+                state.save_output();
+                // Files still being written are waited for
+                if state.saves.failed() {
+                    #[cfg(not(target_arch = "wasm32"))]
+                    state.notify();
+                    event_loop.exit();
+                } else if state.saves.is_done() {
+                    if state.recording_finished() {
+                        tracing::info!("Animation rendered");
+                        #[cfg(not(target_arch = "wasm32"))]
+                        state.notify();
+                        event_loop.exit();
+                    }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if state.saved_output.is_some() {
+                        state.notify();
+                        event_loop.exit();
+                    }
+                }
             }
             _ => {}
         }
     }
 
+    fn device_event(&mut self, _: &ActiveEventLoop, _: DeviceId, event: DeviceEvent) {
+        let Some(ref mut state) = self.state else {
+            return;
+        };
+        if let DeviceEvent::MouseMotion { delta } = event {
+            state.controller.process_mouse_motion(delta);
+        }
+    }
+
     fn about_to_wait(&mut self, _: &ActiveEventLoop) {
         if let Some(ref state) = self.state {
             state.window.request_redraw();
@@ -311,6 +2482,282 @@ impl ApplicationHandler<UserEvent> for App {
     }
 }
 
+/// Writes a screenshot next to the executable.
+#[cfg(not(target_arch = "wasm32"))]
+fn save_screenshot(image: &image::RgbaImage, name: &str) -> Result<()> {
+    let path = std::env::current_exe()
+        .context("Failed to find the executable")?
+        .with_file_name(name);
+    image
+        .save(&path)
+        .with_context(|| format!("Failed to save {}", path.display()))?;
+    tracing::info!("Saved {}", path.display());
+    Ok(())
+}
+
+/// Hands a screenshot to the browser as a download.
+#[cfg(target_arch = "wasm32")]
+fn save_screenshot(image: &image::RgbaImage, name: &str) -> Result<()> {
+    use wasm_bindgen::JsCast;
+
+    let js_error = |err: wasm_bindgen::JsValue| anyhow::anyhow!("{err:?}");
+    let mut png = Vec::new();
+    image
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .context("Failed to encode the screenshot")?;
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(png.as_slice()));
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type("image/png");
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+        .map_err(js_error)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob).map_err(js_error)?;
+    let link: web_sys::HtmlAnchorElement = web_sys::window()
+        .and_then(|window| window.document())
+        .context("No document to download from")?
+        .create_element("a")
+        .map_err(js_error)?
+        .unchecked_into();
+    link.set_href(&url);
+    link.set_download(name);
+    link.click();
+    web_sys::Url::revoke_object_url(&url).map_err(js_error)?;
+    tracing::info!("Downloaded {name}");
+    Ok(())
+}
+
+/// The current UTC time for file names, like `2024-05-01_13-45-00`.
+fn timestamp() -> String {
+    let seconds = web_time::SystemTime::now()
+        .duration_since(web_time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let (days, time) = (seconds / 86_400, seconds % 86_400);
+    // Howard Hinnant's civil_from_days, with eras of 400 years
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+    format!(
+        "{year:04}-{month:02}-{day:02}_{:02}-{:02}-{:02}",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// The display transform `args` ask for, read from a .cube LUT or baked
+/// from a display and view of an OCIO config, and the conversion of saved
+/// .exr images into the config's output color space.
+fn color_transforms(args: &Args) -> Result<(Option<DisplayLut>, Option<ocio::Processor>)> {
+    let Some(path) = &args.ocio else {
+        if args.display.is_some()
+            || args.view.is_some()
+            || args.working_colorspace.is_some()
+            || args.output_colorspace.is_some()
+        {
+            anyhow::bail!("--display, --view and the color spaces require --ocio");
+        }
+        let display_lut = args
+            .display_lut
+            .as_deref()
+            .map(DisplayLut::load)
+            .transpose()?;
+        return Ok((display_lut, None));
+    };
+    if args.display_lut.is_some() {
+        anyhow::bail!("--display-lut and --ocio can't be used together");
+    }
+    let config = ocio::Config::load(path)?;
+    let working = args.working_colorspace.as_deref().unwrap_or("scene_linear");
+    let display_name = match &args.display {
+        Some(display) => display.as_str(),
+        None => config.default_display()?,
+    };
+    let view = match &args.view {
+        Some(view) => view.as_str(),
+        None => config.default_view(display_name)?,
+    };
+    let display_lut = config
+        .display_view(working, display_name, view)
+        .with_context(|| {
+            format!(
+                "Failed to bake {display_name} {view} from {}",
+                path.display()
+            )
+        })?
+        .bake();
+    tracing::info!("Showing {display_name} {view} of {}", path.display());
+    let output = args
+        .output_colorspace
+        .as_deref()
+        .map(|output| config.processor(working, output))
+        .transpose()?;
+    Ok((Some(display_lut), output))
+}
+
+/// Every file a render with `args` reads, absolute, sorted and once each.
+#[cfg(not(target_arch = "wasm32"))]
+fn job_inputs(args: &Args, config: &Config) -> Result<Vec<PathBuf>> {
+    let mut inputs = Vec::new();
+    for path in args.scene.iter().chain(&args.add) {
+        inputs.extend(job::scene_files(path)?);
+    }
+    inputs.extend(
+        [
+            &args.environment,
+            &args.backdrop,
+            &args.display_lut,
+            &args.ocio,
+            &config.path,
+        ]
+        .into_iter()
+        .flatten()
+        .cloned(),
+    );
+    if let Some(path) = &args.ocio {
+        inputs.extend(ocio::Config::load(path)?.files());
+    }
+    let mut inputs: Vec<_> = inputs
+        .into_iter()
+        .map(|path| std::path::absolute(&path).unwrap_or(path))
+        .collect();
+    inputs.sort();
+    inputs.dedup();
+    Ok(inputs)
+}
+
+/// Where `render` saves, next to the scene by default.
+#[cfg(not(target_arch = "wasm32"))]
+fn render_output(args: &Args) -> Result<PathBuf> {
+    Ok(match (&args.output, &args.scene) {
+        (Some(output), _) => output.clone(),
+        (None, Some(scene)) => {
+            let stem = scene.file_stem().unwrap_or_default().to_string_lossy();
+            scene.with_file_name(format!("{stem}_render.png"))
+        }
+        (None, None) => anyhow::bail!("render requires a scene"),
+    })
+}
+
+/// What `render` traces of `scene`, with the display settings it's saved
+/// with.
+#[cfg(not(target_arch = "wasm32"))]
+fn headless_render(
+    args: &Args,
+    scene: &Scene,
+    display_lut: Option<&DisplayLut>,
+) -> (headless::Headless, Settings) {
+    // The same settings the window starts with
+    let mut settings = Settings::default();
+    let mut trace_settings = TraceSettings::default();
+    scene.render.apply(&mut settings, &mut trace_settings);
+    if let Some(preset) = args.preset {
+        preset.apply(&mut settings, &mut trace_settings);
+    }
+    if let Some(sampler) = args.sampler {
+        trace_settings.sampler = sampler;
+    }
+    if let Some(tonemap) = args.tonemap {
+        settings.tonemap = tonemap;
+    }
+    trace_settings.transparent = args.transparent;
+    trace_settings.rays_per_submit = args.rays_per_submit.unwrap_or_default();
+    let mut camera = Camera::default();
+    if let Some(distortion) = args.distortion {
+        camera.distortion = distortion;
+    }
+    let (width, height) = args.resolution().unwrap_or(DEFAULT_RESOLUTION);
+    let headless = headless::Headless {
+        size: PhysicalSize::new(width, height),
+        samples: args
+            .samples
+            .or((settings.max_samples > 0).then_some(settings.max_samples))
+            .unwrap_or(DEFAULT_OUTPUT_SAMPLES),
+        camera,
+        settings: trace_settings,
+        aov: args.aov.unwrap_or_default(),
+        tile: args.tile,
+        preview: (args.preview.is_some() || args.webhook.is_some()).then(|| Preview {
+            path: args.preview.clone(),
+            size: preview::DEFAULT_SIZE,
+            interval: args.preview_interval,
+            webhook: args.webhook.clone(),
+            scale: settings.exposure.exp2(),
+            tonemap: settings.tonemap,
+            display_lut: display_lut.cloned(),
+        }),
+    };
+    (headless, settings)
+}
+
+/// Either step of `calibration`, whichever the arguments are for.
+#[cfg(not(target_arch = "wasm32"))]
+fn calibrate(args: &Args) -> Result<()> {
+    if let Some(measurements) = &args.measurements {
+        let output = args
+            .output
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("display.cube"));
+        let fit = calibration::Fit::new(&calibration::read_sheet(measurements)?)?;
+        std::fs::write(&output, fit.display_lut().to_cube("Spectrum calibration"))
+            .with_context(|| format!("Failed to save {}", output.display()))?;
+        println!(
+            "The monitor was off by {:.4} on average, and the fit by {:.4}.",
+            fit.error, fit.residual
+        );
+        println!(
+            "Saved {}. Render the chart again with --display-lut {} and measure it to check.",
+            output.display(),
+            output.display()
+        );
+        return Ok(());
+    }
+    let output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("calibration_chart.png"));
+    let sheet = output.with_extension("csv");
+    let (display_lut, _) = color_transforms(args)?;
+    let colors = calibration::chart(args.illuminant);
+    lut::display_image(
+        &calibration::chart_image(&colors),
+        1.0,
+        Tonemap::None,
+        display_lut.as_ref(),
+    )
+    .save(&output)
+    .with_context(|| format!("Failed to save {}", output.display()))?;
+    calibration::write_sheet(&sheet, args.illuminant, &colors)?;
+    println!("1. Show {} full screen at 100% zoom.", output.display());
+    println!(
+        "2. Measure each patch with a colorimeter and fill its X, Y and Z into {}.",
+        sheet.display()
+    );
+    println!(
+        "3. Run spectrum calibrate --measurements {} -o display.cube",
+        sheet.display()
+    );
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn notifier(args: &Args) -> Notifier {
+    Notifier {
+        command: args.on_done.clone(),
+        webhook: args.done_webhook.clone(),
+        desktop: args.notify,
+    }
+}
+
 pub fn run() -> Result<()> {
     let env_filter = EnvFilter::builder()
         .with_default_directive(Level::INFO.into())
@@ -328,12 +2775,320 @@ pub fn run() -> Result<()> {
     }
     #[cfg(not(target_arch = "wasm32"))]
     {
-        let fmt_layer = tracing_subscriber::fmt::Layer::default();
+        // Standard output is left for what commands print, such as jobs
+        let fmt_layer = tracing_subscriber::fmt::Layer::default().with_writer(std::io::stderr);
         subscriber.with(fmt_layer).init();
     }
 
+    let mut args = Args::from_env()?;
+    let config = Config::load(args.config.as_deref())?;
+    config.apply(&mut args);
+    if args.command == Command::Samplers {
+        println!("{}", sampler::benchmark(&[16, 64, 256, 1024], 256));
+        return Ok(());
+    }
+    if args.command == Command::Colorspaces {
+        let path = args
+            .ocio
+            .as_deref()
+            .context("colorspaces requires --ocio")?;
+        print!("{}", ocio::Config::load(path)?.describe());
+        return Ok(());
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if args.command == Command::Calibrate {
+        return calibrate(&args);
+    }
+    let mut scene = match &args.scene {
+        Some(path) => Scene::load_with(path, &args.import)?,
+        None => Scene::default(),
+    };
+    for path in &args.add {
+        scene.merge(Scene::load_with(path, &args.import)?);
+    }
+    scene.render = scene.render.or(&config.render);
+    if let Some(path) = &args.environment {
+        scene.environment = Some(Environment::load(path)?);
+    }
+    if let Some(path) = &args.backdrop {
+        scene.backdrop = Some(Environment::load_backdrop(path)?);
+    }
+    let (display_lut, output_colorspace) = color_transforms(&args)?;
+    if args.ocean {
+        scene.add_ocean(Ocean::default());
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if args.command == Command::Benchmark {
+        let scenes = match &args.scene {
+            Some(path) => vec![(path.display().to_string(), scene)],
+            None => benchmark::scenes(),
+        };
+        let (width, height) = args.resolution().unwrap_or((1024, 1024));
+        println!(
+            "{}",
+            benchmark::run(scenes, PhysicalSize::new(width, height))?
+        );
+        return Ok(());
+    }
+    if args.stats {
+        println!("{}", SceneStats::new(&scene, &InstancedBvh::new(&scene)));
+        return Ok(());
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if args.command == Command::Thumbnail {
+        let output = match (&args.output, &args.scene) {
+            (Some(output), _) => output.clone(),
+            (None, Some(scene)) => scene.with_extension("png"),
+            (None, None) => anyhow::bail!("thumbnail requires a scene"),
+        };
+        let samples = args
+            .samples
+            .or(scene.render.samples)
+            .unwrap_or(thumbnail::DEFAULT_SAMPLES);
+        let image =
+            thumbnail::render_hdr(scene, args.size, samples, args.transparent, args.backend)?;
+        Output::new(output.clone(), &args, output_colorspace).save(
+            &image,
+            1.0,
+            args.tonemap.unwrap_or_default(),
+            display_lut.as_ref(),
+        )?;
+        tracing::info!("Saved {}", output.display());
+        return Ok(());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if args.command == Command::Render {
+        let output = render_output(&args)?;
+        let (headless, settings) = headless_render(&args, &scene, display_lut.as_ref());
+        let (width, height) = (headless.size.width, headless.size.height);
+        // What the tile hash covers besides the pixels
+        let inputs_hash = match args.tile {
+            Some(tile) if !tile.fits(headless.size) => {
+                anyhow::bail!("Tile {tile} is outside the {width}x{height} image")
+            }
+            Some(_) => Some(tile::inputs_hash(&job_inputs(&args, &config)?)?),
+            None => None,
+        };
+        let started = Instant::now();
+        let output = Output::new(output, &args, output_colorspace);
+        let result = headless.render(scene, args.backend).and_then(|image| {
+            if let (Some(tile), Some(inputs_hash)) = (headless.tile, &inputs_hash) {
+                // For whatever handed out the tile to check it by
+                println!("{}", tile::hash(inputs_hash, tile, &image));
+            }
+            if headless.aov == Aov::Beauty {
+                let scale = settings.exposure.exp2();
+                output.save(&image, scale, settings.tonemap, display_lut.as_ref())
+            } else {
+                output.save_aov(headless.aov, &image)
+            }
+        });
+        notifier(&args).send(&Summary {
+            output: output.path.clone(),
+            width,
+            height,
+            samples: headless.samples,
+            frames: 1,
+            elapsed: started.elapsed(),
+            error: result.as_ref().err().map(|err| format!("{err:#}")),
+        });
+        result?;
+        tracing::info!("Saved {}", output.path.display());
+        return Ok(());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if args.command == Command::ExportJob {
+        if args.scene.is_none() {
+            anyhow::bail!("export-job requires a scene");
+        }
+        let (render, _) = headless_render(&args, &scene, display_lut.as_ref());
+        let inputs = job_inputs(&args, &config)?;
+        let outputs = [Some(render_output(&args)?), args.preview.clone()];
+        let absolute = |path: &PathBuf| std::path::absolute(path).unwrap_or(path.clone());
+        let verifier = Verifier::new(args.verify_rate, args.seed);
+        let tiles = match args.tile_size {
+            Some(size) => Tile::grid(render.size, size)
+                .into_iter()
+                .enumerate()
+                .map(|(i, tile)| (tile, verifier.should_verify(i)))
+                .collect(),
+            None => Vec::new(),
+        };
+        let job = job::Job {
+            command: job::render_command(std::env::args().skip(2)),
+            working_directory: std::env::current_dir()?,
+            inputs_hash: tile::inputs_hash(&inputs)?,
+            inputs,
+            render,
+            backend: args.backend,
+            outputs: outputs.iter().flatten().map(absolute).collect(),
+            tiles,
+        };
+        let json = job.to_json()?;
+        match &args.job {
+            Some(path) => {
+                std::fs::write(path, json)
+                    .with_context(|| format!("Failed to save {}", path.display()))?;
+                tracing::info!("Saved {}", path.display());
+            }
+            None => print!("{json}"),
+        }
+        return Ok(());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if args.command == Command::ContactSheet {
+        let output = match (&args.output, &args.scene) {
+            (Some(output), _) => output.clone(),
+            (None, Some(scene)) => {
+                let stem = scene.file_stem().unwrap_or_default().to_string_lossy();
+                scene.with_file_name(format!("{stem}_sheet.png"))
+            }
+            (None, None) => anyhow::bail!("contact-sheet requires a scene"),
+        };
+        let samples = args
+            .samples
+            .or(scene.render.samples)
+            .unwrap_or(thumbnail::DEFAULT_SAMPLES);
+        let image = thumbnail::contact_sheet(
+            scene,
+            args.views,
+            args.size,
+            samples,
+            args.transparent,
+            display_lut.as_ref(),
+            args.backend,
+        )?;
+        image
+            .save(&output)
+            .with_context(|| format!("Failed to save {}", output.display()))?;
+        tracing::info!("Saved {}", output.display());
+        return Ok(());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if args.command == Command::Dataset {
+        let directory = match (&args.output, &args.scene) {
+            (Some(output), _) => output.clone(),
+            (None, Some(scene)) => {
+                let stem = scene.file_stem().unwrap_or_default().to_string_lossy();
+                scene.with_file_name(format!("{stem}_dataset"))
+            }
+            (None, None) => anyhow::bail!("dataset requires a scene"),
+        };
+        let dataset = dataset::Dataset {
+            count: args.count,
+            size: args.size,
+            samples: args
+                .samples
+                .or(scene.render.samples)
+                .unwrap_or(thumbnail::DEFAULT_SAMPLES),
+            seed: args.seed,
+            randomization: match &args.randomization {
+                Some(path) => dataset::Randomization::load(path)?,
+                None => Default::default(),
+            },
+        };
+        let started = Instant::now();
+        let result = dataset.render(scene, &directory, display_lut.as_ref(), args.backend);
+        notifier(&args).send(&Summary {
+            output: directory.clone(),
+            width: dataset.size,
+            height: dataset.size,
+            samples: dataset.samples,
+            frames: dataset.count,
+            elapsed: started.elapsed(),
+            error: result.as_ref().err().map(|err| format!("{err:#}")),
+        });
+        result?;
+        tracing::info!("Saved the dataset to {}", directory.display());
+        return Ok(());
+    }
+
     let event_loop = EventLoop::<UserEvent>::with_user_event().build()?;
-    let mut app = App::new(&event_loop);
+    let audio = args
+        .audio
+        .as_deref()
+        .map(|path| AudioInput::open(path, args.audio_rate))
+        .transpose()?;
+    if audio.is_some() && scene.audio_bindings.is_empty() {
+        tracing::warn!("Audio input given, but the scene has no audio bindings");
+    }
+    let control = if args.osc_port.is_some() || args.midi.is_some() {
+        let mut control = ControlInput::default();
+        if let Some(path) = &args.control_map {
+            control.load_map(path)?;
+        }
+        if let Some(port) = args.osc_port {
+            control.listen_osc(port)?;
+        }
+        if let Some(path) = &args.midi {
+            control.open_midi(path)?;
+        }
+        Some(control)
+    } else {
+        None
+    };
+    let mut timeline = match &args.timeline {
+        Some(path) => Timeline::load(path)?,
+        None => Timeline::default(),
+    };
+    // Long enough to play the scene's own animations, and only that
+    // without a timeline of its own
+    if !scene.animations.is_empty() {
+        let duration = args.timeline.as_ref().map_or(0.0, |_| timeline.duration);
+        timeline.duration = duration.max(scene.animation_duration());
+    }
+    let recording = match &args.animation {
+        Some(directory) => {
+            if timeline.is_empty() && scene.animations.is_empty() {
+                tracing::warn!("Rendering an animation without a timeline");
+            }
+            std::fs::create_dir_all(directory)
+                .with_context(|| format!("Failed to create {}", directory.display()))?;
+            timeline.looping = false;
+            Some(Recording {
+                directory: directory.clone(),
+                fps: args.fps,
+                samples: args
+                    .samples
+                    .or(scene.render.samples)
+                    .unwrap_or(DEFAULT_OUTPUT_SAMPLES),
+                frame: 0,
+                frame_count: ((timeline.duration * args.fps).round() as u32).max(1),
+                precision: args.exr,
+                aovs: args.aovs,
+                bracket: args.bracket.clone(),
+                colorspace: output_colorspace.clone(),
+                advance: false,
+            })
+        }
+        None => None,
+    };
+    let notifies = args.notify || args.on_done.is_some() || args.done_webhook.is_some();
+    if notifies && args.output.is_none() && args.animation.is_none() {
+        tracing::warn!("Nothing to notify about without --output or --animation");
+    }
+    let mut app = App::new(
+        &event_loop,
+        scene,
+        audio,
+        control,
+        timeline,
+        recording,
+        &args,
+    );
+    app.display_lut = display_lut;
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        app.output = args
+            .output
+            .clone()
+            .map(|path| Output::new(path, &args, output_colorspace));
+    }
+    app.keys = Some(config.keys);
 
     event_loop.run_app(&mut app)?;
     Ok(())
@@ -0,0 +1,312 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
+
+use glam::{DVec4, Vec3};
+
+use crate::scene::{Mesh, Scene};
+
+/// Meshes smaller than this are never simplified further.
+const MIN_LOD_TRIANGLES: usize = 256;
+const MAX_LODS: usize = 5;
+/// Target screen area covered by a single triangle when picking a level.
+const PIXELS_PER_TRIANGLE: f32 = 4.0;
+/// Boundary edges are kept in place by penalizing movement away from them.
+const BOUNDARY_WEIGHT: f64 = 100.0;
+
+/// Builds a chain of simplified index buffers, each with roughly half
+/// the triangles of the previous level.
+pub fn generate(mesh: &mut Mesh) {
+    mesh.lods.clear();
+    let mut indices = mesh.indices.clone();
+    while indices.len() / 3 > MIN_LOD_TRIANGLES && mesh.lods.len() < MAX_LODS {
+        let triangles = indices.len() / 3;
+        let simplified = simplify(&mesh.positions, &indices, triangles / 2);
+        // Stop once the mesh is locked up by seams and boundaries
+        if simplified.len() / 3 > triangles * 4 / 5 {
+            break;
+        }
+        indices = simplified;
+        mesh.lods.push(indices.clone());
+    }
+}
+
+/// Picks the coarsest level of every instance that still has about one
+/// triangle per `PIXELS_PER_TRIANGLE` pixels of its projected bounding sphere.
+pub fn select(scene: &mut Scene, eye: Vec3, fov_y: f32, viewport_height: u32) {
+    let spheres: Vec<_> = scene
+        .meshes
+        .iter()
+        .map(|mesh| {
+            let bounds = mesh.bounds();
+            (bounds.center(), 0.5 * bounds.extent().length())
+        })
+        .collect();
+    let pixels_per_unit = 0.5 * viewport_height as f32 / (0.5 * fov_y).tan();
+
+    for instance in &mut scene.instances {
+        let mesh = &scene.meshes[instance.mesh];
+        let (center, radius) = spheres[instance.mesh];
+        let center = instance.transform.transform_point3(center);
+        let scale = instance.transform.to_scale_rotation_translation().0;
+        let radius = radius * scale.abs().max_element();
+
+        let distance = center.distance(eye);
+        instance.lod = 0;
+        if distance <= radius {
+            continue;
+        }
+        let projected_radius = pixels_per_unit * radius / distance;
+        let projected_area = std::f32::consts::PI * projected_radius * projected_radius;
+        let wanted_triangles = (projected_area / PIXELS_PER_TRIANGLE) as usize;
+        instance.lod = (0..mesh.lod_count())
+            .rev()
+            .find(|&level| mesh.lod_indices(level).len() / 3 >= wanted_triangles)
+            .unwrap_or(0);
+    }
+}
+
+/// Quadric error metric simplification using half edge collapses, so the
+/// result only references existing vertices.
+pub fn simplify(positions: &[Vec3], indices: &[u32], target_triangles: usize) -> Vec<u32> {
+    let mut triangles: Vec<[u32; 3]> = indices
+        .chunks_exact(3)
+        .map(|t| [t[0], t[1], t[2]])
+        .collect();
+    let mut alive = vec![true; triangles.len()];
+    let mut alive_count = triangles.len();
+
+    let mut quadrics = vec![Quadric::default(); positions.len()];
+    let mut adjacency = vec![Vec::new(); positions.len()];
+    let mut edges: HashMap<(u32, u32), u32> = HashMap::new();
+    for (t, tri) in triangles.iter().enumerate() {
+        let [p0, p1, p2] = tri.map(|v| positions[v as usize]);
+        let normal = (p1 - p0).cross(p2 - p0);
+        let area = 0.5 * normal.length();
+        let Some(normal) = normal.try_normalize() else {
+            continue;
+        };
+        let plane = Quadric::plane(normal, p0, area as f64);
+        for &v in tri {
+            quadrics[v as usize].add(&plane);
+            adjacency[v as usize].push(t);
+        }
+        for k in 0..3 {
+            let (a, b) = (tri[k], tri[(k + 1) % 3]);
+            *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+        }
+    }
+
+    // Constrain open boundaries with planes perpendicular to their faces
+    for tri in &triangles {
+        let [p0, p1, p2] = tri.map(|v| positions[v as usize]);
+        let Some(normal) = (p1 - p0).cross(p2 - p0).try_normalize() else {
+            continue;
+        };
+        for k in 0..3 {
+            let (a, b) = (tri[k], tri[(k + 1) % 3]);
+            if edges[&(a.min(b), a.max(b))] != 1 {
+                continue;
+            }
+            let (pa, pb) = (positions[a as usize], positions[b as usize]);
+            let Some(side) = (pb - pa).cross(normal).try_normalize() else {
+                continue;
+            };
+            let weight = BOUNDARY_WEIGHT * pa.distance_squared(pb) as f64;
+            let plane = Quadric::plane(side, pa, weight);
+            quadrics[a as usize].add(&plane);
+            quadrics[b as usize].add(&plane);
+        }
+    }
+
+    // Vertices split along attribute seams share a position with another
+    // vertex. Moving them would tear the seam open so they stay put.
+    let mut seen: HashMap<[u32; 3], u32> = HashMap::new();
+    for p in positions {
+        *seen.entry(p.to_array().map(f32::to_bits)).or_default() += 1;
+    }
+    let locked: Vec<bool> = positions
+        .iter()
+        .map(|p| seen[&p.to_array().map(f32::to_bits)] > 1)
+        .collect();
+
+    let mut version = vec![0u32; positions.len()];
+    let mut remap: Vec<u32> = (0..positions.len() as u32).collect();
+    let mut heap = BinaryHeap::new();
+    let collapse = |from: u32, to: u32, quadrics: &[Quadric], version: &[u32]| Collapse {
+        cost: quadrics[from as usize]
+            .sum(&quadrics[to as usize])
+            .error(positions[to as usize]),
+        from,
+        to,
+        versions: (version[from as usize], version[to as usize]),
+    };
+    for &(a, b) in edges.keys() {
+        if !locked[a as usize] {
+            heap.push(collapse(a, b, &quadrics, &version));
+        }
+        if !locked[b as usize] {
+            heap.push(collapse(b, a, &quadrics, &version));
+        }
+    }
+
+    while alive_count > target_triangles {
+        let Some(candidate) = heap.pop() else {
+            break;
+        };
+        let (from, to) = (candidate.from as usize, candidate.to as usize);
+        if remap[from] != from as u32 || remap[to] != to as u32 {
+            continue;
+        }
+        if candidate.versions != (version[from], version[to]) {
+            heap.push(collapse(candidate.from, candidate.to, &quadrics, &version));
+            continue;
+        }
+
+        // Reject collapses that would fold a triangle over
+        let flips = adjacency[from].iter().any(|&t| {
+            if !alive[t] || triangles[t].contains(&(to as u32)) {
+                return false;
+            }
+            let [p0, p1, p2] = triangles[t].map(|v| positions[v as usize]);
+            let before = (p1 - p0).cross(p2 - p0);
+            let [q0, q1, q2] =
+                triangles[t].map(|v| positions[if v as usize == from { to } else { v as usize }]);
+            let after = (q1 - q0).cross(q2 - q0);
+            before.dot(after) <= 0.0
+        });
+        if flips {
+            continue;
+        }
+
+        remap[from] = to as u32;
+        let merged = quadrics[from].sum(&quadrics[to]);
+        quadrics[to] = merged;
+        version[to] += 1;
+
+        let moved = std::mem::take(&mut adjacency[from]);
+        for &t in &moved {
+            if !alive[t] {
+                continue;
+            }
+            for v in &mut triangles[t] {
+                if *v as usize == from {
+                    *v = to as u32;
+                }
+            }
+            let [a, b, c] = triangles[t];
+            if a == b || b == c || c == a {
+                alive[t] = false;
+                alive_count -= 1;
+            } else {
+                adjacency[to].push(t);
+            }
+        }
+        for &t in &adjacency[to] {
+            if !alive[t] {
+                continue;
+            }
+            for &v in &triangles[t] {
+                if v as usize == to {
+                    continue;
+                }
+                if !locked[to] {
+                    heap.push(collapse(to as u32, v, &quadrics, &version));
+                }
+                if !locked[v as usize] {
+                    heap.push(collapse(v, to as u32, &quadrics, &version));
+                }
+            }
+        }
+    }
+
+    triangles
+        .iter()
+        .zip(&alive)
+        .filter(|(_, &alive)| alive)
+        .flat_map(|(tri, _)| *tri)
+        .collect()
+}
+
+/// Symmetric 4x4 error quadric, stored as its upper triangle.
+#[derive(Clone, Copy, Debug, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn plane(normal: Vec3, point: Vec3, weight: f64) -> Self {
+        let n = normal.as_dvec3();
+        let p = DVec4::new(n.x, n.y, n.z, -n.dot(point.as_dvec3()));
+        Self(
+            [
+                p.x * p.x,
+                p.x * p.y,
+                p.x * p.z,
+                p.x * p.w,
+                p.y * p.y,
+                p.y * p.z,
+                p.y * p.w,
+                p.z * p.z,
+                p.z * p.w,
+                p.w * p.w,
+            ]
+            .map(|v| v * weight),
+        )
+    }
+
+    fn add(&mut self, other: &Self) {
+        for (a, b) in self.0.iter_mut().zip(other.0) {
+            *a += b;
+        }
+    }
+
+    fn sum(&self, other: &Self) -> Self {
+        let mut q = *self;
+        q.add(other);
+        q
+    }
+
+    fn error(&self, p: Vec3) -> f64 {
+        let [xx, xy, xz, xw, yy, yz, yw, zz, zw, ww] = self.0;
+        let (x, y, z) = (p.x as f64, p.y as f64, p.z as f64);
+        x * x * xx
+            + 2.0 * x * y * xy
+            + 2.0 * x * z * xz
+            + 2.0 * x * xw
+            + y * y * yy
+            + 2.0 * y * z * yz
+            + 2.0 * y * yw
+            + z * z * zz
+            + 2.0 * z * zw
+            + ww
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    // Reversed so the binary heap pops the cheapest collapse first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
@@ -0,0 +1,304 @@
+//! Display transforms read from .cube LUTs, such as a display and view
+//! `ociobakelut --format resolve_cube` baked from an OpenColorIO config,
+//! with a shaper for scene-linear input, or baked from a config by `ocio`.
+//! The window and thumbnails show the image through it, while recorded
+//! frames stay scene-linear unless `ocio` converts them.
+
+use std::{fmt::Write, path::Path};
+
+use anyhow::{bail, Context, Result};
+use glam::{UVec3, Vec3};
+use wgpu::util::DeviceExt;
+
+use crate::{texture::encode_srgb, tonemap::Tonemap};
+
+/// Per channel curves applied before the cube.
+#[derive(Clone, Debug)]
+pub struct Lut1d {
+    pub domain_min: Vec3,
+    pub domain_max: Vec3,
+    pub values: Vec<Vec3>,
+}
+
+/// Colors on a grid with red changing fastest, as the file lists them.
+#[derive(Clone, Debug)]
+pub struct Lut3d {
+    pub size: u32,
+    pub domain_min: Vec3,
+    pub domain_max: Vec3,
+    pub values: Vec<Vec3>,
+}
+
+/// Maps linear light to display values, sRGB encoded for a typical
+/// display.
+#[derive(Clone, Debug)]
+pub struct DisplayLut {
+    pub shaper: Lut1d,
+    pub cube: Lut3d,
+}
+
+impl DisplayLut {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read LUT {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Failed to parse LUT {}", path.display()))
+    }
+
+    /// Reads the Resolve flavor of the format, which also covers Adobe's.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut size_1d = 0;
+        let mut size_3d = 0;
+        let mut domain = (Vec3::ZERO, Vec3::ONE);
+        let mut range_1d = None;
+        let mut range_3d = None;
+        let mut values = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let mut fields = line.split_whitespace();
+            let Some(first) = fields.clone().next() else {
+                continue;
+            };
+            let keyword = first.starts_with(char::is_alphabetic).then(|| {
+                fields.next();
+                first
+            });
+            let numbers = || -> Result<Vec<f32>> {
+                let numbers = fields.clone().map(str::parse).collect::<Result<_, _>>();
+                numbers.with_context(|| format!("line {}: Invalid number", number + 1))
+            };
+            let vec3 = || -> Result<Vec3> {
+                match numbers()?[..] {
+                    [r, g, b] => Ok(Vec3::new(r, g, b)),
+                    _ => bail!("line {}: Expected three numbers", number + 1),
+                }
+            };
+            let range = || -> Result<(Vec3, Vec3)> {
+                match numbers()?[..] {
+                    [min, max] => Ok((Vec3::splat(min), Vec3::splat(max))),
+                    _ => bail!("line {}: Expected a minimum and a maximum", number + 1),
+                }
+            };
+            let size = || -> Result<usize> {
+                fields
+                    .clone()
+                    .next()
+                    .and_then(|size| size.parse().ok())
+                    .filter(|&size| size >= 2)
+                    .with_context(|| format!("line {}: Invalid size", number + 1))
+            };
+            match keyword {
+                Some("TITLE") => {}
+                Some("LUT_1D_SIZE") => size_1d = size()?,
+                Some("LUT_3D_SIZE") => size_3d = size()?,
+                Some("DOMAIN_MIN") => domain.0 = vec3()?,
+                Some("DOMAIN_MAX") => domain.1 = vec3()?,
+                Some("LUT_1D_INPUT_RANGE") => range_1d = Some(range()?),
+                Some("LUT_3D_INPUT_RANGE") => range_3d = Some(range()?),
+                Some(keyword) => tracing::warn!("Ignoring LUT keyword {keyword}"),
+                None => values.push(vec3()?),
+            }
+        }
+
+        if size_1d == 0 && size_3d == 0 {
+            bail!("No LUT_1D_SIZE or LUT_3D_SIZE");
+        }
+        if values.len() != size_1d + size_3d.pow(3) {
+            bail!(
+                "Expected {} entries, found {}",
+                size_1d + size_3d.pow(3),
+                values.len()
+            );
+        }
+        // The domain belongs to the only table, otherwise the ranges say
+        // which is which
+        let shaper_domain = range_1d.unwrap_or(domain);
+        let cube_domain = range_3d.unwrap_or(if size_1d == 0 {
+            domain
+        } else {
+            (Vec3::ZERO, Vec3::ONE)
+        });
+        let cube_values = values.split_off(size_1d);
+        // A missing table is replaced by one that changes nothing in the
+        // other's domain
+        let shaper = if size_1d == 0 {
+            Lut1d {
+                domain_min: cube_domain.0,
+                domain_max: cube_domain.1,
+                values: vec![cube_domain.0, cube_domain.1],
+            }
+        } else {
+            Lut1d {
+                domain_min: shaper_domain.0,
+                domain_max: shaper_domain.1,
+                values,
+            }
+        };
+        let cube = if size_3d == 0 {
+            Lut3d {
+                size: 2,
+                domain_min: cube_domain.0,
+                domain_max: cube_domain.1,
+                values: (0..8)
+                    .map(|i| UVec3::new(i & 1, (i >> 1) & 1, i >> 2).as_vec3())
+                    .collect(),
+            }
+        } else {
+            Lut3d {
+                size: size_3d as u32,
+                domain_min: cube_domain.0,
+                domain_max: cube_domain.1,
+                values: cube_values,
+            }
+        };
+        Ok(Self { shaper, cube })
+    }
+
+    /// Writes the Resolve flavor `parse` reads, with the shaper and the
+    /// cube. Their domains are taken to be the same in every channel.
+    pub fn to_cube(&self, title: &str) -> String {
+        let mut text = format!("TITLE \"{title}\"\n");
+        let _ = writeln!(text, "LUT_1D_SIZE {}", self.shaper.values.len());
+        let _ = writeln!(
+            text,
+            "LUT_1D_INPUT_RANGE {} {}",
+            self.shaper.domain_min.x, self.shaper.domain_max.x
+        );
+        let _ = writeln!(text, "LUT_3D_SIZE {}", self.cube.size);
+        let _ = writeln!(
+            text,
+            "LUT_3D_INPUT_RANGE {} {}",
+            self.cube.domain_min.x, self.cube.domain_max.x
+        );
+        for value in self.shaper.values.iter().chain(&self.cube.values) {
+            let _ = writeln!(text, "{:.6} {:.6} {:.6}", value.x, value.y, value.z);
+        }
+        text
+    }
+
+    /// Display value of a linear color, as the render shader computes it.
+    pub fn apply(&self, color: Vec3) -> Vec3 {
+        self.cube.apply(self.shaper.apply(color))
+    }
+}
+
+impl Lut1d {
+    pub fn apply(&self, color: Vec3) -> Vec3 {
+        let last = (self.values.len() - 1) as f32;
+        let t = ((color - self.domain_min) / (self.domain_max - self.domain_min))
+            .clamp(Vec3::ZERO, Vec3::ONE)
+            * last;
+        let channel = |i: usize| {
+            let lower = (t[i].floor() as usize).min(self.values.len() - 2);
+            let fraction = t[i] - lower as f32;
+            self.values[lower][i] * (1.0 - fraction) + self.values[lower + 1][i] * fraction
+        };
+        Vec3::new(channel(0), channel(1), channel(2))
+    }
+}
+
+impl Lut3d {
+    /// Interpolates the eight surrounding entries.
+    pub fn apply(&self, color: Vec3) -> Vec3 {
+        let last = (self.size - 1) as f32;
+        let t = ((color - self.domain_min) / (self.domain_max - self.domain_min))
+            .clamp(Vec3::ZERO, Vec3::ONE)
+            * last;
+        let lower = t.floor().min(Vec3::splat(last - 1.0));
+        let fraction = t - lower;
+        let lower = lower.as_uvec3();
+        let mut result = Vec3::ZERO;
+        for corner in 0..8 {
+            let offset = UVec3::new(corner & 1, (corner >> 1) & 1, corner >> 2);
+            let weight = Vec3::select(offset.cmpeq(UVec3::ONE), fraction, Vec3::ONE - fraction);
+            let p = lower + offset;
+            let index = p.x + self.size * (p.y + self.size * p.z);
+            result += self.values[index as usize] * weight.x * weight.y * weight.z;
+        }
+        result
+    }
+}
+
+/// 8-bit display values of a linear image with premultiplied alpha, after
+/// scaling it by `scale`, through `lut` or else `tonemap`. Alpha comes out
+/// straight, as PNG has it.
+pub fn display_image(
+    image: &image::Rgba32FImage,
+    scale: f32,
+    tonemap: Tonemap,
+    lut: Option<&DisplayLut>,
+) -> image::RgbaImage {
+    image::RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let unpremultiply = if a > 0.0 { a.recip() } else { 0.0 };
+        let color = Vec3::new(r, g, b) * unpremultiply * scale;
+        let [r, g, b] = match lut {
+            Some(lut) => lut
+                .apply(color)
+                .to_array()
+                .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8),
+            None => tonemap.apply(color).to_array().map(encode_srgb),
+        };
+        image::Rgba([r, g, b, (a.clamp(0.0, 1.0) * 255.0).round() as u8])
+    })
+}
+
+/// Shaper and cube textures for the render shader, ones that aren't read
+/// without a LUT.
+pub fn upload(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    lut: Option<&DisplayLut>,
+) -> [wgpu::TextureView; 2] {
+    let texture = |label, size: wgpu::Extent3d, dimension, values: &[Vec3]| {
+        let texels: Vec<[f32; 4]> = values.iter().map(|v| v.extend(1.0).to_array()).collect();
+        device
+            .create_texture_with_data(
+                queue,
+                &wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension,
+                    format: wgpu::TextureFormat::Rgba32Float,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                },
+                wgpu::util::TextureDataOrder::LayerMajor,
+                bytemuck::cast_slice(&texels),
+            )
+            .create_view(&Default::default())
+    };
+    let unused = [Vec3::ZERO];
+    let (shaper, cube, size) = match lut {
+        Some(lut) => (
+            lut.shaper.values.as_slice(),
+            lut.cube.values.as_slice(),
+            lut.cube.size,
+        ),
+        None => (&unused[..], &unused[..], 1),
+    };
+    [
+        // A row of a 2D texture, since WebGL has no 1D ones
+        texture(
+            "Display Shaper",
+            wgpu::Extent3d {
+                width: shaper.len() as u32,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            wgpu::TextureDimension::D2,
+            shaper,
+        ),
+        texture(
+            "Display Cube",
+            wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: size,
+            },
+            wgpu::TextureDimension::D3,
+            cube,
+        ),
+    ]
+}
@@ -0,0 +1,379 @@
+//! Reads NanoVDB grids, the flattened form of OpenVDB that the GPU walks
+//! as is. The tree is kept in the file's own layout, a root table of tiles
+//! over 32³ upper, 16³ lower and 8³ leaf nodes, so it uploads to a storage
+//! buffer unchanged and volume.wgsl finds voxels in it the way the NanoVDB
+//! headers do. Only uncompressed float grids of format version 32 are read,
+//! which covers the smoke and cloud densities the tracer renders.
+
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+use glam::{IVec3, Mat3, Mat4, Vec3};
+
+use crate::bvh::Aabb;
+
+/// "NanoVDB0", which older files start both the file and grids with.
+const MAGIC_NUMBER: u64 = 0x3042_4456_6f6e_614e;
+/// "NanoVDB1" at the start of a grid.
+const MAGIC_GRID: u64 = 0x3142_4456_6f6e_614e;
+/// "NanoVDB2" at the start of a file.
+const MAGIC_FILE: u64 = 0x3242_4456_6f6e_614e;
+const MAJOR_VERSION: u32 = 32;
+const GRID_TYPE_FLOAT: u32 = 1;
+const GRID_CLASS_LEVEL_SET: u32 = 1;
+
+const FILE_HEADER_SIZE: usize = 16;
+const FILE_METADATA_SIZE: usize = 176;
+const FILE_METADATA_OFF_NAME_SIZE: usize = 136;
+
+// Byte offsets into the structs of NanoVDB.h, as in PNanoVDB.h
+const GRID_SIZE: usize = 672;
+const GRID_OFF_GRID_SIZE: usize = 32;
+const GRID_OFF_MAP: usize = 296;
+const GRID_OFF_GRID_CLASS: usize = 632;
+const GRID_OFF_GRID_TYPE: usize = 636;
+const MAP_OFF_MATD: usize = 88;
+const MAP_OFF_VECD: usize = 232;
+const TREE_OFF_NODE_OFFSET: usize = 0;
+const TREE_OFF_NODE_COUNT: usize = 32;
+const ROOT_OFF_BBOX: usize = 0;
+const ROOT_OFF_TABLE_SIZE: usize = 24;
+const ROOT_OFF_BACKGROUND: usize = 28;
+const ROOT_SIZE: usize = 64;
+const ROOT_TILE_SIZE: usize = 32;
+const ROOT_TILE_OFF_KEY: usize = 0;
+const ROOT_TILE_OFF_CHILD: usize = 8;
+const ROOT_TILE_OFF_VALUE: usize = 20;
+const LEAF_OFF_TABLE: usize = 96;
+const LEAF_SIZE: usize = LEAF_OFF_TABLE + 4 * 512;
+
+/// Upper or lower node, which only differ in size.
+struct Internal {
+    /// Voxels along each side are 1 << `log2_dim` children.
+    log2_dim: u32,
+    /// Bits of the coordinates the node's children cover.
+    child_total: u32,
+    off_child_mask: usize,
+    off_table: usize,
+}
+
+const UPPER: Internal = Internal {
+    log2_dim: 5,
+    child_total: 7,
+    off_child_mask: 32 + 4096,
+    off_table: 8256,
+};
+const LOWER: Internal = Internal {
+    log2_dim: 4,
+    child_total: 3,
+    off_child_mask: 32 + 512,
+    off_table: 1088,
+};
+
+impl Internal {
+    fn size(&self) -> usize {
+        self.off_table + (8 << (3 * self.log2_dim))
+    }
+
+    /// Index of the child or tile holding `ijk`.
+    fn child_index(&self, ijk: IVec3) -> usize {
+        let dim = 1 << self.log2_dim;
+        let i = (ijk >> self.child_total) & (dim - 1);
+        ((i.x << (2 * self.log2_dim)) | (i.y << self.log2_dim) | i.z) as usize
+    }
+}
+
+/// A float grid along with what the tracer needs to place it.
+#[derive(Debug)]
+pub struct Grid {
+    pub name: String,
+    /// The grid as NanoVDB lays it out in memory, from its GridData on.
+    words: Vec<u32>,
+    /// Index space to the grid's world space, with voxel centers at whole
+    /// index coordinates.
+    pub index_to_world: Mat4,
+    /// First and last voxel of the active bounding box.
+    pub index_min: IVec3,
+    pub index_max: IVec3,
+    /// Largest value in the grid, including tiles and the background.
+    pub maximum: f32,
+}
+
+/// Reads every grid of a `.nvdb` file.
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<Grid>> {
+    let path = path.as_ref();
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    parse(&bytes).with_context(|| format!("Failed to load {}", path.display()))
+}
+
+/// Grids of a NanoVDB file, or of a bare grid buffer as NanoVDB keeps it in
+/// memory.
+pub fn parse(bytes: &[u8]) -> Result<Vec<Grid>> {
+    let magic = read_u64(bytes, 0)?;
+    if magic == MAGIC_GRID {
+        return Ok(vec![Grid::new(String::new(), bytes)?]);
+    }
+    ensure!(
+        magic == MAGIC_FILE || magic == MAGIC_NUMBER,
+        "Not a NanoVDB file"
+    );
+    let grid_count = read_u16(bytes, 12)?;
+    let codec = read_u16(bytes, 14)?;
+    if codec != 0 {
+        bail!("The grids are compressed, which isn't supported, save them uncompressed");
+    }
+    let mut grids = Vec::with_capacity(grid_count.into());
+    let mut offset = FILE_HEADER_SIZE;
+    for _ in 0..grid_count {
+        let grid_size = read_u64(bytes, offset)? as usize;
+        let name_size = read_u32(bytes, offset + FILE_METADATA_OFF_NAME_SIZE)? as usize;
+        offset += FILE_METADATA_SIZE;
+        let name = bytes
+            .get(offset..offset + name_size)
+            .context("File ends in a grid name")?;
+        let name = String::from_utf8_lossy(name)
+            .trim_end_matches('\0')
+            .to_owned();
+        offset += name_size;
+        let grid = bytes
+            .get(offset..offset + grid_size)
+            .with_context(|| format!("File ends in grid {name:?}"))?;
+        offset += grid_size;
+        match Grid::new(name, grid) {
+            Ok(grid) => grids.push(grid),
+            Err(err) => tracing::warn!("Skipped a grid: {err:#}"),
+        }
+    }
+    ensure!(!grids.is_empty(), "No float grids to render");
+    Ok(grids)
+}
+
+impl Grid {
+    fn new(name: String, bytes: &[u8]) -> Result<Self> {
+        let magic = read_u64(bytes, 0)?;
+        ensure!(
+            magic == MAGIC_GRID || magic == MAGIC_NUMBER,
+            "Grid {name:?} is corrupt"
+        );
+        let version = read_u32(bytes, 16)?;
+        ensure!(
+            version >> 21 == MAJOR_VERSION,
+            "Grid {name:?} is of NanoVDB {}.{}, only {MAJOR_VERSION}.x is supported",
+            version >> 21,
+            (version >> 10) & 0x7ff
+        );
+        let grid_type = read_u32(bytes, GRID_OFF_GRID_TYPE)?;
+        ensure!(
+            grid_type == GRID_TYPE_FLOAT,
+            "Grid {name:?} isn't made of floats"
+        );
+        ensure!(
+            read_u32(bytes, GRID_OFF_GRID_CLASS)? != GRID_CLASS_LEVEL_SET,
+            "Grid {name:?} is a level set rather than a density"
+        );
+        let size = read_u64(bytes, GRID_OFF_GRID_SIZE)? as usize;
+        ensure!(
+            size <= bytes.len() && size.is_multiple_of(4),
+            "Grid {name:?} is cut short"
+        );
+        let words = bytes[..size]
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+
+        let map = GRID_OFF_MAP;
+        let mut matrix = [0.0; 9];
+        for (i, value) in matrix.iter_mut().enumerate() {
+            *value = read_f64(bytes, map + MAP_OFF_MATD + 8 * i)? as f32;
+        }
+        let mut translation = [0.0; 3];
+        for (i, value) in translation.iter_mut().enumerate() {
+            *value = read_f64(bytes, map + MAP_OFF_VECD + 8 * i)? as f32;
+        }
+        // The map's matrix is stored by rows
+        let index_to_world = Mat4::from_translation(Vec3::from(translation))
+            * Mat4::from_mat3(Mat3::from_cols_array(&matrix).transpose());
+
+        let mut grid = Self {
+            name,
+            words,
+            index_to_world,
+            index_min: IVec3::ZERO,
+            index_max: IVec3::ZERO,
+            maximum: 0.0,
+        };
+        let root = grid.root();
+        grid.index_min = grid.ivec3(root + ROOT_OFF_BBOX)?;
+        grid.index_max = grid.ivec3(root + ROOT_OFF_BBOX + 12)?;
+        ensure!(
+            grid.index_min.cmple(grid.index_max).all(),
+            "Grid {:?} has no active voxels",
+            grid.name
+        );
+        grid.maximum = grid.find_maximum()?;
+        Ok(grid)
+    }
+
+    /// The grid as 32 bit words, to upload.
+    pub fn words(&self) -> &[u32] {
+        &self.words
+    }
+
+    /// Index space bounds of everything the trilinear lookup can see.
+    pub fn index_bounds(&self) -> Aabb {
+        Aabb {
+            min: (self.index_min - 1).as_vec3(),
+            max: (self.index_max + 1).as_vec3(),
+        }
+    }
+
+    /// Value of the voxel at `ijk`, found the way NanoVDB's accessor does
+    /// without caching.
+    pub fn value(&self, ijk: IVec3) -> f32 {
+        let root = self.root();
+        let tile_count = self.word(root + ROOT_OFF_TABLE_SIZE);
+        let key = root_key(ijk);
+        let tile = (0..tile_count as usize)
+            .map(|i| root + ROOT_SIZE + i * ROOT_TILE_SIZE)
+            .find(|&tile| [self.word(tile + ROOT_TILE_OFF_KEY), self.word(tile + 4)] == key);
+        let Some(tile) = tile else {
+            return f32::from_bits(self.word(root + ROOT_OFF_BACKGROUND));
+        };
+        let child = self.word(tile + ROOT_TILE_OFF_CHILD) as usize;
+        if child == 0 {
+            return f32::from_bits(self.word(tile + ROOT_TILE_OFF_VALUE));
+        }
+        let mut node = root + child;
+        for internal in [&UPPER, &LOWER] {
+            let n = internal.child_index(ijk);
+            let entry = node + internal.off_table + 8 * n;
+            let mask = self.word(node + internal.off_child_mask + 4 * (n / 32));
+            if mask & (1 << (n % 32)) == 0 {
+                return f32::from_bits(self.word(entry));
+            }
+            node += self.word(entry) as usize;
+        }
+        let i = ijk & 7;
+        let n = ((i.x << 6) | (i.y << 3) | i.z) as usize;
+        f32::from_bits(self.word(node + LEAF_OFF_TABLE + 4 * n))
+    }
+
+    /// Trilinear interpolation at `p` in index space, as volume.wgsl does.
+    pub fn sample(&self, p: Vec3) -> f32 {
+        let base = p.floor();
+        let f = p - base;
+        let base = base.as_ivec3();
+        let mut value = 0.0;
+        for corner in 0..8 {
+            let offset = IVec3::new(corner & 1, (corner >> 1) & 1, corner >> 2);
+            let weight = Vec3::select(offset.cmpeq(IVec3::ONE), f, 1.0 - f);
+            value += weight.x * weight.y * weight.z * self.value(base + offset);
+        }
+        value
+    }
+
+    fn root(&self) -> usize {
+        let tree = GRID_SIZE;
+        tree + self.word(tree + TREE_OFF_NODE_OFFSET + 24) as usize
+    }
+
+    /// The largest leaf value, tile value or background, for the majorant
+    /// of delta tracking.
+    fn find_maximum(&self) -> Result<f32> {
+        let tree = GRID_SIZE;
+        let root = self.root();
+        let len = 4 * self.words.len();
+        let mut maximum = f32::from_bits(self.word(root + ROOT_OFF_BACKGROUND));
+        let tile_count = self.word(root + ROOT_OFF_TABLE_SIZE) as usize;
+        ensure!(
+            root + ROOT_SIZE + tile_count * ROOT_TILE_SIZE <= len,
+            "Grid {:?} is corrupt",
+            self.name
+        );
+        for i in 0..tile_count {
+            let tile = root + ROOT_SIZE + i * ROOT_TILE_SIZE;
+            if self.word(tile + ROOT_TILE_OFF_CHILD) == 0 {
+                maximum = maximum.max(f32::from_bits(self.word(tile + ROOT_TILE_OFF_VALUE)));
+            }
+        }
+        // Nodes of each level follow one another, leaves first
+        for (level, internal) in [(2, &UPPER), (1, &LOWER)] {
+            let first = tree + self.word(tree + TREE_OFF_NODE_OFFSET + 8 * level) as usize;
+            let count = self.word(tree + TREE_OFF_NODE_COUNT + 4 * level) as usize;
+            ensure!(
+                first + count * internal.size() <= len,
+                "Grid {:?} is corrupt",
+                self.name
+            );
+            for node in (0..count).map(|i| first + i * internal.size()) {
+                for n in 0..1 << (3 * internal.log2_dim) {
+                    let mask = self.word(node + internal.off_child_mask + 4 * (n / 32));
+                    if mask & (1 << (n % 32)) == 0 {
+                        let value = self.word(node + internal.off_table + 8 * n);
+                        maximum = maximum.max(f32::from_bits(value));
+                    }
+                }
+            }
+        }
+        let first = tree + self.word(tree + TREE_OFF_NODE_OFFSET) as usize;
+        let count = self.word(tree + TREE_OFF_NODE_COUNT) as usize;
+        ensure!(
+            first + count * LEAF_SIZE <= len,
+            "Grid {:?} is corrupt",
+            self.name
+        );
+        for leaf in (0..count).map(|i| first + i * LEAF_SIZE) {
+            for n in 0..512 {
+                maximum = maximum.max(f32::from_bits(self.word(leaf + LEAF_OFF_TABLE + 4 * n)));
+            }
+        }
+        Ok(maximum.max(0.0))
+    }
+
+    /// Word at the byte offset `offset`, zero past the end so a corrupt
+    /// grid can't panic a lookup.
+    fn word(&self, offset: usize) -> u32 {
+        self.words.get(offset / 4).copied().unwrap_or(0)
+    }
+
+    fn ivec3(&self, offset: usize) -> Result<IVec3> {
+        ensure!(
+            offset + 12 <= 4 * self.words.len(),
+            "Grid {:?} is corrupt",
+            self.name
+        );
+        Ok(IVec3::new(
+            self.word(offset) as i32,
+            self.word(offset + 4) as i32,
+            self.word(offset + 8) as i32,
+        ))
+    }
+}
+
+/// Low and high words of the 64 bit key of the root tile holding `ijk`,
+/// 21 bits for each coordinate's 4096 voxel block.
+fn root_key(ijk: IVec3) -> [u32; 2] {
+    let [x, y, z] = ijk.to_array().map(|c| (c as u32 >> 12) as u64);
+    let key = z | (y << 21) | (x << 42);
+    [key as u32, (key >> 32) as u32]
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16> {
+    let bytes = bytes.get(offset..offset + 2).context("File is cut short")?;
+    Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    let bytes = bytes.get(offset..offset + 4).context("File is cut short")?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64> {
+    let bytes = bytes.get(offset..offset + 8).context("File is cut short")?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_f64(bytes: &[u8], offset: usize) -> Result<f64> {
+    Ok(f64::from_bits(read_u64(bytes, offset)?))
+}
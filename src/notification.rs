@@ -0,0 +1,170 @@
+//! Word that a render, animation or dataset has finished, or failed, for
+//! long renders nobody is watching. Any of a shell command, a webhook and a
+//! desktop notification can be sent, each with the stats of the render:
+//!
+//! - The command runs through the shell with `SPECTRUM_STATUS` (`done` or
+//!   `failed`), `SPECTRUM_OUTPUT`, `SPECTRUM_WIDTH`, `SPECTRUM_HEIGHT`,
+//!   `SPECTRUM_SAMPLES`, `SPECTRUM_FRAMES`, `SPECTRUM_SECONDS` and, on
+//!   failure, `SPECTRUM_ERROR` set.
+//! - The webhook gets the same as a JSON object.
+//! - The desktop notification goes through `notify-send` on Linux and
+//!   `osascript` on macOS.
+//!
+//! A notification that can't be sent is only logged, the render is done
+//! either way.
+
+use std::{fmt::Write, path::PathBuf, process::Command, time::Duration};
+
+use anyhow::{bail, Context, Result};
+
+use crate::webhook;
+
+#[derive(Clone, Debug, Default)]
+pub struct Notifier {
+    /// Shell command run when done.
+    pub command: Option<String>,
+    /// `http://` URL the stats are POSTed to.
+    pub webhook: Option<String>,
+    pub desktop: bool,
+}
+
+/// What finished and how long it took.
+#[derive(Clone, Debug)]
+pub struct Summary {
+    /// The image, or the directory of a dataset.
+    pub output: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    /// Samples per pixel.
+    pub samples: u32,
+    /// Images rendered, one for a render.
+    pub frames: u32,
+    pub elapsed: Duration,
+    /// Why it failed.
+    pub error: Option<String>,
+}
+
+impl Summary {
+    fn status(&self) -> &'static str {
+        if self.error.is_some() {
+            "failed"
+        } else {
+            "done"
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let mut json = format!(
+            "{{\"status\":\"{}\",\"output\":\"{}\",\"width\":{},\"height\":{},\
+             \"samples\":{},\"frames\":{},\"seconds\":{:.3}",
+            self.status(),
+            webhook::escape(&self.output.to_string_lossy()),
+            self.width,
+            self.height,
+            self.samples,
+            self.frames,
+            self.elapsed.as_secs_f64(),
+        );
+        if let Some(error) = &self.error {
+            let _ = write!(json, ",\"error\":\"{}\"", webhook::escape(error));
+        }
+        json.push('}');
+        json
+    }
+
+    /// One line for a person to read.
+    fn message(&self) -> String {
+        let output = self.output.display();
+        match &self.error {
+            Some(error) => format!("{output} failed: {error}"),
+            None => format!(
+                "{output} done: {} x {}, {} spp, {} frame(s) in {:.1}s",
+                self.width,
+                self.height,
+                self.samples,
+                self.frames,
+                self.elapsed.as_secs_f64()
+            ),
+        }
+    }
+}
+
+impl Notifier {
+    /// Sends everything asked for, logging whatever fails.
+    pub fn send(&self, summary: &Summary) {
+        if let Some(command) = &self.command {
+            if let Err(err) = run_command(command, summary) {
+                tracing::warn!("Completion command failed: {err:#}");
+            }
+        }
+        if let Some(url) = &self.webhook {
+            let json = summary.to_json();
+            if let Err(err) = webhook::post(url, "application/json", &[], json.as_bytes()) {
+                tracing::warn!("Completion webhook failed: {err:#}");
+            }
+        }
+        if self.desktop {
+            if let Err(err) = notify_desktop(&summary.message()) {
+                tracing::warn!("Desktop notification failed: {err:#}");
+            }
+        }
+    }
+}
+
+fn run_command(command: &str, summary: &Summary) -> Result<()> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell
+        .arg(command)
+        .env("SPECTRUM_STATUS", summary.status())
+        .env("SPECTRUM_OUTPUT", &summary.output)
+        .env("SPECTRUM_WIDTH", summary.width.to_string())
+        .env("SPECTRUM_HEIGHT", summary.height.to_string())
+        .env("SPECTRUM_SAMPLES", summary.samples.to_string())
+        .env("SPECTRUM_FRAMES", summary.frames.to_string())
+        .env(
+            "SPECTRUM_SECONDS",
+            format!("{:.3}", summary.elapsed.as_secs_f64()),
+        );
+    if let Some(error) = &summary.error {
+        shell.env("SPECTRUM_ERROR", error);
+    }
+    let status = shell
+        .status()
+        .with_context(|| format!("Failed to run {command}"))?;
+    if !status.success() {
+        bail!("{command} exited with {status}");
+    }
+    Ok(())
+}
+
+fn notify_desktop(message: &str) -> Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(format!(
+            "display notification \"{}\" with title \"Spectrum\"",
+            webhook::escape(message)
+        ));
+        command
+    } else if cfg!(target_os = "linux") {
+        let mut command = Command::new("notify-send");
+        command.arg("Spectrum").arg(message);
+        command
+    } else {
+        bail!("Desktop notifications aren't supported on this platform");
+    };
+    let status = command
+        .status()
+        .context("Failed to show a desktop notification")?;
+    if !status.success() {
+        bail!("The notification exited with {status}");
+    }
+    Ok(())
+}
@@ -0,0 +1,384 @@
+//! FFT ocean after Tessendorf 2001, "Simulating Ocean Water". Wave
+//! amplitudes drawn from a Phillips spectrum are advanced in time and
+//! transformed into heights and horizontal displacements on the GPU, which
+//! then moves the triangles of the water mesh in place.
+
+use std::f32::consts::PI;
+
+use glam::{Vec2, Vec3};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use wgpu::util::DeviceExt;
+
+use crate::{scene::Mesh, shader};
+
+const GRAVITY: f32 = 9.81;
+/// Phillips constant giving a significant wave height of about 0.21 V² / g,
+/// as observed for fully developed seas.
+const PHILLIPS_CONSTANT: f32 = 1.755e-3;
+const WORKGROUP_SIZE: u32 = 64;
+
+#[derive(Clone, Debug)]
+pub struct Ocean {
+    /// Side length of the square patch in world units.
+    pub size: f32,
+    /// Grid cells along each side, a power of two.
+    pub resolution: u32,
+    /// Wind velocity over the XZ plane in m/s.
+    pub wind: Vec2,
+    /// Scales the wave heights.
+    pub amplitude: f32,
+    /// Pulls vertices towards the crests, 0 gives rounded waves.
+    pub choppiness: f32,
+    /// Height of the still water surface.
+    pub level: f32,
+    pub seed: u64,
+}
+
+impl Default for Ocean {
+    fn default() -> Self {
+        Self {
+            size: 64.0,
+            resolution: 64,
+            wind: Vec2::new(8.0, 3.0),
+            amplitude: 1.0,
+            choppiness: 1.0,
+            level: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+impl Ocean {
+    /// Flat grid of the patch, centered on the origin.
+    pub fn mesh(&self) -> Mesh {
+        let n = self.resolution;
+        let cell = self.size / n as f32;
+        let mut mesh = Mesh {
+            name: String::from("ocean"),
+            ..Default::default()
+        };
+        for z in 0..=n {
+            for x in 0..=n {
+                let p = Vec2::new(x as f32, z as f32) * cell - 0.5 * self.size;
+                mesh.positions.push(Vec3::new(p.x, self.level, p.y));
+                mesh.normals.push(Vec3::Y);
+                mesh.uvs.push(Vec2::new(x as f32, z as f32) / n as f32);
+            }
+        }
+        for z in 0..n {
+            for x in 0..n {
+                let v = z * (n + 1) + x;
+                let below = v + n + 1;
+                mesh.indices
+                    .extend_from_slice(&[v, below, v + 1, v + 1, below, below + 1]);
+            }
+        }
+        mesh
+    }
+
+    /// Initial amplitudes of every wave vector, h0(k) and conj(h0(-k)).
+    fn waves(&self) -> Vec<GpuWave> {
+        let n = self.resolution as usize;
+        let dk = 2.0 * PI / self.size;
+        let speed = self.wind.length();
+        let wind_dir = self.wind.normalize_or_zero();
+        // Largest wave arising from a continuous wind
+        let largest = speed * speed / GRAVITY;
+        // Suppresses waves much shorter than the largest ones
+        let smallest = largest / 1000.0;
+
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut gaussian = || {
+            // Box-Muller
+            let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+            let u2: f32 = rng.gen();
+            let r = (-2.0 * u1.ln()).sqrt();
+            Vec2::new(r * (2.0 * PI * u2).cos(), r * (2.0 * PI * u2).sin())
+        };
+        let wave_vector = |x: usize, z: usize| {
+            Vec2::new(x as f32 - (n / 2) as f32, z as f32 - (n / 2) as f32) * dk
+        };
+        let mut h0 = Vec::with_capacity(n * n);
+        for z in 0..n {
+            for x in 0..n {
+                let k = wave_vector(x, z);
+                let k2 = k.length_squared();
+                let phillips = if k2 == 0.0 || largest == 0.0 {
+                    0.0
+                } else {
+                    let alignment = k.dot(wind_dir) * k.dot(wind_dir) / k2;
+                    PHILLIPS_CONSTANT * self.amplitude * (-1.0 / (k2 * largest * largest)).exp()
+                        / (k2 * k2)
+                        * alignment
+                        * (-k2 * smallest * smallest).exp()
+                };
+                // Half the variance goes to h0(-k), so the field has P dk²
+                h0.push(gaussian() * (0.25 * phillips * dk * dk).sqrt());
+            }
+        }
+
+        let mut waves = Vec::with_capacity(n * n);
+        for z in 0..n {
+            for x in 0..n {
+                let k = wave_vector(x, z);
+                let minus = h0[((n - z) % n) * n + (n - x) % n];
+                waves.push(GpuWave {
+                    h0: h0[z * n + x].into(),
+                    h0_minus_conj: [minus.x, -minus.y],
+                    k: k.into(),
+                    // Deep water dispersion
+                    omega: (GRAVITY * k.length()).sqrt(),
+                    _pad: 0.0,
+                });
+            }
+        }
+        waves
+    }
+
+    /// Bounds how far any vertex can move from the flat grid, at any time.
+    pub fn max_displacement(&self) -> f32 {
+        let amplitude: f32 = self
+            .waves()
+            .iter()
+            .map(|wave| Vec2::from(wave.h0).length() + Vec2::from(wave.h0_minus_conj).length())
+            .sum();
+        amplitude * (1.0 + self.choppiness * self.choppiness).sqrt()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct GpuWave {
+    h0: [f32; 2],
+    h0_minus_conj: [f32; 2],
+    k: [f32; 2],
+    omega: f32,
+    _pad: f32,
+}
+
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct OceanParams {
+    resolution: u32,
+    size: f32,
+    choppiness: f32,
+    level: f32,
+    time: f32,
+    _pad: [f32; 3],
+}
+
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct FftStage {
+    stage: u32,
+    vertical: u32,
+    _pad: [u32; 2],
+}
+
+/// Compute passes animating an `Ocean` inside the path tracer's triangle
+/// buffer. The BVH is built around bounds padded by `max_displacement`, so
+/// it stays valid without a refit.
+pub struct OceanSimulation {
+    spectrum_pipeline: wgpu::ComputePipeline,
+    fft_pipeline: wgpu::ComputePipeline,
+    displace_pipeline: wgpu::ComputePipeline,
+    params_buffer: wgpu::Buffer,
+    spectrum_bind_group: wgpu::BindGroup,
+    fft_bind_groups: Vec<wgpu::BindGroup>,
+    displace_bind_group: wgpu::BindGroup,
+    ocean: Ocean,
+    triangle_count: u32,
+}
+
+impl OceanSimulation {
+    /// `triangles` holds, for every triangle of the ocean mesh, its index in
+    /// `triangle_buffer` followed by its three vertex indices.
+    pub fn new(
+        device: &wgpu::Device,
+        ocean: &Ocean,
+        triangle_buffer: &wgpu::Buffer,
+        triangles: &[[u32; 4]],
+    ) -> Self {
+        assert!(
+            ocean.resolution >= 4 && ocean.resolution.is_power_of_two(),
+            "Ocean resolution must be a power of two of at least 4"
+        );
+        let shader = shader::create_module(
+            device,
+            "Ocean Shader",
+            "ocean.wgsl",
+            &[("WORKGROUP_SIZE", WORKGROUP_SIZE.to_string())],
+        );
+        let pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: None,
+                module: &shader,
+                entry_point,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+        let spectrum_pipeline = pipeline("Ocean Spectrum Pipeline", "spectrum");
+        let fft_pipeline = pipeline("Ocean FFT Pipeline", "fft");
+        let displace_pipeline = pipeline("Ocean Displace Pipeline", "displace");
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Ocean Params"),
+            size: std::mem::size_of::<OceanParams>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let wave_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ocean Waves"),
+            contents: bytemuck::cast_slice(&ocean.waves()),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let ocean_triangle_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ocean Triangles"),
+            contents: bytemuck::cast_slice(triangles),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        // Height and the two horizontal displacements, as complex numbers
+        let n = ocean.resolution as u64;
+        let field_buffer = |label| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: 3 * n * n * 8,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        };
+        let fields = [field_buffer("Ocean Field A"), field_buffer("Ocean Field B")];
+
+        let spectrum_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Ocean Spectrum Bind Group"),
+            layout: &spectrum_pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wave_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: fields[0].as_entire_binding(),
+                },
+            ],
+        });
+
+        // Rows then columns, ping-ponging between the fields. There's an
+        // even number of stages, so the result ends up back in field A.
+        let stages = ocean.resolution.trailing_zeros();
+        let fft_layout = fft_pipeline.get_bind_group_layout(0);
+        let fft_bind_groups = (0..2 * stages)
+            .map(|i| {
+                let stage = FftStage {
+                    stage: i % stages,
+                    vertical: (i >= stages) as u32,
+                    _pad: [0; 2],
+                };
+                let stage_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Ocean FFT Stage"),
+                    contents: bytemuck::bytes_of(&stage),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Ocean FFT Bind Group"),
+                    layout: &fft_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: params_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: stage_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 4,
+                            resource: fields[(i % 2) as usize].as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 5,
+                            resource: fields[(1 - i % 2) as usize].as_entire_binding(),
+                        },
+                    ],
+                })
+            })
+            .collect();
+
+        let displace_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Ocean Displace Bind Group"),
+            layout: &displace_pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: fields[0].as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: ocean_triangle_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: triangle_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        Self {
+            spectrum_pipeline,
+            fft_pipeline,
+            displace_pipeline,
+            params_buffer,
+            spectrum_bind_group,
+            fft_bind_groups,
+            displace_bind_group,
+            ocean: ocean.clone(),
+            triangle_count: triangles.len() as u32,
+        }
+    }
+
+    /// Moves the water triangles to the surface at `time` seconds.
+    pub fn update(&self, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, time: f32) {
+        let ocean = &self.ocean;
+        let params = OceanParams {
+            resolution: ocean.resolution,
+            size: ocean.size,
+            choppiness: ocean.choppiness,
+            level: ocean.level,
+            time,
+            _pad: [0.0; 3],
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+
+        let n = ocean.resolution;
+        let workgroups = |threads: u32| threads.div_ceil(WORKGROUP_SIZE);
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Ocean Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.spectrum_pipeline);
+        pass.set_bind_group(0, &self.spectrum_bind_group, &[]);
+        pass.dispatch_workgroups(workgroups(n * n), 1, 1);
+
+        // One thread per butterfly, in every line of all three fields
+        pass.set_pipeline(&self.fft_pipeline);
+        for bind_group in &self.fft_bind_groups {
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(workgroups(n / 2 * n * 3), 1, 1);
+        }
+
+        pass.set_pipeline(&self.displace_pipeline);
+        pass.set_bind_group(0, &self.displace_bind_group, &[]);
+        pass.dispatch_workgroups(workgroups(self.triangle_count), 1, 1);
+    }
+}
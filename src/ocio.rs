@@ -0,0 +1,1681 @@
+//! OpenColorIO configs, read without the library: the displays, views,
+//! looks and color spaces of a v1 or v2 `config.ocio`, with the transforms
+//! between them evaluated on the CPU. A display and view is baked into a
+//! `DisplayLut` for the window and thumbnails, much as `ociobakelut` would,
+//! and saved .exr files can be converted into any of the color spaces.
+//!
+//! The YAML is read by a parser covering what configs use: block and flow
+//! collections, `!<Tag>`s, and quoted and block scalars. Builtin transforms,
+//! such as the ACES output transforms, and LUT formats other than .cube,
+//! .spi1d and .spi3d fail with an error. Views that need them can still be
+//! baked with `ociobakelut` and passed with `--display-lut`.
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use glam::{Mat3, Vec3};
+
+use crate::lut::{DisplayLut, Lut1d, Lut3d};
+
+/// Linear light the baked shaper covers, four stops above white.
+const SHAPER_MAX: f32 = 16.0;
+const SHAPER_SIZE: usize = 4096;
+/// Scale inside the shaper's logarithm, which keeps it close to linear
+/// over its first entry so interpolating it holds up in the shadows.
+const SHAPER_TOE: f32 = SHAPER_SIZE as f32 / SHAPER_MAX;
+const CUBE_SIZE: u32 = 65;
+
+/// Rec.709 luma weights the CDL saturation keeps.
+const LUMA: Vec3 = Vec3::new(0.2126, 0.7152, 0.0722);
+
+/// Placeholder a v2 view names its display's color space with.
+const USE_DISPLAY_NAME: &str = "<USE_DISPLAY_NAME>";
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Directories LUT files are looked up in, the config's own first.
+    search_path: Vec<PathBuf>,
+    /// Role and the color space it stands for.
+    roles: Vec<(String, String)>,
+    displays: Vec<Display>,
+    /// Displays and views offered, in order, every one without them.
+    active_displays: Vec<String>,
+    active_views: Vec<String>,
+    view_transforms: Vec<ViewTransform>,
+    default_view_transform: Option<String>,
+    looks: Vec<Look>,
+    colorspaces: Vec<ColorSpace>,
+}
+
+#[derive(Clone, Debug)]
+struct Display {
+    name: String,
+    views: Vec<View>,
+}
+
+/// A v1 view names one color space, a v2 one a view transform and a
+/// display color space.
+#[derive(Clone, Debug)]
+struct View {
+    name: String,
+    colorspace: Option<String>,
+    view_transform: Option<String>,
+    display_colorspace: Option<String>,
+    looks: Option<String>,
+}
+
+/// Maps the scene reference to the display reference.
+#[derive(Clone, Debug)]
+struct ViewTransform {
+    name: String,
+    to_reference: Option<Node>,
+    from_reference: Option<Node>,
+}
+
+#[derive(Clone, Debug)]
+struct Look {
+    name: String,
+    process_space: String,
+    transform: Option<Node>,
+    inverse_transform: Option<Node>,
+}
+
+#[derive(Clone, Debug)]
+struct ColorSpace {
+    name: String,
+    aliases: Vec<String>,
+    family: String,
+    /// Relative to the display reference rather than the scene one.
+    display: bool,
+    /// Values that aren't colors, which nothing converts.
+    data: bool,
+    to_reference: Option<Node>,
+    from_reference: Option<Node>,
+}
+
+/// A chain of transforms from one color space to another.
+#[derive(Clone, Debug, Default)]
+pub struct Processor {
+    ops: Vec<Op>,
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+    /// Multiplies by the matrix and adds the offset.
+    Matrix(Mat3, Vec3),
+    Clamp(Vec3, Vec3),
+    Power(Vec3, Negative),
+    /// Power with a linear segment through zero, such as the sRGB curve,
+    /// taking encoded values to linear ones unless inverted.
+    Moncurve {
+        gamma: Vec3,
+        offset: Vec3,
+        inverse: bool,
+    },
+    /// Logarithm of linear values, unless inverted.
+    Log {
+        log: LogParams,
+        inverse: bool,
+    },
+    Lut1d {
+        lut: Lut1d,
+        inverse: bool,
+    },
+    Lut3d(Lut3d),
+}
+
+/// What a power does to negative values.
+#[derive(Clone, Copy, Debug)]
+enum Negative {
+    Clamp,
+    Mirror,
+    Pass,
+}
+
+/// `log_slope * log(lin_slope * x + lin_offset) + log_offset`, with a
+/// straight segment up to `lin_break` when there is one, as a
+/// `LogCameraTransform` has.
+#[derive(Clone, Copy, Debug)]
+struct LogParams {
+    base: f32,
+    log_slope: Vec3,
+    log_offset: Vec3,
+    lin_slope: Vec3,
+    lin_offset: Vec3,
+    lin_break: Option<Vec3>,
+    linear_slope: Option<Vec3>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read OCIO config {}", path.display()))?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        Self::parse(&text, directory)
+            .with_context(|| format!("Failed to parse OCIO config {}", path.display()))
+    }
+
+    /// Reads a config whose relative search paths start at `directory`.
+    pub fn parse(text: &str, directory: &Path) -> Result<Self> {
+        let root = parse_yaml(text)?;
+        let mut search_path = vec![directory.to_owned()];
+        if let Some(paths) = root.get("search_path") {
+            search_path = paths
+                .names(':')
+                .into_iter()
+                .map(|path| directory.join(path))
+                .collect();
+        }
+        let roles = root
+            .get("roles")
+            .map(|roles| {
+                roles
+                    .entries()
+                    .iter()
+                    .filter_map(|(role, name)| Some((role.clone(), name.str()?.to_owned())))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let shared_views: Vec<View> = root.get("shared_views").map_or(Ok(Vec::new()), |views| {
+            views.items().iter().map(View::parse).collect()
+        })?;
+        let mut displays = Vec::new();
+        for (name, views) in root.get("displays").map(Node::entries).unwrap_or_default() {
+            let mut display = Display {
+                name: name.clone(),
+                views: Vec::new(),
+            };
+            for view in views.items() {
+                if view.tag.as_deref() == Some("Views") {
+                    for name in view.names(',') {
+                        let shared = shared_views.iter().find(|view| view.name == name);
+                        let shared = shared.with_context(|| {
+                            format!("Display {}: No shared view {name}", display.name)
+                        })?;
+                        display.views.push(shared.clone());
+                    }
+                } else {
+                    display.views.push(View::parse(view)?);
+                }
+            }
+            displays.push(display);
+        }
+
+        let view_transforms = root
+            .get("view_transforms")
+            .map(Node::items)
+            .unwrap_or_default()
+            .iter()
+            .map(|transform| {
+                Ok(ViewTransform {
+                    name: transform.string("name")?,
+                    to_reference: transform.get("to_scene_reference").cloned(),
+                    from_reference: transform.get("from_scene_reference").cloned(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        let looks = root
+            .get("looks")
+            .map(Node::items)
+            .unwrap_or_default()
+            .iter()
+            .map(|look| {
+                Ok(Look {
+                    name: look.string("name")?,
+                    process_space: look.string("process_space")?,
+                    transform: look.get("transform").cloned(),
+                    inverse_transform: look.get("inverse_transform").cloned(),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        let mut colorspaces = Vec::new();
+        for (key, display) in [("colorspaces", false), ("display_colorspaces", true)] {
+            for colorspace in root.get(key).map(Node::items).unwrap_or_default() {
+                // v1 configs name both references the same
+                let reference =
+                    |v1: &str, v2: &str| colorspace.get(v2).or(colorspace.get(v1)).cloned();
+                let (to_reference, from_reference) = match display {
+                    false => (
+                        reference("to_reference", "to_scene_reference"),
+                        reference("from_reference", "from_scene_reference"),
+                    ),
+                    true => (
+                        reference("to_reference", "to_display_reference"),
+                        reference("from_reference", "from_display_reference"),
+                    ),
+                };
+                colorspaces.push(ColorSpace {
+                    name: colorspace.string("name")?,
+                    aliases: colorspace
+                        .get("aliases")
+                        .map(|aliases| aliases.names(','))
+                        .unwrap_or_default(),
+                    family: colorspace
+                        .get("family")
+                        .and_then(Node::str)
+                        .unwrap_or_default()
+                        .to_owned(),
+                    display,
+                    data: colorspace.get("isdata").and_then(Node::str) == Some("true"),
+                    to_reference,
+                    from_reference,
+                });
+            }
+        }
+
+        let names = |key| {
+            root.get(key)
+                .map(|names| names.names(','))
+                .unwrap_or_default()
+        };
+        Ok(Self {
+            search_path,
+            roles,
+            displays,
+            active_displays: names("active_displays"),
+            active_views: names("active_views"),
+            view_transforms,
+            default_view_transform: root
+                .get("default_view_transform")
+                .and_then(Node::str)
+                .map(str::to_owned),
+            looks,
+            colorspaces,
+        })
+    }
+
+    /// The first active display.
+    pub fn default_display(&self) -> Result<&str> {
+        self.active_displays()
+            .next()
+            .map(|display| display.name.as_str())
+            .context("The config has no displays")
+    }
+
+    /// The first active view of `display`.
+    pub fn default_view(&self, display: &str) -> Result<&str> {
+        self.active_views(self.display(display)?)
+            .next()
+            .map(|view| view.name.as_str())
+            .with_context(|| format!("Display {display} has no views"))
+    }
+
+    /// Converts `input` to what `display` shows through `view`.
+    pub fn display_view(&self, input: &str, display: &str, view: &str) -> Result<Processor> {
+        let mut ops = Vec::new();
+        self.push_display_view(self.colorspace(input)?, display, view, &mut ops)?;
+        Ok(Processor { ops })
+    }
+
+    /// Converts `input` to `output`.
+    pub fn processor(&self, input: &str, output: &str) -> Result<Processor> {
+        let mut ops = Vec::new();
+        self.push_conversion(self.colorspace(input)?, self.colorspace(output)?, &mut ops)?;
+        Ok(Processor { ops })
+    }
+
+    /// Every LUT file the transforms of the config read, that exists.
+    pub fn files(&self) -> Vec<PathBuf> {
+        fn walk(config: &Config, node: &Node, files: &mut Vec<PathBuf>) {
+            if node.tag.as_deref() == Some("FileTransform") {
+                let file = node.get("src").and_then(Node::str);
+                files.extend(file.and_then(|file| config.find_file(file).ok()));
+            }
+            match &node.value {
+                Value::Seq(items) => items.iter().for_each(|item| walk(config, item, files)),
+                Value::Map(entries) => entries
+                    .iter()
+                    .for_each(|(_, value)| walk(config, value, files)),
+                _ => {}
+            }
+        }
+        let mut files = Vec::new();
+        let transforms = self
+            .colorspaces
+            .iter()
+            .flat_map(|colorspace| [&colorspace.to_reference, &colorspace.from_reference])
+            .chain(
+                self.view_transforms
+                    .iter()
+                    .flat_map(|transform| [&transform.to_reference, &transform.from_reference]),
+            )
+            .chain(
+                self.looks
+                    .iter()
+                    .flat_map(|look| [&look.transform, &look.inverse_transform]),
+            );
+        for transform in transforms.flatten() {
+            walk(self, transform, &mut files);
+        }
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    /// Lists the active displays and views, the looks, the roles and the
+    /// color spaces, for `spectrum colorspaces`.
+    pub fn describe(&self) -> String {
+        let mut text = String::from("Displays and views:\n");
+        for display in self.active_displays() {
+            let _ = writeln!(text, "  {}", display.name);
+            for view in self.active_views(display) {
+                let _ = writeln!(text, "    {}", view.name);
+            }
+        }
+        if !self.looks.is_empty() {
+            text.push_str("Looks:\n");
+            for look in &self.looks {
+                let _ = writeln!(text, "  {}", look.name);
+            }
+        }
+        text.push_str("Roles:\n");
+        for (role, name) in &self.roles {
+            let _ = writeln!(text, "  {role}: {name}");
+        }
+        text.push_str("Color spaces:\n");
+        for colorspace in &self.colorspaces {
+            let _ = write!(text, "  {}", colorspace.name);
+            if !colorspace.family.is_empty() {
+                let _ = write!(text, " ({})", colorspace.family);
+            }
+            if !colorspace.aliases.is_empty() {
+                let _ = write!(text, ", also {}", colorspace.aliases.join(", "));
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    fn active_displays(&self) -> impl Iterator<Item = &Display> {
+        let active = self
+            .active_displays
+            .iter()
+            .filter_map(|name| self.displays.iter().find(|display| &display.name == name));
+        let all = self.active_displays.is_empty().then_some(&self.displays);
+        active.chain(all.into_iter().flatten())
+    }
+
+    fn active_views<'a>(&'a self, display: &'a Display) -> impl Iterator<Item = &'a View> {
+        let active = self
+            .active_views
+            .iter()
+            .filter_map(|name| display.views.iter().find(|view| &view.name == name));
+        // Views left out of the list still show on displays it has none of
+        let all = active.clone().next().is_none().then_some(&display.views);
+        active.chain(all.into_iter().flatten())
+    }
+
+    fn display(&self, name: &str) -> Result<&Display> {
+        self.displays
+            .iter()
+            .find(|display| display.name.eq_ignore_ascii_case(name))
+            .with_context(|| {
+                let names: Vec<_> = self
+                    .displays
+                    .iter()
+                    .map(|display| display.name.as_str())
+                    .collect();
+                format!("No display {name}, the config has {}", names.join(", "))
+            })
+    }
+
+    /// The color space called `name`, or with it as an alias or role.
+    fn colorspace(&self, name: &str) -> Result<&ColorSpace> {
+        let find = |name: &str| {
+            self.colorspaces.iter().find(|colorspace| {
+                colorspace.name.eq_ignore_ascii_case(name)
+                    || colorspace
+                        .aliases
+                        .iter()
+                        .any(|alias| alias.eq_ignore_ascii_case(name))
+            })
+        };
+        let role = || {
+            let (_, name) = self
+                .roles
+                .iter()
+                .find(|(role, _)| role.eq_ignore_ascii_case(name))?;
+            find(name)
+        };
+        find(name)
+            .or_else(role)
+            .with_context(|| format!("No color space or role {name} in the config"))
+    }
+
+    fn view_transform(&self, name: Option<&str>) -> Result<&ViewTransform> {
+        let name = name.or(self.default_view_transform.as_deref());
+        let transform = match name {
+            Some(name) => self
+                .view_transforms
+                .iter()
+                .find(|transform| transform.name == name),
+            None => self.view_transforms.first(),
+        };
+        transform.with_context(|| match name {
+            Some(name) => format!("No view transform {name} in the config"),
+            None => "The config has no view transforms".to_owned(),
+        })
+    }
+
+    fn push_display_view(
+        &self,
+        input: &ColorSpace,
+        display: &str,
+        view: &str,
+        ops: &mut Vec<Op>,
+    ) -> Result<()> {
+        let display = self.display(display)?;
+        let view = display
+            .views
+            .iter()
+            .find(|candidate| candidate.name.eq_ignore_ascii_case(view))
+            .with_context(|| {
+                let names: Vec<_> = display
+                    .views
+                    .iter()
+                    .map(|view| view.name.as_str())
+                    .collect();
+                format!(
+                    "Display {} has no view {view}, only {}",
+                    display.name,
+                    names.join(", ")
+                )
+            })?;
+        let mut input = input;
+        if let Some(looks) = &view.looks {
+            input = self.push_looks(input, looks, ops)?;
+        }
+        let display_name = |name: &str| match name {
+            USE_DISPLAY_NAME => display.name.clone(),
+            name => name.to_owned(),
+        };
+        match (
+            &view.view_transform,
+            &view.display_colorspace,
+            &view.colorspace,
+        ) {
+            (Some(transform), Some(output), _) => {
+                let output = self.colorspace(&display_name(output))?;
+                self.push_to_reference(input, ops)?;
+                if input.display {
+                    self.push_view_transform(None, true, ops)?;
+                }
+                self.push_view_transform(Some(transform), false, ops)?;
+                self.push_from_reference(output, ops)
+            }
+            (_, _, Some(output)) => {
+                self.push_conversion(input, self.colorspace(&display_name(output))?, ops)
+            }
+            _ => bail!("View {} has no color space", view.name),
+        }
+    }
+
+    /// Applies the looks of a `+look, -look` list in their process spaces,
+    /// and returns the last of those.
+    fn push_looks<'a>(
+        &'a self,
+        input: &'a ColorSpace,
+        looks: &str,
+        ops: &mut Vec<Op>,
+    ) -> Result<&'a ColorSpace> {
+        let mut current = input;
+        for name in looks
+            .split([',', ':'])
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            let (name, inverse) = match name.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (name.trim_start_matches('+'), false),
+            };
+            let look = self
+                .looks
+                .iter()
+                .find(|look| look.name == name)
+                .with_context(|| format!("No look {name} in the config"))?;
+            let process_space = self.colorspace(&look.process_space)?;
+            self.push_conversion(current, process_space, ops)?;
+            let (forward, backward) = match inverse {
+                false => (&look.transform, &look.inverse_transform),
+                true => (&look.inverse_transform, &look.transform),
+            };
+            match (forward, backward) {
+                (Some(transform), _) => ops.extend(self.ops(transform)?),
+                (None, Some(transform)) => ops.extend(invert(self.ops(transform)?)?),
+                (None, None) => {}
+            }
+            current = process_space;
+        }
+        Ok(current)
+    }
+
+    /// Converts between color spaces through their references, and the
+    /// default view transform when one is a display color space and the
+    /// other isn't.
+    fn push_conversion(
+        &self,
+        input: &ColorSpace,
+        output: &ColorSpace,
+        ops: &mut Vec<Op>,
+    ) -> Result<()> {
+        if input.name == output.name || input.data || output.data {
+            return Ok(());
+        }
+        self.push_to_reference(input, ops)?;
+        if input.display != output.display {
+            self.push_view_transform(None, input.display, ops)?;
+        }
+        self.push_from_reference(output, ops)
+    }
+
+    fn push_to_reference(&self, colorspace: &ColorSpace, ops: &mut Vec<Op>) -> Result<()> {
+        let ops_to = match (&colorspace.to_reference, &colorspace.from_reference) {
+            (Some(transform), _) => self.ops(transform),
+            (None, Some(transform)) => self.ops(transform).and_then(invert),
+            (None, None) => Ok(Vec::new()),
+        };
+        ops.extend(ops_to.with_context(|| format!("Color space {}", colorspace.name))?);
+        Ok(())
+    }
+
+    fn push_from_reference(&self, colorspace: &ColorSpace, ops: &mut Vec<Op>) -> Result<()> {
+        let ops_from = match (&colorspace.from_reference, &colorspace.to_reference) {
+            (Some(transform), _) => self.ops(transform),
+            (None, Some(transform)) => self.ops(transform).and_then(invert),
+            (None, None) => Ok(Vec::new()),
+        };
+        ops.extend(ops_from.with_context(|| format!("Color space {}", colorspace.name))?);
+        Ok(())
+    }
+
+    /// From the scene reference to the display one, or back when
+    /// `inverse`, through `name` or the default view transform.
+    fn push_view_transform(
+        &self,
+        name: Option<&str>,
+        inverse: bool,
+        ops: &mut Vec<Op>,
+    ) -> Result<()> {
+        let transform = self.view_transform(name)?;
+        let forward = match (&transform.from_reference, &transform.to_reference) {
+            (Some(from), _) => self.ops(from),
+            (None, Some(to)) => self.ops(to).and_then(invert),
+            (None, None) => bail!(
+                "View transform {} has no scene reference transform",
+                transform.name
+            ),
+        };
+        let forward = forward.with_context(|| format!("View transform {}", transform.name))?;
+        ops.extend(if inverse { invert(forward)? } else { forward });
+        Ok(())
+    }
+
+    /// The ops of a transform node, in the direction it asks for.
+    fn ops(&self, node: &Node) -> Result<Vec<Op>> {
+        let vec3 = |key, default| node.vec3(key, default);
+        let tag = node.tag.as_deref().context("Transform without a type")?;
+        let mut ops = Vec::new();
+        match tag {
+            "GroupTransform" => {
+                let children = node.get("children").map(Node::items).unwrap_or_default();
+                for child in children {
+                    ops.extend(self.ops(child)?);
+                }
+            }
+            "MatrixTransform" => {
+                let matrix = node.floats("matrix")?;
+                let matrix = match matrix.len() {
+                    0 => Mat3::IDENTITY,
+                    16 => Mat3::from_cols_array(&[
+                        matrix[0], matrix[4], matrix[8], matrix[1], matrix[5], matrix[9],
+                        matrix[2], matrix[6], matrix[10],
+                    ]),
+                    _ => bail!("A matrix needs 16 values"),
+                };
+                ops.push(Op::Matrix(matrix, vec3("offset", Vec3::ZERO)?));
+            }
+            "ExponentTransform" => {
+                let negative = match node.get("negative_style").and_then(Node::str) {
+                    Some("mirror") => Negative::Mirror,
+                    Some("pass_thru") => Negative::Pass,
+                    _ => Negative::Clamp,
+                };
+                ops.push(Op::Power(vec3("value", Vec3::ONE)?, negative));
+            }
+            "ExponentWithLinearTransform" => ops.push(Op::Moncurve {
+                gamma: vec3("gamma", Vec3::ONE)?,
+                offset: vec3("offset", Vec3::ZERO)?,
+                inverse: false,
+            }),
+            "LogTransform" | "LogAffineTransform" | "LogCameraTransform" => {
+                let lin_break = match tag {
+                    "LogCameraTransform" => {
+                        node.get("lin_side_break")
+                            .context("A LogCameraTransform needs lin_side_break")?;
+                        Some(vec3("lin_side_break", Vec3::ZERO)?)
+                    }
+                    _ => None,
+                };
+                let linear_slope = match node.get("linear_slope") {
+                    Some(_) => Some(vec3("linear_slope", Vec3::ONE)?),
+                    None => None,
+                };
+                ops.push(Op::Log {
+                    log: LogParams {
+                        base: node.float("base", 2.0)?,
+                        log_slope: vec3("log_side_slope", Vec3::ONE)?,
+                        log_offset: vec3("log_side_offset", Vec3::ZERO)?,
+                        lin_slope: vec3("lin_side_slope", Vec3::ONE)?,
+                        lin_offset: vec3("lin_side_offset", Vec3::ZERO)?,
+                        lin_break,
+                        linear_slope,
+                    },
+                    inverse: false,
+                });
+            }
+            "CDLTransform" => {
+                let clamp = !matches!(
+                    node.get("style").and_then(Node::str).map(|style| style.to_ascii_lowercase().replace('_', "")),
+                    Some(style) if style.starts_with("noclamp")
+                );
+                let saturation = node.float("sat", 1.0)?;
+                let saturation_matrix = Mat3::from_cols(
+                    Vec3::X * saturation + Vec3::ONE * (1.0 - saturation) * LUMA.x,
+                    Vec3::Y * saturation + Vec3::ONE * (1.0 - saturation) * LUMA.y,
+                    Vec3::Z * saturation + Vec3::ONE * (1.0 - saturation) * LUMA.z,
+                );
+                let unit = Op::Clamp(Vec3::ZERO, Vec3::ONE);
+                ops.push(Op::Matrix(
+                    Mat3::from_diagonal(vec3("slope", Vec3::ONE)?),
+                    vec3("offset", Vec3::ZERO)?,
+                ));
+                ops.extend(clamp.then(|| unit.clone()));
+                let negative = if clamp {
+                    Negative::Clamp
+                } else {
+                    Negative::Pass
+                };
+                ops.push(Op::Power(vec3("power", Vec3::ONE)?, negative));
+                ops.push(Op::Matrix(saturation_matrix, Vec3::ZERO));
+                ops.extend(clamp.then_some(unit));
+            }
+            "RangeTransform" => {
+                let value = |key| node.get(key).map(|_| node.float(key, 0.0)).transpose();
+                let (min_in, max_in) = (value("min_in_value")?, value("max_in_value")?);
+                let (min_out, max_out) = (value("min_out_value")?, value("max_out_value")?);
+                let (scale, offset) = match (min_in, max_in, min_out, max_out) {
+                    (Some(min_in), Some(max_in), Some(min_out), Some(max_out)) => {
+                        let scale = (max_out - min_out) / (max_in - min_in);
+                        (scale, min_out - min_in * scale)
+                    }
+                    (Some(min_in), _, Some(min_out), _) => (1.0, min_out - min_in),
+                    (_, Some(max_in), _, Some(max_out)) => (1.0, max_out - max_in),
+                    _ => (1.0, 0.0),
+                };
+                ops.push(Op::Matrix(
+                    Mat3::from_diagonal(Vec3::splat(scale)),
+                    Vec3::splat(offset),
+                ));
+                let no_clamp = node
+                    .get("style")
+                    .and_then(Node::str)
+                    .is_some_and(|style| style.eq_ignore_ascii_case("noclamp"));
+                if !no_clamp {
+                    ops.push(Op::Clamp(
+                        Vec3::splat(min_out.unwrap_or(f32::NEG_INFINITY)),
+                        Vec3::splat(max_out.unwrap_or(f32::INFINITY)),
+                    ));
+                }
+            }
+            "AllocationTransform" => {
+                let vars = node.floats("vars")?;
+                let (min, max) = match vars[..] {
+                    [] => (0.0, 1.0),
+                    [min, max, ..] => (min, max),
+                    _ => bail!("An allocation needs a minimum and a maximum"),
+                };
+                match node.get("allocation").and_then(Node::str) {
+                    Some("lg2") => {
+                        let offset = vars.get(2).copied().unwrap_or(0.0);
+                        ops.push(Op::Matrix(Mat3::IDENTITY, Vec3::splat(offset)));
+                        ops.push(Op::Log {
+                            log: LogParams::log(2.0),
+                            inverse: false,
+                        });
+                    }
+                    Some("uniform") | None => {}
+                    Some(allocation) => bail!("Unknown allocation {allocation}"),
+                }
+                let scale = (max - min).recip();
+                ops.push(Op::Matrix(
+                    Mat3::from_diagonal(Vec3::splat(scale)),
+                    Vec3::splat(-min * scale),
+                ));
+            }
+            "FileTransform" => {
+                let file = node
+                    .get("src")
+                    .and_then(Node::str)
+                    .context("A FileTransform needs src")?;
+                ops.extend(self.file_ops(file).with_context(|| format!("LUT {file}"))?);
+            }
+            "ColorSpaceTransform" => {
+                let input = self.colorspace(&node.string("src")?)?;
+                let output = self.colorspace(&node.string("dst")?)?;
+                self.push_conversion(input, output, &mut ops)?;
+            }
+            "LookTransform" => {
+                let input = self.colorspace(&node.string("src")?)?;
+                let output = self.colorspace(&node.string("dst")?)?;
+                let looks = node.get("looks").and_then(Node::str).unwrap_or_default();
+                let process_space = self.push_looks(input, looks, &mut ops)?;
+                self.push_conversion(process_space, output, &mut ops)?;
+            }
+            "DisplayViewTransform" => {
+                let input = self.colorspace(&node.string("src")?)?;
+                self.push_display_view(
+                    input,
+                    &node.string("display")?,
+                    &node.string("view")?,
+                    &mut ops,
+                )?;
+            }
+            "BuiltinTransform" => bail!(
+                "BuiltinTransform {} isn't supported, bake the view with ociobakelut and pass \
+                 the .cube with --display-lut",
+                node.get("style").and_then(Node::str).unwrap_or_default()
+            ),
+            tag => bail!("{tag} isn't supported"),
+        }
+        match node.get("direction").and_then(Node::str) {
+            Some("inverse") => invert(ops),
+            _ => Ok(ops),
+        }
+    }
+
+    fn file_ops(&self, file: &str) -> Result<Vec<Op>> {
+        let path = self.find_file(file)?;
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let extension = path
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_ascii_lowercase();
+        Ok(match extension.as_str() {
+            "cube" => {
+                let lut = DisplayLut::parse(&text)?;
+                // The parser fills in whichever table the file leaves out
+                let has = |keyword| {
+                    text.lines()
+                        .any(|line| line.trim_start().starts_with(keyword))
+                };
+                let mut ops = Vec::new();
+                if has("LUT_1D_SIZE") {
+                    ops.push(Op::Lut1d {
+                        lut: lut.shaper,
+                        inverse: false,
+                    });
+                }
+                if has("LUT_3D_SIZE") {
+                    ops.push(Op::Lut3d(lut.cube));
+                }
+                ops
+            }
+            "spi1d" => vec![Op::Lut1d {
+                lut: parse_spi1d(&text)?,
+                inverse: false,
+            }],
+            "spi3d" => vec![Op::Lut3d(parse_spi3d(&text)?)],
+            _ => bail!("Only .cube, .spi1d and .spi3d LUTs are supported"),
+        })
+    }
+
+    fn find_file(&self, file: &str) -> Result<PathBuf> {
+        if Path::new(file).is_absolute() {
+            return Ok(PathBuf::from(file));
+        }
+        self.search_path
+            .iter()
+            .map(|directory| directory.join(file))
+            .find(|path| path.is_file())
+            .with_context(|| format!("{file} isn't in the search path"))
+    }
+}
+
+impl View {
+    fn parse(node: &Node) -> Result<Self> {
+        let field = |key| node.get(key).and_then(Node::str).map(str::to_owned);
+        Ok(Self {
+            name: node.string("name")?,
+            colorspace: field("colorspace"),
+            view_transform: field("view_transform"),
+            display_colorspace: field("display_colorspace"),
+            looks: field("looks"),
+        })
+    }
+}
+
+impl Processor {
+    pub fn apply(&self, color: Vec3) -> Vec3 {
+        self.ops.iter().fold(color, |color, op| op.apply(color))
+    }
+
+    /// Converts an image with premultiplied alpha in place.
+    pub fn apply_image(&self, image: &mut image::Rgba32FImage) {
+        for pixel in image.pixels_mut() {
+            let [r, g, b, a] = pixel.0;
+            let unpremultiply = if a > 0.0 { a.recip() } else { 0.0 };
+            let color = self.apply(Vec3::new(r, g, b) * unpremultiply) * a;
+            pixel.0 = [color.x, color.y, color.z, a];
+        }
+    }
+
+    /// Samples the conversion into a shaper, logarithmic apart from near
+    /// black, and a cube in its output, for linear light up to
+    /// `SHAPER_MAX`.
+    pub fn bake(&self) -> DisplayLut {
+        let range = (SHAPER_TOE * SHAPER_MAX).ln_1p();
+        let shaper = (0..SHAPER_SIZE)
+            .map(|i| {
+                let x = i as f32 / (SHAPER_SIZE - 1) as f32 * SHAPER_MAX;
+                Vec3::splat((SHAPER_TOE * x).ln_1p() / range)
+            })
+            .collect();
+        let last = (CUBE_SIZE - 1) as f32;
+        let linear = |i: u32| (i as f32 / last * range).exp_m1() / SHAPER_TOE;
+        let mut cube = Vec::with_capacity(CUBE_SIZE.pow(3) as usize);
+        for b in 0..CUBE_SIZE {
+            for g in 0..CUBE_SIZE {
+                for r in 0..CUBE_SIZE {
+                    cube.push(self.apply(Vec3::new(linear(r), linear(g), linear(b))));
+                }
+            }
+        }
+        DisplayLut {
+            shaper: Lut1d {
+                domain_min: Vec3::ZERO,
+                domain_max: Vec3::splat(SHAPER_MAX),
+                values: shaper,
+            },
+            cube: Lut3d {
+                size: CUBE_SIZE,
+                domain_min: Vec3::ZERO,
+                domain_max: Vec3::ONE,
+                values: cube,
+            },
+        }
+    }
+}
+
+impl LogParams {
+    fn log(base: f32) -> Self {
+        Self {
+            base,
+            log_slope: Vec3::ONE,
+            log_offset: Vec3::ZERO,
+            lin_slope: Vec3::ONE,
+            lin_offset: Vec3::ZERO,
+            lin_break: None,
+            linear_slope: None,
+        }
+    }
+
+    fn apply(&self, color: Vec3, inverse: bool) -> Vec3 {
+        let ln_base = self.base.ln();
+        let channel = |i: usize| {
+            let (log_slope, log_offset) = (self.log_slope[i], self.log_offset[i]);
+            let (lin_slope, lin_offset) = (self.lin_slope[i], self.lin_offset[i]);
+            let log = |x: f32| {
+                log_slope * (lin_slope * x + lin_offset).max(f32::MIN_POSITIVE).ln() / ln_base
+                    + log_offset
+            };
+            let linear = self.lin_break.map(|lin_break| {
+                let lin_break = lin_break[i];
+                // Meets the curve with the same slope unless given
+                let slope = self.linear_slope.map_or_else(
+                    || log_slope * lin_slope / ((lin_slope * lin_break + lin_offset) * ln_base),
+                    |slope| slope[i],
+                );
+                (lin_break, slope, log(lin_break) - slope * lin_break)
+            });
+            let x = color[i];
+            match (linear, inverse) {
+                (Some((lin_break, slope, offset)), false) if x <= lin_break => slope * x + offset,
+                (Some((lin_break, slope, offset)), true) if x <= log(lin_break) => {
+                    (x - offset) / slope
+                }
+                (_, false) => log(x),
+                (_, true) => {
+                    (((x - log_offset) / log_slope * ln_base).exp() - lin_offset) / lin_slope
+                }
+            }
+        };
+        Vec3::new(channel(0), channel(1), channel(2))
+    }
+}
+
+impl Op {
+    fn apply(&self, color: Vec3) -> Vec3 {
+        match self {
+            Op::Matrix(matrix, offset) => *matrix * color + *offset,
+            Op::Clamp(min, max) => color.max(*min).min(*max),
+            Op::Power(exponent, negative) => {
+                let channel = |i: usize| {
+                    let x: f32 = color[i];
+                    match negative {
+                        Negative::Clamp => x.max(0.0).powf(exponent[i]),
+                        Negative::Mirror => x.signum() * x.abs().powf(exponent[i]),
+                        Negative::Pass if x < 0.0 => x,
+                        Negative::Pass => x.powf(exponent[i]),
+                    }
+                };
+                Vec3::new(channel(0), channel(1), channel(2))
+            }
+            Op::Moncurve {
+                gamma,
+                offset,
+                inverse,
+            } => {
+                let channel = |i: usize| {
+                    let (gamma, offset, x) = (gamma[i], offset[i], color[i]);
+                    if offset <= 0.0 {
+                        let exponent = if *inverse { gamma.recip() } else { gamma };
+                        return x.max(0.0).powf(exponent);
+                    }
+                    // Where the power and a line through zero meet with the
+                    // same slope
+                    let knee = offset / (gamma - 1.0);
+                    let slope =
+                        (gamma * offset / ((gamma - 1.0) * (1.0 + offset))).powf(gamma) / knee;
+                    match inverse {
+                        false if x <= knee => x * slope,
+                        false => ((x + offset) / (1.0 + offset)).powf(gamma),
+                        true if x <= knee * slope => x / slope,
+                        true => (1.0 + offset) * x.powf(gamma.recip()) - offset,
+                    }
+                };
+                Vec3::new(channel(0), channel(1), channel(2))
+            }
+            Op::Log { log, inverse } => log.apply(color, *inverse),
+            Op::Lut1d {
+                lut,
+                inverse: false,
+            } => lut.apply(color),
+            Op::Lut1d { lut, inverse: true } => invert_lut1d(lut, color),
+            Op::Lut3d(lut) => lut.apply(color),
+        }
+    }
+
+    fn inverse(self) -> Result<Op> {
+        Ok(match self {
+            Op::Matrix(matrix, offset) => {
+                if matrix.determinant().abs() < 1e-12 {
+                    bail!("Can't invert a singular matrix");
+                }
+                let inverse = matrix.inverse();
+                Op::Matrix(inverse, -(inverse * offset))
+            }
+            Op::Clamp(min, max) => Op::Clamp(min, max),
+            Op::Power(exponent, negative) => Op::Power(exponent.recip(), negative),
+            Op::Moncurve {
+                gamma,
+                offset,
+                inverse,
+            } => Op::Moncurve {
+                gamma,
+                offset,
+                inverse: !inverse,
+            },
+            Op::Log { log, inverse } => Op::Log {
+                log,
+                inverse: !inverse,
+            },
+            Op::Lut1d { lut, inverse } => Op::Lut1d {
+                lut,
+                inverse: !inverse,
+            },
+            Op::Lut3d(_) => bail!("Can't invert a 3D LUT"),
+        })
+    }
+}
+
+/// Undoes `ops`, last first.
+fn invert(ops: Vec<Op>) -> Result<Vec<Op>> {
+    ops.into_iter().rev().map(Op::inverse).collect()
+}
+
+/// Searches the entries of each channel, which have to rise or fall
+/// throughout, for the input giving `color`.
+fn invert_lut1d(lut: &Lut1d, color: Vec3) -> Vec3 {
+    let last = lut.values.len() - 1;
+    let channel = |i: usize| {
+        let y = color[i];
+        let rising = lut.values[last][i] >= lut.values[0][i];
+        let above = lut.values.partition_point(|value| match rising {
+            true => value[i] <= y,
+            false => value[i] >= y,
+        });
+        let t = match above {
+            0 => 0.0,
+            above if above > last => last as f32,
+            above => {
+                let (below, above_value) = (lut.values[above - 1][i], lut.values[above][i]);
+                let fraction = if above_value != below {
+                    (y - below) / (above_value - below)
+                } else {
+                    0.0
+                };
+                (above - 1) as f32 + fraction
+            }
+        };
+        lut.domain_min[i] + t / last as f32 * (lut.domain_max[i] - lut.domain_min[i])
+    };
+    Vec3::new(channel(0), channel(1), channel(2))
+}
+
+/// Reads Sony Pictures Imageworks' 1D format, with one or three values
+/// per entry.
+fn parse_spi1d(text: &str) -> Result<Lut1d> {
+    let mut domain = (0.0, 1.0);
+    let mut length = 0;
+    let mut components = 1;
+    let mut lines = text.lines().map(str::trim);
+    for line in lines.by_ref() {
+        let mut fields = line.split_whitespace();
+        let number = |field: Option<&str>| -> Result<f32> {
+            field
+                .and_then(|field| field.parse().ok())
+                .with_context(|| format!("Invalid line {line}"))
+        };
+        match fields.next() {
+            Some("{") => break,
+            Some("From") => domain = (number(fields.next())?, number(fields.next())?),
+            Some("Length") => length = number(fields.next())? as usize,
+            Some("Components") => components = number(fields.next())? as usize,
+            _ => {}
+        }
+    }
+    let mut values = Vec::with_capacity(length);
+    for line in lines.take_while(|line| !line.starts_with('}')) {
+        let numbers: Vec<f32> = line
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("Invalid entry {line}"))?;
+        values.push(match (components, &numbers[..]) {
+            (_, []) => continue,
+            (1, &[value]) => Vec3::splat(value),
+            (3, &[r, g, b]) => Vec3::new(r, g, b),
+            _ => bail!("Expected {components} values, found {line}"),
+        });
+    }
+    if values.len() != length || length < 2 {
+        bail!("Expected {length} entries, found {}", values.len());
+    }
+    Ok(Lut1d {
+        domain_min: Vec3::splat(domain.0),
+        domain_max: Vec3::splat(domain.1),
+        values,
+    })
+}
+
+/// Reads Sony Pictures Imageworks' 3D format, whose entries give their
+/// own grid position.
+fn parse_spi3d(text: &str) -> Result<Lut3d> {
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+    let _header = lines.next();
+    let _channels = lines.next();
+    let size: Vec<u32> = lines
+        .next()
+        .context("Missing the size")?
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .context("Invalid size")?;
+    let size = match size[..] {
+        [r, g, b] if r == g && g == b && r >= 2 => r,
+        _ => bail!("Only cubes of at least 2 entries a side are supported"),
+    };
+    let mut values = vec![Vec3::ZERO; size.pow(3) as usize];
+    let mut filled = 0;
+    for line in lines {
+        let numbers: Vec<f32> = line
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("Invalid entry {line}"))?;
+        let &[r, g, b, red, green, blue] = &numbers[..] else {
+            bail!("Invalid entry {line}");
+        };
+        let [r, g, b] = [r, g, b].map(|index| index as u32);
+        if r >= size || g >= size || b >= size {
+            bail!("Entry outside the cube {line}");
+        }
+        values[(r + size * (g + size * b)) as usize] = Vec3::new(red, green, blue);
+        filled += 1;
+    }
+    if filled != values.len() {
+        bail!("Expected {} entries, found {filled}", values.len());
+    }
+    Ok(Lut3d {
+        size,
+        domain_min: Vec3::ZERO,
+        domain_max: Vec3::ONE,
+        values,
+    })
+}
+
+/// A YAML value, with the tag OCIO puts on transforms and views.
+#[derive(Clone, Debug, Default)]
+struct Node {
+    tag: Option<String>,
+    value: Value,
+}
+
+#[derive(Clone, Debug, Default)]
+enum Value {
+    #[default]
+    Null,
+    Scalar(String),
+    Seq(Vec<Node>),
+    Map(Vec<(String, Node)>),
+}
+
+impl Node {
+    fn get(&self, key: &str) -> Option<&Node> {
+        self.entries()
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+    }
+
+    fn entries(&self) -> &[(String, Node)] {
+        match &self.value {
+            Value::Map(entries) => entries,
+            _ => &[],
+        }
+    }
+
+    fn items(&self) -> &[Node] {
+        match &self.value {
+            Value::Seq(items) => items,
+            _ => &[],
+        }
+    }
+
+    fn str(&self) -> Option<&str> {
+        match &self.value {
+            Value::Scalar(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn string(&self, key: &str) -> Result<String> {
+        self.get(key)
+            .and_then(Node::str)
+            .map(str::to_owned)
+            .with_context(|| format!("Missing {key}"))
+    }
+
+    /// A list, or a scalar of names split by `separator`.
+    fn names(&self, separator: char) -> Vec<String> {
+        let names = match &self.value {
+            Value::Scalar(names) => names.split(separator).map(str::to_owned).collect(),
+            Value::Seq(items) => items
+                .iter()
+                .filter_map(|item| Some(item.str()?.to_owned()))
+                .collect(),
+            _ => Vec::new(),
+        };
+        let names = names.into_iter().map(|name: String| name.trim().to_owned());
+        names.filter(|name| !name.is_empty()).collect()
+    }
+
+    /// The numbers of a list, or a single one, under `key`.
+    fn floats(&self, key: &str) -> Result<Vec<f32>> {
+        let Some(node) = self.get(key) else {
+            return Ok(Vec::new());
+        };
+        let parse = |node: &Node| node.str().and_then(|value| value.parse().ok());
+        let floats = match &node.value {
+            Value::Seq(items) => items.iter().map(parse).collect(),
+            _ => parse(node).map(|value| vec![value]),
+        };
+        floats.with_context(|| format!("Invalid {key}"))
+    }
+
+    fn float(&self, key: &str, default: f32) -> Result<f32> {
+        Ok(self.floats(key)?.first().copied().unwrap_or(default))
+    }
+
+    /// Three numbers of a list, or one for all three.
+    fn vec3(&self, key: &str, default: Vec3) -> Result<Vec3> {
+        match self.floats(key)?[..] {
+            [] => Ok(default),
+            [value] => Ok(Vec3::splat(value)),
+            [r, g, b, ..] => Ok(Vec3::new(r, g, b)),
+            _ => bail!("Invalid {key}"),
+        }
+    }
+}
+
+/// A line of YAML without its comment, or a flow collection continued over
+/// several lines.
+struct Line {
+    number: usize,
+    indent: usize,
+    text: String,
+    /// The lines of the `|` or `>` scalar the line ends with.
+    block: Option<String>,
+}
+
+fn parse_yaml(text: &str) -> Result<Node> {
+    let mut parser = Parser {
+        lines: yaml_lines(text),
+        pos: 0,
+    };
+    let root = match parser.lines.is_empty() {
+        true => Node::default(),
+        false => parser.node(0)?,
+    };
+    if let Some(line) = parser.lines.get(parser.pos) {
+        bail!("line {}: Unexpected indentation", line.number);
+    }
+    Ok(root)
+}
+
+fn yaml_lines(text: &str) -> Vec<Line> {
+    let raw: Vec<&str> = text.lines().collect();
+    let mut lines: Vec<Line> = Vec::new();
+    // Brackets left open by the line before
+    let mut depth = 0;
+    let mut index = 0;
+    while index < raw.len() {
+        let number = index + 1;
+        let (content, opened) = strip_comment(raw[index], depth > 0);
+        index += 1;
+        if let Some(last) = lines.last_mut().filter(|_| depth > 0) {
+            last.text.push(' ');
+            last.text.push_str(content.trim());
+            depth += opened;
+            continue;
+        }
+        let trimmed = content.trim_start();
+        if trimmed.trim_end().is_empty() || trimmed.starts_with("---") || trimmed.starts_with('%') {
+            continue;
+        }
+        let indent = content.len() - trimmed.len();
+        let text = trimmed.trim_end().to_owned();
+        depth = opened;
+        let indicator = text.rsplit(' ').next().unwrap_or_default();
+        let block = matches!(indicator, "|" | "|-" | "|+" | ">" | ">-" | ">+").then(|| {
+            let mut block = String::new();
+            let mut block_indent = None;
+            while let Some(next) = raw.get(index) {
+                let next_indent = next.len() - next.trim_start().len();
+                if !next.trim().is_empty() && next_indent <= indent {
+                    break;
+                }
+                let block_indent = *block_indent.get_or_insert(next_indent);
+                block.push_str(next.get(block_indent..).unwrap_or_default());
+                block.push('\n');
+                index += 1;
+            }
+            block.trim_end().to_owned()
+        });
+        lines.push(Line {
+            number,
+            indent,
+            text,
+            block,
+        });
+    }
+    lines
+}
+
+/// The line before any comment, and the brackets of flow collections it
+/// opens less those it closes. Only brackets after `- `, `: ` or a tag
+/// start one, unless the line continues one already.
+fn strip_comment(line: &str, in_flow: bool) -> (&str, i32) {
+    let mut quote = None;
+    let mut depth = 0;
+    let mut in_flow = in_flow;
+    let mut previous = ' ';
+    let mut last_token = None;
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') if previous == ' ' || in_flow => quote = Some(c),
+            (None, '#') if previous.is_whitespace() => return (&line[..index], depth),
+            (None, '[' | '{')
+                if in_flow || matches!(last_token, None | Some(':' | '-' | '>' | ',')) =>
+            {
+                in_flow = true;
+                depth += 1;
+            }
+            (None, ']' | '}') if in_flow => depth -= 1,
+            _ => {}
+        }
+        if !c.is_whitespace() {
+            last_token = Some(c);
+        }
+        previous = c;
+    }
+    (line, depth)
+}
+
+struct Parser {
+    lines: Vec<Line>,
+    pos: usize,
+}
+
+impl Parser {
+    /// The node on the lines after a key or item at `indent` with nothing
+    /// after it, null without one. A map's value may be a list at the
+    /// map's own indent.
+    fn child(&mut self, indent: usize, same_indent_list: bool) -> Result<Node> {
+        let Some(line) = self.lines.get(self.pos) else {
+            return Ok(Node::default());
+        };
+        if line.indent > indent
+            || (same_indent_list && line.indent == indent && is_item(&line.text))
+        {
+            self.node(indent)
+        } else {
+            Ok(Node::default())
+        }
+    }
+
+    /// A list or map starting at the current line, or a value on it alone
+    /// belonging to a key or item at `owner`.
+    fn node(&mut self, owner: usize) -> Result<Node> {
+        let line = &self.lines[self.pos];
+        if is_item(&line.text) {
+            self.list(line.indent)
+        } else if split_key(&line.text).is_some() {
+            self.map(line.indent)
+        } else {
+            let (text, block) = (line.text.clone(), line.block.clone());
+            self.pos += 1;
+            self.value(&text, block, owner, false)
+        }
+    }
+
+    fn list(&mut self, indent: usize) -> Result<Node> {
+        let mut items = Vec::new();
+        while let Some(line) = self.lines.get_mut(self.pos) {
+            if line.indent != indent || !is_item(&line.text) {
+                break;
+            }
+            let rest = line.text[1..].trim_start().to_owned();
+            if rest.is_empty() {
+                self.pos += 1;
+                items.push(self.child(indent, false)?);
+            } else {
+                // What follows the dash reads as if on a line of its own
+                line.indent += line.text.len() - rest.len();
+                line.text = rest;
+                items.push(self.node(indent)?);
+            }
+        }
+        Ok(Node {
+            tag: None,
+            value: Value::Seq(items),
+        })
+    }
+
+    fn map(&mut self, indent: usize) -> Result<Node> {
+        let mut entries = Vec::new();
+        while let Some(line) = self.lines.get(self.pos) {
+            if line.indent != indent || is_item(&line.text) {
+                break;
+            }
+            let (key, rest) = split_key(&line.text)
+                .with_context(|| format!("line {}: Expected a key", line.number))?;
+            let (rest, block) = (rest.to_owned(), line.block.clone());
+            self.pos += 1;
+            let value = self.value(&rest, block, indent, true)?;
+            entries.push((key, value));
+        }
+        Ok(Node {
+            tag: None,
+            value: Value::Map(entries),
+        })
+    }
+
+    /// What follows a key or item at `owner`, on its line or those after.
+    fn value(
+        &mut self,
+        text: &str,
+        block: Option<String>,
+        owner: usize,
+        same_indent_list: bool,
+    ) -> Result<Node> {
+        let number = self
+            .lines
+            .get(self.pos.saturating_sub(1))
+            .map_or(0, |line| line.number);
+        let (tag, rest) = split_tag(text);
+        let mut node = if rest.is_empty() {
+            self.child(owner, same_indent_list)?
+        } else if rest.starts_with(['[', '{']) {
+            let mut flow = Flow { text: rest, pos: 0 };
+            let node = flow.value().with_context(|| format!("line {number}"))?;
+            if !flow.text[flow.pos..].trim().is_empty() {
+                bail!("line {number}: Unexpected {}", flow.text[flow.pos..].trim());
+            }
+            node
+        } else if let Some(block) = block {
+            let value = match rest.starts_with('>') {
+                true => block.lines().map(str::trim).collect::<Vec<_>>().join(" "),
+                false => block,
+            };
+            Node {
+                tag: None,
+                value: Value::Scalar(value),
+            }
+        } else {
+            // Plain and quoted scalars go on over more indented lines
+            let mut value = rest.to_owned();
+            while let Some(line) = self.lines.get(self.pos).filter(|line| line.indent > owner) {
+                value.push(' ');
+                value.push_str(&line.text);
+                self.pos += 1;
+            }
+            Node {
+                tag: None,
+                value: Value::Scalar(unquote(&value)),
+            }
+        };
+        if tag.is_some() {
+            node.tag = tag;
+        }
+        Ok(node)
+    }
+}
+
+fn is_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+/// Splits `key: value`, where the key may be quoted.
+fn split_key(text: &str) -> Option<(String, &str)> {
+    if text.starts_with(['!', '[', '{']) {
+        return None;
+    }
+    let key_end = match text.chars().next()? {
+        quote @ ('"' | '\'') => text[1..].find(quote)? + 2,
+        _ => 0,
+    };
+    let colon = key_end
+        + text[key_end..].find(": ").or_else(|| {
+            text[key_end..]
+                .ends_with(':')
+                .then(|| text.len() - key_end - 1)
+        })?;
+    Some((unquote(text[..colon].trim()), text[colon + 1..].trim()))
+}
+
+/// Splits a leading `!<Tag>` or `!Tag` off a value.
+fn split_tag(text: &str) -> (Option<String>, &str) {
+    if !text.starts_with('!') {
+        return (None, text);
+    }
+    let end = text
+        .find(|c: char| c.is_whitespace() || c == '[' || c == '{')
+        .unwrap_or(text.len());
+    let tag = text[..end]
+        .trim_start_matches('!')
+        .trim_start_matches('<')
+        .trim_end_matches('>');
+    (Some(tag.to_owned()), text[end..].trim())
+}
+
+fn unquote(text: &str) -> String {
+    let text = text.trim();
+    if text.len() >= 2 && text.starts_with('\'') && text.ends_with('\'') {
+        text[1..text.len() - 1].replace("''", "'")
+    } else if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        let mut value = String::new();
+        let mut chars = text[1..text.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                value.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some(c) => value.push(c),
+                None => {}
+            }
+        }
+        value
+    } else {
+        text.to_owned()
+    }
+}
+
+/// Reads `[...]` and `{...}` collections, and the scalars in them.
+struct Flow<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Flow<'_> {
+    fn value(&mut self) -> Result<Node> {
+        self.skip_whitespace();
+        let mut tag = None;
+        if self.peek() == Some('!') {
+            let end = self.text[self.pos..]
+                .find(|c: char| c.is_whitespace() || c == '[' || c == '{')
+                .map_or(self.text.len(), |end| self.pos + end);
+            let (parsed, _) = split_tag(&self.text[self.pos..end]);
+            tag = parsed;
+            self.pos = end;
+            self.skip_whitespace();
+        }
+        let value = match self.peek() {
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.eat(']') {
+                        break;
+                    }
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    if !self.eat(',') && !matches!(self.peek(), Some(']')) {
+                        bail!("Expected , or ] in a list");
+                    }
+                }
+                Value::Seq(items)
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut entries = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.eat('}') {
+                        break;
+                    }
+                    let key = self.scalar()?;
+                    self.skip_whitespace();
+                    if !self.eat(':') {
+                        bail!("Expected : after {key}");
+                    }
+                    self.skip_whitespace();
+                    let value = match self.peek() {
+                        Some(',' | '}') => Node::default(),
+                        _ => self.value()?,
+                    };
+                    entries.push((key, value));
+                    self.skip_whitespace();
+                    if !self.eat(',') && !matches!(self.peek(), Some('}')) {
+                        bail!("Expected , or }} in a map");
+                    }
+                }
+                Value::Map(entries)
+            }
+            _ => Value::Scalar(self.scalar()?),
+        };
+        Ok(Node { tag, value })
+    }
+
+    /// A quoted scalar, or a plain one up to the next `,`, `:`, `]` or `}`.
+    fn scalar(&mut self) -> Result<String> {
+        let rest = &self.text[self.pos..];
+        if let Some(quote @ ('"' | '\'')) = self.peek() {
+            let mut chars = rest.char_indices().skip(1);
+            let end = loop {
+                match chars.next() {
+                    Some((_, '\\')) if quote == '"' => {
+                        chars.next();
+                    }
+                    Some((index, c)) if c == quote => break index + 1,
+                    Some(_) => {}
+                    None => bail!("Unterminated string"),
+                }
+            };
+            self.pos += end;
+            return Ok(unquote(&rest[..end]));
+        }
+        let bytes = rest.as_bytes();
+        let end = (0..bytes.len())
+            .find(|&i| match bytes[i] {
+                b',' | b']' | b'}' => true,
+                b':' => bytes
+                    .get(i + 1)
+                    .is_none_or(|next| next.is_ascii_whitespace() || b",]}".contains(next)),
+                _ => false,
+            })
+            .unwrap_or(bytes.len());
+        self.pos += end;
+        Ok(rest[..end].trim().to_owned())
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let eaten = self.peek() == Some(c);
+        if eaten {
+            self.pos += c.len_utf8();
+        }
+        eaten
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+}
@@ -0,0 +1,6 @@
+//! Image files written from the raw render, for use outside the viewer.
+
+pub mod bracket;
+pub mod exr;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod preview;
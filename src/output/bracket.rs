@@ -0,0 +1,63 @@
+//! Exposure brackets, the same frame tonemapped a few stops apart, for
+//! previewing how a print holds up in the shadows and highlights. Each
+//! stop is saved next to the image as `{stem}_ev+1.png` and so on. The
+//! render is linear already, so an .exr or .avif output is the merged HDR
+//! of the brackets.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    lut::{self, DisplayLut},
+    tonemap::Tonemap,
+};
+
+/// Parses stops as `-2,0,2`, or as `5x1` for five stops one EV apart around
+/// the exposure.
+pub fn parse(text: &str) -> Option<Vec<f32>> {
+    if let Some((count, step)) = text.split_once('x') {
+        let count: u32 = count.trim().parse().ok().filter(|&count| count > 0)?;
+        let step: f32 = step.trim().parse().ok().filter(|step: &f32| *step > 0.0)?;
+        let first = (count - 1) as f32 * step / -2.0;
+        return Some((0..count).map(|i| first + i as f32 * step).collect());
+    }
+    text.split(',')
+        .map(|stop| {
+            stop.trim()
+                .parse()
+                .ok()
+                .filter(|stop: &f32| stop.is_finite())
+        })
+        .collect()
+}
+
+/// Where the bracket `stop` EV from `path` is saved, a PNG when `path`
+/// keeps HDR.
+pub fn path(path: &Path, stop: f32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .filter(|extension| !matches!(extension.as_str(), "exr" | "avif"))
+        .unwrap_or_else(|| "png".to_owned());
+    path.with_file_name(format!("{stem}_ev{stop:+}.{extension}"))
+}
+
+/// Saves linear light scaled by `scale` at each of `stops` EV from it.
+pub fn save(
+    image: &image::Rgba32FImage,
+    output: &Path,
+    scale: f32,
+    stops: &[f32],
+    tonemap: Tonemap,
+    display_lut: Option<&DisplayLut>,
+) -> Result<()> {
+    for &stop in stops {
+        let path = path(output, stop);
+        lut::display_image(image, scale * stop.exp2(), tonemap, display_lut)
+            .save(&path)
+            .with_context(|| format!("Failed to save {}", path.display()))?;
+    }
+    Ok(())
+}
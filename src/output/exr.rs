@@ -0,0 +1,109 @@
+//! OpenEXR files of the linear accumulation buffer, which keep everything
+//! above white and every shade in between for grading elsewhere.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use exr::prelude::{
+    AnyChannel, AnyChannels, Encoding, FlatSamples, Image, SmallVec, WritableImage,
+};
+use half::f16;
+
+use crate::aov::Aov;
+
+/// Bits per channel of a written EXR.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Precision {
+    /// 16-bit floats, half the size and plenty for a final image.
+    Half,
+    /// 32-bit floats, exactly what the tracer accumulated.
+    #[default]
+    Float,
+}
+
+impl Precision {
+    pub const ALL: [Self; 2] = [Self::Half, Self::Float];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|precision| precision.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Half => "half",
+            Self::Float => "float",
+        }
+    }
+}
+
+/// Writes linear light with premultiplied alpha, as EXR expects it, ZIP
+/// compressed.
+pub fn save(image: &image::Rgba32FImage, path: &Path, precision: Precision) -> Result<()> {
+    save_with_aovs(image, &[], path, precision)
+}
+
+/// Writes the image along with AOVs of the same size as more channels,
+/// named as `Aov::channels`.
+pub fn save_with_aovs(
+    image: &image::Rgba32FImage,
+    aovs: &[(Aov, image::Rgba32FImage)],
+    path: &Path,
+    precision: Precision,
+) -> Result<()> {
+    let layers =
+        std::iter::once((Aov::Beauty, image)).chain(aovs.iter().map(|(aov, image)| (*aov, image)));
+    let channels: Vec<_> = layers
+        .flat_map(|(aov, image)| aov_channels(aov, image))
+        .collect();
+    save_channels(image.dimensions(), &channels, path, precision)
+}
+
+/// Writes one AOV by itself.
+pub fn save_aov(
+    aov: Aov,
+    image: &image::Rgba32FImage,
+    path: &Path,
+    precision: Precision,
+) -> Result<()> {
+    let channels: Vec<_> = aov_channels(aov, image).collect();
+    save_channels(image.dimensions(), &channels, path, precision)
+}
+
+fn aov_channels(
+    aov: Aov,
+    image: &image::Rgba32FImage,
+) -> impl Iterator<Item = (&'static str, Vec<f32>)> + '_ {
+    (0..).zip(aov.channels()).map(|(component, &name)| {
+        let samples = image.pixels().map(|pixel| pixel.0[component]).collect();
+        (name, samples)
+    })
+}
+
+/// Writes named channels of `width` by `height` samples each, in rows from
+/// the top.
+pub fn save_channels(
+    (width, height): (u32, u32),
+    channels: &[(&str, Vec<f32>)],
+    path: &Path,
+    precision: Precision,
+) -> Result<()> {
+    let channels: SmallVec<_> = channels
+        .iter()
+        .map(|(name, samples)| {
+            let samples = match precision {
+                Precision::Half => {
+                    FlatSamples::F16(samples.iter().copied().map(f16::from_f32).collect())
+                }
+                Precision::Float => FlatSamples::F32(samples.clone()),
+            };
+            AnyChannel::new(*name, samples)
+        })
+        .collect();
+    let size = (width as usize, height as usize);
+    Image::from_encoded_channels(size, Encoding::SMALL_LOSSLESS, AnyChannels::sort(channels))
+        .write()
+        .to_file(path)
+        .with_context(|| format!("Failed to save {}", path.display()))
+}
@@ -0,0 +1,66 @@
+//! Small JPEGs of a headless render written while it accumulates, so farm
+//! dashboards can show how it's coming along. Each one can also be POSTed
+//! to a webhook, with the samples so far in an `X-Spectrum-Samples` header.
+
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result};
+use image::codecs::jpeg::JpegEncoder;
+
+use crate::{
+    lut::{self, DisplayLut},
+    tonemap::Tonemap,
+    webhook,
+};
+
+/// Longest side of a preview.
+pub const DEFAULT_SIZE: u32 = 256;
+const QUALITY: u8 = 80;
+
+#[derive(Clone, Debug)]
+pub struct Preview {
+    /// Replaced by every preview, never left half written.
+    pub path: Option<PathBuf>,
+    /// Longest side in pixels.
+    pub size: u32,
+    pub interval: Duration,
+    /// `http://` URL each preview is POSTed to.
+    pub webhook: Option<String>,
+    /// Exposure scale applied before the display transform.
+    pub scale: f32,
+    pub tonemap: Tonemap,
+    pub display_lut: Option<DisplayLut>,
+}
+
+impl Preview {
+    /// Encodes the linear `image` for display and sends it wherever it
+    /// goes.
+    pub fn write(&self, image: &image::Rgba32FImage, samples: u32) -> Result<()> {
+        let display =
+            lut::display_image(image, self.scale, self.tonemap, self.display_lut.as_ref());
+        let (width, height) = display.dimensions();
+        let scale = (self.size as f32 / width.max(height) as f32).min(1.0);
+        let small = image::imageops::thumbnail(
+            &display,
+            ((width as f32 * scale).round() as u32).max(1),
+            ((height as f32 * scale).round() as u32).max(1),
+        );
+        let rgb = image::DynamicImage::ImageRgba8(small).to_rgb8();
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, QUALITY)
+            .encode_image(&rgb)
+            .context("Failed to encode the preview")?;
+
+        if let Some(path) = &self.path {
+            let partial = path.with_extension("partial");
+            std::fs::write(&partial, &jpeg)
+                .and_then(|()| std::fs::rename(&partial, path))
+                .with_context(|| format!("Failed to save {}", path.display()))?;
+        }
+        if let Some(url) = &self.webhook {
+            let headers = [("X-Spectrum-Samples", samples.to_string())];
+            webhook::post(url, "image/jpeg", &headers, &jpeg)?;
+        }
+        Ok(())
+    }
+}
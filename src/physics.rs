@@ -0,0 +1,179 @@
+//! Rigid body simulation of scene instances with rapier. Instances with a
+//! `body` become rigid bodies, and every step moves their nodes to the new
+//! transforms and refits the BVH around the moved instances.
+
+use glam::{Mat4, Quat, Vec3};
+use rapier3d::{
+    na::{Quaternion, UnitQuaternion},
+    prelude::*,
+};
+
+use crate::{
+    bvh::InstancedBvh,
+    scene::{BodyKind, Scene},
+};
+
+/// Longest step taken at once, so a slow frame doesn't tunnel bodies
+/// through each other.
+const MAX_STEP: f32 = 1.0 / 30.0;
+
+pub struct Physics {
+    gravity: Vector<Real>,
+    integration_parameters: IntegrationParameters,
+    pipeline: PhysicsPipeline,
+    islands: IslandManager,
+    broad_phase: DefaultBroadPhase,
+    narrow_phase: NarrowPhase,
+    bodies: RigidBodySet,
+    colliders: ColliderSet,
+    impulse_joints: ImpulseJointSet,
+    multibody_joints: MultibodyJointSet,
+    ccd_solver: CCDSolver,
+    /// Instance moved by each dynamic body, along with the rest of its node,
+    /// and the scale of its transform that rigid bodies can't carry.
+    dynamic: Vec<(RigidBodyHandle, usize, Vec3)>,
+}
+
+impl Physics {
+    /// Returns `None` if no instance has a dynamic body.
+    pub fn new(scene: &Scene) -> Option<Self> {
+        if !scene
+            .instances
+            .iter()
+            .any(|instance| instance.body == Some(BodyKind::Dynamic))
+        {
+            return None;
+        }
+
+        // The primitives a node places move together, so they make up one
+        // body between them
+        let mut groups: Vec<(Option<usize>, Vec<usize>)> = Vec::new();
+        for (index, instance) in scene.instances.iter().enumerate() {
+            if instance.body.is_none() {
+                continue;
+            }
+            let node = scene.node_of_instance(index);
+            match groups
+                .iter_mut()
+                .find(|group| node.is_some() && group.0 == node)
+            {
+                Some((_, instances)) => instances.push(index),
+                None => groups.push((node, vec![index])),
+            }
+        }
+
+        let mut bodies = RigidBodySet::new();
+        let mut colliders = ColliderSet::new();
+        let mut dynamic = Vec::new();
+        for (_, instances) in groups {
+            let first = &scene.instances[instances[0]];
+            let Some(kind) = first.body else {
+                continue;
+            };
+            let (scale, rotation, translation) = first.transform.to_scale_rotation_translation();
+            let mut points: Vec<Point<Real>> = Vec::new();
+            let mut triangles = Vec::new();
+            for &index in &instances {
+                let mesh = &scene.meshes[scene.instances[index].mesh];
+                let offset = points.len() as u32;
+                triangles.extend(
+                    mesh.indices
+                        .chunks_exact(3)
+                        .map(|t| [t[0] + offset, t[1] + offset, t[2] + offset]),
+                );
+                points.extend(mesh.positions.iter().map(|&p| {
+                    let p = p * scale;
+                    point![p.x, p.y, p.z]
+                }));
+            }
+
+            let body = match kind {
+                BodyKind::Dynamic => RigidBodyBuilder::dynamic(),
+                BodyKind::Fixed => RigidBodyBuilder::fixed(),
+            }
+            .position(Isometry::from_parts(
+                vector![translation.x, translation.y, translation.z].into(),
+                UnitQuaternion::from_quaternion(Quaternion::new(
+                    rotation.w, rotation.x, rotation.y, rotation.z,
+                )),
+            ))
+            .build();
+            // Dynamic bodies collide as their convex hull, which is far
+            // cheaper and more robust than a triangle mesh
+            let collider = match kind {
+                BodyKind::Dynamic => ColliderBuilder::convex_hull(&points),
+                BodyKind::Fixed => Some(ColliderBuilder::trimesh(points, triangles)),
+            };
+            let Some(collider) = collider else {
+                let mesh = &scene.meshes[first.mesh];
+                tracing::warn!("Couldn't build a collider for mesh {:?}", mesh.name);
+                continue;
+            };
+
+            let handle = bodies.insert(body);
+            colliders.insert_with_parent(collider.build(), handle, &mut bodies);
+            if kind == BodyKind::Dynamic {
+                dynamic.push((handle, instances[0], scale));
+            }
+        }
+        tracing::info!("Simulating {} dynamic bodies", dynamic.len());
+
+        Some(Self {
+            gravity: vector![0.0, -9.81, 0.0],
+            integration_parameters: IntegrationParameters::default(),
+            pipeline: PhysicsPipeline::new(),
+            islands: IslandManager::new(),
+            broad_phase: DefaultBroadPhase::new(),
+            narrow_phase: NarrowPhase::new(),
+            bodies,
+            colliders,
+            impulse_joints: ImpulseJointSet::new(),
+            multibody_joints: MultibodyJointSet::new(),
+            ccd_solver: CCDSolver::new(),
+            dynamic,
+        })
+    }
+
+    /// Advances the simulation of `scene` by `dt` seconds. Returns true if
+    /// anything moved, in which case the scene and BVH hold the new positions.
+    pub fn step(&mut self, scene: &mut Scene, bvh: &mut InstancedBvh, dt: f32) -> bool {
+        self.integration_parameters.dt = dt.clamp(f32::EPSILON, MAX_STEP);
+        self.pipeline.step(
+            &self.gravity,
+            &self.integration_parameters,
+            &mut self.islands,
+            &mut self.broad_phase,
+            &mut self.narrow_phase,
+            &mut self.bodies,
+            &mut self.colliders,
+            &mut self.impulse_joints,
+            &mut self.multibody_joints,
+            &mut self.ccd_solver,
+            None,
+            &(),
+            &(),
+        );
+
+        let mut moved = false;
+        for &(handle, instance, scale) in &self.dynamic {
+            let body = &self.bodies[handle];
+            if body.is_sleeping() {
+                continue;
+            }
+            let position = body.position();
+            let t = position.translation.vector;
+            let r = position.rotation;
+            let world = Mat4::from_scale_rotation_translation(
+                scale,
+                Quat::from_xyzw(r.i, r.j, r.k, r.w),
+                Vec3::new(t.x, t.y, t.z),
+            );
+            scene.set_instance_transform(instance, world);
+            moved = true;
+        }
+        if moved {
+            bvh.refit(scene);
+        }
+        moved
+    }
+}
@@ -0,0 +1,103 @@
+//! Named bundles of render settings, switched with the 1-3 keys or
+//! `--preset final`, so going from navigating a scene to a final image
+//! doesn't take a dozen separate changes. Scene files can also carry the
+//! settings they're meant to be rendered with.
+
+use crate::{sampler::SamplerKind, tracer::TraceSettings, DenoiseMode, Settings};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preset {
+    /// Quick feedback while setting up a shot.
+    Draft,
+    Medium,
+    /// Converged, fully detailed image.
+    Final,
+}
+
+impl Preset {
+    pub const ALL: [Self; 3] = [Self::Draft, Self::Medium, Self::Final];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|preset| preset.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Draft => "draft",
+            Self::Medium => "medium",
+            Self::Final => "final",
+        }
+    }
+
+    /// Changes the settings, leaving it to the caller to start the image
+    /// over.
+    pub fn apply(self, settings: &mut Settings, trace: &mut TraceSettings) {
+        let (resolution_scale, max_samples, max_depth, denoise) = match self {
+            Self::Draft => (0.5, 64, 4, DenoiseMode::Svgf),
+            Self::Medium => (1.0, 256, 8, DenoiseMode::Svgf),
+            #[cfg(feature = "oidn")]
+            Self::Final => (1.0, 4096, 16, DenoiseMode::Oidn),
+            #[cfg(not(feature = "oidn"))]
+            Self::Final => (1.0, 4096, 16, DenoiseMode::Off),
+        };
+        settings.resolution_scale = resolution_scale;
+        settings.max_samples = max_samples;
+        settings.denoise = denoise;
+        trace.max_depth = max_depth;
+    }
+}
+
+/// Render settings recommended by a scene file, applied when it's opened
+/// unless the command line says otherwise. Unset fields keep the defaults.
+#[derive(Clone, Debug, Default)]
+pub struct RenderOverrides {
+    /// Applied before the individual settings below.
+    pub preset: Option<Preset>,
+    pub resolution_scale: Option<f32>,
+    pub samples: Option<u32>,
+    pub max_depth: Option<u32>,
+    pub spectral: Option<bool>,
+    pub denoise: Option<DenoiseMode>,
+    pub sampler: Option<SamplerKind>,
+}
+
+impl RenderOverrides {
+    /// These settings, falling back to `defaults` for the unset ones.
+    pub fn or(&self, defaults: &Self) -> Self {
+        Self {
+            preset: self.preset.or(defaults.preset),
+            resolution_scale: self.resolution_scale.or(defaults.resolution_scale),
+            samples: self.samples.or(defaults.samples),
+            max_depth: self.max_depth.or(defaults.max_depth),
+            spectral: self.spectral.or(defaults.spectral),
+            denoise: self.denoise.or(defaults.denoise),
+            sampler: self.sampler.or(defaults.sampler),
+        }
+    }
+
+    pub fn apply(&self, settings: &mut Settings, trace: &mut TraceSettings) {
+        if let Some(preset) = self.preset {
+            preset.apply(settings, trace);
+        }
+        if let Some(resolution_scale) = self.resolution_scale {
+            settings.resolution_scale = resolution_scale;
+        }
+        if let Some(samples) = self.samples {
+            settings.max_samples = samples;
+        }
+        if let Some(max_depth) = self.max_depth {
+            trace.max_depth = max_depth;
+        }
+        if let Some(spectral) = self.spectral {
+            trace.spectral = spectral;
+        }
+        if let Some(denoise) = self.denoise {
+            settings.denoise = denoise;
+        }
+        if let Some(sampler) = self.sampler {
+            trace.sampler = sampler;
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! Copies from the GPU back to the CPU, such as screenshots and the depth
+//! under the cursor. Requests made during a frame have their copies
+//! recorded into one encoder, which is submitted with the frame, and are
+//! handed their data once it has been mapped, as `poll` finds them done,
+//! so the event loop never waits on the GPU for them. Requests are handed
+//! their data in the order they were made, so one can rely on what an
+//! earlier one read.
+
+use std::{
+    collections::VecDeque,
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+use glam::UVec2;
+
+/// What a finished copy's bytes are handed to, on the thread that polls.
+type Done = Box<dyn FnOnce(&[u8])>;
+
+struct Request {
+    /// None for a request made with `then`, which copies nothing.
+    buffer: Option<wgpu::Buffer>,
+    /// How mapping the buffer went, once it's done.
+    mapped: Option<Result<(), wgpu::BufferAsyncError>>,
+    done: Done,
+}
+
+pub struct Readbacks {
+    /// Copies recorded since the last submission.
+    encoder: Option<wgpu::CommandEncoder>,
+    /// Requests whose copies are in `encoder`.
+    recorded: Vec<Request>,
+    /// Submitted requests waiting on their buffer or an earlier request to
+    /// map, by id.
+    mapping: VecDeque<(u64, Request)>,
+    next_id: u64,
+    /// Ids and results of the buffers that finished mapping, sent from the
+    /// map callbacks, which may run on any thread.
+    sender: Sender<(u64, Result<(), wgpu::BufferAsyncError>)>,
+    receiver: Receiver<(u64, Result<(), wgpu::BufferAsyncError>)>,
+}
+
+impl Default for Readbacks {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            encoder: None,
+            recorded: Vec::new(),
+            mapping: VecDeque::new(),
+            next_id: 0,
+            sender,
+            receiver,
+        }
+    }
+}
+
+impl Readbacks {
+    /// Copies layer `layer` of an Rgba32Float texture back, handing it to
+    /// `done` once it arrives.
+    pub fn read_texture(
+        &mut self,
+        device: &wgpu::Device,
+        texture: &wgpu::Texture,
+        layer: u32,
+        done: impl FnOnce(image::Rgba32FImage) + 'static,
+    ) {
+        let size = wgpu::Extent3d {
+            depth_or_array_layers: 1,
+            ..texture.size()
+        };
+        // Rows of a copy have to be aligned
+        let row_bytes = size.width * 16;
+        let padded_row_bytes = row_bytes.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = buffer(
+            device,
+            "Readback Buffer",
+            (padded_row_bytes * size.height) as u64,
+        );
+        self.encoder(device).copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                origin: wgpu::Origin3d {
+                    z: layer,
+                    ..Default::default()
+                },
+                ..texture.as_image_copy()
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
+        self.recorded.push(Request {
+            buffer: Some(buffer),
+            mapped: None,
+            done: Box::new(move |bytes| {
+                let pixels = bytes
+                    .chunks_exact(padded_row_bytes as usize)
+                    .flat_map(|row| {
+                        bytemuck::cast_slice::<u8, f32>(&row[..row_bytes as usize]).to_vec()
+                    })
+                    .collect();
+                done(
+                    image::Rgba32FImage::from_raw(size.width, size.height, pixels)
+                        .expect("readback matches the texture size"),
+                );
+            }),
+        });
+    }
+
+    /// Copies the texel at `pixel` of an Rgba32Float texture back, handing
+    /// it to `done` once it arrives.
+    pub fn read_texel(
+        &mut self,
+        device: &wgpu::Device,
+        texture: &wgpu::Texture,
+        pixel: UVec2,
+        done: impl FnOnce([f32; 4]) + 'static,
+    ) {
+        let buffer = buffer(device, "Texel Readback Buffer", 16);
+        self.encoder(device).copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                origin: wgpu::Origin3d {
+                    x: pixel.x,
+                    y: pixel.y,
+                    z: 0,
+                },
+                ..texture.as_image_copy()
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout::default(),
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.recorded.push(Request {
+            buffer: Some(buffer),
+            mapped: None,
+            done: Box::new(move |bytes| done(bytemuck::pod_read_unaligned(bytes))),
+        });
+    }
+
+    /// Copies `size` bytes from `offset` of a buffer back, handing them to
+    /// `done` once they arrive. Both have to be multiples of 4.
+    pub fn read_buffer(
+        &mut self,
+        device: &wgpu::Device,
+        source: &wgpu::Buffer,
+        offset: u64,
+        size: u64,
+        done: impl FnOnce(&[u8]) + 'static,
+    ) {
+        let buffer = buffer(device, "Buffer Readback Buffer", size);
+        self.encoder(device)
+            .copy_buffer_to_buffer(source, offset, &buffer, 0, size);
+        self.recorded.push(Request {
+            buffer: Some(buffer),
+            mapped: None,
+            done: Box::new(done),
+        });
+    }
+
+    /// Calls `done` once every request made before has been handed its
+    /// data, to put together what several of them read.
+    pub fn then(&mut self, done: impl FnOnce() + 'static) {
+        let request = Request {
+            buffer: None,
+            mapped: Some(Ok(())),
+            done: Box::new(move |_| done()),
+        };
+        match self.encoder {
+            Some(_) => self.recorded.push(request),
+            None => {
+                self.mapping.push_back((self.next_id, request));
+                self.next_id += 1;
+            }
+        }
+    }
+
+    /// Whether every request has been handed its data.
+    pub fn is_idle(&self) -> bool {
+        self.recorded.is_empty() && self.mapping.is_empty()
+    }
+
+    /// Submits the copies recorded since the last call, after what the
+    /// queue was given before, and starts mapping their buffers.
+    pub fn submit(&mut self, queue: &wgpu::Queue) {
+        let Some(encoder) = self.encoder.take() else {
+            return;
+        };
+        queue.submit(std::iter::once(encoder.finish()));
+        for request in self.recorded.drain(..) {
+            let (id, sender) = (self.next_id, self.sender.clone());
+            self.next_id += 1;
+            if let Some(buffer) = &request.buffer {
+                buffer
+                    .slice(..)
+                    .map_async(wgpu::MapMode::Read, move |result| {
+                        let _ = sender.send((id, result));
+                    });
+            }
+            self.mapping.push_back((id, request));
+        }
+    }
+
+    /// Hands the requests whose buffers have mapped their data, without
+    /// waiting for the others.
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        if self.mapping.is_empty() {
+            return;
+        }
+        device.poll(wgpu::Maintain::Poll);
+        self.finish_mapped();
+    }
+
+    fn finish_mapped(&mut self) {
+        while let Ok((id, result)) = self.receiver.try_recv() {
+            if let Some((_, request)) = self.mapping.iter_mut().find(|(other, _)| *other == id) {
+                request.mapped = Some(result);
+            }
+        }
+        while self
+            .mapping
+            .front()
+            .is_some_and(|(_, request)| request.mapped.is_some())
+        {
+            let (_, request) = self.mapping.pop_front().expect("front was mapped");
+            if let Some(Err(err)) = request.mapped {
+                tracing::error!("Failed to read back from the GPU: {err}");
+                continue;
+            }
+            let Some(buffer) = request.buffer else {
+                (request.done)(&[]);
+                continue;
+            };
+            let bytes = buffer.slice(..).get_mapped_range().to_vec();
+            buffer.unmap();
+            (request.done)(&bytes);
+        }
+    }
+
+    fn encoder(&mut self, device: &wgpu::Device) -> &mut wgpu::CommandEncoder {
+        self.encoder.get_or_insert_with(|| {
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            })
+        })
+    }
+}
+
+/// Buffer a copy back from the GPU goes into.
+fn buffer(device: &wgpu::Device, label: &str, size: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    })
+}
@@ -0,0 +1,347 @@
+//! Sequences that drive the random decisions along a path. The trace
+//! shader implements each of them, picked with `SamplerKind`, and the same
+//! sequences are mirrored here so they can be compared without rendering,
+//! see `benchmark` and `spectrum samplers`.
+
+use std::{fmt, sync::OnceLock};
+
+use glam::{UVec2, Vec2};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Strata along each axis of the stratified sampler, matched in the shader.
+pub const STRATA: u32 = 16;
+/// Length of the PMJ02 table, matched in the shader.
+pub const PMJ02_SAMPLES: u32 = 4096;
+
+/// How samples are drawn, matched by value in the trace shader.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum SamplerKind {
+    /// Uncorrelated random numbers, the baseline the others improve on.
+    Independent = 0,
+    /// Jittered cells of a fixed grid, only stratified once all of them
+    /// are used.
+    Stratified = 1,
+    /// Owen-scrambled Sobol, starting out with blue noise.
+    #[default]
+    Sobol = 2,
+    /// Progressive multi-jittered (0, 2) sequence from a table.
+    Pmj02 = 3,
+}
+
+impl SamplerKind {
+    pub const ALL: [Self; 4] = [
+        Self::Independent,
+        Self::Stratified,
+        Self::Sobol,
+        Self::Pmj02,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Independent => "independent",
+            Self::Stratified => "stratified",
+            Self::Sobol => "sobol",
+            Self::Pmj02 => "pmj02",
+        }
+    }
+
+    /// CPU version of the shader's sampler.
+    pub fn sampler(self) -> Box<dyn Sampler> {
+        match self {
+            Self::Independent => Box::new(Independent),
+            Self::Stratified => Box::new(Stratified),
+            Self::Sobol => Box::new(Sobol),
+            Self::Pmj02 => Box::new(Pmj02::new()),
+        }
+    }
+}
+
+pub trait Sampler: Send + Sync {
+    /// Two dimensions in [0, 1) of sample `index` of the pixel with `seed`,
+    /// `dimension` counting the pairs the path drew before. Matches
+    /// `rand2` in trace.wgsl.
+    fn sample(&self, seed: u32, index: u32, dimension: u32) -> Vec2;
+}
+
+/// Seed of a pixel, as the shader derives it.
+pub fn pixel_seed(pixel: UVec2, width: u32) -> u32 {
+    pcg_hash(pixel.x.wrapping_add(pixel.y.wrapping_mul(width)))
+}
+
+pub struct Independent;
+
+impl Sampler for Independent {
+    fn sample(&self, seed: u32, index: u32, dimension: u32) -> Vec2 {
+        independent(dimension_seed(seed, dimension), index)
+    }
+}
+
+pub struct Stratified;
+
+impl Sampler for Stratified {
+    fn sample(&self, seed: u32, index: u32, dimension: u32) -> Vec2 {
+        let cells = STRATA * STRATA;
+        // Every round visits the cells in a different order
+        let hash = pcg_hash(dimension_seed(seed, dimension) ^ pcg_hash(index / cells));
+        let cell = (index % cells)
+            .wrapping_mul(hash | 1)
+            .wrapping_add(hash >> 16)
+            % cells;
+        let corner = Vec2::new((cell % STRATA) as f32, (cell / STRATA) as f32);
+        (corner + independent(hash, index)) / STRATA as f32
+    }
+}
+
+/// The shader also shifts the first few samples by blue noise, which is
+/// left out here.
+pub struct Sobol;
+
+impl Sampler for Sobol {
+    fn sample(&self, seed: u32, index: u32, dimension: u32) -> Vec2 {
+        let seed = dimension_seed(seed, dimension);
+        // Shuffling the index decorrelates the dimensions
+        let index = owen_scramble(index, seed);
+        Vec2::new(
+            to_unit_float(owen_scramble(index.reverse_bits(), pcg_hash(seed))),
+            to_unit_float(owen_scramble(
+                sobol_1(index),
+                pcg_hash(seed.wrapping_add(1)),
+            )),
+        )
+    }
+}
+
+/// Points of Christensen et al. 2018, "Progressive Multi-Jittered Sample
+/// Sequences", in 32-bit fixed point. Every power of two prefix is a
+/// (0, m, 2)-net, and so is every aligned block of one, which lets pixels
+/// and dimensions start from different blocks. Random digit scrambling
+/// keeps that too.
+pub struct Pmj02 {
+    pub points: &'static [[u32; 2]],
+}
+
+impl Pmj02 {
+    /// Generates the table the first time, which takes a moment.
+    pub fn new() -> Self {
+        static POINTS: OnceLock<Vec<[u32; 2]>> = OnceLock::new();
+        let points = POINTS.get_or_init(|| {
+            let mut rng = StdRng::seed_from_u64(0x706d_6a30);
+            let mut points = vec![[rng.gen(), rng.gen()]];
+            while points.len() < PMJ02_SAMPLES as usize {
+                extend_pmj02(&mut points, &mut rng);
+            }
+            points
+        });
+        Self { points }
+    }
+}
+
+impl Default for Pmj02 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sampler for Pmj02 {
+    fn sample(&self, seed: u32, index: u32, dimension: u32) -> Vec2 {
+        let hash = pcg_hash(dimension_seed(seed, dimension) ^ pcg_hash(index / PMJ02_SAMPLES));
+        let [x, y] = self.points[((index ^ hash) % PMJ02_SAMPLES) as usize];
+        Vec2::new(
+            to_unit_float(x ^ pcg_hash(hash)),
+            to_unit_float(y ^ pcg_hash(hash.wrapping_add(1))),
+        )
+    }
+}
+
+/// Doubles the points, putting each new one in the subquadrant opposite an
+/// old one and in elementary intervals no other point takes.
+fn extend_pmj02(points: &mut Vec<[u32; 2]>, rng: &mut StdRng) {
+    let n = points.len();
+    let total = 2 * n;
+    let m = total.trailing_zeros();
+    // Elementary intervals of every shape, 2^a columns by 2^(m - a) rows
+    let mut occupied = vec![vec![false; total]; m as usize + 1];
+    let interval = |[x, y]: [u32; 2], a: u32| {
+        let column = if a == 0 { 0 } else { x >> (32 - a) };
+        let row = if a == m { 0 } else { y >> (32 - (m - a)) };
+        ((row << a) | column) as usize
+    };
+    let occupy = |occupied: &mut [Vec<bool>], point| {
+        for a in 0..=m {
+            occupied[a as usize][interval(point, a)] = true;
+        }
+    };
+    for &point in points.iter() {
+        occupy(&mut occupied, point);
+    }
+    // Old points pair up in cells when n is not a power of four, and the
+    // pair's free subquadrants are across from both in x
+    let even = n.trailing_zeros().is_multiple_of(2);
+    let bits = n.trailing_zeros() / 2 + 1;
+    // Finest strata within a subquadrant along each axis
+    let strata = 1 << (m - bits);
+    let mut candidates = Vec::new();
+    for j in 0..n {
+        let [x, y] = points[j];
+        let quadrant = |value: u32| value >> (32 - bits);
+        let (qx, qy) = if even {
+            (quadrant(x) ^ 1, quadrant(y) ^ 1)
+        } else {
+            (quadrant(x) ^ 1, quadrant(y))
+        };
+        // Only the strata matter, the rest of the digits are jitter
+        let to_point = |sx: u32, sy: u32| {
+            let offset = |q: u32, s: u32| ((q << (m - bits)) | s) << (32 - m);
+            [offset(qx, sx), offset(qy, sy)]
+        };
+        candidates.clear();
+        for sx in 0..strata {
+            for sy in 0..strata {
+                let point = to_point(sx, sy);
+                if (0..=m).all(|a| !occupied[a as usize][interval(point, a)]) {
+                    candidates.push(point);
+                }
+            }
+        }
+        let [left, bottom] = match candidates.as_slice() {
+            [] => to_point(rng.gen_range(0..strata), rng.gen_range(0..strata)),
+            candidates => candidates[rng.gen_range(0..candidates.len())],
+        };
+        let jitter = u32::MAX >> m;
+        let point = [
+            left | (rng.gen::<u32>() & jitter),
+            bottom | (rng.gen::<u32>() & jitter),
+        ];
+        occupy(&mut occupied, point);
+        points.push(point);
+    }
+}
+
+/// Root mean square error of integrating test functions over a unit square
+/// with each sampler, across many pixels.
+#[derive(Clone, Debug)]
+pub struct Benchmark {
+    pub sample_counts: Vec<u32>,
+    /// Errors per sampler and test function, one per sample count.
+    pub errors: Vec<(SamplerKind, &'static str, Vec<f64>)>,
+}
+
+/// Function of a point in the unit square.
+type Integrand = fn(Vec2) -> f64;
+
+/// Test functions with their exact integrals over the unit square.
+const INTEGRANDS: [(&str, Integrand, f64); 3] = [
+    (
+        "disk",
+        |p| (p.length_squared() < 1.0) as u32 as f64,
+        std::f64::consts::FRAC_PI_4,
+    ),
+    (
+        "gaussian",
+        |p| (-p.length_squared() as f64).exp(),
+        0.557_746_285_351_033_5,
+    ),
+    ("bilinear", |p| (p.x * p.y) as f64, 0.25),
+];
+
+/// Compares every sampler on `pixels` pixels, in a later pair of
+/// dimensions than the camera's as paths would use them.
+pub fn benchmark(sample_counts: &[u32], pixels: u32) -> Benchmark {
+    const DIMENSION: u32 = 3;
+    let mut errors = Vec::new();
+    for kind in SamplerKind::ALL {
+        let sampler = kind.sampler();
+        for (name, f, exact) in INTEGRANDS {
+            let rms = sample_counts
+                .iter()
+                .map(|&count| {
+                    let squared: f64 = (0..pixels)
+                        .map(|pixel| {
+                            let seed = pcg_hash(pixel);
+                            let sum: f64 = (0..count)
+                                .map(|index| f(sampler.sample(seed, index, DIMENSION)))
+                                .sum();
+                            (sum / count as f64 - exact).powi(2)
+                        })
+                        .sum();
+                    (squared / pixels as f64).sqrt()
+                })
+                .collect();
+            errors.push((kind, name, rms));
+        }
+    }
+    Benchmark {
+        sample_counts: sample_counts.to_vec(),
+        errors,
+    }
+}
+
+impl fmt::Display for Benchmark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<12}{:<10}", "Sampler", "Integrand")?;
+        for count in &self.sample_counts {
+            write!(f, "{:>12}", format!("{count} spp"))?;
+        }
+        for (kind, integrand, errors) in &self.errors {
+            write!(f, "\n{:<12}{:<10}", kind.name(), integrand)?;
+            for error in errors {
+                write!(f, "{error:>12.2e}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn independent(seed: u32, index: u32) -> Vec2 {
+    let hash = pcg_hash(seed ^ pcg_hash(index));
+    Vec2::new(to_unit_float(hash), to_unit_float(pcg_hash(hash)))
+}
+
+fn dimension_seed(seed: u32, dimension: u32) -> u32 {
+    pcg_hash(seed ^ pcg_hash(dimension))
+}
+
+fn pcg_hash(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+/// Second Sobol dimension, whose direction numbers follow from the first.
+fn sobol_1(index: u32) -> u32 {
+    let mut result = 0;
+    let mut direction = 0x8000_0000u32;
+    let mut i = index;
+    while i != 0 {
+        if i & 1 != 0 {
+            result ^= direction;
+        }
+        direction ^= direction >> 1;
+        i >>= 1;
+    }
+    result
+}
+
+fn laine_karras_permutation(value: u32, seed: u32) -> u32 {
+    let mut x = value.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn owen_scramble(value: u32, seed: u32) -> u32 {
+    laine_karras_permutation(value.reverse_bits(), seed).reverse_bits()
+}
+
+fn to_unit_float(value: u32) -> f32 {
+    (value >> 8) as f32 / 16_777_216.0
+}
@@ -0,0 +1,710 @@
+use std::path::Path;
+
+use anyhow::Result;
+use glam::{BVec3, Mat4, Vec2, Vec3, Vec4};
+
+use crate::{
+    audio::{AudioBinding, AudioTarget},
+    bvh::Aabb,
+    import::ImportOptions,
+    ocean::Ocean,
+    preset::RenderOverrides,
+    spectral::{Conductor, Subsurface, ThinFilm},
+    texture::Texture,
+};
+
+mod animation;
+mod graph;
+mod primitive;
+mod sdf;
+mod volume;
+
+pub use animation::{Animation, Channel, Interpolation, Keys};
+pub use graph::Node;
+pub use primitive::{Primitive, Shape};
+pub use sdf::{Sdf, SdfOp, SdfShape};
+pub use volume::Volume;
+
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub name: String,
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<Vec2>,
+    /// Direction of increasing U in xyz and the sign of the bitangent in w,
+    /// for normal mapping. Empty to derive them from the UVs per triangle.
+    pub tangents: Vec<Vec4>,
+    pub indices: Vec<u32>,
+    /// Simplified index buffers sharing the vertices above, coarsest last.
+    pub lods: Vec<Vec<u32>>,
+}
+
+impl Mesh {
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn triangle(&self, i: usize) -> [u32; 3] {
+        [
+            self.indices[3 * i],
+            self.indices[3 * i + 1],
+            self.indices[3 * i + 2],
+        ]
+    }
+
+    /// Number of detail levels, including the full resolution mesh.
+    pub fn lod_count(&self) -> usize {
+        1 + self.lods.len()
+    }
+
+    pub fn lod_indices(&self, level: usize) -> &[u32] {
+        match level {
+            0 => &self.indices,
+            _ => &self.lods[level - 1],
+        }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.positions.iter().fold(Aabb::EMPTY, |mut aabb, &p| {
+            aabb.grow(p);
+            aabb
+        })
+    }
+
+    /// Area weighted smooth vertex normals.
+    pub fn compute_normals(&mut self) {
+        let mut normals = vec![Vec3::ZERO; self.positions.len()];
+        for i in 0..self.triangle_count() {
+            let [a, b, c] = self.triangle(i).map(|v| v as usize);
+            let (p0, p1, p2) = (self.positions[a], self.positions[b], self.positions[c]);
+            // Not normalized, so larger triangles contribute more
+            let n = (p1 - p0).cross(p2 - p0);
+            normals[a] += n;
+            normals[b] += n;
+            normals[c] += n;
+        }
+        self.normals = normals
+            .into_iter()
+            .map(|n| n.try_normalize().unwrap_or(Vec3::Y))
+            .collect();
+    }
+
+    /// Smooth tangents following the UVs, accumulated from the UV
+    /// derivatives of the triangles around each vertex and made orthogonal
+    /// to its normal. Mirrored UVs flip the bitangent sign.
+    pub fn compute_tangents(&mut self) {
+        let mut tangents = vec![Vec3::ZERO; self.positions.len()];
+        let mut bitangents = vec![Vec3::ZERO; self.positions.len()];
+        for i in 0..self.triangle_count() {
+            let [a, b, c] = self.triangle(i).map(|v| v as usize);
+            let (e1, e2) = (
+                self.positions[b] - self.positions[a],
+                self.positions[c] - self.positions[a],
+            );
+            let (duv1, duv2) = (self.uvs[b] - self.uvs[a], self.uvs[c] - self.uvs[a]);
+            let det = duv1.perp_dot(duv2);
+            if det.abs() < 1e-12 {
+                continue;
+            }
+            // Scaled by the triangle area like the normals
+            let area = e1.cross(e2).length();
+            let t = (e1 * duv2.y - e2 * duv1.y) * (area / det);
+            let bt = (e2 * duv1.x - e1 * duv2.x) * (area / det);
+            for v in [a, b, c] {
+                tangents[v] += t;
+                bitangents[v] += bt;
+            }
+        }
+        self.tangents = (0..self.positions.len())
+            .map(|v| {
+                let n = self.normals[v];
+                let t = tangents[v] - n * n.dot(tangents[v]);
+                match t.try_normalize() {
+                    Some(t) => {
+                        let sign = if n.cross(t).dot(bitangents[v]) < 0.0 {
+                            -1.0
+                        } else {
+                            1.0
+                        };
+                        t.extend(sign)
+                    }
+                    None => Vec4::ZERO,
+                }
+            })
+            .collect();
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name: String,
+    pub base_color: Vec4,
+    pub base_color_texture: Option<usize>,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Tangent space normal map, its XY scaled by `normal_scale`.
+    pub normal_texture: Option<usize>,
+    pub normal_scale: f32,
+    /// Height map displacing the shading normal, white being `bump_scale`
+    /// world units above black.
+    pub bump_texture: Option<usize>,
+    pub bump_scale: f32,
+    /// Roughness in the green and metallic in the blue channel, scaling
+    /// the factors above.
+    pub metallic_roughness_texture: Option<usize>,
+    pub emission: Vec3,
+    pub emission_texture: Option<usize>,
+    /// Emits a blackbody spectrum of this temperature in kelvin instead, as
+    /// bright as the luminance of `emission`.
+    pub temperature: Option<f32>,
+    /// Fraction of light transmitted through a dielectric instead of
+    /// hitting the opaque base.
+    pub transmission: f32,
+    pub ior: f32,
+    /// Treats the surface as an infinitely thin sheet, such as window glass
+    /// modeled as a single plane, so transmitted rays pass straight through
+    /// instead of refracting into a volume.
+    pub thin_walled: bool,
+    /// Stretches the specular highlight along the tangent, from 0 to 1.
+    pub anisotropy: f32,
+    /// Rotation of the anisotropy direction from the UV tangent in radians.
+    pub anisotropy_rotation: f32,
+    /// Strength of a clear dielectric coat layered over the material.
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    /// Normal map of the coat, separate from the base so that the coat can
+    /// have its own surface structure such as orange peel.
+    pub clearcoat_normal_texture: Option<usize>,
+    pub clearcoat_normal_scale: f32,
+    /// Color of the retroreflective sheen layer of cloth, black disables it.
+    pub sheen_color: Vec3,
+    pub sheen_roughness: f32,
+    /// Fraction of the base covered by metallic flakes under the coat.
+    pub flake_coverage: f32,
+    /// Size of one flake in world units.
+    pub flake_size: f32,
+    /// How far the flakes tilt away from the surface, 0 lies flat.
+    pub flake_roughness: f32,
+    /// Replaces `ior` per wavelength when rendering spectrally, splitting
+    /// transmitted light into its colors.
+    pub dispersion: Option<Dispersion>,
+    /// Measured metal whose spectral reflectance replaces the color given
+    /// by `base_color` and `metallic`.
+    pub conductor: Option<Conductor>,
+    /// Iridescent coating, on a thin walled transmissive material it is the
+    /// whole sheet like a soap bubble.
+    pub thin_film: Option<ThinFilm>,
+    /// Medium under the surface that the light of the diffuse base enters
+    /// and scatters through, instead of reflecting right where it landed.
+    pub subsurface: Option<Subsurface>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            name: String::from("default"),
+            base_color: Vec4::new(0.8, 0.8, 0.8, 1.0),
+            base_color_texture: None,
+            metallic: 0.0,
+            roughness: 0.5,
+            normal_texture: None,
+            normal_scale: 1.0,
+            bump_texture: None,
+            bump_scale: 0.01,
+            metallic_roughness_texture: None,
+            emission: Vec3::ZERO,
+            emission_texture: None,
+            temperature: None,
+            transmission: 0.0,
+            ior: 1.5,
+            thin_walled: false,
+            anisotropy: 0.0,
+            anisotropy_rotation: 0.0,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.0,
+            clearcoat_normal_texture: None,
+            clearcoat_normal_scale: 1.0,
+            sheen_color: Vec3::ZERO,
+            sheen_roughness: 0.0,
+            flake_coverage: 0.0,
+            flake_size: 0.001,
+            flake_roughness: 0.3,
+            dispersion: None,
+            conductor: None,
+            thin_film: None,
+            subsurface: None,
+        }
+    }
+}
+
+impl Material {
+    /// Metallic car paint: a glossy colored base sprinkled with tilted
+    /// flakes that sparkle, under a smooth clearcoat.
+    pub fn car_paint(color: Vec3) -> Self {
+        Self {
+            name: String::from("car paint"),
+            base_color: color.extend(1.0),
+            roughness: 0.4,
+            clearcoat: 1.0,
+            clearcoat_roughness: 0.03,
+            flake_coverage: 0.4,
+            ..Default::default()
+        }
+    }
+
+    /// Smooth clear glass that refracts each wavelength by its own index.
+    pub fn dielectric(dispersion: Dispersion) -> Self {
+        Self {
+            name: String::from("dielectric"),
+            base_color: Vec4::ONE,
+            roughness: 0.0,
+            transmission: 1.0,
+            ior: dispersion.ior(Dispersion::D_LINE),
+            dispersion: Some(dispersion),
+            ..Default::default()
+        }
+    }
+
+    /// Polished metal colored by its measured index of refraction.
+    pub fn metal(conductor: Conductor) -> Self {
+        Self {
+            name: format!("{conductor:?}").to_lowercase(),
+            base_color: Vec4::ONE,
+            metallic: 1.0,
+            roughness: 0.1,
+            conductor: Some(conductor),
+            ..Default::default()
+        }
+    }
+
+    /// Soap film of the given thickness in nanometers.
+    pub fn soap_bubble(thickness: f32) -> Self {
+        Self {
+            name: String::from("soap bubble"),
+            base_color: Vec4::ONE,
+            roughness: 0.0,
+            transmission: 1.0,
+            ior: 1.33,
+            thin_walled: true,
+            thin_film: Some(ThinFilm {
+                thickness,
+                ior: 1.33,
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Clear water with IOR 1.333 and a slight blue green tint.
+    pub fn water() -> Self {
+        Self {
+            name: String::from("water"),
+            base_color: Vec4::new(0.8, 0.95, 0.95, 1.0),
+            roughness: 0.02,
+            transmission: 1.0,
+            ior: 1.333,
+            ..Default::default()
+        }
+    }
+
+    /// Light skin with a soft sheen of oil, for scenes in meters.
+    pub fn skin() -> Self {
+        Self::translucent("skin", 0.4, 1.4, Subsurface::skin())
+    }
+
+    /// Candle wax, for scenes in meters.
+    pub fn wax() -> Self {
+        Self::translucent("wax", 0.3, 1.45, Subsurface::wax())
+    }
+
+    /// Polished white marble, for scenes in meters.
+    pub fn marble() -> Self {
+        Self::translucent("marble", 0.1, 1.5, Subsurface::marble())
+    }
+
+    fn translucent(name: &str, roughness: f32, ior: f32, subsurface: Subsurface) -> Self {
+        Self {
+            name: String::from(name),
+            base_color: Vec4::ONE,
+            roughness,
+            ior,
+            subsurface: Some(subsurface),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emission.max_element() > 0.0
+    }
+}
+
+/// Index of refraction as a function of the wavelength in micrometers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dispersion {
+    /// `n = a + b / λ²`
+    Cauchy { a: f32, b: f32 },
+    /// `n² = 1 + Σ bᵢ λ² / (λ² - cᵢ)`
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    /// Fraunhofer d line, where glasses list their IOR.
+    pub const D_LINE: f32 = 0.5876;
+    /// Fraunhofer F and C lines, which define the Abbe number.
+    const F_LINE: f32 = 0.4861;
+    const C_LINE: f32 = 0.6563;
+
+    /// Schott N-BK7, the common optical crown glass.
+    pub const BK7: Self = Self::Sellmeier {
+        b: [1.039_612, 0.231_792_3, 1.010_469_5],
+        c: [0.006_000_699, 0.020_017_914, 103.560_65],
+    };
+
+    /// Cauchy fit of a glass with index `ior` at the d line and Abbe number
+    /// `abbe`, lower numbers disperse more.
+    pub fn from_abbe(ior: f32, abbe: f32) -> Self {
+        let b = (ior - 1.0) / (abbe * (Self::F_LINE.powi(-2) - Self::C_LINE.powi(-2)));
+        Self::Cauchy {
+            a: ior - b * Self::D_LINE.powi(-2),
+            b,
+        }
+    }
+
+    pub fn ior(&self, wavelength: f32) -> f32 {
+        let l2 = wavelength * wavelength;
+        match *self {
+            Self::Cauchy { a, b } => a + b / l2,
+            Self::Sellmeier { b, c } => {
+                (1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f32>()).sqrt()
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Instance {
+    pub mesh: usize,
+    pub material: usize,
+    pub transform: Mat4,
+    /// Detail level of the mesh used when building the BVH.
+    pub lod: usize,
+    /// Rigid body simulated by the `physics` feature.
+    pub body: Option<BodyKind>,
+    /// Cuts a hole to the backdrop, or to zero alpha, where the camera sees
+    /// it. It still casts shadows and shows in reflections, standing in for
+    /// a real object of the plate.
+    pub holdout: bool,
+    /// Semantic class for the class ID AOV, zero for none.
+    pub class: u32,
+}
+
+/// How an instance takes part in the physics simulation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BodyKind {
+    /// Falls and gets pushed around by collisions.
+    Dynamic,
+    /// Collides but never moves.
+    Fixed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    Directional,
+    Point,
+    Spot {
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+    /// Rectangle in the local XY plane, centered on the origin.
+    Quad {
+        width: f32,
+        height: f32,
+    },
+    /// Disk in the local XY plane, centered on the origin.
+    Disk {
+        radius: f32,
+    },
+}
+
+/// Lights shine down their local -Z axis. Area lights emit `color * intensity`
+/// as radiance from their front face only.
+#[derive(Clone, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: Vec3,
+    pub intensity: f32,
+    /// Replaces the color by a blackbody of this temperature in kelvin with
+    /// the same luminance.
+    pub temperature: Option<f32>,
+    pub transform: Mat4,
+}
+
+/// Linear HDR radiance in a latitude-longitude layout with +Y up. The
+/// center of the image looks down -Z.
+#[derive(Clone, Debug, Default)]
+pub struct Environment {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 4]>,
+}
+
+impl Environment {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        crate::import::load_environment(path.as_ref())
+    }
+
+    /// Loads a backdrop plate, decoding the sRGB of 8 and 16-bit images.
+    pub fn load_backdrop(path: impl AsRef<Path>) -> Result<Self> {
+        crate::import::load_backdrop(path.as_ref())
+    }
+
+    /// Halves the resolution with a box filter.
+    pub fn downsample(&self) -> Self {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0.0; 4];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let sx = (2 * x + dx).min(self.width - 1);
+                    let sy = (2 * y + dy).min(self.height - 1);
+                    let pixel = self.pixels[(sy * self.width + sx) as usize];
+                    for (sum, value) in sum.iter_mut().zip(pixel) {
+                        *sum += 0.25 * value;
+                    }
+                }
+                pixels.push(sum);
+            }
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Scene {
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<Material>,
+    pub textures: Vec<Texture>,
+    pub instances: Vec<Instance>,
+    /// Analytic shapes traced alongside the instanced meshes.
+    pub primitives: Vec<Primitive>,
+    /// Distance fields of the `Shape::Sdf` primitives.
+    pub sdfs: Vec<Sdf>,
+    /// Smoke and clouds the paths scatter through.
+    pub volumes: Vec<Volume>,
+    pub lights: Vec<Light>,
+    /// Hierarchy the instances and lights were flattened from, empty for
+    /// formats without one, see `graph`.
+    pub nodes: Vec<Node>,
+    /// Keyframes moving the nodes, played by the timeline.
+    pub animations: Vec<Animation>,
+    pub environment: Option<Environment>,
+    /// Photographic plate filling the camera's view behind the scene. It's
+    /// only seen by camera rays and doesn't light anything. The pixels map
+    /// straight onto the screen rather than a lat-long layout.
+    pub backdrop: Option<Environment>,
+    /// Instance displaced by an animated ocean, and the ocean itself.
+    pub ocean: Option<(usize, Ocean)>,
+    /// Parameters driven by audio input.
+    pub audio_bindings: Vec<AudioBinding>,
+    /// Render settings the scene file asks for.
+    pub render: RenderOverrides,
+}
+
+impl Scene {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::load_with(path, &ImportOptions::default())
+    }
+
+    pub fn load_with(path: impl AsRef<Path>, options: &ImportOptions) -> Result<Self> {
+        crate::import::load(path.as_ref(), options)
+    }
+
+    /// Moves every instance, primitive, light, volume and root node by
+    /// `transform`. With animations, the roots move under a new root
+    /// instead.
+    pub fn transformed(mut self, transform: Mat4) -> Self {
+        if transform != Mat4::IDENTITY {
+            for instance in &mut self.instances {
+                instance.transform = transform * instance.transform;
+            }
+            for primitive in &mut self.primitives {
+                primitive.transform = transform * primitive.transform;
+            }
+            for light in &mut self.lights {
+                light.transform = transform * light.transform;
+            }
+            for volume in &mut self.volumes {
+                volume.transform = transform * volume.transform;
+            }
+            if self.animations.is_empty() {
+                for node in &mut self.nodes {
+                    if node.parent.is_none() {
+                        node.transform = transform * node.transform;
+                    }
+                }
+            } else {
+                // Animations replace the transforms of the nodes they move,
+                // so the roots go under a node of their own instead
+                let roots: Vec<usize> = (0..self.nodes.len())
+                    .filter(|&index| self.nodes[index].parent.is_none())
+                    .collect();
+                let root = self.add_node(String::from("Root"), transform, None);
+                for &index in &roots {
+                    self.nodes[index].parent = Some(root);
+                }
+                self.nodes[root].children = roots;
+            }
+        }
+        self
+    }
+
+    /// Adds everything in `other` to this scene, keeping its own materials
+    /// and textures apart. The environment, backdrop, ocean and render
+    /// settings of this scene win over those of `other`.
+    pub fn merge(&mut self, other: Scene) {
+        let (meshes, materials, textures) =
+            (self.meshes.len(), self.materials.len(), self.textures.len());
+        let (instances, lights, nodes) =
+            (self.instances.len(), self.lights.len(), self.nodes.len());
+        let sdfs = self.sdfs.len();
+
+        self.meshes.extend(other.meshes);
+        self.materials
+            .extend(other.materials.into_iter().map(|mut material| {
+                for texture in [
+                    &mut material.base_color_texture,
+                    &mut material.normal_texture,
+                    &mut material.bump_texture,
+                    &mut material.metallic_roughness_texture,
+                    &mut material.emission_texture,
+                    &mut material.clearcoat_normal_texture,
+                ]
+                .into_iter()
+                .flatten()
+                {
+                    *texture += textures;
+                }
+                material
+            }));
+        self.textures.extend(other.textures);
+        self.instances
+            .extend(other.instances.into_iter().map(|mut instance| {
+                instance.mesh += meshes;
+                instance.material += materials;
+                instance
+            }));
+        self.primitives
+            .extend(other.primitives.into_iter().map(|mut primitive| {
+                primitive.material += materials;
+                if let Shape::Sdf { sdf, .. } = &mut primitive.shape {
+                    *sdf += sdfs;
+                }
+                primitive
+            }));
+        self.sdfs.extend(other.sdfs);
+        self.volumes.extend(other.volumes);
+        self.lights.extend(other.lights);
+        self.nodes.extend(other.nodes.into_iter().map(|mut node| {
+            node.parent = node.parent.map(|parent| parent + nodes);
+            for index in node.children.iter_mut() {
+                *index += nodes;
+            }
+            for index in node.instances.iter_mut() {
+                *index += instances;
+            }
+            for index in node.lights.iter_mut() {
+                *index += lights;
+            }
+            node
+        }));
+        self.animations
+            .extend(other.animations.into_iter().map(|mut animation| {
+                for channel in &mut animation.channels {
+                    channel.node += nodes;
+                }
+                animation
+            }));
+        self.audio_bindings
+            .extend(other.audio_bindings.into_iter().map(|mut binding| {
+                match &mut binding.target {
+                    AudioTarget::LightIntensity { light, .. } => *light += lights,
+                    AudioTarget::Emission { material, .. } => *material += materials,
+                    AudioTarget::Scale { instance, .. } => *instance += instances,
+                }
+                binding
+            }));
+        if self.environment.is_none() {
+            self.environment = other.environment;
+        }
+        if self.backdrop.is_none() {
+            self.backdrop = other.backdrop;
+        }
+        if self.ocean.is_none() {
+            self.ocean = other
+                .ocean
+                .map(|(instance, ocean)| (instance + instances, ocean));
+        }
+    }
+
+    /// Adds a water surface animated by `ocean`.
+    pub fn add_ocean(&mut self, ocean: Ocean) {
+        self.meshes.push(ocean.mesh());
+        self.materials.push(Material::water());
+        self.instances.push(Instance {
+            mesh: self.meshes.len() - 1,
+            material: self.materials.len() - 1,
+            transform: Mat4::IDENTITY,
+            lod: 0,
+            body: None,
+            holdout: false,
+            class: 0,
+        });
+        self.ocean = Some((self.instances.len() - 1, ocean));
+    }
+
+    /// Total number of triangles after instancing.
+    pub fn triangle_count(&self) -> usize {
+        self.instances
+            .iter()
+            .map(|instance| self.meshes[instance.mesh].lod_indices(instance.lod).len() / 3)
+            .sum()
+    }
+
+    /// World space bounds of every instance, bounded primitive and volume,
+    /// leaving out the ocean.
+    pub fn bounds(&self) -> Aabb {
+        let ocean = self.ocean.as_ref().map(|(instance, _)| *instance);
+        let instances = (0..self.instances.len())
+            .filter(|&index| ocean != Some(index))
+            .fold(Aabb::EMPTY, |bounds, index| {
+                bounds.union(&self.instance_bounds(index))
+            });
+        let primitives = self.primitives.iter().fold(instances, |bounds, primitive| {
+            bounds.union(&primitive.bounds())
+        });
+        self.volumes
+            .iter()
+            .fold(primitives, |bounds, volume| bounds.union(&volume.bounds()))
+    }
+
+    /// World space bounds of one instance, from the corners of its mesh
+    /// bounds.
+    pub fn instance_bounds(&self, index: usize) -> Aabb {
+        let instance = &self.instances[index];
+        let mesh = self.meshes[instance.mesh].bounds();
+        let mut bounds = Aabb::EMPTY;
+        if mesh.is_empty() {
+            return bounds;
+        }
+        for corner in 0..8 {
+            let select = BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0);
+            let p = Vec3::select(select, mesh.max, mesh.min);
+            bounds.grow(instance.transform.transform_point3(p));
+        }
+        bounds
+    }
+}
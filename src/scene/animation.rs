@@ -0,0 +1,163 @@
+//! Keyframed animation of nodes, as glTF files carry it. Channels key the
+//! translation, rotation or scale of a node, and `Scene::animate` poses
+//! every animated node at a time of the timeline, flattening the new
+//! transforms into the instances and lights below it.
+
+use std::ops::{Add, Mul};
+
+use glam::{Mat4, Quat, Vec3};
+
+use super::Scene;
+
+/// How a channel moves between its keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Holds every key until the next.
+    Step,
+    #[default]
+    Linear,
+    /// Hermite spline through the keys, with an in and out tangent around
+    /// every value.
+    CubicSpline,
+}
+
+/// The keyed values of a channel, one per key, or three per key with the
+/// tangents of a cubic spline.
+#[derive(Clone, Debug)]
+pub enum Keys {
+    Translation(Vec<Vec3>),
+    Rotation(Vec<Quat>),
+    Scale(Vec<Vec3>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Channel {
+    pub node: usize,
+    /// In seconds from the start of the timeline, increasing.
+    pub times: Vec<f32>,
+    pub keys: Keys,
+    pub interpolation: Interpolation,
+}
+
+impl Channel {
+    /// The value of `values` at `time`, holding the first and last keys
+    /// outside of them. `lerp` blends between keys that are linearly
+    /// interpolated.
+    fn sample<T>(&self, values: &[T], time: f32, lerp: impl Fn(T, T, f32) -> T) -> T
+    where
+        T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+    {
+        let cubic = self.interpolation == Interpolation::CubicSpline;
+        let value = |key: usize| {
+            if cubic {
+                values[3 * key + 1]
+            } else {
+                values[key]
+            }
+        };
+        let next = self.times.partition_point(|&key| key <= time);
+        if next == 0 {
+            return value(0);
+        }
+        if next == self.times.len() {
+            return value(next - 1);
+        }
+        let key = next - 1;
+        let dt = self.times[next] - self.times[key];
+        let t = (time - self.times[key]) / dt;
+        match self.interpolation {
+            Interpolation::Step => value(key),
+            Interpolation::Linear => lerp(value(key), value(next), t),
+            Interpolation::CubicSpline => {
+                let (t2, t3) = (t * t, t * t * t);
+                value(key) * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + values[3 * key + 2] * (dt * (t3 - 2.0 * t2 + t))
+                    + value(next) * (3.0 * t2 - 2.0 * t3)
+                    + values[3 * next] * (dt * (t3 - t2))
+            }
+        }
+    }
+
+    /// Whether there are as many values as the keys and interpolation
+    /// need.
+    pub fn is_valid(&self) -> bool {
+        let values = match &self.keys {
+            Keys::Translation(values) | Keys::Scale(values) => values.len(),
+            Keys::Rotation(values) => values.len(),
+        };
+        let per_key = if self.interpolation == Interpolation::CubicSpline {
+            3
+        } else {
+            1
+        };
+        !self.times.is_empty() && values == per_key * self.times.len()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Animation {
+    pub name: String,
+    pub channels: Vec<Channel>,
+}
+
+impl Animation {
+    /// Time of the last key.
+    pub fn duration(&self) -> f32 {
+        self.channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max)
+    }
+}
+
+impl Scene {
+    /// Time of the last key of any animation, zero without any.
+    pub fn animation_duration(&self) -> f32 {
+        self.animations
+            .iter()
+            .map(Animation::duration)
+            .fold(0.0, f32::max)
+    }
+
+    /// Poses every animated node at `time`, returning whether anything
+    /// moved. Properties a node has no channel for keep their values.
+    pub fn animate(&mut self, time: f32) -> bool {
+        // Scale, rotation and translation of every animated node
+        let mut poses: Vec<(usize, Vec3, Quat, Vec3)> = Vec::new();
+        for channel in self
+            .animations
+            .iter()
+            .flat_map(|animation| &animation.channels)
+        {
+            let index = match poses.iter().position(|pose| pose.0 == channel.node) {
+                Some(index) => index,
+                None => {
+                    let (scale, rotation, translation) = self.nodes[channel.node]
+                        .transform
+                        .to_scale_rotation_translation();
+                    poses.push((channel.node, scale, rotation, translation));
+                    poses.len() - 1
+                }
+            };
+            let pose = &mut poses[index];
+            match &channel.keys {
+                Keys::Translation(values) => pose.3 = channel.sample(values, time, Vec3::lerp),
+                Keys::Rotation(values) => {
+                    pose.2 = channel.sample(values, time, Quat::slerp).normalize();
+                }
+                Keys::Scale(values) => pose.1 = channel.sample(values, time, Vec3::lerp),
+            }
+        }
+        let mut moved = false;
+        for (node, scale, rotation, translation) in poses {
+            let transform = Mat4::from_scale_rotation_translation(scale, rotation, translation);
+            // Properties without a channel went through a decomposition,
+            // which only gives them back approximately
+            if !transform.abs_diff_eq(self.nodes[node].transform, 1e-6) {
+                self.set_node_transform(node, transform);
+                moved = true;
+            }
+        }
+        moved
+    }
+}
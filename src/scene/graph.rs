@@ -0,0 +1,94 @@
+//! Hierarchy of the scene as authored. Nodes are placed relative to their
+//! parent and put instances of shared meshes and lights in the world, whose
+//! transforms are the flattened world transforms of their nodes. The
+//! tracers only see the flattened instances.
+
+use glam::Mat4;
+
+use super::Scene;
+
+#[derive(Clone, Debug, Default)]
+pub struct Node {
+    pub name: String,
+    /// Relative to the parent, or to the world for a root.
+    pub transform: Mat4,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// Instances the node places, one per primitive of its mesh.
+    pub instances: Vec<usize>,
+    pub lights: Vec<usize>,
+}
+
+impl Scene {
+    /// Adds an empty node under `parent`, or as a root, returning its index.
+    pub fn add_node(&mut self, name: String, transform: Mat4, parent: Option<usize>) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            name,
+            transform,
+            parent,
+            ..Default::default()
+        });
+        if let Some(parent) = parent {
+            self.nodes[parent].children.push(index);
+        }
+        index
+    }
+
+    /// The transform of `node` from its local space to the world.
+    pub fn world_transform(&self, node: usize) -> Mat4 {
+        let mut transform = self.nodes[node].transform;
+        let mut parent = self.nodes[node].parent;
+        while let Some(index) = parent {
+            transform = self.nodes[index].transform * transform;
+            parent = self.nodes[index].parent;
+        }
+        transform
+    }
+
+    /// Moves `node` relative to its parent, carrying its descendants, and
+    /// flattens the new transforms into their instances and lights.
+    pub fn set_node_transform(&mut self, node: usize, transform: Mat4) {
+        self.nodes[node].transform = transform;
+        let parent = self.nodes[node]
+            .parent
+            .map_or(Mat4::IDENTITY, |parent| self.world_transform(parent));
+        let mut stack = vec![(node, parent)];
+        while let Some((index, parent)) = stack.pop() {
+            let node = &self.nodes[index];
+            let world = parent * node.transform;
+            for &instance in &node.instances {
+                self.instances[instance].transform = world;
+            }
+            for &light in &node.lights {
+                self.lights[light].transform = world;
+            }
+            stack.extend(node.children.iter().map(|&child| (child, world)));
+        }
+    }
+
+    /// Moves `node` to `world`, as `set_node_transform` does.
+    pub fn set_world_transform(&mut self, node: usize, world: Mat4) {
+        let parent = self.nodes[node]
+            .parent
+            .map_or(Mat4::IDENTITY, |parent| self.world_transform(parent));
+        self.set_node_transform(node, parent.inverse() * world);
+    }
+
+    /// The node that places `instance`, if it came from a hierarchy.
+    pub fn node_of_instance(&self, instance: usize) -> Option<usize> {
+        self.nodes
+            .iter()
+            .position(|node| node.instances.contains(&instance))
+    }
+
+    /// Moves `instance` to `world`. An instance from a hierarchy is moved
+    /// through its node, so the node's other primitives and children follow
+    /// and later node updates start from where it ended up.
+    pub fn set_instance_transform(&mut self, instance: usize, world: Mat4) {
+        match self.node_of_instance(instance) {
+            Some(node) => self.set_world_transform(node, world),
+            None => self.instances[instance].transform = world,
+        }
+    }
+}
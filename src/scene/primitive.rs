@@ -0,0 +1,291 @@
+//! Analytic shapes intersected exactly by the tracers instead of being
+//! tessellated, and a small builder for test scenes that need no asset
+//! files. They're tested against every ray outside the BVH, like area
+//! lights, so they suit a handful of large shapes rather than many. Distance
+//! fields are marched instead, see `sdf`.
+
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use glam::{BVec3, Mat4, Quat, Vec3, Vec4};
+
+use super::{Dispersion, Light, LightKind, Material, Scene, Sdf, SdfOp, SdfShape};
+use crate::{bvh::Aabb, spectral::Conductor};
+
+/// Flat shapes lie in the local XZ plane facing +Y, solids are centered on
+/// the origin.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape {
+    Sphere {
+        radius: f32,
+    },
+    /// The whole local XZ plane, which has no bounds.
+    Plane,
+    Box {
+        half_extents: Vec3,
+    },
+    Quad {
+        width: f32,
+        height: f32,
+    },
+    Disk {
+        radius: f32,
+    },
+    /// Distance field `sdf` of the scene's `sdfs`, traced within
+    /// `half_extents`, which must hold all of it.
+    Sdf {
+        sdf: usize,
+        half_extents: Vec3,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct Primitive {
+    pub shape: Shape,
+    pub material: usize,
+    pub transform: Mat4,
+    /// Semantic class for the class ID AOV, zero for none.
+    pub class: u32,
+}
+
+impl Primitive {
+    /// World space bounds, empty for the unbounded plane.
+    pub fn bounds(&self) -> Aabb {
+        let half_extents = match self.shape {
+            Shape::Sphere { radius } => Vec3::splat(radius),
+            Shape::Plane => return Aabb::EMPTY,
+            Shape::Box { half_extents } => half_extents,
+            Shape::Quad { width, height } => Vec3::new(0.5 * width, 0.0, 0.5 * height),
+            Shape::Disk { radius } => Vec3::new(radius, 0.0, radius),
+            Shape::Sdf { half_extents, .. } => half_extents,
+        };
+        let mut bounds = Aabb::EMPTY;
+        for corner in 0..8 {
+            let select = BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0);
+            let p = Vec3::select(select, half_extents, -half_extents);
+            bounds.grow(self.transform.transform_point3(p));
+        }
+        bounds
+    }
+}
+
+impl Scene {
+    /// Adds a material, returning its index for the shapes using it.
+    pub fn add_material(&mut self, material: Material) -> usize {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    /// Adds `shape` placed by `transform`, returning its index.
+    pub fn add_primitive(&mut self, shape: Shape, material: usize, transform: Mat4) -> usize {
+        self.primitives.push(Primitive {
+            shape,
+            material,
+            transform,
+            class: 0,
+        });
+        self.primitives.len() - 1
+    }
+
+    pub fn add_sphere(&mut self, center: Vec3, radius: f32, material: usize) -> usize {
+        self.add_primitive(
+            Shape::Sphere { radius },
+            material,
+            Mat4::from_translation(center),
+        )
+    }
+
+    /// Adds an infinite ground plane at `height`, facing up.
+    pub fn add_ground(&mut self, height: f32, material: usize) -> usize {
+        self.add_primitive(
+            Shape::Plane,
+            material,
+            Mat4::from_translation(Vec3::Y * height),
+        )
+    }
+
+    /// Adds a distance field traced within `half_extents` of the origin,
+    /// returning the index of the primitive.
+    pub fn add_sdf(
+        &mut self,
+        sdf: Sdf,
+        half_extents: Vec3,
+        material: usize,
+        transform: Mat4,
+    ) -> usize {
+        self.sdfs.push(sdf);
+        let shape = Shape::Sdf {
+            sdf: self.sdfs.len() - 1,
+            half_extents,
+        };
+        self.add_primitive(shape, material, transform)
+    }
+
+    /// Adds a box between `min` and `max` turned by `angle` radians around
+    /// its vertical axis.
+    pub fn add_box(&mut self, min: Vec3, max: Vec3, angle: f32, material: usize) -> usize {
+        let transform =
+            Mat4::from_rotation_translation(Quat::from_rotation_y(angle), 0.5 * (min + max));
+        self.add_primitive(
+            Shape::Box {
+                half_extents: 0.5 * (max - min),
+            },
+            material,
+            transform,
+        )
+    }
+
+    /// The Cornell box: a room two units across around the origin, open
+    /// towards +Z where the default camera looks from, lit by a quad light
+    /// under the ceiling.
+    pub fn cornell_box() -> Self {
+        let mut scene = Self::default();
+        let diffuse = |name: &str, color: Vec3| Material {
+            name: name.to_string(),
+            base_color: color.extend(1.0),
+            roughness: 1.0,
+            ..Default::default()
+        };
+        let white = scene.add_material(diffuse("white", Vec3::new(0.73, 0.73, 0.73)));
+        let red = scene.add_material(diffuse("red", Vec3::new(0.65, 0.05, 0.05)));
+        let green = scene.add_material(diffuse("green", Vec3::new(0.12, 0.45, 0.15)));
+
+        let wall = Shape::Quad {
+            width: 2.0,
+            height: 2.0,
+        };
+        for (rotation, material) in [
+            (Mat4::IDENTITY, white),
+            (Mat4::from_rotation_x(PI), white),
+            (Mat4::from_rotation_x(FRAC_PI_2), white),
+            (Mat4::from_rotation_z(-FRAC_PI_2), red),
+            (Mat4::from_rotation_z(FRAC_PI_2), green),
+        ] {
+            // Each wall is pushed back from the center along its normal
+            let normal = rotation.transform_vector3(Vec3::Y);
+            scene.add_primitive(wall, material, Mat4::from_translation(-normal) * rotation);
+        }
+        scene.add_box(
+            Vec3::new(-0.65, -1.0, -0.7),
+            Vec3::new(-0.05, 0.2, -0.1),
+            0.3,
+            white,
+        );
+        scene.add_box(
+            Vec3::new(0.05, -1.0, -0.1),
+            Vec3::new(0.65, -0.4, 0.5),
+            -0.3,
+            white,
+        );
+        scene.lights.push(Light {
+            kind: LightKind::Quad {
+                width: 0.5,
+                height: 0.4,
+            },
+            color: Vec3::new(1.0, 0.7, 0.25),
+            intensity: 17.0,
+            temperature: None,
+            transform: Mat4::from_translation(Vec3::new(0.0, 0.99, 0.0))
+                * Mat4::from_rotation_x(-FRAC_PI_2),
+        });
+        scene
+    }
+
+    /// A row of spheres in the library's preset materials on a gray floor,
+    /// lit by the sky.
+    pub fn material_spheres() -> Self {
+        let mut scene = Self::default();
+        let floor = scene.add_material(Material {
+            name: String::from("floor"),
+            base_color: Vec4::new(0.5, 0.5, 0.5, 1.0),
+            roughness: 0.8,
+            ..Default::default()
+        });
+        scene.add_ground(-0.5, floor);
+        let materials = [
+            Material::default(),
+            Material::car_paint(Vec3::new(0.6, 0.05, 0.05)),
+            Material::metal(Conductor::Gold),
+            Material::metal(Conductor::Copper),
+            Material::dielectric(Dispersion::BK7),
+            Material::soap_bubble(400.0),
+            Material::skin(),
+            Material::wax(),
+            Material::marble(),
+        ];
+        let spacing = 1.2;
+        let offset = 0.5 * spacing * (materials.len() - 1) as f32;
+        for (i, material) in materials.into_iter().enumerate() {
+            let material = scene.add_material(material);
+            let center = Vec3::new(i as f32 * spacing - offset, 0.0, 0.0);
+            scene.add_sphere(center, 0.5, material);
+        }
+        scene
+    }
+
+    /// A Mandelbulb, a smooth blend of a box and a sphere, a torus and a
+    /// gyroid written in WGSL on a gray floor, lit by the sky.
+    pub fn sdf_shapes() -> Self {
+        let mut scene = Self::default();
+        let floor = scene.add_material(Material {
+            name: String::from("floor"),
+            base_color: Vec4::new(0.5, 0.5, 0.5, 1.0),
+            roughness: 0.8,
+            ..Default::default()
+        });
+        scene.add_ground(-0.6, floor);
+        let gyroid = Sdf::wgsl(
+            "let g = dot(sin(8.0 * p), cos(8.0 * p.zxy)) / 8.0;
+            let shell = abs(g) - 0.02;
+            return max(0.7 * shell, length(p) - 0.5);",
+        )
+        .expect("Built in SDF");
+        let shapes = [
+            (
+                Sdf::Shape(SdfShape::Mandelbulb {
+                    power: 8.0,
+                    iterations: 8,
+                }),
+                Vec3::splat(1.2),
+                0.5,
+                Material::metal(Conductor::Gold),
+            ),
+            (
+                Sdf::Combine {
+                    op: SdfOp::Union,
+                    a: SdfShape::Box {
+                        half_extents: Vec3::splat(0.3),
+                        radius: 0.05,
+                    },
+                    b: SdfShape::Sphere { radius: 0.3 },
+                    offset: Vec3::new(0.0, 0.3, 0.0),
+                    smoothness: 0.15,
+                },
+                Vec3::new(0.35, 0.65, 0.35),
+                1.0,
+                Material::car_paint(Vec3::new(0.05, 0.2, 0.6)),
+            ),
+            (
+                Sdf::Shape(SdfShape::Torus {
+                    major_radius: 0.4,
+                    minor_radius: 0.12,
+                }),
+                Vec3::new(0.52, 0.12, 0.52),
+                1.0,
+                Material::dielectric(Dispersion::BK7),
+            ),
+            (gyroid, Vec3::splat(0.5), 1.0, Material::default()),
+        ];
+        let spacing = 1.4;
+        let offset = 0.5 * spacing * (shapes.len() - 1) as f32;
+        for (i, (sdf, half_extents, scale, material)) in shapes.into_iter().enumerate() {
+            let material = scene.add_material(material);
+            let transform = Mat4::from_scale_rotation_translation(
+                Vec3::splat(scale),
+                Quat::from_rotation_x(0.3),
+                Vec3::new(i as f32 * spacing - offset, 0.0, 0.0),
+            );
+            scene.add_sdf(sdf, half_extents, material, transform);
+        }
+        scene
+    }
+}
@@ -0,0 +1,79 @@
+//! Signed distance fields for shapes with no closed form intersection,
+//! such as fractals and smooth blends, found by sphere tracing inside the
+//! primitive's bounds. The distance is in the primitive's local space.
+
+use anyhow::{bail, Result};
+use glam::Vec3;
+
+/// Shape a built in field is made of, centered on the origin.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SdfShape {
+    Sphere {
+        radius: f32,
+    },
+    /// Box with its edges rounded off by `radius`, within `half_extents`.
+    Box {
+        half_extents: Vec3,
+        radius: f32,
+    },
+    /// Ring around +Y.
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    /// The Mandelbulb fractal of `power`, a little over two units across
+    /// at power 8. The distance is only an estimate, so it takes more steps
+    /// than the other shapes.
+    Mandelbulb {
+        power: f32,
+        iterations: u32,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SdfOp {
+    #[default]
+    Union,
+    Intersection,
+    /// The first shape with the second cut out of it.
+    Subtraction,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sdf {
+    Shape(SdfShape),
+    /// `a` combined with `b` moved by `offset`, blended over `smoothness`
+    /// units where they meet.
+    Combine {
+        op: SdfOp,
+        a: SdfShape,
+        b: SdfShape,
+        offset: Vec3,
+        smoothness: f32,
+    },
+    /// Body of a WGSL function `(p: vec3<f32>) -> f32` returning the
+    /// distance from `p`, see `Sdf::wgsl`. Only the GPU traces it.
+    Wgsl(String),
+}
+
+impl Sdf {
+    /// A field from the body of a WGSL function taking the point `p` and
+    /// returning its distance, such as `return length(p) - 1.0;`. It can
+    /// only call WGSL's built in functions, and is checked here so a typo
+    /// doesn't take the tracer down with it.
+    pub fn wgsl(body: &str) -> Result<Self> {
+        let source = format!("fn sdf(p: vec3<f32>) -> f32 {{\n{body}\n}}\n");
+        let module = match naga::front::wgsl::parse_str(&source) {
+            Ok(module) => module,
+            Err(err) => bail!("Invalid SDF:\n{}", err.emit_to_string(&source)),
+        };
+        let mut validator = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::empty(),
+        );
+        if let Err(err) = validator.validate(&module) {
+            bail!("Invalid SDF:\n{}", err.emit_to_string(&source));
+        }
+        Ok(Self::Wgsl(body.to_owned()))
+    }
+}
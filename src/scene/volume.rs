@@ -0,0 +1,64 @@
+//! Heterogeneous participating media, such as smoke and clouds, whose
+//! density comes from a NanoVDB grid. Paths scatter through them by delta
+//! tracking against the densest voxel of the grid.
+
+use std::sync::Arc;
+
+use glam::{BVec3, Mat4, Vec3};
+
+use crate::{bvh::Aabb, nanovdb::Grid};
+
+#[derive(Clone, Debug)]
+pub struct Volume {
+    pub grid: Arc<Grid>,
+    /// Places the grid's world space in the scene.
+    pub transform: Mat4,
+    /// Scales the grid's values into densities.
+    pub density: f32,
+    /// Absorption and scattering coefficients per unit of density, in
+    /// inverse world units for each of red, green and blue. Unequal ones
+    /// tint what shines through, as smoke reddens the light behind it.
+    pub absorption: Vec3,
+    pub scattering: Vec3,
+    /// Henyey-Greenstein asymmetry of the phase function, positive for
+    /// forward scattering as in clouds.
+    pub anisotropy: f32,
+}
+
+impl Volume {
+    /// Gray smoke of `grid`, scattering most of the light it takes out.
+    pub fn new(grid: Arc<Grid>) -> Self {
+        Self {
+            grid,
+            transform: Mat4::IDENTITY,
+            density: 1.0,
+            absorption: Vec3::splat(0.1),
+            scattering: Vec3::splat(0.9),
+            anisotropy: 0.0,
+        }
+    }
+
+    /// Transform from world space to the grid's index space.
+    pub fn world_to_index(&self) -> Mat4 {
+        (self.transform * self.grid.index_to_world).inverse()
+    }
+
+    /// World space bounds, from the corners of the grid's index bounds.
+    pub fn bounds(&self) -> Aabb {
+        let index_to_world = self.transform * self.grid.index_to_world;
+        let index = self.grid.index_bounds();
+        let mut bounds = Aabb::EMPTY;
+        for corner in 0..8 {
+            let select = BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0);
+            let p = Vec3::select(select, index.max, index.min);
+            bounds.grow(index_to_world.transform_point3(p));
+        }
+        bounds
+    }
+
+    /// Largest extinction anywhere in the volume, the majorant of delta
+    /// tracking.
+    pub fn majorant(&self) -> f32 {
+        self.grid.maximum * self.density * (self.absorption + self.scattering).max_element()
+    }
+}
@@ -0,0 +1,179 @@
+//! Shaders put together from the files in `src/wgsl` by a small
+//! preprocessor, so code such as the camera, BSDFs and ray intersection
+//! lives in one file however many shaders use it. Lines starting with `#`
+//! are directives:
+//!
+//! - `#include "bsdf.wgsl"` pastes in another file, once per shader.
+//! - `#define NAME value` replaces the identifier `NAME` from there on, and
+//!   `#define NAME` on its own sets a flag.
+//! - `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif` keep or drop lines
+//!   depending on what's defined, by a directive or by the Rust side.
+
+use anyhow::{bail, Context, Result};
+
+/// Every shader file, built in so the binary runs without the checkout.
+const FILES: [(&str, &str); 17] = [
+    ("aov.wgsl", include_str!("wgsl/aov.wgsl")),
+    ("bsdf.wgsl", include_str!("wgsl/bsdf.wgsl")),
+    ("camera.wgsl", include_str!("wgsl/camera.wgsl")),
+    ("exposure.wgsl", include_str!("wgsl/exposure.wgsl")),
+    ("intersect.wgsl", include_str!("wgsl/intersect.wgsl")),
+    ("mipmap.wgsl", include_str!("wgsl/mipmap.wgsl")),
+    ("ocean.wgsl", include_str!("wgsl/ocean.wgsl")),
+    ("render.wgsl", include_str!("wgsl/render.wgsl")),
+    ("reproject.wgsl", include_str!("wgsl/reproject.wgsl")),
+    ("sampler.wgsl", include_str!("wgsl/sampler.wgsl")),
+    ("sdf.wgsl", include_str!("wgsl/sdf.wgsl")),
+    ("subsurface.wgsl", include_str!("wgsl/subsurface.wgsl")),
+    ("svgf.wgsl", include_str!("wgsl/svgf.wgsl")),
+    ("tonemap.wgsl", include_str!("wgsl/tonemap.wgsl")),
+    ("trace.wgsl", include_str!("wgsl/trace.wgsl")),
+    ("triangles.wgsl", include_str!("wgsl/triangles.wgsl")),
+    ("volume.wgsl", include_str!("wgsl/volume.wgsl")),
+];
+
+/// The built in contents of a shader file.
+pub fn builtin(file: &str) -> Option<&'static str> {
+    FILES
+        .iter()
+        .find(|(name, _)| *name == file)
+        .map(|(_, source)| *source)
+}
+
+/// WGSL put together from a shader file.
+#[derive(Clone, Debug, Default)]
+pub struct Composed {
+    pub source: String,
+    /// The file and everything it included.
+    pub files: Vec<String>,
+}
+
+/// Preprocesses the shader file `name` with `defines` set beforehand,
+/// reading it and its includes with `read`.
+pub fn compose(
+    name: &str,
+    defines: &[(&str, String)],
+    read: impl Fn(&str) -> Result<String>,
+) -> Result<Composed> {
+    let mut preprocessor = Preprocessor {
+        defines: defines
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect(),
+        composed: Composed::default(),
+        read: &read,
+    };
+    preprocessor.include(name)?;
+    Ok(preprocessor.composed)
+}
+
+/// A module of a built in shader, which has to preprocess and compile.
+pub fn create_module(
+    device: &wgpu::Device,
+    label: &str,
+    name: &str,
+    defines: &[(&str, String)],
+) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(builtin_source(name, defines).into()),
+    })
+}
+
+/// The WGSL of a built in shader after preprocessing.
+pub fn builtin_source(name: &str, defines: &[(&str, String)]) -> String {
+    compose(name, defines, |file| {
+        builtin(file)
+            .map(str::to_owned)
+            .with_context(|| format!("No shader file {file}"))
+    })
+    .unwrap_or_else(|err| panic!("{err:#}"))
+    .source
+}
+
+struct Preprocessor<'a> {
+    defines: Vec<(String, String)>,
+    composed: Composed,
+    read: &'a dyn Fn(&str) -> Result<String>,
+}
+
+impl Preprocessor<'_> {
+    fn include(&mut self, file: &str) -> Result<()> {
+        if self.composed.files.iter().any(|included| included == file) {
+            return Ok(());
+        }
+        self.composed.files.push(file.to_owned());
+        let text = (self.read)(file)?;
+        // Whether the lines of each enclosing #ifdef are kept
+        let mut conditions: Vec<bool> = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let location = || format!("{file}:{}", number + 1);
+            let kept = conditions.iter().all(|&kept| kept);
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if kept {
+                    let line = self.substitute(line);
+                    self.composed.source.push_str(&line);
+                    self.composed.source.push('\n');
+                }
+                continue;
+            };
+            let mut words = directive.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("include"), Some(path)) if kept => {
+                    let path = path.trim_matches('"');
+                    self.include(path)
+                        .with_context(|| format!("{}: Included from here", location()))?;
+                }
+                (Some("define"), Some(name)) if kept => {
+                    let value = words.collect::<Vec<_>>().join(" ");
+                    self.defines.retain(|(defined, _)| defined != name);
+                    self.defines.push((name.to_owned(), value));
+                }
+                (Some("include" | "define"), Some(_)) => {}
+                (Some("ifdef"), Some(name)) => conditions.push(self.is_defined(name)),
+                (Some("ifndef"), Some(name)) => conditions.push(!self.is_defined(name)),
+                (Some("else"), None) => match conditions.last_mut() {
+                    Some(kept) => *kept = !*kept,
+                    None => bail!("{}: #else without #ifdef", location()),
+                },
+                (Some("endif"), None) => {
+                    if conditions.pop().is_none() {
+                        bail!("{}: #endif without #ifdef", location());
+                    }
+                }
+                _ => bail!("{}: Invalid directive #{directive}", location()),
+            }
+        }
+        if !conditions.is_empty() {
+            bail!("{file}: #ifdef without #endif");
+        }
+        Ok(())
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.defines.iter().any(|(defined, _)| defined == name)
+    }
+
+    /// Replaces the identifiers defined with a value.
+    fn substitute(&self, line: &str) -> String {
+        if self.defines.iter().all(|(_, value)| value.is_empty()) {
+            return line.to_owned();
+        }
+        let is_identifier = |c: char| c.is_alphanumeric() || c == '_';
+        let mut result = String::with_capacity(line.len());
+        let mut rest = line;
+        while let Some(start) = rest.find(is_identifier) {
+            result.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = rest.find(|c| !is_identifier(c)).unwrap_or(rest.len());
+            let word = &rest[..end];
+            match self.defines.iter().find(|(name, _)| name == word) {
+                Some((_, value)) if !value.is_empty() => result.push_str(value),
+                _ => result.push_str(word),
+            }
+            rest = &rest[end..];
+        }
+        result.push_str(rest);
+        result
+    }
+}
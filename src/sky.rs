@@ -0,0 +1,232 @@
+//! Analytic sun and sky used when no environment map is loaded.
+//!
+//! Sky radiance follows Preetham et al. 1999, "A Practical Analytic Model
+//! for Daylight": Perez distributions of luminance and chromaticity fitted
+//! against turbidity. Hosek and Wilkie 2012 refine the same parameterization
+//! with ground albedo, but need their large fitted datasets. Here the albedo
+//! lights the ground below the horizon instead. The sun is attenuated by
+//! Rayleigh and aerosol extinction evaluated at one wavelength per channel.
+
+use std::f32::consts::PI;
+
+use glam::{Mat3, Vec3};
+
+/// Render units per kcd/m².
+const LUMINANCE_SCALE: f32 = 0.1;
+/// Luminance of the solar disk above the atmosphere in kcd/m².
+const SUN_LUMINANCE: f32 = 2.0e6;
+/// Angular radius of the sun in radians.
+const SUN_RADIUS: f32 = 0.00465;
+/// Wavelengths in micrometers standing in for the red, green and blue channels.
+const WAVELENGTHS: [f32; 3] = [0.68, 0.55, 0.44];
+
+#[derive(Clone, Copy, Debug)]
+pub struct Sky {
+    /// Haziness of the atmosphere, from 2 for a clear sky to 10 for haze.
+    pub turbidity: f32,
+    /// Angle of the sun above the horizon in radians.
+    pub sun_elevation: f32,
+    /// Rotation of the sun around +Y in radians, zero is towards -Z.
+    pub sun_azimuth: f32,
+    pub ground_albedo: f32,
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Self {
+            turbidity: 3.0,
+            sun_elevation: 35f32.to_radians(),
+            sun_azimuth: 30f32.to_radians(),
+            ground_albedo: 0.3,
+        }
+    }
+}
+
+impl Sky {
+    pub fn sun_direction(&self) -> Vec3 {
+        // Preetham is only defined with the sun above the horizon
+        let elevation = self.sun_elevation.clamp(0.0, 0.5 * PI);
+        let (sin_azimuth, cos_azimuth) = self.sun_azimuth.sin_cos();
+        let (sin_elevation, cos_elevation) = elevation.sin_cos();
+        Vec3::new(
+            cos_elevation * sin_azimuth,
+            sin_elevation,
+            -cos_elevation * cos_azimuth,
+        )
+    }
+
+    pub fn uniform(&self) -> SkyUniform {
+        let model = Model::new(self);
+        let sun_radiance = model.sun_radiance();
+
+        // Light reaching the ground from the sky and the sun, reflected diffusely
+        const THETA_STEPS: usize = 16;
+        const PHI_STEPS: usize = 32;
+        let mut irradiance = Vec3::ZERO;
+        for i in 0..THETA_STEPS {
+            let theta = (i as f32 + 0.5) / THETA_STEPS as f32 * 0.5 * PI;
+            let (sin_theta, cos_theta) = theta.sin_cos();
+            for j in 0..PHI_STEPS {
+                let phi = (j as f32 + 0.5) / PHI_STEPS as f32 * 2.0 * PI;
+                let dir = Vec3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+                irradiance += model.sky_radiance(dir) * cos_theta * sin_theta;
+            }
+        }
+        irradiance *= (0.5 * PI / THETA_STEPS as f32) * (2.0 * PI / PHI_STEPS as f32);
+        let sun_solid_angle = 2.0 * PI * (1.0 - SUN_RADIUS.cos());
+        irradiance += sun_radiance * sun_solid_angle * model.sun.y;
+        let ground_radiance = self.ground_albedo * irradiance / PI;
+
+        SkyUniform {
+            sun_direction: model.sun.into(),
+            sun_cos_radius: SUN_RADIUS.cos(),
+            sun_radiance: sun_radiance.into(),
+            ground_radiance: ground_radiance.into(),
+            a: model.a.into(),
+            b: model.b.into(),
+            c: model.c.into(),
+            d: model.d.into(),
+            e: model.e.into(),
+            // The shader works in render units directly
+            zenith: (model.zenith * Vec3::new(LUMINANCE_SCALE, 1.0, 1.0)).into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Perez coefficients for luminance and the x and y chromaticities.
+struct Model {
+    sun: Vec3,
+    turbidity: f32,
+    a: Vec3,
+    b: Vec3,
+    c: Vec3,
+    d: Vec3,
+    e: Vec3,
+    /// Zenith Yxy divided by the Perez function at the zenith.
+    zenith: Vec3,
+}
+
+impl Model {
+    fn new(sky: &Sky) -> Self {
+        let t = sky.turbidity.clamp(1.7, 10.0);
+        let sun = sky.sun_direction();
+        let theta_s = sun.y.clamp(-1.0, 1.0).acos();
+
+        let a = Vec3::new(
+            0.1787 * t - 1.4630,
+            -0.0193 * t - 0.2592,
+            -0.0167 * t - 0.2608,
+        );
+        let b = Vec3::new(
+            -0.3554 * t + 0.4275,
+            -0.0665 * t + 0.0008,
+            -0.0950 * t + 0.0092,
+        );
+        let c = Vec3::new(
+            -0.0227 * t + 5.3251,
+            -0.0004 * t + 0.2125,
+            -0.0079 * t + 0.2102,
+        );
+        let d = Vec3::new(
+            0.1206 * t - 2.5771,
+            -0.0641 * t - 0.8989,
+            -0.0441 * t - 1.6537,
+        );
+        let e = Vec3::new(
+            -0.0670 * t + 0.3703,
+            -0.0033 * t + 0.0452,
+            -0.0109 * t + 0.0529,
+        );
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let cubic = |k: [f32; 4]| ((k[0] * theta_s + k[1]) * theta_s + k[2]) * theta_s + k[3];
+        let zenith_x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let mut model = Self {
+            sun,
+            turbidity: t,
+            a,
+            b,
+            c,
+            d,
+            e,
+            zenith: Vec3::ZERO,
+        };
+        let at_zenith = model.perez(1.0, theta_s);
+        model.zenith = Vec3::new(zenith_luminance, zenith_x, zenith_y) / at_zenith;
+        model
+    }
+
+    fn perez(&self, cos_theta: f32, gamma: f32) -> Vec3 {
+        let cos_gamma = gamma.cos();
+        let horizon = Vec3::ONE + self.a * (self.b / cos_theta.max(0.01)).exp();
+        let circumsolar =
+            Vec3::ONE + self.c * (self.d * gamma).exp() + self.e * cos_gamma * cos_gamma;
+        horizon * circumsolar
+    }
+
+    fn sky_radiance(&self, dir: Vec3) -> Vec3 {
+        let gamma = dir.dot(self.sun).clamp(-1.0, 1.0).acos();
+        let yxy = self.zenith * self.perez(dir.y, gamma);
+        xyy_to_rgb(yxy) * LUMINANCE_SCALE
+    }
+
+    /// Solar disk radiance after extinction along the path through the
+    /// atmosphere, using Preetham's relative optical mass.
+    fn sun_radiance(&self) -> Vec3 {
+        if self.sun.y <= 0.0 {
+            return Vec3::ZERO;
+        }
+        let zenith_degrees = self.sun.y.acos().to_degrees();
+        let mass = 1.0 / (self.sun.y + 0.15 * (93.885 - zenith_degrees).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = WAVELENGTHS.map(|lambda| {
+            let rayleigh = -0.008735 * lambda.powf(-4.08) * mass;
+            let aerosol = -beta * lambda.powf(-1.3) * mass;
+            (rayleigh + aerosol).exp()
+        });
+        Vec3::from(transmittance) * SUN_LUMINANCE * LUMINANCE_SCALE
+    }
+}
+
+/// CIE xyY with luminance first to linear sRGB.
+fn xyy_to_rgb(yxy: Vec3) -> Vec3 {
+    let (luminance, x, y) = (yxy.x, yxy.y, yxy.z.max(1e-6));
+    let xyz = Vec3::new(x * luminance / y, luminance, (1.0 - x - y) * luminance / y);
+    let to_rgb = Mat3::from_cols(
+        Vec3::new(3.2406, -0.9689, 0.0557),
+        Vec3::new(-1.5372, 1.8758, -0.2040),
+        Vec3::new(-0.4986, 0.0415, 1.0570),
+    );
+    (to_rgb * xyz).max(Vec3::ZERO)
+}
+
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct SkyUniform {
+    pub sun_direction: [f32; 3],
+    pub sun_cos_radius: f32,
+    pub sun_radiance: [f32; 3],
+    _pad0: f32,
+    pub ground_radiance: [f32; 3],
+    _pad1: f32,
+    pub a: [f32; 3],
+    _pad2: f32,
+    pub b: [f32; 3],
+    _pad3: f32,
+    pub c: [f32; 3],
+    _pad4: f32,
+    pub d: [f32; 3],
+    _pad5: f32,
+    pub e: [f32; 3],
+    _pad6: f32,
+    pub zenith: [f32; 3],
+    _pad7: f32,
+}
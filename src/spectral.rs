@@ -0,0 +1,470 @@
+//! Wavelength dependent optics that are integrated into RGB ahead of
+//! rendering. Wavelengths are in micrometers.
+
+use glam::Vec3;
+
+/// Visible range, matching the shader.
+pub const MIN_WAVELENGTH: f32 = 0.38;
+pub const MAX_WAVELENGTH: f32 = 0.78;
+
+/// Resolution of the reflectance tables over the cosine of the incident
+/// angle, matched in the shader.
+pub const FRESNEL_TABLE_SIZE: usize = 32;
+/// Wavelengths summed per table entry.
+const WAVELENGTH_STEPS: usize = 80;
+
+/// Linear sRGB of a single wavelength, clamped to the gamut. Uses the
+/// analytic CIE 1931 fit of Wyman et al. 2013, scaled so that the average
+/// over the visible range is white. Same as `wavelength_rgb` in the shader.
+pub fn wavelength_rgb(wavelength: f32) -> Vec3 {
+    let lobe = |x: f32, mean: f32, below: f32, above: f32| {
+        let t = (x - mean) / if x < mean { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    let l = wavelength * 1000.0;
+    let xyz = Vec3::new(
+        1.056 * lobe(l, 599.8, 37.9, 31.0) + 0.362 * lobe(l, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(l, 501.1, 20.4, 26.2),
+        0.821 * lobe(l, 568.8, 46.9, 40.5) + 0.286 * lobe(l, 530.9, 16.3, 31.1),
+        1.217 * lobe(l, 437.0, 11.8, 36.0) + 0.681 * lobe(l, 459.0, 26.0, 13.8),
+    );
+    let rgb = Vec3::new(
+        Vec3::new(3.2406, -1.5372, -0.4986).dot(xyz),
+        Vec3::new(-0.9689, 1.8758, 0.0415).dot(xyz),
+        Vec3::new(0.0557, -0.2040, 1.0570).dot(xyz),
+    );
+    rgb.max(Vec3::ZERO) * Vec3::new(2.2704, 3.4666, 3.6598)
+}
+
+/// Color temperatures are clamped to this range, cooler bodies barely glow.
+pub const MIN_TEMPERATURE: f32 = 1000.0;
+pub const MAX_TEMPERATURE: f32 = 40000.0;
+/// Second radiation constant hc/k in micrometer kelvin.
+const PLANCK_C2: f32 = 14387.77;
+/// Blackbody radiance is given relative to this wavelength to stay within
+/// the range of floats.
+const REFERENCE_WAVELENGTH: f32 = 0.56;
+
+/// Spectral radiance of a blackbody by Planck's law, relative to its
+/// radiance at the reference wavelength. Same as `planck` in the shader.
+pub fn planck(wavelength: f32, temperature: f32) -> f32 {
+    let temperature = temperature.clamp(MIN_TEMPERATURE, MAX_TEMPERATURE);
+    let x = PLANCK_C2 / (wavelength * temperature);
+    let x_reference = PLANCK_C2 / (REFERENCE_WAVELENGTH * temperature);
+    (REFERENCE_WAVELENGTH / wavelength).powi(5) * x_reference.exp_m1() / x.exp_m1()
+}
+
+/// Color of a blackbody with a luminance of 1, and the scale of `planck`
+/// that gives the same emission per wavelength.
+pub fn blackbody(temperature: f32) -> (Vec3, f32) {
+    let rgb = integrate(|wavelength| planck(wavelength, temperature));
+    let scale = luminance(rgb).recip();
+    (rgb * scale, scale)
+}
+
+pub fn luminance(rgb: Vec3) -> f32 {
+    rgb.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+/// Metal with measured complex index of refraction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Conductor {
+    Gold,
+    Silver,
+    Copper,
+    Aluminum,
+}
+
+/// Wavelength, n and k. Gold, silver and copper from Johnson and Christy
+/// 1972, aluminum from Rakić 1995.
+const GOLD: &[(f32, f32, f32)] = &[
+    (0.381, 1.46, 1.933),
+    (0.397, 1.47, 1.952),
+    (0.413, 1.46, 1.958),
+    (0.431, 1.45, 1.948),
+    (0.451, 1.38, 1.914),
+    (0.471, 1.31, 1.849),
+    (0.496, 1.04, 1.833),
+    (0.521, 0.62, 2.081),
+    (0.549, 0.43, 2.455),
+    (0.582, 0.29, 2.863),
+    (0.617, 0.21, 3.272),
+    (0.659, 0.14, 3.697),
+    (0.704, 0.13, 4.103),
+    (0.756, 0.14, 4.542),
+];
+const SILVER: &[(f32, f32, f32)] = &[
+    (0.381, 0.05, 1.864),
+    (0.397, 0.05, 2.07),
+    (0.413, 0.05, 2.275),
+    (0.431, 0.04, 2.462),
+    (0.451, 0.04, 2.657),
+    (0.471, 0.05, 2.869),
+    (0.496, 0.05, 3.093),
+    (0.521, 0.05, 3.324),
+    (0.549, 0.06, 3.586),
+    (0.582, 0.05, 3.858),
+    (0.617, 0.06, 4.152),
+    (0.659, 0.05, 4.483),
+    (0.704, 0.04, 4.838),
+    (0.756, 0.03, 5.242),
+];
+const COPPER: &[(f32, f32, f32)] = &[
+    (0.381, 1.25, 2.14),
+    (0.397, 1.24, 2.2),
+    (0.413, 1.22, 2.23),
+    (0.431, 1.18, 2.31),
+    (0.451, 1.17, 2.38),
+    (0.471, 1.16, 2.45),
+    (0.496, 1.15, 2.5),
+    (0.521, 1.13, 2.55),
+    (0.549, 1.09, 2.58),
+    (0.582, 0.32, 2.845),
+    (0.617, 0.3, 3.109),
+    (0.659, 0.26, 3.414),
+    (0.704, 0.24, 3.777),
+    (0.756, 0.21, 4.205),
+];
+const ALUMINUM: &[(f32, f32, f32)] = &[
+    (0.38, 0.44, 4.6),
+    (0.4, 0.49, 4.86),
+    (0.45, 0.62, 5.47),
+    (0.5, 0.77, 6.08),
+    (0.55, 0.96, 6.69),
+    (0.6, 1.2, 7.26),
+    (0.65, 1.47, 7.79),
+    (0.7, 1.83, 8.31),
+    (0.75, 2.4, 8.62),
+    (0.78, 2.6, 8.55),
+];
+
+impl Conductor {
+    /// Real and imaginary part of the index of refraction, interpolated
+    /// linearly and clamped outside the measurements.
+    pub fn ior(&self, wavelength: f32) -> (f32, f32) {
+        let table = match self {
+            Self::Gold => GOLD,
+            Self::Silver => SILVER,
+            Self::Copper => COPPER,
+            Self::Aluminum => ALUMINUM,
+        };
+        let i = table.partition_point(|&(l, _, _)| l < wavelength);
+        if i == 0 {
+            return (table[0].1, table[0].2);
+        }
+        let Some(&(l1, n1, k1)) = table.get(i) else {
+            let (_, n, k) = table[table.len() - 1];
+            return (n, k);
+        };
+        let (l0, n0, k0) = table[i - 1];
+        let t = (wavelength - l0) / (l1 - l0);
+        (n0 + (n1 - n0) * t, k0 + (k1 - k0) * t)
+    }
+}
+
+/// What lies under the surface, or under its thin film.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Substrate {
+    Conductor(Conductor),
+    /// Clear dielectric of the given IOR, 1 for air behind a soap film.
+    Dielectric(f32),
+}
+
+impl Substrate {
+    fn ior(&self, wavelength: f32) -> Complex {
+        match self {
+            Self::Conductor(conductor) => {
+                let (n, k) = conductor.ior(wavelength);
+                Complex::new(n, k)
+            }
+            Self::Dielectric(ior) => Complex::new(*ior, 0.0),
+        }
+    }
+}
+
+/// Clear layer on top of a substrate, like oil on water, whose reflections
+/// interfere.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThinFilm {
+    /// In nanometers, on the order of visible wavelengths.
+    pub thickness: f32,
+    pub ior: f32,
+}
+
+/// Fresnel reflectance in linear sRGB of a substrate seen from air, for
+/// every entry of a table over the cosine of the incident angle from 0 to 1.
+pub fn reflectance_table(
+    substrate: Substrate,
+    film: Option<ThinFilm>,
+) -> [Vec3; FRESNEL_TABLE_SIZE] {
+    std::array::from_fn(|i| {
+        let cos_i = i as f32 / (FRESNEL_TABLE_SIZE - 1) as f32;
+        integrate(|wavelength| reflectance(cos_i, wavelength, substrate, film))
+    })
+}
+
+/// Averages a spectrum over the visible range, weighted by the response
+/// of each channel.
+pub fn integrate(spectrum: impl Fn(f32) -> f32) -> Vec3 {
+    let step = (MAX_WAVELENGTH - MIN_WAVELENGTH) / WAVELENGTH_STEPS as f32;
+    (0..WAVELENGTH_STEPS)
+        .map(|i| {
+            let wavelength = MIN_WAVELENGTH + (i as f32 + 0.5) * step;
+            spectrum(wavelength) * wavelength_rgb(wavelength)
+        })
+        .sum::<Vec3>()
+        / WAVELENGTH_STEPS as f32
+}
+
+/// Samples of a `Spectrum`, matched in the shader.
+pub const SPECTRUM_SAMPLES: usize = 16;
+
+/// Spectrum sampled at wavelengths evenly spaced from the shortest to the
+/// longest visible one, and linearly interpolated between them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Spectrum(pub [f32; SPECTRUM_SAMPLES]);
+
+impl Spectrum {
+    pub fn from_fn(spectrum: impl Fn(f32) -> f32) -> Self {
+        Self(std::array::from_fn(|i| {
+            let t = i as f32 / (SPECTRUM_SAMPLES - 1) as f32;
+            spectrum(MIN_WAVELENGTH + t * (MAX_WAVELENGTH - MIN_WAVELENGTH))
+        }))
+    }
+
+    pub fn at(&self, wavelength: f32) -> f32 {
+        let x = (wavelength - MIN_WAVELENGTH) / (MAX_WAVELENGTH - MIN_WAVELENGTH)
+            * (SPECTRUM_SAMPLES - 1) as f32;
+        let x = x.clamp(0.0, (SPECTRUM_SAMPLES - 1) as f32);
+        let i = (x as usize).min(SPECTRUM_SAMPLES - 2);
+        let t = x - i as f32;
+        self.0[i] + (self.0[i + 1] - self.0[i]) * t
+    }
+}
+
+/// Medium under a surface that light scatters through before leaving it
+/// again, like skin, wax and marble. The walk through it is traced per
+/// wavelength when rendering spectrally, so narrow absorption bands such as
+/// those of blood tint it as they do in life.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Subsurface {
+    /// Absorption and scattering coefficients in inverse world units. The
+    /// presets take a world unit to be a meter, see `scaled`.
+    pub absorption: Spectrum,
+    pub scattering: Spectrum,
+    /// Henyey-Greenstein asymmetry of the phase function.
+    pub anisotropy: f32,
+}
+
+impl Subsurface {
+    /// Light skin. The reduced scattering and the absorption at the
+    /// primaries are those of Jensen et al. 2001, "A Practical Model for
+    /// Subsurface Light Transport", spread over the spectrum by the falloff
+    /// of melanin and the Soret and Q bands of oxygenated hemoglobin.
+    pub fn skin() -> Self {
+        let band = |wavelength: f32, center: f32, width: f32| {
+            let t = (wavelength - center) / width;
+            (-0.5 * t * t).exp()
+        };
+        Self::per_millimeter(
+            |wavelength| {
+                let melanin = 0.05 * (wavelength / 0.45).powf(-3.33);
+                let hemoglobin = 30.0 * band(wavelength, 0.415, 0.015)
+                    + band(wavelength, 0.542, 0.015)
+                    + band(wavelength, 0.577, 0.01)
+                    + 0.02;
+                0.005 + melanin + 0.25 * hemoglobin
+            },
+            |wavelength| 0.88 * (wavelength / 0.55).powf(-0.85),
+        )
+    }
+
+    /// Paraffin wax, scattering less than skin and absorbing some blue,
+    /// which warms the light that makes it through.
+    pub fn wax() -> Self {
+        Self::per_millimeter(
+            |wavelength| 0.002 + 0.03 * (wavelength / 0.45).powf(-6.0),
+            |wavelength| 0.6 * (wavelength / 0.55).powf(-1.0),
+        )
+    }
+
+    /// White marble, from the coefficients of Jensen et al. 2001 at the
+    /// primaries.
+    pub fn marble() -> Self {
+        Self::per_millimeter(
+            |wavelength| 0.0041 * (wavelength / 0.55).powf(-3.3),
+            |wavelength| 2.62 * (wavelength / 0.55).powf(-0.86),
+        )
+    }
+
+    /// Isotropic medium of reduced scattering coefficients, which are
+    /// given per millimeter as they are measured.
+    fn per_millimeter(absorption: impl Fn(f32) -> f32, scattering: impl Fn(f32) -> f32) -> Self {
+        Self {
+            absorption: Spectrum::from_fn(|wavelength| 1000.0 * absorption(wavelength)),
+            scattering: Spectrum::from_fn(|wavelength| 1000.0 * scattering(wavelength)),
+            anisotropy: 0.0,
+        }
+    }
+
+    /// Absorption and scattering coefficients of the medium for paths in
+    /// RGB. Averaging the coefficients over each channel would let a narrow
+    /// band absorbing strongly, like blood does in the blue, darken every
+    /// channel it overlaps. What shows is the albedo of multiple
+    /// scattering, so that is averaged instead and the single scattering
+    /// albedo that gives it found again.
+    pub fn rgb(&self) -> (Vec3, Vec3) {
+        let extinction =
+            |wavelength| self.absorption.at(wavelength) + self.scattering.at(wavelength);
+        let albedo = integrate(|wavelength| {
+            let single = self.scattering.at(wavelength) / extinction(wavelength).max(1e-12);
+            multiple_scattering_albedo(single, self.anisotropy)
+        }) / integrate(|_| 1.0);
+        let extinction = integrate(extinction) / integrate(|_| 1.0);
+        let single = Vec3::from_array(albedo.to_array().map(|target| {
+            // The albedo of multiple scattering grows with the single one
+            let (mut low, mut high) = (0.0, 1.0);
+            for _ in 0..32 {
+                let mid = 0.5 * (low + high);
+                if multiple_scattering_albedo(mid, self.anisotropy) < target {
+                    low = mid;
+                } else {
+                    high = mid;
+                }
+            }
+            0.5 * (low + high)
+        }));
+        ((1.0 - single) * extinction, single * extinction)
+    }
+
+    /// The same medium in a scene of `units_per_meter` world units per
+    /// meter, such as 100 for one in centimeters.
+    pub fn scaled(self, units_per_meter: f32) -> Self {
+        let scale = |spectrum: Spectrum| Spectrum(spectrum.0.map(|c| c / units_per_meter));
+        Self {
+            absorption: scale(self.absorption),
+            scattering: scale(self.scattering),
+            ..self
+        }
+    }
+}
+
+/// Share of the light entering a semi-infinite medium that comes back out
+/// after any number of scattering events, from the single scattering
+/// albedo and asymmetry, by the approximation of van de Hulst 1980.
+fn multiple_scattering_albedo(albedo: f32, anisotropy: f32) -> f32 {
+    let s = ((1.0 - albedo) / (1.0 - albedo * anisotropy))
+        .max(0.0)
+        .sqrt();
+    (1.0 - s) * (1.0 - 0.139 * s) / (1.0 + 1.17 * s)
+}
+
+/// Unpolarized reflectance from air at a single wavelength. A film sums the
+/// reflections bouncing inside it with their phase shifts, the Airy formula.
+fn reflectance(cos_i: f32, wavelength: f32, substrate: Substrate, film: Option<ThinFilm>) -> f32 {
+    let air = Complex::new(1.0, 0.0);
+    let cos_i = Complex::new(cos_i, 0.0);
+    let n = substrate.ior(wavelength);
+    let Some(film) = film else {
+        let [rs, rp] = fresnel_amplitudes(air, cos_i, n);
+        return 0.5 * (rs.norm_sqr() + rp.norm_sqr());
+    };
+
+    let n_film = Complex::new(film.ior, 0.0);
+    let cos_film = cos_transmitted(air, cos_i, n_film);
+    let outer = fresnel_amplitudes(air, cos_i, n_film);
+    let inner = fresnel_amplitudes(n_film, cos_film, n);
+    // Phase difference of one round trip through the film
+    let phase = Complex::new(
+        4.0 * std::f32::consts::PI * film.thickness / (1000.0 * wavelength),
+        0.0,
+    ) * n_film
+        * cos_film;
+    let shift = phase.exp_i();
+    let one = Complex::new(1.0, 0.0);
+    let r =
+        |outer: Complex, inner: Complex| (outer + inner * shift) / (one + outer * inner * shift);
+    0.5 * (r(outer[0], inner[0]).norm_sqr() + r(outer[1], inner[1]).norm_sqr())
+}
+
+/// Cosine of the refracted angle by Snell's law, complex past the critical
+/// angle or into conductors.
+fn cos_transmitted(n_i: Complex, cos_i: Complex, n_t: Complex) -> Complex {
+    let one = Complex::new(1.0, 0.0);
+    let sin_t = n_i / n_t;
+    let sin2_t = sin_t * sin_t * (one - cos_i * cos_i);
+    (one - sin2_t).sqrt()
+}
+
+/// Amplitude reflection coefficients for s and p polarized light.
+fn fresnel_amplitudes(n_i: Complex, cos_i: Complex, n_t: Complex) -> [Complex; 2] {
+    let cos_t = cos_transmitted(n_i, cos_i, n_t);
+    let rs = (n_i * cos_i - n_t * cos_t) / (n_i * cos_i + n_t * cos_t);
+    let rp = (n_t * cos_i - n_i * cos_t) / (n_t * cos_i + n_i * cos_t);
+    [rs, rp]
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Complex {
+    fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+
+    fn norm_sqr(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+
+    /// Principal square root, with a non-negative real part.
+    fn sqrt(self) -> Self {
+        let r = self.norm_sqr().sqrt();
+        let re = (0.5 * (r + self.re)).max(0.0).sqrt();
+        let im = (0.5 * (r - self.re)).max(0.0).sqrt();
+        Self::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    /// `e^(i z)`
+    fn exp_i(self) -> Self {
+        let scale = (-self.im).exp();
+        Self::new(scale * self.re.cos(), scale * self.re.sin())
+    }
+}
+
+impl std::ops::Add for Complex {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl std::ops::Sub for Complex {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl std::ops::Mul for Complex {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        Self::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl std::ops::Div for Complex {
+    type Output = Self;
+    fn div(self, other: Self) -> Self {
+        let denominator = other.norm_sqr();
+        Self::new(
+            (self.re * other.re + self.im * other.im) / denominator,
+            (self.im * other.re - self.re * other.im) / denominator,
+        )
+    }
+}
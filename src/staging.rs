@@ -0,0 +1,179 @@
+//! Uploads of uniforms and small buffers that change from frame to frame.
+//! Each frame in flight has a staging buffer of its own, which the CPU
+//! fills while it's mapped and the frame's encoder copies out of, so
+//! writing the next frame never waits on the GPU still copying the last.
+//! The copies are recorded along with the passes, so each pass sees what
+//! was written before it, where `Queue::write_buffer` lands ahead of the
+//! whole submission.
+
+use std::{
+    marker::PhantomData,
+    num::NonZeroU64,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use wgpu::util::DeviceExt;
+
+/// Frames the surface queues up ahead of the GPU, its
+/// `desired_maximum_frame_latency`.
+pub const FRAME_LATENCY: u32 = 2;
+/// Frames whose uploads the GPU may still be reading: the queued ones and
+/// the one being recorded.
+pub const FRAMES_IN_FLIGHT: usize = FRAME_LATENCY as usize + 1;
+
+struct Slot {
+    buffer: wgpu::Buffer,
+    /// Set by the map callback once the GPU is done copying out of it.
+    mapped: Arc<AtomicBool>,
+    /// Bytes written this frame.
+    used: u64,
+}
+
+pub struct FrameStaging {
+    slots: Vec<Slot>,
+    current: usize,
+}
+
+impl FrameStaging {
+    /// `size` bytes of staging per frame in flight. Frames writing more
+    /// spill into buffers of their own.
+    pub fn new(device: &wgpu::Device, label: &str, size: u64) -> Self {
+        let slots = (0..FRAMES_IN_FLIGHT)
+            .map(|_| Slot {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some(label),
+                    size,
+                    usage: wgpu::BufferUsages::MAP_WRITE | wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: true,
+                }),
+                mapped: Arc::new(AtomicBool::new(true)),
+                used: 0,
+            })
+            .collect();
+        Self { slots, current: 0 }
+    }
+
+    /// Records a copy of `data` to `offset` in `target`, through this
+    /// frame's staging if it's free and has room left, otherwise through a
+    /// buffer of its own rather than waiting on the GPU.
+    pub fn write(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::Buffer,
+        offset: u64,
+        data: &[u8],
+    ) {
+        if data.is_empty() {
+            return;
+        }
+        let slot = &mut self.slots[self.current];
+        let size = data.len() as u64;
+        let start = slot.used;
+        let end = start + size.next_multiple_of(wgpu::MAP_ALIGNMENT);
+        if slot.mapped.load(Ordering::Acquire) && end <= slot.buffer.size() {
+            slot.buffer
+                .slice(start..start + size)
+                .get_mapped_range_mut()
+                .copy_from_slice(data);
+            slot.used = end;
+            encoder.copy_buffer_to_buffer(&slot.buffer, start, target, offset, size);
+            return;
+        }
+        let spill = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Spilled Staging"),
+            contents: data,
+            usage: wgpu::BufferUsages::COPY_SRC,
+        });
+        encoder.copy_buffer_to_buffer(&spill, 0, target, offset, size);
+    }
+
+    /// Hands this frame's staging to the encoder, after the frame's last
+    /// write and before the encoder is submitted.
+    pub fn finish(&mut self) {
+        let slot = &mut self.slots[self.current];
+        if slot.used > 0 && slot.mapped.swap(false, Ordering::AcqRel) {
+            slot.buffer.unmap();
+        }
+    }
+
+    /// Moves on to the next frame once the last one was submitted, asking
+    /// for its staging back for when the GPU is done copying out of it.
+    pub fn next_frame(&mut self) {
+        let slot = &mut self.slots[self.current];
+        if slot.used > 0 {
+            slot.used = 0;
+            let mapped = slot.mapped.clone();
+            slot.buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Write, move |result| {
+                    mapped.store(result.is_ok(), Ordering::Release);
+                });
+        }
+        self.current = (self.current + 1) % self.slots.len();
+    }
+}
+
+/// Uniform buffer holding a `T` for each frame in flight, bound at a
+/// dynamic offset, so copying a frame's values in never has to wait for
+/// the last frame's passes to stop reading theirs.
+pub struct UniformRing<T> {
+    buffer: wgpu::Buffer,
+    /// Bytes from one copy to the next, as the device aligns offsets.
+    stride: u64,
+    next: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: bytemuck::Pod> UniformRing<T> {
+    pub fn new(device: &wgpu::Device, label: &str) -> Self {
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let stride = (std::mem::size_of::<T>() as u64).next_multiple_of(alignment);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: stride * FRAMES_IN_FLIGHT as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer,
+            stride,
+            next: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    /// One `T` of the buffer, for a bind group entry whose layout has a
+    /// dynamic offset.
+    pub fn binding(&self) -> wgpu::BindingResource<'_> {
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: &self.buffer,
+            offset: 0,
+            size: NonZeroU64::new(std::mem::size_of::<T>() as u64),
+        })
+    }
+
+    /// Copies `value` into the next copy through `staging`, and returns the
+    /// dynamic offset to bind it at.
+    pub fn write(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        staging: &mut FrameStaging,
+        value: &T,
+    ) -> u32 {
+        let offset = self.next as u64 * self.stride;
+        self.next = (self.next + 1) % FRAMES_IN_FLIGHT;
+        staging.write(
+            device,
+            encoder,
+            &self.buffer,
+            offset,
+            bytemuck::bytes_of(value),
+        );
+        offset as u32
+    }
+}
@@ -0,0 +1,125 @@
+use std::{collections::HashSet, fmt, time::Duration};
+
+use web_time::Instant;
+
+use crate::{bvh::InstancedBvh, scene::Scene};
+
+/// How often `FrameStats` are measured.
+const INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SceneStats {
+    pub triangles: usize,
+    pub instances: usize,
+    /// Meshes the instances share.
+    pub unique_meshes: usize,
+    pub nodes: usize,
+    pub unique_materials: usize,
+    pub texture_bytes: usize,
+    pub lights: usize,
+    pub bvh_depth: u32,
+}
+
+impl SceneStats {
+    pub fn new(scene: &Scene, bvh: &InstancedBvh) -> Self {
+        let materials: HashSet<_> = scene.instances.iter().map(|i| i.material).collect();
+        let meshes: HashSet<_> = scene.instances.iter().map(|i| i.mesh).collect();
+        Self {
+            triangles: scene.triangle_count(),
+            instances: scene.instances.len(),
+            unique_meshes: meshes.len(),
+            nodes: scene.nodes.len(),
+            unique_materials: materials.len(),
+            texture_bytes: scene.textures.iter().map(|t| t.byte_size()).sum(),
+            lights: scene.lights.len(),
+            bvh_depth: bvh.depth(),
+        }
+    }
+}
+
+impl fmt::Display for SceneStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Triangles:        {}", self.triangles)?;
+        writeln!(f, "Instances:        {}", self.instances)?;
+        writeln!(f, "Unique meshes:    {}", self.unique_meshes)?;
+        writeln!(f, "Nodes:            {}", self.nodes)?;
+        writeln!(f, "Unique materials: {}", self.unique_materials)?;
+        writeln!(
+            f,
+            "Texture memory:   {:.2} MiB",
+            self.texture_bytes as f64 / (1024.0 * 1024.0)
+        )?;
+        writeln!(f, "Lights:           {}", self.lights)?;
+        write!(f, "BVH depth:        {}", self.bvh_depth)
+    }
+}
+
+/// How fast the window renders, measured every second so regressions show
+/// while navigating.
+#[derive(Clone, Debug)]
+pub struct FrameStats {
+    started: Instant,
+    frames: u32,
+    rays: u64,
+    pub fps: f32,
+    /// Camera rays, one per pixel per sample, traced per second.
+    pub rays_per_second: f64,
+    /// Samples per pixel so far.
+    pub samples: u32,
+    /// Bytes of buffers and textures, on backends that count them.
+    pub gpu_memory: Option<u64>,
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            frames: 0,
+            rays: 0,
+            fps: 0.0,
+            rays_per_second: 0.0,
+            samples: 0,
+            gpu_memory: None,
+        }
+    }
+}
+
+impl FrameStats {
+    /// Counts a frame that traced `rays`, returning whether the rates were
+    /// measured again.
+    pub fn frame(&mut self, device: &wgpu::Device, rays: u64, samples: u32) -> bool {
+        self.frames += 1;
+        self.rays += rays;
+        self.samples = samples;
+        let elapsed = self.started.elapsed();
+        if elapsed < INTERVAL {
+            return false;
+        }
+        let seconds = elapsed.as_secs_f64();
+        self.fps = (self.frames as f64 / seconds) as f32;
+        self.rays_per_second = self.rays as f64 / seconds;
+        let counters = device.get_internal_counters().hal;
+        let memory = counters.buffer_memory.read() + counters.texture_memory.read();
+        self.gpu_memory = (memory > 0).then_some(memory as u64);
+        self.started = Instant::now();
+        self.frames = 0;
+        self.rays = 0;
+        true
+    }
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.1} fps, {} spp, {:.1} Mrays/s",
+            self.fps,
+            self.samples,
+            self.rays_per_second / 1e6
+        )?;
+        if let Some(memory) = self.gpu_memory {
+            write!(f, ", {:.0} MiB GPU", memory as f64 / (1024.0 * 1024.0))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,337 @@
+//! GPU denoising of the path traced image with spatiotemporal
+//! variance-guided filtering, for when Open Image Denoise isn't available,
+//! as on the web. See svgf.wgsl.
+
+use wgpu::util::DeviceExt;
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    camera::{Camera, CameraUniform},
+    shader,
+    tracer::Renderer,
+};
+
+const WORKGROUP_SIZE: u32 = 8;
+/// Wavelet iterations, each doubling the distance between taps.
+const ITERATIONS: u32 = 5;
+
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct SvgfParams {
+    camera: CameraUniform,
+    prev_camera: CameraUniform,
+    samples: u32,
+    _pad: [u32; 3],
+}
+
+/// What a binding of svgf.wgsl holds.
+#[derive(Clone, Copy)]
+enum Slot {
+    Uniform,
+    Read,
+    Write,
+}
+
+pub struct Svgf {
+    temporal_pipeline: wgpu::ComputePipeline,
+    variance_pipeline: wgpu::ComputePipeline,
+    atrous_pipeline: wgpu::ComputePipeline,
+    modulate_pipeline: wgpu::ComputePipeline,
+    params_buffer: wgpu::Buffer,
+    /// Integrated illumination and luminance moments, ping-ponged between
+    /// frames.
+    history: [wgpu::TextureView; 2],
+    moments: [wgpu::TextureView; 2],
+    prev_normal: wgpu::Texture,
+    prev_normal_view: wgpu::TextureView,
+    /// Ping-ponged between wavelet iterations.
+    filter: [wgpu::TextureView; 2],
+    /// Distance between taps of every wavelet iteration.
+    step_buffers: Vec<wgpu::Buffer>,
+    output: wgpu::TextureView,
+    prev_camera: CameraUniform,
+    frame: usize,
+}
+
+impl Svgf {
+    pub fn new(device: &wgpu::Device, size: PhysicalSize<u32>) -> Self {
+        let shader = shader::create_module(
+            device,
+            "SVGF Shader",
+            "svgf.wgsl",
+            &[("WORKGROUP_SIZE", WORKGROUP_SIZE.to_string())],
+        );
+        // Float textures aren't filterable, so the layouts can't be derived
+        // from the shader
+        let pipeline = |label, entry_point, entries: &[(u32, Slot)]| {
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|&(binding, slot)| wgpu::BindGroupLayoutEntry {
+                    binding,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: match slot {
+                        Slot::Uniform => wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        Slot::Read => wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        Slot::Write => wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: wgpu::TextureFormat::Rgba32Float,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                    },
+                    count: None,
+                })
+                .collect();
+            let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(label),
+                entries: &entries,
+            });
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[&layout],
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+        let temporal_pipeline = pipeline(
+            "SVGF Temporal Pipeline",
+            "temporal",
+            &[
+                (0, Slot::Uniform),
+                (1, Slot::Read),
+                (2, Slot::Read),
+                (3, Slot::Read),
+                (4, Slot::Read),
+                (5, Slot::Read),
+                (6, Slot::Read),
+                (7, Slot::Write),
+                (8, Slot::Write),
+            ],
+        );
+        let variance_pipeline = pipeline(
+            "SVGF Variance Pipeline",
+            "variance",
+            &[
+                (3, Slot::Read),
+                (9, Slot::Read),
+                (10, Slot::Read),
+                (12, Slot::Write),
+            ],
+        );
+        let atrous_pipeline = pipeline(
+            "SVGF A-Trous Pipeline",
+            "atrous",
+            &[
+                (3, Slot::Read),
+                (11, Slot::Read),
+                (12, Slot::Write),
+                (13, Slot::Uniform),
+            ],
+        );
+        let modulate_pipeline = pipeline(
+            "SVGF Modulate Pipeline",
+            "modulate",
+            &[
+                (1, Slot::Read),
+                (2, Slot::Read),
+                (11, Slot::Read),
+                (14, Slot::Write),
+            ],
+        );
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("SVGF Params"),
+            size: std::mem::size_of::<SvgfParams>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let make_texture = |label| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: size.width.max(1),
+                    height: size.height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            })
+        };
+        let view = |label| make_texture(label).create_view(&Default::default());
+        let history = [view("SVGF History 0"), view("SVGF History 1")];
+        let moments = [view("SVGF Moments 0"), view("SVGF Moments 1")];
+        let filter = [view("SVGF Filter 0"), view("SVGF Filter 1")];
+        let output = view("SVGF Output");
+        let prev_normal = make_texture("SVGF Previous Normal");
+        let prev_normal_view = prev_normal.create_view(&Default::default());
+
+        let step_buffers = (0..ITERATIONS)
+            .map(|i| {
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("SVGF Step"),
+                    contents: bytemuck::bytes_of(&[1i32 << i, 0, 0, 0]),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                })
+            })
+            .collect();
+
+        Self {
+            temporal_pipeline,
+            variance_pipeline,
+            atrous_pipeline,
+            modulate_pipeline,
+            params_buffer,
+            history,
+            moments,
+            prev_normal,
+            prev_normal_view,
+            filter,
+            step_buffers,
+            output,
+            prev_camera: CameraUniform::default(),
+            frame: 0,
+        }
+    }
+
+    /// Denoises the latest result of `tracer`, which has to have the size
+    /// the filter was created with.
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        tracer: &dyn Renderer,
+        camera: &Camera,
+    ) {
+        let params = SvgfParams {
+            camera: camera.uniform(),
+            prev_camera: self.prev_camera,
+            samples: tracer.sample_count(),
+            _pad: [0; 3],
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+        self.prev_camera = params.camera;
+
+        let [albedo, normal] = tracer.guide_views();
+        let (read, write) = (self.frame % 2, 1 - self.frame % 2);
+        let texture = wgpu::BindingResource::TextureView;
+        let bind_group = |pipeline: &wgpu::ComputePipeline, entries: Vec<_>| {
+            let entries: Vec<_> = entries
+                .into_iter()
+                .map(|(binding, resource)| wgpu::BindGroupEntry { binding, resource })
+                .collect();
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("SVGF Bind Group"),
+                layout: &pipeline.get_bind_group_layout(0),
+                entries: &entries,
+            })
+        };
+        let temporal = bind_group(
+            &self.temporal_pipeline,
+            vec![
+                (0, self.params_buffer.as_entire_binding()),
+                (1, texture(tracer.output_view())),
+                (2, texture(albedo)),
+                (3, texture(normal)),
+                (4, texture(&self.prev_normal_view)),
+                (5, texture(&self.history[read])),
+                (6, texture(&self.moments[read])),
+                (7, texture(&self.history[write])),
+                (8, texture(&self.moments[write])),
+            ],
+        );
+        let variance = bind_group(
+            &self.variance_pipeline,
+            vec![
+                (3, texture(normal)),
+                (9, texture(&self.history[write])),
+                (10, texture(&self.moments[write])),
+                (12, texture(&self.filter[0])),
+            ],
+        );
+        // Iteration i reads filter i % 2 and writes the other one
+        let atrous: Vec<_> = self
+            .step_buffers
+            .iter()
+            .enumerate()
+            .map(|(i, step_buffer)| {
+                bind_group(
+                    &self.atrous_pipeline,
+                    vec![
+                        (3, texture(normal)),
+                        (11, texture(&self.filter[i % 2])),
+                        (12, texture(&self.filter[1 - i % 2])),
+                        (13, step_buffer.as_entire_binding()),
+                    ],
+                )
+            })
+            .collect();
+        let modulate = bind_group(
+            &self.modulate_pipeline,
+            vec![
+                (1, texture(tracer.output_view())),
+                (2, texture(albedo)),
+                (11, texture(&self.filter[ITERATIONS as usize % 2])),
+                (14, texture(&self.output)),
+            ],
+        );
+
+        let size = tracer.output_texture().size();
+        let workgroups = (
+            size.width.div_ceil(WORKGROUP_SIZE),
+            size.height.div_ceil(WORKGROUP_SIZE),
+        );
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("SVGF Pass"),
+            timestamp_writes: None,
+        });
+        for (pipeline, bind_group) in [
+            (&self.temporal_pipeline, &temporal),
+            (&self.variance_pipeline, &variance),
+        ] {
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+        }
+        pass.set_pipeline(&self.atrous_pipeline);
+        for bind_group in &atrous {
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+        }
+        pass.set_pipeline(&self.modulate_pipeline);
+        pass.set_bind_group(0, &modulate, &[]);
+        pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+        drop(pass);
+
+        encoder.copy_texture_to_texture(
+            tracer.normal_texture().as_image_copy(),
+            self.prev_normal.as_image_copy(),
+            size,
+        );
+        self.frame += 1;
+    }
+
+    pub fn output_view(&self) -> &wgpu::TextureView {
+        &self.output
+    }
+}
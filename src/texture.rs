@@ -0,0 +1,387 @@
+//! Material textures. Images are resized into a few square size classes,
+//! each stored as one texture array with a full mip chain generated on the
+//! GPU, so that the trace shader can index any texture without binding
+//! arrays.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use image::{imageops::FilterType, DynamicImage, RgbaImage};
+use wgpu::util::DeviceExt;
+
+use crate::shader;
+
+/// Side lengths of the texture arrays, textures are scaled to the
+/// smallest one that holds them.
+pub const SIZE_CLASSES: [u32; 4] = [256, 512, 1024, 2048];
+const WORKGROUP_SIZE: u32 = 8;
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+/// An 8-bit RGBA image.
+#[derive(Clone, Debug, Default)]
+pub struct Texture {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+    /// Color stored with the sRGB transfer function, as opposed to data
+    /// like roughness or normals. Mipmaps of sRGB textures are averaged in
+    /// linear light.
+    pub srgb: bool,
+}
+
+impl Texture {
+    /// Loads a color texture from PNG, JPEG, OpenEXR or anything else the
+    /// image crate reads. Float images are encoded as sRGB.
+    pub fn load(path: &Path) -> Result<Self> {
+        let image = image::open(path)
+            .with_context(|| format!("Failed to load texture {}", path.display()))?;
+        let image = match image {
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+                let linear = image.into_rgba32f();
+                RgbaImage::from_fn(linear.width(), linear.height(), |x, y| {
+                    let [r, g, b, a] = linear.get_pixel(x, y).0;
+                    image::Rgba([
+                        encode_srgb(r),
+                        encode_srgb(g),
+                        encode_srgb(b),
+                        (a.clamp(0.0, 1.0) * 255.0 + 0.5) as u8,
+                    ])
+                })
+            }
+            image => image.into_rgba8(),
+        };
+        Ok(Self {
+            name: path.display().to_string(),
+            width: image.width(),
+            height: image.height(),
+            pixels: image.into_raw(),
+            srgb: true,
+        })
+    }
+
+    pub fn byte_size(&self) -> usize {
+        self.pixels.len()
+    }
+
+    fn size_class(&self, max_size: u32) -> usize {
+        let size = self.width.max(self.height);
+        SIZE_CLASSES
+            .iter()
+            .position(|&class| class >= size || class >= max_size)
+            .unwrap_or(SIZE_CLASSES.len() - 1)
+    }
+
+    /// The pixels stretched to a square of `size`.
+    fn resized(&self, size: u32) -> Vec<u8> {
+        if self.width == size && self.height == size {
+            return self.pixels.clone();
+        }
+        let image = RgbaImage::from_raw(self.width, self.height, self.pixels.clone())
+            .expect("pixels match the texture size");
+        image::imageops::resize(&image, size, size, FilterType::Triangle).into_raw()
+    }
+}
+
+/// Clamps linear light and encodes it like an sRGB surface would.
+pub(crate) fn encode_srgb(linear: f32) -> u8 {
+    (encode_srgb_f32(linear) * 255.0 + 0.5) as u8
+}
+
+/// `encode_srgb` between 0 and 1, without rounding to 8 bits.
+pub(crate) fn encode_srgb_f32(linear: f32) -> f32 {
+    let linear = linear.clamp(0.0, 1.0);
+    if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Linear light of an sRGB encoded value between 0 and 1.
+pub(crate) fn decode_srgb(srgb: f32) -> f32 {
+    if srgb <= 0.04045 {
+        srgb / 12.92
+    } else {
+        ((srgb + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// The textures of a scene uploaded into one array per size class, bound
+/// together with a trilinear sampler.
+pub struct GpuTextures {
+    pub layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    references: Vec<u32>,
+}
+
+impl GpuTextures {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, textures: &[Texture]) -> Self {
+        let limits = device.limits();
+        let mut layers: [Vec<usize>; SIZE_CLASSES.len()] = Default::default();
+        let mut references = Vec::with_capacity(textures.len());
+        for (index, texture) in textures.iter().enumerate() {
+            let class = texture.size_class(limits.max_texture_dimension_2d);
+            if layers[class].len() as u32 >= limits.max_texture_array_layers {
+                tracing::warn!("Too many textures, skipping {}", texture.name);
+                references.push(0);
+                continue;
+            }
+            references.push(1 + ((layers[class].len() as u32) << 2 | class as u32));
+            layers[class].push(index);
+        }
+
+        let shader = shader::create_module(
+            device,
+            "Mipmap Shader",
+            "mipmap.wgsl",
+            &[("WORKGROUP_SIZE", WORKGROUP_SIZE.to_string())],
+        );
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Mipmap Pipeline"),
+            layout: None,
+            module: &shader,
+            entry_point: "downsample",
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mipmap Encoder"),
+        });
+        let arrays: Vec<wgpu::Texture> = layers
+            .iter()
+            .zip(SIZE_CLASSES)
+            .map(|(layers, size)| {
+                let size = size.min(limits.max_texture_dimension_2d);
+                // Bindings can't be left empty
+                let size = if layers.is_empty() { 1 } else { size };
+                let pixels: Vec<(Vec<u8>, bool)> = layers
+                    .iter()
+                    .map(|&i| (textures[i].resized(size), textures[i].srgb))
+                    .collect();
+                upload_array(device, queue, &mut encoder, &pipeline, size, &pixels)
+            })
+            .collect();
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let array_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2Array,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Texture Bind Group Layout"),
+            entries: &[
+                array_entry(0),
+                array_entry(1),
+                array_entry(2),
+                array_entry(3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let views: Vec<wgpu::TextureView> = arrays
+            .iter()
+            .map(|array| {
+                array.create_view(&wgpu::TextureViewDescriptor {
+                    dimension: Some(wgpu::TextureViewDimension::D2Array),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Texture Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let mut entries: Vec<wgpu::BindGroupEntry> = views
+            .iter()
+            .enumerate()
+            .map(|(binding, view)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: wgpu::BindingResource::TextureView(view),
+            })
+            .collect();
+        entries.push(wgpu::BindGroupEntry {
+            binding: 4,
+            resource: wgpu::BindingResource::Sampler(&sampler),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Texture Bind Group"),
+            layout: &layout,
+            entries: &entries,
+        });
+
+        tracing::info!(
+            "Uploaded {} textures, layers per size class: {:?}",
+            references
+                .iter()
+                .filter(|&&reference| reference != 0)
+                .count(),
+            layers.each_ref().map(Vec::len)
+        );
+        Self {
+            layout,
+            bind_group,
+            references,
+        }
+    }
+
+    /// Packs the array and layer of a scene texture for the shader: one
+    /// plus the layer shifted past two bits of size class, or 0 for none.
+    pub fn reference(&self, texture: Option<usize>) -> u32 {
+        texture
+            .and_then(|index| self.references.get(index).copied())
+            .unwrap_or(0)
+    }
+}
+
+/// Creates a texture array with a full mip chain from the pixels of its
+/// layers. There are always at least two layers, as the GL backend
+/// guesses the view dimension from the layer count.
+fn upload_array(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    encoder: &mut wgpu::CommandEncoder,
+    pipeline: &wgpu::ComputePipeline,
+    size: u32,
+    layers: &[(Vec<u8>, bool)],
+) -> wgpu::Texture {
+    let layer_count = layers.len().max(2) as u32;
+    let array = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Texture Array"),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: layer_count,
+        },
+        mip_level_count: size.ilog2() + 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    // Levels are downsampled between scratch textures and then copied
+    // into place, some backends can't read and write levels of one texture
+    let scratch = |size| {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Mipmap Level"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: layer_count,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        })
+    };
+    let copy_level = |encoder: &mut wgpu::CommandEncoder, level: &wgpu::Texture, mip_level| {
+        encoder.copy_texture_to_texture(
+            level.as_image_copy(),
+            wgpu::ImageCopyTexture {
+                texture: &array,
+                mip_level,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            level.size(),
+        );
+    };
+
+    let mut level = scratch(size);
+    for (layer, (pixels, _)) in layers.iter().enumerate() {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &level,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer as u32,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * size),
+                rows_per_image: Some(size),
+            },
+            wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+    copy_level(encoder, &level, 0);
+
+    let mut srgb: Vec<u32> = layers.iter().map(|&(_, srgb)| srgb as u32).collect();
+    srgb.resize(layer_count as usize, 0);
+    let srgb_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Mipmap sRGB Flags"),
+        contents: bytemuck::cast_slice(&srgb),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+    for mip_level in 1..array.mip_level_count() {
+        let next = scratch(size >> mip_level);
+        let view = |texture: &wgpu::Texture| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                ..Default::default()
+            })
+        };
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Mipmap Bind Group"),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view(&level)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view(&next)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: srgb_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Mipmap Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        let groups = next.width().div_ceil(WORKGROUP_SIZE);
+        pass.dispatch_workgroups(groups, groups, layer_count);
+        drop(pass);
+        copy_level(encoder, &next, mip_level);
+        level = next;
+    }
+    array
+}
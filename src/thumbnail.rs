@@ -0,0 +1,186 @@
+//! Quick previews of scenes rendered without a window, for asset managers
+//! and file browsers.
+
+use std::{
+    cell::RefCell,
+    f32::consts::{PI, TAU},
+    rc::Rc,
+};
+
+use anyhow::{Context, Result};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    bvh::InstancedBvh,
+    camera::Camera,
+    lod,
+    lut::{self, DisplayLut},
+    readback::Readbacks,
+    scene::Scene,
+    tonemap::Tonemap,
+    tracer::{Backend, TraceSettings},
+};
+
+pub const DEFAULT_SAMPLES: u32 = 32;
+/// Bounces of the draft, enough for glass but not much more.
+const MAX_DEPTH: u32 = 4;
+/// View from the front right of the scene, looking down a little.
+const YAW: f32 = -30.0 * PI / 180.0;
+const PITCH: f32 = -20.0 * PI / 180.0;
+
+/// Renders a square image of `scene` seen from above and to the front
+/// right, framed to show all of it. A transparent background is left out
+/// of the image with zero alpha. Colors are encoded as sRGB, or by the
+/// display LUT if there is one. Traces with `backend`, by default on the
+/// CPU when the only adapter is a software one.
+pub fn render(
+    scene: Scene,
+    size: u32,
+    samples: u32,
+    transparent: bool,
+    display_lut: Option<&DisplayLut>,
+    backend: Option<Backend>,
+) -> Result<image::RgbaImage> {
+    let hdr = render_hdr(scene, size, samples, transparent, backend)?;
+    Ok(lut::display_image(&hdr, 1.0, Tonemap::None, display_lut))
+}
+
+/// Linear light with premultiplied alpha, as the tracer leaves it.
+pub fn render_hdr(
+    scene: Scene,
+    size: u32,
+    samples: u32,
+    transparent: bool,
+    backend: Option<Backend>,
+) -> Result<image::Rgba32FImage> {
+    let mut images = render_views(scene, &[YAW], size, samples, transparent, backend)?;
+    Ok(images.remove(0))
+}
+
+/// Renders `views` turntable stops around `scene` and lays them out in a
+/// grid, left to right and top to bottom, for reviewing an asset at a
+/// glance. The first stop is the thumbnail's view. Cells left over in the
+/// last row stay transparent.
+pub fn contact_sheet(
+    scene: Scene,
+    views: u32,
+    size: u32,
+    samples: u32,
+    transparent: bool,
+    display_lut: Option<&DisplayLut>,
+    backend: Option<Backend>,
+) -> Result<image::RgbaImage> {
+    let yaws: Vec<f32> = (0..views)
+        .map(|view| YAW + view as f32 / views as f32 * TAU)
+        .collect();
+    let images = render_views(scene, &yaws, size, samples, transparent, backend)?;
+    let columns = (views as f32).sqrt().ceil() as u32;
+    let rows = views.div_ceil(columns);
+    let mut sheet = image::RgbaImage::new(columns * size, rows * size);
+    for (index, image) in (0..).zip(&images) {
+        let image = lut::display_image(image, 1.0, Tonemap::None, display_lut);
+        let (x, y) = (index % columns * size, index / columns * size);
+        image::imageops::replace(&mut sheet, &image, x.into(), y.into());
+    }
+    Ok(sheet)
+}
+
+/// Square images of `scene` from above at each of `yaws`, sharing the
+/// device and acceleration structure.
+fn render_views(
+    mut scene: Scene,
+    yaws: &[f32],
+    size: u32,
+    samples: u32,
+    transparent: bool,
+    backend: Option<Backend>,
+) -> Result<Vec<image::Rgba32FImage>> {
+    let (adapter, device, queue) = request_device()?;
+
+    let cameras: Vec<Camera> = yaws
+        .iter()
+        .map(|&yaw| {
+            let mut camera = Camera {
+                yaw,
+                pitch: PITCH,
+                ..Default::default()
+            };
+            camera.frame(&scene.bounds(), 1.0);
+            camera
+        })
+        .collect();
+    // Every stop is as far from the scene, so one selection suits them all
+    if let Some(camera) = cameras.first() {
+        lod::select(&mut scene, camera.position, camera.fov_y, size);
+    }
+    let bvh = InstancedBvh::new(&scene);
+    let backend = choose_backend(&adapter, backend);
+    let mut tracer = backend.create(&device, &queue, &scene, &bvh, PhysicalSize::new(size, size));
+    let settings = TraceSettings {
+        max_depth: MAX_DEPTH,
+        transparent,
+        ..Default::default()
+    };
+    let images = Rc::new(RefCell::new(Vec::with_capacity(cameras.len())));
+    let mut readbacks = Readbacks::default();
+    for camera in &cameras {
+        tracer.reset();
+        for _ in 0..samples {
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Thumbnail Encoder"),
+            });
+            tracer.render_frame(&device, &queue, &mut encoder, camera, &settings);
+            queue.submit(std::iter::once(encoder.finish()));
+        }
+        // The next view is traced while this one comes back
+        let images = images.clone();
+        readbacks.read_texture(&device, tracer.output_texture(), 0, move |image| {
+            images.borrow_mut().push(image);
+        });
+        readbacks.submit(&queue);
+    }
+    finish_readbacks(&device, &queue, &mut readbacks);
+    Ok(images.take())
+}
+
+/// Hands every request of `readbacks` its data. Batch renders have nothing
+/// else to do by then, so they wait on the GPU here, where the event loop
+/// only ever polls.
+pub(crate) fn finish_readbacks(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    readbacks: &mut Readbacks,
+) {
+    readbacks.submit(queue);
+    while !readbacks.is_idle() {
+        device.poll(wgpu::Maintain::Wait);
+        readbacks.poll(device);
+    }
+}
+
+/// A device of the best adapter there is, without a window, falling back
+/// to a software one.
+pub(crate) fn request_device() -> Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::default();
+    let request = |force_fallback_adapter| {
+        pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+            force_fallback_adapter,
+        }))
+    };
+    let adapter = request(false)
+        .or_else(|| request(true))
+        .context("No GPU adapter found")?;
+    let (device, queue) = pollster::block_on(adapter.request_device(&Default::default(), None))
+        .context("Failed to create a GPU device")?;
+    Ok((adapter, device, queue))
+}
+
+/// `backend`, or by default the CPU when `adapter` is a software one.
+pub(crate) fn choose_backend(adapter: &wgpu::Adapter, backend: Option<Backend>) -> Backend {
+    backend.unwrap_or(match adapter.get_info().device_type {
+        wgpu::DeviceType::Cpu => Backend::Cpu,
+        _ => Backend::Gpu,
+    })
+}
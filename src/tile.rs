@@ -0,0 +1,179 @@
+//! Tiles of a render handed out to different machines, and checks that
+//! they come back right. The CPU traces a tile exactly as it traces that
+//! part of the whole image, whichever machine runs it, so a tile can be
+//! hashed and compared with a redundant render of it:
+//!
+//! - `render --tile x,y,width,height` prints the tile's hash, which covers
+//!   the scene inputs it read as well as the pixels.
+//! - `export-job --tile-size 256` lists the tiles of a job and marks a
+//!   sample of them, `--verify-rate` of them, to render twice.
+//! - `Verifier` compares the hashes and points out the workers that
+//!   return tiles they didn't render right.
+
+use std::{fmt, path::PathBuf};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use winit::dpi::PhysicalSize;
+
+/// Fraction of tiles rendered twice by default.
+pub const DEFAULT_VERIFY_RATE: f32 = 0.05;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    /// Parses `x,y,width,height`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut numbers = text.split(',').map(|number| number.trim().parse().ok());
+        let tile = Self {
+            x: numbers.next()??,
+            y: numbers.next()??,
+            width: numbers.next()??,
+            height: numbers.next()??,
+        };
+        (numbers.next().is_none() && tile.width > 0 && tile.height > 0).then_some(tile)
+    }
+
+    pub fn size(self) -> PhysicalSize<u32> {
+        PhysicalSize::new(self.width, self.height)
+    }
+
+    pub fn fits(self, image: PhysicalSize<u32>) -> bool {
+        self.x.saturating_add(self.width) <= image.width
+            && self.y.saturating_add(self.height) <= image.height
+    }
+
+    /// Tiles of `size` covering `image` row by row, smaller at the right
+    /// and bottom edges.
+    pub fn grid(image: PhysicalSize<u32>, size: u32) -> Vec<Self> {
+        let mut tiles = Vec::new();
+        for y in (0..image.height).step_by(size as usize) {
+            for x in (0..image.width).step_by(size as usize) {
+                tiles.push(Self {
+                    x,
+                    y,
+                    width: size.min(image.width - x),
+                    height: size.min(image.height - y),
+                });
+            }
+        }
+        tiles
+    }
+}
+
+impl fmt::Display for Tile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+/// SHA-256 in hex of the contents of `inputs`, in order, so renders of the
+/// same files hash alike wherever they are.
+pub fn inputs_hash(inputs: &[PathBuf]) -> Result<String> {
+    let mut hasher = Sha256::new();
+    for path in inputs {
+        let contents = std::fs::read(path)
+            .with_context(|| format!("Failed to read input {}", path.display()))?;
+        hasher.update(Sha256::digest(&contents));
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+/// SHA-256 in hex of the rendered `tile` and the `inputs_hash` of what it
+/// was rendered from.
+pub fn hash(inputs: &str, tile: Tile, image: &image::Rgba32FImage) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(inputs.as_bytes());
+    for number in [tile.x, tile.y, tile.width, tile.height] {
+        hasher.update(number.to_le_bytes());
+    }
+    for value in image.as_raw() {
+        hasher.update(value.to_bits().to_le_bytes());
+    }
+    hex(&hasher.finalize())
+}
+
+/// Lowercase hex of a digest.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// What a worker has returned that was checked.
+#[derive(Clone, Debug, Default)]
+pub struct WorkerRecord {
+    pub worker: String,
+    pub verified: u32,
+    pub mismatched: u32,
+}
+
+/// Picks the tiles to render twice and keeps count of the workers whose
+/// tiles didn't match.
+#[derive(Clone, Debug)]
+pub struct Verifier {
+    /// Fraction of tiles checked.
+    pub rate: f32,
+    pub seed: u64,
+    pub workers: Vec<WorkerRecord>,
+}
+
+impl Verifier {
+    pub fn new(rate: f32, seed: u64) -> Self {
+        Self {
+            rate: rate.clamp(0.0, 1.0),
+            seed,
+            workers: Vec::new(),
+        }
+    }
+
+    /// Whether tile `index` of a job is rendered again, the same tiles for
+    /// the same seed.
+    pub fn should_verify(&self, index: usize) -> bool {
+        // SplitMix64 of the seed and index, uniform enough to sample with
+        let mut z = self
+            .seed
+            .wrapping_add((index as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        ((z >> 40) as f32 / (1u64 << 24) as f32) < self.rate
+    }
+
+    /// Checks the `hash` of a tile from `worker` against the `reference`
+    /// hash of a render that's trusted, such as one on the machine handing
+    /// out tiles, and returns whether they match.
+    pub fn record(&mut self, worker: &str, hash: &str, reference: &str) -> bool {
+        let matched = hash.eq_ignore_ascii_case(reference);
+        let index = match self
+            .workers
+            .iter()
+            .position(|record| record.worker == worker)
+        {
+            Some(index) => index,
+            None => {
+                self.workers.push(WorkerRecord {
+                    worker: worker.to_owned(),
+                    ..Default::default()
+                });
+                self.workers.len() - 1
+            }
+        };
+        let record = &mut self.workers[index];
+        record.verified += 1;
+        if !matched {
+            record.mismatched += 1;
+            tracing::warn!("Tile from {worker} doesn't match its redundant render");
+        }
+        matched
+    }
+
+    /// Workers that have returned a tile that didn't match.
+    pub fn misbehaving(&self) -> impl Iterator<Item = &WorkerRecord> {
+        self.workers.iter().filter(|record| record.mismatched > 0)
+    }
+}
@@ -0,0 +1,252 @@
+//! Keyframed animation of render and scene parameters.
+//!
+//! Parameters are addressed like control surface targets, see
+//! [`ControlTarget`]. Timelines are saved as text files of lines
+//! `<time> <target> <value> [<easing>]`, and `duration <seconds>` sets the
+//! length if it should run past the last key.
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use crate::control::ControlTarget;
+
+/// How a value moves from one key to the next.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    /// Holds the value until the next key.
+    Step,
+}
+
+impl Easing {
+    pub const ALL: [Self; 5] = [
+        Self::Linear,
+        Self::EaseIn,
+        Self::EaseOut,
+        Self::EaseInOut,
+        Self::Step,
+    ];
+
+    /// Maps linear progress between keys to eased progress, both from 0 to 1.
+    pub fn apply(self, t: f32) -> f32 {
+        match self {
+            Self::Linear => t,
+            Self::EaseIn => t * t * t,
+            Self::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Self::EaseInOut => t * t * (3.0 - 2.0 * t),
+            Self::Step => 0.0,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Linear => "linear",
+            Self::EaseIn => "ease_in",
+            Self::EaseOut => "ease_out",
+            Self::EaseInOut => "ease_in_out",
+            Self::Step => "step",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|easing| easing.name() == name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    /// In seconds from the start.
+    pub time: f32,
+    pub value: f32,
+    /// Easing towards the following key.
+    pub easing: Easing,
+}
+
+/// The keys of one parameter, sorted by time.
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub target: ControlTarget,
+    pub keys: Vec<Keyframe>,
+}
+
+impl Track {
+    pub fn sample(&self, time: f32) -> Option<f32> {
+        let i = self.keys.partition_point(|key| key.time <= time);
+        let Some(next) = self.keys.get(i) else {
+            return self.keys.last().map(|key| key.value);
+        };
+        if i == 0 {
+            return Some(next.value);
+        }
+        let key = self.keys[i - 1];
+        let t = (time - key.time) / (next.time - key.time);
+        Some(key.value + (next.value - key.value) * key.easing.apply(t))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Timeline {
+    pub tracks: Vec<Track>,
+    /// Length in seconds, at least up to the last key.
+    pub duration: f32,
+    /// Playhead in seconds.
+    pub time: f32,
+    pub playing: bool,
+    pub looping: bool,
+    /// Playhead of the values last handed out by `update`.
+    applied: Option<f32>,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self {
+            tracks: Vec::new(),
+            duration: 10.0,
+            time: 0.0,
+            playing: false,
+            looping: true,
+            applied: None,
+        }
+    }
+}
+
+impl Timeline {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read timeline {}", path.display()))?;
+        let mut timeline = Self {
+            duration: 0.0,
+            ..Default::default()
+        };
+        for (number, line) in text.lines().enumerate() {
+            let location = || format!("{}:{}", path.display(), number + 1);
+            let line = line.split('#').next().unwrap_or_default();
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (time, target, value, easing) = match fields[..] {
+                [] => continue,
+                ["duration", duration] => {
+                    timeline.duration = duration
+                        .parse()
+                        .with_context(|| format!("{}: Invalid duration", location()))?;
+                    continue;
+                }
+                [time, target, value] => (time, target, value, "linear"),
+                [time, target, value, easing] => (time, target, value, easing),
+                _ => bail!(
+                    "{}: Expected a time, a target, a value and an optional easing",
+                    location()
+                ),
+            };
+            let target = ControlTarget::parse(target)
+                .with_context(|| format!("{}: Unknown target {target}", location()))?;
+            let easing = Easing::parse(easing)
+                .with_context(|| format!("{}: Unknown easing {easing}", location()))?;
+            let (time, value) = time
+                .parse()
+                .and_then(|time| Ok((time, value.parse()?)))
+                .with_context(|| format!("{}: Invalid time or value", location()))?;
+            timeline.insert(target, time, value, easing);
+        }
+        Ok(timeline)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut text = format!("duration {}\n", self.duration);
+        for track in &self.tracks {
+            for key in &track.keys {
+                text += &format!(
+                    "{} {} {} {}\n",
+                    key.time,
+                    track.target.address(),
+                    key.value,
+                    key.easing.name()
+                );
+            }
+        }
+        std::fs::write(path, text)
+            .with_context(|| format!("Failed to write timeline {}", path.display()))
+    }
+
+    /// Adds a key, replacing one of the same target at the same time.
+    pub fn insert(&mut self, target: ControlTarget, time: f32, value: f32, easing: Easing) {
+        let index = match self.tracks.iter().position(|t| t.target == target) {
+            Some(index) => index,
+            None => {
+                self.tracks.push(Track {
+                    target,
+                    keys: Vec::new(),
+                });
+                self.tracks.len() - 1
+            }
+        };
+        let keys = &mut self.tracks[index].keys;
+        let key = Keyframe {
+            time,
+            value,
+            easing,
+        };
+        let i = keys.partition_point(|k| k.time < time);
+        match keys.get_mut(i) {
+            Some(existing) if existing.time == time => *existing = key,
+            _ => keys.insert(i, key),
+        }
+        self.duration = self.duration.max(time);
+        self.applied = None;
+    }
+
+    pub fn remove(&mut self, track: usize, key: usize) {
+        self.tracks[track].keys.remove(key);
+        if self.tracks[track].keys.is_empty() {
+            self.tracks.remove(track);
+        }
+        self.applied = None;
+    }
+
+    /// Replaces the tracks of the targets `other` animates with its own.
+    pub fn merge(&mut self, other: Timeline) {
+        self.tracks
+            .retain(|track| other.tracks.iter().all(|t| t.target != track.target));
+        self.tracks.extend(other.tracks);
+        self.duration = self.duration.max(other.duration);
+        self.applied = None;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+
+    /// Every animated parameter at `time`.
+    pub fn sample(&self, time: f32) -> Vec<(ControlTarget, f32)> {
+        self.tracks
+            .iter()
+            .filter_map(|track| Some((track.target, track.sample(time)?)))
+            .collect()
+    }
+
+    /// Plays the timeline forward by `dt` seconds. Returns the parameters
+    /// if the playhead moved or the keys changed since the last call, so
+    /// scrubbing previews without overriding other edits while paused.
+    pub fn update(&mut self, dt: f32) -> Option<Vec<(ControlTarget, f32)>> {
+        if self.playing {
+            self.time += dt;
+            if self.time > self.duration {
+                if self.looping && self.duration > 0.0 {
+                    self.time %= self.duration;
+                } else {
+                    self.time = self.duration;
+                    self.playing = false;
+                }
+            }
+        }
+        if self.is_empty() || self.applied == Some(self.time) {
+            return None;
+        }
+        self.applied = Some(self.time);
+        Some(self.sample(self.time))
+    }
+}
@@ -0,0 +1,364 @@
+//! Tone mapping of the exposed scene light for display, in a pass of its
+//! own between the render pass and the surface, see tonemap.wgsl. The
+//! render pass draws into a float texture the size of the window, which
+//! this pass maps with the selected operator and, on surfaces that don't
+//! encode sRGB themselves, encodes. Saved images go through the same
+//! operators on the CPU.
+//!
+//! The pass also draws focus peaking, marking the edges that are in focus
+//! so the depth of field can be judged before the samples converge.
+
+use glam::{Mat3, Vec3};
+use winit::dpi::PhysicalSize;
+
+use crate::{camera::Camera, shader};
+
+/// Format of the texture between the render and tone mapping passes.
+pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// How light above white is brought into range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tonemap {
+    /// Clipped at white.
+    #[default]
+    None,
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve.
+    Aces,
+    /// Troy Sobotka's AgX, which desaturates highlights rather than skewing
+    /// their hue.
+    Agx,
+}
+
+impl Tonemap {
+    pub const ALL: [Self; 4] = [Self::None, Self::Reinhard, Self::Aces, Self::Agx];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|tonemap| tonemap.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Reinhard => "reinhard",
+            Self::Aces => "aces",
+            Self::Agx => "agx",
+        }
+    }
+
+    /// The operator after this one, wrapping around.
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&tonemap| tonemap == self);
+        Self::ALL[index.map_or(0, |index| (index + 1) % Self::ALL.len())]
+    }
+
+    /// Linear display light of linear scene light, as tonemap.wgsl maps it.
+    pub fn apply(self, color: Vec3) -> Vec3 {
+        let color = color.max(Vec3::ZERO);
+        match self {
+            Self::None => color,
+            Self::Reinhard => color / (1.0 + color),
+            Self::Aces => {
+                let x = color * 0.6;
+                ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14))
+                    .clamp(Vec3::ZERO, Vec3::ONE)
+            }
+            Self::Agx => agx(color),
+        }
+    }
+}
+
+/// Largest circle of confusion, in image pixels, that focus peaking counts
+/// as in focus.
+const MAX_BLUR: f32 = 1.0;
+
+/// What focus peaking needs of the camera.
+#[derive(Clone, Copy, Debug)]
+pub struct FocusPeaking {
+    pub focus_distance: f32,
+    /// Circle of confusion in image pixels of a point at distance `d` is
+    /// this times `|d - focus_distance| / d`.
+    pub blur_scale: f32,
+    /// Image pixels per window pixel.
+    pub resolution_scale: f32,
+}
+
+impl FocusPeaking {
+    /// For the thin lens of `camera` and an image `height` pixels tall,
+    /// scaled by `resolution_scale` from the window.
+    pub fn new(camera: &Camera, height: u32, resolution_scale: f32) -> Self {
+        // The lens blurs a point over its diameter scaled by how far out of
+        // focus it is, measured on the plane in focus
+        let pixel = 2.0 * camera.focus_distance * (0.5 * camera.fov_y).tan() / height as f32;
+        Self {
+            focus_distance: camera.focus_distance,
+            blur_scale: 2.0 * camera.aperture_radius() / pixel,
+            resolution_scale,
+        }
+    }
+}
+
+/// Range of the log encoding AgX works in, in stops around middle gray.
+const AGX_MIN_EV: f32 = -12.47393;
+const AGX_MAX_EV: f32 = 4.026069;
+
+fn agx(color: Vec3) -> Vec3 {
+    let inset = Mat3::from_cols_array(&[
+        0.8424791, 0.04232824, 0.04237565, 0.0784336, 0.8784686, 0.0784336, 0.07922375, 0.07916613,
+        0.879143,
+    ]);
+    let outset = Mat3::from_cols_array(&[
+        1.196879,
+        -0.05289685,
+        -0.05297164,
+        -0.09802088,
+        1.151903,
+        -0.09804345,
+        -0.09902974,
+        -0.09896118,
+        1.151074,
+    ]);
+    let x = (inset * color).max(Vec3::splat(1e-10));
+    let x = Vec3::from_array(x.to_array().map(f32::log2))
+        .clamp(Vec3::splat(AGX_MIN_EV), Vec3::splat(AGX_MAX_EV));
+    let x = (x - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+    // Polynomial fit of the sigmoid, giving display values
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let x =
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232;
+    (outset * x).clamp(Vec3::ZERO, Vec3::ONE).powf(2.2)
+}
+
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct TonemapParams {
+    /// Operator, matching tonemap.wgsl.
+    curve: u32,
+    /// Whether the surface stores values as they are.
+    encode_srgb: u32,
+    focus_peaking: u32,
+    /// Image pixels per window pixel.
+    resolution_scale: f32,
+    focus_distance: f32,
+    blur_scale: f32,
+    max_blur: f32,
+    _pad: u32,
+}
+
+pub struct TonemapPass {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    params_buffer: wgpu::Buffer,
+    /// Format of the surface drawn to.
+    format: wgpu::TextureFormat,
+    /// What the render pass draws into.
+    view: wgpu::TextureView,
+}
+
+impl TonemapPass {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        size: PhysicalSize<u32>,
+    ) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tonemap Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tonemap Params"),
+            size: std::mem::size_of::<TonemapParams>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let module = shader::create_module(device, "Tonemap Shader", "tonemap.wgsl", &[]);
+        let pipeline = pipeline(device, &layout, format, &module);
+        Self {
+            pipeline,
+            layout,
+            params_buffer,
+            format,
+            view: target(device, size),
+        }
+    }
+
+    /// The texture the render pass draws into, which must match the size
+    /// of the surface.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, size: PhysicalSize<u32>) {
+        self.view = target(device, size);
+    }
+
+    /// Maps what the render pass drew onto `surface` with `tonemap`, and
+    /// marks what's in focus if `peaking` is given with the normal guide of
+    /// the tracer, which has the distance to the first hit in alpha.
+    pub fn draw(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        surface: &wgpu::TextureView,
+        tonemap: Tonemap,
+        peaking: Option<(FocusPeaking, &wgpu::TextureView)>,
+    ) {
+        let params = TonemapParams {
+            curve: tonemap as u32,
+            encode_srgb: !self.format.is_srgb() as u32,
+            focus_peaking: peaking.is_some() as u32,
+            resolution_scale: peaking.map_or(1.0, |(peaking, _)| peaking.resolution_scale),
+            focus_distance: peaking.map_or(0.0, |(peaking, _)| peaking.focus_distance),
+            blur_scale: peaking.map_or(0.0, |(peaking, _)| peaking.blur_scale),
+            max_blur: MAX_BLUR,
+            _pad: 0,
+        };
+        // Something has to be bound when there's no guide to read
+        let guide = peaking.map_or(&self.view, |(_, guide)| guide);
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tonemap Bind Group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&self.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(guide),
+                },
+            ],
+        });
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: surface,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    #[cfg(feature = "hot-reload")]
+    pub fn reload_shaders(&mut self, device: &wgpu::Device, changed: &[String]) {
+        use crate::hot_reload;
+
+        if let Some(source) = hot_reload::compose("tonemap.wgsl", &[], changed) {
+            let (layout, format) = (&self.layout, self.format);
+            if let Some(pipeline) =
+                hot_reload::compile(device, "Tonemap Shader", &source, |module| {
+                    pipeline(device, layout, format, module)
+                })
+            {
+                self.pipeline = pipeline;
+            }
+        }
+    }
+}
+
+fn pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+    module: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Tonemap Pipeline Layout"),
+        bind_group_layouts: &[layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Tonemap Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "vert_main",
+            buffers: &[],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: "frag_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// The texture the render pass draws into.
+fn target(device: &wgpu::Device, size: PhysicalSize<u32>) -> wgpu::TextureView {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Tonemap Input"),
+        size: wgpu::Extent3d {
+            width: size.width.max(1),
+            height: size.height.max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
@@ -0,0 +1,2116 @@
+use std::{
+    collections::HashMap,
+    f32::consts::{FRAC_PI_2, PI},
+    ops::Range,
+    sync::Arc,
+};
+
+use glam::{Mat4, Vec3};
+use half::f16;
+use wgpu::util::DeviceExt;
+use winit::dpi::PhysicalSize;
+
+#[cfg(feature = "hot-reload")]
+use crate::hot_reload;
+use crate::{
+    blue_noise,
+    bvh::{BvhNode, InstancedBvh},
+    camera::{Camera, CameraUniform},
+    nanovdb::Grid,
+    ocean::OceanSimulation,
+    sampler::{Pmj02, SamplerKind, PMJ02_SAMPLES},
+    scene::{
+        Dispersion, Environment, Instance, Light, LightKind, Primitive, Scene, Sdf, SdfOp,
+        SdfShape, Shape, Volume,
+    },
+    shader,
+    sky::{Sky, SkyUniform},
+    spectral::{
+        blackbody, luminance, reflectance_table, Substrate, FRESNEL_TABLE_SIZE, MAX_TEMPERATURE,
+        MIN_TEMPERATURE, SPECTRUM_SAMPLES,
+    },
+    staging::{FrameStaging, UniformRing},
+    texture::GpuTextures,
+};
+
+mod cpu;
+
+pub use cpu::CpuRenderer;
+
+/// Threads along each side of a workgroup, until `tuning` picks another.
+pub const WORKGROUP_SIZE: u32 = 8;
+
+const MATERIAL_THIN_WALLED: u32 = 1;
+const MATERIAL_SUBSURFACE: u32 = 2;
+
+const TRIANGLE_HOLDOUT: u32 = 1;
+const INSTANCE_MIRRORED: u32 = 2;
+
+const DISPERSION_NONE: u32 = 0;
+const DISPERSION_CAUCHY: u32 = 1;
+const DISPERSION_SELLMEIER: u32 = 2;
+
+/// Resolution of the sheen albedo table in both view angle and roughness.
+const SHEEN_TABLE_SIZE: usize = 16;
+/// Lower bound of the Charlie sheen alpha, matched in the shader.
+const MIN_SHEEN_ALPHA: f32 = 0.01;
+/// Width and height of the tiled blue noise mask, matched in the shader.
+const BLUE_NOISE_SIZE: u32 = 64;
+/// Points along each row of the PMJ02 table texture.
+const PMJ02_TABLE_WIDTH: u32 = 64;
+/// Bytes of staging per frame in flight, enough for the parameters and the
+/// lights and materials edited in the UI.
+const STAGING_SIZE: u64 = 64 * 1024;
+/// Samples the reprojected history counts as at most after the camera
+/// moves, so it fades within a few frames where it's wrong.
+const HISTORY_LIMIT: u32 = 8;
+
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct TraceParams {
+    camera: CameraUniform,
+    sky: SkyUniform,
+    frame: u32,
+    max_depth: u32,
+    environment_rotation: f32,
+    environment_intensity: f32,
+    has_environment: u32,
+    light_count: u32,
+    spectral: u32,
+    /// Writes the bare sample for `resolve` to blend with the reprojected
+    /// history.
+    reproject: u32,
+    seed: u32,
+    transparent: u32,
+    has_backdrop: u32,
+    sampler_kind: u32,
+    /// First row of the band the dispatch traces.
+    row_offset: u32,
+    primitive_count: u32,
+    light_sampling: u32,
+    volume_count: u32,
+}
+
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct ResolveParams {
+    camera: CameraUniform,
+    prev_camera: CameraUniform,
+    history: u32,
+    _pad: [u32; 3],
+}
+
+/// How the image is traced, kept apart from the backends so that
+/// switching between them keeps it.
+#[derive(Clone, Copy, Debug)]
+pub struct TraceSettings {
+    /// Seconds into the animation of dynamic geometry.
+    pub time: f32,
+    pub max_depth: u32,
+    /// Traces single wavelengths through dispersive materials, instead of
+    /// refracting all colors alike.
+    pub spectral: bool,
+    /// Samples the lights at every bounce, weighted against the bounces
+    /// that hit them. Without it lights are only found by chance and
+    /// point lights not at all, which is noisier but handy for checking
+    /// the light sampling against.
+    pub light_sampling: bool,
+    /// Rotation of the environment around +Y in radians.
+    pub environment_rotation: f32,
+    /// Scales the environment map, or the sky without one.
+    pub environment_intensity: f32,
+    /// Lights the scene when there's no environment map.
+    pub sky: Sky,
+    /// Keeps the accumulated image through camera moves by reprojecting
+    /// it, instead of starting over.
+    pub reproject: bool,
+    /// Leaves the background out of the image, with zero alpha, for
+    /// compositing. It still lights the scene.
+    pub transparent: bool,
+    /// Sequence the random decisions of paths are drawn from.
+    pub sampler: SamplerKind,
+    /// Most camera rays the GPU traces in one submission. Larger images
+    /// are traced in bands of rows over several `render_frame` calls, so
+    /// long dispatches don't trip the Windows watchdog or lose the device
+    /// in a browser. Zero traces whole frames.
+    pub rays_per_submit: u32,
+}
+
+impl Default for TraceSettings {
+    fn default() -> Self {
+        Self {
+            time: 0.0,
+            max_depth: 8,
+            spectral: true,
+            light_sampling: true,
+            environment_rotation: 0.0,
+            environment_intensity: 1.0,
+            sky: Sky::default(),
+            reproject: true,
+            transparent: false,
+            sampler: SamplerKind::default(),
+            rays_per_submit: 0,
+        }
+    }
+}
+
+impl TraceSettings {
+    fn params(
+        &self,
+        scene: &SceneInfo,
+        camera: CameraUniform,
+        frame: u32,
+        seed: u32,
+        reproject: bool,
+    ) -> TraceParams {
+        TraceParams {
+            camera,
+            sky: self.sky.uniform(),
+            frame,
+            max_depth: self.max_depth,
+            environment_rotation: self.environment_rotation,
+            environment_intensity: self.environment_intensity,
+            has_environment: scene.has_environment as u32,
+            light_count: scene.light_count,
+            spectral: self.spectral as u32,
+            reproject: reproject as u32,
+            seed,
+            transparent: self.transparent as u32,
+            has_backdrop: scene.has_backdrop as u32,
+            sampler_kind: self.sampler as u32,
+            row_offset: 0,
+            primitive_count: scene.primitive_count,
+            light_sampling: self.light_sampling as u32,
+            volume_count: scene.volume_count,
+        }
+    }
+}
+
+/// What the trace parameters tell about the uploaded scene.
+#[derive(Clone, Copy, Debug)]
+struct SceneInfo {
+    has_environment: bool,
+    has_backdrop: bool,
+    light_count: u32,
+    primitive_count: u32,
+    volume_count: u32,
+}
+
+impl SceneInfo {
+    fn new(scene: &Scene) -> Self {
+        Self {
+            has_environment: scene.environment.is_some(),
+            has_backdrop: scene.backdrop.is_some(),
+            light_count: gpu_lights(scene).1,
+            primitive_count: scene.primitives.len() as u32,
+            volume_count: scene.volumes.len() as u32,
+        }
+    }
+}
+
+/// Where paths are traced.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// The trace shader.
+    #[default]
+    Gpu,
+    /// Every core of the CPU, for adapters that can't run the shader well.
+    Cpu,
+}
+
+impl Backend {
+    pub const ALL: [Self; 2] = [Self::Gpu, Self::Cpu];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|backend| backend.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Gpu => "gpu",
+            Self::Cpu => "cpu",
+        }
+    }
+
+    pub fn create(
+        self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+        bvh: &InstancedBvh,
+        size: PhysicalSize<u32>,
+    ) -> Box<dyn Renderer> {
+        match self {
+            Self::Gpu => Box::new(PathTracer::new(
+                device,
+                queue,
+                scene,
+                bvh,
+                size,
+                WORKGROUP_SIZE,
+            )),
+            Self::Cpu => Box::new(CpuRenderer::new(device, scene, bvh, size)),
+        }
+    }
+}
+
+/// Progressive renderer of a scene. Every `render_frame` adds one sample
+/// per pixel to an Rgba32Float texture, along with the albedo and normal
+/// guides of denoisers, or a band of one, see
+/// `TraceSettings::rays_per_submit`.
+pub trait Renderer {
+    /// Uploads a different scene from scratch.
+    fn init(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+        bvh: &InstancedBvh,
+    );
+
+    fn resize(&mut self, device: &wgpu::Device, size: PhysicalSize<u32>);
+
+    /// Builds new pipelines first if `settings` changed what the kernels
+    /// are specialized on. The frame's uploads are recorded into `encoder`
+    /// along with its passes, so it must be submitted before the next call.
+    fn render_frame(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        camera: &Camera,
+        settings: &TraceSettings,
+    );
+
+    fn backend(&self) -> Backend;
+
+    /// Throws away the accumulated samples.
+    fn reset(&mut self);
+
+    /// Reprojects the accumulated samples to where the camera moved on the
+    /// next frame if `reproject` is set and the backend can, or throws them
+    /// away.
+    fn camera_moved(&mut self, reproject: bool);
+
+    /// Uploads moved instances, primitives and volumes. The BVH must cover
+    /// the same instances and meshes as the one the scene was uploaded with,
+    /// as after `InstancedBvh::refit`, or `InstancedBvh::update` for
+    /// instances that only moved.
+    fn update_geometry(&mut self, queue: &wgpu::Queue, scene: &Scene, bvh: &InstancedBvh);
+
+    /// Uploads changed material parameters. Materials can't be added or
+    /// removed.
+    fn update_materials(&mut self, queue: &wgpu::Queue, scene: &Scene);
+
+    /// Uploads changed lights. Lights can't be added or removed.
+    fn update_lights(&mut self, queue: &wgpu::Queue, scene: &Scene);
+
+    /// Whether the scene changes over time, which restarts accumulation.
+    fn is_animated(&self) -> bool;
+
+    /// Whether an environment map replaces the sky.
+    fn has_environment(&self) -> bool;
+
+    /// Number of samples per pixel accumulated so far.
+    fn sample_count(&self) -> u32;
+
+    /// The texture holding the latest result.
+    fn output_texture(&self) -> &wgpu::Texture;
+
+    fn output_view(&self) -> &wgpu::TextureView;
+
+    /// Latest albedo and normal guides, with the extra channels described
+    /// in trace.wgsl.
+    fn guide_views(&self) -> [&wgpu::TextureView; 2];
+
+    /// Instance seen by the latest sample of each pixel plus one, in red,
+    /// and zero where it saw none.
+    fn object_view(&self) -> &wgpu::TextureView;
+
+    /// Texture of the latest albedo guide, to read it back.
+    fn albedo_texture(&self) -> &wgpu::Texture;
+
+    /// Texture of the latest normal guide, to read it back.
+    fn normal_texture(&self) -> &wgpu::Texture;
+
+    /// Rebuilds the pipelines whose shaders use one of the `changed` files,
+    /// see `hot_reload`.
+    #[cfg(feature = "hot-reload")]
+    fn reload_shaders(&mut self, device: &wgpu::Device, changed: &[String]);
+
+    /// Rebuilds the kernels with `size` by `size` threads per workgroup,
+    /// see `tuning`. The samples so far are kept.
+    fn set_workgroup_size(&mut self, device: &wgpu::Device, size: u32);
+}
+
+/// Triangle in the space of its mesh with everything needed for shading.
+/// The material, flags, instance and class are left zero and filled in
+/// from the `GpuInstance` hit, along with the move to world space. The GPU
+/// gets them split up by `triangle_streams`.
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct GpuTriangle {
+    p0: [f32; 3],
+    material: u32,
+    p1: [f32; 3],
+    flags: u32,
+    p2: [f32; 3],
+    /// Index of the instance it belongs to, for the object ID AOV.
+    instance: u32,
+    n0: [f32; 3],
+    /// Semantic class of its instance, for the class ID AOV.
+    class: u32,
+    n1: [f32; 3],
+    _pad3: u32,
+    n2: [f32; 3],
+    _pad4: u32,
+    uv0: [f32; 2],
+    uv1: [f32; 2],
+    uv2: [f32; 2],
+    _pad5: [u32; 2],
+    /// Zero where the mesh has no tangents.
+    t0: [f32; 4],
+    t1: [f32; 4],
+    t2: [f32; 4],
+}
+
+/// Placement of a mesh's bottom-level tree in the scene, see
+/// `InstancedBvh`.
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct GpuInstance {
+    to_world: [[f32; 4]; 4],
+    to_local: [[f32; 4]; 4],
+    /// Root of its bottom-level tree in the node buffer.
+    root: u32,
+    material: u32,
+    /// TRIANGLE_HOLDOUT, which its triangles take on, and INSTANCE_MIRRORED.
+    flags: u32,
+    /// Index into `Scene::instances`, for the object ID AOV.
+    instance: u32,
+    /// Semantic class, for the class ID AOV.
+    class: u32,
+    _pad: [u32; 3],
+}
+
+impl GpuInstance {
+    fn new(instance: &Instance, index: usize, root: u32) -> Self {
+        let mut flags = 0;
+        if instance.holdout {
+            flags |= TRIANGLE_HOLDOUT;
+        }
+        // Mirroring flips the bitangent, and the winding to keep the
+        // geometric normal pointing out
+        if instance.transform.determinant() < 0.0 {
+            flags |= INSTANCE_MIRRORED;
+        }
+        Self {
+            to_world: instance.transform.to_cols_array_2d(),
+            to_local: instance.transform.inverse().to_cols_array_2d(),
+            root,
+            material: instance.material as u32,
+            flags,
+            instance: index as u32,
+            class: instance.class,
+            ..Default::default()
+        }
+    }
+
+    fn local_to_world(&self) -> Mat4 {
+        Mat4::from_cols_array_2d(&self.to_world)
+    }
+
+    fn world_to_local(&self) -> Mat4 {
+        Mat4::from_cols_array_2d(&self.to_local)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct GpuMaterial {
+    base_color: [f32; 4],
+    emission: [f32; 3],
+    metallic: f32,
+    roughness: f32,
+    transmission: f32,
+    ior: f32,
+    flags: u32,
+    anisotropy: f32,
+    anisotropy_rotation: f32,
+    clearcoat: f32,
+    clearcoat_roughness: f32,
+    sheen_color: [f32; 3],
+    sheen_roughness: f32,
+    flake_coverage: f32,
+    flake_size: f32,
+    flake_roughness: f32,
+    dispersion: u32,
+    dispersion_b: [f32; 3],
+    temperature: f32,
+    dispersion_c: [f32; 3],
+    /// One past the index of the reflectance table, or 0 for Schlick.
+    fresnel_table: u32,
+    emission_scale: f32,
+    /// See `GpuTextures::reference`.
+    base_color_texture: u32,
+    metallic_roughness_texture: u32,
+    emission_texture: u32,
+    normal_texture: u32,
+    normal_scale: f32,
+    bump_texture: u32,
+    bump_scale: f32,
+    clearcoat_normal_texture: u32,
+    clearcoat_normal_scale: f32,
+    _pad0: [u32; 2],
+    /// Subsurface coefficients averaged over each channel, for RGB paths.
+    subsurface_absorption: [f32; 3],
+    subsurface_anisotropy: f32,
+    subsurface_scattering: [f32; 3],
+    _pad1: u32,
+    /// The samples of the subsurface spectra, for paths with a wavelength.
+    absorption_spectrum: [[f32; 4]; SPECTRUM_SAMPLES / 4],
+    scattering_spectrum: [[f32; 4]; SPECTRUM_SAMPLES / 4],
+}
+
+const LIGHT_DIRECTIONAL: u32 = 0;
+const LIGHT_POINT: u32 = 1;
+const LIGHT_SPOT: u32 = 2;
+const LIGHT_QUAD: u32 = 3;
+const LIGHT_DISK: u32 = 4;
+
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct GpuLight {
+    position: [f32; 3],
+    kind: u32,
+    axis_u: [f32; 3],
+    cos_inner: f32,
+    axis_v: [f32; 3],
+    cos_outer: f32,
+    direction: [f32; 3],
+    area: f32,
+    emission: [f32; 3],
+    temperature: f32,
+    emission_scale: f32,
+    _pad: [u32; 3],
+}
+
+impl GpuLight {
+    fn new(light: &Light) -> Self {
+        let transform = light.transform;
+        let mut gpu = Self {
+            position: transform.transform_point3(Vec3::ZERO).into(),
+            direction: transform
+                .transform_vector3(Vec3::NEG_Z)
+                .normalize_or_zero()
+                .into(),
+            ..Default::default()
+        };
+        let (emission, temperature, scale) =
+            blackbody_emission(light.color * light.intensity, light.temperature);
+        gpu.emission = emission.into();
+        gpu.temperature = temperature;
+        gpu.emission_scale = scale;
+        // Area lights keep their world space half extents and area
+        let mut area = |half_width: f32, half_height: f32| {
+            let u = transform.transform_vector3(Vec3::X * half_width);
+            let v = transform.transform_vector3(Vec3::Y * half_height);
+            gpu.axis_u = u.into();
+            gpu.axis_v = v.into();
+            u.cross(v).length()
+        };
+        match light.kind {
+            LightKind::Directional => gpu.kind = LIGHT_DIRECTIONAL,
+            LightKind::Point => gpu.kind = LIGHT_POINT,
+            LightKind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => {
+                gpu.kind = LIGHT_SPOT;
+                gpu.cos_inner = inner_cone_angle.cos();
+                gpu.cos_outer = outer_cone_angle.cos();
+            }
+            LightKind::Quad { width, height } => {
+                gpu.area = 4.0 * area(0.5 * width, 0.5 * height);
+                gpu.kind = LIGHT_QUAD;
+            }
+            LightKind::Disk { radius } => {
+                gpu.area = PI * area(radius, radius);
+                gpu.kind = LIGHT_DISK;
+            }
+        }
+        gpu
+    }
+}
+
+const PRIMITIVE_SPHERE: u32 = 0;
+const PRIMITIVE_PLANE: u32 = 1;
+const PRIMITIVE_BOX: u32 = 2;
+const PRIMITIVE_QUAD: u32 = 3;
+const PRIMITIVE_DISK: u32 = 4;
+const PRIMITIVE_SDF: u32 = 5;
+
+const SDF_SPHERE: u32 = 0;
+const SDF_BOX: u32 = 1;
+const SDF_TORUS: u32 = 2;
+const SDF_MANDELBULB: u32 = 3;
+
+const SDF_SHAPE: u32 = 0;
+const SDF_UNION: u32 = 1;
+const SDF_INTERSECTION: u32 = 2;
+const SDF_SUBTRACTION: u32 = 3;
+const SDF_WGSL: u32 = 4;
+
+/// Analytic shape, intersected in its own space where it has the half
+/// extents `size`.
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct GpuPrimitive {
+    to_local: [[f32; 4]; 4],
+    to_world: [[f32; 4]; 4],
+    size: [f32; 3],
+    kind: u32,
+    material: u32,
+    /// Object ID, counting on from the instances.
+    instance: u32,
+    class: u32,
+    /// SDF_* operation of a distance field.
+    sdf_op: u32,
+    /// SDF_* shapes it combines, or the index of its WGSL function.
+    sdf_shapes: [u32; 2],
+    smoothness: f32,
+    _pad: u32,
+    /// Parameters of each shape, see `sdf_shape`.
+    sdf_params: [[f32; 4]; 2],
+    /// Where the second shape is.
+    sdf_offset: [f32; 3],
+    _pad1: u32,
+}
+
+impl GpuPrimitive {
+    fn new(primitive: &Primitive, instance: usize, sdfs: &[Sdf]) -> Self {
+        let mut gpu = Self {
+            to_local: primitive.transform.inverse().to_cols_array_2d(),
+            to_world: primitive.transform.to_cols_array_2d(),
+            material: primitive.material as u32,
+            instance: instance as u32,
+            class: primitive.class,
+            ..Default::default()
+        };
+        let (kind, size) = match primitive.shape {
+            Shape::Sphere { radius } => (PRIMITIVE_SPHERE, Vec3::splat(radius)),
+            Shape::Plane => (PRIMITIVE_PLANE, Vec3::ZERO),
+            Shape::Box { half_extents } => (PRIMITIVE_BOX, half_extents),
+            Shape::Quad { width, height } => {
+                (PRIMITIVE_QUAD, Vec3::new(0.5 * width, 0.0, 0.5 * height))
+            }
+            Shape::Disk { radius } => (PRIMITIVE_DISK, Vec3::new(radius, 0.0, radius)),
+            Shape::Sdf { sdf, half_extents } => {
+                match &sdfs[sdf] {
+                    Sdf::Shape(shape) => {
+                        (gpu.sdf_shapes[0], gpu.sdf_params[0]) = sdf_shape(shape);
+                    }
+                    Sdf::Combine {
+                        op,
+                        a,
+                        b,
+                        offset,
+                        smoothness,
+                    } => {
+                        gpu.sdf_op = match op {
+                            SdfOp::Union => SDF_UNION,
+                            SdfOp::Intersection => SDF_INTERSECTION,
+                            SdfOp::Subtraction => SDF_SUBTRACTION,
+                        };
+                        (gpu.sdf_shapes[0], gpu.sdf_params[0]) = sdf_shape(a);
+                        (gpu.sdf_shapes[1], gpu.sdf_params[1]) = sdf_shape(b);
+                        gpu.sdf_offset = offset.to_array();
+                        gpu.smoothness = *smoothness;
+                    }
+                    Sdf::Wgsl(_) => {
+                        gpu.sdf_op = SDF_WGSL;
+                        gpu.sdf_shapes[0] = sdf as u32;
+                    }
+                }
+                (PRIMITIVE_SDF, half_extents)
+            }
+        };
+        gpu.kind = kind;
+        gpu.size = size.into();
+        gpu
+    }
+
+    fn world_to_local(&self) -> Mat4 {
+        Mat4::from_cols_array_2d(&self.to_local)
+    }
+
+    fn local_to_world(&self) -> Mat4 {
+        Mat4::from_cols_array_2d(&self.to_world)
+    }
+}
+
+/// SDF_* kind and parameters of a shape of a distance field.
+fn sdf_shape(shape: &SdfShape) -> (u32, [f32; 4]) {
+    match *shape {
+        SdfShape::Sphere { radius } => (SDF_SPHERE, [radius, 0.0, 0.0, 0.0]),
+        SdfShape::Box {
+            half_extents,
+            radius,
+        } => (SDF_BOX, half_extents.extend(radius).to_array()),
+        SdfShape::Torus {
+            major_radius,
+            minor_radius,
+        } => (SDF_TORUS, [major_radius, minor_radius, 0.0, 0.0]),
+        SdfShape::Mandelbulb { power, iterations } => {
+            (SDF_MANDELBULB, [power, iterations as f32, 0.0, 0.0])
+        }
+    }
+}
+
+/// The scene's WGSL distance functions, appended to trace.wgsl along with
+/// the `sdf_wgsl` it calls them through by the index of their field.
+fn sdf_wgsl(scene: &Scene) -> String {
+    let mut cases = String::new();
+    let mut functions = String::new();
+    for (i, sdf) in scene.sdfs.iter().enumerate() {
+        if let Sdf::Wgsl(body) = sdf {
+            cases.push_str(&format!(
+                "        case {i}u: {{ return sdf_wgsl_{i}(p); }}\n"
+            ));
+            functions.push_str(&format!(
+                "\nfn sdf_wgsl_{i}(p: vec3<f32>) -> f32 {{\n{body}\n}}\n"
+            ));
+        }
+    }
+    format!(
+        "\nfn sdf_wgsl(index: u32, p: vec3<f32>) -> f32 {{\n    switch index {{\n{cases}        default: {{}}\n    }}\n    return T_MAX;\n}}\n{functions}"
+    )
+}
+
+/// Packs the primitives, numbering their object IDs after the instances.
+/// Storage buffers can't be empty, so an empty scene gets a placeholder
+/// that `TraceParams::primitive_count` leaves out.
+fn gpu_primitives(scene: &Scene) -> Vec<GpuPrimitive> {
+    let mut primitives: Vec<GpuPrimitive> = scene
+        .primitives
+        .iter()
+        .enumerate()
+        .map(|(i, primitive)| GpuPrimitive::new(primitive, scene.instances.len() + i, &scene.sdfs))
+        .collect();
+    if primitives.is_empty() {
+        primitives.push(GpuPrimitive::default());
+    }
+    primitives
+}
+
+/// Every triangle the bottom-level trees index, in the space of its mesh
+/// and in the order of `InstancedBvh::triangles`, so leaves index straight
+/// into the triangle buffer. Storage buffers can't be empty.
+fn object_triangles(scene: &Scene, bvh: &InstancedBvh) -> Vec<GpuTriangle> {
+    let mut triangles: Vec<GpuTriangle> = bvh
+        .triangles()
+        .map(|(mesh, lod, triangle)| {
+            let mesh = &scene.meshes[mesh];
+            let i = 3 * triangle as usize;
+            let [a, b, c] = [0, 1, 2].map(|v| mesh.lod_indices(lod)[i + v] as usize);
+            let t = |v: usize| mesh.tangents.get(v).map_or([0.0; 4], |t| t.to_array());
+            GpuTriangle {
+                p0: mesh.positions[a].into(),
+                p1: mesh.positions[b].into(),
+                p2: mesh.positions[c].into(),
+                n0: mesh.normals[a].into(),
+                n1: mesh.normals[b].into(),
+                n2: mesh.normals[c].into(),
+                uv0: mesh.uvs[a].into(),
+                uv1: mesh.uvs[b].into(),
+                uv2: mesh.uvs[c].into(),
+                t0: t(a),
+                t1: t(b),
+                t2: t(c),
+                ..Default::default()
+            }
+        })
+        .collect();
+    if triangles.is_empty() {
+        triangles.push(GpuTriangle::default());
+    }
+    triangles
+}
+
+/// Vec4s each triangle takes up in the buffer from `triangle_streams`.
+const TRIANGLE_VEC4S: usize = 11;
+
+/// Lays `triangles` out as a struct of arrays, see triangles.wgsl: the
+/// corners of every triangle, then the normals, UVs and tangents.
+/// Traversal only reads the corners, 48 bytes a triangle rather than all
+/// 176 of a `GpuTriangle`, so a leaf's triangles share cache lines, see
+/// `benchmark` for timing it.
+fn triangle_streams(triangles: &[GpuTriangle]) -> Vec<[f32; 4]> {
+    let mut streams = Vec::with_capacity(triangles.len() * TRIANGLE_VEC4S);
+    let point = |p: [f32; 3]| [p[0], p[1], p[2], 0.0];
+    for tri in triangles {
+        streams.extend([tri.p0, tri.p1, tri.p2].map(point));
+    }
+    for tri in triangles {
+        streams.extend([tri.n0, tri.n1, tri.n2].map(point));
+    }
+    for tri in triangles {
+        streams.push([tri.uv0[0], tri.uv0[1], tri.uv1[0], tri.uv1[1]]);
+        streams.push([tri.uv2[0], tri.uv2[1], 0.0, 0.0]);
+    }
+    for tri in triangles {
+        streams.extend([tri.t0, tri.t1, tri.t2]);
+    }
+    streams
+}
+
+/// The instances in the order the top-level leaves index them. An empty
+/// scene gets a placeholder whose tree is the empty top-level root, see
+/// `InstancedBvh::nodes`.
+fn gpu_instances(scene: &Scene, bvh: &InstancedBvh) -> Vec<GpuInstance> {
+    let mut instances: Vec<GpuInstance> = bvh
+        .instances(scene)
+        .into_iter()
+        .map(|(index, root)| GpuInstance::new(&scene.instances[index], index, root))
+        .collect();
+    if instances.is_empty() {
+        instances.push(GpuInstance {
+            to_world: Mat4::IDENTITY.to_cols_array_2d(),
+            to_local: Mat4::IDENTITY.to_cols_array_2d(),
+            ..Default::default()
+        });
+    }
+    instances
+}
+
+/// RGB emission, and the temperature and scale of `spectral::planck` that
+/// replace it per wavelength, both zero for plain RGB.
+fn blackbody_emission(emission: Vec3, temperature: Option<f32>) -> (Vec3, f32, f32) {
+    let Some(temperature) = temperature else {
+        return (emission, 0.0, 0.0);
+    };
+    let temperature = temperature.clamp(MIN_TEMPERATURE, MAX_TEMPERATURE);
+    let strength = luminance(emission);
+    let (rgb, scale) = blackbody(temperature);
+    (rgb * strength, temperature, scale * strength)
+}
+
+/// Packs the materials along with the reflectance tables of conductors and
+/// thin films, untextured without `textures`.
+fn gpu_materials(
+    scene: &Scene,
+    textures: Option<&GpuTextures>,
+) -> (Vec<GpuMaterial>, Vec<[f32; 4]>) {
+    let reference = |texture| textures.map_or(0, |textures| textures.reference(texture));
+    let mut tables = Vec::new();
+    let mut materials: Vec<GpuMaterial> = scene
+        .materials
+        .iter()
+        .map(|material| {
+            let (dispersion, dispersion_b, dispersion_c) = match material.dispersion {
+                None => (DISPERSION_NONE, [0.0; 3], [0.0; 3]),
+                Some(Dispersion::Cauchy { a, b }) => (DISPERSION_CAUCHY, [a, b, 0.0], [0.0; 3]),
+                Some(Dispersion::Sellmeier { b, c }) => (DISPERSION_SELLMEIER, b, c),
+            };
+            let mut fresnel_table = 0;
+            if material.conductor.is_some() || material.thin_film.is_some() {
+                let substrate = match material.conductor {
+                    Some(conductor) => Substrate::Conductor(conductor),
+                    // A film on a thin sheet has air behind it
+                    None if material.thin_walled && material.transmission > 0.0 => {
+                        Substrate::Dielectric(1.0)
+                    }
+                    None => Substrate::Dielectric(material.ior),
+                };
+                let table = reflectance_table(substrate, material.thin_film);
+                tables.extend(table.map(|f| f.extend(1.0).to_array()));
+                fresnel_table = (tables.len() / FRESNEL_TABLE_SIZE) as u32;
+            }
+            let (emission, temperature, emission_scale) =
+                blackbody_emission(material.emission, material.temperature);
+            let mut gpu = GpuMaterial {
+                base_color: material.base_color.into(),
+                emission: emission.into(),
+                metallic: if material.conductor.is_some() {
+                    1.0
+                } else {
+                    material.metallic
+                },
+                roughness: material.roughness,
+                transmission: material.transmission,
+                ior: material.ior,
+                flags: if material.thin_walled {
+                    MATERIAL_THIN_WALLED
+                } else {
+                    0
+                },
+                anisotropy: material.anisotropy,
+                anisotropy_rotation: material.anisotropy_rotation,
+                clearcoat: material.clearcoat,
+                clearcoat_roughness: material.clearcoat_roughness,
+                sheen_color: material.sheen_color.into(),
+                sheen_roughness: material.sheen_roughness,
+                flake_coverage: material.flake_coverage,
+                flake_size: material.flake_size,
+                flake_roughness: material.flake_roughness,
+                dispersion,
+                dispersion_b,
+                temperature,
+                dispersion_c,
+                fresnel_table,
+                emission_scale,
+                base_color_texture: reference(material.base_color_texture),
+                metallic_roughness_texture: reference(material.metallic_roughness_texture),
+                emission_texture: reference(material.emission_texture),
+                normal_texture: reference(material.normal_texture),
+                normal_scale: material.normal_scale,
+                bump_texture: reference(material.bump_texture),
+                bump_scale: material.bump_scale,
+                clearcoat_normal_texture: reference(material.clearcoat_normal_texture),
+                clearcoat_normal_scale: material.clearcoat_normal_scale,
+                ..Default::default()
+            };
+            if let Some(subsurface) = &material.subsurface {
+                gpu.flags |= MATERIAL_SUBSURFACE;
+                let (absorption, scattering) = subsurface.rgb();
+                gpu.subsurface_absorption = absorption.into();
+                gpu.subsurface_scattering = scattering.into();
+                gpu.subsurface_anisotropy = subsurface.anisotropy;
+                gpu.absorption_spectrum = bytemuck::cast(subsurface.absorption.0);
+                gpu.scattering_spectrum = bytemuck::cast(subsurface.scattering.0);
+            }
+            gpu
+        })
+        .collect();
+    if materials.is_empty() {
+        materials.push(GpuMaterial::default());
+    }
+    if tables.is_empty() {
+        tables.push([0.0; 4]);
+    }
+    (materials, tables)
+}
+
+/// Volume in the trace shader, which looks its density up in the grid
+/// starting at word `grid` of the grid buffer.
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct GpuVolume {
+    to_index: [[f32; 4]; 4],
+    /// Index space bounds of what the lookup can see.
+    min: [f32; 3],
+    grid: u32,
+    max: [f32; 3],
+    majorant: f32,
+    /// Coefficients per unit of the grid's values.
+    absorption: [f32; 3],
+    anisotropy: f32,
+    scattering: [f32; 3],
+    _pad: u32,
+}
+
+impl GpuVolume {
+    fn new(volume: &Volume, grid: u32) -> Self {
+        let bounds = volume.grid.index_bounds();
+        Self {
+            to_index: volume.world_to_index().to_cols_array_2d(),
+            min: bounds.min.into(),
+            grid,
+            max: bounds.max.into(),
+            majorant: volume.majorant(),
+            absorption: (volume.density * volume.absorption).into(),
+            anisotropy: volume.anisotropy,
+            scattering: (volume.density * volume.scattering).into(),
+            _pad: 0,
+        }
+    }
+}
+
+/// Every grid of the scene once, however many volumes share it.
+fn unique_grids(scene: &Scene) -> Vec<&Arc<Grid>> {
+    let mut grids: Vec<&Arc<Grid>> = Vec::new();
+    for volume in &scene.volumes {
+        if !grids.iter().any(|grid| Arc::ptr_eq(grid, &volume.grid)) {
+            grids.push(&volume.grid);
+        }
+    }
+    grids
+}
+
+/// Words of a `GpuVolume` in the volume buffer.
+const VOLUME_WORDS: usize = std::mem::size_of::<GpuVolume>() / 4;
+
+/// Packs the volumes, pointing into the grids that follow them in the
+/// volume buffer, see `gpu_volume_data`.
+fn gpu_volumes(scene: &Scene) -> Vec<GpuVolume> {
+    let grids = unique_grids(scene);
+    let offsets: Vec<u32> = grids
+        .iter()
+        .scan(scene.volumes.len() * VOLUME_WORDS, |offset, grid| {
+            let start = *offset;
+            *offset += grid.words().len();
+            Some(start as u32)
+        })
+        .collect();
+    scene
+        .volumes
+        .iter()
+        .map(|volume| {
+            let index = grids
+                .iter()
+                .position(|grid| Arc::ptr_eq(grid, &volume.grid))
+                .unwrap();
+            GpuVolume::new(volume, offsets[index])
+        })
+        .collect()
+}
+
+/// The volumes followed by their grids one after another, in words, so
+/// they take up one binding. Storage buffers can't be empty.
+fn gpu_volume_data(scene: &Scene) -> Vec<u32> {
+    let mut words: Vec<u32> = bytemuck::cast_slice(&gpu_volumes(scene)).to_vec();
+    words.extend(
+        unique_grids(scene)
+            .into_iter()
+            .flat_map(|grid| grid.words())
+            .copied(),
+    );
+    if words.is_empty() {
+        words.push(0);
+    }
+    words
+}
+
+/// Packs the lights, skipping empty area lights. Returns the number of
+/// real lights, as storage buffers can't be empty.
+fn gpu_lights(scene: &Scene) -> (Vec<GpuLight>, u32) {
+    let mut lights: Vec<GpuLight> = scene
+        .lights
+        .iter()
+        .filter(|light| match light.kind {
+            LightKind::Quad { width, height } => width > 0.0 && height > 0.0,
+            LightKind::Disk { radius } => radius > 0.0,
+            _ => true,
+        })
+        .map(GpuLight::new)
+        .collect();
+    let light_count = lights.len() as u32;
+    if lights.is_empty() {
+        lights.push(GpuLight::default());
+    }
+    (lights, light_count)
+}
+
+/// Every water triangle's index in the triangle buffer, followed by its
+/// vertex indices, see `OceanSimulation::new`.
+fn ocean_triangles(scene: &Scene, bvh: &InstancedBvh, instance: usize) -> Vec<[u32; 4]> {
+    let instance = &scene.instances[instance];
+    let indices = scene.meshes[instance.mesh].lod_indices(instance.lod);
+    bvh.triangles()
+        .enumerate()
+        .filter(|(_, (mesh, lod, _))| (*mesh, *lod) == (instance.mesh, instance.lod))
+        .map(|(i, (_, _, triangle))| {
+            let v = &indices[3 * triangle as usize..];
+            [i as u32, v[0], v[1], v[2]]
+        })
+        .collect()
+}
+
+/// Progressive GPU path tracer. Every frame adds one sample per pixel to
+/// a pair of ping-ponged accumulation textures.
+pub struct PathTracer {
+    pipeline: wgpu::ComputePipeline,
+    pipeline_layout: wgpu::PipelineLayout,
+    /// Preprocessed trace.wgsl the pipeline is specialized from, with
+    /// `sdf_wgsl` appended.
+    trace_source: String,
+    /// The scene's WGSL distance functions.
+    sdf_wgsl: String,
+    /// Traversal stack entries `trace_source` is built with.
+    stack_size: u32,
+    /// Whether `trace_source` changed since the pipeline was built.
+    stale_pipeline: bool,
+    specialization: Specialization,
+    params_buffer: UniformRing<TraceParams>,
+    target_layout: wgpu::BindGroupLayout,
+    resolve_layout: wgpu::BindGroupLayout,
+    resolve_pipeline: wgpu::ComputePipeline,
+    resolve_pipeline_layout: wgpu::PipelineLayout,
+    /// Threads along each side of a workgroup of both kernels.
+    workgroup_size: u32,
+    resolve_buffer: UniformRing<ResolveParams>,
+    /// Uploads the parameters and scene updates of each frame.
+    staging: FrameStaging,
+    scene_bind_group: wgpu::BindGroup,
+    node_buffer: DynamicBuffer<BvhNode>,
+    /// Written once but for the water, which the ocean displaces in place.
+    triangle_buffer: DynamicBuffer<[f32; 4]>,
+    instance_buffer: DynamicBuffer<GpuInstance>,
+    primitive_buffer: DynamicBuffer<GpuPrimitive>,
+    material_buffer: DynamicBuffer<GpuMaterial>,
+    fresnel_buffer: DynamicBuffer<[f32; 4]>,
+    light_buffer: DynamicBuffer<GpuLight>,
+    /// The volumes and then their grids, see `gpu_volume_data`.
+    volume_buffer: DynamicBuffer<u32>,
+    material_textures: GpuTextures,
+    targets: Targets,
+    frame: u32,
+    /// Index into the sample sequence, which keeps counting through
+    /// reprojected camera moves unlike `frame`.
+    seed: u32,
+    prev_camera: CameraUniform,
+    /// The camera moved since the last frame.
+    moved: bool,
+    /// First row of the band traced next, zero at the start of a frame.
+    row: u32,
+    info: SceneInfo,
+    ocean: Option<OceanSimulation>,
+}
+
+impl PathTracer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+        bvh: &InstancedBvh,
+        size: PhysicalSize<u32>,
+        workgroup_size: u32,
+    ) -> Self {
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let scene_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Scene Bind Group Layout"),
+            entries: &[
+                storage_entry(0),
+                storage_entry(1),
+                storage_entry(2),
+                storage_entry(6),
+                storage_entry(7),
+                storage_entry(12),
+                storage_entry(13),
+                storage_entry(14),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                table_entry(5, wgpu::TextureSampleType::Float { filterable: false }),
+                table_entry(8, wgpu::TextureSampleType::Float { filterable: false }),
+                table_entry(11, wgpu::TextureSampleType::Uint),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 9,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let read_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let write_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: wgpu::TextureFormat::Rgba32Float,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let target_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Accumulation Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                read_entry(1),
+                write_entry(2),
+                read_entry(3),
+                write_entry(4),
+                read_entry(5),
+                write_entry(6),
+                write_entry(7),
+            ],
+        });
+
+        let sdf_wgsl = sdf_wgsl(scene);
+        let stack_size = stack_size(bvh);
+        let defines = shader_defines(workgroup_size, stack_size);
+        let trace_source = shader::builtin_source("trace.wgsl", &defines) + &sdf_wgsl;
+        let shader = trace_module(device, &trace_source);
+        let material_textures = GpuTextures::new(device, queue, &scene.textures);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Trace Pipeline Layout"),
+            bind_group_layouts: &[&target_layout, &scene_layout, &material_textures.layout],
+            push_constant_ranges: &[],
+        });
+        let specialization = Specialization::new(&TraceSettings::default());
+        let pipeline = trace_pipeline(device, &pipeline_layout, &shader, specialization);
+
+        let params_buffer = UniformRing::new(device, "Trace Params");
+
+        let resolve_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Resolve Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                read_entry(1),
+                read_entry(2),
+                read_entry(3),
+                read_entry(4),
+                write_entry(5),
+            ],
+        });
+        let resolve_shader =
+            shader::create_module(device, "Reproject Shader", "reproject.wgsl", &defines);
+        let resolve_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Resolve Pipeline Layout"),
+                bind_group_layouts: &[&resolve_layout],
+                push_constant_ranges: &[],
+            });
+        let resolve_pipeline = resolve_pipeline(device, &resolve_pipeline_layout, &resolve_shader);
+        let resolve_buffer = UniformRing::new(device, "Resolve Params");
+
+        let (materials, fresnel_tables) = gpu_materials(scene, Some(&material_textures));
+        let (lights, _) = gpu_lights(scene);
+
+        let node_buffer = DynamicBuffer::new(device, "BVH Nodes", bvh.nodes());
+        let triangle_buffer = DynamicBuffer::new(
+            device,
+            "Triangles",
+            triangle_streams(&object_triangles(scene, bvh)),
+        );
+        let instance_buffer = DynamicBuffer::new(device, "Instances", gpu_instances(scene, bvh));
+        let primitive_buffer = DynamicBuffer::new(device, "Primitives", gpu_primitives(scene));
+        let ocean = scene.ocean.as_ref().map(|(instance, ocean)| {
+            let triangles = ocean_triangles(scene, bvh, *instance);
+            OceanSimulation::new(device, ocean, &triangle_buffer.buffer, &triangles)
+        });
+        let material_buffer = DynamicBuffer::new(device, "Materials", materials);
+        let fresnel_buffer = DynamicBuffer::new(device, "Fresnel Tables", fresnel_tables);
+        let light_buffer = DynamicBuffer::new(device, "Lights", lights);
+        let volume_buffer = DynamicBuffer::new(device, "Volumes", gpu_volume_data(scene));
+        let environment_view =
+            upload_image(device, queue, "Environment", scene.environment.as_ref())
+                .create_view(&wgpu::TextureViewDescriptor::default());
+        let backdrop_view = upload_image(device, queue, "Backdrop", scene.backdrop.as_ref())
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let blue_noise_view = table_texture(
+            device,
+            queue,
+            "Blue Noise",
+            [BLUE_NOISE_SIZE; 2],
+            wgpu::TextureFormat::R32Float,
+            bytemuck::cast_slice(&blue_noise::mask(BLUE_NOISE_SIZE as usize)),
+        );
+        let sheen_view = table_texture(
+            device,
+            queue,
+            "Sheen Albedo",
+            [SHEEN_TABLE_SIZE as u32; 2],
+            wgpu::TextureFormat::R32Float,
+            bytemuck::cast_slice(&sheen_albedo()),
+        );
+        let pmj02_view = table_texture(
+            device,
+            queue,
+            "PMJ02 Samples",
+            [PMJ02_TABLE_WIDTH, PMJ02_SAMPLES / PMJ02_TABLE_WIDTH],
+            wgpu::TextureFormat::Rg32Uint,
+            bytemuck::cast_slice(Pmj02::new().points),
+        );
+        let environment_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Environment Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let backdrop_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Backdrop Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let scene_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Scene Bind Group"),
+            layout: &scene_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: node_buffer.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: triangle_buffer.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: material_buffer.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&environment_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&environment_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&sheen_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: light_buffer.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: fresnel_buffer.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::TextureView(&blue_noise_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::TextureView(&backdrop_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: wgpu::BindingResource::Sampler(&backdrop_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: wgpu::BindingResource::TextureView(&pmj02_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: primitive_buffer.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 13,
+                    resource: volume_buffer.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 14,
+                    resource: instance_buffer.buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let layouts = [&target_layout, &resolve_layout];
+        let targets = Targets::new(device, layouts, &params_buffer, &resolve_buffer, size);
+
+        Self {
+            pipeline,
+            pipeline_layout,
+            trace_source,
+            sdf_wgsl,
+            stack_size,
+            stale_pipeline: false,
+            specialization,
+            params_buffer,
+            target_layout,
+            resolve_layout,
+            resolve_pipeline,
+            resolve_pipeline_layout,
+            workgroup_size,
+            resolve_buffer,
+            staging: FrameStaging::new(device, "Trace Staging", STAGING_SIZE),
+            scene_bind_group,
+            node_buffer,
+            triangle_buffer,
+            instance_buffer,
+            primitive_buffer,
+            material_buffer,
+            fresnel_buffer,
+            light_buffer,
+            volume_buffer,
+            material_textures,
+            targets,
+            frame: 0,
+            seed: 0,
+            prev_camera: CameraUniform::default(),
+            moved: false,
+            row: 0,
+            info: SceneInfo::new(scene),
+            ocean,
+        }
+    }
+}
+
+impl PathTracer {
+    /// Builds the trace pipeline for `specialization` from a module of its
+    /// own, as the GL backend of wgpu 22 caches programs by module and would
+    /// keep the constants of the first pipeline.
+    fn specialize(&mut self, device: &wgpu::Device, specialization: Specialization) {
+        let module = trace_module(device, &self.trace_source);
+        self.pipeline = trace_pipeline(device, &self.pipeline_layout, &module, specialization);
+        self.specialization = specialization;
+        self.stale_pipeline = false;
+    }
+
+    /// Records the copies of the scene updates since the last frame.
+    fn flush_updates(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        let staging = &mut self.staging;
+        self.node_buffer.flush(device, encoder, staging);
+        self.triangle_buffer.flush(device, encoder, staging);
+        self.instance_buffer.flush(device, encoder, staging);
+        self.primitive_buffer.flush(device, encoder, staging);
+        self.material_buffer.flush(device, encoder, staging);
+        self.fresnel_buffer.flush(device, encoder, staging);
+        self.light_buffer.flush(device, encoder, staging);
+        self.volume_buffer.flush(device, encoder, staging);
+    }
+}
+
+/// Rows of an image of `size` traced per dispatch for at most
+/// `rays_per_submit` rays, whole workgroups of them.
+fn band_rows(size: wgpu::Extent3d, rays_per_submit: u32, workgroup_size: u32) -> u32 {
+    match rays_per_submit {
+        0 => size.height,
+        rays => (rays / size.width / workgroup_size).max(1) * workgroup_size,
+    }
+}
+
+/// What the tracer's shaders are preprocessed with. Shading stays in
+/// full precision even on devices with `SHADER_F16`, since the WGSL parser
+/// of wgpu 22 knows neither `enable f16` nor the `f16` type.
+fn shader_defines(workgroup_size: u32, stack_size: u32) -> [(&'static str, String); 2] {
+    [
+        ("WORKGROUP_SIZE", workgroup_size.to_string()),
+        ("STACK_SIZE", format!("{stack_size}u")),
+    ]
+}
+
+/// Traversal stack the trace kernel is built with for `bvh`, rounded up so
+/// the top-level tree getting a little deeper doesn't rebuild it.
+fn stack_size(bvh: &InstancedBvh) -> u32 {
+    bvh.stack_size().next_multiple_of(16)
+}
+
+/// Settings the trace kernel is built for through its overridable
+/// constants, so the driver can leave out the code they turn off. Changing
+/// them builds a new pipeline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Specialization {
+    max_depth: u32,
+    spectral: bool,
+    light_sampling: bool,
+}
+
+impl Specialization {
+    fn new(settings: &TraceSettings) -> Self {
+        Self {
+            max_depth: settings.max_depth,
+            spectral: settings.spectral,
+            light_sampling: settings.light_sampling,
+        }
+    }
+
+    fn constants(&self) -> HashMap<String, f64> {
+        HashMap::from([
+            (String::from("MAX_DEPTH"), self.max_depth as f64),
+            (String::from("SPECTRAL"), self.spectral as u32 as f64),
+            (
+                String::from("LIGHT_SAMPLING"),
+                self.light_sampling as u32 as f64,
+            ),
+        ])
+    }
+}
+
+fn trace_module(device: &wgpu::Device, source: &str) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Trace Shader"),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    })
+}
+
+fn trace_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    specialization: Specialization,
+) -> wgpu::ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Trace Pipeline"),
+        layout: Some(layout),
+        module,
+        entry_point: "main",
+        compilation_options: wgpu::PipelineCompilationOptions {
+            constants: &specialization.constants(),
+            ..Default::default()
+        },
+        cache: None,
+    })
+}
+
+fn resolve_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+) -> wgpu::ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Resolve Pipeline"),
+        layout: Some(layout),
+        module,
+        entry_point: "resolve",
+        compilation_options: wgpu::PipelineCompilationOptions::default(),
+        cache: None,
+    })
+}
+
+impl Renderer for PathTracer {
+    fn init(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+        bvh: &InstancedBvh,
+    ) {
+        let size = self.targets.color[0].size();
+        *self = Self::new(
+            device,
+            queue,
+            scene,
+            bvh,
+            PhysicalSize::new(size.width, size.height),
+            self.workgroup_size,
+        );
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, size: PhysicalSize<u32>) {
+        self.targets = Targets::new(
+            device,
+            [&self.target_layout, &self.resolve_layout],
+            &self.params_buffer,
+            &self.resolve_buffer,
+            size,
+        );
+        self.reset();
+    }
+
+    fn render_frame(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        camera: &Camera,
+        settings: &TraceSettings,
+    ) {
+        let specialization = Specialization::new(settings);
+        if specialization != self.specialization || self.stale_pipeline {
+            tracing::debug!("Specializing the trace pipeline for {specialization:?}");
+            self.specialize(device, specialization);
+        }
+        // The last frame was submitted, so its staging can come back
+        self.staging.next_frame();
+        self.flush_updates(device, encoder);
+        // Dynamic geometry only moves when the image restarts
+        if let (0, 0, Some(ocean)) = (self.frame, self.row, &self.ocean) {
+            ocean.update(queue, encoder, settings.time);
+        }
+        let reproject = self.moved;
+        let mut params = settings.params(
+            &self.info,
+            camera.uniform(),
+            self.frame,
+            self.seed,
+            reproject,
+        );
+        params.row_offset = self.row;
+        let params_offset = self
+            .params_buffer
+            .write(device, encoder, &mut self.staging, &params);
+
+        let size = self.targets.color[0].size();
+        let read = (self.frame % 2) as usize;
+        let band = band_rows(size, settings.rays_per_submit, self.workgroup_size)
+            .min(size.height - self.row);
+        // The history is only carried over once the whole frame is traced
+        let resolve = reproject && self.row + band >= size.height;
+        let mut resolve_offset = 0;
+        if resolve {
+            let resolve = ResolveParams {
+                camera: params.camera,
+                prev_camera: self.prev_camera,
+                history: self.frame,
+                _pad: [0; 3],
+            };
+            resolve_offset =
+                self.resolve_buffer
+                    .write(device, encoder, &mut self.staging, &resolve);
+        }
+        self.staging.finish();
+        let columns = size.width.div_ceil(self.workgroup_size);
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Trace Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.targets.bind_groups[read], &[params_offset]);
+        pass.set_bind_group(1, &self.scene_bind_group, &[]);
+        pass.set_bind_group(2, &self.material_textures.bind_group, &[]);
+        pass.dispatch_workgroups(columns, band.div_ceil(self.workgroup_size), 1);
+        if resolve {
+            pass.set_pipeline(&self.resolve_pipeline);
+            pass.set_bind_group(
+                0,
+                &self.targets.resolve_bind_groups[read],
+                &[resolve_offset],
+            );
+            pass.dispatch_workgroups(columns, size.height.div_ceil(self.workgroup_size), 1);
+        }
+        drop(pass);
+        if resolve {
+            encoder.copy_texture_to_texture(
+                self.targets.resolved.as_image_copy(),
+                self.targets.color[1 - read].as_image_copy(),
+                size,
+            );
+        }
+
+        self.row += band;
+        if self.row < size.height {
+            return;
+        }
+        self.row = 0;
+        self.moved = false;
+        self.prev_camera = params.camera;
+        self.frame += 1;
+        self.seed = self.seed.wrapping_add(1);
+    }
+
+    fn backend(&self) -> Backend {
+        Backend::Gpu
+    }
+
+    fn reset(&mut self) {
+        self.frame = 0;
+        self.seed = 0;
+        self.moved = false;
+        self.row = 0;
+    }
+
+    fn camera_moved(&mut self, reproject: bool) {
+        if reproject && self.frame > 0 {
+            self.frame = self.frame.min(HISTORY_LIMIT);
+            self.moved = true;
+            self.row = 0;
+        } else {
+            self.reset();
+        }
+    }
+
+    fn update_geometry(&mut self, _: &wgpu::Queue, scene: &Scene, bvh: &InstancedBvh) {
+        // A deeper top-level tree needs a larger stack, set when the
+        // pipeline is built
+        let stack_size = stack_size(bvh);
+        if stack_size > self.stack_size {
+            tracing::debug!("Growing the traversal stack to {stack_size} entries");
+            let defines = shader_defines(self.workgroup_size, stack_size);
+            self.trace_source = shader::builtin_source("trace.wgsl", &defines) + &self.sdf_wgsl;
+            self.stack_size = stack_size;
+            self.stale_pipeline = true;
+        }
+        // The bottom-level trees and their triangles never change
+        self.node_buffer.update_from(0, &bvh.tlas_nodes());
+        self.instance_buffer.update(gpu_instances(scene, bvh));
+        self.primitive_buffer.update(gpu_primitives(scene));
+        // The grids behind the volumes never change
+        let volumes = gpu_volumes(scene);
+        self.volume_buffer
+            .update_from(0, bytemuck::cast_slice(&volumes));
+        self.reset();
+    }
+
+    fn update_materials(&mut self, _: &wgpu::Queue, scene: &Scene) {
+        let (materials, fresnel_tables) = gpu_materials(scene, Some(&self.material_textures));
+        self.material_buffer.update(materials);
+        self.fresnel_buffer.update(fresnel_tables);
+        self.reset();
+    }
+
+    fn update_lights(&mut self, _: &wgpu::Queue, scene: &Scene) {
+        let (lights, light_count) = gpu_lights(scene);
+        debug_assert_eq!(light_count, self.info.light_count);
+        self.light_buffer.update(lights);
+        self.reset();
+    }
+
+    fn is_animated(&self) -> bool {
+        self.ocean.is_some()
+    }
+
+    fn has_environment(&self) -> bool {
+        self.info.has_environment
+    }
+
+    fn sample_count(&self) -> u32 {
+        self.frame
+    }
+
+    fn output_texture(&self) -> &wgpu::Texture {
+        &self.targets.color[(self.frame % 2) as usize]
+    }
+
+    fn output_view(&self) -> &wgpu::TextureView {
+        &self.targets.color_views[(self.frame % 2) as usize]
+    }
+
+    fn guide_views(&self) -> [&wgpu::TextureView; 2] {
+        let latest = (self.frame % 2) as usize;
+        [
+            &self.targets.albedo_views[latest],
+            &self.targets.normal_views[latest],
+        ]
+    }
+
+    fn object_view(&self) -> &wgpu::TextureView {
+        &self.targets.object_view
+    }
+
+    fn albedo_texture(&self) -> &wgpu::Texture {
+        &self.targets.albedo[(self.frame % 2) as usize]
+    }
+
+    fn normal_texture(&self) -> &wgpu::Texture {
+        &self.targets.normal[(self.frame % 2) as usize]
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn reload_shaders(&mut self, device: &wgpu::Device, changed: &[String]) {
+        let defines = shader_defines(self.workgroup_size, self.stack_size);
+        if let Some(source) = hot_reload::compose("trace.wgsl", &defines, changed) {
+            let source = source + &self.sdf_wgsl;
+            let layout = &self.pipeline_layout;
+            let specialization = self.specialization;
+            if let Some(pipeline) = hot_reload::compile(device, "Trace Shader", &source, |module| {
+                trace_pipeline(device, layout, module, specialization)
+            }) {
+                self.pipeline = pipeline;
+                self.trace_source = source;
+                // The samples so far are of the old shader
+                self.reset();
+            }
+        }
+        if let Some(source) = hot_reload::compose("reproject.wgsl", &defines, changed) {
+            let layout = &self.resolve_pipeline_layout;
+            if let Some(pipeline) =
+                hot_reload::compile(device, "Reproject Shader", &source, |module| {
+                    resolve_pipeline(device, layout, module)
+                })
+            {
+                self.resolve_pipeline = pipeline;
+            }
+        }
+    }
+
+    fn set_workgroup_size(&mut self, device: &wgpu::Device, size: u32) {
+        if size == self.workgroup_size {
+            return;
+        }
+        let defines = shader_defines(size, self.stack_size);
+        self.trace_source = shader::builtin_source("trace.wgsl", &defines) + &self.sdf_wgsl;
+        self.specialize(device, self.specialization);
+        let shader = shader::create_module(device, "Reproject Shader", "reproject.wgsl", &defines);
+        self.resolve_pipeline = resolve_pipeline(device, &self.resolve_pipeline_layout, &shader);
+        self.workgroup_size = size;
+    }
+}
+
+/// Directional albedo of the Charlie sheen lobe with Ashikhmin visibility,
+/// indexed by the cosine of the view angle and by the sheen roughness. It
+/// sets how much light the sheen takes away from the layers underneath.
+fn sheen_albedo() -> Vec<f32> {
+    const THETA_STEPS: usize = 32;
+    const PHI_STEPS: usize = 64;
+    let n = SHEEN_TABLE_SIZE;
+    let mut table = Vec::with_capacity(n * n);
+    for j in 0..n {
+        let roughness = j as f32 / (n - 1) as f32;
+        let alpha = (roughness * roughness).max(MIN_SHEEN_ALPHA);
+        for i in 0..n {
+            let cos_o = (i as f32 / (n - 1) as f32).max(1e-3);
+            let wo = Vec3::new((1.0 - cos_o * cos_o).sqrt(), 0.0, cos_o);
+            let mut albedo = 0.0;
+            for t in 0..THETA_STEPS {
+                let theta = (t as f32 + 0.5) / THETA_STEPS as f32 * FRAC_PI_2;
+                let (sin_theta, cos_i) = theta.sin_cos();
+                for p in 0..PHI_STEPS {
+                    let phi = (p as f32 + 0.5) / PHI_STEPS as f32 * 2.0 * PI;
+                    let wi = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_i);
+                    let h = (wo + wi).normalize();
+                    let sin2_h = (1.0 - h.z * h.z).max(0.0);
+                    let d = (2.0 + 1.0 / alpha) * sin2_h.powf(0.5 / alpha) / (2.0 * PI);
+                    let v = 1.0 / (4.0 * (cos_o + cos_i - cos_o * cos_i));
+                    albedo += d * v * cos_i * sin_theta;
+                }
+            }
+            albedo *= FRAC_PI_2 / THETA_STEPS as f32 * 2.0 * PI / PHI_STEPS as f32;
+            table.push(albedo.min(1.0));
+        }
+    }
+    table
+}
+
+/// Storage buffer the updates of the scene write to in place. It keeps a
+/// copy of what it holds so an update only uploads the elements that
+/// changed, which keeps editing one material or moving one instance of a
+/// large scene quick.
+struct DynamicBuffer<T> {
+    buffer: wgpu::Buffer,
+    contents: Vec<T>,
+    /// Runs of elements changed since the last frame, which uploads them.
+    pending: Vec<Range<usize>>,
+}
+
+impl<T: bytemuck::Pod> DynamicBuffer<T> {
+    fn new(device: &wgpu::Device, label: &str, contents: Vec<T>) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents: bytemuck::cast_slice(&contents),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        Self {
+            buffer,
+            contents,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Marks every run of elements of `contents` that differ from the
+    /// buffer's for the next `flush`. The GPU can change it too, as the
+    /// ocean does, and what it wrote survives where the update leaves the
+    /// elements alone.
+    fn update(&mut self, contents: Vec<T>) {
+        debug_assert_eq!(contents.len(), self.contents.len());
+        self.update_from(0, &contents);
+    }
+
+    /// As `update`, for the elements from `offset` on that `contents`
+    /// covers, without looking at the others.
+    fn update_from(&mut self, offset: usize, contents: &[T]) {
+        let old = &mut self.contents[offset..offset + contents.len()];
+        let changed = |i: usize| bytemuck::bytes_of(&contents[i]) != bytemuck::bytes_of(&old[i]);
+        let mut i = 0;
+        while i < contents.len() {
+            if !changed(i) {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < contents.len() && changed(i) {
+                i += 1;
+            }
+            self.pending.push(offset + start..offset + i);
+        }
+        old.copy_from_slice(contents);
+    }
+
+    /// Records the copies of the runs changed since the last flush.
+    fn flush(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        staging: &mut FrameStaging,
+    ) {
+        let size = std::mem::size_of::<T>();
+        for run in self.pending.drain(..) {
+            staging.write(
+                device,
+                encoder,
+                &self.buffer,
+                (run.start * size) as u64,
+                bytemuck::cast_slice(&self.contents[run]),
+            );
+        }
+    }
+}
+
+/// Layout entry of a table uploaded by `table_texture`.
+fn table_entry(binding: u32, sample_type: wgpu::TextureSampleType) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            sample_type,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+/// Uploads a table the kernel looks up with `textureLoad`. Textures rather
+/// than storage buffers hold the fixed tables, since wgpu only promises 8
+/// storage buffers per stage and the scene takes those.
+fn table_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    label: &str,
+    [width, height]: [u32; 2],
+    format: wgpu::TextureFormat,
+    data: &[u8],
+) -> wgpu::TextureView {
+    device
+        .create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            data,
+        )
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+/// Uploads the environment or backdrop as half floats, or a black pixel
+/// without one.
+fn upload_image(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    label: &str,
+    environment: Option<&Environment>,
+) -> wgpu::Texture {
+    let black = Environment {
+        width: 1,
+        height: 1,
+        pixels: vec![[0.0; 4]],
+    };
+    let mut environment = environment.unwrap_or(&black).clone();
+    let max_size = device.limits().max_texture_dimension_2d;
+    while environment.width > max_size || environment.height > max_size {
+        environment = environment.downsample();
+        tracing::warn!(
+            "{label} too large, downsampled to {}x{}",
+            environment.width,
+            environment.height
+        );
+    }
+
+    let pixels: Vec<f16> = environment
+        .pixels
+        .iter()
+        .flatten()
+        .map(|&value| f16::from_f32(value))
+        .collect();
+    device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: environment.width,
+                height: environment.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        },
+        wgpu::util::TextureDataOrder::LayerMajor,
+        bytemuck::cast_slice(&pixels),
+    )
+}
+
+/// Image sized texture that traced results go into.
+fn create_target(device: &wgpu::Device, label: &str, size: PhysicalSize<u32>) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size.width.max(1),
+            height: size.height.max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba32Float,
+        usage: wgpu::TextureUsages::STORAGE_BINDING
+            | wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_SRC
+            | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    })
+}
+
+/// Ping-ponged accumulation of the image and of the first hit's albedo
+/// and normal. Bind group `i` reads textures `i` and writes the others.
+/// The first hit's object isn't averaged, so it's only written.
+struct Targets {
+    color: [wgpu::Texture; 2],
+    /// Blend of new samples and reprojected history, copied over the new
+    /// accumulation because it's read while written.
+    resolved: wgpu::Texture,
+    color_views: [wgpu::TextureView; 2],
+    albedo: [wgpu::Texture; 2],
+    albedo_views: [wgpu::TextureView; 2],
+    normal: [wgpu::Texture; 2],
+    normal_views: [wgpu::TextureView; 2],
+    object_view: wgpu::TextureView,
+    bind_groups: [wgpu::BindGroup; 2],
+    resolve_bind_groups: [wgpu::BindGroup; 2],
+}
+
+impl Targets {
+    /// Takes the layouts of the trace and resolve bind groups.
+    fn new(
+        device: &wgpu::Device,
+        [layout, resolve_layout]: [&wgpu::BindGroupLayout; 2],
+        params_buffer: &UniformRing<TraceParams>,
+        resolve_buffer: &UniformRing<ResolveParams>,
+        size: PhysicalSize<u32>,
+    ) -> Self {
+        let make_texture = |label| create_target(device, label, size);
+        let color = [
+            make_texture("Accumulation Texture 0"),
+            make_texture("Accumulation Texture 1"),
+        ];
+        let albedo = [
+            make_texture("Albedo Texture 0"),
+            make_texture("Albedo Texture 1"),
+        ];
+        let normal = [
+            make_texture("Normal Texture 0"),
+            make_texture("Normal Texture 1"),
+        ];
+        let views = |textures: &[wgpu::Texture; 2]| {
+            textures
+                .each_ref()
+                .map(|texture| texture.create_view(&wgpu::TextureViewDescriptor::default()))
+        };
+        let object_view =
+            make_texture("Object Texture").create_view(&wgpu::TextureViewDescriptor::default());
+        let resolved = make_texture("Resolved Texture");
+        let (color_views, albedo_views, normal_views) =
+            (views(&color), views(&albedo), views(&normal));
+        let resolved_view = resolved.create_view(&wgpu::TextureViewDescriptor::default());
+        let view = |binding, view| wgpu::BindGroupEntry {
+            binding,
+            resource: wgpu::BindingResource::TextureView(view),
+        };
+        let make_bind_group = |read: usize| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Accumulation Bind Group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: params_buffer.binding(),
+                    },
+                    view(1, &color_views[read]),
+                    view(2, &color_views[1 - read]),
+                    view(3, &albedo_views[read]),
+                    view(4, &albedo_views[1 - read]),
+                    view(5, &normal_views[read]),
+                    view(6, &normal_views[1 - read]),
+                    view(7, &object_view),
+                ],
+            })
+        };
+        let bind_groups = [make_bind_group(0), make_bind_group(1)];
+        // Blends the samples just written with the accumulation they read
+        let make_resolve_bind_group = |read: usize| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Resolve Bind Group"),
+                layout: resolve_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: resolve_buffer.binding(),
+                    },
+                    view(1, &color_views[1 - read]),
+                    view(2, &normal_views[1 - read]),
+                    view(3, &color_views[read]),
+                    view(4, &normal_views[read]),
+                    view(5, &resolved_view),
+                ],
+            })
+        };
+        let resolve_bind_groups = [make_resolve_bind_group(0), make_resolve_bind_group(1)];
+        Self {
+            color,
+            resolved,
+            color_views,
+            albedo,
+            albedo_views,
+            normal,
+            normal_views,
+            object_view,
+            bind_groups,
+            resolve_bind_groups,
+        }
+    }
+}
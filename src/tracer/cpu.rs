@@ -0,0 +1,1904 @@
+//! Multithreaded CPU version of the trace shader, for machines without a
+//! GPU that runs it well. It reads the same flattened scene and parameters
+//! and fills a texture like the shader's accumulation, but keeps to the
+//! core of the shader: untextured materials made of a diffuse, a GGX and a
+//! smooth dielectric lobe with random walk subsurface scattering, in RGB.
+//! The ocean stays still, camera moves restart the image and denoisers and
+//! AOVs get blank guides.
+
+use std::{
+    f32::consts::{FRAC_1_PI, PI},
+    ops::Range,
+    sync::Arc,
+};
+
+use glam::{Mat3, Mat4, UVec2, Vec2, Vec3, Vec3Swizzles, Vec4};
+use rayon::prelude::*;
+
+use winit::dpi::PhysicalSize;
+
+use super::{
+    create_target, gpu_instances, gpu_lights, gpu_materials, gpu_primitives, object_triangles,
+    Backend, GpuInstance, GpuLight, GpuMaterial, GpuPrimitive, GpuTriangle, GpuVolume, Renderer,
+    SceneInfo, TraceParams, TraceSettings, INSTANCE_MIRRORED, LIGHT_DISK, LIGHT_POINT, LIGHT_QUAD,
+    LIGHT_SPOT, MATERIAL_SUBSURFACE, MATERIAL_THIN_WALLED, PRIMITIVE_BOX, PRIMITIVE_DISK,
+    PRIMITIVE_QUAD, PRIMITIVE_SDF, PRIMITIVE_SPHERE, SDF_BOX, SDF_INTERSECTION, SDF_MANDELBULB,
+    SDF_SHAPE, SDF_SPHERE, SDF_SUBTRACTION, SDF_TORUS, SDF_WGSL, TRIANGLE_HOLDOUT,
+};
+use crate::{
+    bvh::{BvhNode, InstancedBvh},
+    camera::{Camera, CameraProjection, CameraUniform, DistortionMode},
+    nanovdb::Grid,
+    sampler::{pixel_seed, Sampler},
+    scene::{Environment, Scene, Sdf},
+    sky::SkyUniform,
+    spectral::luminance,
+    tile::Tile,
+};
+
+/// Offset of rays leaving a surface, matched in the shader.
+const EPSILON: f32 = 1e-4;
+/// Traversal stack reserved for a walk, which deeper trees grow past.
+const STACK_SIZE: usize = 64;
+const UNDISTORT_ITERATIONS: usize = 8;
+/// Lower bound of the GGX alpha, below which it's too peaked for floats.
+const MIN_ALPHA: f32 = 1e-3;
+/// Sphere tracing, as in sdf.wgsl.
+const SDF_MAX_STEPS: u32 = 256;
+const SDF_HIT_DISTANCE: f32 = 2e-5;
+const SDF_NORMAL_STEP: f32 = 1e-4;
+const MANDELBULB_BAILOUT: f32 = 2.0;
+/// Collisions tracked along a ray before giving up, as in volume.wgsl.
+const VOLUME_MAX_STEPS: u32 = 1024;
+/// Collisions of a random walk before giving up, as in subsurface.wgsl.
+const SUBSURFACE_MAX_STEPS: u32 = 256;
+
+/// Backend that traces with a `CpuTracer` and uploads every frame.
+pub struct CpuRenderer {
+    tracer: CpuTracer,
+    info: SceneInfo,
+    /// The image, and the albedo and normal guides and object IDs, which
+    /// stay blank.
+    targets: [wgpu::Texture; 4],
+    views: [wgpu::TextureView; 4],
+    frame: u32,
+    seed: u32,
+    /// The whole image and the tile of it traced, when it's only a tile.
+    region: Option<(PhysicalSize<u32>, Tile)>,
+}
+
+impl CpuRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        scene: &Scene,
+        bvh: &InstancedBvh,
+        size: PhysicalSize<u32>,
+    ) -> Self {
+        let (targets, views) = create_targets(device, size);
+        Self {
+            tracer: CpuTracer::from_scene(scene, bvh),
+            info: SceneInfo::new(scene),
+            targets,
+            views,
+            frame: 0,
+            seed: 0,
+            region: None,
+        }
+    }
+
+    /// Traces only `tile` of an `image`, with the same pixels as tracing
+    /// the whole of it.
+    pub fn tile(
+        device: &wgpu::Device,
+        scene: &Scene,
+        bvh: &InstancedBvh,
+        image: PhysicalSize<u32>,
+        tile: Tile,
+    ) -> Self {
+        Self {
+            region: Some((image, tile)),
+            ..Self::new(device, scene, bvh, tile.size())
+        }
+    }
+}
+
+fn create_targets(
+    device: &wgpu::Device,
+    size: PhysicalSize<u32>,
+) -> ([wgpu::Texture; 4], [wgpu::TextureView; 4]) {
+    let targets = ["CPU Image", "CPU Albedo", "CPU Normal", "CPU Object"]
+        .map(|label| create_target(device, label, size));
+    let views = targets
+        .each_ref()
+        .map(|texture| texture.create_view(&wgpu::TextureViewDescriptor::default()));
+    (targets, views)
+}
+
+fn real_primitives(scene: &Scene) -> Vec<GpuPrimitive> {
+    let mut primitives = gpu_primitives(scene);
+    primitives.truncate(scene.primitives.len());
+    primitives
+}
+
+/// The volumes as the shader sees them, with the grids looked up directly
+/// instead of from the grid buffer.
+fn cpu_volumes(scene: &Scene) -> Vec<(GpuVolume, Arc<Grid>)> {
+    scene
+        .volumes
+        .iter()
+        .map(|volume| (GpuVolume::new(volume, 0), volume.grid.clone()))
+        .collect()
+}
+
+impl Renderer for CpuRenderer {
+    fn init(&mut self, device: &wgpu::Device, _: &wgpu::Queue, scene: &Scene, bvh: &InstancedBvh) {
+        let size = self.targets[0].size();
+        *self = Self {
+            region: self.region,
+            ..Self::new(
+                device,
+                scene,
+                bvh,
+                PhysicalSize::new(size.width, size.height),
+            )
+        };
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, size: PhysicalSize<u32>) {
+        (self.targets, self.views) = create_targets(device, size);
+        self.region = None;
+        self.reset();
+    }
+
+    fn render_frame(
+        &mut self,
+        _: &wgpu::Device,
+        queue: &wgpu::Queue,
+        _: &mut wgpu::CommandEncoder,
+        camera: &Camera,
+        settings: &TraceSettings,
+    ) {
+        let params = settings.params(&self.info, camera.uniform(), self.frame, self.seed, false);
+        let size = self.targets[0].size();
+        let sampler = settings.sampler.sampler();
+        let (image, origin) = match self.region {
+            Some((image, tile)) => (
+                UVec2::new(image.width, image.height),
+                UVec2::new(tile.x, tile.y),
+            ),
+            None => (UVec2::new(size.width, size.height), UVec2::ZERO),
+        };
+        let pixels = self.tracer.render(
+            &params,
+            &*sampler,
+            image,
+            origin,
+            UVec2::new(size.width, size.height),
+        );
+        queue.write_texture(
+            self.targets[0].as_image_copy(),
+            bytemuck::cast_slice(pixels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(size.width * 16),
+                rows_per_image: None,
+            },
+            size,
+        );
+        self.frame += 1;
+        self.seed = self.seed.wrapping_add(1);
+    }
+
+    fn backend(&self) -> Backend {
+        Backend::Cpu
+    }
+
+    fn reset(&mut self) {
+        self.frame = 0;
+        self.seed = 0;
+    }
+
+    /// Only the GPU reprojects, the CPU starts over.
+    fn camera_moved(&mut self, _: bool) {
+        self.reset();
+    }
+
+    fn update_geometry(&mut self, _: &wgpu::Queue, scene: &Scene, bvh: &InstancedBvh) {
+        // The bottom-level trees and their triangles never change
+        let tlas_nodes = bvh.tlas_nodes();
+        self.tracer.nodes[..tlas_nodes.len()].copy_from_slice(&tlas_nodes);
+        self.tracer.instances = gpu_instances(scene, bvh);
+        self.tracer.primitives = real_primitives(scene);
+        self.tracer.volumes = cpu_volumes(scene);
+        self.reset();
+    }
+
+    fn update_materials(&mut self, _: &wgpu::Queue, scene: &Scene) {
+        self.tracer.materials = gpu_materials(scene, None).0;
+        self.reset();
+    }
+
+    fn update_lights(&mut self, _: &wgpu::Queue, scene: &Scene) {
+        let (lights, light_count) = gpu_lights(scene);
+        self.tracer.lights = lights[..light_count as usize].to_vec();
+        self.reset();
+    }
+
+    fn is_animated(&self) -> bool {
+        false
+    }
+
+    fn has_environment(&self) -> bool {
+        self.info.has_environment
+    }
+
+    fn sample_count(&self) -> u32 {
+        self.frame
+    }
+
+    fn output_texture(&self) -> &wgpu::Texture {
+        &self.targets[0]
+    }
+
+    fn output_view(&self) -> &wgpu::TextureView {
+        &self.views[0]
+    }
+
+    fn guide_views(&self) -> [&wgpu::TextureView; 2] {
+        [&self.views[1], &self.views[2]]
+    }
+
+    fn object_view(&self) -> &wgpu::TextureView {
+        &self.views[3]
+    }
+
+    fn albedo_texture(&self) -> &wgpu::Texture {
+        &self.targets[1]
+    }
+
+    fn normal_texture(&self) -> &wgpu::Texture {
+        &self.targets[2]
+    }
+
+    /// The CPU tracer has no shaders.
+    #[cfg(feature = "hot-reload")]
+    fn reload_shaders(&mut self, _: &wgpu::Device, _: &[String]) {}
+
+    fn set_workgroup_size(&mut self, _: &wgpu::Device, _: u32) {}
+}
+
+pub struct CpuTracer {
+    /// Both levels of the BVH, see `InstancedBvh::nodes`.
+    pub nodes: Vec<BvhNode>,
+    /// In the space of their mesh and in BVH order, as on the GPU.
+    pub triangles: Vec<GpuTriangle>,
+    /// In the order of the top-level leaves.
+    pub instances: Vec<GpuInstance>,
+    /// Only the real primitives, without the placeholder of an empty buffer.
+    pub primitives: Vec<GpuPrimitive>,
+    pub materials: Vec<GpuMaterial>,
+    /// Only the real lights, without the placeholder of an empty buffer.
+    pub lights: Vec<GpuLight>,
+    volumes: Vec<(GpuVolume, Arc<Grid>)>,
+    pub environment: Option<Environment>,
+    pub backdrop: Option<Environment>,
+    /// Running mean of the samples, laid out like the texture.
+    accumulated: Vec<[f32; 4]>,
+}
+
+struct Ray {
+    origin: Vec3,
+    dir: Vec3,
+}
+
+struct Hit {
+    t: f32,
+    u: f32,
+    v: f32,
+    triangle: usize,
+    /// Index into `CpuTracer::instances` of the triangle's instance.
+    instance: usize,
+    /// Set instead of `triangle` when a primitive was hit.
+    primitive: Option<usize>,
+}
+
+/// Random numbers of one path, drawn like `rand2` in the shader.
+struct PathSampler<'a> {
+    sampler: &'a dyn Sampler,
+    seed: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl PathSampler<'_> {
+    fn next2(&mut self) -> Vec2 {
+        let sample = self.sampler.sample(self.seed, self.index, self.dimension);
+        self.dimension += 1;
+        sample
+    }
+
+    fn next(&mut self) -> f32 {
+        self.next2().x
+    }
+}
+
+struct Surface {
+    /// Shading normal, on the side the ray came from.
+    n: Vec3,
+    /// Geometric normal on the same side.
+    ng: Vec3,
+    /// Tangent space around `n`.
+    frame: Mat3,
+    entering: bool,
+}
+
+struct BsdfSample {
+    wi: Vec3,
+    /// BSDF times cosine over pdf.
+    weight: Vec3,
+    /// Zero for the smooth dielectric, which lights can't be sampled for.
+    pdf: f32,
+}
+
+/// Where a ray's tracking through the media stopped.
+enum MediumEvent {
+    /// It got through.
+    None,
+    Scattered {
+        t: f32,
+        anisotropy: f32,
+    },
+    Absorbed,
+}
+
+/// Absorption and scattering coefficients at a point, summed over volumes.
+struct Medium {
+    absorption: Vec3,
+    scattering: Vec3,
+    /// Asymmetry averaged over the volumes by how much they scatter.
+    anisotropy: f32,
+}
+
+/// Where a random walk left the medium under a surface, with the normals
+/// facing out of it.
+struct SubsurfaceExit {
+    position: Vec3,
+    n: Vec3,
+    ng: Vec3,
+}
+
+struct LightSample {
+    wi: Vec3,
+    distance: f32,
+    /// Incident radiance over pdf.
+    weight: Vec3,
+    /// Solid angle density, zero for lights that can't be hit by chance.
+    pdf: f32,
+}
+
+impl CpuTracer {
+    /// Flattens the scene like the GPU upload, without textures.
+    pub fn from_scene(scene: &Scene, bvh: &InstancedBvh) -> Self {
+        if scene.sdfs.iter().any(|sdf| matches!(sdf, Sdf::Wgsl(_))) {
+            tracing::warn!("Distance fields written in WGSL are left out on the CPU");
+        }
+        let (materials, _) = gpu_materials(scene, None);
+        let (mut lights, light_count) = gpu_lights(scene);
+        lights.truncate(light_count as usize);
+        Self {
+            nodes: bvh.nodes(),
+            triangles: object_triangles(scene, bvh),
+            instances: gpu_instances(scene, bvh),
+            primitives: real_primitives(scene),
+            materials,
+            lights,
+            volumes: cpu_volumes(scene),
+            environment: scene.environment.clone(),
+            backdrop: scene.backdrop.clone(),
+            accumulated: Vec::new(),
+        }
+    }
+
+    /// Adds one sample per pixel of the `size` pixels at `origin` of an
+    /// `image`, on every core, and returns them.
+    pub fn render(
+        &mut self,
+        params: &TraceParams,
+        sampler: &dyn Sampler,
+        image: UVec2,
+        origin: UVec2,
+        size: UVec2,
+    ) -> &[[f32; 4]] {
+        let mut accumulated = std::mem::take(&mut self.accumulated);
+        accumulated.resize((size.x * size.y) as usize, [0.0; 4]);
+        let weight = 1.0 / (params.frame + 1) as f32;
+        accumulated
+            .par_chunks_mut(size.x as usize)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    let id = origin + UVec2::new(x as u32, y as u32);
+                    let mut path = PathSampler {
+                        sampler,
+                        seed: pixel_seed(id, image.x),
+                        index: params.seed,
+                        dimension: 0,
+                    };
+                    let sample = self.sample(params, &mut path, id.as_vec2(), image.as_vec2());
+                    if params.frame == 0 {
+                        *pixel = if sample.is_finite() {
+                            sample.into()
+                        } else {
+                            [0.0, 0.0, 0.0, 1.0]
+                        };
+                    } else if sample.is_finite() {
+                        *pixel = Vec4::from(*pixel).lerp(sample, weight).into();
+                    }
+                }
+            });
+        self.accumulated = accumulated;
+        &self.accumulated
+    }
+
+    /// Radiance and coverage of one sample of the pixel.
+    fn sample(
+        &self,
+        params: &TraceParams,
+        path: &mut PathSampler,
+        pixel: Vec2,
+        size: Vec2,
+    ) -> Vec4 {
+        let pixel = pixel + path.next2();
+        let camera = &params.camera;
+        match camera_ray(camera, pixel, size, path) {
+            Some(ray) => {
+                let backdrop_uv = backdrop_position(camera, pixel / size, size);
+                self.radiance(params, path, ray, backdrop_uv)
+            }
+            None if params.transparent != 0 => Vec4::ZERO,
+            None => Vec4::W,
+        }
+    }
+
+    fn radiance(
+        &self,
+        params: &TraceParams,
+        path: &mut PathSampler,
+        primary: Ray,
+        backdrop_uv: Vec2,
+    ) -> Vec4 {
+        let mut ray = primary;
+        let mut throughput = Vec3::ONE;
+        let mut color = Vec3::ZERO;
+        // Density of the last bounce, zero if direct light sampling didn't
+        // cover it
+        let mut pdf = 0.0;
+        let light_count = self.sampled_light_count(params) as f32;
+
+        for depth in 0..params.max_depth {
+            let hit = self.trace(&ray, f32::MAX);
+
+            // Area lights aren't part of the BVH
+            let mut light_t = hit.t;
+            let mut hit_light = None;
+            for light in &self.lights {
+                let t = intersect_light(light, &ray);
+                if t < light_t {
+                    light_t = t;
+                    hit_light = Some(light);
+                }
+            }
+            if let Some(light) = hit_light {
+                let mut mis = 1.0;
+                if pdf > 0.0 {
+                    let cos_light = -ray.dir.dot(light.direction.into());
+                    let light_pdf = light_t * light_t / (light.area * cos_light);
+                    mis = power_heuristic(pdf, light_pdf / light_count);
+                }
+                color += throughput * Vec3::from(light.emission) * mis;
+                break;
+            }
+
+            // The media in front of whatever the ray hits
+            if !self.volumes.is_empty() {
+                match self.track_media(&ray, light_t, &mut throughput, path) {
+                    MediumEvent::None => {}
+                    MediumEvent::Absorbed => break,
+                    MediumEvent::Scattered { t, anisotropy } => {
+                        let wo = -ray.dir;
+                        let position = ray.origin + t * ray.dir;
+                        if params.light_sampling != 0 {
+                            color += throughput
+                                * self.medium_direct_light(params, path, position, wo, anisotropy);
+                        }
+                        let wi = sample_hg(wo, anisotropy, path.next2());
+                        pdf = if params.light_sampling != 0 {
+                            phase_hg(wo.dot(wi), anisotropy)
+                        } else {
+                            0.0
+                        };
+                        if !russian_roulette(depth, &mut throughput, path) {
+                            break;
+                        }
+                        ray = Ray {
+                            origin: position,
+                            dir: wi,
+                        };
+                        continue;
+                    }
+                }
+            }
+
+            if hit.t == f32::MAX {
+                if depth == 0 && (params.transparent != 0 || params.has_backdrop != 0) {
+                    return self.background(params, backdrop_uv);
+                }
+                let mut sun_mis = 1.0;
+                if pdf > 0.0 && sun_enabled(params) {
+                    sun_mis = power_heuristic(pdf, sun_pdf(&params.sky) / light_count);
+                }
+                color += throughput
+                    * (self.environment(params, ray.dir) + sun_mis * sun(params, ray.dir));
+                break;
+            }
+
+            let tri = self.hit_triangle(&ray, &hit);
+            // Holdouts only hide from the camera
+            if depth == 0 && tri.flags & TRIANGLE_HOLDOUT != 0 {
+                return self.background(params, backdrop_uv);
+            }
+            let material = &self.materials[tri.material as usize];
+            color += throughput * Vec3::from(material.emission);
+
+            let [p0, p1, p2] = [tri.p0, tri.p1, tri.p2].map(Vec3::from);
+            let w = 1.0 - hit.u - hit.v;
+            let mut ng = (p1 - p0).cross(p2 - p0).normalize();
+            let mut n =
+                (w * Vec3::from(tri.n0) + hit.u * Vec3::from(tri.n1) + hit.v * Vec3::from(tri.n2))
+                    .normalize_or(ng);
+            let entering = ng.dot(ray.dir) < 0.0;
+            if !entering {
+                ng = -ng;
+            }
+            if n.dot(ng) < 0.0 {
+                n = -n;
+            }
+            let surface = Surface {
+                n,
+                ng,
+                frame: basis(n),
+                entering,
+            };
+
+            let wo = -ray.dir;
+            let position = ray.origin + hit.t * ray.dir;
+            if params.light_sampling != 0 {
+                color +=
+                    throughput * self.direct_light(params, path, material, &surface, position, wo);
+            }
+
+            let Some(bsdf) = sample_material(material, &surface, wo, path) else {
+                break;
+            };
+            throughput *= bsdf.weight;
+            pdf = if params.light_sampling != 0 {
+                bsdf.pdf
+            } else {
+                0.0
+            };
+            let side = if bsdf.wi.dot(n) < 0.0 { -1.0 } else { 1.0 };
+            if throughput == Vec3::ZERO || side * bsdf.wi.dot(ng) <= 0.0 {
+                break;
+            }
+
+            if !russian_roulette(depth, &mut throughput, path) {
+                break;
+            }
+
+            if material.flags & MATERIAL_SUBSURFACE != 0 && side < 0.0 {
+                let start = Ray {
+                    origin: position - ng * EPSILON,
+                    dir: bsdf.wi,
+                };
+                let Some(exit) = self.subsurface_walk(material, start, &mut throughput, path)
+                else {
+                    break;
+                };
+                if params.light_sampling != 0 {
+                    color += throughput * self.exit_direct_light(params, path, &exit);
+                }
+                let wi = basis(exit.n) * sample_cosine_hemisphere(path.next2());
+                if wi.dot(exit.ng) <= 0.0 {
+                    break;
+                }
+                pdf = if params.light_sampling != 0 {
+                    wi.dot(exit.n) * FRAC_1_PI
+                } else {
+                    0.0
+                };
+                ray = Ray {
+                    origin: exit.position + exit.ng * EPSILON,
+                    dir: wi,
+                };
+                continue;
+            }
+
+            ray = Ray {
+                origin: position + side * ng * EPSILON,
+                dir: bsdf.wi,
+            };
+        }
+        color.extend(1.0)
+    }
+
+    /// Closest hit before `t_max`, or a hit at `t_max` if there is none.
+    /// The top-level tree leads to the instances, whose triangles are tested
+    /// in the space of their mesh.
+    fn trace(&self, ray: &Ray, t_max: f32) -> Hit {
+        let mut hit = Hit {
+            t: t_max,
+            u: 0.0,
+            v: 0.0,
+            triangle: 0,
+            instance: 0,
+            primitive: None,
+        };
+        // Primitives aren't part of the BVH
+        for (i, primitive) in self.primitives.iter().enumerate() {
+            let t = intersect_primitive(ray, primitive, hit.t);
+            if t < hit.t {
+                hit = Hit {
+                    t,
+                    primitive: Some(i),
+                    ..hit
+                };
+            }
+        }
+        self.walk(ray, 0, &mut hit, |hit, instances| {
+            for index in instances {
+                self.intersect_instance(ray, index, hit);
+            }
+        });
+        hit
+    }
+
+    /// Walks the bottom-level tree of an instance for a hit nearer than
+    /// `hit`. The ray is moved into the mesh's space without normalizing
+    /// it, so distances carry over.
+    fn intersect_instance(&self, world_ray: &Ray, index: usize, hit: &mut Hit) {
+        let instance = &self.instances[index];
+        let to_local = instance.world_to_local();
+        let ray = Ray {
+            origin: to_local.transform_point3(world_ray.origin),
+            dir: to_local.transform_vector3(world_ray.dir),
+        };
+        // `hit_triangle` swaps the last two vertices of mirrored instances
+        let mirrored = instance.flags & INSTANCE_MIRRORED != 0;
+        self.walk(&ray, instance.root as usize, hit, |hit, triangles| {
+            for i in triangles {
+                if let Some((t, u, v)) = intersect_triangle(&ray, &self.triangles[i]) {
+                    if t < hit.t {
+                        let (u, v) = if mirrored { (v, u) } else { (u, v) };
+                        *hit = Hit {
+                            t,
+                            u,
+                            v,
+                            triangle: i,
+                            instance: index,
+                            primitive: None,
+                        };
+                    }
+                }
+            }
+        });
+    }
+
+    /// Visits the tree under `root` nearest child first, handing the range
+    /// of every leaf the ray reaches before the closest hit so far to
+    /// `leaf`.
+    fn walk(
+        &self,
+        ray: &Ray,
+        root: usize,
+        hit: &mut Hit,
+        mut leaf: impl FnMut(&mut Hit, Range<usize>),
+    ) {
+        let inv_dir = ray.dir.recip();
+        let mut stack = Vec::with_capacity(STACK_SIZE);
+        let mut node_index = root;
+        if intersect_aabb(ray, inv_dir, &self.nodes[root], hit.t) == f32::MAX {
+            return;
+        }
+        loop {
+            let node = &self.nodes[node_index];
+            if node.is_leaf() {
+                let first = node.left_first as usize;
+                leaf(hit, first..first + node.count as usize);
+            } else {
+                let mut near = node.left_first as usize;
+                let mut far = near + 1;
+                let mut t_near = intersect_aabb(ray, inv_dir, &self.nodes[near], hit.t);
+                let mut t_far = intersect_aabb(ray, inv_dir, &self.nodes[far], hit.t);
+                if t_far < t_near {
+                    std::mem::swap(&mut near, &mut far);
+                    std::mem::swap(&mut t_near, &mut t_far);
+                }
+                if t_near != f32::MAX {
+                    if t_far != f32::MAX {
+                        stack.push(far);
+                    }
+                    node_index = near;
+                    continue;
+                }
+            }
+            let Some(next) = stack.pop() else {
+                break;
+            };
+            node_index = next;
+        }
+    }
+
+    /// The triangle hit moved into world space with what its instance adds,
+    /// or the tangent plane of the primitive hit.
+    fn hit_triangle(&self, ray: &Ray, hit: &Hit) -> GpuTriangle {
+        if let Some(i) = hit.primitive {
+            return primitive_triangle(&self.primitives[i], ray.origin + hit.t * ray.dir);
+        }
+        let instance = &self.instances[hit.instance];
+        let local = &self.triangles[hit.triangle];
+        let to_world = instance.local_to_world();
+        let normal_matrix = Mat3::from_mat4(instance.world_to_local()).transpose();
+        // Mirroring flips the bitangent, and the winding to keep the
+        // geometric normal pointing out, which `trace` already swapped u and
+        // v for
+        let mirrored = instance.flags & INSTANCE_MIRRORED != 0;
+        let handedness = if mirrored { -1.0 } else { 1.0 };
+        let [a, b, c] = if mirrored { [0, 2, 1] } else { [0, 1, 2] };
+        let p = [local.p0, local.p1, local.p2].map(|p| to_world.transform_point3(p.into()));
+        let n = [local.n0, local.n1, local.n2]
+            .map(|n| (normal_matrix * Vec3::from(n)).normalize_or_zero());
+        let uv = [local.uv0, local.uv1, local.uv2];
+        let t = [local.t0, local.t1, local.t2].map(|t| {
+            let t = Vec4::from(t);
+            if t == Vec4::ZERO {
+                return [0.0; 4];
+            }
+            let xyz = to_world.transform_vector3(t.truncate()).normalize_or_zero();
+            xyz.extend(t.w * handedness).into()
+        });
+        GpuTriangle {
+            p0: p[a].into(),
+            p1: p[b].into(),
+            p2: p[c].into(),
+            n0: n[a].into(),
+            n1: n[b].into(),
+            n2: n[c].into(),
+            uv0: uv[a],
+            uv1: uv[b],
+            uv2: uv[c],
+            t0: t[a],
+            t1: t[b],
+            t2: t[c],
+            material: instance.material,
+            flags: instance.flags,
+            instance: instance.instance,
+            class: instance.class,
+            ..Default::default()
+        }
+    }
+
+    /// The backdrop behind camera rays, or nothing with zero alpha.
+    fn background(&self, params: &TraceParams, uv: Vec2) -> Vec4 {
+        match &self.backdrop {
+            Some(backdrop) if params.transparent == 0 => {
+                lookup(backdrop, uv.clamp(Vec2::ZERO, Vec2::ONE)).extend(1.0)
+            }
+            _ => Vec4::ZERO,
+        }
+    }
+
+    /// Radiance from infinitely far away, apart from the sun.
+    fn environment(&self, params: &TraceParams, dir: Vec3) -> Vec3 {
+        let Some(environment) = &self.environment else {
+            return params.environment_intensity * sky(&params.sky, dir);
+        };
+        let phi = dir.x.atan2(-dir.z) - params.environment_rotation;
+        let theta = dir.y.clamp(-1.0, 1.0).acos();
+        let uv = Vec2::new(
+            (0.5 + 0.5 * FRAC_1_PI * phi).rem_euclid(1.0),
+            FRAC_1_PI * theta,
+        );
+        params.environment_intensity * lookup(environment, uv)
+    }
+
+    fn sampled_light_count(&self, params: &TraceParams) -> u32 {
+        self.lights.len() as u32 + sun_enabled(params) as u32
+    }
+
+    /// Picks one of the lights direct light sampling covers uniformly and
+    /// samples it, with the pick folded into the weight and density.
+    fn sample_lights(
+        &self,
+        params: &TraceParams,
+        path: &mut PathSampler,
+        position: Vec3,
+    ) -> LightSample {
+        let count = self.sampled_light_count(params);
+        let index = ((path.next() * count as f32) as usize).min(count as usize - 1);
+        let light = match self.lights.get(index) {
+            Some(light) => sample_light(light, position, path),
+            None => sample_sun(params, path),
+        };
+        LightSample {
+            weight: count as f32 * light.weight,
+            pdf: light.pdf / count as f32,
+            ..light
+        }
+    }
+
+    /// What of a light sample reaches `origin`: nothing past a surface,
+    /// otherwise what the media let through.
+    fn visibility(&self, path: &mut PathSampler, origin: Vec3, light: &LightSample) -> Vec3 {
+        let t_max = light.distance * (1.0 - 1e-3);
+        let shadow = Ray {
+            origin,
+            dir: light.wi,
+        };
+        if self.trace(&shadow, t_max).t < t_max {
+            return Vec3::ZERO;
+        }
+        self.transmittance(&shadow, t_max, path)
+    }
+
+    /// Samples one light and casts a shadow ray towards it, weighted
+    /// against `sample_material` finding the same light.
+    fn direct_light(
+        &self,
+        params: &TraceParams,
+        path: &mut PathSampler,
+        material: &GpuMaterial,
+        surface: &Surface,
+        position: Vec3,
+        wo: Vec3,
+    ) -> Vec3 {
+        if self.sampled_light_count(params) == 0 {
+            return Vec3::ZERO;
+        }
+        let light = self.sample_lights(params, path, position);
+        if light.weight == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
+        let (value, pdf) = eval_material(material, surface, wo, light.wi);
+        if value == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
+
+        let shadow = self.visibility(path, position + surface.ng * EPSILON, &light);
+        let mut mis = 1.0;
+        if light.pdf > 0.0 {
+            mis = power_heuristic(light.pdf, pdf);
+        }
+        light.weight * value * mis * shadow
+    }
+
+    /// Next event estimation from a point in a medium, weighted against
+    /// `sample_hg` finding the same light.
+    fn medium_direct_light(
+        &self,
+        params: &TraceParams,
+        path: &mut PathSampler,
+        position: Vec3,
+        wo: Vec3,
+        g: f32,
+    ) -> Vec3 {
+        if self.sampled_light_count(params) == 0 {
+            return Vec3::ZERO;
+        }
+        let light = self.sample_lights(params, path, position);
+        if light.weight == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
+        let phase = phase_hg(wo.dot(light.wi), g);
+        let mut mis = 1.0;
+        if light.pdf > 0.0 {
+            mis = power_heuristic(light.pdf, phase);
+        }
+        light.weight * phase * mis * self.visibility(path, position, &light)
+    }
+
+    /// Next event estimation from where a random walk leaves a surface,
+    /// like from a Lambertian one, weighted against cosine sampling finding
+    /// the same light.
+    fn exit_direct_light(
+        &self,
+        params: &TraceParams,
+        path: &mut PathSampler,
+        exit: &SubsurfaceExit,
+    ) -> Vec3 {
+        if self.sampled_light_count(params) == 0 {
+            return Vec3::ZERO;
+        }
+        let light = self.sample_lights(params, path, exit.position);
+        let cos_i = exit.n.dot(light.wi);
+        if light.weight == Vec3::ZERO || cos_i <= 0.0 || exit.ng.dot(light.wi) <= 0.0 {
+            return Vec3::ZERO;
+        }
+        let pdf = cos_i * FRAC_1_PI;
+        let mut mis = 1.0;
+        if light.pdf > 0.0 {
+            mis = power_heuristic(light.pdf, pdf);
+        }
+        let shadow = self.visibility(path, exit.position + exit.ng * EPSILON, &light);
+        light.weight * pdf * mis * shadow
+    }
+
+    /// Walks from the start of the ray through the medium under the
+    /// surface of `material` until it leaves, updating `throughput` with
+    /// the weight of every collision. Distances are sampled for a channel
+    /// picked by its throughput and weighted against the other channels.
+    fn subsurface_walk(
+        &self,
+        material: &GpuMaterial,
+        start: Ray,
+        throughput: &mut Vec3,
+        path: &mut PathSampler,
+    ) -> Option<SubsurfaceExit> {
+        let absorption = Vec3::from(material.subsurface_absorption);
+        let scattering = Vec3::from(material.subsurface_scattering);
+        let extinction = absorption + scattering;
+        let mut ray = start;
+        for _ in 0..SUBSURFACE_MAX_STEPS {
+            let total = throughput.element_sum();
+            if total <= 0.0 {
+                return None;
+            }
+            let p_channel = *throughput / total;
+            let u = path.next();
+            let sigma = if u < p_channel.x {
+                extinction.x
+            } else if u < p_channel.x + p_channel.y {
+                extinction.y
+            } else {
+                extinction.z
+            };
+            let t = (-(1.0 - path.next()).ln() / sigma).min(f32::MAX);
+            let hit = self.trace(&ray, t);
+            let tr = (-extinction * hit.t).exp();
+            if hit.t < t {
+                // Through to the surface, weighted by the chance of getting
+                // this far with any channel
+                *throughput *= tr / p_channel.dot(tr);
+                let position = ray.origin + hit.t * ray.dir;
+                let tri = self.hit_triangle(&ray, &hit);
+                let [p0, p1, p2] = [tri.p0, tri.p1, tri.p2].map(Vec3::from);
+                let w = 1.0 - hit.u - hit.v;
+                let mut ng = (p1 - p0).cross(p2 - p0).normalize();
+                if ng.dot(ray.dir) < 0.0 {
+                    ng = -ng;
+                }
+                let mut n = (w * Vec3::from(tri.n0)
+                    + hit.u * Vec3::from(tri.n1)
+                    + hit.v * Vec3::from(tri.n2))
+                .normalize_or(ng);
+                if n.dot(ng) < 0.0 {
+                    n = -n;
+                }
+                return Some(SubsurfaceExit { position, n, ng });
+            }
+            // Nothing closes the medium off
+            if t == f32::MAX {
+                return None;
+            }
+            *throughput *= tr * scattering / p_channel.dot(tr * extinction);
+            let anisotropy = material.subsurface_anisotropy;
+            ray = Ray {
+                origin: ray.origin + t * ray.dir,
+                dir: sample_hg(-ray.dir, anisotropy, path.next2()),
+            };
+        }
+        None
+    }
+
+    fn medium(&self, p: Vec3) -> Medium {
+        let mut medium = Medium {
+            absorption: Vec3::ZERO,
+            scattering: Vec3::ZERO,
+            anisotropy: 0.0,
+        };
+        let mut weight = 0.0;
+        for (volume, grid) in &self.volumes {
+            let q = Mat4::from_cols_array_2d(&volume.to_index).transform_point3(p);
+            if q.cmplt(volume.min.into()).any() || q.cmpgt(volume.max.into()).any() {
+                continue;
+            }
+            let density = grid.sample(q).max(0.0);
+            medium.absorption += density * Vec3::from(volume.absorption);
+            medium.scattering += density * Vec3::from(volume.scattering);
+            let scattering = density * luminance(volume.scattering.into());
+            medium.anisotropy += scattering * volume.anisotropy;
+            weight += scattering;
+        }
+        if weight > 0.0 {
+            medium.anisotropy /= weight;
+        }
+        medium
+    }
+
+    /// Span of the ray before `t_max` through any volume, and the sum of
+    /// the majorants of the volumes it passes.
+    fn media_span(&self, ray: &Ray, t_max: f32) -> (f32, f32, f32) {
+        let mut span: (f32, f32, f32) = (f32::MAX, 0.0, 0.0);
+        for (volume, _) in &self.volumes {
+            let to_index = Mat4::from_cols_array_2d(&volume.to_index);
+            let o = to_index.transform_point3(ray.origin);
+            let inv_d = to_index.transform_vector3(ray.dir).recip();
+            let t0 = (Vec3::from(volume.min) - o) * inv_d;
+            let t1 = (Vec3::from(volume.max) - o) * inv_d;
+            let near = t0.min(t1).max_element().max(0.0);
+            let far = t0.max(t1).min_element().min(t_max);
+            if near < far {
+                span = (span.0.min(near), span.1.max(far), span.2 + volume.majorant);
+            }
+        }
+        span
+    }
+
+    /// Tracks the ray through the media up to `t_max` by spectral
+    /// tracking, as in volume.wgsl.
+    fn track_media(
+        &self,
+        ray: &Ray,
+        t_max: f32,
+        throughput: &mut Vec3,
+        path: &mut PathSampler,
+    ) -> MediumEvent {
+        let (mut t, end, majorant) = self.media_span(ray, t_max);
+        if majorant <= 0.0 {
+            return MediumEvent::None;
+        }
+        for _ in 0..VOLUME_MAX_STEPS {
+            t -= (1.0 - path.next()).ln() / majorant;
+            if t >= end {
+                return MediumEvent::None;
+            }
+            let medium = self.medium(ray.origin + t * ray.dir);
+            let null = majorant - medium.absorption - medium.scattering;
+            let p_absorb = (*throughput * medium.absorption).abs().max_element();
+            let p_scatter = (*throughput * medium.scattering).abs().max_element();
+            let p_null = (*throughput * null).abs().max_element();
+            let total = p_absorb + p_scatter + p_null;
+            if total <= 0.0 {
+                break;
+            }
+            let u = path.next() * total;
+            if u < p_absorb {
+                break;
+            }
+            if u < p_absorb + p_scatter {
+                *throughput *= medium.scattering * total / (majorant * p_scatter);
+                return MediumEvent::Scattered {
+                    t,
+                    anisotropy: medium.anisotropy,
+                };
+            }
+            *throughput *= null * total / (majorant * p_null);
+        }
+        *throughput = Vec3::ZERO;
+        MediumEvent::Absorbed
+    }
+
+    /// Transmittance of the media along the ray up to `t_max`, by ratio
+    /// tracking.
+    fn transmittance(&self, ray: &Ray, t_max: f32, path: &mut PathSampler) -> Vec3 {
+        let mut tr = Vec3::ONE;
+        if self.volumes.is_empty() {
+            return tr;
+        }
+        let (mut t, end, majorant) = self.media_span(ray, t_max);
+        if majorant <= 0.0 {
+            return tr;
+        }
+        for _ in 0..VOLUME_MAX_STEPS {
+            t -= (1.0 - path.next()).ln() / majorant;
+            if t >= end {
+                break;
+            }
+            let medium = self.medium(ray.origin + t * ray.dir);
+            tr *= (1.0 - (medium.absorption + medium.scattering) / majorant).max(Vec3::ZERO);
+            if tr == Vec3::ZERO {
+                break;
+            }
+        }
+        tr
+    }
+}
+
+/// Bilinear lookup with `uv` in [0, 1].
+fn lookup(image: &Environment, uv: Vec2) -> Vec3 {
+    let size = UVec2::new(image.width, image.height);
+    let p = (uv * size.as_vec2() - 0.5).max(Vec2::ZERO);
+    let lower = p.floor().as_uvec2().min(size - 1);
+    let upper = (lower + 1).min(size - 1);
+    let fraction = p - lower.as_vec2();
+    let texel =
+        |x: u32, y: u32| Vec4::from(image.pixels[(y * image.width + x) as usize]).truncate();
+    let top = texel(lower.x, lower.y).lerp(texel(upper.x, lower.y), fraction.x);
+    let bottom = texel(lower.x, upper.y).lerp(texel(upper.x, upper.y), fraction.x);
+    top.lerp(bottom, fraction.y)
+}
+
+fn intersect_aabb(ray: &Ray, inv_dir: Vec3, node: &BvhNode, t_max: f32) -> f32 {
+    let t0 = (Vec3::from(node.min) - ray.origin) * inv_dir;
+    let t1 = (Vec3::from(node.max) - ray.origin) * inv_dir;
+    let t_near = t0.min(t1).max_element();
+    let t_far = t0.max(t1).min_element();
+    if t_far >= t_near.max(0.0) && t_near < t_max {
+        t_near
+    } else {
+        f32::MAX
+    }
+}
+
+/// Möller–Trumbore, returns (t, u, v).
+fn intersect_triangle(ray: &Ray, tri: &GpuTriangle) -> Option<(f32, f32, f32)> {
+    let p0 = Vec3::from(tri.p0);
+    let e1 = Vec3::from(tri.p1) - p0;
+    let e2 = Vec3::from(tri.p2) - p0;
+    let p = ray.dir.cross(e2);
+    let det = e1.dot(p);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let s = ray.origin - p0;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(e1);
+    let v = ray.dir.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = e2.dot(q) * inv_det;
+    (t > EPSILON).then_some((t, u, v))
+}
+
+/// The nearer of two roots past the surface the ray left, `f32::MAX` if
+/// neither is.
+fn nearest_root(t0: f32, t1: f32) -> f32 {
+    if t0 > EPSILON {
+        t0
+    } else if t1 > EPSILON {
+        t1
+    } else {
+        f32::MAX
+    }
+}
+
+/// Exact distance to a primitive, `f32::MAX` on a miss, or one found by
+/// sphere tracing up to `t_max` for distance fields. The ray is moved into
+/// the primitive's space without normalizing it, so distances carry over.
+fn intersect_primitive(ray: &Ray, primitive: &GpuPrimitive, t_max: f32) -> f32 {
+    let to_local = primitive.world_to_local();
+    let o = to_local.transform_point3(ray.origin);
+    let d = to_local.transform_vector3(ray.dir);
+    let size = Vec3::from(primitive.size);
+    match primitive.kind {
+        PRIMITIVE_SPHERE => {
+            let (a, b, c) = (d.dot(d), o.dot(d), o.dot(o) - size.x * size.x);
+            let discriminant = b * b - a * c;
+            if discriminant < 0.0 {
+                return f32::MAX;
+            }
+            let root = discriminant.sqrt();
+            return nearest_root((-b - root) / a, (-b + root) / a);
+        }
+        PRIMITIVE_BOX => {
+            let inv_d = d.recip();
+            let t0 = (-size - o) * inv_d;
+            let t1 = (size - o) * inv_d;
+            let t_near = t0.min(t1).max_element();
+            let t_far = t0.max(t1).min_element();
+            if t_near > t_far {
+                return f32::MAX;
+            }
+            return nearest_root(t_near, t_far);
+        }
+        PRIMITIVE_SDF => return march_sdf(primitive, o, d, t_max),
+        _ => {}
+    }
+    // Flat shapes in the XZ plane
+    if d.y == 0.0 {
+        return f32::MAX;
+    }
+    let t = -o.y / d.y;
+    let p = (o + t * d).xz();
+    let half = size.xz();
+    let inside = match primitive.kind {
+        PRIMITIVE_QUAD => p.abs().cmple(half).all(),
+        PRIMITIVE_DISK => (p / half).length_squared() <= 1.0,
+        _ => true,
+    };
+    if t > EPSILON && inside {
+        t
+    } else {
+        f32::MAX
+    }
+}
+
+/// The tangent plane of a primitive at a point on it, as a triangle
+/// spanning one unit of UV from there, so primitives shade like meshes.
+fn primitive_triangle(primitive: &GpuPrimitive, position: Vec3) -> GpuTriangle {
+    let p = primitive.world_to_local().transform_point3(position);
+    let size = Vec3::from(primitive.size);
+    // The plane is mapped one UV unit per local unit
+    let (n, uv, dpdu, dpdv) = match primitive.kind {
+        PRIMITIVE_SPHERE => {
+            let n = p.normalize();
+            let r = p.xz().length();
+            let uv = Vec2::new(
+                0.5 + 0.5 * FRAC_1_PI * p.z.atan2(p.x),
+                FRAC_1_PI * n.y.clamp(-1.0, 1.0).acos(),
+            );
+            let dpdu = 2.0 * PI * Vec3::new(-p.z, 0.0, p.x);
+            let dpdv = if r > 0.0 {
+                PI * Vec3::new(p.y * p.x / r, -r, p.y * p.z / r)
+            } else {
+                Vec3::ZERO
+            };
+            (n, uv, dpdu, dpdv)
+        }
+        PRIMITIVE_BOX => {
+            // The face is along the axis the point is furthest out on
+            let q = p / size;
+            let a = q.abs();
+            if a.x >= a.y && a.x >= a.z {
+                (
+                    Vec3::X * q.x.signum(),
+                    0.5 + 0.5 * q.zy(),
+                    Vec3::Z * 2.0 * size.z,
+                    Vec3::Y * 2.0 * size.y,
+                )
+            } else if a.y >= a.z {
+                (
+                    Vec3::Y * q.y.signum(),
+                    0.5 + 0.5 * q.xz(),
+                    Vec3::X * 2.0 * size.x,
+                    Vec3::Z * 2.0 * size.z,
+                )
+            } else {
+                (
+                    Vec3::Z * q.z.signum(),
+                    0.5 + 0.5 * q.xy(),
+                    Vec3::X * 2.0 * size.x,
+                    Vec3::Y * 2.0 * size.y,
+                )
+            }
+        }
+        PRIMITIVE_QUAD | PRIMITIVE_DISK => (
+            Vec3::Y,
+            0.5 + 0.5 * Vec2::new(p.x / size.x, -p.z / size.z),
+            Vec3::X * 2.0 * size.x,
+            Vec3::NEG_Z * 2.0 * size.z,
+        ),
+        PRIMITIVE_SDF => {
+            // Mapped like the plane, spanned along the surface
+            let n = sdf_normal(primitive, p);
+            let frame = basis(n);
+            (n, Vec2::new(p.x, -p.z), frame.x_axis, frame.y_axis)
+        }
+        _ => (Vec3::Y, Vec2::new(p.x, -p.z), Vec3::X, Vec3::NEG_Z),
+    };
+    let n = primitive
+        .world_to_local()
+        .transpose()
+        .transform_vector3(n)
+        .normalize();
+    let to_world = primitive.local_to_world();
+    let mut e1 = to_world.transform_vector3(dpdu);
+    let mut e2 = to_world.transform_vector3(dpdv);
+    let mut uv1 = uv + Vec2::X;
+    let mut uv2 = uv + Vec2::Y;
+    // The UVs pinch at the poles of spheres
+    if e1.cross(e2).length() < 1e-12 {
+        let frame = basis(n);
+        (e1, e2) = (frame.x_axis, frame.y_axis);
+    }
+    // Wind the triangle around the outward normal, as the UVs and
+    // mirroring transforms may not
+    if e1.cross(e2).dot(n) < 0.0 {
+        std::mem::swap(&mut e1, &mut e2);
+        std::mem::swap(&mut uv1, &mut uv2);
+    }
+    GpuTriangle {
+        p0: position.into(),
+        p1: (position + e1).into(),
+        p2: (position + e2).into(),
+        n0: n.into(),
+        n1: n.into(),
+        n2: n.into(),
+        uv0: uv.into(),
+        uv1: uv1.into(),
+        uv2: uv2.into(),
+        material: primitive.material,
+        instance: primitive.instance,
+        class: primitive.class,
+        ..Default::default()
+    }
+}
+
+/// Distance estimate from the running derivative of the iteration.
+fn mandelbulb(p: Vec3, power: f32, iterations: u32) -> f32 {
+    let mut z = p;
+    let mut dr = 1.0;
+    let mut r = z.length();
+    for _ in 0..iterations {
+        if r >= MANDELBULB_BAILOUT {
+            break;
+        }
+        dr = power * r.powf(power - 1.0) * dr + 1.0;
+        let theta = (z.z / r).clamp(-1.0, 1.0).acos() * power;
+        let phi = z.y.atan2(z.x) * power;
+        z = r.powf(power)
+            * Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.sin() * phi.sin(),
+                theta.cos(),
+            )
+            + p;
+        r = z.length();
+    }
+    0.5 * r.ln() * r / dr
+}
+
+fn sdf_shape(kind: u32, params: Vec4, p: Vec3) -> f32 {
+    match kind {
+        SDF_SPHERE => p.length() - params.x,
+        SDF_BOX => {
+            let q = p.abs() - params.truncate() + params.w;
+            q.max(Vec3::ZERO).length() + q.max_element().min(0.0) - params.w
+        }
+        SDF_TORUS => Vec2::new(p.xz().length() - params.x, p.y).length() - params.y,
+        SDF_MANDELBULB => mandelbulb(p, params.x, params.y as u32),
+        _ => f32::MAX,
+    }
+}
+
+/// Polynomial smooth minimum blending over `k` units, Quilez.
+fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (k - (a - b).abs()).max(0.0) / k;
+    a.min(b) - 0.25 * h * h * k
+}
+
+/// Distance field of a primitive, which never reaches WGSL fields as
+/// the CPU can't run them.
+fn sdf(primitive: &GpuPrimitive, p: Vec3) -> f32 {
+    let [params_a, params_b] = primitive.sdf_params.map(Vec4::from);
+    let a = sdf_shape(primitive.sdf_shapes[0], params_a, p);
+    if primitive.sdf_op == SDF_SHAPE {
+        return a;
+    }
+    let b = sdf_shape(
+        primitive.sdf_shapes[1],
+        params_b,
+        p - Vec3::from(primitive.sdf_offset),
+    );
+    let k = primitive.smoothness;
+    match primitive.sdf_op {
+        SDF_INTERSECTION => -smooth_min(-a, -b, k),
+        SDF_SUBTRACTION => -smooth_min(-a, b, k),
+        _ => smooth_min(a, b, k),
+    }
+}
+
+/// Sphere traces a distance field through its bounds from `o` along `d`,
+/// by the unsigned distance so the surface is found from inside too.
+fn march_sdf(primitive: &GpuPrimitive, o: Vec3, d: Vec3, t_max: f32) -> f32 {
+    if primitive.sdf_op == SDF_WGSL {
+        return f32::MAX;
+    }
+    let size = Vec3::from(primitive.size);
+    let inv_d = d.recip();
+    let t0 = (-size - o) * inv_d;
+    let t1 = (size - o) * inv_d;
+    let mut t = t0.min(t1).max_element().max(0.0);
+    let t_far = t0.max(t1).min_element().min(t_max);
+    let speed = d.length();
+    for _ in 0..SDF_MAX_STEPS {
+        if t >= t_far {
+            break;
+        }
+        let distance = sdf(primitive, o + t * d).abs();
+        if distance < SDF_HIT_DISTANCE && t > EPSILON {
+            return t;
+        }
+        t += distance.max(SDF_HIT_DISTANCE) / speed;
+    }
+    f32::MAX
+}
+
+/// Gradient of a distance field from four samples around `p`, Quilez.
+fn sdf_normal(primitive: &GpuPrimitive, p: Vec3) -> Vec3 {
+    let h = SDF_NORMAL_STEP;
+    [
+        Vec3::new(1.0, -1.0, -1.0),
+        Vec3::new(-1.0, -1.0, 1.0),
+        Vec3::new(-1.0, 1.0, -1.0),
+        Vec3::ONE,
+    ]
+    .into_iter()
+    .map(|k| k * sdf(primitive, p + h * k))
+    .sum::<Vec3>()
+    .normalize_or(Vec3::Y)
+}
+
+/// Distance to the front face of an area light, `f32::MAX` if the ray
+/// misses it.
+fn intersect_light(light: &GpuLight, ray: &Ray) -> f32 {
+    let direction = Vec3::from(light.direction);
+    let cos_light = -ray.dir.dot(direction);
+    if (light.kind != LIGHT_QUAD && light.kind != LIGHT_DISK) || cos_light <= 0.0 {
+        return f32::MAX;
+    }
+    let position = Vec3::from(light.position);
+    let t = (ray.origin - position).dot(direction) / cos_light;
+    if t <= 0.0 {
+        return f32::MAX;
+    }
+    let d = ray.origin + t * ray.dir - position;
+    let (axis_u, axis_v) = (Vec3::from(light.axis_u), Vec3::from(light.axis_v));
+    let s = d.dot(axis_u) / axis_u.length_squared();
+    let r = d.dot(axis_v) / axis_v.length_squared();
+    let inside = match light.kind {
+        LIGHT_QUAD => s.abs().max(r.abs()) <= 1.0,
+        _ => s * s + r * r <= 1.0,
+    };
+    if inside {
+        t
+    } else {
+        f32::MAX
+    }
+}
+
+fn sample_light(light: &GpuLight, p: Vec3, path: &mut PathSampler) -> LightSample {
+    let emission = Vec3::from(light.emission);
+    let position = Vec3::from(light.position);
+    let direction = Vec3::from(light.direction);
+    match light.kind {
+        LIGHT_POINT | LIGHT_SPOT => {
+            let d = position - p;
+            let distance = d.length();
+            let wi = d / distance;
+            let mut weight = emission / (distance * distance);
+            if light.kind == LIGHT_SPOT {
+                weight *= smoothstep(light.cos_outer, light.cos_inner, (-wi).dot(direction));
+            }
+            LightSample {
+                wi,
+                distance,
+                weight,
+                pdf: 0.0,
+            }
+        }
+        LIGHT_QUAD | LIGHT_DISK => {
+            let offset = if light.kind == LIGHT_QUAD {
+                2.0 * path.next2() - 1.0
+            } else {
+                sample_disk(path.next2())
+            };
+            let d = position
+                + offset.x * Vec3::from(light.axis_u)
+                + offset.y * Vec3::from(light.axis_v)
+                - p;
+            let distance = d.length();
+            let wi = d / distance;
+            let cos_light = -wi.dot(direction);
+            if cos_light <= 0.0 {
+                return LightSample {
+                    wi,
+                    distance,
+                    weight: Vec3::ZERO,
+                    pdf: 0.0,
+                };
+            }
+            let pdf = distance * distance / (light.area * cos_light);
+            LightSample {
+                wi,
+                distance,
+                weight: emission / pdf,
+                pdf,
+            }
+        }
+        _ => LightSample {
+            wi: -direction,
+            distance: f32::MAX,
+            weight: emission,
+            pdf: 0.0,
+        },
+    }
+}
+
+fn sun_enabled(params: &TraceParams) -> bool {
+    params.has_environment == 0 && Vec3::from(params.sky.sun_radiance).max_element() > 0.0
+}
+
+/// Uniform over the cone of the solar disk.
+fn sun_pdf(sky: &SkyUniform) -> f32 {
+    1.0 / (2.0 * PI * (1.0 - sky.sun_cos_radius))
+}
+
+fn sun(params: &TraceParams, dir: Vec3) -> Vec3 {
+    let sky = &params.sky;
+    if params.has_environment != 0 || dir.dot(sky.sun_direction.into()) < sky.sun_cos_radius {
+        return Vec3::ZERO;
+    }
+    params.environment_intensity * Vec3::from(sky.sun_radiance)
+}
+
+fn sample_sun(params: &TraceParams, path: &mut PathSampler) -> LightSample {
+    let sky = &params.sky;
+    let u = path.next2();
+    let cos_theta = 1.0 + (sky.sun_cos_radius - 1.0) * u.x;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let (sin_phi, cos_phi) = (2.0 * PI * u.y).sin_cos();
+    let local = Vec3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta);
+    let radiance = params.environment_intensity * Vec3::from(sky.sun_radiance);
+    LightSample {
+        wi: basis(sky.sun_direction.into()) * local,
+        distance: f32::MAX,
+        weight: radiance / sun_pdf(sky),
+        pdf: sun_pdf(sky),
+    }
+}
+
+/// Preetham sky, as in the shader.
+fn sky(sky: &SkyUniform, dir: Vec3) -> Vec3 {
+    if dir.y < 0.0 {
+        return sky.ground_radiance.into();
+    }
+    let cos_gamma = dir.dot(sky.sun_direction.into());
+    let gamma = cos_gamma.clamp(-1.0, 1.0).acos();
+    let [a, b, c, d, e] = [sky.a, sky.b, sky.c, sky.d, sky.e].map(Vec3::from);
+    let horizon = 1.0 + a * (b / dir.y.max(0.01)).exp();
+    let circumsolar = 1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma;
+    let yxy = Vec3::from(sky.zenith) * horizon * circumsolar;
+
+    let y = yxy.z.max(1e-6);
+    let xyz = Vec3::new(yxy.y * yxy.x / y, yxy.x, (1.0 - yxy.y - yxy.z) * yxy.x / y);
+    let to_rgb = Mat3::from_cols(
+        Vec3::new(3.2406, -0.9689, 0.0557),
+        Vec3::new(-1.5372, 1.8758, -0.2040),
+        Vec3::new(-0.4986, 0.0415, 1.0570),
+    );
+    (to_rgb * xyz).max(Vec3::ZERO)
+}
+
+/// Reflection off the opaque base, the BSDF times the cosine and the
+/// density `sample_material` picks `wi` with.
+fn eval_material(material: &GpuMaterial, surface: &Surface, wo: Vec3, wi: Vec3) -> (Vec3, f32) {
+    let n = surface.n;
+    let (cos_o, cos_i) = (n.dot(wo), n.dot(wi));
+    if cos_o <= 0.0 || cos_i <= 0.0 || wi.dot(surface.ng) <= 0.0 {
+        return (Vec3::ZERO, 0.0);
+    }
+    let base = Vec4::from(material.base_color).truncate();
+    let alpha = ggx_alpha(material);
+    let f0 = Vec3::splat(((material.ior - 1.0) / (material.ior + 1.0)).powi(2))
+        .lerp(base, material.metallic);
+    let h = (wo + wi).normalize();
+    let cos_h = n.dot(h);
+    let wo_h = wo.dot(h).max(1e-6);
+    let d = ggx_d(cos_h, alpha);
+    let fresnel = f0 + (1.0 - f0) * (1.0 - wo_h).powi(5);
+    let specular =
+        fresnel * d * smith_g1(cos_o, alpha) * smith_g1(cos_i, alpha) / (4.0 * cos_o * cos_i);
+    let p = specular_probability(material);
+    let pdf_specular = p * d * cos_h / (4.0 * wo_h);
+    let opaque = 1.0 - material.transmission;
+    // The diffuse lobe of subsurface scattering enters the surface instead
+    if material.flags & MATERIAL_SUBSURFACE != 0 {
+        return (specular * cos_i * opaque, pdf_specular * opaque);
+    }
+    let diffuse = base * (1.0 - material.metallic) * (1.0 - fresnel) * FRAC_1_PI;
+    let pdf = pdf_specular + (1.0 - p) * cos_i * FRAC_1_PI;
+    ((diffuse + specular) * cos_i * opaque, pdf * opaque)
+}
+
+fn sample_material(
+    material: &GpuMaterial,
+    surface: &Surface,
+    wo: Vec3,
+    path: &mut PathSampler,
+) -> Option<BsdfSample> {
+    if path.next() < material.transmission {
+        return sample_dielectric(material, surface, wo, path);
+    }
+    let u = path.next2();
+    let p = specular_probability(material);
+    let wi = if path.next() < p {
+        let h = surface.frame * sample_ggx(ggx_alpha(material), u);
+        (-wo).reflect(h)
+    } else if material.flags & MATERIAL_SUBSURFACE != 0 {
+        // Into the surface, for the random walk to carry on from, with
+        // what the diffuse lobe would have reflected
+        let base = Vec4::from(material.base_color).truncate();
+        let f0 = Vec3::splat(((material.ior - 1.0) / (material.ior + 1.0)).powi(2))
+            .lerp(base, material.metallic);
+        let cos_o = surface.n.dot(wo).clamp(0.0, 1.0);
+        let fresnel = f0 + (1.0 - f0) * (1.0 - cos_o).powi(5);
+        let wi = surface.frame * (sample_cosine_hemisphere(u) * Vec3::new(1.0, 1.0, -1.0));
+        return Some(BsdfSample {
+            wi,
+            weight: base * (1.0 - material.metallic) * (1.0 - fresnel) / (1.0 - p),
+            pdf: 0.0,
+        });
+    } else {
+        surface.frame * sample_cosine_hemisphere(u)
+    };
+    let (value, pdf) = eval_material(material, surface, wo, wi);
+    (pdf > 0.0).then(|| BsdfSample {
+        wi,
+        weight: value / pdf,
+        pdf,
+    })
+}
+
+/// Smooth glass, reflecting or refracting by the Fresnel term. A thin
+/// sheet lets light straight through instead.
+fn sample_dielectric(
+    material: &GpuMaterial,
+    surface: &Surface,
+    wo: Vec3,
+    path: &mut PathSampler,
+) -> Option<BsdfSample> {
+    let n = surface.n;
+    let tint = Vec4::from(material.base_color).truncate();
+    let cos_o = n.dot(wo).clamp(0.0, 1.0);
+    let mirror = BsdfSample {
+        wi: (-wo).reflect(n),
+        weight: Vec3::ONE,
+        pdf: 0.0,
+    };
+    if material.flags & MATERIAL_THIN_WALLED != 0 {
+        // Bounces between the two faces add up
+        let r = fresnel_dielectric(cos_o, 1.0 / material.ior);
+        if path.next() < 2.0 * r / (1.0 + r) {
+            return Some(mirror);
+        }
+        return Some(BsdfSample {
+            wi: -wo,
+            weight: tint,
+            pdf: 0.0,
+        });
+    }
+    let eta = if surface.entering {
+        1.0 / material.ior
+    } else {
+        material.ior
+    };
+    if path.next() < fresnel_dielectric(cos_o, eta) {
+        return Some(mirror);
+    }
+    let wi = (-wo).refract(n, eta);
+    (wi != Vec3::ZERO).then_some(BsdfSample {
+        wi,
+        weight: tint,
+        pdf: 0.0,
+    })
+}
+
+/// Unpolarized reflectance with `eta` the ratio of the indices outside
+/// and inside.
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let rp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+fn ggx_alpha(material: &GpuMaterial) -> f32 {
+    (material.roughness * material.roughness).max(MIN_ALPHA)
+}
+
+/// Metals are all specular, dielectrics split evenly.
+fn specular_probability(material: &GpuMaterial) -> f32 {
+    0.5 + 0.5 * material.metallic
+}
+
+fn ggx_d(cos_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = cos_h * cos_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+fn smith_g1(cos: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    2.0 * cos / (cos + (a2 + (1.0 - a2) * cos * cos).sqrt())
+}
+
+/// Half vector with density D(h) cos(h).
+fn sample_ggx(alpha: f32, u: Vec2) -> Vec3 {
+    let tan2_theta = alpha * alpha * u.x / (1.0 - u.x).max(1e-6);
+    let cos_theta = (1.0 + tan2_theta).sqrt().recip();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let (sin_phi, cos_phi) = (2.0 * PI * u.y).sin_cos();
+    Vec3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta)
+}
+
+fn sample_cosine_hemisphere(u: Vec2) -> Vec3 {
+    let d = sample_disk(u);
+    Vec3::new(d.x, d.y, (1.0 - d.length_squared()).max(0.0).sqrt())
+}
+
+/// Concentric mapping of the unit square to the unit disk, Shirley 1997.
+fn sample_disk(u: Vec2) -> Vec2 {
+    let p = 2.0 * u - 1.0;
+    if p == Vec2::ZERO {
+        return Vec2::ZERO;
+    }
+    let (r, theta) = if p.x.abs() > p.y.abs() {
+        (p.x, 0.25 * PI * (p.y / p.x))
+    } else {
+        (p.y, 0.5 * PI - 0.25 * PI * (p.x / p.y))
+    };
+    r * Vec2::new(theta.cos(), theta.sin())
+}
+
+/// Orthonormal basis from a unit normal, Duff et al. 2017.
+fn basis(n: Vec3) -> Mat3 {
+    let s = if n.z >= 0.0 { 1.0 } else { -1.0 };
+    let a = -1.0 / (s + n.z);
+    let b = n.x * n.y * a;
+    let t = Vec3::new(1.0 + s * n.x * n.x * a, s * b, -s * n.x);
+    let bt = Vec3::new(b, s + n.y * n.y * a, -n.y);
+    Mat3::from_cols(t, bt, n)
+}
+
+/// Whether the path goes on past `depth`, making up for the ones ended.
+fn russian_roulette(depth: u32, throughput: &mut Vec3, path: &mut PathSampler) -> bool {
+    if depth < 3 {
+        return true;
+    }
+    let p = throughput.max_element().clamp(0.05, 1.0);
+    if path.next() > p {
+        return false;
+    }
+    *throughput /= p;
+    true
+}
+
+/// Henyey-Greenstein phase function of the angle between `wo` and `wi`,
+/// both pointing away from the point.
+fn phase_hg(cos_theta: f32, g: f32) -> f32 {
+    let denom = 1.0 + g * g + 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denom * denom.max(1e-8).sqrt())
+}
+
+/// Direction scattered into from `wo`, with the density `phase_hg`.
+fn sample_hg(wo: Vec3, g: f32, u: Vec2) -> Vec3 {
+    // Cosine to the direction of travel, -wo
+    let mut cos_theta = 1.0 - 2.0 * u.x;
+    if g.abs() >= 1e-3 {
+        let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u.x);
+        cos_theta = (1.0 + g * g - s * s) / (2.0 * g);
+    }
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    basis(-wo) * Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+fn power_heuristic(pdf: f32, other: f32) -> f32 {
+    let a = pdf * pdf;
+    a / (a + other * other)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Brown-Conrady distortion, as in the shader.
+fn distort(camera: &CameraUniform, p: Vec2) -> Vec2 {
+    let r2 = p.length_squared();
+    let [k1, k2, k3] = camera.distortion_k;
+    let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+    let [p1, p2] = camera.distortion_p;
+    let tangential = Vec2::new(
+        2.0 * p1 * p.x * p.y + p2 * (r2 + 2.0 * p.x * p.x),
+        p1 * (r2 + 2.0 * p.y * p.y) + 2.0 * p2 * p.x * p.y,
+    );
+    p * radial + tangential
+}
+
+fn undistort(camera: &CameraUniform, p: Vec2) -> Vec2 {
+    let mut u = p;
+    for _ in 0..UNDISTORT_ITERATIONS {
+        u += p - distort(camera, u);
+    }
+    u
+}
+
+fn backdrop_position(camera: &CameraUniform, uv: Vec2, size: Vec2) -> Vec2 {
+    if camera.distortion != DistortionMode::Remove as u32
+        || camera.projection != CameraProjection::Perspective as u32
+    {
+        return uv;
+    }
+    let scale = Vec2::new(size.x / size.y, 1.0) * camera.tan_half_fov;
+    let p = (uv * 2.0 - 1.0) * scale;
+    (distort(camera, p) / scale + 1.0) * 0.5
+}
+
+/// None for pixels outside the projection.
+fn camera_ray(
+    camera: &CameraUniform,
+    pixel: Vec2,
+    size: Vec2,
+    path: &mut PathSampler,
+) -> Option<Ray> {
+    let position = Vec3::from(camera.position);
+    let [forward, right, up] = [camera.forward, camera.right, camera.up].map(Vec3::from);
+    let ndc = pixel / size * 2.0 - 1.0;
+    let aspect = size.x / size.y;
+    if camera.projection == CameraProjection::Orthographic as u32 {
+        let half_height = camera.focus_distance * camera.tan_half_fov;
+        let offset = ndc.x * aspect * right - ndc.y * up;
+        return Some(Ray {
+            origin: position + half_height * offset,
+            dir: forward,
+        });
+    }
+    if camera.projection == CameraProjection::Fisheye as u32 {
+        let p = Vec2::new(ndc.x * aspect, -ndc.y);
+        let r = p.length();
+        if r > 1.0 {
+            return None;
+        }
+        let theta = 0.5 * PI * r;
+        let side = p / r.max(1e-8);
+        let dir = theta.cos() * forward + theta.sin() * (side.x * right + side.y * up);
+        return Some(Ray {
+            origin: position,
+            dir: dir.normalize(),
+        });
+    }
+    if camera.projection == CameraProjection::Equirectangular as u32 {
+        let phi = (pixel.x / size.x - 0.5) * 2.0 * PI;
+        let theta = (0.5 - pixel.y / size.y) * PI;
+        let horizontal = phi.sin() * right + phi.cos() * forward;
+        let dir = theta.cos() * horizontal + theta.sin() * up;
+        return Some(Ray {
+            origin: position,
+            dir: dir.normalize(),
+        });
+    }
+
+    let mut p = Vec2::new(ndc.x * aspect, ndc.y) * camera.tan_half_fov;
+    if camera.distortion == DistortionMode::Apply as u32 {
+        p = undistort(camera, p);
+    }
+    let dir = forward + p.x * right - p.y * up;
+    if camera.aperture_radius <= 0.0 {
+        return Some(Ray {
+            origin: position,
+            dir: dir.normalize(),
+        });
+    }
+
+    // Thin lens: start on the aperture and aim at the point on the focal plane
+    let focus_point = position + dir * camera.focus_distance;
+    let lens = camera.aperture_radius * sample_disk(path.next2());
+    let origin = position + lens.x * right + lens.y * up;
+    Some(Ray {
+        origin,
+        dir: (focus_point - origin).normalize(),
+    })
+}
@@ -0,0 +1,132 @@
+//! Picks the workgroup size of the tracer's kernels per adapter. The
+//! fastest size differs a lot between vendors, so a few are timed on the
+//! scene at hand the first time an adapter is seen, and the winner is kept
+//! in a cache file for the next runs.
+
+use std::path::PathBuf;
+
+use web_time::{Duration, Instant};
+
+use crate::{
+    camera::Camera,
+    tracer::{Renderer, TraceSettings, WORKGROUP_SIZE},
+};
+
+/// Threads along each side of a workgroup tried, the default first so it
+/// wins ties.
+const CANDIDATES: [u32; 3] = [WORKGROUP_SIZE, 4, 16];
+/// Frames timed per candidate, after one that compiles and warms up.
+const FRAMES: u32 = 4;
+const CACHE_FILE: &str = "spectrum-workgroup-sizes.txt";
+
+/// Sets the workgroup size of `tracer` to the fastest for `adapter` and
+/// returns it, timing the candidates on it unless the cache already knows.
+/// The accumulated samples are thrown away if it had to time them.
+pub fn tune(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    adapter: &wgpu::AdapterInfo,
+    tracer: &mut dyn Renderer,
+    camera: &Camera,
+    settings: &TraceSettings,
+) -> u32 {
+    let key = cache_key(adapter);
+    if let Some(size) = cached(&key) {
+        tracing::debug!("Workgroup size {size} cached for {}", adapter.name);
+        tracer.set_workgroup_size(device, size);
+        return size;
+    }
+    // Browsers don't let the CPU wait for the GPU to time it
+    if cfg!(target_arch = "wasm32") {
+        return WORKGROUP_SIZE;
+    }
+
+    let limits = device.limits();
+    let fits = |&size: &u32| {
+        size <= limits.max_compute_workgroup_size_x
+            && size <= limits.max_compute_workgroup_size_y
+            && size * size <= limits.max_compute_invocations_per_workgroup
+    };
+    let mut best = (WORKGROUP_SIZE, Duration::MAX);
+    for size in CANDIDATES.into_iter().filter(fits) {
+        tracer.set_workgroup_size(device, size);
+        render(device, queue, tracer, camera, settings, 1);
+        let start = Instant::now();
+        render(device, queue, tracer, camera, settings, FRAMES);
+        let elapsed = start.elapsed();
+        tracing::debug!(
+            "Workgroup size {size} took {:.2} ms a frame",
+            elapsed.as_secs_f64() * 1000.0 / FRAMES as f64
+        );
+        if elapsed < best.1 {
+            best = (size, elapsed);
+        }
+    }
+    tracing::info!("Picked workgroup size {} for {}", best.0, adapter.name);
+    tracer.set_workgroup_size(device, best.0);
+    tracer.reset();
+    store(&key, best.0);
+    best.0
+}
+
+/// Traces `frames` calls of `render_frame` and waits for the GPU.
+fn render(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    tracer: &mut dyn Renderer,
+    camera: &Camera,
+    settings: &TraceSettings,
+    frames: u32,
+) {
+    for _ in 0..frames {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Tuning Encoder"),
+        });
+        tracer.render_frame(device, queue, &mut encoder, camera, settings);
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+    device.poll(wgpu::Maintain::Wait);
+}
+
+/// Identifies the adapter along with its driver, since a new driver can
+/// change which size is fastest.
+fn cache_key(adapter: &wgpu::AdapterInfo) -> String {
+    format!(
+        "{:?} {:04x}:{:04x} {} {} {}",
+        adapter.backend,
+        adapter.vendor,
+        adapter.device,
+        adapter.name,
+        adapter.driver,
+        adapter.driver_info
+    )
+    .replace('\n', " ")
+}
+
+/// One `<size> <key>` line per adapter seen.
+fn cache_path() -> Option<PathBuf> {
+    if cfg!(target_arch = "wasm32") {
+        return None;
+    }
+    Some(std::env::temp_dir().join(CACHE_FILE))
+}
+
+fn cached(key: &str) -> Option<u32> {
+    let text = std::fs::read_to_string(cache_path()?).ok()?;
+    text.lines().find_map(|line| {
+        let (size, line_key) = line.split_once(' ')?;
+        (line_key == key).then(|| size.parse().ok()).flatten()
+    })
+}
+
+fn store(key: &str, size: u32) {
+    let Some(path) = cache_path() else {
+        return;
+    };
+    let mut text = std::fs::read_to_string(&path).unwrap_or_default();
+    text.push_str(&format!("{size} {key}\n"));
+    // Only costs the timing again on the next run
+    if let Err(err) = std::fs::write(&path, text) {
+        tracing::warn!("Failed to save {}: {err}", path.display());
+    }
+}
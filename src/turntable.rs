@@ -0,0 +1,87 @@
+//! Turntable that spins the camera, or the selected instance, around a
+//! vertical axis through a pivot at a steady speed, for looking a model over
+//! or recording a spin.
+
+use glam::{Mat4, Quat, Vec3};
+
+use crate::{camera::Camera, scene::Scene};
+
+#[derive(Clone, Debug)]
+pub struct Turntable {
+    /// Degrees per second, negative turns the other way.
+    pub speed: f32,
+    /// Point turned around, the center of the selection or of the scene if
+    /// none.
+    pub pivot: Option<Vec3>,
+    /// Turns the selected instance instead of the camera.
+    pub spin_selected: bool,
+    /// Keeps accumulating as the camera turns, blurring the spin like a
+    /// long exposure, instead of restarting the image every step. Moved
+    /// instances always restart it.
+    pub motion_blur: bool,
+    /// Where it's turning around, while on.
+    center: Option<Vec3>,
+}
+
+impl Default for Turntable {
+    fn default() -> Self {
+        Self {
+            speed: 30.0,
+            pivot: None,
+            spin_selected: false,
+            motion_blur: false,
+            center: None,
+        }
+    }
+}
+
+impl Turntable {
+    /// Parses a pivot as `x,y,z`.
+    pub fn parse_pivot(text: &str) -> Option<Vec3> {
+        let coordinates: Vec<f32> = text
+            .split(',')
+            .map(|value| value.trim().parse().ok())
+            .collect::<Option<_>>()?;
+        <[f32; 3]>::try_from(coordinates).ok().map(Vec3::from)
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.center.is_some()
+    }
+
+    /// Starts turning around the pivot, or around the center of what
+    /// `selected` is if there's none.
+    pub fn start(&mut self, scene: &Scene, selected: Option<usize>) {
+        let bounds = selected.map_or_else(|| scene.bounds(), |index| scene.instance_bounds(index));
+        self.center = Some(self.pivot.unwrap_or_else(|| bounds.center()));
+    }
+
+    pub fn stop(&mut self) {
+        self.center = None;
+    }
+
+    /// The axis and radians to turn by as `dt` seconds pass, none while
+    /// off.
+    pub fn step(&self, dt: f32) -> Option<(Vec3, f32)> {
+        let center = self.center?;
+        let angle = (self.speed * dt).to_radians();
+        (angle != 0.0).then_some((center, angle))
+    }
+}
+
+/// Moves the camera `angle` radians around a vertical axis through
+/// `center`, turning it to keep the same view of it.
+pub fn turn_camera(camera: &mut Camera, center: Vec3, angle: f32) {
+    // Yaw turns the other way than a rotation around +Y
+    camera.position = center + Quat::from_rotation_y(-angle) * (camera.position - center);
+    camera.yaw += angle;
+}
+
+/// The transform of an instance turned `angle` radians around a vertical
+/// axis through `center`.
+pub fn turn_transform(transform: Mat4, center: Vec3, angle: f32) -> Mat4 {
+    Mat4::from_translation(center)
+        * Mat4::from_rotation_y(-angle)
+        * Mat4::from_translation(-center)
+        * transform
+}
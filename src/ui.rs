@@ -0,0 +1,869 @@
+//! Optional egui overlay for tweaking the renderer at runtime, F1 toggles it.
+
+use std::path::PathBuf;
+
+use winit::{
+    event::{ElementState, KeyEvent, WindowEvent},
+    keyboard::{KeyCode, PhysicalKey},
+    window::Window,
+};
+
+use crate::{
+    aov::Aov,
+    autofocus::AutofocusMode,
+    camera::{
+        Camera, CameraController, CameraMode, CameraProjection, DistortionMode, LensDistortion,
+    },
+    camera_path::PathRecorder,
+    control::ControlTarget,
+    preset::Preset,
+    sampler::SamplerKind,
+    scene::Scene,
+    stats::{FrameStats, SceneStats},
+    timeline::{Easing, Timeline},
+    tonemap::Tonemap,
+    tracer::{Backend, Renderer, TraceSettings},
+    turntable::Turntable,
+    DenoiseMode, PresentMode, Settings, MAX_EXPOSURE,
+};
+
+const CAMERA_TARGETS: [ControlTarget; 7] = [
+    ControlTarget::CameraPosition(0),
+    ControlTarget::CameraPosition(1),
+    ControlTarget::CameraPosition(2),
+    ControlTarget::CameraYaw,
+    ControlTarget::CameraPitch,
+    ControlTarget::CameraFov,
+    ControlTarget::CameraFocusDistance,
+];
+
+/// Everything the panels can edit.
+pub struct Panels<'a> {
+    pub camera: &'a mut Camera,
+    pub controller: &'a mut CameraController,
+    pub tracer: &'a dyn Renderer,
+    pub trace_settings: &'a mut TraceSettings,
+    pub settings: &'a mut Settings,
+    pub timeline: &'a mut Timeline,
+    pub camera_path: &'a mut PathRecorder,
+    pub turntable: &'a mut Turntable,
+    pub selected: &'a mut Option<usize>,
+    pub autofocus: &'a mut AutofocusMode,
+    /// Ways the window's surface can present frames.
+    pub present_modes: &'a [wgpu::PresentMode],
+    pub scene: &'a Scene,
+    /// Shown in a corner, even with the panels hidden.
+    pub stats: Option<(&'a FrameStats, &'a SceneStats)>,
+}
+
+/// File typed into the import field, and the one to add to the scene once
+/// the frame is done.
+struct ImportEditor {
+    path: String,
+    requested: Option<PathBuf>,
+}
+
+/// State of the timeline panel between frames.
+struct TimelineEditor {
+    target: ControlTarget,
+    easing: Easing,
+    path: String,
+    /// Set when the user starts or stops recording the camera path.
+    record_toggle: bool,
+}
+
+pub struct Ui {
+    context: egui::Context,
+    state: egui_winit::State,
+    renderer: egui_wgpu::Renderer,
+    visible: bool,
+    editor: TimelineEditor,
+    import: ImportEditor,
+    /// Instance to turn into a holdout or back once the frame is done.
+    holdout_toggle: Option<usize>,
+    /// Backend to switch to once the frame is done.
+    backend: Option<Backend>,
+    /// Set when the user asks for one more frame while paused.
+    step: bool,
+}
+
+impl Ui {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, window: &Window) -> Self {
+        let context = egui::Context::default();
+        let state = egui_winit::State::new(
+            context.clone(),
+            egui::ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            None,
+            Some(device.limits().max_texture_dimension_2d as usize),
+        );
+        let renderer = egui_wgpu::Renderer::new(device, format, None, 1, false);
+        Self {
+            context,
+            state,
+            renderer,
+            visible: true,
+            editor: TimelineEditor {
+                target: ControlTarget::CameraPosition(0),
+                easing: Easing::EaseInOut,
+                path: String::from("timeline.txt"),
+                record_toggle: false,
+            },
+            import: ImportEditor {
+                path: String::new(),
+                requested: None,
+            },
+            holdout_toggle: None,
+            backend: None,
+            step: false,
+        }
+    }
+
+    /// File the user asked to merge into the scene, if any.
+    pub fn take_import(&mut self) -> Option<PathBuf> {
+        self.import.requested.take()
+    }
+
+    /// Instance whose holdout flag the user flipped, if any.
+    pub fn take_holdout_toggle(&mut self) -> Option<usize> {
+        self.holdout_toggle.take()
+    }
+
+    /// Whether the user started or stopped recording the camera path.
+    pub fn take_record_toggle(&mut self) -> bool {
+        std::mem::take(&mut self.editor.record_toggle)
+    }
+
+    /// Whether the user asked for one more frame while paused.
+    pub fn take_step(&mut self) -> bool {
+        std::mem::take(&mut self.step)
+    }
+
+    /// Backend the user picked, if any.
+    pub fn take_backend(&mut self) -> Option<Backend> {
+        self.backend.take()
+    }
+
+    /// Returns true if the overlay consumed the event.
+    pub fn on_window_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        if let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::F1),
+                    repeat: false,
+                    ..
+                },
+            ..
+        } = event
+        {
+            self.visible = !self.visible;
+            return true;
+        }
+        if !self.visible {
+            return false;
+        }
+        self.state.on_window_event(window, event).consumed
+    }
+
+    /// Draws the overlay on top of `view`. Returns true if a setting changed
+    /// that invalidates the accumulated image.
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        window: &Window,
+        mut panels: Panels,
+    ) -> bool {
+        if !self.visible && panels.stats.is_none() {
+            return false;
+        }
+
+        let mut changed = false;
+        let input = self.state.take_egui_input(window);
+        let output = self.context.run(input, |context| {
+            if let Some((stats, scene_stats)) = panels.stats {
+                draw_stats(context, stats, scene_stats);
+            }
+            if self.visible {
+                changed = draw_panels(
+                    context,
+                    &mut panels,
+                    &mut self.import,
+                    &mut self.holdout_toggle,
+                    &mut self.backend,
+                    &mut self.step,
+                );
+                draw_timeline(context, &mut panels, &mut self.editor);
+            }
+        });
+        self.state
+            .handle_platform_output(window, output.platform_output);
+
+        let primitives = self
+            .context
+            .tessellate(output.shapes, output.pixels_per_point);
+        let size = window.inner_size();
+        let screen = egui_wgpu::ScreenDescriptor {
+            size_in_pixels: [size.width, size.height],
+            pixels_per_point: output.pixels_per_point,
+        };
+        for (id, delta) in &output.textures_delta.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        let commands = self
+            .renderer
+            .update_buffers(device, queue, encoder, &primitives, &screen);
+        queue.submit(commands);
+
+        {
+            let mut render_pass = encoder
+                .begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("UI Render Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    occlusion_query_set: None,
+                    timestamp_writes: None,
+                })
+                .forget_lifetime();
+            self.renderer.render(&mut render_pass, &primitives, &screen);
+        }
+        for id in &output.textures_delta.free {
+            self.renderer.free_texture(id);
+        }
+
+        changed
+    }
+}
+
+fn draw_stats(context: &egui::Context, stats: &FrameStats, scene: &SceneStats) {
+    egui::Area::new(egui::Id::new("Stats"))
+        .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-8.0, 8.0))
+        .interactable(false)
+        .show(context, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.monospace(format!("{:>7.1} fps", stats.fps));
+                ui.monospace(format!("{:>7} spp", stats.samples));
+                ui.monospace(format!("{:>7.1} Mrays/s", stats.rays_per_second / 1e6));
+                if let Some(memory) = stats.gpu_memory {
+                    ui.monospace(format!(
+                        "{:>7.0} MiB GPU",
+                        memory as f64 / (1024.0 * 1024.0)
+                    ));
+                }
+                ui.separator();
+                ui.monospace(format!("{:>7} triangles", scene.triangles));
+                ui.monospace(format!(
+                    "{:>7} instances of {} meshes",
+                    scene.instances, scene.unique_meshes
+                ));
+                ui.monospace(format!("{:>7} nodes", scene.nodes));
+                ui.monospace(format!("{:>7} materials", scene.unique_materials));
+                ui.monospace(format!(
+                    "{:>7.1} MiB textures",
+                    scene.texture_bytes as f64 / (1024.0 * 1024.0)
+                ));
+                ui.monospace(format!("{:>7} lights", scene.lights));
+                ui.monospace(format!("{:>7} BVH depth", scene.bvh_depth));
+            });
+        });
+}
+
+fn draw_panels(
+    context: &egui::Context,
+    panels: &mut Panels,
+    import: &mut ImportEditor,
+    holdout_toggle: &mut Option<usize>,
+    backend: &mut Option<Backend>,
+    step: &mut bool,
+) -> bool {
+    let Panels {
+        camera,
+        controller,
+        tracer,
+        trace_settings: trace,
+        settings,
+        selected,
+        autofocus,
+        turntable,
+        present_modes,
+        scene,
+        ..
+    } = panels;
+    let mut changed = false;
+
+    egui::Window::new("Settings")
+        .default_width(260.0)
+        .show(context, |ui| {
+            egui::CollapsingHeader::new("Camera")
+                .default_open(true)
+                .show(ui, |ui| {
+                    let mut mode = controller.mode;
+                    ui.horizontal(|ui| {
+                        ui.label("Mode");
+                        ui.selectable_value(&mut mode, CameraMode::Fly, "Fly");
+                        ui.selectable_value(&mut mode, CameraMode::Orbit, "Orbit");
+                    });
+                    if mode != controller.mode {
+                        controller.toggle_mode(camera);
+                    }
+
+                    egui::ComboBox::from_label("Projection")
+                        .selected_text(format!("{:?}", camera.projection))
+                        .show_ui(ui, |ui| {
+                            for projection in [
+                                CameraProjection::Perspective,
+                                CameraProjection::Orthographic,
+                                CameraProjection::Fisheye,
+                                CameraProjection::Equirectangular,
+                            ] {
+                                changed |= ui
+                                    .selectable_value(
+                                        &mut camera.projection,
+                                        projection,
+                                        format!("{projection:?}"),
+                                    )
+                                    .changed();
+                            }
+                        });
+
+                    ui.add_enabled_ui(camera.projection == CameraProjection::Perspective, |ui| {
+                        changed |= distortion_ui(ui, &mut camera.distortion);
+                    });
+
+                    let mut fov = camera.fov_y.to_degrees();
+                    if ui
+                        .add(egui::Slider::new(&mut fov, 5.0..=150.0).text("Vertical FOV"))
+                        .changed()
+                    {
+                        camera.fov_y = fov.to_radians();
+                        changed = true;
+                    }
+
+                    let mut depth_of_field = camera.f_stop.is_finite();
+                    if ui.checkbox(&mut depth_of_field, "Depth of field").changed() {
+                        camera.f_stop = if depth_of_field { 2.8 } else { f32::INFINITY };
+                        changed = true;
+                    }
+                    ui.add_enabled_ui(depth_of_field, |ui| {
+                        let mut f_stop = if depth_of_field { camera.f_stop } else { 2.8 };
+                        if ui
+                            .add(
+                                egui::Slider::new(&mut f_stop, 1.0..=32.0)
+                                    .logarithmic(true)
+                                    .text("f-stop"),
+                            )
+                            .changed()
+                        {
+                            camera.f_stop = f_stop;
+                            changed = true;
+                        }
+                    });
+                    changed |= ui
+                        .add(
+                            egui::Slider::new(&mut camera.focus_distance, 0.05..=1000.0)
+                                .logarithmic(true)
+                                .text("Focus distance"),
+                        )
+                        .on_hover_text("Right click the image to focus there")
+                        .changed();
+                    egui::ComboBox::from_label("Autofocus (G)")
+                        .selected_text(autofocus.name())
+                        .show_ui(ui, |ui| {
+                            for mode in AutofocusMode::ALL {
+                                ui.selectable_value(&mut **autofocus, mode, mode.name());
+                            }
+                        });
+                    ui.horizontal(|ui| {
+                        let mut on = turntable.is_on();
+                        if ui.checkbox(&mut on, "Turntable (O)").changed() {
+                            if on {
+                                turntable.start(scene, **selected);
+                            } else {
+                                turntable.stop();
+                            }
+                        }
+                        ui.add(
+                            egui::DragValue::new(&mut turntable.speed)
+                                .range(-360.0..=360.0)
+                                .suffix(" °/s"),
+                        );
+                    });
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut turntable.spin_selected, "Spin selection");
+                        ui.checkbox(&mut turntable.motion_blur, "Motion blur")
+                            .on_hover_text("Keeps accumulating while the camera turns");
+                    });
+
+                    let p = camera.position;
+                    ui.label(format!("Position {:.2} {:.2} {:.2}", p.x, p.y, p.z));
+
+                    let instance_name = |index: usize| {
+                        let mesh = &scene.meshes[scene.instances[index].mesh];
+                        format!("{index}: {}", mesh.name)
+                    };
+                    ui.horizontal(|ui| {
+                        egui::ComboBox::from_id_salt("selection")
+                            .selected_text(
+                                selected.map_or(String::from("Everything"), instance_name),
+                            )
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut **selected, None, "Everything");
+                                for index in 0..scene.instances.len() {
+                                    ui.selectable_value(
+                                        &mut **selected,
+                                        Some(index),
+                                        instance_name(index),
+                                    );
+                                }
+                            });
+                        if ui.button("Frame (F)").clicked() {
+                            let bounds = selected.map_or_else(
+                                || scene.bounds(),
+                                |index| scene.instance_bounds(index),
+                            );
+                            let size = tracer.output_texture().size();
+                            let aspect = size.width as f32 / size.height.max(1) as f32;
+                            controller.frame(camera, &bounds, aspect);
+                            changed = true;
+                        }
+                    });
+                    if let Some(index) = **selected {
+                        let mut holdout = scene.instances[index].holdout;
+                        if ui.checkbox(&mut holdout, "Holdout").changed() {
+                            *holdout_toggle = Some(index);
+                        }
+                    }
+                });
+
+            egui::CollapsingHeader::new("Sampling")
+                .default_open(true)
+                .show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("Samples per pixel: {}", tracer.sample_count()));
+                        let label = if settings.paused {
+                            "Resume (Space)"
+                        } else {
+                            "Pause (Space)"
+                        };
+                        if ui.button(label).clicked() {
+                            settings.paused = !settings.paused;
+                        }
+                        if ui
+                            .add_enabled(settings.paused, egui::Button::new("Step (N)"))
+                            .clicked()
+                        {
+                            *step = true;
+                        }
+                    });
+                    ui.add(
+                        egui::DragValue::new(&mut settings.max_samples)
+                            .prefix("Stop after ")
+                            .suffix(" samples (0 = never)"),
+                    );
+                    let mut selected = tracer.backend();
+                    egui::ComboBox::from_label("Backend")
+                        .selected_text(selected.name().to_uppercase())
+                        .show_ui(ui, |ui| {
+                            for option in Backend::ALL {
+                                ui.selectable_value(
+                                    &mut selected,
+                                    option,
+                                    option.name().to_uppercase(),
+                                );
+                            }
+                        });
+                    if selected != tracer.backend() {
+                        *backend = Some(selected);
+                    }
+                    changed |= ui
+                        .add(egui::Slider::new(&mut trace.max_depth, 1..=64).text("Max bounces"))
+                        .changed();
+                    changed |= ui
+                        .checkbox(&mut trace.spectral, "Spectral dispersion")
+                        .changed();
+                    changed |= ui
+                        .checkbox(&mut trace.light_sampling, "Light sampling")
+                        .changed();
+                    egui::ComboBox::from_label("Sampler")
+                        .selected_text(trace.sampler.name())
+                        .show_ui(ui, |ui| {
+                            for sampler in SamplerKind::ALL {
+                                changed |= ui
+                                    .selectable_value(&mut trace.sampler, sampler, sampler.name())
+                                    .changed();
+                            }
+                        });
+                    ui.horizontal(|ui| {
+                        ui.label("Preset");
+                        for preset in Preset::ALL {
+                            if ui.button(preset.name()).clicked() {
+                                preset.apply(settings, trace);
+                                changed = true;
+                            }
+                        }
+                    });
+                    ui.add(
+                        egui::Slider::new(&mut settings.resolution_scale, 0.25..=1.0)
+                            .text("Resolution scale"),
+                    );
+                    ui.checkbox(&mut settings.rescale_on_resize, "Stretch while resizing")
+                        .on_hover_text("Until the image is traced at the new size");
+                    ui.checkbox(&mut settings.accumulate, "Accumulate");
+                    ui.checkbox(&mut trace.reproject, "Reproject on camera moves");
+                    egui::ComboBox::from_label("Denoise")
+                        .selected_text(format!("{:?}", settings.denoise))
+                        .show_ui(ui, |ui| {
+                            for mode in [DenoiseMode::Off, DenoiseMode::Svgf] {
+                                ui.selectable_value(
+                                    &mut settings.denoise,
+                                    mode,
+                                    format!("{mode:?}"),
+                                );
+                            }
+                            #[cfg(feature = "oidn")]
+                            ui.selectable_value(&mut settings.denoise, DenoiseMode::Oidn, "Oidn");
+                        });
+                    egui::ComboBox::from_label("Show")
+                        .selected_text(settings.aov.name())
+                        .show_ui(ui, |ui| {
+                            for aov in Aov::ALL {
+                                ui.selectable_value(&mut settings.aov, aov, aov.name());
+                            }
+                        });
+                    changed |= ui.button("Restart").clicked();
+                });
+
+            egui::CollapsingHeader::new("Environment")
+                .default_open(true)
+                .show(ui, |ui| {
+                    changed |= ui
+                        .checkbox(&mut trace.transparent, "Transparent background")
+                        .changed();
+                    changed |= ui
+                        .add(
+                            egui::Slider::new(&mut trace.environment_intensity, 0.01..=100.0)
+                                .logarithmic(true)
+                                .text("Intensity"),
+                        )
+                        .changed();
+                    let mut rotation = trace.environment_rotation.to_degrees();
+                    if ui
+                        .add(egui::Slider::new(&mut rotation, -180.0..=180.0).text("Rotation"))
+                        .changed()
+                    {
+                        trace.environment_rotation = rotation.to_radians();
+                        changed = true;
+                    }
+
+                    if !tracer.has_environment() {
+                        let sky = &mut trace.sky;
+                        changed |= ui
+                            .add(
+                                egui::Slider::new(&mut sky.turbidity, 1.7..=10.0).text("Turbidity"),
+                            )
+                            .changed();
+                        let mut elevation = sky.sun_elevation.to_degrees();
+                        if ui
+                            .add(
+                                egui::Slider::new(&mut elevation, 0.0..=90.0).text("Sun elevation"),
+                            )
+                            .changed()
+                        {
+                            sky.sun_elevation = elevation.to_radians();
+                            changed = true;
+                        }
+                        let mut azimuth = sky.sun_azimuth.to_degrees();
+                        if ui
+                            .add(
+                                egui::Slider::new(&mut azimuth, -180.0..=180.0).text("Sun azimuth"),
+                            )
+                            .changed()
+                        {
+                            sky.sun_azimuth = azimuth.to_radians();
+                            changed = true;
+                        }
+                        changed |= ui
+                            .add(
+                                egui::Slider::new(&mut sky.ground_albedo, 0.0..=1.0)
+                                    .text("Ground albedo"),
+                            )
+                            .changed();
+                    }
+                });
+
+            egui::CollapsingHeader::new("Display")
+                .default_open(true)
+                .show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::Slider::new(&mut settings.exposure, -MAX_EXPOSURE..=MAX_EXPOSURE)
+                                .text("Exposure (EV)"),
+                        );
+                        if ui.button("-1/3 ([)").clicked() {
+                            settings.step_exposure(-1.0);
+                        }
+                        if ui.button("+1/3 (])").clicked() {
+                            settings.step_exposure(1.0);
+                        }
+                    });
+                    ui.checkbox(&mut settings.auto_exposure, "Auto-exposure (X)");
+                    ui.checkbox(&mut settings.focus_peaking, "Focus peaking (K)");
+                    egui::ComboBox::from_label("Tone mapping (T)")
+                        .selected_text(settings.tonemap.name())
+                        .show_ui(ui, |ui| {
+                            for tonemap in Tonemap::ALL {
+                                ui.selectable_value(&mut settings.tonemap, tonemap, tonemap.name());
+                            }
+                        });
+                    egui::ComboBox::from_label("Present mode (V)")
+                        .selected_text(settings.present_mode.name())
+                        .show_ui(ui, |ui| {
+                            for mode in PresentMode::ALL {
+                                if mode.supported(present_modes) {
+                                    ui.selectable_value(
+                                        &mut settings.present_mode,
+                                        mode,
+                                        mode.name(),
+                                    );
+                                }
+                            }
+                        })
+                        .response
+                        .on_hover_text("Fifo waits for vsync, immediate tears");
+                });
+
+            egui::CollapsingHeader::new("Import")
+                .default_open(false)
+                .show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(&mut import.path);
+                        let path = import.path.trim();
+                        if ui
+                            .add_enabled(!path.is_empty(), egui::Button::new("Add to scene"))
+                            .clicked()
+                        {
+                            import.requested = Some(PathBuf::from(path));
+                        }
+                    });
+                });
+        });
+
+    changed
+}
+
+/// Lens distortion controls, returning whether anything changed.
+fn distortion_ui(ui: &mut egui::Ui, distortion: &mut LensDistortion) -> bool {
+    let mut changed = false;
+    egui::ComboBox::from_label("Lens distortion")
+        .selected_text(format!("{:?}", distortion.mode))
+        .show_ui(ui, |ui| {
+            for mode in [
+                DistortionMode::Off,
+                DistortionMode::Apply,
+                DistortionMode::Remove,
+            ] {
+                changed |= ui
+                    .selectable_value(&mut distortion.mode, mode, format!("{mode:?}"))
+                    .changed();
+            }
+        });
+    if distortion.mode != DistortionMode::Off {
+        ui.horizontal(|ui| {
+            for (label, coefficient) in [
+                ("k1", &mut distortion.k1),
+                ("k2", &mut distortion.k2),
+                ("k3", &mut distortion.k3),
+                ("p1", &mut distortion.p1),
+                ("p2", &mut distortion.p2),
+            ] {
+                changed |= ui
+                    .add(
+                        egui::DragValue::new(coefficient)
+                            .speed(0.001)
+                            .prefix(format!("{label} ")),
+                    )
+                    .changed();
+            }
+        });
+    }
+    changed
+}
+
+/// Keys the parameters at the playhead with their current values, and
+/// scrubs or plays the result.
+fn draw_timeline(context: &egui::Context, panels: &mut Panels, editor: &mut TimelineEditor) {
+    egui::Window::new("Timeline")
+        .default_width(420.0)
+        .default_open(false)
+        .show(context, |ui| {
+            let timeline = &mut *panels.timeline;
+            ui.horizontal(|ui| {
+                let label = if timeline.playing {
+                    "Pause (Enter)"
+                } else {
+                    "Play (Enter)"
+                };
+                if ui.button(label).clicked() {
+                    timeline.playing = !timeline.playing;
+                }
+                ui.checkbox(&mut timeline.looping, "Loop");
+                ui.add(
+                    egui::DragValue::new(&mut timeline.duration)
+                        .range(0.1..=3600.0)
+                        .speed(0.1)
+                        .prefix("Duration ")
+                        .suffix(" s"),
+                );
+            });
+            let duration = timeline.duration;
+            ui.add(egui::Slider::new(&mut timeline.time, 0.0..=duration).text("Time"));
+
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_salt("timeline target")
+                    .selected_text(editor.target.address())
+                    .show_ui(ui, |ui| {
+                        for target in keyable_targets(panels.scene) {
+                            ui.selectable_value(&mut editor.target, target, target.address());
+                        }
+                    });
+                egui::ComboBox::from_id_salt("timeline easing")
+                    .selected_text(editor.easing.name())
+                    .show_ui(ui, |ui| {
+                        for easing in Easing::ALL {
+                            ui.selectable_value(&mut editor.easing, easing, easing.name());
+                        }
+                    });
+            });
+            ui.horizontal(|ui| {
+                let mut keyed = Vec::new();
+                if ui.button("Key").clicked() {
+                    keyed.push(editor.target);
+                }
+                if ui.button("Key camera").clicked() {
+                    keyed.extend(CAMERA_TARGETS);
+                }
+                for target in keyed {
+                    if let Some(value) = current_value(panels, target) {
+                        let timeline = &mut *panels.timeline;
+                        timeline.insert(target, timeline.time, value, editor.easing);
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                let label = if panels.camera_path.is_recording() {
+                    "Stop recording (R)"
+                } else {
+                    "Record camera (R)"
+                };
+                if ui
+                    .button(label)
+                    .on_hover_text("Replaces the camera tracks with the path flown")
+                    .clicked()
+                {
+                    editor.record_toggle = true;
+                }
+                ui.add(
+                    egui::DragValue::new(&mut panels.camera_path.smoothing)
+                        .range(0.0..=5.0)
+                        .speed(0.05)
+                        .prefix("Smoothing ")
+                        .suffix(" s"),
+                );
+            });
+
+            let timeline = &mut *panels.timeline;
+            let mut removed = None;
+            egui::ScrollArea::vertical()
+                .max_height(200.0)
+                .show(ui, |ui| {
+                    for (t, track) in timeline.tracks.iter().enumerate() {
+                        ui.horizontal_wrapped(|ui| {
+                            ui.label(track.target.address());
+                            for (k, key) in track.keys.iter().enumerate() {
+                                let button = ui
+                                    .small_button(format!("{:.2}s", key.time))
+                                    .on_hover_text(format!(
+                                        "{:.3}, {}. Right click removes the key.",
+                                        key.value,
+                                        key.easing.name()
+                                    ));
+                                if button.clicked() {
+                                    timeline.time = key.time;
+                                }
+                                if button.secondary_clicked() {
+                                    removed = Some((t, k));
+                                }
+                            }
+                        });
+                    }
+                });
+            if let Some((track, key)) = removed {
+                timeline.remove(track, key);
+            }
+
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut editor.path);
+                let path = std::path::Path::new(&editor.path);
+                if ui.button("Save").clicked() {
+                    if let Err(err) = timeline.save(path) {
+                        tracing::error!("{err:#}");
+                    }
+                }
+                if ui.button("Load").clicked() {
+                    match Timeline::load(path) {
+                        Ok(loaded) => *timeline = loaded,
+                        Err(err) => tracing::error!("{err:#}"),
+                    }
+                }
+            });
+        });
+}
+
+fn keyable_targets(scene: &Scene) -> Vec<ControlTarget> {
+    let mut targets = CAMERA_TARGETS.to_vec();
+    targets.extend([
+        ControlTarget::Exposure,
+        ControlTarget::EnvironmentIntensity,
+        ControlTarget::EnvironmentRotation,
+        ControlTarget::SunElevation,
+        ControlTarget::SunAzimuth,
+        ControlTarget::Turbidity,
+    ]);
+    targets.extend((0..scene.lights.len()).map(ControlTarget::LightIntensity));
+    targets.extend((0..scene.materials.len()).map(ControlTarget::MaterialEmission));
+    targets
+}
+
+/// Value of a parameter in the units of its target.
+fn current_value(panels: &Panels, target: ControlTarget) -> Option<f32> {
+    let camera = &panels.camera;
+    let trace = &panels.trace_settings;
+    Some(match target {
+        ControlTarget::Exposure => panels.settings.exposure,
+        ControlTarget::EnvironmentIntensity => trace.environment_intensity,
+        ControlTarget::EnvironmentRotation => trace.environment_rotation.to_degrees(),
+        ControlTarget::SunElevation => trace.sky.sun_elevation.to_degrees(),
+        ControlTarget::SunAzimuth => trace.sky.sun_azimuth.to_degrees(),
+        ControlTarget::Turbidity => trace.sky.turbidity,
+        ControlTarget::LightIntensity(index) => panels.scene.lights.get(index)?.intensity,
+        ControlTarget::MaterialEmission(index) => {
+            panels.scene.materials.get(index)?.emission.max_element()
+        }
+        ControlTarget::CameraPosition(axis) => camera.position[axis],
+        ControlTarget::CameraYaw => camera.yaw.to_degrees(),
+        ControlTarget::CameraPitch => camera.pitch.to_degrees(),
+        ControlTarget::CameraFov => camera.fov_y.to_degrees(),
+        ControlTarget::CameraFocusDistance => camera.focus_distance,
+    })
+}
@@ -0,0 +1,131 @@
+//! Watchdog for frames the GPU takes too long over, as on drivers that
+//! hang or reset the device on long dispatches. A stalled frame is logged
+//! with what was being traced, and tracing restarts at lighter settings,
+//! first in smaller bands of rows and then at a lower resolution.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use web_time::{Duration, Instant};
+use winit::dpi::PhysicalSize;
+
+use crate::{tracer::TraceSettings, Settings};
+
+/// Windows resets a driver that doesn't answer for two seconds.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+/// Fewest camera rays per submission a recovery goes down to, before it
+/// lowers the resolution instead.
+const MIN_RAYS_PER_SUBMIT: u32 = 1 << 16;
+const MIN_RESOLUTION_SCALE: f32 = 0.25;
+
+pub struct Watchdog {
+    /// How long the GPU may take over a frame, zero never times out.
+    pub timeout: Duration,
+    adapter: wgpu::AdapterInfo,
+    /// Frames submitted so far.
+    submitted: u64,
+    /// Number and submission time of the frames the GPU hasn't finished.
+    pending: Arc<Mutex<VecDeque<(u64, Instant)>>>,
+}
+
+impl Watchdog {
+    pub fn new(adapter: wgpu::AdapterInfo, timeout: Duration) -> Self {
+        Self {
+            timeout,
+            adapter,
+            submitted: 0,
+            pending: Arc::default(),
+        }
+    }
+
+    /// Watches the work just submitted to `queue`.
+    pub fn submitted(&mut self, queue: &wgpu::Queue) {
+        if self.timeout.is_zero() {
+            return;
+        }
+        self.submitted += 1;
+        let frame = self.submitted;
+        self.pending
+            .lock()
+            .unwrap()
+            .push_back((frame, Instant::now()));
+        let pending = self.pending.clone();
+        queue.on_submitted_work_done(move || {
+            // Work finishes in order, so earlier frames are done too
+            pending
+                .lock()
+                .unwrap()
+                .retain(|&(pending, _)| pending > frame);
+        });
+    }
+
+    /// How long the oldest unfinished frame has been on the GPU, if past
+    /// the timeout. The stalled frames are forgotten, so a stall is only
+    /// reported once.
+    pub fn stalled(&self, device: &wgpu::Device) -> Option<Duration> {
+        if self.timeout.is_zero() {
+            return None;
+        }
+        device.poll(wgpu::Maintain::Poll);
+        let mut pending = self.pending.lock().unwrap();
+        let elapsed = pending.front()?.1.elapsed();
+        (elapsed > self.timeout).then(|| {
+            pending.clear();
+            elapsed
+        })
+    }
+
+    /// Logs a stall of `elapsed` along with what was being traced.
+    pub fn report(
+        &self,
+        elapsed: Duration,
+        trace: &TraceSettings,
+        size: PhysicalSize<u32>,
+        samples: u32,
+    ) {
+        let adapter = &self.adapter;
+        tracing::warn!(
+            "A frame stalled for {:.1} s on {} ({:?}, {} {}): {}x{} at {samples} samples, \
+             depth {}, {} rays per submit",
+            elapsed.as_secs_f32(),
+            adapter.name,
+            adapter.backend,
+            adapter.driver,
+            adapter.driver_info,
+            size.width,
+            size.height,
+            trace.max_depth,
+            trace.rays_per_submit,
+        );
+    }
+}
+
+/// Lightens the load of a frame traced at `size` after a stall, returning
+/// false if there's nothing left to lower.
+pub fn lighten(
+    settings: &mut Settings,
+    trace: &mut TraceSettings,
+    size: PhysicalSize<u32>,
+) -> bool {
+    let rays = match trace.rays_per_submit {
+        0 => size.width * size.height,
+        rays => rays,
+    };
+    if rays / 2 >= MIN_RAYS_PER_SUBMIT {
+        trace.rays_per_submit = rays / 2;
+        tracing::warn!("Tracing at most {} rays per submit", trace.rays_per_submit);
+        true
+    } else if settings.resolution_scale > MIN_RESOLUTION_SCALE {
+        settings.resolution_scale = (0.5 * settings.resolution_scale).max(MIN_RESOLUTION_SCALE);
+        tracing::warn!(
+            "Tracing at {:.0}% resolution",
+            100.0 * settings.resolution_scale
+        );
+        true
+    } else {
+        tracing::warn!("Nothing left to lower, the GPU may be unable to trace this scene");
+        false
+    }
+}
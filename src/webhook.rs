@@ -0,0 +1,83 @@
+//! POSTs to `http://` URLs with a bare HTTP/1.1 request, which is all a
+//! webhook needs and saves pulling in an HTTP client.
+
+use std::{
+    fmt::Write as _,
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends `body` to `url` with the extra `headers`, failing unless the reply
+/// is a 2xx.
+pub fn post(url: &str, content_type: &str, headers: &[(&str, String)], body: &[u8]) -> Result<()> {
+    send(url, content_type, headers, body).with_context(|| format!("Failed to POST to {url}"))
+}
+
+fn send(url: &str, content_type: &str, headers: &[(&str, String)], body: &[u8]) -> Result<()> {
+    let rest = url
+        .strip_prefix("http://")
+        .context("Webhooks need an http:// URL")?;
+    let (host, path) = rest.find('/').map_or((rest, "/"), |i| rest.split_at(i));
+    let address = if host.contains(':') {
+        host.to_owned()
+    } else {
+        format!("{host}:80")
+    };
+    let address = address
+        .to_socket_addrs()?
+        .next()
+        .with_context(|| format!("Couldn't resolve {host}"))?;
+    let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut request = format!(
+        "POST {path} HTTP/1.1\r\n\
+         Host: {host}\r\n\
+         Content-Type: {content_type}\r\n\
+         Content-Length: {}\r\n",
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{name}: {value}\r\n"));
+    }
+    request.push_str("Connection: close\r\n\r\n");
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+
+    let mut response = [0; 64];
+    let read = stream.read(&mut response)?;
+    let status = String::from_utf8_lossy(&response[..read]);
+    let status = status.lines().next().unwrap_or_default();
+    if !status
+        .split_whitespace()
+        .nth(1)
+        .is_some_and(|code| code.starts_with('2'))
+    {
+        bail!("Unexpected response {status:?}");
+    }
+    Ok(())
+}
+
+/// Escapes `text` for inside the double quotes of a JSON string, which
+/// also does for AppleScript.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,86 @@
+// Gathers the renderer's guides into one layer per AOV, see aov.rs. The
+// guides' extra channels are left out and alpha is always one.
+
+#include "camera.wgsl"
+
+struct Params {
+    camera: Camera,
+    prev_camera: Camera,
+}
+
+@group(0) @binding(0)
+var albedo_texture: texture_2d<f32>;
+// Shading normal, and the distance to the hit in w
+@group(0) @binding(1)
+var normal_texture: texture_2d<f32>;
+// Instance plus one in red and its class in green
+@group(0) @binding(2)
+var object_texture: texture_2d<f32>;
+// Albedo, normal, depth, object ID, class ID and motion, in the order of
+// `Aov`
+@group(0) @binding(3)
+var aovs: texture_storage_2d_array<rgba32float, write>;
+@group(0) @binding(4)
+var<uniform> params: Params;
+
+fn camera_dir(camera: Camera, pixel: vec2<f32>, size: vec2<f32>) -> vec3<f32> {
+    let ndc = pixel / size * 2.0 - 1.0;
+    let aspect = size.x / size.y;
+    return normalize(
+        camera.forward
+            + ndc.x * aspect * camera.tan_half_fov * camera.right
+            - ndc.y * camera.tan_half_fov * camera.up,
+    );
+}
+
+// Pixels of a pinhole camera, which a distorted lens isn't
+fn is_pinhole(camera: Camera) -> bool {
+    return camera.projection == PROJECTION_PERSPECTIVE && camera.distortion != DISTORTION_APPLY;
+}
+
+// Pixel looking along `dir` from the camera, none when behind it
+fn project(camera: Camera, dir: vec3<f32>, size: vec2<f32>) -> vec3<f32> {
+    let z = dot(dir, camera.forward);
+    if z <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+    let aspect = size.x / size.y;
+    let ndc = vec2<f32>(
+        dot(dir, camera.right) / (z * camera.tan_half_fov * aspect),
+        -dot(dir, camera.up) / (z * camera.tan_half_fov),
+    );
+    return vec3<f32>((ndc + 1.0) * 0.5 * size, 1.0);
+}
+
+// Where the previous camera saw the point this pixel sees, relative to the
+// pixel. The background is infinitely far away, so only directions matter.
+fn motion(pixel: vec2<f32>, size: vec2<f32>, depth: f32) -> vec2<f32> {
+    if !is_pinhole(params.camera) || !is_pinhole(params.prev_camera) {
+        return vec2<f32>(0.0);
+    }
+    let dir = camera_dir(params.camera, pixel, size);
+    var prev_dir = dir;
+    if depth > 0.0 {
+        prev_dir = params.camera.position + depth * dir - params.prev_camera.position;
+    }
+    let prev = project(params.prev_camera, prev_dir, size);
+    return select(vec2<f32>(0.0), pixel - prev.xy, prev.z > 0.0);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(aovs);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    let albedo = textureLoad(albedo_texture, id.xy, 0);
+    let normal = textureLoad(normal_texture, id.xy, 0);
+    let object = textureLoad(object_texture, id.xy, 0);
+    textureStore(aovs, id.xy, 0, vec4<f32>(albedo.rgb, 1.0));
+    textureStore(aovs, id.xy, 1, vec4<f32>(normal.xyz, 1.0));
+    textureStore(aovs, id.xy, 2, vec4<f32>(vec3<f32>(normal.w), 1.0));
+    textureStore(aovs, id.xy, 3, vec4<f32>(vec3<f32>(object.r), 1.0));
+    textureStore(aovs, id.xy, 4, vec4<f32>(vec3<f32>(object.g), 1.0));
+    let moved = motion(vec2<f32>(id.xy) + 0.5, vec2<f32>(size), normal.w);
+    textureStore(aovs, id.xy, 5, vec4<f32>(moved, 0.0, 1.0));
+}
@@ -0,0 +1,323 @@
+// Scattering of the layered material model, in a local shading frame with
+// z along the normal. Material, the sheen and Fresnel tables and the
+// helpers for sampling come from trace.wgsl.
+
+// Anisotropic GGX with the roughness along the tangent in alpha.x and
+// along the bitangent in alpha.y
+fn ggx_d(h: vec3<f32>, alpha: vec2<f32>) -> f32 {
+    let s = h.xy / alpha;
+    let d = dot(s, s) + h.z * h.z;
+    return 1.0 / (PI * alpha.x * alpha.y * d * d);
+}
+
+fn ggx_lambda(w: vec3<f32>, alpha: vec2<f32>) -> f32 {
+    let s = w.xy * alpha;
+    let tan2 = dot(s, s) / max(w.z * w.z, 1e-7);
+    return 0.5 * (sqrt(1.0 + tan2) - 1.0);
+}
+
+fn ggx_g1(w: vec3<f32>, alpha: vec2<f32>) -> f32 {
+    return 1.0 / (1.0 + ggx_lambda(w, alpha));
+}
+
+fn ggx_g2(wo: vec3<f32>, wi: vec3<f32>, alpha: vec2<f32>) -> f32 {
+    return 1.0 / (1.0 + ggx_lambda(wo, alpha) + ggx_lambda(wi, alpha));
+}
+
+// Stretches the roughness along the tangent as in KHR_materials_anisotropy
+fn ggx_alpha(material: Material) -> vec2<f32> {
+    let alpha = max(material.roughness * material.roughness, 1e-3);
+    let k = material.anisotropy * material.anisotropy;
+    return vec2<f32>(mix(alpha, 1.0, k), alpha);
+}
+
+// Visible normal sampling, Heitz 2018
+fn sample_ggx_vndf(wo: vec3<f32>, alpha: vec2<f32>, u: vec2<f32>) -> vec3<f32> {
+    let vh = normalize(vec3<f32>(alpha * wo.xy, wo.z));
+    let len2 = vh.x * vh.x + vh.y * vh.y;
+    var t1 = vec3<f32>(1.0, 0.0, 0.0);
+    if len2 > 0.0 {
+        t1 = vec3<f32>(-vh.y, vh.x, 0.0) / sqrt(len2);
+    }
+    let t2 = cross(vh, t1);
+    let r = sqrt(u.x);
+    let phi = 2.0 * PI * u.y;
+    let p1 = r * cos(phi);
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * sqrt(1.0 - p1 * p1) + s * r * sin(phi);
+    let nh = p1 * t1 + p2 * t2 + sqrt(max(0.0, 1.0 - p1 * p1 - p2 * p2)) * vh;
+    return normalize(vec3<f32>(alpha * nh.xy, max(1e-6, nh.z)));
+}
+
+struct BsdfSample {
+    wi: vec3<f32>,
+    weight: vec3<f32>,
+}
+
+struct Lobes {
+    diffuse: vec3<f32>,
+    f0: vec3<f32>,
+    fresnel_table: u32,
+    alpha: vec2<f32>,
+    p_specular: f32,
+    // The diffuse lobe enters the medium below instead of reflecting
+    subsurface: bool,
+}
+
+fn fresnel_lookup(table: u32, cos_theta: f32) -> vec3<f32> {
+    let x = clamp(cos_theta, 0.0, 1.0) * f32(FRESNEL_TABLE_SIZE - 1u);
+    let i = min(u32(x), FRESNEL_TABLE_SIZE - 2u);
+    let base = (table - 1u) * FRESNEL_TABLE_SIZE + i;
+    return mix(fresnel_tables[base].rgb, fresnel_tables[base + 1u].rgb, x - f32(i));
+}
+
+// Measured reflectance where there is a table, Schlick otherwise
+fn lobe_fresnel(lobes: Lobes, cos_theta: f32) -> vec3<f32> {
+    if lobes.fresnel_table != 0u {
+        return fresnel_lookup(lobes.fresnel_table, cos_theta);
+    }
+    return fresnel_schlick(lobes.f0, cos_theta);
+}
+
+fn material_lobes(material: Material, cos_o: f32) -> Lobes {
+    let base = material.base_color.rgb;
+    let diffuse = base * (1.0 - material.metallic);
+    let f0 = mix(vec3<f32>(0.04), base, material.metallic);
+    let alpha = ggx_alpha(material);
+    let subsurface = (material.flags & MATERIAL_SUBSURFACE) != 0u;
+    var lobes = Lobes(diffuse, f0, material.fresnel_table, alpha, 0.0, subsurface);
+    let spec_weight = luminance(lobe_fresnel(lobes, cos_o));
+    let diff_weight = luminance(diffuse);
+    lobes.p_specular = clamp(spec_weight / max(spec_weight + diff_weight, 1e-6), 0.05, 1.0);
+    return lobes;
+}
+
+// Local shading frame, z is the normal. Returns (f * cos, pdf)
+fn eval_bsdf(lobes: Lobes, wo: vec3<f32>, wi: vec3<f32>) -> vec4<f32> {
+    if wi.z <= 0.0 || wo.z <= 0.0 {
+        return vec4<f32>(0.0);
+    }
+    let h = normalize(wo + wi);
+    let d = ggx_d(h, lobes.alpha);
+    let f = lobe_fresnel(lobes, dot(wo, h));
+    let specular = f * d * ggx_g2(wo, wi, lobes.alpha) / (4.0 * wo.z);
+    let pdf_specular = ggx_g1(wo, lobes.alpha) * d / (4.0 * wo.z);
+    if lobes.subsurface {
+        return vec4<f32>(specular, lobes.p_specular * pdf_specular);
+    }
+    let diffuse = lobes.diffuse * INV_PI * wi.z;
+    let pdf_diffuse = wi.z * INV_PI;
+    let pdf = mix(pdf_diffuse, pdf_specular, lobes.p_specular);
+    return vec4<f32>(specular + diffuse, pdf);
+}
+
+// Smooth or rough glass. Thin walled sheets sum the reflections between
+// both interfaces and transmit without bending, solid glass refracts.
+fn sample_dielectric(material: Material, wo: vec3<f32>, entering: bool) -> BsdfSample {
+    let alpha = ggx_alpha(material);
+    let h = sample_ggx_vndf(wo, alpha, rand2());
+    let cos_o = dot(wo, h);
+    let thin = (material.flags & MATERIAL_THIN_WALLED) != 0u;
+    let eta = select(material.ior, 1.0 / material.ior, entering || thin);
+
+    var reflectance = fresnel_dielectric(cos_o, eta);
+    if thin && reflectance < 1.0 {
+        let t = 1.0 - reflectance;
+        reflectance += t * t * reflectance / (1.0 - reflectance * reflectance);
+    }
+    // Thin films reflect each color differently, seen from outside
+    var fresnel = vec3<f32>(reflectance);
+    if material.fresnel_table != 0u && (entering || thin) {
+        fresnel = fresnel_lookup(material.fresnel_table, cos_o);
+        reflectance = (fresnel.r + fresnel.g + fresnel.b) / 3.0;
+    }
+
+    var wi = reflect(-wo, h);
+    var tint = fresnel / reflectance;
+    let transmit = rand() >= reflectance;
+    if transmit {
+        tint = material.base_color.rgb * (1.0 - fresnel) / (1.0 - reflectance);
+        if thin {
+            wi.z = -wi.z;
+        } else {
+            wi = refract(-wo, h, eta);
+        }
+    }
+    // Microfacet directions can end up on the wrong side of the surface
+    if (wi.z < 0.0) != transmit || wi.z == 0.0 {
+        return BsdfSample(wi, vec3<f32>(0.0));
+    }
+    // VNDF sampling with Fresnel chosen lobes leaves only the masking ratio
+    let masking = ggx_g2(wo, wi, alpha) / ggx_g1(wo, alpha);
+    return BsdfSample(wi, tint * masking);
+}
+
+fn clearcoat_alpha(material: Material) -> vec2<f32> {
+    return vec2<f32>(max(material.clearcoat_roughness * material.clearcoat_roughness, 1e-3));
+}
+
+// Charlie sheen distribution, Estevez and Kulla 2017
+fn charlie_d(h: vec3<f32>, alpha: f32) -> f32 {
+    let sin2 = max(0.0, 1.0 - h.z * h.z);
+    return (2.0 + 1.0 / alpha) * pow(sin2, 0.5 / alpha) / (2.0 * PI);
+}
+
+fn sheen_alpha(material: Material) -> f32 {
+    return max(material.sheen_roughness * material.sheen_roughness, MIN_SHEEN_ALPHA);
+}
+
+// Bilinear lookup of the precomputed directional albedo, see tracer.rs
+fn sheen_albedo(cos_o: f32, roughness: f32) -> f32 {
+    let last = f32(SHEEN_TABLE_SIZE - 1u);
+    let p = clamp(vec2<f32>(cos_o, roughness), vec2<f32>(0.0), vec2<f32>(1.0)) * last;
+    let i = min(vec2<u32>(p), vec2<u32>(SHEEN_TABLE_SIZE - 2u));
+    let f = p - vec2<f32>(i);
+    let a = mix(
+        textureLoad(sheen_table, i, 0).r,
+        textureLoad(sheen_table, i + vec2<u32>(1u, 0u), 0).r,
+        f.x,
+    );
+    let b = mix(
+        textureLoad(sheen_table, i + vec2<u32>(0u, 1u), 0).r,
+        textureLoad(sheen_table, i + vec2<u32>(1u, 1u), 0).r,
+        f.x,
+    );
+    return mix(a, b, f.y);
+}
+
+// Shading state at a hit, shared by sampling and evaluating the layers
+struct Surface {
+    // Base layer frame, z is the shading normal
+    frame: mat3x3<f32>,
+    // The coat is shaded in its own frame, independent of the base normal
+    coat_frame: mat3x3<f32>,
+    // Geometric normal on the side light arrives from
+    ng: vec3<f32>,
+    entering: bool,
+    p_coat: f32,
+    p_sheen: f32,
+}
+
+// n is the normal of the base layer and coat_n of the coat above it
+fn make_surface(
+    material: Material,
+    tangent: vec4<f32>,
+    n: vec3<f32>,
+    coat_n: vec3<f32>,
+    ng: vec3<f32>,
+    wo: vec3<f32>,
+    entering: bool,
+) -> Surface {
+    let frame = tangent_frame(n, tangent, material.anisotropy_rotation);
+    // The coat is picked with the probability of its Fresnel at the macro
+    // normal and the sheen with its albedo, the layers below get the rest
+    let p_coat = material.clearcoat * fresnel_schlick(vec3<f32>(0.04), dot(wo, coat_n)).x;
+    let sheen_max = max(material.sheen_color.r, max(material.sheen_color.g, material.sheen_color.b));
+    let p_sheen = sheen_max * sheen_albedo(dot(wo, n), material.sheen_roughness);
+    return Surface(frame, basis(coat_n), ng, entering, p_coat, p_sheen);
+}
+
+// Metallic flakes of car paint. Space is split into cells of flake_size,
+// each holding a flake with probability flake_coverage. A flake turns the
+// base into a tilted, nearly smooth metal. The cell hash decides both, so
+// the sparkle stays in place while samples accumulate. Returns the flake
+// normal, or zero where there's no flake.
+fn flake_normal(material: Material, position: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
+    let cell = bitcast<vec3<u32>>(vec3<i32>(floor(position / material.flake_size)));
+    let h0 = pcg_hash(cell.x ^ pcg_hash(cell.y ^ pcg_hash(cell.z)));
+    let h1 = pcg_hash(h0);
+    let h2 = pcg_hash(h1);
+    let u = vec3<f32>(vec3<u32>(h0, h1, h2) >> vec3<u32>(8u)) / 16777216.0;
+    if u.x >= material.flake_coverage {
+        return vec3<f32>(0.0);
+    }
+    let tilt = material.flake_roughness * sample_disk(u.yz);
+    return normalize(basis(n) * vec3<f32>(tilt, 1.0));
+}
+
+// Clearcoat, sheen and opaque base, each scaled by the share of light that
+// gets through the layers above. Returns (f * cos, pdf) with the pdf of
+// sample_material picking one of these lobes and then wi. Transmission is
+// left out since only sample_material produces it.
+fn eval_material(material: Material, surface: Surface, wo: vec3<f32>, wi: vec3<f32>) -> vec4<f32> {
+    if dot(wi, surface.ng) <= 0.0 {
+        return vec4<f32>(0.0);
+    }
+    var result = vec4<f32>(0.0);
+
+    // Smooth dielectric coat with IOR 1.5
+    let coat_wo = wo * surface.coat_frame;
+    let coat_wi = wi * surface.coat_frame;
+    if surface.p_coat > 0.0 && coat_wo.z > 0.0 && coat_wi.z > 0.0 {
+        let alpha = clearcoat_alpha(material);
+        let h = normalize(coat_wo + coat_wi);
+        let d = ggx_d(h, alpha);
+        let fresnel = material.clearcoat * fresnel_schlick(vec3<f32>(0.04), dot(coat_wo, h)).x;
+        let f = fresnel * d * ggx_g2(coat_wo, coat_wi, alpha) / (4.0 * coat_wo.z);
+        let pdf = ggx_g1(coat_wo, alpha) * d / (4.0 * coat_wo.z);
+        result += vec4<f32>(vec3<f32>(f), surface.p_coat * pdf);
+    }
+
+    let local_wo = wo * surface.frame;
+    let local_wi = wi * surface.frame;
+    if local_wo.z <= 0.0 || local_wi.z <= 0.0 {
+        return result;
+    }
+    let below_coat = 1.0 - surface.p_coat;
+
+    // Cosine sampled sheen with Ashikhmin visibility
+    if surface.p_sheen > 0.0 {
+        let h = normalize(local_wo + local_wi);
+        let d = charlie_d(h, sheen_alpha(material));
+        let v = 1.0 / (4.0 * (local_wo.z + local_wi.z - local_wo.z * local_wi.z));
+        let f = material.sheen_color * d * v * local_wi.z;
+        result += below_coat * vec4<f32>(f, surface.p_sheen * local_wi.z * INV_PI);
+    }
+
+    let opaque = below_coat * (1.0 - surface.p_sheen) * (1.0 - material.transmission);
+    if opaque > 0.0 {
+        result += opaque * eval_bsdf(material_lobes(material, local_wo.z), local_wo, local_wi);
+    }
+    return result;
+}
+
+struct MaterialSample {
+    wi: vec3<f32>,
+    weight: vec3<f32>,
+    // Density for weighting against light sampling, zero for transmission
+    pdf: f32,
+}
+
+// Picks a lobe, then samples it. Except for transmission and subsurface
+// scattering the weight uses every lobe at wi so it matches eval_material.
+fn sample_material(material: Material, surface: Surface, wo: vec3<f32>) -> MaterialSample {
+    let local_wo = wo * surface.frame;
+    var wi: vec3<f32>;
+    if rand() < surface.p_coat {
+        let coat_wo = wo * surface.coat_frame;
+        let h = sample_ggx_vndf(coat_wo, clearcoat_alpha(material), rand2());
+        wi = surface.coat_frame * reflect(-coat_wo, h);
+    } else if rand() < surface.p_sheen {
+        wi = surface.frame * sample_cosine_hemisphere(rand2());
+    } else if rand() < material.transmission {
+        let bsdf = sample_dielectric(material, local_wo, surface.entering);
+        return MaterialSample(surface.frame * bsdf.wi, bsdf.weight, 0.0);
+    } else {
+        let lobes = material_lobes(material, local_wo.z);
+        if rand() < lobes.p_specular {
+            let h = sample_ggx_vndf(local_wo, lobes.alpha, rand2());
+            wi = surface.frame * reflect(-local_wo, h);
+        } else if lobes.subsurface {
+            // Into the surface, for subsurface_walk to carry on from
+            let local_wi = sample_cosine_hemisphere(rand2()) * vec3<f32>(1.0, 1.0, -1.0);
+            return MaterialSample(surface.frame * local_wi, lobes.diffuse / (1.0 - lobes.p_specular), 0.0);
+        } else {
+            wi = surface.frame * sample_cosine_hemisphere(rand2());
+        }
+    }
+    let eval = eval_material(material, surface, wo, wi);
+    if eval.w <= 0.0 {
+        return MaterialSample(wi, vec3<f32>(0.0), 0.0);
+    }
+    return MaterialSample(wi, eval.rgb / eval.w, eval.w);
+}
@@ -0,0 +1,24 @@
+// Camera of every frame, see camera.rs
+
+const PROJECTION_PERSPECTIVE: u32 = 0u;
+const PROJECTION_ORTHOGRAPHIC: u32 = 1u;
+const PROJECTION_FISHEYE: u32 = 2u;
+const PROJECTION_EQUIRECTANGULAR: u32 = 3u;
+
+const DISTORTION_APPLY: u32 = 1u;
+const DISTORTION_REMOVE: u32 = 2u;
+
+struct Camera {
+    position: vec3<f32>,
+    tan_half_fov: f32,
+    forward: vec3<f32>,
+    aperture_radius: f32,
+    right: vec3<f32>,
+    focus_distance: f32,
+    up: vec3<f32>,
+    projection: u32,
+    // Brown-Conrady radial and tangential coefficients, see camera.rs
+    distortion_k: vec3<f32>,
+    distortion: u32,
+    distortion_p: vec2<f32>,
+}
@@ -0,0 +1,90 @@
+// Auto-exposure metered from a histogram of the image's log luminance,
+// built and averaged on the GPU so the exposure never leaves it. The first
+// pass bins every pixel, the second reduces the bins to the average and
+// eases the exposure toward putting it at middle gray.
+
+// Bins of the histogram, one invocation each when averaging. Bin 0 holds
+// pixels too dark to count, such as the background of a transparent image.
+const BINS: u32 = 256u;
+// Luminance that the average is exposed to
+const MIDDLE_GRAY: f32 = 0.18;
+
+struct Params {
+    // Range of log2 luminance the bins cover
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    // How far the exposure moves to the metered one this frame, 1 to jump
+    adaptation: f32,
+}
+
+// The metered exposure in stops, read by render.wgsl
+struct Metered {
+    exposure: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var image: texture_2d<f32>;
+@group(0) @binding(2)
+var<storage, read_write> histogram: array<atomic<u32>, BINS>;
+@group(0) @binding(3)
+var<storage, read_write> metered: Metered;
+
+var<workgroup> local_bins: array<atomic<u32>, BINS>;
+var<workgroup> weighted: array<f32, BINS>;
+var<workgroup> counted: array<f32, BINS>;
+
+fn luminance_bin(color: vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    if luminance < exp2(params.min_log_luminance) {
+        return 0u;
+    }
+    let t = clamp((log2(luminance) - params.min_log_luminance) / params.log_luminance_range, 0.0, 1.0);
+    return 1u + u32(t * f32(BINS - 2u));
+}
+
+@compute @workgroup_size(16, 16)
+fn build_histogram(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    atomicStore(&local_bins[index], 0u);
+    workgroupBarrier();
+    let size = textureDimensions(image);
+    if all(id.xy < size) {
+        let color = textureLoad(image, id.xy, 0).rgb;
+        atomicAdd(&local_bins[luminance_bin(color)], 1u);
+    }
+    workgroupBarrier();
+    let count = atomicLoad(&local_bins[index]);
+    if count > 0u {
+        atomicAdd(&histogram[index], count);
+    }
+}
+
+@compute @workgroup_size(256)
+fn average_histogram(@builtin(local_invocation_index) index: u32) {
+    // Cleared for the next frame as it's read
+    let count = f32(atomicExchange(&histogram[index], 0u));
+    let counts = select(count, 0.0, index == 0u);
+    weighted[index] = counts * f32(index);
+    counted[index] = counts;
+    workgroupBarrier();
+    for (var stride = BINS / 2u; stride > 0u; stride >>= 1u) {
+        if index < stride {
+            weighted[index] += weighted[index + stride];
+            counted[index] += counted[index + stride];
+        }
+        workgroupBarrier();
+    }
+    // Nothing lit keeps the exposure as it was
+    if index != 0u || counted[0] == 0.0 {
+        return;
+    }
+    // Bins past the first cover equal steps, counted from their middles
+    let bin = weighted[0] / counted[0] - 0.5;
+    let log_luminance = params.min_log_luminance + bin / f32(BINS - 2u) * params.log_luminance_range;
+    let exposure = log2(MIDDLE_GRAY) - log_luminance;
+    metered.exposure = mix(metered.exposure, exposure, params.adaptation);
+}
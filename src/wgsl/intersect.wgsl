@@ -0,0 +1,217 @@
+// Rays against the BVH, instances, triangles and primitives bound by the
+// includer, see bvh.rs.
+
+struct Ray {
+    origin: vec3<f32>,
+    dir: vec3<f32>,
+}
+
+struct Hit {
+    t: f32,
+    u: f32,
+    v: f32,
+    // Index of the triangle, or of the primitive along with PRIMITIVE_HIT
+    triangle: u32,
+    // Index into instances of the triangle's instance
+    instance: u32,
+}
+
+fn intersect_aabb(ray: Ray, inv_dir: vec3<f32>, bmin: vec3<f32>, bmax: vec3<f32>, t_max: f32) -> f32 {
+    let t0 = (bmin - ray.origin) * inv_dir;
+    let t1 = (bmax - ray.origin) * inv_dir;
+    let t_near = max(max(min(t0.x, t1.x), min(t0.y, t1.y)), min(t0.z, t1.z));
+    let t_far = min(min(max(t0.x, t1.x), max(t0.y, t1.y)), max(t0.z, t1.z));
+    if t_far >= max(t_near, 0.0) && t_near < t_max {
+        return t_near;
+    }
+    return T_MAX;
+}
+
+// Möller–Trumbore, returns (t, u, v) with t = T_MAX on a miss
+fn intersect_triangle(ray: Ray, p0: vec3<f32>, p1: vec3<f32>, p2: vec3<f32>) -> vec3<f32> {
+    let e1 = p1 - p0;
+    let e2 = p2 - p0;
+    let p = cross(ray.dir, e2);
+    let det = dot(e1, p);
+    if abs(det) < 1e-12 {
+        return vec3<f32>(T_MAX, 0.0, 0.0);
+    }
+    let inv_det = 1.0 / det;
+    let s = ray.origin - p0;
+    let u = dot(s, p) * inv_det;
+    if u < 0.0 || u > 1.0 {
+        return vec3<f32>(T_MAX, 0.0, 0.0);
+    }
+    let q = cross(s, e1);
+    let v = dot(ray.dir, q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return vec3<f32>(T_MAX, 0.0, 0.0);
+    }
+    let t = dot(e2, q) * inv_det;
+    if t <= EPSILON {
+        return vec3<f32>(T_MAX, 0.0, 0.0);
+    }
+    return vec3<f32>(t, u, v);
+}
+
+// The nearer of two roots past the surface the ray left, T_MAX if neither
+fn nearest_root(t0: f32, t1: f32) -> f32 {
+    if t0 > EPSILON {
+        return t0;
+    }
+    if t1 > EPSILON {
+        return t1;
+    }
+    return T_MAX;
+}
+
+// Exact distance to a primitive, T_MAX on a miss, or one found by sphere
+// tracing up to t_max for distance fields. The ray is moved into the
+// primitive's space without normalizing it, so distances carry over
+fn intersect_primitive(ray: Ray, primitive: Primitive, t_max: f32) -> f32 {
+    let o = (primitive.to_local * vec4<f32>(ray.origin, 1.0)).xyz;
+    let d = (primitive.to_local * vec4<f32>(ray.dir, 0.0)).xyz;
+    let size = primitive.size;
+    switch primitive.kind {
+        case PRIMITIVE_SPHERE: {
+            let a = dot(d, d);
+            let b = dot(o, d);
+            let c = dot(o, o) - size.x * size.x;
+            let discriminant = b * b - a * c;
+            if discriminant < 0.0 {
+                return T_MAX;
+            }
+            let root = sqrt(discriminant);
+            return nearest_root((-b - root) / a, (-b + root) / a);
+        }
+        case PRIMITIVE_BOX: {
+            let inv_d = 1.0 / d;
+            let t0 = (-size - o) * inv_d;
+            let t1 = (size - o) * inv_d;
+            let t_near = max(max(min(t0.x, t1.x), min(t0.y, t1.y)), min(t0.z, t1.z));
+            let t_far = min(min(max(t0.x, t1.x), max(t0.y, t1.y)), max(t0.z, t1.z));
+            if t_near > t_far {
+                return T_MAX;
+            }
+            return nearest_root(t_near, t_far);
+        }
+        case PRIMITIVE_SDF: {
+            return march_sdf(primitive, o, d, t_max);
+        }
+        default: {}
+    }
+    // Flat shapes in the XZ plane
+    if d.y == 0.0 {
+        return T_MAX;
+    }
+    let t = -o.y / d.y;
+    let p = (o + t * d).xz;
+    if t <= EPSILON {
+        return T_MAX;
+    }
+    if primitive.kind == PRIMITIVE_QUAD && any(abs(p) > size.xz) {
+        return T_MAX;
+    }
+    if primitive.kind == PRIMITIVE_DISK && dot(p / size.xz, p / size.xz) > 1.0 {
+        return T_MAX;
+    }
+    return t;
+}
+
+// Closest hit before t_max, or a hit at t_max if there is none. The
+// top-level leaves push their instances onto the stack, and popping one
+// walks its bottom-level tree above it on the stack, with the ray moved
+// into the space of its mesh without normalizing it so distances carry over
+fn trace(ray: Ray, t_max: f32) -> Hit {
+    var hit = Hit(t_max, 0.0, 0.0, 0u, 0u);
+
+    // Primitives aren't part of the BVH
+    for (var i = 0u; i < params.primitive_count; i++) {
+        let t = intersect_primitive(ray, primitives[i], hit.t);
+        if t < hit.t {
+            hit = Hit(t, 0.0, 0.0, i | PRIMITIVE_HIT, 0u);
+        }
+    }
+
+    var stack: array<u32, STACK_SIZE>;
+    var stack_len = 0u;
+    // Stack entries from here up belong to the instance walked, STACK_SIZE
+    // on the top level
+    var instance_base = STACK_SIZE;
+    var instance_index = 0u;
+    // hit_triangle swaps the last two vertices of mirrored instances
+    var mirrored = false;
+    var local = ray;
+    var inv_dir = 1.0 / ray.dir;
+    var node_index = 0u;
+    var visit = intersect_aabb(ray, inv_dir, nodes[0].min, nodes[0].max, hit.t) != T_MAX;
+    loop {
+        if visit {
+            let node = nodes[node_index];
+            if node.count == 0u {
+                var near = node.left_first;
+                var far = near + 1u;
+                var t_near = intersect_aabb(local, inv_dir, nodes[near].min, nodes[near].max, hit.t);
+                var t_far = intersect_aabb(local, inv_dir, nodes[far].min, nodes[far].max, hit.t);
+                if t_far < t_near {
+                    let tmp_node = near;
+                    near = far;
+                    far = tmp_node;
+                    let tmp_t = t_near;
+                    t_near = t_far;
+                    t_far = tmp_t;
+                }
+                if t_near != T_MAX {
+                    if t_far != T_MAX {
+                        stack[stack_len] = far;
+                        stack_len++;
+                    }
+                    node_index = near;
+                    continue;
+                }
+            } else if instance_base == STACK_SIZE {
+                for (var i = node.left_first; i < node.left_first + node.count; i++) {
+                    stack[stack_len] = i | INSTANCE_ENTRY;
+                    stack_len++;
+                }
+            } else {
+                for (var i = node.left_first; i < node.left_first + node.count; i++) {
+                    // The corners are the first section of the buffer
+                    let p = 3u * i;
+                    let result = intersect_triangle(local, triangles[p].xyz, triangles[p + 1u].xyz, triangles[p + 2u].xyz);
+                    if result.x < hit.t {
+                        let uv = select(result.yz, result.zy, mirrored);
+                        hit = Hit(result.x, uv.x, uv.y, i, instance_index);
+                    }
+                }
+            }
+        }
+        // Back on the top level once the instance's nodes are done
+        if stack_len == instance_base {
+            instance_base = STACK_SIZE;
+            local = ray;
+            inv_dir = 1.0 / ray.dir;
+        }
+        if stack_len == 0u {
+            break;
+        }
+        stack_len--;
+        node_index = stack[stack_len];
+        visit = true;
+        if (node_index & INSTANCE_ENTRY) != 0u {
+            instance_index = node_index & ~INSTANCE_ENTRY;
+            let instance = instances[instance_index];
+            local = Ray(
+                (instance.to_local * vec4<f32>(ray.origin, 1.0)).xyz,
+                (instance.to_local * vec4<f32>(ray.dir, 0.0)).xyz,
+            );
+            inv_dir = 1.0 / local.dir;
+            mirrored = (instance.flags & INSTANCE_MIRRORED) != 0u;
+            instance_base = stack_len;
+            node_index = instance.root;
+            let root = nodes[node_index];
+            visit = intersect_aabb(local, inv_dir, root.min, root.max, hit.t) != T_MAX;
+        }
+    }
+    return hit;
+}
@@ -0,0 +1,36 @@
+@group(0) @binding(0) var source: texture_2d_array<f32>;
+@group(0) @binding(1) var destination: texture_storage_2d_array<rgba8unorm, write>;
+// Nonzero for layers stored as sRGB, which are averaged in linear light
+@group(0) @binding(2) var<storage, read> srgb: array<u32>;
+
+fn decode_srgb(c: vec3<f32>) -> vec3<f32> {
+    return select(pow((c + 0.055) / 1.055, vec3(2.4)), c / 12.92, c <= vec3(0.04045));
+}
+
+fn encode_srgb(c: vec3<f32>) -> vec3<f32> {
+    return select(1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, 12.92 * c, c <= vec3(0.0031308));
+}
+
+// One texel of the destination level from the four under it in the source
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE)
+fn downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id.xy >= textureDimensions(destination)) {
+        return;
+    }
+    let layer = id.z;
+    let is_srgb = srgb[layer] != 0u;
+    let p = vec2<i32>(id.xy * 2u);
+    var sum = vec4(0.0);
+    for (var i = 0; i < 4; i++) {
+        var texel = textureLoad(source, p + vec2(i & 1, i >> 1u), layer, 0);
+        if is_srgb {
+            texel = vec4(decode_srgb(texel.rgb), texel.a);
+        }
+        sum += texel;
+    }
+    var average = 0.25 * sum;
+    if is_srgb {
+        average = vec4(encode_srgb(average.rgb), average.a);
+    }
+    textureStore(destination, id.xy, layer, average);
+}
@@ -0,0 +1,158 @@
+const PI: f32 = 3.14159265358979;
+
+struct Params {
+    resolution: u32,
+    size: f32,
+    choppiness: f32,
+    level: f32,
+    time: f32,
+}
+
+struct Wave {
+    h0: vec2<f32>,
+    h0_minus_conj: vec2<f32>,
+    k: vec2<f32>,
+    omega: f32,
+}
+
+struct FftStage {
+    stage: u32,
+    vertical: u32,
+}
+
+// Each field holds the height followed by the X and Z displacements,
+// resolution² complex numbers each
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var<storage, read> waves: array<Wave>;
+@group(0) @binding(2)
+var<storage, read_write> spectrum_out: array<vec2<f32>>;
+@group(0) @binding(3)
+var<uniform> stage: FftStage;
+@group(0) @binding(4)
+var<storage, read> fft_src: array<vec2<f32>>;
+@group(0) @binding(5)
+var<storage, read_write> fft_dst: array<vec2<f32>>;
+@group(0) @binding(6)
+var<storage, read> field: array<vec2<f32>>;
+// Triangle index followed by its three grid vertices
+@group(0) @binding(7)
+var<storage, read> ocean_triangles: array<vec4<u32>>;
+@group(0) @binding(8)
+var<storage, read_write> triangles: array<vec4<f32>>;
+
+#include "triangles.wgsl"
+
+fn cmul(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+}
+
+// Advances every wave to the current time
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn spectrum(@builtin(global_invocation_id) id: vec3<u32>) {
+    let count = params.resolution * params.resolution;
+    if id.x >= count {
+        return;
+    }
+    let wave = waves[id.x];
+    let phase = wave.omega * params.time;
+    let e = vec2<f32>(cos(phase), sin(phase));
+    let h = cmul(wave.h0, e) + cmul(wave.h0_minus_conj, vec2<f32>(e.x, -e.y));
+
+    // Horizontal displacement -i k / |k| h pushes vertices towards crests
+    var dir = vec2<f32>(0.0);
+    if any(wave.k != vec2<f32>(0.0)) {
+        dir = normalize(wave.k);
+    }
+    let minus_i_h = vec2<f32>(h.y, -h.x);
+    spectrum_out[id.x] = h;
+    spectrum_out[count + id.x] = minus_i_h * dir.x;
+    spectrum_out[2u * count + id.x] = minus_i_h * dir.y;
+}
+
+// Element i of line `line` in plane `plane`, along rows or columns
+fn fft_index(plane: u32, line: u32, i: u32) -> u32 {
+    let n = params.resolution;
+    if stage.vertical != 0u {
+        return plane * n * n + i * n + line;
+    }
+    return plane * n * n + line * n + i;
+}
+
+// One radix 2 Stockham pass of the inverse FFT, which leaves the output in
+// natural order without a bit reversal, Govindaraju et al. 2008
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn fft(@builtin(global_invocation_id) id: vec3<u32>) {
+    let n = params.resolution;
+    let half = n / 2u;
+    if id.x >= half * n * 3u {
+        return;
+    }
+    let j = id.x % half;
+    let line = (id.x / half) % n;
+    let plane = id.x / (half * n);
+
+    let span = 1u << stage.stage;
+    let k = j & (span - 1u);
+    let a = fft_src[fft_index(plane, line, j)];
+    let b = fft_src[fft_index(plane, line, j + half)];
+    let angle = PI * f32(k) / f32(span);
+    let v = cmul(b, vec2<f32>(cos(angle), sin(angle)));
+    let out = (j - k) * 2u + k;
+    fft_dst[fft_index(plane, line, out)] = a + v;
+    fft_dst[fft_index(plane, line, out + span)] = a - v;
+}
+
+// Position of a vertex of the (resolution + 1)² grid, in the space of the
+// water mesh
+fn displaced(v: u32) -> vec3<f32> {
+    let n = params.resolution;
+    let x = v % (n + 1u);
+    let z = v / (n + 1u);
+    let i = (z % n) * n + x % n;
+    // The wave vectors start at -n / 2, which flips the sign of every
+    // other sample. The patch tiles, so the last row repeats the first.
+    let sign = select(1.0, -1.0, ((x + z) & 1u) == 1u);
+    let h = sign * field[i].x;
+    let dx = sign * field[n * n + i].x;
+    let dz = sign * field[2u * n * n + i].x;
+
+    let cell = params.size / f32(n);
+    let p = vec2<f32>(f32(x), f32(z)) * cell - 0.5 * params.size;
+    return vec3<f32>(
+        p.x + params.choppiness * dx,
+        params.level + h,
+        p.y + params.choppiness * dz,
+    );
+}
+
+// Central differences of the displaced neighbours, one sided at the edges
+fn vertex_normal(v: u32) -> vec3<f32> {
+    let n = params.resolution;
+    let x = v % (n + 1u);
+    let z = v / (n + 1u);
+    let left = z * (n + 1u) + max(x, 1u) - 1u;
+    let right = z * (n + 1u) + min(x + 1u, n);
+    let up = (max(z, 1u) - 1u) * (n + 1u) + x;
+    let down = min(z + 1u, n) * (n + 1u) + x;
+    let tangent = displaced(right) - displaced(left);
+    let bitangent = displaced(down) - displaced(up);
+    return normalize(cross(bitangent, tangent));
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn displace(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= arrayLength(&ocean_triangles) {
+        return;
+    }
+    let entry = ocean_triangles[id.x];
+    let p = triangle_section(TRIANGLE_POSITIONS) + 3u * entry.x;
+    let n = triangle_section(TRIANGLE_NORMALS) + 3u * entry.x;
+    triangles[p] = vec4<f32>(displaced(entry.y), 0.0);
+    triangles[p + 1u] = vec4<f32>(displaced(entry.z), 0.0);
+    triangles[p + 2u] = vec4<f32>(displaced(entry.w), 0.0);
+    triangles[n] = vec4<f32>(vertex_normal(entry.y), 0.0);
+    triangles[n + 1u] = vec4<f32>(vertex_normal(entry.z), 0.0);
+    triangles[n + 2u] = vec4<f32>(vertex_normal(entry.w), 0.0);
+}
@@ -10,12 +10,128 @@ fn vert_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position)
     return vec4<f32>(x, y, 0.0, 1.0);
 }
 
+// Brightness change per pixel of motion, see aov.rs
+const MOTION_SCALE: f32 = 0.05;
+
+struct DisplayParams {
+    // Exposure compensation in stops
+    exposure: f32,
+    // Whether colors go through the display LUT instead of plain sRGB
+    display_lut: u32,
+    // Image pixels per window pixel, across and down
+    resolution_scale: vec2<f32>,
+    // AOV in the texture, matching `Aov`
+    aov: u32,
+    // Whether the metered exposure is added to `exposure`
+    auto_exposure: u32,
+    shaper_min: vec4<f32>,
+    shaper_max: vec4<f32>,
+    cube_min: vec4<f32>,
+    cube_max: vec4<f32>,
+}
+
 @group(0) @binding(0)
 var in_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> display: DisplayParams;
+@group(0) @binding(2)
+var shaper_texture: texture_2d<f32>;
+@group(0) @binding(3)
+var cube_texture: texture_3d<f32>;
+// Exposure in stops metered by exposure.wgsl
+@group(0) @binding(4)
+var<storage, read> metered_exposure: f32;
+
+// Position of `value` in a table of `size` entries covering the domain
+fn lut_position(value: vec3<f32>, domain_min: vec3<f32>, domain_max: vec3<f32>, size: u32) -> vec3<f32> {
+    let t = clamp((value - domain_min) / (domain_max - domain_min), vec3(0.0), vec3(1.0));
+    return t * f32(size - 1u);
+}
+
+fn apply_shaper(color: vec3<f32>) -> vec3<f32> {
+    let size = textureDimensions(shaper_texture).x;
+    let t = lut_position(color, display.shaper_min.xyz, display.shaper_max.xyz, size);
+    var result = vec3(0.0);
+    for (var channel = 0; channel < 3; channel++) {
+        let lower = min(u32(t[channel]), size - 2u);
+        let fraction = t[channel] - f32(lower);
+        let a = textureLoad(shaper_texture, vec2(lower, 0u), 0)[channel];
+        let b = textureLoad(shaper_texture, vec2(lower + 1u, 0u), 0)[channel];
+        result[channel] = mix(a, b, fraction);
+    }
+    return result;
+}
+
+// Trilinear, since float32 textures can't be filtered everywhere
+fn apply_cube(color: vec3<f32>) -> vec3<f32> {
+    let size = textureDimensions(cube_texture).x;
+    let t = lut_position(color, display.cube_min.xyz, display.cube_max.xyz, size);
+    let lower = min(vec3<u32>(t), vec3(size - 2u));
+    let fraction = t - vec3<f32>(lower);
+    var result = vec3(0.0);
+    for (var corner = 0u; corner < 8u; corner++) {
+        let offset = vec3((corner & 1u), (corner >> 1u) & 1u, corner >> 2u);
+        let weights = select(1.0 - fraction, fraction, offset == vec3(1u));
+        let value = textureLoad(cube_texture, lower + offset, 0).rgb;
+        result += value * weights.x * weights.y * weights.z;
+    }
+    return result;
+}
+
+// Linear light of a display value, which the sRGB surface encodes again
+fn decode_srgb(srgb: vec3<f32>) -> vec3<f32> {
+    let low = srgb / 12.92;
+    let high = pow((srgb + 0.055) / 1.055, vec3(2.4));
+    return select(high, low, srgb <= vec3(0.04045));
+}
+
+// Distinct color of an object or class ID, black for none, see `aov::id_color`
+fn id_color(id: u32) -> vec3<f32> {
+    let state = id * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    let hash = (word >> 22u) ^ word;
+    let color = vec3<f32>(vec3(hash, hash >> 8u, hash >> 16u) & vec3(255u)) / 255.0;
+    return select(color, vec3(0.0), id == 0u);
+}
+
+// AOVs are shown as data, without exposure or the display transform
+fn aov_color(aov: u32, value: vec4<f32>) -> vec3<f32> {
+    switch aov {
+        case 2u: {
+            return decode_srgb(value.xyz * 0.5 + 0.5);
+        }
+        case 3u: {
+            // Closer is brighter, nothing is black
+            return decode_srgb(select(vec3(1.0 / (1.0 + 0.2 * value.x)), vec3(0.0), value.x <= 0.0));
+        }
+        case 4u, 5u: {
+            return decode_srgb(id_color(u32(round(value.x))));
+        }
+        case 6u: {
+            // Still is gray, moving right is red and down is green
+            return decode_srgb(vec3(clamp(0.5 + MOTION_SCALE * value.xy, vec2(0.0), vec2(1.0)), 0.5));
+        }
+        default: {
+            return value.rgb;
+        }
+    }
+}
 
 @fragment
 fn frag_main(@builtin(position) coord_in: vec4<f32>) -> @location(0) vec4<f32> {
-    let pixel_color = textureLoad(in_texture, vec2<u32>(coord_in.xy), 0);
-    return pixel_color;
+    let pixel = vec2<u32>(coord_in.xy * display.resolution_scale);
+    let pixel_color = textureLoad(in_texture, min(pixel, textureDimensions(in_texture) - 1u), 0);
+    if display.aov != 0u {
+        return vec4<f32>(aov_color(display.aov, pixel_color), 1.0);
+    }
+    let metered = select(0.0, metered_exposure, display.auto_exposure != 0u);
+    var color = pixel_color.rgb * exp2(display.exposure + metered);
+    if display.display_lut != 0u {
+        // The transform expects straight colors
+        let alpha = pixel_color.a;
+        let straight = select(vec3(0.0), color / alpha, alpha > 0.0);
+        color = decode_srgb(clamp(apply_cube(apply_shaper(straight)), vec3(0.0), vec3(1.0))) * alpha;
+    }
+    return vec4<f32>(color, pixel_color.a);
     // return vec4<f32>(coord_in.x, coord_in.y, 0.1, 1.0);
 }
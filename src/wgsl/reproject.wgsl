@@ -0,0 +1,117 @@
+// Carries the accumulated image over a camera move: every pixel finds what
+// it sees in the previous frame and blends the new sample into it. History
+// outside the spread of the new samples around the pixel is clamped, which
+// keeps disocclusions and moving highlights from ghosting.
+
+#include "camera.wgsl"
+// Reprojected history is rejected past these differences
+const NORMAL_THRESHOLD: f32 = 0.9;
+const DEPTH_THRESHOLD: f32 = 0.1;
+// Standard deviations of the neighborhood the history is clamped to
+const CLAMP_SIGMA: f32 = 2.0;
+
+struct Params {
+    camera: Camera,
+    prev_camera: Camera,
+    // Samples the history counts as
+    history: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+// New samples, with normals and hit distances from trace.wgsl
+@group(0) @binding(1)
+var samples: texture_2d<f32>;
+@group(0) @binding(2)
+var normal: texture_2d<f32>;
+@group(0) @binding(3)
+var prev_color: texture_2d<f32>;
+@group(0) @binding(4)
+var prev_normal: texture_2d<f32>;
+@group(0) @binding(5)
+var output: texture_storage_2d<rgba32float, write>;
+
+fn camera_dir(camera: Camera, pixel: vec2<f32>, size: vec2<f32>) -> vec3<f32> {
+    let ndc = pixel / size * 2.0 - 1.0;
+    let aspect = size.x / size.y;
+    return normalize(
+        camera.forward
+            + ndc.x * aspect * camera.tan_half_fov * camera.right
+            - ndc.y * camera.tan_half_fov * camera.up,
+    );
+}
+
+// Pixel looking along `dir` from the camera, negative when behind it
+// Pixels of a pinhole camera, which a distorted lens isn't
+fn is_pinhole(camera: Camera) -> bool {
+    return camera.projection == PROJECTION_PERSPECTIVE && camera.distortion != DISTORTION_APPLY;
+}
+
+fn project(camera: Camera, dir: vec3<f32>, size: vec2<f32>) -> vec2<f32> {
+    let z = dot(dir, camera.forward);
+    if z <= 0.0 {
+        return vec2<f32>(-1.0);
+    }
+    let aspect = size.x / size.y;
+    let ndc = vec2<f32>(
+        dot(dir, camera.right) / (z * camera.tan_half_fov * aspect),
+        -dot(dir, camera.up) / (z * camera.tan_half_fov),
+    );
+    return (ndc + 1.0) * 0.5 * size;
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE)
+fn resolve(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = vec2<i32>(textureDimensions(samples));
+    let p = vec2<i32>(id.xy);
+    if any(p >= size) {
+        return;
+    }
+    let sample = textureLoad(samples, p, 0);
+    var color = sample;
+
+    let perspective = is_pinhole(params.camera) && is_pinhole(params.prev_camera);
+    if perspective {
+        let fsize = vec2<f32>(size);
+        let g = textureLoad(normal, p, 0);
+        let dir = camera_dir(params.camera, vec2<f32>(p) + 0.5, fsize);
+        // The background is infinitely far away, so only directions matter
+        var prev_dir = dir;
+        if g.w > 0.0 {
+            prev_dir = params.camera.position + g.w * dir - params.prev_camera.position;
+        }
+        let q = project(params.prev_camera, prev_dir, fsize);
+        let qi = vec2<i32>(floor(q));
+        if all(q >= vec2<f32>(0.0)) && all(qi < size) {
+            let prev_g = textureLoad(prev_normal, qi, 0);
+            var same_surface = prev_g.w == 0.0 && g.w == 0.0;
+            if g.w > 0.0 && prev_g.w > 0.0 {
+                let expected = length(prev_dir);
+                same_surface = dot(normalize(prev_g.xyz), normalize(g.xyz)) > NORMAL_THRESHOLD
+                    && abs(prev_g.w - expected) < DEPTH_THRESHOLD * expected;
+            }
+            if same_surface {
+                var mean = vec3<f32>(0.0);
+                var square = vec3<f32>(0.0);
+                for (var dy = -1; dy <= 1; dy++) {
+                    for (var dx = -1; dx <= 1; dx++) {
+                        let neighbor = clamp(p + vec2<i32>(dx, dy), vec2<i32>(0), size - 1);
+                        let c = textureLoad(samples, neighbor, 0).rgb;
+                        mean += c / 9.0;
+                        square += c * c / 9.0;
+                    }
+                }
+                let sigma = sqrt(max(square - mean * mean, vec3<f32>(0.0)));
+                let prev = textureLoad(prev_color, qi, 0);
+                let history = clamp(
+                    prev.rgb,
+                    mean - CLAMP_SIGMA * sigma,
+                    mean + CLAMP_SIGMA * sigma,
+                );
+                let weight = 1.0 / f32(params.history + 1u);
+                color = mix(vec4<f32>(history, prev.a), sample, weight);
+            }
+        }
+    }
+    textureStore(output, p, color);
+}
@@ -0,0 +1,141 @@
+// Random numbers of the path tracer, included by trace.wgsl. The blue
+// noise texture and PMJ02 table are bound by the includer.
+
+// Samples come from one of the sequences of sampler.rs, decorrelated
+// between pixels and between the pairs of dimensions along the path.
+const SAMPLER_INDEPENDENT: u32 = 0u;
+const SAMPLER_STRATIFIED: u32 = 1u;
+const SAMPLER_SOBOL: u32 = 2u;
+const SAMPLER_PMJ02: u32 = 3u;
+const STRATA: u32 = 16u;
+const PMJ02_SAMPLES: u32 = 4096u;
+// The Sobol sampler uses the first two dimensions of the Sobol sequence,
+// Owen scrambled differently for every pixel and dimension of the path
+// after Burley 2020, "Practical Hash-based Owen Scrambling". Every pair of
+// dimensions is then stratified over the samples of a pixel on its own.
+//
+// The first few samples of all pixels share one sequence instead, shifted
+// by blue noise, so the error of a low sample count preview is spread
+// evenly over the screen rather than clumping.
+const BLUE_NOISE_SIZE: u32 = 64u;
+const BLUE_NOISE_SAMPLES: u32 = 8u;
+var<private> sample_pixel: vec2<u32>;
+var<private> sample_seed: u32;
+var<private> sample_index: u32;
+var<private> sample_dimension: u32;
+
+fn init_sampler(pixel: vec2<u32>, width: u32, index: u32) {
+    sample_pixel = pixel;
+    sample_seed = pcg_hash(pixel.x + pixel.y * width);
+    sample_index = index;
+    sample_dimension = 0u;
+}
+
+// Second Sobol dimension, whose direction numbers follow from the first
+fn sobol_1(index: u32) -> u32 {
+    var result = 0u;
+    var direction = 0x80000000u;
+    for (var i = index; i != 0u; i >>= 1u) {
+        if (i & 1u) != 0u {
+            result ^= direction;
+        }
+        direction ^= direction >> 1u;
+    }
+    return result;
+}
+
+fn laine_karras_permutation(value: u32, seed: u32) -> u32 {
+    var x = value + seed;
+    x ^= x * 0x6c50b47cu;
+    x ^= x * 0xb82f1e52u;
+    x ^= x * 0xc7afe638u;
+    x ^= x * 0x8d22f6e6u;
+    return x;
+}
+
+fn owen_scramble(value: u32, seed: u32) -> u32 {
+    return reverseBits(laine_karras_permutation(reverseBits(value), seed));
+}
+
+fn to_unit_float(value: u32) -> f32 {
+    return f32(value >> 8u) / 16777216.0;
+}
+
+fn independent_2d(seed: u32, index: u32) -> vec2<f32> {
+    let hash = pcg_hash(seed ^ pcg_hash(index));
+    return vec2<f32>(to_unit_float(hash), to_unit_float(pcg_hash(hash)));
+}
+
+fn stratified_2d(seed: u32, index: u32) -> vec2<f32> {
+    let cells = STRATA * STRATA;
+    // Every round visits the cells in a different order
+    let hash = pcg_hash(seed ^ pcg_hash(index / cells));
+    let cell = ((index % cells) * (hash | 1u) + (hash >> 16u)) % cells;
+    let corner = vec2<f32>(f32(cell % STRATA), f32(cell / STRATA));
+    return (corner + independent_2d(hash, index)) / f32(STRATA);
+}
+
+fn sobol_2d(seed: u32, index: u32) -> vec2<f32> {
+    // Shuffling the index decorrelates the dimensions
+    let scrambled = owen_scramble(index, seed);
+    return vec2<f32>(
+        to_unit_float(owen_scramble(reverseBits(scrambled), pcg_hash(seed))),
+        to_unit_float(owen_scramble(sobol_1(scrambled), pcg_hash(seed + 1u))),
+    );
+}
+
+fn blue_noise_sobol_2d(dimension: u32) -> vec2<f32> {
+    let seed = pcg_hash(dimension);
+    let u = sobol_2d(seed, sample_index);
+    // Every dimension reads the mask from elsewhere, and the second
+    // coordinate half a tile away from the first
+    let offset = vec2<u32>(seed, seed >> 16u);
+    let p = sample_pixel + offset;
+    let q = p + BLUE_NOISE_SIZE / 2u;
+    let shift = vec2<f32>(
+        textureLoad(blue_noise, p % BLUE_NOISE_SIZE, 0).r,
+        textureLoad(blue_noise, vec2<u32>(p.x, q.y) % BLUE_NOISE_SIZE, 0).r,
+    );
+    return fract(u + shift);
+}
+
+// Aligned blocks of the table are nets too, so XORing the index and the
+// digits of the points keeps them stratified
+fn pmj02_2d(seed: u32, index: u32) -> vec2<f32> {
+    let hash = pcg_hash(seed ^ pcg_hash(index / PMJ02_SAMPLES));
+    let i = (index ^ hash) % PMJ02_SAMPLES;
+    let width = textureDimensions(pmj02_points).x;
+    let point = textureLoad(pmj02_points, vec2<u32>(i % width, i / width), 0).xy;
+    return vec2<f32>(
+        to_unit_float(point.x ^ pcg_hash(hash)),
+        to_unit_float(point.y ^ pcg_hash(hash + 1u)),
+    );
+}
+
+// Next two dimensions of the current sample
+fn rand2() -> vec2<f32> {
+    let dimension = sample_dimension;
+    sample_dimension += 1u;
+    let seed = pcg_hash(sample_seed ^ pcg_hash(dimension));
+    switch params.sampler_kind {
+        case SAMPLER_INDEPENDENT: {
+            return independent_2d(seed, sample_index);
+        }
+        case SAMPLER_STRATIFIED: {
+            return stratified_2d(seed, sample_index);
+        }
+        case SAMPLER_PMJ02: {
+            return pmj02_2d(seed, sample_index);
+        }
+        default: {
+            if sample_index < BLUE_NOISE_SAMPLES {
+                return blue_noise_sobol_2d(dimension);
+            }
+            return sobol_2d(seed, sample_index);
+        }
+    }
+}
+
+fn rand() -> f32 {
+    return rand2().x;
+}
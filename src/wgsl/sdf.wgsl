@@ -0,0 +1,121 @@
+// Distance fields of the SDF primitives, see sdf.rs, in the primitive's
+// local space. sdf_wgsl() is generated from the scene's WGSL fields and
+// appended to the shader by the tracer.
+
+const SDF_SPHERE: u32 = 0u;
+const SDF_BOX: u32 = 1u;
+const SDF_TORUS: u32 = 2u;
+const SDF_MANDELBULB: u32 = 3u;
+
+const SDF_SHAPE: u32 = 0u;
+const SDF_UNION: u32 = 1u;
+const SDF_INTERSECTION: u32 = 2u;
+const SDF_SUBTRACTION: u32 = 3u;
+const SDF_WGSL: u32 = 4u;
+
+// Most steps of sphere tracing before a ray counts as missing
+const SDF_MAX_STEPS: u32 = 256u;
+// Distance counted as on the surface, well inside the offset of rays
+// leaving it
+const SDF_HIT_DISTANCE: f32 = 2e-5;
+// Offset of the differences the normal is taken from
+const SDF_NORMAL_STEP: f32 = 1e-4;
+const MANDELBULB_BAILOUT: f32 = 2.0;
+
+// Distance estimate from the running derivative of the iteration
+fn mandelbulb(p: vec3<f32>, power: f32, iterations: u32) -> f32 {
+    var z = p;
+    var dr = 1.0;
+    var r = length(z);
+    for (var i = 0u; i < iterations && r < MANDELBULB_BAILOUT; i++) {
+        dr = power * pow(r, power - 1.0) * dr + 1.0;
+        let theta = acos(clamp(z.z / r, -1.0, 1.0)) * power;
+        let phi = atan2(z.y, z.x) * power;
+        z = pow(r, power) * vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta)) + p;
+        r = length(z);
+    }
+    return 0.5 * log(r) * r / dr;
+}
+
+fn sdf_shape(kind: u32, params: vec4<f32>, p: vec3<f32>) -> f32 {
+    switch kind {
+        case SDF_SPHERE: {
+            return length(p) - params.x;
+        }
+        case SDF_BOX: {
+            let q = abs(p) - params.xyz + params.w;
+            return length(max(q, vec3<f32>(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0) - params.w;
+        }
+        case SDF_TORUS: {
+            return length(vec2<f32>(length(p.xz) - params.x, p.y)) - params.y;
+        }
+        case SDF_MANDELBULB: {
+            return mandelbulb(p, params.x, u32(params.y));
+        }
+        default: {
+            return T_MAX;
+        }
+    }
+}
+
+// Polynomial smooth minimum blending over k units, Quilez
+fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0.0 {
+        return min(a, b);
+    }
+    let h = max(k - abs(a - b), 0.0) / k;
+    return min(a, b) - 0.25 * h * h * k;
+}
+
+fn sdf(primitive: Primitive, p: vec3<f32>) -> f32 {
+    if primitive.sdf_op == SDF_WGSL {
+        return sdf_wgsl(primitive.sdf_shapes.x, p);
+    }
+    let a = sdf_shape(primitive.sdf_shapes.x, primitive.sdf_params[0], p);
+    if primitive.sdf_op == SDF_SHAPE {
+        return a;
+    }
+    let b = sdf_shape(primitive.sdf_shapes.y, primitive.sdf_params[1], p - primitive.sdf_offset);
+    let k = primitive.smoothness;
+    switch primitive.sdf_op {
+        case SDF_INTERSECTION: {
+            return -smooth_min(-a, -b, k);
+        }
+        case SDF_SUBTRACTION: {
+            return -smooth_min(-a, b, k);
+        }
+        default: {
+            return smooth_min(a, b, k);
+        }
+    }
+}
+
+// Sphere traces the field through its bounds from o along d, which isn't
+// normalized so distances carry over to world space. Rays march by the
+// unsigned distance, so they find the surface from inside too
+fn march_sdf(primitive: Primitive, o: vec3<f32>, d: vec3<f32>, t_max: f32) -> f32 {
+    let inv_d = 1.0 / d;
+    let t0 = (-primitive.size - o) * inv_d;
+    let t1 = (primitive.size - o) * inv_d;
+    var t = max(max(max(min(t0.x, t1.x), min(t0.y, t1.y)), min(t0.z, t1.z)), 0.0);
+    let t_far = min(min(min(max(t0.x, t1.x), max(t0.y, t1.y)), max(t0.z, t1.z)), t_max);
+    let speed = length(d);
+    for (var i = 0u; i < SDF_MAX_STEPS && t < t_far; i++) {
+        let distance = abs(sdf(primitive, o + t * d));
+        if distance < SDF_HIT_DISTANCE && t > EPSILON {
+            return t;
+        }
+        t += max(distance, SDF_HIT_DISTANCE) / speed;
+    }
+    return T_MAX;
+}
+
+// Gradient of the field from four samples around p, Quilez
+fn sdf_normal(primitive: Primitive, p: vec3<f32>) -> vec3<f32> {
+    let k = vec2<f32>(1.0, -1.0);
+    let h = SDF_NORMAL_STEP;
+    return normalize(
+        k.xyy * sdf(primitive, p + h * k.xyy) + k.yyx * sdf(primitive, p + h * k.yyx)
+            + k.yxy * sdf(primitive, p + h * k.yxy) + k.xxx * sdf(primitive, p + h * k.xxx)
+    );
+}
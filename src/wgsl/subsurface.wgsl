@@ -0,0 +1,99 @@
+// Random walk subsurface scattering. Light the diffuse base of a material
+// with MATERIAL_SUBSURFACE takes in scatters through the homogeneous medium
+// under its surface until it reaches a surface again, where it leaves as
+// from a Lambertian one. Paths with a wavelength walk through the medium
+// at that wavelength, RGB ones pick the channel a distance is sampled for
+// by its throughput and weight the collisions against the other channels.
+
+// Collisions of a walk before giving up, which absorbs the path
+const SUBSURFACE_MAX_STEPS: u32 = 256u;
+
+// Where a walk left the medium, with the normals facing out of it
+struct SubsurfaceExit {
+    found: bool,
+    position: vec3<f32>,
+    n: vec3<f32>,
+    ng: vec3<f32>,
+}
+
+// Linear interpolation of the samples of a spectrum, see Spectrum in
+// spectral.rs
+fn spectrum_at(samples: array<vec4<f32>, 4>, wavelength: f32) -> f32 {
+    var s = samples;
+    let last = f32(SPECTRUM_SAMPLES - 1u);
+    let x = clamp((wavelength - MIN_WAVELENGTH) / (MAX_WAVELENGTH - MIN_WAVELENGTH), 0.0, 1.0) * last;
+    let i = min(u32(x), SPECTRUM_SAMPLES - 2u);
+    let j = i + 1u;
+    return mix(s[i / 4u][i % 4u], s[j / 4u][j % 4u], x - f32(i));
+}
+
+// The medium under the surface of a material, at the path's wavelength
+// when it has one
+fn subsurface_medium(material: Material, wavelength: f32) -> Medium {
+    if wavelength > 0.0 {
+        return Medium(
+            vec3<f32>(spectrum_at(material.absorption_spectrum, wavelength)),
+            vec3<f32>(spectrum_at(material.scattering_spectrum, wavelength)),
+            material.subsurface_anisotropy,
+        );
+    }
+    return Medium(
+        material.subsurface_absorption,
+        material.subsurface_scattering,
+        material.subsurface_anisotropy,
+    );
+}
+
+// Walks from the start of the ray inside the medium until it leaves,
+// updating throughput with the weight of every collision
+fn subsurface_walk(
+    material: Material,
+    start: Ray,
+    wavelength: f32,
+    throughput: ptr<function, vec3<f32>>,
+) -> SubsurfaceExit {
+    var exit = SubsurfaceExit(false, vec3<f32>(0.0), vec3<f32>(0.0), vec3<f32>(0.0));
+    let m = subsurface_medium(material, wavelength);
+    let extinction = m.absorption + m.scattering;
+    var ray = start;
+    for (var i = 0u; i < SUBSURFACE_MAX_STEPS; i++) {
+        let beta = *throughput;
+        let total = beta.x + beta.y + beta.z;
+        if total <= 0.0 {
+            break;
+        }
+        let p_channel = beta / total;
+        let u = rand();
+        var sigma = extinction.x;
+        if u >= p_channel.x {
+            sigma = select(extinction.y, extinction.z, u >= p_channel.x + p_channel.y);
+        }
+        let t = min(-log(1.0 - rand()) / sigma, T_MAX);
+        let hit = trace(ray, t);
+        let tr = exp(-extinction * hit.t);
+        if hit.t < t {
+            // Through to the surface, weighted by the chance of getting
+            // this far with any channel
+            *throughput *= tr / dot(p_channel, tr);
+            let tri = hit_triangle(ray, hit);
+            let w = 1.0 - hit.u - hit.v;
+            var ng = normalize(cross(tri.p1 - tri.p0, tri.p2 - tri.p0));
+            if dot(ng, ray.dir) < 0.0 {
+                ng = -ng;
+            }
+            var n = normalize(w * tri.n0 + hit.u * tri.n1 + hit.v * tri.n2);
+            if dot(n, ng) < 0.0 {
+                n = -n;
+            }
+            exit = SubsurfaceExit(true, ray.origin + hit.t * ray.dir, n, ng);
+            break;
+        }
+        // Nothing closes the medium off
+        if t == T_MAX {
+            break;
+        }
+        *throughput *= tr * m.scattering / dot(p_channel, tr * extinction);
+        ray = Ray(ray.origin + t * ray.dir, sample_hg(-ray.dir, m.anisotropy, rand2()));
+    }
+    return exit;
+}
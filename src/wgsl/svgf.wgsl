@@ -0,0 +1,252 @@
+// Spatiotemporal variance-guided filtering of the path traced image, after
+// Schied et al. 2017. Illumination is filtered with the albedo divided out,
+// so texture detail survives, and multiplied back in at the end.
+
+#include "camera.wgsl"
+// Smallest albedo illumination is divided by, as in trace.wgsl
+const ALBEDO_EPSILON: f32 = 0.01;
+// Frames of history blended at most, and its least weight after motion
+const MAX_HISTORY: f32 = 32.0;
+const MIN_ALPHA: f32 = 0.2;
+// Reprojected history is rejected past these differences
+const NORMAL_THRESHOLD: f32 = 0.9;
+const DEPTH_THRESHOLD: f32 = 0.1;
+// Edge stopping of the wavelet filter
+const NORMAL_SIGMA: f32 = 128.0;
+const DEPTH_SIGMA: f32 = 0.02;
+const LUMINANCE_SIGMA: f32 = 4.0;
+
+struct Params {
+    camera: Camera,
+    prev_camera: Camera,
+    // Samples per pixel accumulated by the tracer
+    samples: u32,
+}
+
+struct Step {
+    // Distance between the taps of this wavelet iteration
+    step: i32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+// Latest accumulation of the tracer and its guides
+@group(0) @binding(1)
+var color: texture_2d<f32>;
+@group(0) @binding(2)
+var albedo: texture_2d<f32>;
+@group(0) @binding(3)
+var normal: texture_2d<f32>;
+// Normals and depths of the previous frame, and its integrated
+// illumination with the history length in alpha and luminance moments
+@group(0) @binding(4)
+var prev_normal: texture_2d<f32>;
+@group(0) @binding(5)
+var prev_history: texture_2d<f32>;
+@group(0) @binding(6)
+var prev_moments: texture_2d<f32>;
+@group(0) @binding(7)
+var history_out: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(8)
+var moments_out: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(9)
+var history: texture_2d<f32>;
+@group(0) @binding(10)
+var moments: texture_2d<f32>;
+// Illumination with its variance in alpha, between wavelet iterations
+@group(0) @binding(11)
+var filter_in: texture_2d<f32>;
+@group(0) @binding(12)
+var filter_out: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(13)
+var<uniform> step: Step;
+@group(0) @binding(14)
+var output: texture_storage_2d<rgba32float, write>;
+
+fn luminance(c: vec3<f32>) -> f32 {
+    return dot(c, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+fn demodulate(c: vec3<f32>, a: vec3<f32>) -> vec3<f32> {
+    return c / max(a, vec3<f32>(ALBEDO_EPSILON));
+}
+
+// Point at `depth` along the pinhole ray through the center of `pixel`
+fn world_position(camera: Camera, pixel: vec2<f32>, size: vec2<f32>, depth: f32) -> vec3<f32> {
+    let ndc = pixel / size * 2.0 - 1.0;
+    let aspect = size.x / size.y;
+    let dir = camera.forward
+        + ndc.x * aspect * camera.tan_half_fov * camera.right
+        - ndc.y * camera.tan_half_fov * camera.up;
+    return camera.position + normalize(dir) * depth;
+}
+
+// Pixel `p` is seen in, negative when behind the camera
+// Pixels of a pinhole camera, which a distorted lens isn't
+fn is_pinhole(camera: Camera) -> bool {
+    return camera.projection == PROJECTION_PERSPECTIVE && camera.distortion != DISTORTION_APPLY;
+}
+
+fn project(camera: Camera, p: vec3<f32>, size: vec2<f32>) -> vec2<f32> {
+    let v = p - camera.position;
+    let z = dot(v, camera.forward);
+    if z <= 0.0 {
+        return vec2<f32>(-1.0);
+    }
+    let aspect = size.x / size.y;
+    let ndc = vec2<f32>(
+        dot(v, camera.right) / (z * camera.tan_half_fov * aspect),
+        -dot(v, camera.up) / (z * camera.tan_half_fov),
+    );
+    return (ndc + 1.0) * 0.5 * size;
+}
+
+// How much two pixels see the same surface, from their normals and depths
+fn geometry_weight(p: vec4<f32>, q: vec4<f32>, distance: f32) -> f32 {
+    // The background only blends with itself
+    if p.w == 0.0 || q.w == 0.0 {
+        return select(0.0, 1.0, p.w == q.w);
+    }
+    let n = max(dot(normalize(p.xyz), normalize(q.xyz)), 0.0);
+    let z = abs(p.w - q.w) / (DEPTH_SIGMA * p.w * distance + 1e-6);
+    return pow(n, NORMAL_SIGMA) * exp(-z);
+}
+
+// Blends the new samples into the history reprojected from the last frame.
+// While the view stays put the tracer's own accumulation is the history.
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE)
+fn temporal(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(color);
+    if any(id.xy >= size) {
+        return;
+    }
+    let p = vec2<i32>(id.xy);
+    let guide = textureLoad(albedo, p, 0);
+    let g = textureLoad(normal, p, 0);
+    let illumination = demodulate(textureLoad(color, p, 0).rgb, guide.rgb);
+    let samples = f32(params.samples);
+    // Moments of the accumulated mean, whose variance falls with the samples
+    let m1 = luminance(illumination);
+    let m2 = m1 * m1 + max(guide.a - m1 * m1, 0.0) / samples;
+    var integrated = vec4<f32>(illumination, samples);
+    var moment = vec4<f32>(m1, m2, 0.0, 0.0);
+
+    let perspective = is_pinhole(params.camera) && is_pinhole(params.prev_camera);
+    if params.samples == 1u && perspective && g.w > 0.0 {
+        let fsize = vec2<f32>(size);
+        let position = world_position(params.camera, vec2<f32>(id.xy) + 0.5, fsize, g.w);
+        let q = project(params.prev_camera, position, fsize);
+        let qi = vec2<i32>(floor(q));
+        if all(q >= vec2<f32>(0.0)) && all(qi < vec2<i32>(size)) {
+            let prev_g = textureLoad(prev_normal, qi, 0);
+            let expected = distance(position, params.prev_camera.position);
+            let same_surface = prev_g.w > 0.0
+                && dot(normalize(prev_g.xyz), normalize(g.xyz)) > NORMAL_THRESHOLD
+                && abs(prev_g.w - expected) < DEPTH_THRESHOLD * expected;
+            if same_surface {
+                let prev = textureLoad(prev_history, qi, 0);
+                let length = min(prev.a, MAX_HISTORY) + 1.0;
+                let alpha = max(1.0 / length, MIN_ALPHA);
+                integrated = vec4<f32>(mix(prev.rgb, illumination, alpha), length);
+                moment = mix(textureLoad(prev_moments, qi, 0), moment, alpha);
+            }
+        }
+    }
+    textureStore(history_out, p, integrated);
+    textureStore(moments_out, p, moment);
+}
+
+// Variance from the temporal moments, or from the neighborhood while the
+// history is too short for them
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE)
+fn variance(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = vec2<i32>(textureDimensions(history));
+    let p = vec2<i32>(id.xy);
+    if any(p >= size) {
+        return;
+    }
+    let center = textureLoad(history, p, 0);
+    var m = textureLoad(moments, p, 0).xy;
+    if center.a < 4.0 {
+        let g = textureLoad(normal, p, 0);
+        var sum_m = vec2<f32>(0.0);
+        var sum_w = 0.0;
+        for (var dy = -3; dy <= 3; dy++) {
+            for (var dx = -3; dx <= 3; dx++) {
+                let q = p + vec2<i32>(dx, dy);
+                if any(q < vec2<i32>(0)) || any(q >= size) {
+                    continue;
+                }
+                let distance = length(vec2<f32>(f32(dx), f32(dy)));
+                let w = geometry_weight(g, textureLoad(normal, q, 0), distance);
+                sum_m += w * textureLoad(moments, q, 0).xy;
+                sum_w += w;
+            }
+        }
+        // Few samples underestimate the variance
+        m = sum_m / sum_w;
+        m.y = m.x * m.x + (m.y - m.x * m.x) * 4.0 / max(center.a, 1.0);
+    }
+    textureStore(filter_out, p, vec4<f32>(center.rgb, max(m.y - m.x * m.x, 0.0)));
+}
+
+// One iteration of the edge-avoiding à-trous wavelet filter, a 5x5 B-spline
+// kernel with taps `step.step` pixels apart
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE)
+fn atrous(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = vec2<i32>(textureDimensions(filter_in));
+    let p = vec2<i32>(id.xy);
+    if any(p >= size) {
+        return;
+    }
+    var kernel = array<f32, 3>(3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0);
+    let center = textureLoad(filter_in, p, 0);
+    let g = textureLoad(normal, p, 0);
+
+    // Variance blurred over 3x3 pixels is a steadier edge stop
+    var blurred = 0.0;
+    for (var dy = -1; dy <= 1; dy++) {
+        for (var dx = -1; dx <= 1; dx++) {
+            let q = clamp(p + vec2<i32>(dx, dy), vec2<i32>(0), size - 1);
+            let w = select(0.25, 0.5, dx == 0) * select(0.25, 0.5, dy == 0);
+            blurred += w * textureLoad(filter_in, q, 0).a;
+        }
+    }
+    let l = luminance(center.rgb);
+    let sigma_l = LUMINANCE_SIGMA * sqrt(blurred) + 1e-6;
+
+    var sum = vec4<f32>(0.0);
+    var sum_w = 0.0;
+    for (var dy = -2; dy <= 2; dy++) {
+        for (var dx = -2; dx <= 2; dx++) {
+            let q = p + vec2<i32>(dx, dy) * step.step;
+            if any(q < vec2<i32>(0)) || any(q >= size) {
+                continue;
+            }
+            let sample = textureLoad(filter_in, q, 0);
+            let distance = f32(step.step) * length(vec2<f32>(f32(dx), f32(dy)));
+            var w = kernel[abs(dx)] * kernel[abs(dy)];
+            if dx != 0 || dy != 0 {
+                w *= geometry_weight(g, textureLoad(normal, q, 0), distance)
+                    * exp(-abs(l - luminance(sample.rgb)) / sigma_l);
+            }
+            // Variance falls with the square of the weights
+            sum += vec4<f32>(w * sample.rgb, w * w * sample.a);
+            sum_w += w;
+        }
+    }
+    textureStore(filter_out, p, vec4<f32>(sum.rgb / sum_w, sum.a / (sum_w * sum_w)));
+}
+
+// Multiplies the albedo back in, keeping the alpha of the samples
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE)
+fn modulate(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(filter_in);
+    if any(id.xy >= size) {
+        return;
+    }
+    let p = vec2<i32>(id.xy);
+    let illumination = textureLoad(filter_in, p, 0).rgb;
+    let a = max(textureLoad(albedo, p, 0).rgb, vec3<f32>(ALBEDO_EPSILON));
+    textureStore(output, p, vec4<f32>(illumination * a, textureLoad(color, p, 0).a));
+}
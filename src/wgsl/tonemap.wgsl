@@ -0,0 +1,132 @@
+// Last pass before the surface, mapping the exposed scene light the render
+// pass leaves in a float texture to the display and marking what's in focus,
+// see tonemap.rs
+
+@vertex
+fn vert_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let x = -1.0 + f32((in_vertex_index & u32(1)) << u32(2));
+    let y = -1.0 + f32((in_vertex_index & u32(2)) << u32(1));
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+struct TonemapParams {
+    // Operator, matching `Tonemap`
+    curve: u32,
+    // Whether the surface stores values as they are, so they're encoded here
+    encode_srgb: u32,
+    focus_peaking: u32,
+    // Image pixels per window pixel
+    resolution_scale: f32,
+    focus_distance: f32,
+    // Circle of confusion in image pixels per |d - focus| / d
+    blur_scale: f32,
+    // Largest circle of confusion counted as in focus
+    max_blur: f32,
+}
+
+@group(0) @binding(0)
+var in_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> params: TonemapParams;
+// Normal guide of the tracer, with the distance to the first hit in w
+@group(0) @binding(2)
+var guide_texture: texture_2d<f32>;
+
+// Stops of contrast between neighbors that make an edge
+const EDGE_CONTRAST: f32 = 0.5;
+// Display color of in-focus edges
+const PEAKING_COLOR: vec3<f32> = vec3<f32>(1.0, 0.05, 0.05);
+
+// Range of the log encoding AgX works in, in stops around middle gray
+const AGX_MIN_EV: f32 = -12.47393;
+const AGX_MAX_EV: f32 = 4.026069;
+
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    // Narkowicz's fit of the ACES reference rendering and output transforms
+    let x = color * 0.6;
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), vec3(0.0), vec3(1.0));
+}
+
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        0.8424791, 0.04232824, 0.04237565,
+        0.0784336, 0.8784686, 0.0784336,
+        0.07922375, 0.07916613, 0.879143,
+    );
+    let outset = mat3x3<f32>(
+        1.196879, -0.05289685, -0.05297164,
+        -0.09802088, 1.151903, -0.09804345,
+        -0.09902974, -0.09896118, 1.151074,
+    );
+    var x = clamp(log2(max(inset * color, vec3(1e-10))), vec3(AGX_MIN_EV), vec3(AGX_MAX_EV));
+    x = (x - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+    // Polynomial fit of the sigmoid, giving display values
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    x = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+    return pow(clamp(outset * x, vec3(0.0), vec3(1.0)), vec3(2.2));
+}
+
+fn tonemap(color: vec3<f32>) -> vec3<f32> {
+    switch params.curve {
+        case 1u: {
+            return color / (1.0 + color);
+        }
+        case 2u: {
+            return aces(color);
+        }
+        case 3u: {
+            return agx(color);
+        }
+        default: {
+            return color;
+        }
+    }
+}
+
+fn encode_srgb(linear: vec3<f32>) -> vec3<f32> {
+    let low = linear * 12.92;
+    let high = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+    return select(high, low, linear <= vec3(0.0031308));
+}
+
+fn log_luminance(pixel: vec2<i32>) -> f32 {
+    let size = vec2<i32>(textureDimensions(in_texture));
+    let color = textureLoad(in_texture, clamp(pixel, vec2(0), size - 1), 0).rgb;
+    return log2(max(dot(color, vec3(0.2126, 0.7152, 0.0722)), 1e-4));
+}
+
+// Whether the pixel is on an edge of something within the depth of field
+fn in_focus_edge(pixel: vec2<i32>) -> bool {
+    let guide_size = textureDimensions(guide_texture);
+    let guide_pixel = min(vec2<u32>(vec2<f32>(pixel) * params.resolution_scale), guide_size - 1u);
+    let distance = textureLoad(guide_texture, guide_pixel, 0).w;
+    if distance <= 0.0 {
+        return false;
+    }
+    let blur = params.blur_scale * abs(distance - params.focus_distance) / distance;
+    if blur > params.max_blur {
+        return false;
+    }
+    let contrast = max(
+        abs(log_luminance(pixel + vec2(1, 0)) - log_luminance(pixel - vec2(1, 0))),
+        abs(log_luminance(pixel + vec2(0, 1)) - log_luminance(pixel - vec2(0, 1))),
+    );
+    return contrast > EDGE_CONTRAST;
+}
+
+@fragment
+fn frag_main(@builtin(position) coord_in: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel_color = textureLoad(in_texture, vec2<u32>(coord_in.xy), 0);
+    // Operators work on straight colors
+    let alpha = pixel_color.a;
+    let straight = select(vec3(0.0), pixel_color.rgb / alpha, alpha > 0.0);
+    var color = max(tonemap(max(straight, vec3(0.0))), vec3(0.0));
+    if params.focus_peaking != 0u && in_focus_edge(vec2<i32>(coord_in.xy)) {
+        color = PEAKING_COLOR;
+    }
+    if params.encode_srgb != 0u {
+        color = encode_srgb(clamp(color, vec3(0.0), vec3(1.0)));
+    }
+    return vec4<f32>(color * alpha, alpha);
+}
@@ -0,0 +1,1403 @@
+const PI: f32 = 3.14159265358979;
+const INV_PI: f32 = 0.31830988618379;
+const T_MAX: f32 = 3.4e38;
+const EPSILON: f32 = 1e-4;
+// Smallest albedo illumination is divided by, see svgf.wgsl
+const ALBEDO_EPSILON: f32 = 0.01;
+// STACK_SIZE is defined by the tracer to fit the deepest walk of the BVH
+
+const MATERIAL_THIN_WALLED: u32 = 1u;
+const MATERIAL_SUBSURFACE: u32 = 2u;
+
+const TRIANGLE_HOLDOUT: u32 = 1u;
+const INSTANCE_MIRRORED: u32 = 2u;
+// Set in stack entries of trace that index the instances instead of nodes
+const INSTANCE_ENTRY: u32 = 0x80000000u;
+
+const PRIMITIVE_SPHERE: u32 = 0u;
+const PRIMITIVE_PLANE: u32 = 1u;
+const PRIMITIVE_BOX: u32 = 2u;
+const PRIMITIVE_QUAD: u32 = 3u;
+const PRIMITIVE_DISK: u32 = 4u;
+const PRIMITIVE_SDF: u32 = 5u;
+// Set in Hit.triangle when it indexes the primitives instead
+const PRIMITIVE_HIT: u32 = 0x80000000u;
+
+const DISPERSION_CAUCHY: u32 = 1u;
+const DISPERSION_SELLMEIER: u32 = 2u;
+// Visible range in micrometers
+const MIN_WAVELENGTH: f32 = 0.38;
+const MAX_WAVELENGTH: f32 = 0.78;
+// Planck's law, see spectral.rs
+const PLANCK_C2: f32 = 14387.77;
+const REFERENCE_WAVELENGTH: f32 = 0.56;
+const FRESNEL_TABLE_SIZE: u32 = 32u;
+const SPECTRUM_SAMPLES: u32 = 16u;
+const SHEEN_TABLE_SIZE: u32 = 16u;
+const MIN_SHEEN_ALPHA: f32 = 0.01;
+
+const LIGHT_DIRECTIONAL: u32 = 0u;
+const LIGHT_POINT: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+const LIGHT_QUAD: u32 = 3u;
+const LIGHT_DISK: u32 = 4u;
+
+// Roughness of a single flake, they're near mirrors
+const FLAKE_SURFACE_ROUGHNESS: f32 = 0.1;
+
+// Settings the pipeline is specialized on, see `Specialization`, so the
+// driver can drop what they turn off instead of branching on every ray
+override MAX_DEPTH: u32 = 8u;
+override SPECTRAL: bool = true;
+override LIGHT_SAMPLING: bool = true;
+
+#include "camera.wgsl"
+
+// Fixed point iterations inverting the distortion
+const UNDISTORT_ITERATIONS: i32 = 8;
+
+// Perez coefficients for luminance and xy chromaticity, see sky.rs
+struct Sky {
+    sun_direction: vec3<f32>,
+    sun_cos_radius: f32,
+    sun_radiance: vec3<f32>,
+    ground_radiance: vec3<f32>,
+    a: vec3<f32>,
+    b: vec3<f32>,
+    c: vec3<f32>,
+    d: vec3<f32>,
+    e: vec3<f32>,
+    zenith: vec3<f32>,
+}
+
+struct Params {
+    camera: Camera,
+    sky: Sky,
+    frame: u32,
+    // The kernel reads MAX_DEPTH, SPECTRAL and LIGHT_SAMPLING instead of
+    // these, which are for the CPU tracer
+    max_depth: u32,
+    environment_rotation: f32,
+    environment_intensity: f32,
+    has_environment: u32,
+    light_count: u32,
+    spectral: u32,
+    // Stores the bare sample for reproject.wgsl to blend with the history
+    reproject: u32,
+    seed: u32,
+    // Camera rays escaping to the background come back empty instead
+    transparent: u32,
+    // Camera rays escaping see the backdrop instead of the environment
+    has_backdrop: u32,
+    // One of the SAMPLER constants
+    sampler_kind: u32,
+    // First row of the band of the image this dispatch traces
+    row_offset: u32,
+    primitive_count: u32,
+    light_sampling: u32,
+    volume_count: u32,
+}
+
+struct BvhNode {
+    min: vec3<f32>,
+    left_first: u32,
+    max: vec3<f32>,
+    count: u32,
+}
+
+// In the space of its mesh, gathered from the sections of the triangle
+// buffer, see triangles.wgsl and hit_triangle
+struct Triangle {
+    p0: vec3<f32>,
+    material: u32,
+    p1: vec3<f32>,
+    // TRIANGLE_HOLDOUT for instances that cut a hole to the backdrop
+    flags: u32,
+    p2: vec3<f32>,
+    // Index of the instance, for the object ID AOV
+    instance: u32,
+    n0: vec3<f32>,
+    // Semantic class of the instance, for the class ID AOV
+    class_id: u32,
+    n1: vec3<f32>,
+    n2: vec3<f32>,
+    uv0: vec2<f32>,
+    uv1: vec2<f32>,
+    uv2: vec2<f32>,
+    // Vertex tangents with the bitangent sign in w, zero to use the UVs
+    t0: vec4<f32>,
+    t1: vec4<f32>,
+    t2: vec4<f32>,
+}
+
+// Placement of a mesh's bottom-level tree, see GpuInstance in tracer.rs
+struct Instance {
+    to_world: mat4x4<f32>,
+    to_local: mat4x4<f32>,
+    root: u32,
+    material: u32,
+    // TRIANGLE_HOLDOUT and INSTANCE_MIRRORED
+    flags: u32,
+    instance: u32,
+    class_id: u32,
+}
+
+// Analytic shape in its own space, see Shape in scene/primitive.rs. Flat
+// shapes lie in the XZ plane, size holds the half extents
+struct Primitive {
+    to_local: mat4x4<f32>,
+    to_world: mat4x4<f32>,
+    size: vec3<f32>,
+    kind: u32,
+    material: u32,
+    // Object ID, counting on from the instances
+    instance: u32,
+    class_id: u32,
+    // SDF_* operation of a distance field
+    sdf_op: u32,
+    // SDF_* shapes it combines, or the index of its WGSL function
+    sdf_shapes: vec2<u32>,
+    smoothness: f32,
+    sdf_params: array<vec4<f32>, 2>,
+    // Where the second shape is
+    sdf_offset: vec3<f32>,
+}
+
+struct Material {
+    base_color: vec4<f32>,
+    emission: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    transmission: f32,
+    ior: f32,
+    flags: u32,
+    anisotropy: f32,
+    anisotropy_rotation: f32,
+    clearcoat: f32,
+    clearcoat_roughness: f32,
+    sheen_color: vec3<f32>,
+    sheen_roughness: f32,
+    flake_coverage: f32,
+    flake_size: f32,
+    flake_roughness: f32,
+    // Cauchy a and b in the first two, or Sellmeier b and c
+    dispersion: u32,
+    dispersion_b: vec3<f32>,
+    // Blackbody emission in kelvin, zero for RGB
+    temperature: f32,
+    dispersion_c: vec3<f32>,
+    // One past the index of the reflectance table, zero for Schlick
+    fresnel_table: u32,
+    // Scale of planck giving the emission per wavelength
+    emission_scale: f32,
+    // Texture references, see sample_texture
+    base_color_texture: u32,
+    metallic_roughness_texture: u32,
+    emission_texture: u32,
+    normal_texture: u32,
+    normal_scale: f32,
+    // Height map, white is bump_scale world units above black
+    bump_texture: u32,
+    bump_scale: f32,
+    clearcoat_normal_texture: u32,
+    clearcoat_normal_scale: f32,
+    // Subsurface coefficients averaged over each channel for RGB paths,
+    // and their spectra for paths with a wavelength, see subsurface.wgsl
+    subsurface_absorption: vec3<f32>,
+    subsurface_anisotropy: f32,
+    subsurface_scattering: vec3<f32>,
+    absorption_spectrum: array<vec4<f32>, 4>,
+    scattering_spectrum: array<vec4<f32>, 4>,
+}
+
+// Volume of participating media, see GpuVolume in tracer.rs. The grid is
+// the word its NanoVDB tree starts at in volume_data
+struct Volume {
+    to_index: mat4x4<f32>,
+    min: vec3<f32>,
+    grid: u32,
+    max: vec3<f32>,
+    majorant: f32,
+    absorption: vec3<f32>,
+    anisotropy: f32,
+    scattering: vec3<f32>,
+}
+
+// Directional lights only use direction and emission. Area lights emit
+// towards direction from a quad spanning position +- axis_u +- axis_v, or
+// from the ellipse with those axes.
+struct Light {
+    position: vec3<f32>,
+    kind: u32,
+    axis_u: vec3<f32>,
+    cos_inner: f32,
+    axis_v: vec3<f32>,
+    cos_outer: f32,
+    direction: vec3<f32>,
+    area: f32,
+    emission: vec3<f32>,
+    temperature: f32,
+    emission_scale: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var prev_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var next_texture: texture_storage_2d<rgba32float, write>;
+// Average albedo and shading normal of the first hit, guides for denoising.
+// The albedo's alpha is the second moment of the sample luminance divided
+// by the albedo, and the normal's w the distance to the hit, zero for none.
+@group(0) @binding(3)
+var prev_albedo: texture_2d<f32>;
+@group(0) @binding(4)
+var next_albedo: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(5)
+var prev_normal: texture_2d<f32>;
+@group(0) @binding(6)
+var next_normal: texture_storage_2d<rgba32float, write>;
+// Instance of the latest sample's first hit plus one in red and its class in
+// green, zero for none
+@group(0) @binding(7)
+var next_object: texture_storage_2d<rgba32float, write>;
+
+@group(1) @binding(0)
+var<storage, read> nodes: array<BvhNode>;
+@group(1) @binding(1)
+var<storage, read> triangles: array<vec4<f32>>;
+@group(1) @binding(2)
+var<storage, read> materials: array<Material>;
+@group(1) @binding(3)
+var environment_texture: texture_2d<f32>;
+@group(1) @binding(4)
+var environment_sampler: sampler;
+// Directional albedo of sheen, over cos theta across and roughness down
+@group(1) @binding(5)
+var sheen_table: texture_2d<f32>;
+@group(1) @binding(6)
+var<storage, read> lights: array<Light>;
+// Spectrally integrated RGB reflectance over cos theta, see spectral.rs
+@group(1) @binding(7)
+var<storage, read> fresnel_tables: array<vec4<f32>>;
+// Tiled over the screen, see blue_noise.rs
+@group(1) @binding(8)
+var blue_noise: texture_2d<f32>;
+// Plate stretched over the screen behind the scene, never lighting it
+@group(1) @binding(9)
+var backdrop_texture: texture_2d<f32>;
+@group(1) @binding(10)
+var backdrop_sampler: sampler;
+// Fixed point PMJ02 points in rows, see sampler.rs
+@group(1) @binding(11)
+var pmj02_points: texture_2d<u32>;
+@group(1) @binding(12)
+var<storage, read> primitives: array<Primitive>;
+// The volumes, VOLUME_WORDS each, followed by their NanoVDB grids one after
+// another, see nanovdb.rs
+@group(1) @binding(13)
+var<storage, read> volume_data: array<u32>;
+// Indexed by the top-level leaves of nodes, see bvh/instanced.rs
+@group(1) @binding(14)
+var<storage, read> instances: array<Instance>;
+
+// Material textures by size class, see texture.rs
+@group(2) @binding(0)
+var textures_256: texture_2d_array<f32>;
+@group(2) @binding(1)
+var textures_512: texture_2d_array<f32>;
+@group(2) @binding(2)
+var textures_1024: texture_2d_array<f32>;
+@group(2) @binding(3)
+var textures_2048: texture_2d_array<f32>;
+@group(2) @binding(4)
+var texture_sampler: sampler;
+
+// Set by the camera ray's first hit, see `main`
+var<private> first_albedo: vec3<f32>;
+var<private> first_normal: vec3<f32>;
+var<private> first_depth: f32;
+var<private> first_object: u32;
+var<private> first_class: u32;
+// Zero when the camera ray left through a transparent background
+var<private> coverage: f32;
+// Where the camera ray's pixel falls on the backdrop
+var<private> backdrop_uv: vec2<f32>;
+
+fn pcg_hash(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+#include "sampler.wgsl"
+
+#include "triangles.wgsl"
+#include "sdf.wgsl"
+#include "intersect.wgsl"
+
+// Orthonormal basis from a unit normal, Duff et al. 2017
+fn basis(n: vec3<f32>) -> mat3x3<f32> {
+    let s = select(-1.0, 1.0, n.z >= 0.0);
+    let a = -1.0 / (s + n.z);
+    let b = n.x * n.y * a;
+    let t = vec3<f32>(1.0 + s * n.x * n.x * a, s * b, -s * n.x);
+    let bt = vec3<f32>(b, s + n.y * n.y * a, -n.y);
+    return mat3x3<f32>(t, bt, n);
+}
+
+// Concentric mapping of the unit square to the unit disk, Shirley 1997
+fn sample_disk(u: vec2<f32>) -> vec2<f32> {
+    let p = 2.0 * u - 1.0;
+    if p.x == 0.0 && p.y == 0.0 {
+        return vec2<f32>(0.0);
+    }
+    var r: f32;
+    var theta: f32;
+    if abs(p.x) > abs(p.y) {
+        r = p.x;
+        theta = 0.25 * PI * (p.y / p.x);
+    } else {
+        r = p.y;
+        theta = 0.5 * PI - 0.25 * PI * (p.x / p.y);
+    }
+    return r * vec2<f32>(cos(theta), sin(theta));
+}
+
+// Derivatives of the position along U and V, zero without usable UVs
+fn position_derivatives(tri: Triangle) -> mat2x3<f32> {
+    let e1 = tri.p1 - tri.p0;
+    let e2 = tri.p2 - tri.p0;
+    let duv1 = tri.uv1 - tri.uv0;
+    let duv2 = tri.uv2 - tri.uv0;
+    let det = duv1.x * duv2.y - duv1.y * duv2.x;
+    if abs(det) < 1e-12 {
+        return mat2x3<f32>(vec3<f32>(0.0), vec3<f32>(0.0));
+    }
+    return mat2x3<f32>((e1 * duv2.y - e2 * duv1.y) / det, (e2 * duv1.x - e1 * duv2.x) / det);
+}
+
+// Tangent at the hit orthogonal to n, with the sign of the bitangent
+// cross(n, t) along V in w. Interpolated from the vertices if the mesh has
+// tangents, otherwise from the UVs of the triangle. Zero if neither works.
+fn surface_tangent(tri: Triangle, hit: Hit, n: vec3<f32>) -> vec4<f32> {
+    let vertex = (1.0 - hit.u - hit.v) * tri.t0 + hit.u * tri.t1 + hit.v * tri.t2;
+    var t = vertex.xyz;
+    var sign = select(1.0, -1.0, vertex.w < 0.0);
+    if dot(t, t) == 0.0 {
+        let dp = position_derivatives(tri);
+        t = dp[0];
+        sign = select(1.0, -1.0, dot(cross(n, t), dp[1]) < 0.0);
+    }
+    t -= n * dot(n, t);
+    if dot(t, t) < 1e-12 {
+        return vec4<f32>(0.0);
+    }
+    return vec4<f32>(normalize(t), sign);
+}
+
+// Shading frame with x along the tangent rotated by the anisotropy
+// rotation, falling back to an arbitrary tangent without one
+fn tangent_frame(n: vec3<f32>, tangent: vec4<f32>, rotation: f32) -> mat3x3<f32> {
+    var t = tangent.xyz - n * dot(n, tangent.xyz);
+    if dot(t, t) < 1e-12 {
+        return basis(n);
+    }
+    t = normalize(t);
+    let b = cross(n, t);
+    let c = cos(rotation);
+    let s = sin(rotation);
+    return mat3x3<f32>(c * t + s * b, c * b - s * t, n);
+}
+
+fn sample_cosine_hemisphere(u: vec2<f32>) -> vec3<f32> {
+    let r = sqrt(u.x);
+    let phi = 2.0 * PI * u.y;
+    return vec3<f32>(r * cos(phi), r * sin(phi), sqrt(max(0.0, 1.0 - u.x)));
+}
+
+fn luminance(c: vec3<f32>) -> f32 {
+    return dot(c, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+fn fresnel_schlick(f0: vec3<f32>, cos_theta: f32) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(1.0 - clamp(cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Unpolarized Fresnel reflectance, eta is the incident over transmitted IOR
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let sin2_t = eta * eta * max(0.0, 1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = sqrt(1.0 - sin2_t);
+    let rs = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let rp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    return 0.5 * (rs * rs + rp * rp);
+}
+
+// Index of refraction at a wavelength in micrometers
+fn dispersion_ior(material: Material, wavelength: f32) -> f32 {
+    let l2 = wavelength * wavelength;
+    switch material.dispersion {
+        case DISPERSION_CAUCHY: {
+            return material.dispersion_b.x + material.dispersion_b.y / l2;
+        }
+        case DISPERSION_SELLMEIER: {
+            let terms = material.dispersion_b * l2 / (l2 - material.dispersion_c);
+            return sqrt(1.0 + terms.x + terms.y + terms.z);
+        }
+        default: {
+            return material.ior;
+        }
+    }
+}
+
+fn lobe(x: f32, mean: f32, below: f32, above: f32) -> f32 {
+    let t = (x - mean) / select(above, below, x < mean);
+    return exp(-0.5 * t * t);
+}
+
+// Linear sRGB of a single wavelength in micrometers, clamped to the gamut.
+// Uses the analytic CIE 1931 fit of Wyman et al. 2013, scaled so that the
+// average over the visible range is white.
+fn wavelength_rgb(wavelength: f32) -> vec3<f32> {
+    let l = wavelength * 1000.0;
+    let xyz = vec3<f32>(
+        1.056 * lobe(l, 599.8, 37.9, 31.0) + 0.362 * lobe(l, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(l, 501.1, 20.4, 26.2),
+        0.821 * lobe(l, 568.8, 46.9, 40.5) + 0.286 * lobe(l, 530.9, 16.3, 31.1),
+        1.217 * lobe(l, 437.0, 11.8, 36.0) + 0.681 * lobe(l, 459.0, 26.0, 13.8),
+    );
+    let rgb = vec3<f32>(
+        dot(vec3<f32>(3.2406, -1.5372, -0.4986), xyz),
+        dot(vec3<f32>(-0.9689, 1.8758, 0.0415), xyz),
+        dot(vec3<f32>(0.0557, -0.2040, 1.0570), xyz),
+    );
+    return max(rgb, vec3<f32>(0.0)) * vec3<f32>(2.2704, 3.4666, 3.6598);
+}
+
+// Blackbody radiance relative to the reference wavelength, see spectral.rs
+fn planck(wavelength: f32, temperature: f32) -> f32 {
+    let x = PLANCK_C2 / (wavelength * temperature);
+    let x_reference = PLANCK_C2 / (REFERENCE_WAVELENGTH * temperature);
+    return pow(REFERENCE_WAVELENGTH / wavelength, 5.0) * (exp(x_reference) - 1.0) / (exp(x) - 1.0);
+}
+
+// Emission of a path that is RGB, or has a wavelength once it is non-zero
+fn emitted(emission: vec3<f32>, temperature: f32, scale: f32, wavelength: f32) -> vec3<f32> {
+    if wavelength > 0.0 && temperature > 0.0 {
+        return vec3<f32>(scale * planck(wavelength, temperature));
+    }
+    return emission;
+}
+
+fn light_emission(light: Light, wavelength: f32) -> vec3<f32> {
+    return emitted(light.emission, light.temperature, light.emission_scale, wavelength);
+}
+
+#include "bsdf.wgsl"
+#include "volume.wgsl"
+#include "subsurface.wgsl"
+
+fn sky(dir: vec3<f32>) -> vec3<f32> {
+    let sky = params.sky;
+    if dir.y < 0.0 {
+        return sky.ground_radiance;
+    }
+    let cos_gamma = dot(dir, sky.sun_direction);
+    let gamma = acos(clamp(cos_gamma, -1.0, 1.0));
+    let horizon = 1.0 + sky.a * exp(sky.b / max(dir.y, 0.01));
+    let circumsolar = 1.0 + sky.c * exp(sky.d * gamma) + sky.e * cos_gamma * cos_gamma;
+    let yxy = sky.zenith * horizon * circumsolar;
+
+    let y = max(yxy.z, 1e-6);
+    let xyz = vec3<f32>(yxy.y * yxy.x / y, yxy.x, (1.0 - yxy.y - yxy.z) * yxy.x / y);
+    let to_rgb = mat3x3<f32>(
+        vec3<f32>(3.2406, -0.9689, 0.0557),
+        vec3<f32>(-1.5372, 1.8758, -0.2040),
+        vec3<f32>(-0.4986, 0.0415, 1.0570),
+    );
+    return max(to_rgb * xyz, vec3<f32>(0.0));
+}
+
+// Radiance arriving from infinitely far away in direction dir, apart from
+// the sun which is handled as a light
+fn environment(dir: vec3<f32>) -> vec3<f32> {
+    if params.has_environment == 0u {
+        return params.environment_intensity * sky(dir);
+    }
+    let phi = atan2(dir.x, -dir.z) - params.environment_rotation;
+    let theta = acos(clamp(dir.y, -1.0, 1.0));
+    let uv = vec2<f32>(0.5 + 0.5 * INV_PI * phi, INV_PI * theta);
+    let radiance = textureSampleLevel(environment_texture, environment_sampler, uv, 0.0).rgb;
+    return params.environment_intensity * radiance;
+}
+
+// The solar disk, sampled as a light rather than being part of the sky
+fn sun(dir: vec3<f32>) -> vec3<f32> {
+    if params.has_environment != 0u || dot(dir, params.sky.sun_direction) < params.sky.sun_cos_radius {
+        return vec3<f32>(0.0);
+    }
+    return params.environment_intensity * params.sky.sun_radiance;
+}
+
+fn sun_enabled() -> bool {
+    return params.has_environment == 0u && any(params.sky.sun_radiance > vec3<f32>(0.0));
+}
+
+// Uniform over the cone of the solar disk
+fn sun_pdf() -> f32 {
+    return 1.0 / (2.0 * PI * (1.0 - params.sky.sun_cos_radius));
+}
+
+struct LightSample {
+    wi: vec3<f32>,
+    distance: f32,
+    // Incident radiance over pdf
+    weight: vec3<f32>,
+    // Solid angle density, zero for lights that can't be hit by chance
+    pdf: f32,
+}
+
+fn sample_sun() -> LightSample {
+    let u = rand2();
+    let cos_theta = mix(1.0, params.sky.sun_cos_radius, u.x);
+    let sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+    let phi = 2.0 * PI * u.y;
+    let local = vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+    let wi = basis(params.sky.sun_direction) * local;
+    let radiance = params.environment_intensity * params.sky.sun_radiance;
+    return LightSample(wi, T_MAX, radiance / sun_pdf(), sun_pdf());
+}
+
+fn sample_light(light: Light, p: vec3<f32>, wavelength: f32) -> LightSample {
+    let emission = light_emission(light, wavelength);
+    switch light.kind {
+        case LIGHT_POINT, LIGHT_SPOT: {
+            let d = light.position - p;
+            let distance = length(d);
+            let wi = d / distance;
+            var weight = emission / (distance * distance);
+            if light.kind == LIGHT_SPOT {
+                weight *= smoothstep(light.cos_outer, light.cos_inner, dot(-wi, light.direction));
+            }
+            return LightSample(wi, distance, weight, 0.0);
+        }
+        case LIGHT_QUAD, LIGHT_DISK: {
+            var offset = sample_disk(rand2());
+            if light.kind == LIGHT_QUAD {
+                offset = 2.0 * rand2() - 1.0;
+            }
+            let d = light.position + offset.x * light.axis_u + offset.y * light.axis_v - p;
+            let distance = length(d);
+            let wi = d / distance;
+            let cos_light = -dot(wi, light.direction);
+            if cos_light <= 0.0 {
+                return LightSample(wi, distance, vec3<f32>(0.0), 0.0);
+            }
+            let pdf = distance * distance / (light.area * cos_light);
+            return LightSample(wi, distance, emission / pdf, pdf);
+        }
+        default: {
+            return LightSample(-light.direction, T_MAX, emission, 0.0);
+        }
+    }
+}
+
+// Distance to the front face of an area light, T_MAX if the ray misses it
+fn intersect_light(light: Light, ray: Ray) -> f32 {
+    let cos_light = -dot(ray.dir, light.direction);
+    if (light.kind != LIGHT_QUAD && light.kind != LIGHT_DISK) || cos_light <= 0.0 {
+        return T_MAX;
+    }
+    let t = dot(ray.origin - light.position, light.direction) / cos_light;
+    if t <= 0.0 {
+        return T_MAX;
+    }
+    let d = ray.origin + t * ray.dir - light.position;
+    let s = dot(d, light.axis_u) / dot(light.axis_u, light.axis_u);
+    let r = dot(d, light.axis_v) / dot(light.axis_v, light.axis_v);
+    if light.kind == LIGHT_QUAD && max(abs(s), abs(r)) > 1.0 {
+        return T_MAX;
+    }
+    if light.kind == LIGHT_DISK && s * s + r * r > 1.0 {
+        return T_MAX;
+    }
+    return t;
+}
+
+fn power_heuristic(pdf: f32, other: f32) -> f32 {
+    let a = pdf * pdf;
+    return a / (a + other * other);
+}
+
+// Lights and the sun that direct light sampling picks from uniformly
+fn sampled_light_count() -> u32 {
+    return params.light_count + u32(sun_enabled());
+}
+
+// Picks one of the lights direct light sampling covers uniformly and
+// samples it, with the pick folded into the weight and density
+fn sample_lights(position: vec3<f32>, wavelength: f32) -> LightSample {
+    let count = sampled_light_count();
+    let index = min(u32(rand() * f32(count)), count - 1u);
+    var light: LightSample;
+    if index == params.light_count {
+        light = sample_sun();
+    } else {
+        light = sample_light(lights[index], position, wavelength);
+    }
+    light.weight *= f32(count);
+    light.pdf /= f32(count);
+    return light;
+}
+
+// What of a light sample reaches origin: nothing past a surface, otherwise
+// what the media let through
+fn visibility(origin: vec3<f32>, light: LightSample) -> vec3<f32> {
+    let t_max = light.distance * (1.0 - 1e-3);
+    let ray = Ray(origin, light.wi);
+    if trace(ray, t_max).t < t_max {
+        return vec3<f32>(0.0);
+    }
+    if params.volume_count == 0u {
+        return vec3<f32>(1.0);
+    }
+    return transmittance(ray, t_max);
+}
+
+// Next event estimation: samples one light and casts a shadow ray towards
+// it, weighted against sample_material finding the same light
+fn direct_light(
+    material: Material,
+    surface: Surface,
+    position: vec3<f32>,
+    wo: vec3<f32>,
+    wavelength: f32,
+) -> vec3<f32> {
+    if sampled_light_count() == 0u {
+        return vec3<f32>(0.0);
+    }
+    let light = sample_lights(position, wavelength);
+    if all(light.weight == vec3<f32>(0.0)) {
+        return vec3<f32>(0.0);
+    }
+    let eval = eval_material(material, surface, wo, light.wi);
+    if all(eval.rgb == vec3<f32>(0.0)) {
+        return vec3<f32>(0.0);
+    }
+
+    let shadow = visibility(position + surface.ng * EPSILON, light);
+    var mis = 1.0;
+    if light.pdf > 0.0 {
+        mis = power_heuristic(light.pdf, eval.w);
+    }
+    return light.weight * eval.rgb * mis * shadow;
+}
+
+// Next event estimation from a point in a medium, weighted against
+// sample_hg finding the same light
+fn medium_direct_light(position: vec3<f32>, wo: vec3<f32>, g: f32, wavelength: f32) -> vec3<f32> {
+    if sampled_light_count() == 0u {
+        return vec3<f32>(0.0);
+    }
+    let light = sample_lights(position, wavelength);
+    if all(light.weight == vec3<f32>(0.0)) {
+        return vec3<f32>(0.0);
+    }
+    let phase = phase_hg(dot(wo, light.wi), g);
+    var mis = 1.0;
+    if light.pdf > 0.0 {
+        mis = power_heuristic(light.pdf, phase);
+    }
+    return light.weight * phase * mis * visibility(position, light);
+}
+
+// Next event estimation from where a random walk leaves a surface, like
+// from a Lambertian one, weighted against cosine sampling finding the same
+// light
+fn exit_direct_light(exit: SubsurfaceExit, wavelength: f32) -> vec3<f32> {
+    if sampled_light_count() == 0u {
+        return vec3<f32>(0.0);
+    }
+    let light = sample_lights(exit.position, wavelength);
+    let cos_i = dot(exit.n, light.wi);
+    if all(light.weight == vec3<f32>(0.0)) || cos_i <= 0.0 || dot(exit.ng, light.wi) <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+    let pdf = cos_i * INV_PI;
+    var mis = 1.0;
+    if light.pdf > 0.0 {
+        mis = power_heuristic(light.pdf, pdf);
+    }
+    return light.weight * pdf * mis * visibility(exit.position + exit.ng * EPSILON, light);
+}
+
+// Returns a ray with a zero direction for pixels outside the projection
+// Brown-Conrady distortion of a point on the image plane one focal length
+// in front of the lens, with y down
+fn distort(camera: Camera, p: vec2<f32>) -> vec2<f32> {
+    let r2 = dot(p, p);
+    let k = camera.distortion_k;
+    let radial = 1.0 + r2 * (k.x + r2 * (k.y + r2 * k.z));
+    let t = camera.distortion_p;
+    let tangential = vec2<f32>(
+        2.0 * t.x * p.x * p.y + t.y * (r2 + 2.0 * p.x * p.x),
+        t.x * (r2 + 2.0 * p.y * p.y) + 2.0 * t.y * p.x * p.y,
+    );
+    return p * radial + tangential;
+}
+
+// Converges for the mild distortion of real lenses
+fn undistort(camera: Camera, p: vec2<f32>) -> vec2<f32> {
+    var u = p;
+    for (var i = 0; i < UNDISTORT_ITERATIONS; i++) {
+        u += p - distort(camera, u);
+    }
+    return u;
+}
+
+// Where the backdrop is sampled for the pixel at `uv`, undistorted to
+// match the render when the distortion is removed
+fn backdrop_position(uv: vec2<f32>, size: vec2<f32>) -> vec2<f32> {
+    let camera = params.camera;
+    if camera.distortion != DISTORTION_REMOVE || camera.projection != PROJECTION_PERSPECTIVE {
+        return uv;
+    }
+    let scale = vec2<f32>(size.x / size.y, 1.0) * camera.tan_half_fov;
+    let p = (uv * 2.0 - 1.0) * scale;
+    return (distort(camera, p) / scale + 1.0) * 0.5;
+}
+
+fn camera_ray(pixel: vec2<f32>, size: vec2<f32>) -> Ray {
+    let camera = params.camera;
+    let ndc = (pixel / size) * 2.0 - 1.0;
+    let aspect = size.x / size.y;
+    switch camera.projection {
+        case PROJECTION_ORTHOGRAPHIC: {
+            let half_height = camera.focus_distance * camera.tan_half_fov;
+            let offset = ndc.x * aspect * camera.right - ndc.y * camera.up;
+            return Ray(camera.position + half_height * offset, camera.forward);
+        }
+        case PROJECTION_FISHEYE: {
+            let p = vec2<f32>(ndc.x * aspect, -ndc.y);
+            let r = length(p);
+            if r > 1.0 {
+                return Ray(camera.position, vec3<f32>(0.0));
+            }
+            let theta = 0.5 * PI * r;
+            let side = p / max(r, 1e-8);
+            let dir = cos(theta) * camera.forward
+                + sin(theta) * (side.x * camera.right + side.y * camera.up);
+            return Ray(camera.position, normalize(dir));
+        }
+        case PROJECTION_EQUIRECTANGULAR: {
+            let phi = (pixel.x / size.x - 0.5) * 2.0 * PI;
+            let theta = (0.5 - pixel.y / size.y) * PI;
+            let horizontal = sin(phi) * camera.right + cos(phi) * camera.forward;
+            let dir = cos(theta) * horizontal + sin(theta) * camera.up;
+            return Ray(camera.position, normalize(dir));
+        }
+        default: {}
+    }
+
+    var p = vec2<f32>(ndc.x * aspect, ndc.y) * camera.tan_half_fov;
+    if camera.distortion == DISTORTION_APPLY {
+        p = undistort(camera, p);
+    }
+    let dir = camera.forward + p.x * camera.right - p.y * camera.up;
+    if camera.aperture_radius <= 0.0 {
+        return Ray(camera.position, normalize(dir));
+    }
+
+    // Thin lens: start on the aperture and aim at the point on the focal plane
+    let focus_point = camera.position + dir * camera.focus_distance;
+    let lens = camera.aperture_radius * sample_disk(rand2());
+    let origin = camera.position + lens.x * camera.right + lens.y * camera.up;
+    return Ray(origin, normalize(focus_point - origin));
+}
+
+fn decode_srgb(c: vec3<f32>) -> vec3<f32> {
+    return select(pow((c + 0.055) / 1.055, vec3<f32>(2.4)), c / 12.92, c <= vec3<f32>(0.04045));
+}
+
+// Side length of the array holding a texture. A reference is one plus the
+// layer shifted past two bits of size class.
+fn texture_size(reference: u32) -> f32 {
+    switch (reference - 1u) & 3u {
+        case 0u: {
+            return f32(textureDimensions(textures_256).x);
+        }
+        case 1u: {
+            return f32(textureDimensions(textures_512).x);
+        }
+        case 2u: {
+            return f32(textureDimensions(textures_1024).x);
+        }
+        default: {
+            return f32(textureDimensions(textures_2048).x);
+        }
+    }
+}
+
+// The mip level covers the footprint, its width in texture coordinates
+fn sample_texture(reference: u32, uv: vec2<f32>, footprint: f32) -> vec4<f32> {
+    let layer = (reference - 1u) >> 2u;
+    let lod = log2(max(footprint * texture_size(reference), 1e-8));
+    switch (reference - 1u) & 3u {
+        case 0u: {
+            return textureSampleLevel(textures_256, texture_sampler, uv, layer, lod);
+        }
+        case 1u: {
+            return textureSampleLevel(textures_512, texture_sampler, uv, layer, lod);
+        }
+        case 2u: {
+            return textureSampleLevel(textures_1024, texture_sampler, uv, layer, lod);
+        }
+        default: {
+            return textureSampleLevel(textures_2048, texture_sampler, uv, layer, lod);
+        }
+    }
+}
+
+// The tangent plane of a primitive at a point on it, as a triangle
+// spanning one unit of UV from there, so primitives shade like meshes
+fn primitive_triangle(primitive: Primitive, position: vec3<f32>) -> Triangle {
+    let p = (primitive.to_local * vec4<f32>(position, 1.0)).xyz;
+    let size = primitive.size;
+    // The plane is mapped one UV unit per local unit
+    var n = vec3<f32>(0.0, 1.0, 0.0);
+    var uv = vec2<f32>(p.x, -p.z);
+    var dpdu = vec3<f32>(1.0, 0.0, 0.0);
+    var dpdv = vec3<f32>(0.0, 0.0, -1.0);
+    switch primitive.kind {
+        case PRIMITIVE_SPHERE: {
+            n = normalize(p);
+            let phi = atan2(p.z, p.x);
+            let r = length(p.xz);
+            uv = vec2<f32>(0.5 + 0.5 * INV_PI * phi, INV_PI * acos(clamp(n.y, -1.0, 1.0)));
+            dpdu = 2.0 * PI * vec3<f32>(-p.z, 0.0, p.x);
+            dpdv = vec3<f32>(0.0);
+            if r > 0.0 {
+                dpdv = PI * vec3<f32>(p.y * p.x / r, -r, p.y * p.z / r);
+            }
+        }
+        case PRIMITIVE_BOX: {
+            // The face is along the axis the point is furthest out on
+            let q = p / size;
+            let a = abs(q);
+            if a.x >= a.y && a.x >= a.z {
+                n = vec3<f32>(sign(q.x), 0.0, 0.0);
+                uv = 0.5 + 0.5 * q.zy;
+                dpdu = vec3<f32>(0.0, 0.0, 2.0 * size.z);
+                dpdv = vec3<f32>(0.0, 2.0 * size.y, 0.0);
+            } else if a.y >= a.z {
+                n = vec3<f32>(0.0, sign(q.y), 0.0);
+                uv = 0.5 + 0.5 * q.xz;
+                dpdu = vec3<f32>(2.0 * size.x, 0.0, 0.0);
+                dpdv = vec3<f32>(0.0, 0.0, 2.0 * size.z);
+            } else {
+                n = vec3<f32>(0.0, 0.0, sign(q.z));
+                uv = 0.5 + 0.5 * q.xy;
+                dpdu = vec3<f32>(2.0 * size.x, 0.0, 0.0);
+                dpdv = vec3<f32>(0.0, 2.0 * size.y, 0.0);
+            }
+        }
+        case PRIMITIVE_QUAD, PRIMITIVE_DISK: {
+            uv = 0.5 + 0.5 * vec2<f32>(p.x / size.x, -p.z / size.z);
+            dpdu = vec3<f32>(2.0 * size.x, 0.0, 0.0);
+            dpdv = vec3<f32>(0.0, 0.0, -2.0 * size.z);
+        }
+        case PRIMITIVE_SDF: {
+            // Mapped like the plane, spanned along the surface
+            n = sdf_normal(primitive, p);
+            let frame = basis(n);
+            dpdu = frame[0];
+            dpdv = frame[1];
+        }
+        default: {}
+    }
+    let to_local = mat3x3<f32>(primitive.to_local[0].xyz, primitive.to_local[1].xyz, primitive.to_local[2].xyz);
+    n = normalize(transpose(to_local) * n);
+    var e1 = (primitive.to_world * vec4<f32>(dpdu, 0.0)).xyz;
+    var e2 = (primitive.to_world * vec4<f32>(dpdv, 0.0)).xyz;
+    var uv1 = uv + vec2<f32>(1.0, 0.0);
+    var uv2 = uv + vec2<f32>(0.0, 1.0);
+    // The UVs pinch at the poles of spheres
+    if length(cross(e1, e2)) < 1e-12 {
+        let frame = basis(n);
+        e1 = frame[0];
+        e2 = frame[1];
+    }
+    // Wind the triangle around the outward normal, as the UVs and
+    // mirroring transforms may not
+    if dot(cross(e1, e2), n) < 0.0 {
+        let e = e1;
+        e1 = e2;
+        e2 = e;
+        let uv0 = uv1;
+        uv1 = uv2;
+        uv2 = uv0;
+    }
+    return Triangle(
+        position,
+        primitive.material,
+        position + e1,
+        0u,
+        position + e2,
+        primitive.instance,
+        n,
+        primitive.class_id,
+        n,
+        n,
+        uv,
+        uv1,
+        uv2,
+        vec4<f32>(0.0),
+        vec4<f32>(0.0),
+        vec4<f32>(0.0),
+    );
+}
+
+// The triangle hit moved into world space with what its instance adds, or
+// the tangent plane of the primitive hit
+fn hit_triangle(ray: Ray, hit: Hit) -> Triangle {
+    if (hit.triangle & PRIMITIVE_HIT) != 0u {
+        let primitive = primitives[hit.triangle & ~PRIMITIVE_HIT];
+        return primitive_triangle(primitive, ray.origin + hit.t * ray.dir);
+    }
+    let instance = instances[hit.instance];
+    let local = load_triangle(hit.triangle);
+    let m = instance.to_world;
+    let normal_matrix = transpose(mat3x3<f32>(instance.to_local[0].xyz, instance.to_local[1].xyz, instance.to_local[2].xyz));
+    // Mirroring flips the bitangent, and the winding to keep the geometric
+    // normal pointing out, which trace already swapped u and v for
+    let mirrored = (instance.flags & INSTANCE_MIRRORED) != 0u;
+    let handedness = select(1.0, -1.0, mirrored);
+    var tri = local;
+    tri.p0 = (m * vec4<f32>(local.p0, 1.0)).xyz;
+    tri.p1 = (m * vec4<f32>(select(local.p1, local.p2, mirrored), 1.0)).xyz;
+    tri.p2 = (m * vec4<f32>(select(local.p2, local.p1, mirrored), 1.0)).xyz;
+    tri.n0 = normalize(normal_matrix * local.n0);
+    tri.n1 = normalize(normal_matrix * select(local.n1, local.n2, mirrored));
+    tri.n2 = normalize(normal_matrix * select(local.n2, local.n1, mirrored));
+    tri.uv1 = select(local.uv1, local.uv2, mirrored);
+    tri.uv2 = select(local.uv2, local.uv1, mirrored);
+    tri.t0 = instance_tangent(m, local.t0, handedness);
+    tri.t1 = instance_tangent(m, select(local.t1, local.t2, mirrored), handedness);
+    tri.t2 = instance_tangent(m, select(local.t2, local.t1, mirrored), handedness);
+    tri.material = instance.material;
+    tri.flags = instance.flags;
+    tri.instance = instance.instance;
+    tri.class_id = instance.class_id;
+    return tri;
+}
+
+fn load_triangle(i: u32) -> Triangle {
+    let p = triangle_section(TRIANGLE_POSITIONS) + 3u * i;
+    let n = triangle_section(TRIANGLE_NORMALS) + 3u * i;
+    let uv = triangle_section(TRIANGLE_UVS) + 2u * i;
+    let t = triangle_section(TRIANGLE_TANGENTS) + 3u * i;
+    return Triangle(
+        triangles[p].xyz,
+        0u,
+        triangles[p + 1u].xyz,
+        0u,
+        triangles[p + 2u].xyz,
+        0u,
+        triangles[n].xyz,
+        0u,
+        triangles[n + 1u].xyz,
+        triangles[n + 2u].xyz,
+        triangles[uv].xy,
+        triangles[uv].zw,
+        triangles[uv + 1u].xy,
+        triangles[t],
+        triangles[t + 1u],
+        triangles[t + 2u],
+    );
+}
+
+// A vertex tangent of a mesh in world space, staying zero without one
+fn instance_tangent(to_world: mat4x4<f32>, t: vec4<f32>, handedness: f32) -> vec4<f32> {
+    if all(t == vec4<f32>(0.0)) {
+        return t;
+    }
+    return vec4<f32>(normalize((to_world * vec4<f32>(t.xyz, 0.0)).xyz), t.w * handedness);
+}
+
+fn hit_uv(tri: Triangle, hit: Hit) -> vec2<f32> {
+    return (1.0 - hit.u - hit.v) * tri.uv0 + hit.u * tri.uv1 + hit.v * tri.uv2;
+}
+
+// Width in texture coordinates of a ray cone hitting the triangle, after
+// Akenine-Möller et al. 2019, "Texture Level of Detail Strategies for
+// Real-Time Ray Tracing"
+fn texture_footprint(tri: Triangle, dir: vec3<f32>, cone_width: f32) -> f32 {
+    let e1 = tri.uv1 - tri.uv0;
+    let e2 = tri.uv2 - tri.uv0;
+    let uv_area = abs(e1.x * e2.y - e2.x * e1.y);
+    let normal = cross(tri.p1 - tri.p0, tri.p2 - tri.p0);
+    let area = max(length(normal), 1e-12);
+    // Grazing angles stretch the footprint along the view direction
+    let cos_theta = max(abs(dot(normal / area, dir)), 0.1);
+    return cone_width * sqrt(uv_area / area) / cos_theta;
+}
+
+// Scales the material factors by its textures
+fn textured(material: Material, uv: vec2<f32>, footprint: f32) -> Material {
+    var out = material;
+    if material.base_color_texture != 0u {
+        let texel = sample_texture(material.base_color_texture, uv, footprint);
+        out.base_color *= vec4<f32>(decode_srgb(texel.rgb), texel.a);
+    }
+    if material.metallic_roughness_texture != 0u {
+        let texel = sample_texture(material.metallic_roughness_texture, uv, footprint);
+        out.roughness *= texel.g;
+        out.metallic *= texel.b;
+    }
+    if material.emission_texture != 0u {
+        let texel = decode_srgb(sample_texture(material.emission_texture, uv, footprint).rgb);
+        out.emission *= texel;
+        out.emission_scale *= luminance(texel);
+    }
+    return out;
+}
+
+// Tilts n by a tangent space normal map with +Y along the bitangent, its
+// XY scaled by scale as in glTF
+fn normal_mapped(
+    reference: u32,
+    scale: f32,
+    n: vec3<f32>,
+    tangent: vec4<f32>,
+    uv: vec2<f32>,
+    footprint: f32,
+) -> vec3<f32> {
+    let t = tangent.xyz - n * dot(n, tangent.xyz);
+    if reference == 0u || dot(t, t) < 1e-12 {
+        return n;
+    }
+    let m = (2.0 * sample_texture(reference, uv, footprint).xyz - 1.0) * vec3<f32>(scale, scale, 1.0);
+    let b = cross(n, normalize(t)) * tangent.w;
+    return normalize(m.x * normalize(t) + m.y * b + m.z * n);
+}
+
+// Tilts n by the gradient of the height map, using the surface gradient of
+// Mikkelsen 2010, "Bump Mapping Unparametrized Surfaces on the GPU", with
+// the height derivatives taken by finite differences over the footprint
+fn bumped(material: Material, tri: Triangle, n: vec3<f32>, uv: vec2<f32>, footprint: f32) -> vec3<f32> {
+    let dp = position_derivatives(tri);
+    let r1 = cross(dp[1], n);
+    let r2 = cross(n, dp[0]);
+    let det = dot(dp[0], r1);
+    if material.bump_texture == 0u || abs(det) < 1e-12 {
+        return n;
+    }
+    let reference = material.bump_texture;
+    let step = max(footprint, 1.0 / texture_size(reference));
+    let h = sample_texture(reference, uv, footprint).r;
+    let dh_du = sample_texture(reference, uv + vec2<f32>(step, 0.0), footprint).r - h;
+    let dh_dv = sample_texture(reference, uv + vec2<f32>(0.0, step), footprint).r - h;
+    let gradient = (dh_du * r1 + dh_dv * r2) / (step * det);
+    return normalize(n - material.bump_scale * gradient);
+}
+
+// Width and spread angle of the ray cone through one pixel
+fn pixel_cone(size: vec2<f32>) -> vec2<f32> {
+    let camera = params.camera;
+    switch camera.projection {
+        case PROJECTION_ORTHOGRAPHIC: {
+            return vec2<f32>(2.0 * camera.focus_distance * camera.tan_half_fov / size.y, 0.0);
+        }
+        case PROJECTION_FISHEYE, PROJECTION_EQUIRECTANGULAR: {
+            return vec2<f32>(0.0, PI / size.y);
+        }
+        default: {
+            return vec2<f32>(0.0, 2.0 * camera.tan_half_fov / size.y);
+        }
+    }
+}
+
+// What camera rays see behind the scene when it's left out of the image,
+// the backdrop or nothing with zero alpha
+fn background() -> vec3<f32> {
+    if params.transparent != 0u || params.has_backdrop == 0u {
+        coverage = 0.0;
+        return vec3<f32>(0.0);
+    }
+    return textureSampleLevel(backdrop_texture, backdrop_sampler, backdrop_uv, 0.0).rgb;
+}
+
+// Whether the path goes on past depth, making up for the ones ended
+fn russian_roulette(depth: u32, throughput: ptr<function, vec3<f32>>) -> bool {
+    if depth < 3u {
+        return true;
+    }
+    let p = clamp(max_component(*throughput), 0.05, 1.0);
+    if rand() > p {
+        return false;
+    }
+    *throughput /= p;
+    return true;
+}
+
+// The cone holds the width of the pixel footprint at the camera and its
+// spread angle, which is kept at every bounce
+fn radiance(primary: Ray, cone: vec2<f32>) -> vec3<f32> {
+    var ray = primary;
+    var throughput = vec3<f32>(1.0);
+    var color = vec3<f32>(0.0);
+    // Density of the last bounce, zero if direct light sampling didn't
+    // cover it, so lights it finds are counted in full
+    var pdf = 0.0;
+    let light_count = f32(sampled_light_count());
+    // Paths stay RGB until they reach a dispersive material, then carry
+    // the single wavelength in micrometers picked there
+    var wavelength = 0.0;
+    var cone_width = cone.x;
+
+    for (var depth = 0u; depth < MAX_DEPTH; depth++) {
+        let hit = trace(ray, T_MAX);
+
+        // Area lights aren't part of the BVH
+        var light_t = hit.t;
+        var light_index = params.light_count;
+        for (var i = 0u; i < params.light_count; i++) {
+            let t = intersect_light(lights[i], ray);
+            if t < light_t {
+                light_t = t;
+                light_index = i;
+            }
+        }
+
+        // The media in front of whatever the ray hits
+        if params.volume_count > 0u {
+            let event = track_media(ray, light_t, &throughput);
+            if event.kind == MEDIUM_ABSORBED {
+                break;
+            }
+            if event.kind == MEDIUM_SCATTERED {
+                let wo = -ray.dir;
+                let position = ray.origin + event.t * ray.dir;
+                if depth == 0u {
+                    first_albedo = event.albedo;
+                    first_normal = wo;
+                    first_depth = event.t;
+                }
+                if LIGHT_SAMPLING {
+                    color += throughput * medium_direct_light(position, wo, event.anisotropy, wavelength);
+                }
+                let wi = sample_hg(wo, event.anisotropy, rand2());
+                pdf = select(0.0, phase_hg(dot(wo, wi), event.anisotropy), LIGHT_SAMPLING);
+                if !russian_roulette(depth, &throughput) {
+                    break;
+                }
+                ray = Ray(position, wi);
+                continue;
+            }
+        }
+        if light_index < params.light_count {
+            let light = lights[light_index];
+            var mis = 1.0;
+            if pdf > 0.0 {
+                let cos_light = -dot(ray.dir, light.direction);
+                let light_pdf = light_t * light_t / (light.area * cos_light);
+                mis = power_heuristic(pdf, light_pdf / light_count);
+            }
+            color += throughput * light_emission(light, wavelength) * mis;
+            break;
+        }
+
+        if hit.t == T_MAX {
+            if depth == 0u && (params.transparent != 0u || params.has_backdrop != 0u) {
+                color = background();
+                break;
+            }
+            var sun_mis = 1.0;
+            if pdf > 0.0 && sun_enabled() {
+                sun_mis = power_heuristic(pdf, sun_pdf() / light_count);
+            }
+            color += throughput * (environment(ray.dir) + sun_mis * sun(ray.dir));
+            break;
+        }
+
+        let tri = hit_triangle(ray, hit);
+        // Holdouts only hide from the camera, other rays see them as usual
+        if depth == 0u && (tri.flags & TRIANGLE_HOLDOUT) != 0u {
+            color = background();
+            break;
+        }
+        cone_width += cone.y * hit.t;
+        let uv = hit_uv(tri, hit);
+        let footprint = texture_footprint(tri, ray.dir, cone_width);
+        var material = textured(materials[tri.material], uv, footprint);
+        let emission = emitted(
+            material.emission,
+            material.temperature,
+            material.emission_scale,
+            wavelength,
+        );
+        color += throughput * emission;
+        if SPECTRAL && material.dispersion != 0u && material.transmission > 0.0 {
+            if wavelength == 0.0 {
+                wavelength = mix(MIN_WAVELENGTH, MAX_WAVELENGTH, rand());
+                throughput *= wavelength_rgb(wavelength);
+            }
+            material.ior = dispersion_ior(material, wavelength);
+        }
+
+        let w = 1.0 - hit.u - hit.v;
+        var ng = normalize(cross(tri.p1 - tri.p0, tri.p2 - tri.p0));
+        var n = normalize(w * tri.n0 + hit.u * tri.n1 + hit.v * tri.n2);
+        let entering = dot(ng, ray.dir) < 0.0;
+        if !entering {
+            ng = -ng;
+        }
+        if dot(n, ng) < 0.0 {
+            n = -n;
+        }
+        // Without its own normal map the coat follows the smooth normal
+        let tangent = surface_tangent(tri, hit, n);
+        var coat_n = normal_mapped(
+            material.clearcoat_normal_texture,
+            material.clearcoat_normal_scale,
+            n,
+            tangent,
+            uv,
+            footprint,
+        );
+        var mapped_n = bumped(material, tri, n, uv, footprint);
+        mapped_n = normal_mapped(
+            material.normal_texture,
+            material.normal_scale,
+            mapped_n,
+            tangent,
+            uv,
+            footprint,
+        );
+        // Maps can't turn the surface away from the geometric side
+        coat_n = select(n, coat_n, dot(coat_n, ng) > 0.0);
+        n = select(n, mapped_n, dot(mapped_n, ng) > 0.0);
+        if depth == 0u {
+            first_albedo = material.base_color.rgb;
+            first_normal = n;
+            first_depth = hit.t;
+            first_object = tri.instance + 1u;
+            first_class = tri.class_id;
+        }
+
+        let wo = -ray.dir;
+        let position = ray.origin + hit.t * ray.dir;
+        var base_n = n;
+        if material.flake_coverage > 0.0 {
+            let flake_n = flake_normal(material, position, n);
+            if dot(flake_n, wo) > 0.0 {
+                base_n = flake_n;
+                material.metallic = 1.0;
+                material.roughness = FLAKE_SURFACE_ROUGHNESS;
+                material.anisotropy = 0.0;
+            }
+        }
+        let surface = make_surface(material, tangent, base_n, coat_n, ng, wo, entering);
+        if LIGHT_SAMPLING {
+            color += throughput * direct_light(material, surface, position, wo, wavelength);
+        }
+
+        let bsdf = sample_material(material, surface, wo);
+        throughput *= bsdf.weight;
+        pdf = select(0.0, bsdf.pdf, LIGHT_SAMPLING);
+        let side = select(1.0, -1.0, dot(bsdf.wi, n) < 0.0);
+        if all(throughput == vec3<f32>(0.0)) || side * dot(bsdf.wi, ng) <= 0.0 {
+            break;
+        }
+
+        if !russian_roulette(depth, &throughput) {
+            break;
+        }
+
+        if (material.flags & MATERIAL_SUBSURFACE) != 0u && side < 0.0 {
+            if SPECTRAL && wavelength == 0.0 {
+                wavelength = mix(MIN_WAVELENGTH, MAX_WAVELENGTH, rand());
+                throughput *= wavelength_rgb(wavelength);
+            }
+            let start = Ray(position - ng * EPSILON, bsdf.wi);
+            let exit = subsurface_walk(material, start, wavelength, &throughput);
+            if !exit.found {
+                break;
+            }
+            if LIGHT_SAMPLING {
+                color += throughput * exit_direct_light(exit, wavelength);
+            }
+            let wi = basis(exit.n) * sample_cosine_hemisphere(rand2());
+            if dot(wi, exit.ng) <= 0.0 {
+                break;
+            }
+            pdf = select(0.0, dot(wi, exit.n) * INV_PI, LIGHT_SAMPLING);
+            ray = Ray(exit.position + exit.ng * EPSILON, wi);
+            continue;
+        }
+
+        ray = Ray(position + side * ng * EPSILON, bsdf.wi);
+    }
+    return color;
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let id = global_id + vec3<u32>(0u, params.row_offset, 0u);
+    let size = textureDimensions(next_texture);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    init_sampler(id.xy, size.x, params.seed);
+
+    let pixel = vec2<f32>(id.xy) + rand2();
+    let ray = camera_ray(pixel, vec2<f32>(size));
+    backdrop_uv = backdrop_position(pixel / vec2<f32>(size), vec2<f32>(size));
+    var sample = vec3<f32>(0.0);
+    first_albedo = vec3<f32>(0.0);
+    first_normal = vec3<f32>(0.0);
+    first_depth = 0.0;
+    first_object = 0u;
+    first_class = 0u;
+    coverage = select(1.0, 0.0, params.transparent != 0u);
+    if any(ray.dir != vec3<f32>(0.0)) {
+        coverage = 1.0;
+        sample = radiance(ray, pixel_cone(vec2<f32>(size)));
+    }
+    // Lights and the background are their own albedo
+    if all(first_normal == vec3<f32>(0.0)) {
+        first_albedo = saturate(sample);
+    }
+
+    let illumination = luminance(sample / max(first_albedo, vec3<f32>(ALBEDO_EPSILON)));
+    var color = vec4<f32>(sample, coverage);
+    var albedo = vec4<f32>(first_albedo, illumination * illumination);
+    var normal = vec4<f32>(first_normal, first_depth);
+    let invalid = any(sample != sample) || any(abs(sample) > vec3<f32>(T_MAX));
+    if params.reproject != 0u {
+        // The history can't stand in for a pixel that moved
+        if invalid {
+            color = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+            albedo.a = 0.0;
+        }
+    } else if params.frame > 0u {
+        let prev = textureLoad(prev_texture, id.xy, 0);
+        let prev_guide = textureLoad(prev_albedo, id.xy, 0);
+        let weight = 1.0 / f32(params.frame + 1u);
+        if invalid {
+            color = prev;
+            albedo.a = prev_guide.a;
+        } else {
+            color = mix(prev, color, weight);
+        }
+        albedo = mix(prev_guide, albedo, weight);
+        normal = mix(textureLoad(prev_normal, id.xy, 0), normal, weight);
+    }
+    textureStore(next_texture, id.xy, color);
+    textureStore(next_albedo, id.xy, albedo);
+    textureStore(next_normal, id.xy, normal);
+    textureStore(next_object, id.xy, vec4<f32>(f32(first_object), f32(first_class), 0.0, 1.0));
+}
@@ -0,0 +1,18 @@
+// Layout of the triangle buffer bound as `triangles` by the includer, see
+// triangle_streams in tracer.rs. It's a struct of arrays in vec4s: the
+// corners of every triangle, then their normals, UVs and tangents, so
+// traversal only pulls the corners into the cache.
+
+// Vec4s each triangle takes up across the sections
+const TRIANGLE_VEC4S: u32 = 11u;
+// Where each section starts, in vec4s per triangle before it
+const TRIANGLE_POSITIONS: u32 = 0u;
+const TRIANGLE_NORMALS: u32 = 3u;
+// The first two UVs, then the third in xy
+const TRIANGLE_UVS: u32 = 6u;
+const TRIANGLE_TANGENTS: u32 = 8u;
+
+// Index of the first vec4 of a section
+fn triangle_section(section: u32) -> u32 {
+    return section * (arrayLength(&triangles) / TRIANGLE_VEC4S);
+}
@@ -0,0 +1,279 @@
+// Participating media whose density comes from NanoVDB grids, see
+// scene/volume.rs. Lookups walk the grid's tree in volume_data the way
+// PNanoVDB.h does, without an accessor caching the path. Paths scatter
+// through the media by spectral tracking, Kutz et al. 2017, "Spectral and
+// Decomposition Tracking for Rendering Heterogeneous Volumes", against the
+// sum of the majorants of the volumes a ray passes through.
+
+// Byte offsets into the grid, see nanovdb.rs
+const NANOVDB_GRID_SIZE: u32 = 672u;
+const NANOVDB_TREE_OFF_ROOT: u32 = 24u;
+const NANOVDB_ROOT_OFF_TABLE_SIZE: u32 = 24u;
+const NANOVDB_ROOT_OFF_BACKGROUND: u32 = 28u;
+const NANOVDB_ROOT_SIZE: u32 = 64u;
+const NANOVDB_ROOT_TILE_SIZE: u32 = 32u;
+const NANOVDB_ROOT_TILE_OFF_CHILD: u32 = 8u;
+const NANOVDB_ROOT_TILE_OFF_VALUE: u32 = 20u;
+const NANOVDB_UPPER_OFF_CHILD_MASK: u32 = 4128u;
+const NANOVDB_UPPER_OFF_TABLE: u32 = 8256u;
+const NANOVDB_LOWER_OFF_CHILD_MASK: u32 = 544u;
+const NANOVDB_LOWER_OFF_TABLE: u32 = 1088u;
+const NANOVDB_LEAF_OFF_TABLE: u32 = 96u;
+
+// Collisions tracked along a ray before giving up, which absorbs the path
+const VOLUME_MAX_STEPS: u32 = 1024u;
+
+const MEDIUM_NONE: u32 = 0u;
+const MEDIUM_SCATTERED: u32 = 1u;
+const MEDIUM_ABSORBED: u32 = 2u;
+
+// Where a ray's tracking through the media stopped
+struct MediumEvent {
+    // One of the MEDIUM_* constants, MEDIUM_NONE when it got through
+    kind: u32,
+    t: f32,
+    // Single scattering albedo and phase asymmetry where it scattered
+    albedo: vec3<f32>,
+    anisotropy: f32,
+}
+
+// Absorption and scattering coefficients at a point, summed over volumes
+struct Medium {
+    absorption: vec3<f32>,
+    scattering: vec3<f32>,
+    // Asymmetry averaged over the volumes by how much they scatter
+    anisotropy: f32,
+}
+
+// Words of a volume at the start of volume_data
+const VOLUME_WORDS: u32 = 32u;
+
+fn volume_vec4(word: u32) -> vec4<f32> {
+    return bitcast<vec4<f32>>(vec4<u32>(
+        volume_data[word],
+        volume_data[word + 1u],
+        volume_data[word + 2u],
+        volume_data[word + 3u],
+    ));
+}
+
+fn load_volume(i: u32) -> Volume {
+    let w = i * VOLUME_WORDS;
+    let to_index = mat4x4<f32>(volume_vec4(w), volume_vec4(w + 4u), volume_vec4(w + 8u), volume_vec4(w + 12u));
+    let min_grid = volume_vec4(w + 16u);
+    let max_majorant = volume_vec4(w + 20u);
+    let absorption = volume_vec4(w + 24u);
+    let scattering = volume_vec4(w + 28u);
+    return Volume(
+        to_index,
+        min_grid.xyz,
+        bitcast<u32>(min_grid.w),
+        max_majorant.xyz,
+        max_majorant.w,
+        absorption.xyz,
+        absorption.w,
+        scattering.xyz,
+    );
+}
+
+// Word at a byte offset into the grid starting at word `grid`
+fn grid_word(grid: u32, offset: u32) -> u32 {
+    return volume_data[grid + offset / 4u];
+}
+
+// Whether entry n of an internal node's table is a child rather than a tile
+fn internal_child(grid: u32, node: u32, n: u32, off_child_mask: u32) -> bool {
+    return (grid_word(grid, node + off_child_mask + 4u * (n / 32u)) & (1u << (n % 32u))) != 0u;
+}
+
+fn grid_value(grid: u32, ijk: vec3<i32>) -> f32 {
+    let root = NANOVDB_GRID_SIZE + grid_word(grid, NANOVDB_GRID_SIZE + NANOVDB_TREE_OFF_ROOT);
+    let c = bitcast<vec3<u32>>(ijk);
+    // Halves of the 64 bit key of the root tile, see root_key
+    let block = c >> vec3<u32>(12u);
+    let key = vec2<u32>(block.z | (block.y << 21u), (block.y >> 11u) | (block.x << 10u));
+    let tile_count = grid_word(grid, root + NANOVDB_ROOT_OFF_TABLE_SIZE);
+    var tile = 0u;
+    for (var i = 0u; i < tile_count; i++) {
+        let offset = root + NANOVDB_ROOT_SIZE + i * NANOVDB_ROOT_TILE_SIZE;
+        if grid_word(grid, offset) == key.x && grid_word(grid, offset + 4u) == key.y {
+            tile = offset;
+            break;
+        }
+    }
+    if tile == 0u {
+        return bitcast<f32>(grid_word(grid, root + NANOVDB_ROOT_OFF_BACKGROUND));
+    }
+    let child = grid_word(grid, tile + NANOVDB_ROOT_TILE_OFF_CHILD);
+    if child == 0u {
+        return bitcast<f32>(grid_word(grid, tile + NANOVDB_ROOT_TILE_OFF_VALUE));
+    }
+
+    let upper = root + child;
+    let u = (c >> vec3<u32>(7u)) & vec3<u32>(31u);
+    let upper_n = (u.x << 10u) | (u.y << 5u) | u.z;
+    let upper_entry = upper + NANOVDB_UPPER_OFF_TABLE + 8u * upper_n;
+    if !internal_child(grid, upper, upper_n, NANOVDB_UPPER_OFF_CHILD_MASK) {
+        return bitcast<f32>(grid_word(grid, upper_entry));
+    }
+    let lower = upper + grid_word(grid, upper_entry);
+    let l = (c >> vec3<u32>(3u)) & vec3<u32>(15u);
+    let lower_n = (l.x << 8u) | (l.y << 4u) | l.z;
+    let lower_entry = lower + NANOVDB_LOWER_OFF_TABLE + 8u * lower_n;
+    if !internal_child(grid, lower, lower_n, NANOVDB_LOWER_OFF_CHILD_MASK) {
+        return bitcast<f32>(grid_word(grid, lower_entry));
+    }
+    let leaf = lower + grid_word(grid, lower_entry);
+    let v = c & vec3<u32>(7u);
+    let leaf_n = (v.x << 6u) | (v.y << 3u) | v.z;
+    return bitcast<f32>(grid_word(grid, leaf + NANOVDB_LEAF_OFF_TABLE + 4u * leaf_n));
+}
+
+// Trilinear interpolation at p in index space, as Grid::sample
+fn grid_sample(grid: u32, p: vec3<f32>) -> f32 {
+    let base = floor(p);
+    let f = p - base;
+    let ijk = vec3<i32>(base);
+    var value = 0.0;
+    for (var corner = 0; corner < 8; corner++) {
+        let offset = vec3<i32>(corner & 1, (corner >> 1u) & 1, corner >> 2u);
+        let weight = select(1.0 - f, f, offset == vec3<i32>(1));
+        value += weight.x * weight.y * weight.z * grid_value(grid, ijk + offset);
+    }
+    return value;
+}
+
+fn medium(p: vec3<f32>) -> Medium {
+    var out = Medium(vec3<f32>(0.0), vec3<f32>(0.0), 0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < params.volume_count; i++) {
+        let volume = load_volume(i);
+        let q = (volume.to_index * vec4<f32>(p, 1.0)).xyz;
+        if any(q < volume.min) || any(q > volume.max) {
+            continue;
+        }
+        let density = max(grid_sample(volume.grid, q), 0.0);
+        out.absorption += density * volume.absorption;
+        out.scattering += density * volume.scattering;
+        let scattering = density * luminance(volume.scattering);
+        out.anisotropy += scattering * volume.anisotropy;
+        weight += scattering;
+    }
+    if weight > 0.0 {
+        out.anisotropy /= weight;
+    }
+    return out;
+}
+
+// Span of the ray before t_max through any volume in x and y, and the sum
+// of the majorants of the volumes it passes in z
+fn media_span(ray: Ray, t_max: f32) -> vec3<f32> {
+    var span = vec3<f32>(T_MAX, 0.0, 0.0);
+    for (var i = 0u; i < params.volume_count; i++) {
+        let volume = load_volume(i);
+        let o = (volume.to_index * vec4<f32>(ray.origin, 1.0)).xyz;
+        let inv_d = 1.0 / (volume.to_index * vec4<f32>(ray.dir, 0.0)).xyz;
+        let t0 = (volume.min - o) * inv_d;
+        let t1 = (volume.max - o) * inv_d;
+        let near = max(max(max(min(t0.x, t1.x), min(t0.y, t1.y)), min(t0.z, t1.z)), 0.0);
+        let far = min(min(min(max(t0.x, t1.x), max(t0.y, t1.y)), max(t0.z, t1.z)), t_max);
+        if near < far {
+            span = vec3<f32>(min(span.x, near), max(span.y, far), span.z + volume.majorant);
+        }
+    }
+    return span;
+}
+
+// Tracks the ray through the media up to t_max, updating throughput with
+// the weights of spectral tracking. Collisions are told apart with
+// probabilities of the throughput weighted coefficients, so one channel
+// scattering where another doesn't keeps the weights bounded
+fn track_media(ray: Ray, t_max: f32, throughput: ptr<function, vec3<f32>>) -> MediumEvent {
+    var event = MediumEvent(MEDIUM_NONE, t_max, vec3<f32>(0.0), 0.0);
+    let span = media_span(ray, t_max);
+    let majorant = span.z;
+    if majorant <= 0.0 {
+        return event;
+    }
+    var t = span.x;
+    for (var i = 0u; i < VOLUME_MAX_STEPS; i++) {
+        t -= log(1.0 - rand()) / majorant;
+        if t >= span.y {
+            return event;
+        }
+        let m = medium(ray.origin + t * ray.dir);
+        let extinction = m.absorption + m.scattering;
+        let sigma_null = majorant - extinction;
+        let beta = *throughput;
+        let p_absorb = max_component(abs(beta * m.absorption));
+        let p_scatter = max_component(abs(beta * m.scattering));
+        let p_null = max_component(abs(beta * sigma_null));
+        let total = p_absorb + p_scatter + p_null;
+        if total <= 0.0 {
+            break;
+        }
+        let u = rand() * total;
+        if u < p_absorb {
+            break;
+        }
+        if u < p_absorb + p_scatter {
+            *throughput *= m.scattering * total / (majorant * p_scatter);
+            event.kind = MEDIUM_SCATTERED;
+            event.t = t;
+            event.albedo = m.scattering / max(extinction, vec3<f32>(1e-8));
+            event.anisotropy = m.anisotropy;
+            return event;
+        }
+        *throughput *= sigma_null * total / (majorant * p_null);
+    }
+    *throughput = vec3<f32>(0.0);
+    event.kind = MEDIUM_ABSORBED;
+    return event;
+}
+
+// Transmittance of the media along the ray up to t_max, by ratio tracking
+fn transmittance(ray: Ray, t_max: f32) -> vec3<f32> {
+    let span = media_span(ray, t_max);
+    let majorant = span.z;
+    var tr = vec3<f32>(1.0);
+    if majorant <= 0.0 {
+        return tr;
+    }
+    var t = span.x;
+    for (var i = 0u; i < VOLUME_MAX_STEPS; i++) {
+        t -= log(1.0 - rand()) / majorant;
+        if t >= span.y {
+            break;
+        }
+        let m = medium(ray.origin + t * ray.dir);
+        tr *= max(1.0 - (m.absorption + m.scattering) / majorant, vec3<f32>(0.0));
+        if all(tr == vec3<f32>(0.0)) {
+            break;
+        }
+    }
+    return tr;
+}
+
+fn max_component(v: vec3<f32>) -> f32 {
+    return max(v.x, max(v.y, v.z));
+}
+
+// Henyey-Greenstein phase function of the angle between wo and wi, both
+// pointing away from the point, so positive g scatters forward
+fn phase_hg(cos_theta: f32, g: f32) -> f32 {
+    let denom = 1.0 + g * g + 2.0 * g * cos_theta;
+    return (1.0 - g * g) / (4.0 * PI * denom * sqrt(max(denom, 1e-8)));
+}
+
+// Direction scattered into from wo, with the density phase_hg
+fn sample_hg(wo: vec3<f32>, g: f32, u: vec2<f32>) -> vec3<f32> {
+    // Cosine to the direction of travel, -wo
+    var cos_theta = 1.0 - 2.0 * u.x;
+    if abs(g) >= 1e-3 {
+        let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u.x);
+        cos_theta = (1.0 + g * g - s * s) / (2.0 * g);
+    }
+    let sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+    let phi = 2.0 * PI * u.y;
+    return basis(-wo) * vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+}